@@ -0,0 +1,117 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Insert and adjust throughput of `LooseOctree` with 100k entries. These need a nightly compiler:
+//!
+//! ```text
+//! cargo bench --bench octree
+//! ```
+
+#![feature(test)]
+
+#[macro_use] extern crate isoengine;
+extern crate test;
+
+use isoengine::math::BoundingCube;
+use isoengine::rng::Rng;
+use isoengine::scene::{EntryID, LooseOctree};
+use isoengine::units::Meters;
+
+use test::Bencher;
+
+const ENTRIES: usize = 100_000;
+
+// Entries are scattered over a square kilometer, a few meters up or down, like units on a map.
+fn random_bcube(rng: &mut Rng) -> BoundingCube {
+    BoundingCube {
+        center:    vec3!(Meters ; rng.range_f32(-500.0, 500.0),
+                                  rng.range_f32(-500.0, 500.0),
+                                  rng.range_f32(-4.0, 4.0)),
+        half_edge: Meters(rng.range_f32(0.25, 1.0)),
+    }
+}
+
+fn new_tree() -> LooseOctree<usize> {
+    let initial = BoundingCube { center: vec3!(Meters ; 0.0, 0.0, 0.0), half_edge: Meters(16.0) };
+
+    LooseOctree::new(initial, Meters(1.0), 2.0)
+}
+
+#[bench]
+fn insert_100k(b: &mut Bencher) {
+    let mut rng    = Rng::new(1);
+    let     bcubes: Vec<_> = (0..ENTRIES).map(|_| random_bcube(&mut rng)).collect();
+
+    b.iter(|| {
+        let mut tree = new_tree();
+
+        for (i, &bcube) in bcubes.iter().enumerate() {
+            tree.insert(i, bcube);
+        }
+
+        tree
+    });
+}
+
+#[bench]
+fn from_entries_100k(b: &mut Bencher) {
+    let mut rng    = Rng::new(1);
+    let     bcubes: Vec<_> = (0..ENTRIES).map(|_| random_bcube(&mut rng)).collect();
+
+    b.iter(|| {
+        LooseOctree::from_entries(bcubes.iter().cloned().enumerate(), Meters(1.0), 2.0)
+    });
+}
+
+#[bench]
+fn adjust_100k(b: &mut Bencher) {
+    let mut rng  = Rng::new(2);
+    let mut tree = new_tree();
+
+    let ids: Vec<EntryID> = (0..ENTRIES).map(|i| tree.insert(i, random_bcube(&mut rng))).collect();
+
+    // Every entry takes a small step, which is the common case for moving units: most stay in the
+    // same node, and a few cross into a neighbor.
+    b.iter(|| {
+        for &id in ids.iter() {
+            let mut bcube = tree.get(id).unwrap().1;
+
+            bcube.center.x = bcube.center.x + Meters(rng.range_f32(-0.5, 0.5));
+            bcube.center.y = bcube.center.y + Meters(rng.range_f32(-0.5, 0.5));
+
+            tree.adjust(id, bcube);
+        }
+    });
+}
+
+#[bench]
+fn query_100k(b: &mut Bencher) {
+    let mut rng  = Rng::new(3);
+    let mut tree = new_tree();
+
+    for i in 0..ENTRIES {
+        tree.insert(i, random_bcube(&mut rng));
+    }
+
+    let region = BoundingCube { center: vec3!(Meters ; 0.0, 0.0, 0.0), half_edge: Meters(32.0) };
+    let mut out = vec![];
+
+    b.iter(|| {
+        tree.query(&region, &mut out);
+        out.len()
+    });
+}
@@ -0,0 +1,45 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// Generates the `wire` and `verify` modules from the schemas listed in `flatbuffers.list`. The
+// output goes to `OUT_DIR`, and the `wire.rs` and `verify.rs` files in the source tree just
+// `include!` their modules from there.
+
+use std::env;
+
+#[path = "src/codegen.rs"]
+#[allow(dead_code)]
+mod codegen;
+
+fn main() {
+    let root    = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = env::var("OUT_DIR").unwrap();
+
+    let mut gen = codegen::Generator::new();
+    gen.include(codegen::SCHEMA_DIR);
+
+    println!("cargo:rerun-if-changed=flatbuffers.list");
+    println!("cargo:rerun-if-changed=src/codegen.rs");
+
+    match gen.generate_list("flatbuffers.list", &root, &out_dir) {
+        Ok(schemas) => for schema in schemas {
+            println!("cargo:rerun-if-changed={}", schema.display());
+        },
+
+        Err(err) => panic!("couldn't generate wire modules: {:?}", err),
+    }
+}
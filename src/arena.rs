@@ -0,0 +1,103 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::cell::RefCell;
+
+/// A pool of `Vec`s for data which only lives for a frame, such as draw requests or event queues.
+/// Rather than each frame allocating its buffers and freeing them at the end, buffers are taken
+/// from the arena and handed back when they're done with, keeping their capacity. Once the arena
+/// has warmed up (i.e. it holds as many buffers, as large, as a typical frame needs) a frame
+/// doesn't touch the heap at all.
+pub struct FrameArena<T> {
+    free: Vec<Vec<T>>,
+
+    // The number of times `take` had to allocate a new buffer, rather than reusing one.
+    misses: usize,
+}
+
+impl<T> FrameArena<T> {
+    /// Create an empty arena.
+    pub fn new() -> FrameArena<T> {
+        FrameArena {
+            free:   vec![],
+            misses: 0,
+        }
+    }
+
+    /// Take an empty buffer from the arena. It will have whatever capacity it had when it was last
+    /// returned.
+    pub fn take(&mut self) -> Vec<T> {
+        match self.free.pop() {
+            Some(v) => v,
+            None    => {
+                self.misses += 1;
+                vec![]
+            },
+        }
+    }
+
+    /// Return a buffer to the arena, dropping its contents. Buffers which never allocated aren't
+    /// worth keeping, so they're discarded.
+    pub fn give(&mut self, mut v: Vec<T>) {
+        if v.capacity() == 0 {
+            return
+        }
+
+        v.clear();
+        self.free.push(v);
+    }
+
+    /// The number of buffers available for reuse.
+    pub fn available(&self) -> usize {
+        self.free.len()
+    }
+
+    /// The number of times `take` has had to create a new buffer. In steady state this should stop
+    /// increasing; if it doesn't, buffers aren't being returned.
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// Free every buffer held by the arena, e.g. after a loading screen which needed far more than
+    /// regular frames will.
+    pub fn shrink(&mut self) {
+        self.free = vec![];
+    }
+}
+
+/// A `FrameArena` which can be shared by several users on the same thread, e.g. by every system
+/// which produces events during a frame.
+pub struct SharedFrameArena<T> {
+    inner: RefCell<FrameArena<T>>,
+}
+
+impl<T> SharedFrameArena<T> {
+    /// Create an empty arena.
+    pub fn new() -> SharedFrameArena<T> {
+        SharedFrameArena { inner: RefCell::new(FrameArena::new()) }
+    }
+
+    /// See `FrameArena::take`.
+    pub fn take(&self) -> Vec<T> {
+        self.inner.borrow_mut().take()
+    }
+
+    /// See `FrameArena::give`.
+    pub fn give(&self, v: Vec<T>) {
+        self.inner.borrow_mut().give(v)
+    }
+}
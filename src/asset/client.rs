@@ -15,21 +15,33 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-use std::cell::{self, RefCell};
-use std::collections::BTreeMap;
+use std::cell::{self, Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::{AsRef, From};
+use std::fs;
+use std::io::Read;
 use std::mem;
+use std::path::Path;
 use std::rc::Rc;
 
+use flatbuffers as fb;
+
 use grafix::anim;
+use grafix::device::Recreate;
 use grafix::sprite;
 use asset;
+use crash;
+use logging;
+use memory;
+use watch::Watched;
 
 enum Asset {
     PlaceHolder,
 
     SpriteSheetAbsent(sprite::SheetDesc),
-    SpriteSheet(sprite::Sheet),
+
+    // The descriptor is kept around so that the sheet can be reloaded.
+    SpriteSheet(sprite::SheetDesc, sprite::Sheet),
 
     Animation(anim::Anim),
 }
@@ -37,40 +49,159 @@ enum Asset {
 /// A database containing assets which can be retreived by name or ID.
 pub struct AssetDb {
     inner: Rc<RefCell<AssetDbInner>>,
+
+    // Called with the ID of each asset reloaded by `poll_reloads`. These are kept outside of
+    // `inner` so that a hook is free to call `get_handle`.
+    hooks: RefCell<Vec<Box<FnMut(asset::AssetID)>>>,
+
+    // The number of live `SheetRef`s to each sprite sheet. These are also kept outside of `inner`,
+    // so that a `SheetRef` can be dropped while a `Handle` is outstanding.
+    refs: RefCell<BTreeMap<asset::AssetID, Rc<Cell<usize>>>>,
+}
+
+/// A counted reference to a sprite sheet in an `AssetDb`. The sheet won't be unloaded by
+/// `AssetDb::unload_unreferenced` for as long as any `SheetRef` to it exists.
+pub struct SheetRef {
+    id:    asset::AssetID,
+    count: Rc<Cell<usize>>,
+}
+
+impl SheetRef {
+    /// The ID of the referenced sheet.
+    pub fn id(&self) -> asset::AssetID { self.id }
+}
+
+impl Clone for SheetRef {
+    fn clone(&self) -> SheetRef {
+        self.count.set(self.count.get() + 1);
+
+        SheetRef { id: self.id, count: self.count.clone() }
+    }
+}
+
+impl Drop for SheetRef {
+    fn drop(&mut self) {
+        self.count.set(self.count.get() - 1);
+    }
 }
 
 impl AssetDb {
-    /// Load an `AssetDb` from a manifest file.
-    pub fn from_manifest(w: &asset::wire::AssetManifest) -> AssetDb {
+    /// Load an `AssetDb` from a manifest file. The manifest is checked with `asset::validate`
+    /// first, and every problem it finds is returned.
+    pub fn from_manifest(w: &asset::wire::AssetManifest)
+        -> Result<AssetDb, Vec<asset::ValidationError>> {
+
         let db = AssetDb {
             inner: Rc::new(RefCell::new(AssetDbInner::empty())),
+            hooks: RefCell::new(vec![]),
+            refs:  RefCell::new(BTreeMap::new()),
         };
 
-        for wire_sheet_desc in w.sprite_sheets().unwrap().iter() {
-            let id: asset::AssetID = db.inner.borrow().by_id.len();
+        try!(db.update_from_manifest(w));
+
+        Ok(db)
+    }
+
+    /// Bring the database in line with a (possibly modified) manifest. Assets are matched up by
+    /// name: existing assets keep their IDs and take on their new descriptors (reloading sprite
+    /// sheets which were loaded), and new assets are appended. Assets which are no longer in the
+    /// manifest are left alone, so that nothing holding their IDs breaks.
+    ///
+    /// Returns the IDs of the existing assets which changed. The database is left untouched if the
+    /// manifest doesn't pass `asset::validate`.
+    pub fn update_from_manifest(&self, w: &asset::wire::AssetManifest)
+        -> Result<Vec<asset::AssetID>, Vec<asset::ValidationError>> {
+
+        use self::Asset::*;
+
+        try!(asset::validate(w));
+
+        let mut changed = vec![];
+
+        // Validation guarantees that the fields unwrapped below are present, and that the sheets
+        // convert.
+
+        for wire_sheet_desc in w.sprite_sheets().iter().flat_map(|v| v.iter()) {
+            let name: String = From::from(wire_sheet_desc.name().unwrap().as_ref());
+            let desc         = sprite::SheetDesc::from_wire(wire_sheet_desc).unwrap();
+
+            let mut inner = self.inner.borrow_mut();
+
+            let existing = inner.by_name.get(&name).cloned();
 
-            let name = From::from(wire_sheet_desc.name().unwrap().as_ref());
+            match existing {
+                Some(id) => {
+                    let reload = match inner.by_id[id] {
+                        SpriteSheetAbsent(ref old) => { if *old == desc { continue } false },
+                        SpriteSheet(ref old, _)    => { if *old == desc { continue } true },
+                        _                          => false,
+                    };
 
-            db.inner.borrow_mut().by_name.insert(name, id);
+                    // The paths may have changed, so start watching afresh.
+                    inner.watches.remove(&id);
 
-            let sheet_desc = sprite::SheetDesc::from_wire(wire_sheet_desc);
+                    let slot = &mut inner.by_id[id];
 
-            db.inner.borrow_mut().by_id.push(Asset::SpriteSheetAbsent(sheet_desc));
+                    if reload {
+                        match sprite::Sheet::from_desc(&desc) {
+                            Ok(sheet) => { *slot = SpriteSheet(desc, sheet); }
+                            Err(err)  => {
+                                error!(target: logging::ASSET,
+                                       "couldn't reload sprite sheet `{}': {:?}", name, err);
+                                continue
+                            },
+                        }
+                    } else {
+                        *slot = SpriteSheetAbsent(desc);
+                    }
+
+                    changed.push(id);
+                },
+
+                None => {
+                    let id = inner.by_id.len();
+                    inner.by_name.insert(name, id);
+                    inner.by_id.push(SpriteSheetAbsent(desc));
+                },
+            }
         }
 
-        for wire_anim in w.anims().unwrap().iter() {
-            let id: asset::AssetID = db.inner.borrow().by_id.len();
+        for wire_anim in w.anims().iter().flat_map(|v| v.iter()) {
+            let name: String = From::from(wire_anim.name().unwrap().as_ref());
+
+            let existing = self.inner.borrow().by_name.get(&name).cloned();
+
+            let id = match existing {
+                Some(id) => id,
+                None     => {
+                    let mut inner = self.inner.borrow_mut();
+                    let id = inner.by_id.len();
+                    inner.by_name.insert(name, id);
+                    inner.by_id.push(PlaceHolder);
+                    id
+                },
+            };
 
-            let name = From::from(wire_anim.name().unwrap().as_ref());
+            let anim = anim::Anim::from_wire(wire_anim, self.get_handle());
 
-            db.inner.borrow_mut().by_name.insert(name, id);
+            // Keep a placeholder for a bad animation, so that the IDs of the assets which follow it
+            // still line up with the server's.
+            let asset = match anim {
+                Ok(anim) => Animation(anim),
+                Err(err) => {
+                    error!(target: logging::ASSET, "couldn't load animation #{}: {:?}", id, err);
+                    PlaceHolder
+                },
+            };
 
-            let anim = anim::Anim::from_wire(wire_anim, db.get_handle());
+            if existing.is_some() {
+                changed.push(id);
+            }
 
-            db.inner.borrow_mut().by_id.push(Asset::Animation(anim));
+            self.inner.borrow_mut().by_id[id] = asset;
         }
 
-        db
+        Ok(changed)
     }
 
     /// Load a given asset by its ID. Some assets (e.g. sprite sheets) only have a descriptor loaded
@@ -81,14 +212,394 @@ impl AssetDb {
 
         let mut mref = self.inner.borrow_mut();
 
+        // The files are watched as they are now, so that changes made while the sheet wasn't
+        // loaded aren't mistaken for new ones.
+        let watching  = mref.watching;
+        let mut watch = None;
+
         if let Some(x @ &mut SpriteSheetAbsent(..)) = mref.by_id.get_mut(id) {
             if let SpriteSheetAbsent(desc) = mem::replace(x, PlaceHolder) {
-                match sprite::Sheet::from_desc(desc) {
-                    Ok(sheet) => { mem::replace(x, SpriteSheet(sheet)); }
-                    Err(err)  => debug!("couldn't load sprite: {:?}", err),
+                // Refuse to load files which don't match the manifest, so that corrupted art is
+                // caught here rather than showing up on screen.
+                let corrupt = desc.files().iter().any(|&(path, hash)| {
+                    hash != 0 && !asset::hash::file_matches(path, hash)
+                });
+
+                if corrupt {
+                    error!(target: logging::ASSET,
+                           "sprite sheet #{} doesn't match its content hashes", id);
+                    mem::replace(x, SpriteSheetAbsent(desc));
+                    return
+                }
+
+                match sprite::Sheet::from_desc(&desc) {
+                    Ok(sheet) => {
+                        if watching {
+                            watch = Some(desc.files().iter()
+                                .map(|&(path, _)| Watched::new(path))
+                                .collect());
+                        }
+
+                        mem::replace(x, SpriteSheet(desc, sheet));
+                    },
+                    Err(err)  => {
+                        debug!(target: logging::ASSET, "couldn't load sprite: {:?}", err);
+                        mem::replace(x, SpriteSheetAbsent(desc));
+                        return
+                    },
                 }
             } else { unreachable!() }
+        } else {
+            return
         }
+
+        if let Some(files) = watch {
+            mref.watches.insert(id, files);
+        }
+
+        let frame = mref.frame;
+        mref.last_used.insert(id, Cell::new(frame));
+        mref.evicted_ids.remove(&id);
+
+        mref.enforce_budget(&self.refs.borrow());
+    }
+
+    /// Limit the GPU memory used by sprite sheets to `bytes`, or lift the limit with `None`. When
+    /// loading a sheet takes the total over budget, the least recently used sheets are unloaded
+    /// until it fits again. Sheets which have been used during the current frame (see
+    /// `begin_frame`), or which have outstanding `SheetRef`s, are never unloaded this way, so the
+    /// budget may be exceeded if they need more than it allows.
+    ///
+    /// Unloaded sheets go back to the state they were in before `load`. They're loaded again at the
+    /// start of the frame after something tries to use them.
+    pub fn set_texture_budget(&self, bytes: Option<usize>) {
+        let mut inner = self.inner.borrow_mut();
+
+        inner.budget = bytes;
+        inner.enforce_budget(&self.refs.borrow());
+    }
+
+    /// Mark the start of a new frame. This reloads any sheets which were unloaded to stay within
+    /// the texture budget but have since been asked for through a `Handle`. It should be called
+    /// once per iteration of the main loop, while no `Handle`s are outstanding.
+    pub fn begin_frame(&self) {
+        let wanted = {
+            let mut inner = self.inner.borrow_mut();
+
+            inner.frame += 1;
+
+            let wanted = mem::replace(&mut *inner.wanted.borrow_mut(), BTreeSet::new());
+            wanted
+        };
+
+        for id in wanted {
+            self.load(id);
+        }
+
+        crash::set_asset_count(self.inner.borrow().loaded_count());
+    }
+
+    /// Report how much GPU memory sprite sheets are using, for display to the player or in debug
+    /// overlays.
+    pub fn texture_usage(&self) -> TextureUsage {
+        let inner = self.inner.borrow();
+
+        TextureUsage {
+            resident: inner.resident_bytes(),
+            budget:   inner.budget,
+            sheets:   inner.last_used.len(),
+            evicted:  inner.evicted,
+        }
+    }
+
+    /// Describe every loaded sprite sheet, in order of name, e.g. for a debug view of texture
+    /// memory.
+    pub fn residency(&self) -> Vec<Residency> {
+        let inner = self.inner.borrow();
+
+        inner.by_name.iter().filter_map(|(name, &id)| match inner.by_id.get(id) {
+            Some(&Asset::SpriteSheet(_, ref sheet)) => {
+                let (width, height) = sheet.dimensions();
+
+                Some(Residency {
+                    id:        id,
+                    name:      name.clone(),
+                    width:     width,
+                    height:    height,
+                    bytes:     sheet.bytes(),
+                    last_used: inner.last_used.get(&id).map_or(0, |used| used.get()),
+                })
+            },
+
+            _ => None,
+        }).collect()
+    }
+
+    /// The number of frames begun so far (see `begin_frame`), which `Residency::last_used` counts
+    /// in.
+    pub fn frame(&self) -> u64 {
+        self.inner.borrow().frame
+    }
+
+    /// Unload sprite sheet `id`, returning false if it isn't loaded. Unlike sheets unloaded to stay
+    /// within the texture budget, it isn't loaded again when something asks for it; call `load`.
+    pub fn unload(&self, id: asset::AssetID) -> bool {
+        let mut inner = self.inner.borrow_mut();
+
+        let sheet = match inner.by_id.get_mut(id) {
+            Some(x @ &mut Asset::SpriteSheet(..)) => mem::replace(x, Asset::PlaceHolder),
+            _                                     => return false,
+        };
+
+        if let Asset::SpriteSheet(desc, _) = sheet {
+            inner.by_id[id] = Asset::SpriteSheetAbsent(desc);
+        }
+
+        inner.last_used.remove(&id);
+
+        debug!(target: logging::ASSET, "unloaded sprite sheet #{} on request", id);
+
+        true
+    }
+
+    /// Add the textures of each loaded sprite sheet to `report`, by name, along with the copies of
+    /// their depth textures kept for hit tests.
+    pub fn report_memory(&self, report: &mut memory::Report) {
+        let inner = self.inner.borrow();
+
+        for (name, &id) in inner.by_name.iter() {
+            if let Some(&Asset::SpriteSheet(_, ref sheet)) = inner.by_id.get(id) {
+                report.add(logging::ASSET, &name[..], 1, sheet.bytes());
+                report.add(logging::ASSET, format!("{} depth mask", name), 1, sheet.mask_bytes());
+            }
+        }
+    }
+
+    /// Return the files backing an asset which hasn't been loaded yet that are missing or out of
+    /// date (according to the manifest's content hashes), paired with their indices in the order
+    /// the server streams them. Returns an empty vector for assets which are already loaded, or
+    /// which have everything they need.
+    pub fn pending_files(&self, id: asset::AssetID) -> Vec<(usize, String)> {
+        use self::Asset::*;
+
+        match self.inner.borrow().by_id.get(id) {
+            Some(&SpriteSheetAbsent(ref desc)) => desc.files().iter().enumerate()
+                .filter(|&(_, &(path, hash))| !asset::hash::file_matches(path, hash))
+                .map(|(i, &(path, _))| (i, From::from(path)))
+                .collect(),
+
+            _ => vec![],
+        }
+    }
+
+    /// Start watching the files behind every loaded sprite sheet, so that `poll_reloads` will
+    /// pick up changes to them. Sheets loaded later are watched as soon as they're loaded.
+    pub fn watch(&self) {
+        self.inner.borrow_mut().watching = true;
+        self.poll_reloads();
+    }
+
+    /// Also watch the manifest at `path`, so that `poll_reloads` will apply changes to it with
+    /// `update_from_manifest`.
+    pub fn watch_manifest<P: AsRef<Path>>(&self, path: P) {
+        let mut inner = self.inner.borrow_mut();
+        inner.watching = true;
+        inner.manifest = Some(Watched::new(path));
+    }
+
+    /// Register a function to be called with the ID of each asset reloaded by `poll_reloads`.
+    /// Anything which caches data derived from an asset (e.g. a sprite's dimensions) should use
+    /// this to invalidate it.
+    pub fn on_reload<F: FnMut(asset::AssetID) + 'static>(&self, hook: F) {
+        self.hooks.borrow_mut().push(Box::new(hook));
+    }
+
+    /// Check the watched files for changes, and reload the assets they belong to. A sheet which
+    /// fails to reload keeps its old textures. This should be called once per iteration of the
+    /// main loop, while no `Handle`s are outstanding.
+    ///
+    /// Returns the IDs of the reloaded assets, after calling the `on_reload` hooks for each.
+    pub fn poll_reloads(&self) -> Vec<asset::AssetID> {
+        if !self.inner.borrow().watching {
+            return vec![]
+        }
+
+        let mut reloaded = self.poll_manifest();
+        reloaded.extend(self.poll_sheets());
+
+        reloaded.sort();
+        reloaded.dedup();
+
+        let mut hooks = self.hooks.borrow_mut();
+
+        for &id in reloaded.iter() {
+            for hook in hooks.iter_mut() {
+                hook(id);
+            }
+        }
+
+        reloaded
+    }
+
+    /// Load the textures of every loaded sprite sheet again from its descriptor, after the GL
+    /// context has been recreated (see `grafix::device`). A sheet which fails to load is unloaded,
+    /// since its old textures are gone, and the errors are returned.
+    pub fn recreate_textures(&self) -> Vec<(asset::AssetID, sprite::Error)> {
+        use self::Asset::*;
+
+        let mut inner  = self.inner.borrow_mut();
+        let mut errors = vec![];
+
+        for (id, asset) in inner.by_id.iter_mut().enumerate() {
+            let desc = match *asset {
+                SpriteSheet(ref desc, _) => desc.clone(),
+                _                        => continue,
+            };
+
+            match sprite::Sheet::from_desc(&desc) {
+                Ok(sheet) => *asset = SpriteSheet(desc, sheet),
+                Err(err)  => {
+                    *asset = SpriteSheetAbsent(desc);
+                    errors.push((id, err));
+                },
+            }
+        }
+
+        for &(id, _) in errors.iter() {
+            inner.last_used.remove(&id);
+        }
+
+        debug!(target: logging::ASSET, "recreated sprite sheet textures ({} failed)",
+               errors.len());
+
+        errors
+    }
+
+    // Reload every loaded sprite sheet whose files have changed.
+    fn poll_sheets(&self) -> Vec<asset::AssetID> {
+        use self::Asset::*;
+
+        let mut inner = self.inner.borrow_mut();
+        let inner     = &mut *inner;
+
+        let mut reloaded = vec![];
+
+        for (id, asset) in inner.by_id.iter_mut().enumerate() {
+            let desc = match *asset {
+                SpriteSheet(ref desc, _) => desc.clone(),
+                _                        => continue,
+            };
+
+            let files = inner.watches.entry(id).or_insert_with(|| {
+                desc.files().iter().map(|&(path, _)| Watched::new(path)).collect()
+            });
+
+            // Poll every file, so that all of their timestamps are up to date.
+            let changed = files.iter_mut().fold(false, |acc, f| f.changed() || acc);
+
+            if !changed {
+                continue
+            }
+
+            match sprite::Sheet::from_desc(&desc) {
+                Ok(sheet) => {
+                    debug!(target: logging::ASSET, "reloaded sprite sheet #{}", id);
+                    *asset = SpriteSheet(desc, sheet);
+                    reloaded.push(id);
+                },
+                Err(err) => error!(target: logging::ASSET,
+                                   "couldn't reload sprite sheet #{}: {:?}", id, err),
+            }
+        }
+
+        reloaded
+    }
+
+    // Re-read the manifest if it's being watched and has changed.
+    fn poll_manifest(&self) -> Vec<asset::AssetID> {
+        let path = match self.inner.borrow_mut().manifest {
+            Some(ref mut m) if m.changed() => m.path().to_path_buf(),
+            _                              => return vec![],
+        };
+
+        let mut buf = vec![];
+
+        if let Err(err) = fs::File::open(&path).and_then(|mut f| f.read_to_end(&mut buf)) {
+            error!(target: logging::ASSET, "couldn't read manifest `{}': {}", path.display(), err);
+            return vec![]
+        }
+
+        if let Err(err) = asset::verify::verify_buffer(&buf) {
+            error!(target: logging::ASSET, "manifest `{}' is malformed: {:?}", path.display(), err);
+            return vec![]
+        }
+
+        match self.update_from_manifest(fb::get_root::<asset::wire::AssetManifest>(&buf)) {
+            Ok(changed) => changed,
+            Err(errs)   => {
+                error!(target: logging::ASSET,
+                       "manifest `{}' is invalid: {:?}", path.display(), errs);
+                vec![]
+            },
+        }
+    }
+
+    /// Get a counted reference to the sprite sheet `id`, loading it if it isn't loaded already.
+    /// Returns `None` if `id` isn't a sprite sheet. To keep the sheet of an animation around, use
+    /// its `sheet_id`.
+    pub fn acquire_sheet(&self, id: asset::AssetID) -> Option<SheetRef> {
+        use self::Asset::*;
+
+        match self.inner.borrow().by_id.get(id) {
+            Some(&SpriteSheet(..)) | Some(&SpriteSheetAbsent(..)) => (),
+            _                                                      => return None,
+        }
+
+        self.load(id);
+
+        let mut refs = self.refs.borrow_mut();
+        let count    = refs.entry(id).or_insert_with(|| Rc::new(Cell::new(0)));
+
+        count.set(count.get() + 1);
+
+        Some(SheetRef { id: id, count: count.clone() })
+    }
+
+    /// Unload every sprite sheet which has no outstanding `SheetRef`s, freeing its textures. The
+    /// sheets go back to the state they were in before `load`, so they can be loaded again later.
+    /// Note that this includes sheets which were loaded with `load` but never acquired.
+    ///
+    /// Returns the IDs of the unloaded sheets.
+    pub fn unload_unreferenced(&self) -> Vec<asset::AssetID> {
+        use self::Asset::*;
+
+        let refs      = self.refs.borrow();
+        let mut inner = self.inner.borrow_mut();
+        let inner     = &mut *inner;
+
+        let mut unloaded = vec![];
+
+        for (id, asset) in inner.by_id.iter_mut().enumerate() {
+            if let SpriteSheet(..) = *asset {
+                if refs.get(&id).map_or(false, |count| count.get() > 0) {
+                    continue
+                }
+
+                if let SpriteSheet(desc, _) = mem::replace(asset, PlaceHolder) {
+                    *asset = SpriteSheetAbsent(desc);
+                }
+
+                inner.watches.remove(&id);
+                inner.last_used.remove(&id);
+                unloaded.push(id);
+            }
+        }
+
+        if !unloaded.is_empty() {
+            debug!(target: logging::ASSET, "unloaded {} sprite sheet(s)", unloaded.len());
+        }
+
+        unloaded
     }
 
     /// A read-only view into the database. It is capable of handing out references to resources
@@ -101,6 +612,82 @@ impl AssetDb {
 struct AssetDbInner {
     by_name: BTreeMap<String, asset::AssetID>,
     by_id:   Vec<Asset>,
+
+    // True once `watch` or `watch_manifest` has been called.
+    watching: bool,
+
+    // The files behind each watched sprite sheet.
+    watches: BTreeMap<asset::AssetID, Vec<Watched>>,
+
+    manifest: Option<Watched>,
+
+    // Incremented by `AssetDb::begin_frame`.
+    frame: u64,
+
+    // The frame in which each loaded sprite sheet was last handed out by a `Handle`.
+    last_used: BTreeMap<asset::AssetID, Cell<u64>>,
+
+    // Evicted sheets which a `Handle` has been asked for since the last frame began. A `RefCell`,
+    // since `Handle`s only have shared access.
+    wanted: RefCell<BTreeSet<asset::AssetID>>,
+
+    budget: Option<usize>,
+
+    // Sheets which have been unloaded to stay within `budget`, and not loaded since.
+    evicted_ids: BTreeSet<asset::AssetID>,
+
+    // The number of sheets unloaded to stay within `budget`.
+    evicted: usize,
+}
+
+/// A loaded sprite sheet, as described by `AssetDb::residency`.
+#[derive(Clone,Debug)]
+pub struct Residency {
+    /// The sheet's ID.
+    pub id: asset::AssetID,
+
+    /// The sheet's name.
+    pub name: String,
+
+    /// The width of the sheet's image, in pixels.
+    pub width: u32,
+
+    /// The height of the sheet's image, in pixels.
+    pub height: u32,
+
+    /// Bytes used by the sheet's textures.
+    pub bytes: usize,
+
+    /// The frame (see `AssetDb::frame`) in which the sheet was last handed out by a `Handle`.
+    pub last_used: u64,
+}
+
+/// A summary of the GPU memory used by sprite sheets, returned by `AssetDb::texture_usage`.
+#[derive(Clone,Copy,Debug)]
+pub struct TextureUsage {
+    /// Bytes used by the textures of loaded sheets.
+    pub resident: usize,
+
+    /// The budget set by `AssetDb::set_texture_budget`, if any.
+    pub budget: Option<usize>,
+
+    /// The number of loaded sheets.
+    pub sheets: usize,
+
+    /// The total number of sheets which have been unloaded to stay within budget.
+    pub evicted: usize,
+}
+
+impl Recreate for AssetDb {
+    fn recreate(&mut self) -> Result<(), String> {
+        let errors = self.recreate_textures();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("{} sprite sheets failed to load: {:?}", errors.len(), errors))
+        }
+    }
 }
 
 impl AssetDbInner {
@@ -108,6 +695,79 @@ impl AssetDbInner {
         AssetDbInner {
             by_name: BTreeMap::new(),
             by_id:   Vec::new(),
+
+            watching: false,
+            watches:  BTreeMap::new(),
+            manifest: None,
+
+            frame:     0,
+            last_used: BTreeMap::new(),
+            wanted:    RefCell::new(BTreeSet::new()),
+            budget:    None,
+
+            evicted_ids: BTreeSet::new(),
+            evicted:     0,
+        }
+    }
+
+    fn loaded_count(&self) -> usize {
+        self.by_id.iter().filter(|asset| match **asset {
+            Asset::SpriteSheet(..) | Asset::Animation(..) => true,
+            _                                              => false,
+        }).count()
+    }
+
+    fn resident_bytes(&self) -> usize {
+        self.by_id.iter().fold(0, |total, asset| match *asset {
+            Asset::SpriteSheet(_, ref sheet) => total + sheet.bytes(),
+            _                                => total,
+        })
+    }
+
+    // Unload least recently used sheets until we're within budget, sparing those used this frame.
+    fn enforce_budget(&mut self, refs: &BTreeMap<asset::AssetID, Rc<Cell<usize>>>) {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None         => return,
+        };
+
+        let mut resident = self.resident_bytes();
+
+        if resident <= budget {
+            return
+        }
+
+        let mut candidates: Vec<(u64, asset::AssetID)> = self.last_used.iter()
+            .map(|(&id, used)| (used.get(), id))
+            .filter(|&(used, _)| used < self.frame)
+            .filter(|&(_, id)| refs.get(&id).map_or(true, |count| count.get() == 0))
+            .collect();
+
+        candidates.sort();
+
+        for (_, id) in candidates {
+            if resident <= budget {
+                break
+            }
+
+            if let Asset::SpriteSheet(desc, sheet) = mem::replace(&mut self.by_id[id],
+                                                                  Asset::PlaceHolder) {
+                resident -= sheet.bytes();
+                self.by_id[id] = Asset::SpriteSheetAbsent(desc);
+            }
+
+            self.last_used.remove(&id);
+            self.watches.remove(&id);
+            self.evicted_ids.insert(id);
+            self.evicted += 1;
+
+            debug!(target: logging::ASSET,
+                   "evicted sprite sheet #{} to stay within texture budget", id);
+        }
+
+        if resident > budget {
+            debug!(target: logging::ASSET,
+                   "texture budget exceeded by sheets in use: {} > {} bytes", resident, budget);
         }
     }
 }
@@ -134,13 +794,37 @@ impl<'x> Handle<'x> {
         }
     }
 
+    /// Get a `sprite::Sheet` if it's loaded, without counting it as used or asking for it to be
+    /// loaded again if it's been evicted. This is for debug tools, which shouldn't change what
+    /// stays resident by looking at it.
+    pub fn peek_sprite_sheet(&self, id: asset::AssetID) -> Option<&sprite::Sheet> {
+        match self.inner.by_id.get(id) {
+            Some(&Asset::SpriteSheet(_, ref sheet)) => Some(sheet),
+            _                                       => None,
+        }
+    }
+
     /// Get a `sprite::Sheet` from an `asset::AssetID`.
     pub fn get_sprite_sheet(&self, id: asset::AssetID) -> Option<&sprite::Sheet> {
         use self::Asset::*;
-        if let Some(&SpriteSheet(ref sheet)) = self.inner.by_id.get(id) {
-            Some(sheet)
-        } else {
-            None
+        match self.inner.by_id.get(id) {
+            Some(&SpriteSheet(_, ref sheet)) => {
+                if let Some(used) = self.inner.last_used.get(&id) {
+                    used.set(self.inner.frame);
+                }
+
+                Some(sheet)
+            },
+
+            Some(&SpriteSheetAbsent(..)) => {
+                if self.inner.evicted_ids.contains(&id) {
+                    self.inner.wanted.borrow_mut().insert(id);
+                }
+
+                None
+            },
+
+            _ => None,
         }
     }
 }
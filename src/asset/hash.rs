@@ -0,0 +1,84 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// A 64-bit FNV-1a hash of a file's contents. Zero is reserved to mean "unknown", so a hash which
+/// would otherwise come out as zero is reported as 1 instead.
+pub type ContentHash = u64;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME:        u64 = 0x100000001b3;
+
+/// An incremental FNV-1a hasher, for hashing data which arrives in pieces.
+pub struct Fnv64 {
+    state: u64,
+}
+
+impl Fnv64 {
+    /// Create a hasher which hasn't seen any data.
+    pub fn new() -> Fnv64 {
+        Fnv64 { state: FNV_OFFSET_BASIS }
+    }
+
+    /// Feed `data` into the hash.
+    pub fn update(&mut self, data: &[u8]) {
+        for &b in data.iter() {
+            self.state = (self.state ^ b as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    /// The hash of all of the data seen so far.
+    pub fn finish(&self) -> ContentHash {
+        if self.state == 0 { 1 } else { self.state }
+    }
+}
+
+/// Hash a buffer.
+pub fn hash_bytes(data: &[u8]) -> ContentHash {
+    let mut h = Fnv64::new();
+    h.update(data);
+    h.finish()
+}
+
+/// Hash the contents of the file at `path`.
+pub fn hash_file<P: AsRef<Path>>(path: P) -> io::Result<ContentHash> {
+    let mut file = try!(fs::File::open(path));
+    let mut buf  = [0u8; 8192];
+    let mut h    = Fnv64::new();
+
+    loop {
+        let n = try!(file.read(&mut buf));
+
+        if n == 0 {
+            return Ok(h.finish())
+        }
+
+        h.update(&buf[..n]);
+    }
+}
+
+/// Return true if the file at `path` exists and, when `expected` is known (non-zero), has that
+/// hash. A file which can't be read never matches.
+pub fn file_matches<P: AsRef<Path>>(path: P, expected: ContentHash) -> bool {
+    match hash_file(path) {
+        Ok(actual) => expected == 0 || actual == expected,
+        Err(_)     => false,
+    }
+}
@@ -0,0 +1,356 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::BTreeMap;
+use std::convert::{AsRef, From};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use flatbuffers as fb;
+
+use asset::{hash, wire, ValidationError};
+use grafix::anim::AnimDesc;
+use grafix::sprite::SheetDesc;
+
+/// Builds an asset manifest from descriptors, for use by tools in the asset pipeline.
+///
+/// Assets are written to the manifest in the order they were added (sheets before animations),
+/// which determines their `AssetID`s.
+pub struct ManifestBuilder {
+    sheets: Vec<(String, SheetDesc)>,
+    anims:  Vec<(String, AnimDesc)>,
+}
+
+/// An error encountered while building a manifest.
+#[derive(Debug)]
+pub enum BuildError {
+    /// The manifest is inconsistent.
+    Invalid(Vec<ValidationError>),
+
+    /// The manifest couldn't be written out.
+    Io(io::Error),
+}
+
+impl From<io::Error> for BuildError {
+    fn from(err: io::Error) -> BuildError {
+        BuildError::Io(err)
+    }
+}
+
+impl ManifestBuilder {
+    /// Create a builder for an empty manifest.
+    pub fn new() -> ManifestBuilder {
+        ManifestBuilder {
+            sheets: vec![],
+            anims:  vec![],
+        }
+    }
+
+    /// Register a sprite sheet under `name`.
+    pub fn add_sheet<S>(&mut self, name: S, desc: SheetDesc) where String: From<S> {
+        self.sheets.push((From::from(name), desc));
+    }
+
+    /// Register an animation under `name`.
+    pub fn add_anim<S>(&mut self, name: S, desc: AnimDesc) where String: From<S> {
+        self.anims.push((From::from(name), desc));
+    }
+
+    /// Read the descriptors back out of a serialized manifest, so that it can be validated or
+    /// modified. Fails if any required fields are missing. An absent list of sheets or animations
+    /// is treated as an empty one.
+    pub fn from_wire(w: &wire::AssetManifest) -> Result<ManifestBuilder, Vec<ValidationError>> {
+        let mut builder = ManifestBuilder::new();
+        let mut errors  = vec![];
+
+        let missing = |asset: &str, field| ValidationError::MissingField {
+            asset: From::from(asset),
+            field: field,
+        };
+
+        if let Some(sheets) = w.sprite_sheets() {
+            for (i, sheet) in sheets.iter().enumerate() {
+                let name: String = match sheet.name() {
+                    Some(name) => From::from(name.as_ref()),
+                    None       => {
+                        errors.push(missing(&format!("sprite_sheets[{}]", i), "name"));
+                        continue
+                    },
+                };
+
+                let mut complete = true;
+
+                for &(field, present) in [("color_path", sheet.color_path().is_some()),
+                                          ("depth_path", sheet.depth_path().is_some())].iter() {
+                    if !present {
+                        errors.push(missing(&name, field));
+                        complete = false;
+                    }
+                }
+
+                if complete {
+                    match SheetDesc::from_wire(sheet) {
+                        Ok(desc)   => builder.add_sheet(name, desc),
+                        Err(error) => errors.push(ValidationError::InvalidSheet {
+                            sheet: name,
+                            error: error,
+                        }),
+                    }
+                }
+            }
+        }
+
+        if let Some(anims) = w.anims() {
+            for (i, anim) in anims.iter().enumerate() {
+                let name: String = match anim.name() {
+                    Some(name) => From::from(name.as_ref()),
+                    None       => {
+                        errors.push(missing(&format!("anims[{}]", i), "name"));
+                        continue
+                    },
+                };
+
+                match (anim.sheet(), anim.indices()) {
+                    (Some(sheet), Some(indices)) => builder.add_anim(name, AnimDesc {
+                        sheet:   From::from(sheet.as_ref()),
+                        indices: indices.iter().collect(),
+                    }),
+
+                    (sheet, _) => errors.push(missing(&name,
+                                                      if sheet.is_none() { "sheet" }
+                                                      else               { "indices" })),
+                }
+            }
+        }
+
+        if errors.is_empty() { Ok(builder) } else { Err(errors) }
+    }
+
+    /// Compute the content hash of every file backing a registered sprite sheet, and store it in
+    /// the sheet's descriptor. This should be run by the asset pipeline before `finish`, so that
+    /// clients can tell whether their copies of the files are current.
+    pub fn hash_files(&mut self) -> io::Result<()> {
+        for &mut (_, ref mut desc) in self.sheets.iter_mut() {
+            desc.color_hash = try!(hash::hash_file(&desc.color_path));
+            desc.depth_hash = try!(hash::hash_file(&desc.depth_path));
+
+            if let Some(ref path) = desc.normal_path {
+                desc.normal_hash = try!(hash::hash_file(path));
+            }
+
+            if let Some(ref path) = desc.emissive_path {
+                desc.emissive_hash = try!(hash::hash_file(path));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The sprite sheets which have been registered, in order.
+    pub fn sheets(&self) -> &[(String, SheetDesc)] {
+        &self.sheets
+    }
+
+    /// The animations which have been registered, in order.
+    pub fn anims(&self) -> &[(String, AnimDesc)] {
+        &self.anims
+    }
+
+    /// Check the manifest for consistency: names must be unique across all assets, sheets must pass
+    /// `SheetDesc::validate`, animations must have frames, and every frame must exist in a sheet
+    /// which has been registered.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = vec![];
+
+        let mut sheets = BTreeMap::new();
+
+        for &(ref name, ref desc) in self.sheets.iter() {
+            if sheets.insert(&name[..], desc).is_some() {
+                errors.push(ValidationError::DuplicateName(name.clone()));
+            }
+
+            if let Err(error) = desc.validate() {
+                errors.push(ValidationError::InvalidSheet { sheet: name.clone(), error: error });
+            }
+        }
+
+        let mut anims = BTreeMap::new();
+
+        for &(ref name, ref desc) in self.anims.iter() {
+            if sheets.contains_key(&name[..]) || anims.insert(&name[..], ()).is_some() {
+                errors.push(ValidationError::DuplicateName(name.clone()));
+            }
+
+            if desc.indices.is_empty() {
+                errors.push(ValidationError::NoFrames(name.clone()));
+            }
+
+            match sheets.get(&desc.sheet[..]) {
+                Some(sheet) => for &idx in desc.indices.iter() {
+                    if idx >= sheet.total {
+                        errors.push(ValidationError::SpriteOutOfRange {
+                            anim:  name.clone(),
+                            index: idx,
+                            total: sheet.total,
+                        });
+                    }
+                },
+
+                None => errors.push(ValidationError::MissingSheet {
+                    anim:  name.clone(),
+                    sheet: desc.sheet.clone(),
+                }),
+            }
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Validate the manifest and serialize it to a FlatBuffer.
+    pub fn finish(&self) -> Result<Vec<u8>, BuildError> {
+        try!(self.validate().map_err(BuildError::Invalid));
+
+        let mut fbb = fb::FlatBufferBuilder::new();
+
+        let sheets: Vec<_> = self.sheets.iter()
+            .map(|&(ref name, ref desc)| desc.to_wire(name, &mut fbb))
+            .collect();
+
+        let anims: Vec<_> = self.anims.iter()
+            .map(|&(ref name, ref desc)| desc.to_wire(name, &mut fbb))
+            .collect();
+
+        let sheets = fbb.create_vector(&sheets);
+        let anims  = fbb.create_vector(&anims);
+
+        let root = {
+            let mut b = wire::AssetManifestBuilder::new(&mut fbb);
+            b.add_sprite_sheets(sheets);
+            b.add_anims(anims);
+            b.finish()
+        };
+
+        fbb.finish(root);
+
+        Ok(fbb.into_vec())
+    }
+
+    /// Validate the manifest and write it to `path`.
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> Result<(), BuildError> {
+        let buf = try!(self.finish());
+
+        let mut file = try!(fs::File::create(path));
+        try!(file.write_all(&buf));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flatbuffers as fb;
+
+    use asset::manifest::{BuildError, ManifestBuilder};
+    use asset::{verify, wire, ValidationError};
+    use grafix::anim::{self, AnimDesc};
+    use grafix::sprite::{DescError, SheetDesc};
+
+    fn sheet(num_across: u16) -> SheetDesc {
+        SheetDesc {
+            img_width:     128,
+            img_height:    64,
+            origin_x:      16,
+            origin_y:      28,
+            spr_width:     32,
+            spr_height:    32,
+            num_across:    num_across,
+            num_down:      2,
+            total:         8,
+            color_path:    "sheets/tree.png".to_string(),
+            depth_path:    "sheets/tree.depth.png".to_string(),
+            color_hash:    0,
+            depth_hash:    0,
+            normal_path:   None,
+            normal_hash:   0,
+            emissive_path: None,
+            emissive_hash: 0,
+        }
+    }
+
+    #[test]
+    fn manifest_round_trip() {
+        let mut manifest = ManifestBuilder::new();
+
+        manifest.add_sheet("tree", sheet(4));
+        manifest.add_anim("sway", AnimDesc { sheet: "tree".to_string(), indices: vec![0, 1, 2] });
+
+        let buf = manifest.finish().unwrap();
+
+        verify::verify_asset_manifest_buffer(&buf).unwrap();
+
+        let back = ManifestBuilder::from_wire(fb::get_root::<wire::AssetManifest>(&buf)).unwrap();
+
+        assert_eq!(back.sheets(), manifest.sheets());
+        assert_eq!(back.anims().len(), 1);
+        assert_eq!(back.anims()[0].0, "sway");
+        assert_eq!(back.anims()[0].1.sheet, "tree");
+        assert_eq!(back.anims()[0].1.indices, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn manifest_invalid_sheet() {
+        let invalid = ValidationError::InvalidSheet {
+            sheet: "tree".to_string(),
+            error: DescError::Zero("num_across"),
+        };
+
+        let mut manifest = ManifestBuilder::new();
+
+        manifest.add_sheet("tree", sheet(0));
+
+        match manifest.finish() {
+            Err(BuildError::Invalid(errors)) => assert_eq!(errors, vec![invalid.clone()]),
+            _                                => panic!("a zero-width sheet was accepted"),
+        }
+
+        // A manifest which didn't come through `finish` is caught when it's read.
+        let mut fbb = fb::FlatBufferBuilder::new();
+
+        let sheets = vec![sheet(0).to_wire("tree", &mut fbb)];
+        let sheets = fbb.create_vector(&sheets);
+        let anims: Vec<fb::Offset<anim::wire::Anim>> = vec![];
+        let anims  = fbb.create_vector(&anims);
+
+        let root = {
+            let mut b = wire::AssetManifestBuilder::new(&mut fbb);
+            b.add_sprite_sheets(sheets);
+            b.add_anims(anims);
+            b.finish()
+        };
+
+        fbb.finish(root);
+
+        let buf = fbb.into_vec();
+
+        verify::verify_asset_manifest_buffer(&buf).unwrap();
+
+        let w = fb::get_root::<wire::AssetManifest>(&buf);
+
+        assert_eq!(ManifestBuilder::from_wire(w).err(), Some(vec![invalid]));
+    }
+}
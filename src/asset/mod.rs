@@ -18,9 +18,28 @@
 use std::collections::BTreeMap;
 use std::convert::AsRef;
 
+use grafix::sprite;
+
 #[allow(missing_docs)]
 pub mod wire;
 
+/// Structural verification of untrusted `wire` buffers.
+pub mod verify;
+
+/// Content hashes, used to detect stale or corrupted asset files.
+pub mod hash;
+
+/// Building manifests for the asset pipeline.
+pub mod manifest;
+
+pub use self::manifest::ManifestBuilder;
+
+/// Transferring asset files from the server to clients which don't have them.
+pub mod stream;
+
+/// Mounting mods over the base game's assets.
+pub mod mods;
+
 #[cfg(feature = "client")] mod client;
 
 #[cfg(feature = "client")] pub use self::client::*;
@@ -42,37 +61,45 @@ pub enum Type {
 pub struct ServerDb {
     by_name: BTreeMap<String, AssetID>,
     by_id:   Vec<Type>,
+
+    // Paths of the files backing each asset, indexed by ID.
+    files: Vec<Vec<String>>,
 }
 
 impl ServerDb {
-    /// Load a `ServerDb`
-    pub fn from_manifest(w: &wire::AssetManifest) -> ServerDb {
+    /// Load a `ServerDb` from a manifest, which is validated first.
+    pub fn from_manifest(w: &wire::AssetManifest) -> Result<ServerDb, Vec<ValidationError>> {
+        let manifest = try!(ManifestBuilder::from_wire(w));
+
+        try!(manifest.validate());
+
         let mut db = ServerDb{
             by_name: BTreeMap::new(),
             by_id:   Vec::new(),
+            files:   Vec::new(),
         };
 
-        for wire_sheet_desc in w.sprite_sheets().unwrap().iter() {
+        for &(ref name, ref desc) in manifest.sheets() {
             let id: AssetID = db.by_id.len();
 
-            let name = From::from(wire_sheet_desc.name().unwrap().as_ref());
-
-            db.by_name.insert(name, id);
+            db.by_name.insert(name.clone(), id);
 
             db.by_id.push(Type::SpriteSheet);
+
+            db.files.push(desc.files().iter().map(|&(path, _)| From::from(path)).collect());
         }
 
-        for wire_anim in w.anims().unwrap().iter() {
+        for &(ref name, _) in manifest.anims() {
             let id: AssetID = db.by_id.len();
 
-            let name = From::from(wire_anim.name().unwrap().as_ref());
-
-            db.by_name.insert(name, id);
+            db.by_name.insert(name.clone(), id);
 
             db.by_id.push(Type::Animation);
+
+            db.files.push(vec![]);
         }
 
-        db
+        Ok(db)
     }
 
     /// Get the type of the asset referred to by a given ID, if such an asset exists.
@@ -80,6 +107,12 @@ impl ServerDb {
         self.by_id.get(id).cloned()
     }
 
+    /// Get the paths of the files which back the asset referred to by a given ID, in the order
+    /// they are streamed to clients. Returns `None` if there is no such asset.
+    pub fn files_by_id(&self, id: AssetID) -> Option<&[String]> {
+        self.files.get(id).map(|f| &f[..])
+    }
+
     /// Get the ID of the asset referred to by a given name, if such an asset exists.
     pub fn id_by_name<S: AsRef<str>>(&self, name: &S) -> Option<AssetID> {
         self.by_name.get(name.as_ref()).cloned()
@@ -101,3 +134,60 @@ impl ServerDb {
         }
     }
 }
+
+/// Check a manifest for missing fields, duplicate names, sheets with zero sizes, animations
+/// referring to missing sheets, and sprite indices which are out of range. Every problem found is
+/// reported, rather than just the first.
+pub fn validate(w: &wire::AssetManifest) -> Result<(), Vec<ValidationError>> {
+    ManifestBuilder::from_wire(w).and_then(|manifest| manifest.validate())
+}
+
+/// A problem with the contents of an asset manifest.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum ValidationError {
+    /// A required field is absent.
+    MissingField {
+        /// The asset's name, or its position in the manifest if the name itself is missing (e.g.
+        /// `anims[3]`).
+        asset: String,
+
+        /// The name of the missing field.
+        field: &'static str,
+    },
+
+    /// More than one asset has this name.
+    DuplicateName(String),
+
+    /// A sprite sheet's descriptor is unusable.
+    InvalidSheet {
+        /// The sheet's name.
+        sheet: String,
+
+        /// What's wrong with it.
+        error: sprite::DescError,
+    },
+
+    /// An animation refers to a sprite sheet which isn't in the manifest.
+    MissingSheet {
+        /// The animation's name.
+        anim: String,
+
+        /// The name of the sheet it refers to.
+        sheet: String,
+    },
+
+    /// An animation has no frames.
+    NoFrames(String),
+
+    /// An animation refers to a sprite which is beyond the end of its sheet.
+    SpriteOutOfRange {
+        /// The animation's name.
+        anim: String,
+
+        /// The offending sprite index.
+        index: u16,
+
+        /// The number of sprites in the sheet.
+        total: u16,
+    },
+}
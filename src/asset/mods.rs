@@ -0,0 +1,255 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! A mod is a directory of assets with its own manifest, mounted over the base game. Sources are
+//! merged in order of priority (lowest first, with ties broken by the order they were mounted),
+//! and when two sources define an asset or prefab with the same name, the later one wins. Paths
+//! in each source's manifest are relative to the source's root directory.
+//!
+//! Replaced assets keep the position of the asset they replace, so the `AssetID`s of the base
+//! game's assets don't depend on which mods are installed, as long as those mods only replace
+//! assets and add new ones.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use flatbuffers as fb;
+
+use asset::{wire, ManifestBuilder, ValidationError};
+use grafix::anim::AnimDesc;
+use grafix::sprite::SheetDesc;
+use logging;
+use tilemap::{Prefab, PrefabSet};
+
+/// The name of the manifest file at the root of a source mounted with `ModSet::mount_dir`.
+pub const MANIFEST_FILE: &'static str = "manifest.fb";
+
+/// A directory of assets which can be mounted over others.
+pub struct Source {
+    /// A name for the source, used in logs and `Override`s.
+    pub name: String,
+
+    /// The directory that paths in the source's manifest are relative to.
+    pub root: PathBuf,
+
+    /// Sources with higher priority override those with lower priority.
+    pub priority: i32,
+
+    /// The assets this source defines.
+    pub manifest: ManifestBuilder,
+
+    /// The prefabs this source defines.
+    pub prefabs: PrefabSet,
+}
+
+/// A record of an asset or prefab being replaced by a higher-priority source.
+#[derive(Clone,Debug)]
+pub struct Override {
+    /// The name of the replaced asset or prefab.
+    pub name: String,
+
+    /// The source whose definition was replaced.
+    pub replaced: String,
+
+    /// The source whose definition is used.
+    pub by: String,
+}
+
+/// The result of merging every mounted source.
+pub struct Merged {
+    /// The combined manifest, with every path resolved against its source's root.
+    pub manifest: ManifestBuilder,
+
+    /// The combined prefabs.
+    pub prefabs: PrefabSet,
+
+    /// Every definition which was replaced by another source's, in the order they were found.
+    pub overrides: Vec<Override>,
+}
+
+/// An error encountered while mounting a source.
+#[derive(Debug)]
+pub enum Error {
+    /// The source's manifest couldn't be read.
+    Io(io::Error),
+
+    /// The source's manifest isn't a valid FlatBuffer.
+    Malformed(::wire::Error),
+
+    /// The source's manifest is missing required fields.
+    Invalid(Vec<ValidationError>),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+/// A set of asset sources, such as the base game and any number of mods.
+pub struct ModSet {
+    sources: Vec<Source>,
+}
+
+impl ModSet {
+    /// Create an empty set.
+    pub fn new() -> ModSet {
+        ModSet { sources: vec![] }
+    }
+
+    /// Mount a source, returning it so that prefabs can be added to it.
+    pub fn mount<S, P>(&mut self, name: S, root: P, priority: i32, manifest: ManifestBuilder)
+        -> &mut Source where String: From<S>, P: AsRef<Path> {
+
+        self.sources.push(Source {
+            name:     From::from(name),
+            root:     root.as_ref().to_path_buf(),
+            priority: priority,
+            manifest: manifest,
+            prefabs:  PrefabSet::new(),
+        });
+
+        let last = self.sources.len() - 1;
+        &mut self.sources[last]
+    }
+
+    /// Mount the directory `root`, reading its manifest from `MANIFEST_FILE`.
+    pub fn mount_dir<S, P>(&mut self, name: S, root: P, priority: i32) -> Result<&mut Source, Error>
+        where String: From<S>, P: AsRef<Path> {
+
+        let root = root.as_ref();
+        let mut buf = vec![];
+
+        try!(try!(File::open(root.join(MANIFEST_FILE))).read_to_end(&mut buf));
+        try!(::asset::verify::verify_buffer(&buf).map_err(Error::Malformed));
+
+        let manifest = try!(ManifestBuilder::from_wire(fb::get_root::<wire::AssetManifest>(&buf))
+                                .map_err(Error::Invalid));
+
+        Ok(self.mount(name, root, priority, manifest))
+    }
+
+    /// Remove the source named `name`, returning it.
+    pub fn unmount(&mut self, name: &str) -> Option<Source> {
+        match self.sources.iter().position(|s| s.name == name) {
+            Some(i) => Some(self.sources.remove(i)),
+            None    => None,
+        }
+    }
+
+    /// The mounted sources, in the order they're merged.
+    pub fn sources(&self) -> Vec<&Source> {
+        let mut sources: Vec<&Source> = self.sources.iter().collect();
+
+        // This sort is stable, so sources with equal priority stay in the order they were mounted.
+        sources.sort_by(|a, b| a.priority.cmp(&b.priority));
+        sources
+    }
+
+    /// Find the file at `rel` in the highest-priority source which has one.
+    pub fn resolve<P: AsRef<Path>>(&self, rel: P) -> Option<PathBuf> {
+        self.sources().into_iter().rev()
+            .map(|s| s.root.join(rel.as_ref()))
+            .find(|p| p.exists())
+    }
+
+    /// Merge every source into a single manifest and set of prefabs. The result should be
+    /// validated before use, since a mod may refer to assets which don't exist.
+    pub fn merge(&self) -> Merged {
+        let mut sheets:  Vec<(String, SheetDesc, usize)> = vec![];
+        let mut anims:   Vec<(String, AnimDesc, usize)>  = vec![];
+        let mut prefabs: Vec<(Prefab, usize)>            = vec![];
+
+        let mut overrides = vec![];
+
+        let sources = self.sources();
+
+        for (i, src) in sources.iter().enumerate() {
+            for &(ref name, ref desc) in src.manifest.sheets() {
+                let mut desc = desc.clone();
+                desc.color_path    = resolve_in(&src.root, &desc.color_path);
+                desc.depth_path    = resolve_in(&src.root, &desc.depth_path);
+                desc.normal_path   = desc.normal_path.map(|p| resolve_in(&src.root, &p));
+                desc.emissive_path = desc.emissive_path.map(|p| resolve_in(&src.root, &p));
+
+                // Asset names are shared between sheets and animations, so a sheet also replaces
+                // an animation of the same name.
+                if let Some(j) = anims.iter().position(|a| a.0 == *name) {
+                    overrides.push(record(name, &sources, anims.remove(j).2, i));
+                }
+
+                match sheets.iter().position(|s| s.0 == *name) {
+                    Some(j) => {
+                        overrides.push(record(name, &sources, sheets[j].2, i));
+                        sheets[j] = (name.clone(), desc, i);
+                    },
+                    None => sheets.push((name.clone(), desc, i)),
+                }
+            }
+
+            for &(ref name, ref desc) in src.manifest.anims() {
+                if let Some(j) = sheets.iter().position(|s| s.0 == *name) {
+                    overrides.push(record(name, &sources, sheets.remove(j).2, i));
+                }
+
+                match anims.iter().position(|a| a.0 == *name) {
+                    Some(j) => {
+                        overrides.push(record(name, &sources, anims[j].2, i));
+                        anims[j] = (name.clone(), desc.clone(), i);
+                    },
+                    None => anims.push((name.clone(), desc.clone(), i)),
+                }
+            }
+
+            for prefab in src.prefabs.iter() {
+                match prefabs.iter().position(|p| p.0.name == prefab.name) {
+                    Some(j) => {
+                        overrides.push(record(&prefab.name, &sources, prefabs[j].1, i));
+                        prefabs[j] = (prefab.clone(), i);
+                    },
+                    None => prefabs.push((prefab.clone(), i)),
+                }
+            }
+        }
+
+        for o in overrides.iter() {
+            debug!(target: logging::ASSET, "'{}' from {} overrides {}", o.name, o.by, o.replaced);
+        }
+
+        let mut manifest = ManifestBuilder::new();
+        let mut set      = PrefabSet::new();
+
+        for (name, desc, _) in sheets  { manifest.add_sheet(name, desc); }
+        for (name, desc, _) in anims   { manifest.add_anim(name, desc); }
+        for (prefab, _)     in prefabs { set.add(prefab); }
+
+        Merged { manifest: manifest, prefabs: set, overrides: overrides }
+    }
+}
+
+fn resolve_in(root: &Path, path: &str) -> String {
+    root.join(path).to_string_lossy().into_owned()
+}
+
+fn record(name: &str, sources: &[&Source], replaced: usize, by: usize) -> Override {
+    Override {
+        name:     From::from(name),
+        replaced: sources[replaced].name.clone(),
+        by:       sources[by].name.clone(),
+    }
+}
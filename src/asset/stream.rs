@@ -0,0 +1,478 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::From;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+
+#[cfg(feature = "client")] use std::io::Write;
+#[cfg(feature = "client")] use std::path::Path;
+
+use flatbuffers as fb;
+
+use asset::{wire, AssetID, ServerDb};
+
+/// The largest amount of file data carried by a single `Chunk`.
+pub const CHUNK_SIZE: usize = 16 * 1024;
+
+/// The most files an asset can have and still be streamed, as `AssetRequest::files` has one bit
+/// for each.
+pub const MAX_FILES: usize = 32;
+
+/// Identifies the client a transfer is destined for. This is deliberately the same type as
+/// `net::interest::ClientID`.
+pub type ClientID = u32;
+
+/// A piece of one of the files backing an asset.
+pub struct Chunk {
+    /// The asset this chunk belongs to.
+    pub id: AssetID,
+
+    /// Index of the file within the asset's list of files.
+    pub file: usize,
+
+    /// Offset of `data` within the file.
+    pub offset: usize,
+
+    /// Total size of the file.
+    pub total: usize,
+
+    /// The bytes themselves.
+    pub data: Vec<u8>,
+
+    /// True if the server couldn't read the asset's files, and won't send any more of them. Such a
+    /// chunk carries no data.
+    pub failed: bool,
+}
+
+impl Chunk {
+    /// Convert from FlatBuffer representation. Chunks arrive from the network, so the buffer
+    /// should be checked with `asset::verify::verify_asset_chunk_buffer` first.
+    pub fn from_wire(w: &wire::AssetChunk) -> Chunk {
+        Chunk {
+            id:     w.id() as AssetID,
+            file:   w.file() as usize,
+            offset: w.offset() as usize,
+            total:  w.total() as usize,
+            data:   w.data().map(|d| d.iter().collect()).unwrap_or(vec![]),
+            failed: w.failed(),
+        }
+    }
+
+    /// A chunk telling the client that asset `id` can't be sent.
+    pub fn failure(id: AssetID) -> Chunk {
+        Chunk { id: id, file: 0, offset: 0, total: 0, data: vec![], failed: true }
+    }
+
+    /// Add this chunk to a FlatBuffer under construction.
+    pub fn to_wire(&self, fbb: &mut fb::FlatBufferBuilder) -> fb::Offset<wire::AssetChunk> {
+        let data = fbb.create_vector(&self.data);
+
+        let mut b = wire::AssetChunkBuilder::new(fbb);
+        b.add_id(self.id as u32);
+        b.add_file(self.file as u16);
+        b.add_offset(self.offset as u32);
+        b.add_total(self.total as u32);
+        b.add_data(data);
+        b.add_failed(self.failed);
+        b.finish()
+    }
+}
+
+/// An error encountered while sending or receiving asset files.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file failed.
+    Io(io::Error),
+
+    /// There is no asset with the requested ID.
+    NoSuchAsset(AssetID),
+
+    /// The asset has more than `MAX_FILES` files, so they can't all be requested.
+    TooManyFiles(AssetID),
+
+    /// A chunk arrived for an asset which wasn't requested, or out of order. Transfers are
+    /// expected to use a reliable, ordered channel, so this indicates a bug or a hostile peer.
+    UnexpectedChunk(AssetID),
+
+    /// The server couldn't send the asset, and its download was dropped.
+    Failed(AssetID),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+// A file transfer in progress on the server.
+struct Outgoing {
+    id:     AssetID,
+    files:  Vec<(usize, String)>, // The requested files, with their indices within the asset.
+    file:   usize,                // Index into `files`.
+    offset: usize,
+    failed: bool,                 // Reading failed, and the client is yet to be told.
+}
+
+/// The server half of asset streaming. Requests are queued per client, and chunks are pulled out
+/// by the networking code at whatever rate it can afford to send them.
+pub struct Streamer {
+    queues: BTreeMap<ClientID, VecDeque<Outgoing>>,
+}
+
+impl Streamer {
+    /// Create a `Streamer` with nothing to send.
+    pub fn new() -> Streamer {
+        Streamer { queues: BTreeMap::new() }
+    }
+
+    /// Queue up the files requested by `client`. Files which the client already has an up to date
+    /// copy of are left out of the request, and so aren't sent.
+    pub fn request(&mut self, db: &ServerDb, client: ClientID, req: &wire::AssetRequest)
+        -> Result<(), Error> {
+
+        let id   = req.id() as AssetID;
+        let mask = req.files();
+
+        let files = try!(db.files_by_id(id).ok_or(Error::NoSuchAsset(id)));
+
+        let files = files.iter().enumerate()
+            .filter(|&(i, _)| i < MAX_FILES && mask & (1 << i) != 0)
+            .map(|(i, path)| (i, path.clone()))
+            .collect();
+
+        self.queues.entry(client).or_insert(VecDeque::new()).push_back(Outgoing {
+            id:     id,
+            files:  files,
+            file:   0,
+            offset: 0,
+            failed: false,
+        });
+
+        Ok(())
+    }
+
+    /// Forget everything queued for `client`, e.g. because it disconnected.
+    pub fn cancel(&mut self, client: ClientID) {
+        self.queues.remove(&client);
+    }
+
+    /// Return true if there is anything left to send to `client`.
+    pub fn is_pending(&self, client: ClientID) -> bool {
+        self.queues.get(&client).map_or(false, |q| !q.is_empty())
+    }
+
+    /// Read the next chunk destined for `client`, if there is one. If an asset's files can't be
+    /// read, the error is returned, and the chunk after it is a `Chunk::failure` for the asset, so
+    /// that the client stops waiting for it.
+    pub fn next_chunk(&mut self, client: ClientID) -> Option<Result<Chunk, Error>> {
+        let queue = match self.queues.get_mut(&client) {
+            Some(queue) => queue,
+            None        => return None,
+        };
+
+        loop {
+            let (finished, failed) = match queue.front() {
+                Some(out) => (out.file >= out.files.len(), out.failed),
+                None      => return None,
+            };
+
+            if failed {
+                let out = queue.pop_front().unwrap();
+                return Some(Ok(Chunk::failure(out.id)))
+            }
+
+            if finished {
+                queue.pop_front();
+                continue
+            }
+
+            let out = queue.front_mut().unwrap();
+
+            let (index, ref path) = out.files[out.file];
+
+            let result = read_chunk(path, out.offset);
+
+            return Some(match result {
+                Ok((data, total)) => {
+                    let chunk = Chunk {
+                        id:     out.id,
+                        file:   index,
+                        offset: out.offset,
+                        total:  total,
+                        data:   data,
+                        failed: false,
+                    };
+
+                    out.offset += chunk.data.len();
+
+                    if out.offset >= total {
+                        out.file  += 1;
+                        out.offset = 0;
+                    }
+
+                    Ok(chunk)
+                },
+
+                Err(err) => {
+                    // Give up on this asset, but keep going with the rest of the queue.
+                    out.failed = true;
+                    Err(From::from(err))
+                },
+            })
+        }
+    }
+}
+
+// Read up to `CHUNK_SIZE` bytes from `path` starting at `offset`. Returns the bytes read along with
+// the total size of the file.
+fn read_chunk(path: &str, offset: usize) -> io::Result<(Vec<u8>, usize)> {
+    let mut file = try!(fs::File::open(path));
+    let total    = try!(file.metadata()).len() as usize;
+
+    try!(file.seek(SeekFrom::Start(offset as u64)));
+
+    let mut data = Vec::with_capacity(CHUNK_SIZE);
+    try!(file.take(CHUNK_SIZE as u64).read_to_end(&mut data));
+
+    Ok((data, total))
+}
+
+// A download in progress on the client.
+#[cfg(feature = "client")]
+struct Incoming {
+    files:  Vec<(usize, String)>, // The requested files, with their indices within the asset.
+    file:   usize,                // Index into `files`.
+    offset: usize,
+}
+
+/// The client half of asset streaming. It asks for assets whose files are missing, writes the
+/// chunks it receives into place, and loads each asset into the `AssetDb` once it's complete.
+#[cfg(feature = "client")]
+pub struct Receiver {
+    downloads: BTreeMap<AssetID, Incoming>,
+}
+
+#[cfg(feature = "client")]
+impl Receiver {
+    /// Create a `Receiver` with no downloads in progress.
+    pub fn new() -> Receiver {
+        Receiver { downloads: BTreeMap::new() }
+    }
+
+    /// Start downloading an asset, returning the request to send to the server. Only files which
+    /// are missing, or whose contents don't match the manifest's hashes, are requested. Returns
+    /// `None` if the asset is already being downloaded, or doesn't need any files.
+    pub fn request(&mut self, db: &::asset::AssetDb, id: AssetID)
+        -> Result<Option<wire::AssetRequest>, Error> {
+
+        if self.downloads.contains_key(&id) {
+            return Ok(None)
+        }
+
+        // The destination paths come from our own manifest rather than from the server, so a
+        // misbehaving server can't write outside of the asset directory.
+        let files = db.pending_files(id);
+
+        if files.is_empty() {
+            return Ok(None)
+        }
+
+        if files.iter().any(|&(i, _)| i >= MAX_FILES) {
+            return Err(Error::TooManyFiles(id))
+        }
+
+        let mask = files.iter().fold(0u32, |mask, &(i, _)| mask | (1 << i));
+
+        self.downloads.insert(id, Incoming { files: files, file: 0, offset: 0 });
+
+        Ok(Some(wire::AssetRequest::new(id as u32, mask)))
+    }
+
+    /// Return true if any downloads are still in progress.
+    pub fn is_pending(&self) -> bool {
+        !self.downloads.is_empty()
+    }
+
+    /// Stop waiting for an asset, e.g. because its download timed out. It can be requested again
+    /// later. Returns false if it wasn't being downloaded.
+    pub fn cancel(&mut self, id: AssetID) -> bool {
+        self.downloads.remove(&id).is_some()
+    }
+
+    /// Write a chunk to disk. If it completes an asset, that asset is loaded into `db` and its ID
+    /// is returned. If the server failed to send the asset, its download is dropped and
+    /// `Error::Failed` is returned.
+    pub fn receive(&mut self, db: &::asset::AssetDb, chunk: &Chunk)
+        -> Result<Option<AssetID>, Error> {
+
+        if chunk.failed {
+            return if self.cancel(chunk.id) {
+                Err(Error::Failed(chunk.id))
+            } else {
+                Err(Error::UnexpectedChunk(chunk.id))
+            }
+        }
+
+        let done = {
+            let inc = try!(self.downloads.get_mut(&chunk.id)
+                .ok_or(Error::UnexpectedChunk(chunk.id)));
+
+            if chunk.file != inc.files[inc.file].0 || chunk.offset != inc.offset {
+                return Err(Error::UnexpectedChunk(chunk.id))
+            }
+
+            let path = Path::new(&inc.files[inc.file].1);
+
+            if let Some(dir) = path.parent() {
+                try!(fs::create_dir_all(dir));
+            }
+
+            // The first chunk of a file replaces whatever (stale or partial) copy was there.
+            let mut file = if chunk.offset == 0 {
+                try!(fs::File::create(path))
+            } else {
+                try!(fs::OpenOptions::new().write(true).append(true).open(path))
+            };
+
+            try!(file.write_all(&chunk.data));
+
+            inc.offset += chunk.data.len();
+
+            if inc.offset >= chunk.total {
+                inc.file  += 1;
+                inc.offset = 0;
+            }
+
+            inc.file >= inc.files.len()
+        };
+
+        if done {
+            self.downloads.remove(&chunk.id);
+            db.load(chunk.id);
+            Ok(Some(chunk.id))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flatbuffers as fb;
+
+    use asset::manifest::ManifestBuilder;
+    use asset::stream::{Chunk, Error, Streamer};
+    use asset::{verify, wire, ServerDb};
+    use grafix::sprite::SheetDesc;
+
+    // Write `chunk` into a buffer, verify it, and read it back.
+    fn through_buffer(chunk: &Chunk) -> Chunk {
+        let mut fbb = fb::FlatBufferBuilder::new();
+
+        let root = chunk.to_wire(&mut fbb);
+
+        fbb.finish(root);
+
+        let buf = fbb.into_vec();
+
+        verify::verify_asset_chunk_buffer(&buf).unwrap();
+
+        Chunk::from_wire(fb::get_root::<wire::AssetChunk>(&buf))
+    }
+
+    #[test]
+    fn chunk_round_trip() {
+        let chunk = Chunk {
+            id:     3,
+            file:   1,
+            offset: 4096,
+            total:  5000,
+            data:   vec![1, 2, 3, 4],
+            failed: false,
+        };
+
+        let back = through_buffer(&chunk);
+
+        assert_eq!(back.id, 3);
+        assert_eq!(back.file, 1);
+        assert_eq!(back.offset, 4096);
+        assert_eq!(back.total, 5000);
+        assert_eq!(back.data, vec![1, 2, 3, 4]);
+        assert!(!back.failed);
+    }
+
+    #[test]
+    fn failure_round_trip() {
+        let back = through_buffer(&Chunk::failure(5));
+
+        assert_eq!(back.id, 5);
+        assert!(back.data.is_empty());
+        assert!(back.failed);
+    }
+
+    #[test]
+    fn unreadable_asset_fails() {
+        let mut manifest = ManifestBuilder::new();
+
+        manifest.add_sheet("tree", SheetDesc {
+            img_width:     64,
+            img_height:    32,
+            origin_x:      0,
+            origin_y:      0,
+            spr_width:     32,
+            spr_height:    32,
+            num_across:    2,
+            num_down:      1,
+            total:         2,
+            color_path:    "does/not/exist.png".to_string(),
+            depth_path:    "does/not/exist.depth.png".to_string(),
+            color_hash:    0,
+            depth_hash:    0,
+            normal_path:   None,
+            normal_hash:   0,
+            emissive_path: None,
+            emissive_hash: 0,
+        });
+
+        let buf = manifest.finish().unwrap();
+        let db  = ServerDb::from_manifest(fb::get_root::<wire::AssetManifest>(&buf)).unwrap();
+
+        let req = wire::AssetRequest::new(0, 0b11);
+
+        assert_eq!(req.id(), 0);
+        assert_eq!(req.files(), 0b11);
+
+        let mut streamer = Streamer::new();
+
+        streamer.request(&db, 9, &req).unwrap();
+
+        match streamer.next_chunk(9) {
+            Some(Err(Error::Io(_))) => (),
+            _                       => panic!("a missing file was read"),
+        }
+
+        let failure = streamer.next_chunk(9).unwrap().unwrap();
+
+        assert_eq!(failure.id, 0);
+        assert!(failure.failed);
+
+        assert!(streamer.next_chunk(9).is_none());
+        assert!(!streamer.is_pending(9));
+    }
+}
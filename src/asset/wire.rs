@@ -1,47 +1,19 @@
-// automatically generated by the FlatBuffers compiler, do not modify
-
-use flatbuffers as fb;
-
-pub struct AssetManifest {
-    inner: fb::Table,
-}
-
-impl AssetManifest {
-    pub fn sprite_sheets(&self) -> Option<&fb::Vector<fb::Offset<::grafix::sprite::wire::SpriteSheetDesc>, &::grafix::sprite::wire::SpriteSheetDesc>> {
-        self.inner.get_ref(4)
-    }
-    pub fn anims(&self) -> Option<&fb::Vector<fb::Offset<::grafix::anim::wire::Anim>, &::grafix::anim::wire::Anim>> {
-        self.inner.get_ref(6)
-    }
-}
-
-pub struct AssetManifestBuilder<'x> {
-    fbb:   &'x mut fb::FlatBufferBuilder,
-    start: fb::UOffset,
-}
-
-impl<'x> AssetManifestBuilder<'x> {
-    pub fn new(fbb: &'x mut fb::FlatBufferBuilder) -> AssetManifestBuilder<'x> {
-        let start = fbb.start_table();
-        AssetManifestBuilder {
-            fbb:   fbb,
-            start: start,
-        }
-    }
-
-    pub fn add_sprite_sheets(&mut self, sprite_sheets: fb::Offset<fb::Vector<fb::Offset<::grafix::sprite::wire::SpriteSheetDesc>, &::grafix::sprite::wire::SpriteSheetDesc>>) {
-        self.fbb.add_offset(4, sprite_sheets)
-    }
-
-    pub fn add_anims(&mut self, anims: fb::Offset<fb::Vector<fb::Offset<::grafix::anim::wire::Anim>, &::grafix::anim::wire::Anim>>) {
-        self.fbb.add_offset(6, anims)
-    }
-
-    pub fn finish(&mut self) -> fb::Offset<AssetManifest> {
-        let o = fb::Offset::new(self.fbb.end_table(self.start, 2));
-        // self.fbb.required(o, 4);  // sprite_sheets
-        // self.fbb.required(o, 6);  // anims
-        o
-    }
-}
-
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// Generated by `build.rs` from `wire.fbs` in this directory; see `codegen`.
+include!(concat!(env!("OUT_DIR"), "/src/asset/wire.rs"));
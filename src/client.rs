@@ -15,29 +15,139 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "config")] use config::Config;
+use crash;
+use entity::window::{self, WindowEvents};
 use grafix::opengl;
+use logging;
+use png;
 use sdl2;
+use trace;
+
+/// Where the panic hook installed by `Context::new` writes crash dumps.
+pub const CRASH_DUMP_PATH: &'static str = "crash-dump.txt";
 
 /// A RAII handle for the client-side engine.
 #[allow(dead_code)]
 pub struct Context {
     gfx: opengl::Context,
     sdl: sdl2::Sdl,
+
+    // Number of frames drawn so far.
+    frame: Cell<u64>,
 }
 
 impl Context {
     /// Create a new client context. This will create a window and an OpenGL context, as well as
     /// initialize all SDL subsystems.
+    ///
+    /// This also installs a panic hook which writes the state of the engine to `CRASH_DUMP_PATH`
+    /// (see the `crash` module). Use `crash::set_enabled` to turn it off.
     pub fn new(title: &str, x_res: i32, y_res: i32) -> Result<Context, String> {
         let sdl = try!(sdl2::init(sdl2::INIT_EVERYTHING));
         let gfx = try!(opengl::Context::new(title, x_res, y_res));
 
-        Ok(Context { sdl: sdl, gfx: gfx })
+        Ok(Context::finish(sdl, gfx))
+    }
+
+    /// Create a new client context whose window follows the video settings in `cfg`.
+    #[cfg(feature = "config")]
+    pub fn from_config(title: &str, cfg: &Config) -> Result<Context, String> {
+        let sdl = try!(sdl2::init(sdl2::INIT_EVERYTHING));
+        let gfx = try!(opengl::Context::with_options(title, cfg.width, cfg.height,
+                                                     cfg.fullscreen, cfg.vsync, cfg.srgb));
+
+        Ok(Context::finish(sdl, gfx))
+    }
+
+    fn finish(sdl: sdl2::Sdl, gfx: opengl::Context) -> Context {
+        crash::install(CRASH_DUMP_PATH, Some(opengl::get_error));
+
+        Context { sdl: sdl, gfx: gfx, frame: Cell::new(0) }
     }
 
     /// Swap OpenGL buffers, drawing the frame to the screen.
+    ///
+    /// This is also the boundary between frames for `trace` captures.
     pub fn draw_frame(&self) {
-        self.gfx.draw_frame();
+        {
+            let _span = trace::span(trace::GL, "swap_buffers");
+            self.gfx.draw_frame();
+        }
+
+        self.frame.set(self.frame.get() + 1);
+        crash::set_frame(self.frame.get());
+
+        match trace::next_frame(self.frame.get()) {
+            Some(Ok(path)) => info!(target: logging::GFX, "wrote frame trace to {}",
+                                    path.display()),
+            Some(Err(err)) => warn!(target: logging::GFX, "couldn't write frame trace: {}", err),
+            None           => {},
+        }
+    }
+
+    /// Replace the OpenGL context after the old one has been lost. GPU resources must then be
+    /// recreated, which a `grafix::device::Registry` takes care of.
+    pub fn recreate_gl(&mut self) -> Result<(), String> {
+        self.gfx.recreate()
+    }
+
+    /// The window's current title.
+    pub fn title(&self) -> String {
+        self.gfx.title()
+    }
+
+    /// Change the window's title, e.g. to show which server is connected or the frame rate.
+    pub fn set_title(&mut self, title: &str) {
+        self.gfx.set_title(title);
+    }
+
+    /// Load the PNG at `path` and use it as the window's icon.
+    pub fn set_icon<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        let img = try!(png::load_png(path.as_ref()));
+
+        self.set_icon_png(&img)
+    }
+
+    /// Use an already-loaded PNG as the window's icon.
+    pub fn set_icon_png(&mut self, img: &png::Image) -> Result<(), String> {
+        self.gfx.set_icon(img)
+    }
+
+    /// Take the events SDL has received since the last call. Window events (resizes, focus
+    /// changes, requests to quit and dropped files) are pushed into `window`, to reach systems at
+    /// the next `Manager::update`. Everything else, such as keys and the mouse, is returned for the
+    /// game to translate into `input::Event`s, e.g. with `input::Keymap::translate`.
+    pub fn pump_events(&self, window: &mut WindowEvents) -> Vec<sdl2::event::Event> {
+        use sdl2::event::{Event, WindowEventId};
+
+        let mut pump = self.sdl.event_pump();
+        let mut rest = vec![];
+
+        for ev in pump.poll_iter() {
+            match ev {
+                Event::Quit { .. } => window.push(window::Event::QuitRequested),
+
+                Event::Window { win_event_id: WindowEventId::Resized, data1, data2, .. } =>
+                    window.push(window::Event::Resized(data1 as u32, data2 as u32)),
+
+                Event::Window { win_event_id: WindowEventId::FocusGained, .. } =>
+                    window.push(window::Event::FocusGained),
+
+                Event::Window { win_event_id: WindowEventId::FocusLost, .. } =>
+                    window.push(window::Event::FocusLost),
+
+                Event::DropFile { filename, .. } =>
+                    window.push(window::Event::FileDropped(PathBuf::from(filename))),
+
+                other => rest.push(other),
+            }
+        }
+
+        rest
     }
 
     /// A debug method to get the sdl.
@@ -0,0 +1,1287 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// This module is also compiled into the crate's own build script (see `build.rs`), so it must not
+// refer to anything else in the crate.
+//
+// Only the part of the schema language which the engine actually uses is supported: `include`,
+// `namespace`, `struct`, `table` and `root_type` declarations, with scalar, `string`, vector,
+// struct and table fields, defaults for scalars and the `required` attribute. Anything else is
+// rejected with an error rather than quietly generating something which doesn't match the schema.
+
+use std::collections::HashSet;
+use std::convert::AsRef;
+use std::fmt::Write;
+use std::fs;
+use std::io::{self, Read};
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::str::Chars;
+
+/// The directory containing the engine's own schemas. Downstream crates should pass this to
+/// `Generator::include`, so that their schemas can refer to engine types, e.g.
+///
+/// ```text
+/// include "grafix/anim/wire.fbs";
+/// ```
+pub const SCHEMA_DIR: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+
+/// An error encountered while generating code.
+#[derive(Debug)]
+pub enum Error {
+    /// A file couldn't be read or written. Carries the file's path.
+    Io(PathBuf, io::Error),
+
+    /// A schema is malformed, or uses something the generator doesn't support. Carries the
+    /// schema's path, the line the problem was found on, and a description of it.
+    Schema(PathBuf, usize, String),
+}
+
+/// Turns `.fbs` schemas into `wire` modules.
+///
+/// For each schema the generator writes two files. `wire.rs` contains an accessor type for every
+/// table and struct, and a builder for every table. `verify.rs` contains a function for every table
+/// which checks it with a `wire::Verifier`, and a `verify_<table>_buffer` entry point for each
+/// table (plus `verify_buffer` for the `root_type`). The generator is meant to be run from a build
+/// script, with the output going to `OUT_DIR` and the crate's `wire` and `verify` modules
+/// `include!`ing it. A downstream crate can generate its own schemas this way:
+///
+/// ```no_run
+/// extern crate isoengine;
+///
+/// use std::env;
+///
+/// use isoengine::codegen::{Generator, SCHEMA_DIR};
+///
+/// fn main() {
+///     let out_dir = env::var("OUT_DIR").unwrap();
+///
+///     let mut gen = Generator::new();
+///     gen.include(SCHEMA_DIR).include("src");
+///
+///     for schema in gen.generate("src/game/wire.fbs", &out_dir).unwrap() {
+///         println!("cargo:rerun-if-changed={}", schema.display());
+///     }
+/// }
+/// ```
+///
+/// with `src/game/wire.rs` and `src/game/verify.rs` containing
+///
+/// ```text
+/// include!(concat!(env!("OUT_DIR"), "/wire.rs"));
+/// include!(concat!(env!("OUT_DIR"), "/verify.rs"));
+/// ```
+///
+/// The generated code refers to types from other namespaces by their absolute path within the
+/// crate (the namespace `grafix.anim.wire` becomes `::grafix::anim::wire`, and its verifiers are
+/// expected in the sibling module `::grafix::anim::verify`). The verifiers also use `::wire` for
+/// the `Verifier` itself. So a downstream crate including engine schemas needs to re-export those
+/// modules at its root (`pub use isoengine::{grafix, wire};`).
+pub struct Generator {
+    includes: Vec<PathBuf>,
+}
+
+impl Generator {
+    /// Create a generator with no include directories.
+    pub fn new() -> Generator {
+        Generator {
+            includes: vec![],
+        }
+    }
+
+    /// Add a directory to search for `include`d schemas. Includes are looked up relative to the
+    /// including schema first, and then in each of these directories in order.
+    pub fn include<P: AsRef<Path>>(&mut self, dir: P) -> &mut Generator {
+        self.includes.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Generate the `wire` and `verify` modules for `schema`, writing them to `wire.rs` and
+    /// `verify.rs` in `out_dir`. Returns the paths of every schema which was read (`schema` and
+    /// everything it includes), so that a build script can tell cargo to watch them.
+    pub fn generate<P, Q>(&self, schema: P, out_dir: Q) -> Result<Vec<PathBuf>, Error>
+        where P: AsRef<Path>, Q: AsRef<Path> {
+
+        let schema  = schema.as_ref();
+        let out_dir = out_dir.as_ref();
+
+        let main = try!(self.parse(schema));
+
+        let mut deps = vec![];
+        let mut seen = HashSet::new();
+
+        seen.insert(try!(canonicalize(schema)));
+
+        for &(ref inc, line) in main.includes.iter() {
+            let path = try!(self.find_include(schema, inc, line));
+            try!(self.load(&path, &mut deps, &mut seen));
+        }
+
+        let (wire, verify) = {
+            let types = Types::new(&main, &deps);
+            (try!(types.wire_module(&main)), try!(types.verify_module(&main)))
+        };
+
+        try!(fs::create_dir_all(out_dir).map_err(|e| Error::Io(out_dir.to_path_buf(), e)));
+        try!(write_file(&out_dir.join("wire.rs"), &wire));
+        try!(write_file(&out_dir.join("verify.rs"), &verify));
+
+        let mut read = vec![schema.to_path_buf()];
+        read.extend(deps.into_iter().map(|s| s.path));
+
+        Ok(read)
+    }
+
+    /// Generate code for every schema named in `list` (one path per line, relative to `root`).
+    /// The modules are written to the same relative path under `out_dir` as their schema, so e.g.
+    /// `src/asset/wire.fbs` becomes `<out_dir>/src/asset/wire.rs` and `verify.rs`. This is the
+    /// format of the engine's own `flatbuffers.list`. Returns the paths of every schema which was
+    /// read.
+    pub fn generate_list<P, Q, R>(&self, list: P, root: Q, out_dir: R)
+        -> Result<Vec<PathBuf>, Error> where P: AsRef<Path>, Q: AsRef<Path>, R: AsRef<Path> {
+
+        let contents = try!(read_file(list.as_ref()));
+
+        let mut read = vec![];
+
+        for line in contents.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+            let schema = root.as_ref().join(line);
+            let out    = out_dir.as_ref().join(line);
+            let out    = out.parent().unwrap_or(out_dir.as_ref());
+
+            for path in try!(self.generate(&schema, out)) {
+                if !read.contains(&path) {
+                    read.push(path);
+                }
+            }
+        }
+
+        Ok(read)
+    }
+
+    fn parse(&self, path: &Path) -> Result<Schema, Error> {
+        let src = try!(read_file(path));
+
+        parse_schema(path, &src).map_err(|(line, msg)| Error::Schema(path.to_path_buf(), line, msg))
+    }
+
+    // Load the schema at `path` and everything it includes into `out`, skipping anything in
+    // `seen`.
+    fn load(&self, path: &Path, out: &mut Vec<Schema>, seen: &mut HashSet<PathBuf>)
+        -> Result<(), Error> {
+
+        if !seen.insert(try!(canonicalize(path))) {
+            return Ok(())
+        }
+
+        let schema = try!(self.parse(path));
+
+        for &(ref inc, line) in schema.includes.iter() {
+            let inc_path = try!(self.find_include(path, inc, line));
+            try!(self.load(&inc_path, out, seen));
+        }
+
+        out.push(schema);
+
+        Ok(())
+    }
+
+    fn find_include(&self, from: &Path, inc: &str, line: usize) -> Result<PathBuf, Error> {
+        let local = from.parent().map(|dir| dir.join(inc));
+
+        for path in local.into_iter().chain(self.includes.iter().map(|dir| dir.join(inc))) {
+            if path.is_file() {
+                return Ok(path)
+            }
+        }
+
+        Err(Error::Schema(from.to_path_buf(), line, format!("can't find `{}`", inc)))
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, Error> {
+    let mut contents = String::new();
+
+    try!(fs::File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .map_err(|e| Error::Io(path.to_path_buf(), e)));
+
+    Ok(contents)
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<(), Error> {
+    fs::File::create(path)
+        .and_then(|mut f| io::Write::write_all(&mut f, contents.as_bytes()))
+        .map_err(|e| Error::Io(path.to_path_buf(), e))
+}
+
+fn canonicalize(path: &Path) -> Result<PathBuf, Error> {
+    fs::canonicalize(path).map_err(|e| Error::Io(path.to_path_buf(), e))
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+enum Scalar {
+    Bool,
+    Byte,
+    UByte,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Long,
+    ULong,
+    Float,
+    Double,
+}
+
+impl Scalar {
+    fn from_name(name: &str) -> Option<Scalar> {
+        match name {
+            "bool"             => Some(Scalar::Bool),
+            "byte"   | "int8"  => Some(Scalar::Byte),
+            "ubyte"  | "uint8" => Some(Scalar::UByte),
+            "short"  | "int16" => Some(Scalar::Short),
+            "ushort" | "uint16" => Some(Scalar::UShort),
+            "int"    | "int32" => Some(Scalar::Int),
+            "uint"   | "uint32" => Some(Scalar::UInt),
+            "long"   | "int64" => Some(Scalar::Long),
+            "ulong"  | "uint64" => Some(Scalar::ULong),
+            "float"  | "float32" => Some(Scalar::Float),
+            "double" | "float64" => Some(Scalar::Double),
+            _                  => None,
+        }
+    }
+
+    // Scalars are aligned to their own size.
+    fn size(self) -> usize {
+        match self {
+            Scalar::Bool  | Scalar::Byte   | Scalar::UByte => 1,
+            Scalar::Short | Scalar::UShort                 => 2,
+            Scalar::Int   | Scalar::UInt   | Scalar::Float => 4,
+            Scalar::Long  | Scalar::ULong  | Scalar::Double => 8,
+        }
+    }
+
+    // The type the accessors deal in.
+    fn rust(self) -> &'static str {
+        match self {
+            Scalar::Bool => "bool",
+            _            => self.storage(),
+        }
+    }
+
+    // The type the value is stored as. `bool`s are stored as a byte, since Rust doesn't allow a
+    // `bool` to hold anything other than 0 or 1, and a buffer could.
+    fn storage(self) -> &'static str {
+        match self {
+            Scalar::Bool   => "u8",
+            Scalar::Byte   => "i8",
+            Scalar::UByte  => "u8",
+            Scalar::Short  => "i16",
+            Scalar::UShort => "u16",
+            Scalar::Int    => "i32",
+            Scalar::UInt   => "u32",
+            Scalar::Long   => "i64",
+            Scalar::ULong  => "u64",
+            Scalar::Float  => "f32",
+            Scalar::Double => "f64",
+        }
+    }
+
+    // Turn a default from the schema into a Rust literal of the storage type.
+    fn literal(self, value: &str) -> Option<String> {
+        match self {
+            Scalar::Bool => match value {
+                "true"  | "1" => Some("1".to_string()),
+                "false" | "0" => Some("0".to_string()),
+                _             => None,
+            },
+
+            Scalar::Float | Scalar::Double => value.parse::<f64>().ok().map(|_| {
+                if value.contains(|c| c == '.' || c == 'e' || c == 'E') {
+                    value.to_string()
+                } else {
+                    format!("{}.0", value)
+                }
+            }),
+
+            _ => value.parse::<i64>().ok().map(|_| value.to_string()),
+        }
+    }
+}
+
+#[derive(Clone,Debug)]
+enum Type {
+    Scalar(Scalar),
+    String,
+    Vector(Box<Type>),
+    Named(String),
+}
+
+#[derive(Clone,Debug)]
+struct Field {
+    name:     String,
+    ty:       Type,
+    default:  Option<String>,
+    required: bool,
+    line:     usize,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+enum Kind {
+    Struct,
+    Table,
+}
+
+#[derive(Clone,Debug)]
+struct Decl {
+    kind:      Kind,
+    name:      String,
+    namespace: Vec<String>,
+    fields:    Vec<Field>,
+    line:      usize,
+}
+
+#[derive(Debug)]
+struct Schema {
+    path:      PathBuf,
+    includes:  Vec<(String, usize)>,
+    decls:     Vec<Decl>,
+    root_type: Option<(String, usize)>,
+}
+
+#[derive(Clone,Debug,PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    Str(String),
+    Punct(char),
+    End,
+}
+
+fn describe(tok: &Token) -> String {
+    match *tok {
+        Token::Ident(ref s) | Token::Number(ref s) => format!("`{}`", s),
+        Token::Str(ref s)                          => format!("\"{}\"", s),
+        Token::Punct(c)                            => format!("`{}`", c),
+        Token::End                                 => "the end of the file".to_string(),
+    }
+}
+
+fn take_while<F>(chars: &mut Peekable<Chars>, first: char, f: F) -> String
+    where F: Fn(char) -> bool {
+
+    let mut s = first.to_string();
+
+    loop {
+        let c = match chars.peek() {
+            Some(&c) if f(c) => c,
+            _                => break,
+        };
+
+        chars.next();
+        s.push(c);
+    }
+
+    s
+}
+
+fn tokenize(src: &str) -> Result<Vec<(Token, usize)>, (usize, String)> {
+    let mut toks  = vec![];
+    let mut chars = src.chars().peekable();
+    let mut line  = 1;
+
+    while let Some(c) = chars.next() {
+        let tok = match c {
+            '\n' => { line += 1; continue },
+
+            c if c.is_whitespace() => continue,
+
+            '/' => match chars.next() {
+                Some('/') => {
+                    take_while(&mut chars, '/', |c| c != '\n');
+                    continue
+                },
+
+                Some('*') => {
+                    let mut prev = ' ';
+
+                    loop {
+                        match chars.next() {
+                            Some('/') if prev == '*' => break,
+                            Some(c)                  => {
+                                if c == '\n' { line += 1 }
+                                prev = c;
+                            },
+                            None => return Err((line, "unterminated comment".to_string())),
+                        }
+                    }
+
+                    continue
+                },
+
+                _ => return Err((line, "unexpected `/`".to_string())),
+            },
+
+            '"' => {
+                let mut s = String::new();
+
+                loop {
+                    match chars.next() {
+                        Some('"')  => break,
+                        Some('\n') | None => {
+                            return Err((line, "unterminated string".to_string()))
+                        },
+                        Some(c)    => s.push(c),
+                    }
+                }
+
+                Token::Str(s)
+            },
+
+            '{' | '}' | '[' | ']' | '(' | ')' | ':' | ';' | '=' | ',' => Token::Punct(c),
+
+            c if c.is_alphabetic() || c == '_' => {
+                Token::Ident(take_while(&mut chars, c, |c| {
+                    c.is_alphanumeric() || c == '_' || c == '.'
+                }))
+            },
+
+            c if c.is_digit(10) || c == '-' || c == '+' || c == '.' => {
+                Token::Number(take_while(&mut chars, c, |c| {
+                    c.is_alphanumeric() || c == '.' || c == '-' || c == '+'
+                }))
+            },
+
+            c => return Err((line, format!("unexpected `{}`", c))),
+        };
+
+        toks.push((tok, line));
+    }
+
+    toks.push((Token::End, line));
+
+    Ok(toks)
+}
+
+struct Parser {
+    toks: Vec<(Token, usize)>,
+    pos:  usize,
+}
+
+impl Parser {
+    fn line(&self) -> usize {
+        self.toks[self.pos].1
+    }
+
+    fn peek(&self) -> &Token {
+        &self.toks[self.pos].0
+    }
+
+    fn next(&mut self) -> Token {
+        let tok = self.toks[self.pos].0.clone();
+
+        // The last token is always `End`, and we just keep returning it.
+        if self.pos + 1 < self.toks.len() {
+            self.pos += 1;
+        }
+
+        tok
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if *self.peek() == Token::Punct(c) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.next() {
+            Token::Punct(p) if p == c => Ok(()),
+            tok                       => Err(format!("expected `{}`, found {}", c, describe(&tok))),
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, String> {
+        match self.next() {
+            Token::Ident(s) => Ok(s),
+            tok             => Err(format!("expected a name, found {}", describe(&tok))),
+        }
+    }
+
+    fn ty(&mut self) -> Result<Type, String> {
+        if self.eat('[') {
+            let elem = try!(self.ty());
+
+            if let Type::Vector(_) = elem {
+                return Err("vectors of vectors aren't allowed".to_string())
+            }
+
+            try!(self.expect(']'));
+
+            return Ok(Type::Vector(Box::new(elem)))
+        }
+
+        let name = try!(self.ident());
+
+        Ok(match Scalar::from_name(&name) {
+            Some(s)                  => Type::Scalar(s),
+            None if name == "string" => Type::String,
+            None                     => Type::Named(name),
+        })
+    }
+
+    fn field(&mut self) -> Result<Field, String> {
+        let line = self.line();
+        let name = try!(self.ident());
+
+        try!(self.expect(':'));
+
+        let ty = try!(self.ty());
+
+        let default = if self.eat('=') {
+            match self.next() {
+                Token::Number(s) | Token::Ident(s) => Some(s),
+                tok => return Err(format!("expected a default value, found {}", describe(&tok))),
+            }
+        } else {
+            None
+        };
+
+        let mut required = false;
+
+        if self.eat('(') {
+            loop {
+                match &*try!(self.ident()) {
+                    "required" => required = true,
+                    attr       => return Err(format!("the `{}` attribute isn't supported", attr)),
+                }
+
+                if !self.eat(',') {
+                    break
+                }
+            }
+
+            try!(self.expect(')'));
+        }
+
+        try!(self.expect(';'));
+
+        Ok(Field {
+            name:     name,
+            ty:       ty,
+            default:  default,
+            required: required,
+            line:     line,
+        })
+    }
+}
+
+fn parse_schema(path: &Path, src: &str) -> Result<Schema, (usize, String)> {
+    let mut p = Parser {
+        toks: try!(tokenize(src)),
+        pos:  0,
+    };
+
+    let mut schema = Schema {
+        path:      path.to_path_buf(),
+        includes:  vec![],
+        decls:     vec![],
+        root_type: None,
+    };
+
+    let mut namespace = vec![];
+
+    // Parse errors are reported on the line of the token where parsing stopped.
+    macro_rules! at_line {
+        ($e:expr) => (try!($e.map_err(|msg| (p.line(), msg))))
+    }
+
+    loop {
+        let line = p.line();
+
+        let keyword = match p.next() {
+            Token::Ident(s) => s,
+            Token::End      => break,
+            tok             => return Err((line, format!("unexpected {}", describe(&tok)))),
+        };
+
+        match &*keyword {
+            "include" => {
+                match p.next() {
+                    Token::Str(s) => schema.includes.push((s, line)),
+                    tok => return Err((line, format!("expected a path, found {}", describe(&tok)))),
+                }
+
+                at_line!(p.expect(';'));
+            },
+
+            "namespace" => {
+                let ns = at_line!(p.ident());
+                namespace = ns.split('.').map(|s| s.to_string()).collect();
+
+                at_line!(p.expect(';'));
+            },
+
+            "struct" | "table" => {
+                let name = at_line!(p.ident());
+
+                at_line!(p.expect('{'));
+
+                let mut fields = vec![];
+
+                while !p.eat('}') {
+                    fields.push(at_line!(p.field()));
+                }
+
+                schema.decls.push(Decl {
+                    kind:      if keyword == "struct" { Kind::Struct } else { Kind::Table },
+                    name:      name,
+                    namespace: namespace.clone(),
+                    fields:    fields,
+                    line:      line,
+                });
+            },
+
+            "root_type" => {
+                schema.root_type = Some((at_line!(p.ident()), line));
+                at_line!(p.expect(';'));
+            },
+
+            other => return Err((line, format!("`{}` declarations aren't supported", other))),
+        }
+    }
+
+    Ok(schema)
+}
+
+const KEYWORDS: &'static [&'static str] = &[
+    "abstract", "alignof", "as", "become", "box", "break", "const", "continue", "crate", "do",
+    "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in", "let", "loop",
+    "macro", "match", "mod", "move", "mut", "offsetof", "override", "priv", "proc", "pub", "pure",
+    "ref", "return", "self", "sizeof", "static", "struct", "super", "trait", "true", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+// Every declaration visible to the schema being generated.
+struct Types<'x> {
+    decls: Vec<&'x Decl>,
+}
+
+// Errors found while generating code are reported against the main schema, with the line of the
+// declaration or field responsible.
+type GenResult<T> = Result<T, (usize, String)>;
+
+macro_rules! w {
+    ($($arg:tt)*) => (writeln!($($arg)*).unwrap())
+}
+
+impl<'x> Types<'x> {
+    fn new(main: &'x Schema, deps: &'x [Schema]) -> Types<'x> {
+        let mut decls: Vec<&Decl> = main.decls.iter().collect();
+
+        for schema in deps.iter() {
+            decls.extend(schema.decls.iter());
+        }
+
+        Types { decls: decls }
+    }
+
+    // Find the declaration named `name`, as seen from namespace `from`. A name without a namespace
+    // refers to a declaration in `from` if there is one, and must be unambiguous otherwise.
+    fn lookup(&self, name: &str, from: &[String], line: usize) -> GenResult<&'x Decl> {
+        let mut parts: Vec<String> = name.split('.').map(|s| s.to_string()).collect();
+        let     base = parts.pop().unwrap();
+
+        let found: Vec<&Decl> = self.decls.iter().cloned().filter(|d| {
+            d.name == base && (parts.is_empty() || d.namespace == parts)
+        }).collect();
+
+        if let Some(d) = found.iter().find(|d| parts.is_empty() && d.namespace == from) {
+            return Ok(d)
+        }
+
+        match found.len() {
+            1 => Ok(found[0]),
+            0 => Err((line, format!("no such type `{}`", name))),
+            _ => Err((line, format!("`{}` is ambiguous; qualify it with its namespace", name))),
+        }
+    }
+
+    // The Rust path of `decl`'s type, as seen from namespace `from`.
+    fn path(&self, decl: &Decl, from: &[String]) -> String {
+        if decl.namespace == from {
+            decl.name.clone()
+        } else {
+            let mut path = String::new();
+
+            for part in decl.namespace.iter() {
+                path.push_str("::");
+                path.push_str(part);
+            }
+
+            format!("{}::{}", path, decl.name)
+        }
+    }
+
+    // The size and alignment of a struct. Fields are laid out in order, each aligned to its own
+    // alignment, and the struct is padded out to a multiple of its largest alignment.
+    fn layout(&self, decl: &Decl, depth: usize) -> GenResult<(usize, usize)> {
+        if depth > 64 {
+            return Err((decl.line, format!("struct `{}` contains itself", decl.name)))
+        }
+
+        let mut size  = 0;
+        let mut align = 1;
+
+        for field in decl.fields.iter() {
+            let (fsize, falign) = try!(self.struct_field_layout(decl, field, depth));
+
+            size  = round_up(size, falign) + fsize;
+            align = if falign > align { falign } else { align };
+        }
+
+        Ok((round_up(size, align), align))
+    }
+
+    fn struct_field_layout(&self, decl: &Decl, field: &Field, depth: usize)
+        -> GenResult<(usize, usize)> {
+
+        match field.ty {
+            Type::Scalar(s) => Ok((s.size(), s.size())),
+
+            Type::Named(ref name) => {
+                let inner = try!(self.lookup(name, &decl.namespace, field.line));
+
+                if inner.kind != Kind::Struct {
+                    return Err((field.line, format!("struct fields can't be tables (`{}`)", name)))
+                }
+
+                self.layout(inner, depth + 1)
+            },
+
+            _ => Err((field.line, "struct fields must be scalars or structs".to_string())),
+        }
+    }
+
+    fn check_field(&self, decl: &Decl, field: &Field) -> GenResult<()> {
+        if KEYWORDS.contains(&&*field.name) || field.name.starts_with("__") {
+            return Err((field.line, format!("`{}` can't be used as a field name", field.name)))
+        }
+
+        if decl.kind == Kind::Struct {
+            if field.name == "new" {
+                return Err((field.line, "struct fields can't be called `new`".to_string()))
+            }
+
+            if field.default.is_some() || field.required {
+                return Err((field.line, "struct fields can't have defaults or attributes"
+                    .to_string()))
+            }
+        }
+
+        match (&field.ty, &field.default) {
+            (&Type::Scalar(s), &Some(ref d)) if s.literal(d).is_none() => {
+                Err((field.line, format!("`{}` isn't a valid default for `{}`", d, field.name)))
+            },
+
+            (&Type::Scalar(_), _) if field.required => {
+                Err((field.line, "scalar fields can't be required".to_string()))
+            },
+
+            (&Type::Scalar(_), _) | (_, &None) => Ok(()),
+
+            _ => Err((field.line, "only scalar fields can have defaults".to_string())),
+        }
+    }
+
+    fn wire_module(&self, main: &Schema) -> Result<String, Error> {
+        let mut out = String::new();
+
+        w!(out, "// Generated from `{}` by `isoengine::codegen`. Edit the schema rather than this",
+           main.path.display());
+        w!(out, "// file, which is rewritten on every build.");
+        w!(out, "");
+        w!(out, "use flatbuffers as fb;");
+
+        for decl in main.decls.iter() {
+            let res = match decl.kind {
+                Kind::Struct => self.struct_code(&mut out, decl),
+                Kind::Table  => self.table_code(&mut out, decl),
+            };
+
+            try!(res.map_err(|(line, msg)| Error::Schema(main.path.clone(), line, msg)));
+        }
+
+        Ok(out)
+    }
+
+    fn struct_code(&self, out: &mut String, decl: &Decl) -> GenResult<()> {
+        let ns   = &decl.namespace;
+        let name = &decl.name;
+
+        // Pad explicitly, so that `new` can zero the padding and a struct's bytes are always the
+        // same for the same values. The fields are all aligned, so `repr(C)` adds nothing extra.
+        let (size, _) = try!(self.layout(decl, 0));
+
+        let mut members = vec![];
+        let mut pads    = 0;
+        let mut offset  = 0;
+
+        for field in decl.fields.iter() {
+            try!(self.check_field(decl, field));
+
+            let (fsize, falign) = try!(self.struct_field_layout(decl, field, 0));
+
+            let aligned = round_up(offset, falign);
+            padding(&mut members, &mut pads, &mut offset, aligned);
+
+            let ty = match field.ty {
+                Type::Scalar(s)       => s.storage().to_string(),
+                Type::Named(ref name) => self.path(try!(self.lookup(name, ns, field.line)), ns),
+                _                     => unreachable!(),
+            };
+
+            members.push((field.name.clone(), ty));
+            offset += fsize;
+        }
+
+        padding(&mut members, &mut pads, &mut offset, size);
+
+        w!(out, "");
+        w!(out, "#[derive(Clone,Copy)]");
+        w!(out, "#[repr(C)]");
+        w!(out, "pub struct {} {{", name);
+
+        for &(ref member, ref ty) in members.iter() {
+            w!(out, "    {}: {},", member, ty);
+        }
+
+        w!(out, "}}");
+        w!(out, "");
+        w!(out, "impl {} {{", name);
+
+        let mut args = vec![];
+
+        for field in decl.fields.iter() {
+            args.push(match field.ty {
+                Type::Scalar(s)       => format!("{}: {}", field.name, s.rust()),
+                Type::Named(ref name) => {
+                    let inner = try!(self.lookup(name, ns, field.line));
+                    format!("{}: &{}", field.name, self.path(inner, ns))
+                },
+                _ => unreachable!(),
+            });
+        }
+
+        w!(out, "    pub fn new({}) -> {} {{", args.join(", "), name);
+        w!(out, "        {} {{", name);
+
+        for field in decl.fields.iter() {
+            match field.ty {
+                Type::Scalar(Scalar::Bool) => {
+                    w!(out, "            {0}: if {0} {{ 1 }} else {{ 0 }},", field.name)
+                },
+                Type::Scalar(_) => w!(out, "            {0}: fb::Endian::to_le({0}),", field.name),
+                _               => w!(out, "            {0}: *{0},", field.name),
+            }
+        }
+
+        for &(ref member, _) in members.iter().filter(|m| m.0.starts_with("__")) {
+            w!(out, "            {}: 0,", member);
+        }
+
+        w!(out, "        }}");
+        w!(out, "    }}");
+
+        for field in decl.fields.iter() {
+            w!(out, "");
+
+            match field.ty {
+                Type::Scalar(Scalar::Bool) => {
+                    w!(out, "    pub fn {0}(&self) -> bool {{ self.{0} != 0 }}", field.name)
+                },
+
+                Type::Scalar(s) => {
+                    w!(out, "    pub fn {0}(&self) -> {1} {{ fb::Endian::from_le(self.{0}) }}",
+                       field.name, s.rust())
+                },
+
+                Type::Named(ref name) => {
+                    let inner = try!(self.lookup(name, ns, field.line));
+
+                    w!(out, "    pub fn {0}(&self) -> &{1} {{ &self.{0} }}",
+                       field.name, self.path(inner, ns))
+                },
+
+                _ => unreachable!(),
+            }
+        }
+
+        w!(out, "}}");
+
+        Ok(())
+    }
+
+    // The Rust type of a vector's elements as they're stored in the buffer.
+    fn vector_elem(&self, decl: &Decl, field: &Field, elem: &Type) -> GenResult<String> {
+        let ns = &decl.namespace;
+
+        match *elem {
+            Type::Scalar(s) => Ok(s.storage().to_string()),
+
+            Type::Named(ref name) => {
+                let inner = try!(self.lookup(name, ns, field.line));
+                let path  = self.path(inner, ns);
+
+                Ok(match inner.kind {
+                    Kind::Struct => path,
+                    Kind::Table  => format!("fb::Offset<{0}>, &{0}", path),
+                })
+            },
+
+            _ => Err((field.line, "vectors of strings aren't supported".to_string())),
+        }
+    }
+
+    fn table_code(&self, out: &mut String, decl: &Decl) -> GenResult<()> {
+        let ns   = &decl.namespace;
+        let name = &decl.name;
+
+        w!(out, "");
+        w!(out, "pub struct {} {{", name);
+        w!(out, "    inner: fb::Table,");
+        w!(out, "}}");
+        w!(out, "");
+        w!(out, "impl {} {{", name);
+
+        // Each field's accessor return type, builder argument type, and the builder's body.
+        let mut builders = vec![];
+
+        for (i, field) in decl.fields.iter().enumerate() {
+            try!(self.check_field(decl, field));
+
+            let slot = 4 + 2 * i;
+
+            if i > 0 {
+                w!(out, "");
+            }
+
+            match field.ty {
+                Type::Scalar(s) => {
+                    let def = s.literal(field.default.as_ref().map(|d| &**d).unwrap_or("0"))
+                        .unwrap();
+
+                    w!(out, "    pub fn {}(&self) -> {} {{", field.name, s.rust());
+
+                    if s == Scalar::Bool {
+                        w!(out, "        let x: u8 = self.inner.get_field({}, {});", slot, def);
+                        w!(out, "        x != 0");
+
+                        builders.push((field, "bool".to_string(), format!(
+                            "self.fbb.add_scalar({}, if {} {{ 1u8 }} else {{ 0u8 }}, {})",
+                            slot, field.name, def)));
+                    } else {
+                        w!(out, "        self.inner.get_field({}, {})", slot, def);
+
+                        builders.push((field, s.rust().to_string(), format!(
+                            "self.fbb.add_scalar({}, {}, {})", slot, field.name, def)));
+                    }
+
+                    w!(out, "    }}");
+                },
+
+                Type::String => {
+                    w!(out, "    pub fn {}(&self) -> Option<&fb::String> {{", field.name);
+                    w!(out, "        self.inner.get_ref({})", slot);
+                    w!(out, "    }}");
+
+                    builders.push((field, "fb::Offset<fb::String>".to_string(),
+                                   format!("self.fbb.add_offset({}, {})", slot, field.name)));
+                },
+
+                Type::Vector(ref elem) => {
+                    let ty = format!("fb::Vector<{}>", try!(self.vector_elem(decl, field, elem)));
+
+                    w!(out, "    pub fn {}(&self) -> Option<&{}> {{", field.name, ty);
+                    w!(out, "        self.inner.get_ref({})", slot);
+                    w!(out, "    }}");
+
+                    builders.push((field, format!("fb::Offset<{}>", ty),
+                                   format!("self.fbb.add_offset({}, {})", slot, field.name)));
+                },
+
+                Type::Named(ref tname) => {
+                    let inner = try!(self.lookup(tname, ns, field.line));
+                    let path  = self.path(inner, ns);
+
+                    w!(out, "    pub fn {}(&self) -> Option<&{}> {{", field.name, path);
+
+                    match inner.kind {
+                        Kind::Struct => {
+                            w!(out, "        self.inner.get_struct({})", slot);
+
+                            builders.push((field, format!("&{}", path),
+                                format!("self.fbb.add_struct({}, {})", slot, field.name)));
+                        },
+
+                        Kind::Table => {
+                            w!(out, "        self.inner.get_ref({})", slot);
+
+                            builders.push((field, format!("fb::Offset<{}>", path),
+                                format!("self.fbb.add_offset({}, {})", slot, field.name)));
+                        },
+                    }
+
+                    w!(out, "    }}");
+                },
+            }
+        }
+
+        w!(out, "}}");
+        w!(out, "");
+        w!(out, "pub struct {}Builder<'x> {{", name);
+        w!(out, "    fbb:   &'x mut fb::FlatBufferBuilder,");
+        w!(out, "    start: fb::UOffset,");
+        w!(out, "}}");
+        w!(out, "");
+        w!(out, "impl<'x> {}Builder<'x> {{", name);
+        w!(out, "    pub fn new(fbb: &'x mut fb::FlatBufferBuilder) -> {}Builder<'x> {{", name);
+        w!(out, "        let start = fbb.start_table();");
+        w!(out, "        {}Builder {{", name);
+        w!(out, "            fbb:   fbb,");
+        w!(out, "            start: start,");
+        w!(out, "        }}");
+        w!(out, "    }}");
+
+        for &(field, ref ty, ref body) in builders.iter() {
+            w!(out, "");
+            w!(out, "    pub fn add_{0}(&mut self, {0}: {1}) {{", field.name, ty);
+            w!(out, "        {}", body);
+            w!(out, "    }}");
+        }
+
+        w!(out, "");
+        w!(out, "    pub fn finish(&mut self) -> fb::Offset<{}> {{", name);
+        w!(out, "        fb::Offset::new(self.fbb.end_table(self.start, {}))", decl.fields.len());
+        w!(out, "    }}");
+        w!(out, "}}");
+
+        Ok(())
+    }
+
+    // The path of the function which verifies table `decl`, as seen from namespace `from`.
+    fn verify_path(&self, decl: &Decl, from: &[String]) -> String {
+        let func = snake_case(&decl.name);
+
+        if decl.namespace == from {
+            return func
+        }
+
+        let mut path = String::new();
+
+        if let Some((_, parent)) = decl.namespace.split_last() {
+            for part in parent.iter() {
+                path.push_str("::");
+                path.push_str(part);
+            }
+        }
+
+        format!("{}::verify::{}", path, func)
+    }
+
+    fn verify_module(&self, main: &Schema) -> Result<String, Error> {
+        let mut out = String::new();
+
+        w!(out, "// Generated from `{}` by `isoengine::codegen`. Edit the schema rather than this",
+           main.path.display());
+        w!(out, "// file, which is rewritten on every build.");
+
+        let tables: Vec<&Decl> = main.decls.iter().filter(|d| d.kind == Kind::Table).collect();
+
+        if !tables.is_empty() {
+            w!(out, "");
+            w!(out, "use wire::{{Error, Verifier}};");
+        }
+
+        for decl in tables.iter() {
+            try!(self.verify_code(&mut out, decl)
+                .map_err(|(line, msg)| Error::Schema(main.path.clone(), line, msg)));
+        }
+
+        for decl in tables.iter() {
+            let func = snake_case(&decl.name);
+
+            w!(out, "");
+            w!(out, "/// Check a buffer whose root is {} `{}`. This should be called before \
+                      handing the", article(&decl.name), decl.name);
+            w!(out, "/// buffer to any of the accessors in the `wire` module.");
+            w!(out, "pub fn verify_{}_buffer(buf: &[u8]) -> Result<(), Error> {{", func);
+            w!(out, "    let mut v = Verifier::new(buf);");
+            w!(out, "    let root  = try!(v.root());");
+            w!(out, "");
+            w!(out, "    {}(&mut v, root)", func);
+            w!(out, "}}");
+        }
+
+        if let Some((ref name, line)) = main.root_type {
+            let base = name.rsplit('.').next().unwrap();
+
+            let root = try!(tables.iter().find(|d| d.name == base).ok_or_else(|| {
+                Error::Schema(main.path.clone(), line,
+                              format!("`{}` isn't a table in this schema", name))
+            }));
+
+            w!(out, "");
+            w!(out, "/// Check a buffer whose root is {} `{}`, the schema's root type.",
+               article(&root.name), root.name);
+            w!(out, "pub fn verify_buffer(buf: &[u8]) -> Result<(), Error> {{");
+            w!(out, "    verify_{}_buffer(buf)", snake_case(&root.name));
+            w!(out, "}}");
+        }
+
+        Ok(out)
+    }
+
+    fn verify_code(&self, out: &mut String, decl: &Decl) -> GenResult<()> {
+        let ns = &decl.namespace;
+
+        w!(out, "");
+        w!(out, "/// Check {} `{}` table at `pos`.", article(&decl.name), decl.name);
+        w!(out, "pub fn {}(v: &mut Verifier, pos: usize) -> Result<(), Error> {{",
+           snake_case(&decl.name));
+
+        if decl.fields.is_empty() {
+            w!(out, "    try!(v.table(pos));");
+        } else {
+            w!(out, "    let t = try!(v.table(pos));");
+            w!(out, "");
+        }
+
+        for (i, field) in decl.fields.iter().enumerate() {
+            let slot = 4 + 2 * i;
+            let req  = field.required;
+
+            let check = match field.ty {
+                Type::Scalar(s) => format!("t.inline(v, {}, {}, {})", slot, s.size(), s.size()),
+
+                Type::String => format!("t.string(v, {}, {})", slot, req),
+
+                Type::Vector(ref elem) => match **elem {
+                    Type::Scalar(s) => {
+                        format!("t.vector(v, {}, {}, {}, {})", slot, s.size(), s.size(), req)
+                    },
+
+                    Type::Named(ref name) => {
+                        let inner = try!(self.lookup(name, ns, field.line));
+
+                        match inner.kind {
+                            Kind::Struct => {
+                                let (size, align) = try!(self.layout(inner, 0));
+                                format!("t.vector(v, {}, {}, {}, {})", slot, size, align, req)
+                            },
+
+                            Kind::Table => {
+                                format!("t.tables(v, {}, {}, {})", slot, req,
+                                        self.verify_path(inner, ns))
+                            },
+                        }
+                    },
+
+                    _ => {
+                        return Err((field.line, "vectors of strings aren't supported".to_string()))
+                    },
+                },
+
+                Type::Named(ref name) => {
+                    let inner = try!(self.lookup(name, ns, field.line));
+
+                    match inner.kind {
+                        Kind::Struct => {
+                            let (size, align) = try!(self.layout(inner, 0));
+                            format!("t.inline(v, {}, {}, {})", slot, size, align)
+                        },
+
+                        Kind::Table => {
+                            format!("t.table(v, {}, {}, {})", slot, req,
+                                    self.verify_path(inner, ns))
+                        },
+                    }
+                },
+            };
+
+            w!(out, "    try!({}); // {}", check, field.name);
+        }
+
+        if !decl.fields.is_empty() {
+            w!(out, "");
+        }
+
+        w!(out, "    Ok(())");
+        w!(out, "}}");
+
+        Ok(())
+    }
+}
+
+fn article(name: &str) -> &'static str {
+    match name.chars().next() {
+        Some(c) if "AEIOUaeiou".contains(c) => "an",
+        _                                   => "a",
+    }
+}
+
+// `SpriteSheetDesc` becomes `sprite_sheet_desc`.
+fn snake_case(name: &str) -> String {
+    let mut out = String::new();
+
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+fn round_up(x: usize, align: usize) -> usize {
+    (x + align - 1) / align * align
+}
+
+// Add padding members to take `offset` up to `to`, using the largest integer types which will be
+// aligned where they're placed.
+fn padding(members: &mut Vec<(String, String)>, pads: &mut usize, offset: &mut usize, to: usize) {
+    while *offset < to {
+        let size = [8, 4, 2, 1].iter().cloned()
+            .find(|&n| *offset + n <= to && *offset % n == 0)
+            .unwrap();
+
+        members.push((format!("__padding{}", *pads), format!("u{}", size * 8)));
+
+        *pads   += 1;
+        *offset += size;
+    }
+}
@@ -0,0 +1,308 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Settings are kept in a TOML file with one table per subsystem:
+//!
+//! ```toml
+//! [video]
+//! width = 1280
+//! height = 720
+//! fullscreen = false
+//! vsync = true
+//! srgb = false
+//! instanced = false
+//!
+//! [audio]
+//! volume = 0.8
+//!
+//! [assets]
+//! paths = ["assets"]
+//!
+//! [keybinds]
+//! move_north = "W"
+//! ```
+//!
+//! Any setting which is missing from the file takes its default value. Keys which the engine
+//! doesn't know about are kept, and written back out by `Config::save`, so games can store their
+//! own settings in the same file.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use toml::{self, Value};
+
+/// Engine settings.
+#[derive(Clone,Debug)]
+pub struct Config {
+    /// Horizontal resolution of the window, in pixels.
+    pub width: i32,
+
+    /// Vertical resolution of the window, in pixels.
+    pub height: i32,
+
+    /// Whether the window covers the whole screen.
+    pub fullscreen: bool,
+
+    /// Whether buffer swaps wait for the display's vertical refresh.
+    pub vsync: bool,
+
+    /// Whether to draw with correct sRGB color handling (see `grafix::opengl::srgb`). This is off
+    /// by default, since it changes how blending and tints look, and not every driver supports it.
+    pub srgb: bool,
+
+    /// Whether sprites are drawn as instances of a quad rather than expanded by a geometry shader
+    /// (see `grafix::sprite::RendererKind`). Which is faster depends on the driver.
+    pub instanced: bool,
+
+    /// Master volume, from 0 (silent) to 1 (full volume).
+    pub volume: f32,
+
+    /// Directories searched for assets, in order.
+    pub asset_paths: Vec<String>,
+
+    /// Maps the names of input actions to the names of the keys bound to them (as SDL names
+    /// them, e.g. "Left Shift"). See `input::Keymap::from_config`.
+    pub keybinds: BTreeMap<String, String>,
+
+    // The file as it was loaded, so that unknown keys survive a save.
+    raw: toml::Table,
+}
+
+/// An error encountered while loading or saving a `Config`.
+#[derive(Debug)]
+pub enum Error {
+    /// The file couldn't be read or written.
+    Io(io::Error),
+
+    /// The file isn't valid TOML. Holds the line and column where each problem was found, along
+    /// with a description.
+    Parse(Vec<(usize, usize, String)>),
+
+    /// A setting has the wrong type or an unusable value. Holds the setting's name as
+    /// "table.key".
+    Invalid(String),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl Config {
+    /// The default settings: a 1280x720 window with vsync, no sRGB, sprites expanded by a geometry
+    /// shader and full volume, and assets under "assets".
+    pub fn new() -> Config {
+        Config {
+            width:       1280,
+            height:      720,
+            fullscreen:  false,
+            vsync:       true,
+            srgb:        false,
+            instanced:   false,
+            volume:      1.0,
+            asset_paths: vec![From::from("assets")],
+            keybinds:    BTreeMap::new(),
+            raw:         BTreeMap::new(),
+        }
+    }
+
+    /// Load settings from the TOML file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Io` if the file can't be read. A missing file is not an error; the defaults
+    /// are returned instead, so that a game can call `save` to create it.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, Error> {
+        let mut text = String::new();
+
+        match File::open(path.as_ref()) {
+            Ok(mut f) => { try!(f.read_to_string(&mut text)); },
+
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Config::new()),
+
+            Err(err) => return Err(Error::Io(err)),
+        }
+
+        Config::from_str(&text)
+    }
+
+    /// Parse settings from TOML text.
+    pub fn from_str(text: &str) -> Result<Config, Error> {
+        let mut parser = toml::Parser::new(text);
+
+        let raw = match parser.parse() {
+            Some(raw) => raw,
+            None      => {
+                let errors = parser.errors.iter().map(|e| {
+                    let (line, col) = parser.to_linecol(e.lo);
+                    (line + 1, col + 1, e.desc.clone())
+                }).collect();
+
+                return Err(Error::Parse(errors))
+            },
+        };
+
+        let mut cfg = Config::new();
+
+        if let Some(v) = try!(get_int(&raw, "video", "width"))       { cfg.width = v }
+        if let Some(v) = try!(get_int(&raw, "video", "height"))      { cfg.height = v }
+        if let Some(v) = try!(get_bool(&raw, "video", "fullscreen")) { cfg.fullscreen = v }
+        if let Some(v) = try!(get_bool(&raw, "video", "vsync"))      { cfg.vsync = v }
+        if let Some(v) = try!(get_bool(&raw, "video", "srgb"))       { cfg.srgb = v }
+        if let Some(v) = try!(get_bool(&raw, "video", "instanced"))  { cfg.instanced = v }
+        if let Some(v) = try!(get_float(&raw, "audio", "volume"))    { cfg.volume = v }
+
+        if cfg.width <= 0 {
+            return Err(Error::Invalid(From::from("video.width")))
+        }
+
+        if cfg.height <= 0 {
+            return Err(Error::Invalid(From::from("video.height")))
+        }
+
+        if !(cfg.volume >= 0.0 && cfg.volume <= 1.0) {
+            return Err(Error::Invalid(From::from("audio.volume")))
+        }
+
+        if let Some(v) = get(&raw, "assets", "paths") {
+            cfg.asset_paths = try!(as_strings(v, "assets.paths"));
+        }
+
+        if let Some(binds) = try!(table(&raw, "keybinds")) {
+            for (action, key) in binds.iter() {
+                match *key {
+                    Value::String(ref key) => { cfg.keybinds.insert(action.clone(), key.clone()); },
+                    _ => return Err(Error::Invalid(format!("keybinds.{}", action))),
+                }
+            }
+        }
+
+        cfg.raw = raw;
+
+        Ok(cfg)
+    }
+
+    /// Write the settings to the TOML file at `path`, replacing it.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut f = try!(File::create(path));
+        try!(f.write_all(self.to_string().as_bytes()));
+
+        Ok(())
+    }
+
+    /// Render the settings as TOML text, including any unknown keys from the file they were
+    /// loaded from.
+    pub fn to_string(&self) -> String {
+        let mut raw = self.raw.clone();
+
+        set(&mut raw, "video", "width",      Value::Integer(self.width as i64));
+        set(&mut raw, "video", "height",     Value::Integer(self.height as i64));
+        set(&mut raw, "video", "fullscreen", Value::Boolean(self.fullscreen));
+        set(&mut raw, "video", "vsync",      Value::Boolean(self.vsync));
+        set(&mut raw, "video", "srgb",       Value::Boolean(self.srgb));
+        set(&mut raw, "video", "instanced",  Value::Boolean(self.instanced));
+        set(&mut raw, "audio", "volume",     Value::Float(self.volume as f64));
+
+        let paths = self.asset_paths.iter().map(|p| Value::String(p.clone())).collect();
+        set(&mut raw, "assets", "paths", Value::Array(paths));
+
+        let binds = self.keybinds.iter().map(|(k, v)| (k.clone(), Value::String(v.clone())));
+        raw.insert(From::from("keybinds"), Value::Table(binds.collect()));
+
+        format!("{}", Value::Table(raw))
+    }
+
+    /// Look up a setting which the engine doesn't know about, e.g. `get("game", "difficulty")`.
+    pub fn get(&self, table: &str, key: &str) -> Option<&Value> {
+        get(&self.raw, table, key)
+    }
+
+    /// Set a setting which the engine doesn't know about. It will be written out by `save`.
+    pub fn set(&mut self, table: &str, key: &str, val: Value) {
+        set(&mut self.raw, table, key, val)
+    }
+}
+
+fn table<'x>(raw: &'x toml::Table, name: &str) -> Result<Option<&'x toml::Table>, Error> {
+    match raw.get(name) {
+        Some(&Value::Table(ref t)) => Ok(Some(t)),
+        Some(_)                    => Err(Error::Invalid(From::from(name))),
+        None                       => Ok(None),
+    }
+}
+
+fn get<'x>(raw: &'x toml::Table, table: &str, key: &str) -> Option<&'x Value> {
+    match raw.get(table) {
+        Some(&Value::Table(ref t)) => t.get(key),
+        _                          => None,
+    }
+}
+
+fn set(raw: &mut toml::Table, table: &str, key: &str, val: Value) {
+    let entry = raw.entry(From::from(table)).or_insert_with(|| Value::Table(BTreeMap::new()));
+
+    // A non-table value in the way gets replaced, since the file can't hold both.
+    let is_table = match *entry { Value::Table(_) => true, _ => false };
+
+    if !is_table {
+        *entry = Value::Table(BTreeMap::new());
+    }
+
+    if let Value::Table(ref mut t) = *entry {
+        t.insert(From::from(key), val);
+    }
+}
+
+fn get_int(raw: &toml::Table, table: &str, key: &str) -> Result<Option<i32>, Error> {
+    match get(raw, table, key) {
+        Some(&Value::Integer(v)) if v >= 0 && v <= i32::max_value() as i64 => Ok(Some(v as i32)),
+        Some(_) => Err(Error::Invalid(format!("{}.{}", table, key))),
+        None    => Ok(None),
+    }
+}
+
+fn get_bool(raw: &toml::Table, table: &str, key: &str) -> Result<Option<bool>, Error> {
+    match get(raw, table, key) {
+        Some(&Value::Boolean(v)) => Ok(Some(v)),
+        Some(_)                  => Err(Error::Invalid(format!("{}.{}", table, key))),
+        None                     => Ok(None),
+    }
+}
+
+fn get_float(raw: &toml::Table, table: &str, key: &str) -> Result<Option<f32>, Error> {
+    match get(raw, table, key) {
+        Some(&Value::Float(v))   => Ok(Some(v as f32)),
+        Some(&Value::Integer(v)) => Ok(Some(v as f32)),
+        Some(_)                  => Err(Error::Invalid(format!("{}.{}", table, key))),
+        None                     => Ok(None),
+    }
+}
+
+fn as_strings(v: &Value, name: &str) -> Result<Vec<String>, Error> {
+    let arr = match *v {
+        Value::Array(ref arr) => arr,
+        _                     => return Err(Error::Invalid(From::from(name))),
+    };
+
+    arr.iter().map(|v| match *v {
+        Value::String(ref s) => Ok(s.clone()),
+        _                    => Err(Error::Invalid(From::from(name))),
+    }).collect()
+}
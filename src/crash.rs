@@ -0,0 +1,146 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! When a game crashes in the field, the panic message alone rarely says enough to reproduce it.
+//! The engine records a little context as it runs (the frame number, which system is running, and
+//! so on), and the hook installed by `install` writes that context to a file when a panic occurs,
+//! before unwinding begins.
+
+use std::cell::Cell;
+use std::fs::File;
+use std::io::Write;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT};
+
+static FRAME:      AtomicUsize = ATOMIC_USIZE_INIT;
+static ASSETS:     AtomicUsize = ATOMIC_USIZE_INIT;
+static ENTITIES:   AtomicUsize = ATOMIC_USIZE_INIT;
+static GL_ERROR:   AtomicUsize = ATOMIC_USIZE_INIT;
+static INSTALLED:  AtomicBool  = ATOMIC_BOOL_INIT;
+static DISABLED:   AtomicBool  = ATOMIC_BOOL_INIT;
+
+thread_local!(static SYSTEM: Cell<Option<&'static str>> = Cell::new(None));
+
+/// Record the number of the frame being processed.
+pub fn set_frame(frame: u64) {
+    FRAME.store(frame as usize, Ordering::Relaxed);
+}
+
+/// Record the number of assets which are loaded.
+pub fn set_asset_count(count: usize) {
+    ASSETS.store(count, Ordering::Relaxed);
+}
+
+/// Record the number of live entities.
+pub fn set_entity_count(count: usize) {
+    ENTITIES.store(count, Ordering::Relaxed);
+}
+
+/// Record an error code returned by `glGetError`.
+pub fn set_gl_error(code: u32) {
+    GL_ERROR.store(code as usize, Ordering::Relaxed);
+}
+
+/// Marks a system as running on the current thread until it is dropped. See `enter_system`.
+pub struct SystemGuard {
+    prev: Option<&'static str>,
+}
+
+impl Drop for SystemGuard {
+    fn drop(&mut self) {
+        let prev = self.prev;
+        SYSTEM.with(|s| s.set(prev));
+    }
+}
+
+/// Record that the system called `name` is running on the current thread, until the returned
+/// guard is dropped.
+pub fn enter_system(name: &'static str) -> SystemGuard {
+    let prev = SYSTEM.with(|s| {
+        let prev = s.get();
+        s.set(Some(name));
+        prev
+    });
+
+    SystemGuard { prev: prev }
+}
+
+/// Enable or disable writing crash dumps from the installed hook. Dumps are enabled by default.
+pub fn set_enabled(enabled: bool) {
+    DISABLED.store(!enabled, Ordering::Relaxed);
+}
+
+/// Describe the state of the engine, as it would appear in a crash dump. `gl_error` is used to
+/// check for an OpenGL error which hasn't been recorded yet.
+pub fn report(gl_error: Option<fn() -> u32>) -> String {
+    let mut gl = GL_ERROR.load(Ordering::Relaxed) as u32;
+
+    if let Some(get_error) = gl_error {
+        match get_error() {
+            0    => {},
+            code => gl = code,
+        }
+    }
+
+    format!("frame:          {}\n\
+             system:         {}\n\
+             last GL error:  {:#x}\n\
+             loaded assets:  {}\n\
+             entities:       {}\n",
+            FRAME.load(Ordering::Relaxed),
+            SYSTEM.with(|s| s.get()).unwrap_or("none"),
+            gl,
+            ASSETS.load(Ordering::Relaxed),
+            ENTITIES.load(Ordering::Relaxed))
+}
+
+/// Install a panic hook which writes a crash dump to `path` and then runs the previously installed
+/// hook (which normally prints the panic message). `gl_error` is called by the hook to fetch the
+/// current OpenGL error, if there is a GL context. Only the first call has any effect.
+pub fn install<P: AsRef<Path>>(path: P, gl_error: Option<fn() -> u32>) {
+    if INSTALLED.swap(true, Ordering::SeqCst) {
+        return
+    }
+
+    let path: PathBuf = path.as_ref().to_path_buf();
+    let prev = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        if !DISABLED.load(Ordering::Relaxed) {
+            let location = info.location()
+                .map(|l| format!("{}:{}", l.file(), l.line()))
+                .unwrap_or(String::from("unknown"));
+
+            let message = match info.payload().downcast_ref::<&'static str>() {
+                Some(s) => String::from(*s),
+                None    => match info.payload().downcast_ref::<String>() {
+                    Some(s) => s.clone(),
+                    None    => String::from("<non-string payload>"),
+                },
+            };
+
+            // There's nobody left to report failures to, so they're ignored.
+            if let Ok(mut file) = File::create(&path) {
+                let _ = write!(file, "panic:          {}\nlocation:       {}\n{}",
+                               message, location, report(gl_error));
+            }
+        }
+
+        prev(info);
+    }));
+}
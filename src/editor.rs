@@ -0,0 +1,273 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! A level editor drives the world through an `Editor` rather than poking at the `Manager`
+//! directly, so that every change it makes can be undone. The usual setup is to pause the
+//! `Manager` (see `Manager::set_paused`) while editing, which stops the simulation but keeps the
+//! world rendering.
+//!
+//! Each edit is stored as a diff: the components of the affected entity before and after the
+//! change. Undoing an edit puts the old components back, under the same entity ID.
+
+use num::Float;
+
+use entity::client::{Components, EntityID, Manager};
+use grafix::camera::Camera;
+use math;
+use units::*;
+
+/// The default number of edits which can be undone.
+pub const DEFAULT_HISTORY: usize = 256;
+
+/// A change to the world.
+#[derive(Clone)]
+pub enum Command {
+    /// Create an entity with the given components.
+    Spawn(Components),
+
+    /// Move an entity's `WorldLocation` so that its bounds are centered on a point.
+    Move(EntityID, math::Vec3<Meters>),
+
+    /// Remove an entity.
+    Delete(EntityID),
+
+    /// Replace all of an entity's components.
+    Set(EntityID, Components),
+}
+
+// One undoable change to a single entity.
+struct Edit {
+    id:     EntityID,
+    before: Components,
+    after:  Components,
+}
+
+// An entity being dragged with the mouse.
+struct Drag {
+    id:     EntityID,
+    before: Components,
+
+    // The height of the plane the entity is dragged across.
+    z: Meters,
+
+    // From the point under the cursor to the entity's center.
+    offset: math::Vec3<Meters>,
+}
+
+/// Applies editing commands to a `Manager`, and keeps the history needed to undo them.
+pub struct Editor {
+    undo:  Vec<Edit>,
+    redo:  Vec<Edit>,
+    limit: usize,
+    drag:  Option<Drag>,
+}
+
+impl Editor {
+    /// Create an editor with an empty history, which remembers up to `DEFAULT_HISTORY` edits.
+    pub fn new() -> Editor {
+        Editor {
+            undo:  vec![],
+            redo:  vec![],
+            limit: DEFAULT_HISTORY,
+            drag:  None,
+        }
+    }
+
+    /// Change the number of edits which can be undone, forgetting the oldest if there are already
+    /// more than `limit`.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.trim();
+    }
+
+    /// Apply `cmd` to `mgr` and record it, so that it can be undone. Returns the ID of the
+    /// affected entity, or `None` if the command had nothing to act on (e.g. moving an entity
+    /// which has no location).
+    pub fn apply(&mut self, mgr: &mut Manager, cmd: Command) -> Option<EntityID> {
+        let (id, after) = match cmd {
+            Command::Spawn(comps) => {
+                let id = mgr.create_entity(comps.clone());
+                self.record(id, Components::none(), comps);
+                return Some(id)
+            },
+
+            Command::Move(id, to) => {
+                let mut comps = mgr.components(id);
+
+                match comps.world_location {
+                    Some(ref mut loc) => loc.bounds.center = to,
+                    None              => return None,
+                }
+
+                (id, comps)
+            },
+
+            Command::Delete(id) => (id, Components::none()),
+
+            Command::Set(id, comps) => (id, comps),
+        };
+
+        let before = mgr.components(id);
+
+        if before.is_empty() && after.is_empty() {
+            return None
+        }
+
+        mgr.set_components(id, after.clone());
+        self.record(id, before, after);
+
+        Some(id)
+    }
+
+    /// Revert the most recent edit. Returns false if there was nothing to undo.
+    pub fn undo(&mut self, mgr: &mut Manager) -> bool {
+        self.cancel_drag(mgr);
+
+        match self.undo.pop() {
+            Some(edit) => {
+                mgr.set_components(edit.id, edit.before.clone());
+                self.redo.push(edit);
+                true
+            },
+
+            None => false,
+        }
+    }
+
+    /// Re-apply the most recently undone edit. Returns false if there was nothing to redo.
+    pub fn redo(&mut self, mgr: &mut Manager) -> bool {
+        self.cancel_drag(mgr);
+
+        match self.redo.pop() {
+            Some(edit) => {
+                mgr.set_components(edit.id, edit.after.clone());
+                self.undo.push(edit);
+                true
+            },
+
+            None => false,
+        }
+    }
+
+    /// True if there is an edit which `undo` would revert.
+    pub fn can_undo(&self) -> bool { !self.undo.is_empty() }
+
+    /// True if there is an edit which `redo` would re-apply.
+    pub fn can_redo(&self) -> bool { !self.redo.is_empty() }
+
+    /// Forget all edits, e.g. after loading a new level.
+    pub fn clear_history(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    /// Find the entity drawn at screen coordinate `scr` (measured from the center of the screen),
+    /// taking the one nearest the camera if several overlap. An entity covers the square on screen
+    /// which its bounding cube's half-edge spans around its center.
+    pub fn pick(&self, mgr: &mut Manager, cam: &Camera, scr: math::Vec2<Pixels>)
+        -> Option<EntityID> {
+
+        let mut best: Option<(EntityID, Meters)> = None;
+
+        for id in mgr.entities() {
+            let bounds = match mgr.view_entity(id).world_location {
+                Some(ref loc) => loc.bounds,
+                None          => continue,
+            };
+
+            let (center, depth) = cam.camera_to_screen(cam.game_to_camera(bounds.center));
+            let reach           = Pixels(bounds.half_edge.0 * cam.scale);
+
+            let hit = (scr.x - center.x).abs() <= reach && (scr.y - center.y).abs() <= reach;
+
+            if hit && best.map_or(true, |(_, d)| depth < d) {
+                best = Some((id, depth));
+            }
+        }
+
+        best.map(|(id, _)| id)
+    }
+
+    /// Start dragging the entity at `scr`, if there is one. It moves across the horizontal plane
+    /// at its current height. Returns the ID of the entity picked up.
+    pub fn begin_drag(&mut self, mgr: &mut Manager, cam: &Camera, scr: math::Vec2<Pixels>)
+        -> Option<EntityID> {
+
+        self.cancel_drag(mgr);
+
+        let id     = match self.pick(mgr, cam, scr) { Some(id) => id, None => return None };
+        let before = mgr.components(id);
+
+        let center = match before.world_location {
+            Some(ref loc) => loc.bounds.center,
+            None          => return None,
+        };
+
+        self.drag = Some(Drag {
+            id:     id,
+            before: before,
+            z:      center.z,
+            offset: center - cam.screen_to_game(scr, center.z),
+        });
+
+        Some(id)
+    }
+
+    /// Move the entity being dragged so that it stays under the cursor at `scr`.
+    pub fn drag_to(&mut self, mgr: &mut Manager, cam: &Camera, scr: math::Vec2<Pixels>) {
+        if let Some(ref drag) = self.drag {
+            let to = cam.screen_to_game(scr, drag.z) + drag.offset;
+
+            if let Some(mut loc) = mgr.view_entity(drag.id).world_location {
+                loc.bounds.center = to;
+            }
+        }
+    }
+
+    /// Drop the entity being dragged where it is, recording the move as a single edit.
+    pub fn end_drag(&mut self, mgr: &mut Manager) {
+        if let Some(drag) = self.drag.take() {
+            let after = mgr.components(drag.id);
+            self.record(drag.id, drag.before, after);
+        }
+    }
+
+    /// Put the entity being dragged back where it started, without recording an edit.
+    pub fn cancel_drag(&mut self, mgr: &mut Manager) {
+        if let Some(drag) = self.drag.take() {
+            mgr.set_components(drag.id, drag.before);
+        }
+    }
+
+    /// The entity being dragged, if any.
+    pub fn dragging(&self) -> Option<EntityID> {
+        self.drag.as_ref().map(|d| d.id)
+    }
+
+    fn record(&mut self, id: EntityID, before: Components, after: Components) {
+        self.undo.push(Edit { id: id, before: before, after: after });
+        self.redo.clear();
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        if self.undo.len() > self.limit {
+            let extra = self.undo.len() - self.limit;
+            self.undo = self.undo.split_off(extra);
+        }
+    }
+}
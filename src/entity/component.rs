@@ -15,9 +15,12 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+use std::convert::From;
+
 use entity::wire;
 use grafix::anim;
 use math;
+use path::flow::FlowGoal;
 use units::*;
 
 /// Provides an entity with a location on the world map.
@@ -29,17 +32,27 @@ pub struct WorldLocation {
 
 impl WorldLocation {
     /// Convert from FlatBuffer representation.
-    pub fn from_wire(w: &wire::WorldLocation) -> WorldLocation {
-        WorldLocation {
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidBounds` if any coordinate is not finite, or if the half-edge of the
+    /// bounding cube is not positive.
+    pub fn from_wire(w: &wire::WorldLocation) -> Result<WorldLocation, Error> {
+        let b = w.bounds();
+
+        let finite = [b.center_x(), b.center_y(), b.center_z(), b.half_edge()].iter()
+            .all(|x| x.is_finite());
+
+        if !finite || b.half_edge() <= 0.0 {
+            return Err(Error::InvalidBounds)
+        }
+
+        Ok(WorldLocation {
             bounds: math::BoundingCube {
-                center: vec3!(Meters ;
-                    w.bounds().center_x(),
-                    w.bounds().center_y(),
-                    w.bounds().center_z(),
-                ),
-                half_edge: Meters(w.bounds().half_edge()),
+                center: vec3!(Meters ; b.center_x(), b.center_y(), b.center_z()),
+                half_edge: Meters(b.half_edge()),
             }
-        }
+        })
     }
 
     /// Convert to FlatBuffer representation.
@@ -65,8 +78,8 @@ pub struct WorldRender {
 
 impl WorldRender {
     /// Convert from FlatBuffer representation.
-    pub fn from_wire(w: &wire::WorldRender) -> WorldRender {
-        WorldRender { anim: anim::Instance::from_wire(w.anim()) }
+    pub fn from_wire(w: &wire::WorldRender) -> Result<WorldRender, Error> {
+        Ok(WorldRender { anim: try!(anim::Instance::from_wire(w.anim())) })
     }
 
     /// Convert to FlatBuffer representation.
@@ -74,3 +87,174 @@ impl WorldRender {
         wire::WorldRender::new(&self.anim.to_wire())
     }
 }
+
+/// Makes an entity walk towards a goal region, following the shortest route over the tile grid
+/// (see `path::flow::FlowSystem`).
+#[derive(Clone)]
+pub struct Steering {
+    /// The cells to walk to.
+    pub goal: FlowGoal,
+
+    /// Walking speed, in meters per second.
+    pub speed: Meters,
+}
+
+/// The shape of the light given off by an entity with a `Light`.
+#[derive(Clone,Copy,Debug)]
+pub enum LightKind {
+    /// Light radiating from the center of the entity's `WorldLocation`, fading out to nothing at
+    /// `radius`. Point lights on entities without a location are ignored.
+    Point {
+        /// The distance at which the light stops having any effect.
+        radius: Meters,
+    },
+
+    /// Light falling everywhere from the same direction, like sunlight. The entity's location
+    /// doesn't matter.
+    Directional {
+        /// The direction the light travels in, in game space. It needn't be normalized.
+        direction: math::Vec3<Meters>,
+
+        /// Whether sprites cast shadows from this light. Only one light's shadows are drawn.
+        shadows: bool,
+    },
+}
+
+/// Makes an entity light up the sprites around it (see `grafix::system::LightGather`).
+#[derive(Clone,Copy,Debug)]
+pub struct Light {
+    /// The shape of the light.
+    pub kind: LightKind,
+
+    /// The light's red, green and blue, each in `[0, 1]`.
+    pub color: [f32; 3],
+
+    /// How bright the light is. The color is multiplied by this, so it may be greater than 1.
+    pub intensity: f32,
+}
+
+/// An error encountered when converting a component from its FlatBuffer representation.
+#[derive(Debug,PartialEq)]
+pub enum Error {
+    /// A bounding cube had a non-finite coordinate or a non-positive half-edge.
+    InvalidBounds,
+
+    /// An animation instance was invalid.
+    Anim(anim::Error),
+}
+
+impl From<anim::Error> for Error {
+    fn from(err: anim::Error) -> Error {
+        Error::Anim(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flatbuffers as fb;
+
+    use entity::component::{Error, WorldLocation, WorldRender};
+    use entity::{verify, wire};
+    use grafix::anim;
+    use math;
+    use time;
+    use units::*;
+
+    #[test]
+    fn world_location_round_trip() {
+        let loc = WorldLocation {
+            bounds: math::BoundingCube {
+                center:    vec3!(Meters ; 1.0, -2.0, 3.5),
+                half_edge: Meters(0.5),
+            },
+        };
+
+        let back = WorldLocation::from_wire(&loc.to_wire()).unwrap();
+
+        assert_eq!(back.bounds.center.x.0, 1.0);
+        assert_eq!(back.bounds.center.y.0, -2.0);
+        assert_eq!(back.bounds.center.z.0, 3.5);
+        assert_eq!(back.bounds.half_edge.0, 0.5);
+    }
+
+    #[test]
+    fn world_location_invalid_bounds() {
+        use std::f32;
+
+        let bad = [
+            (0.0,      0.0, 0.0, 0.0),
+            (0.0,      0.0, 0.0, -1.0),
+            (f32::NAN, 0.0, 0.0, 1.0),
+            (0.0,      f32::INFINITY, 0.0, 1.0),
+            (0.0,      0.0, 0.0, f32::NAN),
+        ];
+
+        for &(x, y, z, half_edge) in bad.iter() {
+            let w = wire::WorldLocation::new(&wire::BoundingCube::new(x, y, z, half_edge));
+
+            assert_eq!(WorldLocation::from_wire(&w).err(), Some(Error::InvalidBounds));
+        }
+    }
+
+    // Write an `Entity` with `ren` into a buffer, verify it, and read `ren` back.
+    fn through_buffer(ren: &WorldRender) -> Result<WorldRender, Error> {
+        let mut fbb = fb::FlatBufferBuilder::new();
+
+        let root = {
+            let mut b = wire::EntityBuilder::new(&mut fbb);
+            b.add_id(7);
+            b.add_world_ren(&ren.to_wire());
+            b.finish()
+        };
+
+        fbb.finish(root);
+
+        let buf = fbb.into_vec();
+
+        verify::verify_buffer(&buf).unwrap();
+
+        let w = fb::get_root::<wire::Entity>(&buf);
+
+        assert_eq!(w.id(), 7);
+
+        WorldRender::from_wire(w.world_ren().unwrap())
+    }
+
+    #[test]
+    fn world_render_round_trip() {
+        let cases = [(false, false), (true, false), (false, true), (true, true)];
+
+        for &(repeat, flip_x) in cases.iter() {
+            let ren = WorldRender {
+                anim: anim::Instance {
+                    anim_id:  12,
+                    t_start:  time::Duration::usec(5_000),
+                    duration: time::Duration::usec(400_000),
+                    repeat:   repeat,
+                    flip_x:   flip_x,
+                },
+            };
+
+            let back = through_buffer(&ren).unwrap();
+
+            assert!(back.anim == ren.anim);
+            assert_eq!(back.anim.repeat, repeat);
+            assert_eq!(back.anim.flip_x, flip_x);
+        }
+    }
+
+    #[test]
+    fn world_render_zero_duration() {
+        let ren = WorldRender {
+            anim: anim::Instance {
+                anim_id:  12,
+                t_start:  time::Duration::usec(5_000),
+                duration: time::Duration::usec(0),
+                repeat:   true,
+                flip_x:   false,
+            },
+        };
+
+        assert_eq!(through_buffer(&ren).err(), Some(Error::Anim(anim::Error::ZeroDuration)));
+    }
+}
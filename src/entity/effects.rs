@@ -0,0 +1,387 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Timed modifiers on entities, such as poison, haste or a shield: status effects.
+//!
+//! An `Effects` holds every entity's active effects, each of which is a number of stacks with a
+//! value and an expiry time. Gameplay code applies effects and asks for their total value (e.g.
+//! the sum of every haste stack, to scale a unit's speed). How repeat applications combine is
+//! chosen per kind of effect, with `Stacking`. An `EffectSystem` expires effects as the clock
+//! passes them and forgets the effects of removed entities.
+//!
+//! Every change is queued as an `Event`, so that the game can react to effects starting and ending
+//! (starting a particle effect, say) without polling each entity.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::mem;
+use std::rc::Rc;
+
+use entity::{EngineSystem, EngineView};
+use entity::sleep::Sleep;
+use time::Duration;
+
+/// The name of a kind of effect, such as `"poison"`.
+pub type Kind = &'static str;
+
+/// What happens when an effect is applied to an entity which already has it.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Stacking {
+    /// The new application replaces the old one, restarting its timer. This is the default.
+    Refresh,
+
+    /// Each application adds a stack, which expires on its own timer, up to the given number of
+    /// stacks. Beyond that, the stack which would expire first is replaced.
+    Stack(u32),
+
+    /// The new application is ignored until the old one ends.
+    Ignore,
+}
+
+/// How an entity's effect changed.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Change {
+    /// The effect was applied, adding a stack or starting the effect.
+    Applied,
+
+    /// The effect was applied again, replacing a stack.
+    Refreshed,
+
+    /// A stack ran out of time.
+    Expired,
+
+    /// Every stack was removed with `Effects::remove`.
+    Removed,
+}
+
+/// A change to one of an entity's effects.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct Event {
+    /// The entity.
+    pub id: u64,
+
+    /// The kind of effect.
+    pub kind: Kind,
+
+    /// What happened.
+    pub change: Change,
+
+    /// The number of stacks the entity has afterwards.
+    pub stacks: u32,
+}
+
+// One application of an effect. `seq` identifies its timer.
+struct Stack {
+    seq:     u64,
+    value:   f32,
+    expires: Option<Duration>,
+}
+
+/// Every entity's active effects. This is shared between gameplay code and the `EffectSystem`.
+pub struct Effects {
+    stacking: BTreeMap<Kind, Stacking>,
+    active:   BTreeMap<(u64, Kind), Vec<Stack>>,
+
+    // The stacks which expire, by time and then by sequence number, so that stacks which expire
+    // at the same time do so in the order they were applied.
+    timers:   BTreeMap<(Duration, u64), (u64, Kind)>,
+    next_seq: u64,
+
+    events: VecDeque<Event>,
+
+    // Entities whose effects have changed since the `EffectSystem` last looked.
+    touched: BTreeSet<u64>,
+}
+
+impl Effects {
+    /// Create an empty set of effects, in which every kind uses `Stacking::Refresh`.
+    pub fn new() -> Effects {
+        Effects {
+            stacking: BTreeMap::new(),
+            active:   BTreeMap::new(),
+            timers:   BTreeMap::new(),
+            next_seq: 0,
+            events:   VecDeque::new(),
+            touched:  BTreeSet::new(),
+        }
+    }
+
+    /// Choose how applications of `kind` combine. This only affects later applications.
+    pub fn define(&mut self, kind: Kind, stacking: Stacking) {
+        self.stacking.insert(kind, stacking);
+    }
+
+    /// How applications of `kind` combine.
+    pub fn stacking(&self, kind: Kind) -> Stacking {
+        self.stacking.get(kind).cloned().unwrap_or(Stacking::Refresh)
+    }
+
+    /// Apply `kind` to entity `id` at `now`, with `value` (e.g. the fraction by which it slows the
+    /// entity), lasting for `duration` or until it's removed if that's `None`. Returns the number
+    /// of stacks the entity has afterwards.
+    pub fn apply(&mut self, now: Duration, id: u64, kind: Kind, value: f32,
+                 duration: Option<Duration>) -> u32 {
+
+        let stacking = self.stacking(kind);
+        let count    = self.stacks(id, kind);
+
+        let change = match stacking {
+            Stacking::Ignore if count > 0 => return count,
+
+            Stacking::Refresh if count > 0 => {
+                self.clear_stacks(id, kind);
+                Change::Refreshed
+            },
+
+            Stacking::Stack(max) if count >= max => {
+                if max == 0 {
+                    return count
+                }
+
+                self.drop_soonest(id, kind);
+                Change::Refreshed
+            },
+
+            _ => Change::Applied,
+        };
+
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        let expires = duration.map(|d| now + d);
+
+        if let Some(at) = expires {
+            self.timers.insert((at, seq), (id, kind));
+        }
+
+        let stacks = {
+            let list = self.active.entry((id, kind)).or_insert(vec![]);
+            list.push(Stack { seq: seq, value: value, expires: expires });
+            list.len() as u32
+        };
+
+        self.notify(id, kind, change, stacks);
+
+        stacks
+    }
+
+    /// Remove every stack of `kind` from entity `id`. Returns false if it didn't have any.
+    pub fn remove(&mut self, id: u64, kind: Kind) -> bool {
+        if !self.clear_stacks(id, kind) {
+            return false
+        }
+
+        self.notify(id, kind, Change::Removed, 0);
+        true
+    }
+
+    /// The number of stacks of `kind` which entity `id` has.
+    pub fn stacks(&self, id: u64, kind: Kind) -> u32 {
+        self.active.get(&(id, kind)).map_or(0, |list| list.len() as u32)
+    }
+
+    /// True if entity `id` has at least one stack of `kind`.
+    pub fn has(&self, id: u64, kind: Kind) -> bool {
+        self.stacks(id, kind) > 0
+    }
+
+    /// The sum of the values of entity `id`'s stacks of `kind`, or 0 if it has none.
+    pub fn value(&self, id: u64, kind: Kind) -> f32 {
+        self.active.get(&(id, kind)).map_or(0.0, |list| {
+            list.iter().fold(0.0, |total, stack| total + stack.value)
+        })
+    }
+
+    /// How long until entity `id`'s last stack of `kind` expires, as of `now`. Returns `None` if
+    /// it has no stacks, or if any of them last until removed.
+    pub fn remaining(&self, now: Duration, id: u64, kind: Kind) -> Option<Duration> {
+        let list = match self.active.get(&(id, kind)) {
+            Some(list) => list,
+            None       => return None,
+        };
+
+        let mut last = now;
+
+        for stack in list.iter() {
+            match stack.expires {
+                Some(at) => if at > last { last = at },
+                None     => return None,
+            }
+        }
+
+        Some(last - now)
+    }
+
+    /// The kinds of effect which entity `id` has, with the number of stacks of each.
+    pub fn kinds(&self, id: u64) -> Vec<(Kind, u32)> {
+        self.active.iter()
+            .filter(|&(&(owner, _), _)| owner == id)
+            .map(|(&(_, kind), list)| (kind, list.len() as u32))
+            .collect()
+    }
+
+    /// The entities which have at least one effect.
+    pub fn entities(&self) -> Vec<u64> {
+        let ids: BTreeSet<u64> = self.active.keys().map(|&(id, _)| id).collect();
+        ids.into_iter().collect()
+    }
+
+    /// Drop every effect of entity `id`, without any events, e.g. because it's been removed.
+    pub fn forget(&mut self, id: u64) {
+        let kinds: Vec<Kind> = self.kinds(id).into_iter().map(|(kind, _)| kind).collect();
+
+        for kind in kinds {
+            self.clear_stacks(id, kind);
+        }
+
+        self.touched.remove(&id);
+    }
+
+    /// Expire every stack whose time is up by `now`. This is called by the `EffectSystem`.
+    pub fn expire(&mut self, now: Duration) {
+        loop {
+            let key = match self.timers.keys().next() {
+                Some(&key) if key.0 <= now => key,
+                _                          => break,
+            };
+
+            let (id, kind) = self.timers.remove(&key).unwrap();
+
+            let stacks = match self.active.get_mut(&(id, kind)) {
+                Some(list) => {
+                    list.retain(|stack| stack.seq != key.1);
+                    list.len() as u32
+                },
+                None => continue,
+            };
+
+            if stacks == 0 {
+                self.active.remove(&(id, kind));
+            }
+
+            self.notify(id, kind, Change::Expired, stacks);
+        }
+    }
+
+    /// Take the next change off of the queue.
+    pub fn poll(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    // Remove every stack of `kind` from `id`, and their timers. Returns false if there were none.
+    fn clear_stacks(&mut self, id: u64, kind: Kind) -> bool {
+        let list = match self.active.remove(&(id, kind)) {
+            Some(list) => list,
+            None       => return false,
+        };
+
+        for stack in list.iter() {
+            if let Some(at) = stack.expires {
+                self.timers.remove(&(at, stack.seq));
+            }
+        }
+
+        true
+    }
+
+    // Remove the stack of `kind` on `id` which would expire first.
+    fn drop_soonest(&mut self, id: u64, kind: Kind) {
+        let timers = &mut self.timers;
+
+        if let Some(list) = self.active.get_mut(&(id, kind)) {
+            // Stacks which never expire sort last.
+            let key = |stack: &Stack| (stack.expires.is_none(), stack.expires, stack.seq);
+
+            let soonest = list.iter().enumerate().fold(None, |best: Option<usize>, (i, stack)| {
+                match best {
+                    Some(b) if key(&list[b]) <= key(stack) => Some(b),
+                    _                                       => Some(i),
+                }
+            });
+
+            if let Some(i) = soonest {
+                let stack = list.remove(i);
+
+                if let Some(at) = stack.expires {
+                    timers.remove(&(at, stack.seq));
+                }
+            }
+        }
+    }
+
+    fn notify(&mut self, id: u64, kind: Kind, change: Change, stacks: u32) {
+        self.events.push_back(Event { id: id, kind: kind, change: change, stacks: stacks });
+        self.touched.insert(id);
+    }
+}
+
+/// A `System` which expires effects as time passes, forgets the effects of entities which have
+/// been removed, and optionally wakes entities whose effects change (see `entity::sleep`).
+///
+/// It should run before the systems which read effects, so that they don't see stacks which have
+/// expired.
+pub struct EffectSystem {
+    effects: Rc<RefCell<Effects>>,
+    sleep:   Option<Rc<RefCell<Sleep>>>,
+
+    // The entities processed since the last update.
+    seen: BTreeSet<u64>,
+}
+
+impl EffectSystem {
+    /// Create a system which runs `effects`, waking entities in `sleep` when their effects change.
+    pub fn new(effects: Rc<RefCell<Effects>>, sleep: Option<Rc<RefCell<Sleep>>>) -> EffectSystem {
+        EffectSystem { effects: effects, sleep: sleep, seen: BTreeSet::new() }
+    }
+}
+
+impl EngineSystem for EffectSystem {
+    /// Forget entities which weren't processed last step, then expire effects.
+    fn update(&mut self, now: Duration) {
+        let seen   = mem::replace(&mut self.seen, BTreeSet::new());
+        let mut fx = self.effects.borrow_mut();
+
+        let mut touched = mem::replace(&mut fx.touched, BTreeSet::new());
+
+        // Effects applied since the last step may be for entities which haven't been processed
+        // yet, so those are kept.
+        for id in fx.entities() {
+            if !seen.contains(&id) && !touched.contains(&id) {
+                fx.forget(id);
+            }
+        }
+
+        fx.expire(now);
+
+        touched.extend(mem::replace(&mut fx.touched, BTreeSet::new()));
+
+        if let Some(ref sleep) = self.sleep {
+            let mut sleep = sleep.borrow_mut();
+
+            for &id in touched.iter() {
+                sleep.wake(id);
+            }
+        }
+    }
+
+    /// Note that the entity still exists.
+    fn process_entity<V: EngineView>(&mut self, _now: Duration, entity: &mut V) {
+        self.seen.insert(entity.id());
+    }
+
+    fn name(&self) -> &'static str { "effects" }
+}
@@ -0,0 +1,281 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Simulation level of detail. In a large world most entities are far from every player, and
+//! nobody notices if they're simulated less often. Each entity is put in a tier, and the `Manager`
+//! only passes it to throttled systems (see `System::throttled`) once every few steps, as set for
+//! that tier. Systems which aren't throttled, such as rendering, still see every entity every step.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use entity::{EngineSystem, EngineView};
+use math::{self, BoundingCube, BoundingSphere};
+use scene::{EntryID, LooseOctree};
+use time;
+use units::*;
+
+/// A simulation LOD tier. Tier 0 is simulated every step; higher tiers are further away.
+pub type Tier = u8;
+
+// The initial bounds of `LodSystem`'s spatial index. It grows as needed.
+const INDEX_HALF_EDGE: f32 = 64.0;
+const INDEX_MIN:       f32 = 1.0;
+const INDEX_LOOSE:     f32 = 2.0;
+
+/// Which tier each entity is in, and how often each tier is simulated. This is shared between the
+/// `Manager`, which skips entities that aren't due, and whatever assigns the tiers (usually a
+/// `LodSystem`).
+pub struct LodTiers {
+    intervals: Vec<u32>,
+
+    // Entities not in tier 0.
+    tiers: BTreeMap<u64, Tier>,
+
+    // The step at which each throttled entity was last simulated.
+    last: BTreeMap<u64, u64>,
+}
+
+impl LodTiers {
+    /// Create a set of tiers where entities in tier `i` are simulated every `intervals[i]` steps.
+    /// Every entity starts in tier 0.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no tiers, or an interval is zero.
+    pub fn new(intervals: Vec<u32>) -> LodTiers {
+        assert!(!intervals.is_empty(), "no LOD tiers");
+        assert!(intervals.iter().all(|&n| n > 0), "LOD interval of zero steps");
+
+        LodTiers {
+            intervals: intervals,
+            tiers:     BTreeMap::new(),
+            last:      BTreeMap::new(),
+        }
+    }
+
+    /// The number of tiers.
+    pub fn tier_count(&self) -> usize {
+        self.intervals.len()
+    }
+
+    /// How many steps pass between simulating entities in `tier`. Tiers past the last behave like
+    /// the last.
+    pub fn interval(&self, tier: Tier) -> u32 {
+        let i = (tier as usize).min(self.intervals.len() - 1);
+        self.intervals[i]
+    }
+
+    /// The tier entity `id` is in.
+    pub fn tier(&self, id: u64) -> Tier {
+        self.tiers.get(&id).cloned().unwrap_or(0)
+    }
+
+    /// Move entity `id` to `tier`.
+    pub fn set_tier(&mut self, id: u64, tier: Tier) {
+        if tier == 0 {
+            self.tiers.remove(&id);
+        } else {
+            self.tiers.insert(id, tier);
+        }
+    }
+
+    /// Forget entity `id`, e.g. because it's been removed.
+    pub fn forget(&mut self, id: u64) {
+        self.tiers.remove(&id);
+        self.last.remove(&id);
+    }
+
+    /// Put every entity back in tier 0.
+    pub fn clear(&mut self) {
+        self.tiers.clear();
+        self.last.clear();
+    }
+
+    /// Decide whether entity `id` is simulated at `step`. If so, returns the number of steps since
+    /// it last was, which throttled systems should scale their work by. Entities in the same tier
+    /// are spread over the interval, so they don't all land on the same step.
+    pub fn due(&mut self, id: u64, step: u64) -> Option<u32> {
+        let n    = self.interval(self.tier(id)) as u64;
+        let last = self.last.get(&id).cloned();
+
+        if n <= 1 {
+            self.last.remove(&id);
+            return Some(last.map_or(1, |l| (step - l) as u32))
+        }
+
+        // An entity which has just left tier 0 was simulated last step.
+        let last = last.unwrap_or(step.saturating_sub(1));
+
+        if (step + id) % n == 0 || step - last >= n {
+            self.last.insert(id, step);
+            Some((step - last) as u32)
+        } else {
+            self.last.insert(id, last);
+            None
+        }
+    }
+}
+
+// Where an entity is, as far as `LodSystem` knows.
+struct Tracked {
+    entry:  EntryID,
+    bounds: BoundingCube,
+
+    // The value of `LodSystem::frame` when this entity was last processed.
+    seen: u64,
+}
+
+/// A `System` which assigns tiers by distance from a set of focus points (players, cameras...),
+/// using a spatial index of every entity with a `WorldLocation`. Entities within `radii[0]` of a
+/// focus point are in tier 0, those within `radii[1]` in tier 1, and so on; the rest are in the
+/// last tier.
+///
+/// Tiers are assigned in `update` from where entities were last step, so this should run before
+/// the throttled systems. Entities without a `WorldLocation` stay in tier 0.
+pub struct LodSystem {
+    tiers: Rc<RefCell<LodTiers>>,
+    radii: Vec<Meters>,
+    focus: Vec<math::Vec3<Meters>>,
+
+    index:   LooseOctree<u64>,
+    tracked: BTreeMap<u64, Tracked>,
+    frame:   u64,
+
+    // Scratch space for querying `index`.
+    found: Vec<EntryID>,
+}
+
+impl LodSystem {
+    /// Create a system which assigns entities to `tiers` by distance.
+    ///
+    /// # Panics
+    ///
+    /// Panics unless there is one fewer radius than tiers, in increasing order.
+    pub fn new(tiers: Rc<RefCell<LodTiers>>, radii: Vec<Meters>) -> LodSystem {
+        assert_eq!(radii.len() + 1, tiers.borrow().tier_count());
+        assert!(radii.windows(2).all(|w| w[0] <= w[1]), "LOD radii out of order");
+
+        let initial = BoundingCube {
+            center:    vec3!(Meters ; 0.0, 0.0, 0.0),
+            half_edge: Meters(INDEX_HALF_EDGE),
+        };
+
+        LodSystem {
+            tiers:   tiers,
+            radii:   radii,
+            focus:   vec![],
+            index:   LooseOctree::new(initial, Meters(INDEX_MIN), INDEX_LOOSE),
+            tracked: BTreeMap::new(),
+            frame:   0,
+            found:   vec![],
+        }
+    }
+
+    /// Set the points which entities are simulated in full detail around. With no focus points,
+    /// every tracked entity is in the last tier.
+    pub fn set_focus(&mut self, focus: Vec<math::Vec3<Meters>>) {
+        self.focus = focus;
+    }
+
+    // Drop entities which weren't processed last step.
+    fn forget_stale(&mut self) {
+        let frame = self.frame;
+
+        let gone: Vec<_> = self.tracked.iter()
+            .filter(|&(_, t)| t.seen + 1 < frame)
+            .map(|(&id, _)| id)
+            .collect();
+
+        let mut tiers = self.tiers.borrow_mut();
+
+        for id in gone {
+            if let Some(t) = self.tracked.remove(&id) {
+                self.index.remove(t.entry);
+            }
+
+            tiers.forget(id);
+        }
+    }
+}
+
+impl EngineSystem for LodSystem {
+    /// Assign every tracked entity to a tier.
+    fn update(&mut self, _now: time::Duration) {
+        self.frame += 1;
+        self.forget_stale();
+
+        let far = self.radii.len() as Tier;
+
+        let mut assigned: BTreeMap<u64, Tier> =
+            self.tracked.keys().map(|&id| (id, far)).collect();
+
+        let mut found = ::std::mem::replace(&mut self.found, vec![]);
+
+        // Nearer tiers are assigned last, so they win.
+        for (tier, &radius) in self.radii.iter().enumerate().rev() {
+            for &point in self.focus.iter() {
+                self.index.query_sphere(&BoundingSphere::new(point, radius), &mut found);
+
+                for &entry in found.iter() {
+                    if let Some((&id, _)) = self.index.get(entry) {
+                        assigned.insert(id, tier as Tier);
+                    }
+                }
+            }
+        }
+
+        self.found = found;
+
+        let mut tiers = self.tiers.borrow_mut();
+
+        for (id, tier) in assigned {
+            tiers.set_tier(id, tier);
+        }
+    }
+
+    /// Record where the entity is.
+    fn process_entity<V: EngineView>(&mut self, _now: time::Duration, entity: &mut V) {
+        let bounds = match entity.world_location() {
+            Some(loc) => loc.bounds,
+            None      => return,
+        };
+
+        let id = entity.id();
+
+        let frame = self.frame;
+
+        if let Some(t) = self.tracked.get_mut(&id) {
+            let (a, b) = (t.bounds.center, bounds.center);
+
+            if a.x != b.x || a.y != b.y || a.z != b.z || t.bounds.half_edge != bounds.half_edge {
+                self.index.adjust(t.entry, bounds);
+                t.bounds = bounds;
+            }
+
+            t.seen = frame;
+            return
+        }
+
+        let entry = self.index.insert(id, bounds);
+
+        self.tracked.insert(id, Tracked { entry: entry, bounds: bounds, seen: frame });
+    }
+
+    fn name(&self) -> &'static str { "sim_lod" }
+}
@@ -15,13 +15,24 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+/// Generate an entity system with the given components: the `EntityID`, `System`, `Components`,
+/// `View` and `Manager` items, in the module where it is invoked. Most games want
+/// `register_components!`, which includes the components the engine itself uses.
+#[macro_export]
 macro_rules! make_ecs {
     { $($comp_name:ident : $comp_type:ty),+ } => { make_ecs! { $($comp_name: $comp_type, )+ } };
     { $($comp_name:ident : $comp_type:ty),+ , } => {
 
         use ::std::mem;
-        use ::std::collections::{btree_map, BTreeMap};
-
+        use ::std::cell::RefCell;
+        use ::std::collections::BTreeMap;
+        use ::std::rc::Rc;
+
+        use $crate::entity::{ComponentRef, OrderError, SystemID, SystemOrder};
+        use $crate::entity::lod::{LodTiers, Tier};
+        use $crate::entity::sleep::Sleep;
+        use $crate::entity::window::WindowEvents;
+        use $crate::rng::Rng;
         use $crate::time;
 
         /// An entity is just a unique identifier which is used to locate associated components.
@@ -37,6 +48,41 @@ macro_rules! make_ecs {
 
             /// Process an entity. This will be called once per entity, per simulation step.
             fn process_entity<'x>(&mut self, now: time::Duration, entity: &mut View<'x>);
+
+            /// A name for the system, which identifies it in crash dumps.
+            fn name(&self) -> &'static str { "unnamed" }
+
+            /// Whether the system keeps running while the `Manager` is paused. Systems which only
+            /// present the world (such as rendering) should return true, so that a paused world
+            /// stays on screen.
+            fn runs_while_paused(&self) -> bool { false }
+
+            /// Whether the system runs at a reduced rate for entities in distant LOD tiers (see
+            /// `Manager::set_lod`), and not at all for sleeping entities (see
+            /// `Manager::set_sleep`). Throttled systems only see a distant entity every few steps,
+            /// and should scale the work they do for it by `View::lod_steps`.
+            fn throttled(&self) -> bool { false }
+        }
+
+        /// An owned copy of all of an entity's components. Components which the entity doesn't
+        /// have are `None`.
+        #[derive(Clone)]
+        #[allow(missing_docs)] pub struct Components {
+            $(pub $comp_name: Option<$comp_type>,)+
+        }
+
+        impl Components {
+            /// A set of components which is entirely empty.
+            pub fn none() -> Components {
+                Components {
+                    $($comp_name: None,)+
+                }
+            }
+
+            /// True if none of the components are present.
+            pub fn is_empty(&self) -> bool {
+                true $(&& self.$comp_name.is_none())+
+            }
         }
 
         /// A view of an entity. This struct is passed to the `System`s for each entity they
@@ -44,22 +90,86 @@ macro_rules! make_ecs {
         #[allow(missing_docs)] pub struct View<'x> {
             pub id: EntityID,
 
-            $(pub $comp_name: Option<&'x mut $comp_type>,)+
+            /// The `Manager`'s random number generator. This is `None` for views which aren't
+            /// attached to a `Manager`.
+            pub rng: Option<&'x mut Rng>,
+
+            /// The entity's simulation LOD tier.
+            pub lod_tier: Tier,
+
+            /// The number of steps since throttled systems last saw this entity, which is 1 unless
+            /// it's in a distant tier. This is 0 on steps where throttled systems skip it.
+            pub lod_steps: u32,
+
+            /// True if the entity is asleep, in which case throttled systems skip it.
+            pub asleep: bool,
+
+            /// Systems which change the entity set this, so that it isn't put to sleep.
+            pub changed: bool,
+
+            $(pub $comp_name: Option<ComponentRef<'x, $comp_type>>,)+
         }
 
         impl<'x> View<'x> {
             /// Create a new view which doesn't reference any components.
             pub fn empty() -> View<'x> {
                 View {
-                    id: 0,
+                    id:        0,
+                    rng:       None,
+                    lod_tier:  0,
+                    lod_steps: 1,
+                    asleep:    false,
+                    changed:   false,
                     $($comp_name: None,)+
                 }
             }
         }
 
-        struct ComponentIter<'x, C> where C: 'x {
-            next: Option<(&'x EntityID, &'x mut C)>,
-            iter: btree_map::IterMut<'x, EntityID, C>,
+        // The components of one type, each shared with any `Snapshot`s which have them.
+        type ComponentMap<C> = Rc<BTreeMap<EntityID, Rc<C>>>;
+
+        /// The state of every entity in a `Manager` at some point, to be restored later with
+        /// `Manager::restore` (e.g. for rollback netcode, or undo in an editor).
+        ///
+        /// Snapshots share their components with the `Manager` and each other, so taking or
+        /// restoring one only copies a pointer per component type. A component is copied the first
+        /// time it's mutated through a `View` after a snapshot, and then only if a snapshot still
+        /// holds it (see `ComponentRef`), so an editor which keeps a snapshot per edit, or rollback
+        /// netcode which keeps one per step, pays only for the components which change. Systems and
+        /// settings such as pausing aren't included.
+        #[derive(Clone)]
+        pub struct Snapshot {
+            next_id: EntityID,
+            count:   usize,
+            rng:     Rng,
+            step:    u64,
+
+            $($comp_name: ComponentMap<$comp_type>,)+
+        }
+
+        impl Snapshot {
+            /// The number of entities which had at least one component.
+            pub fn entity_count(&self) -> usize {
+                self.count
+            }
+
+            /// The number of times `Manager::update` had been called.
+            pub fn step(&self) -> u64 {
+                self.step
+            }
+
+            /// Copy all of the components which an entity had.
+            pub fn components(&self, id: EntityID) -> Components {
+                Components {
+                    $($comp_name: self.$comp_name.get(&id).map(|c| (**c).clone()),)+
+                }
+            }
+        }
+
+        // A system, along with the ID it's found by.
+        struct SystemSlot {
+            id:     SystemID,
+            system: Box<System>,
         }
 
         /// A structure which holds all of the Components and Systems in the game, and processes
@@ -67,91 +177,316 @@ macro_rules! make_ecs {
         pub struct Manager {
             next_id: EntityID,
 
-            systems: Vec<Box<System>>,
+            // Number of entities with at least one component.
+            count: usize,
+
+            systems: Vec<SystemSlot>,
+
+            next_system: SystemID,
+
+            paused: bool,
+
+            rng: Rng,
+
+            lod: Option<Rc<RefCell<LodTiers>>>,
+
+            sleep: Option<Rc<RefCell<Sleep>>>,
 
-            $($comp_name: BTreeMap<EntityID, $comp_type>,)+
+            window: Option<Rc<RefCell<WindowEvents>>>,
+
+            // The number of times `update` has been called.
+            step: u64,
+
+            // The IDs of the entities `update` is processing, kept to save allocating every step.
+            step_ids: Vec<EntityID>,
+
+            $($comp_name: ComponentMap<$comp_type>,)+
         }
 
         impl Manager {
-            /// Create a new manager with no entities and no systems.
+            /// Create a new manager with no entities and no systems. Its random number
+            /// generator is seeded with zero.
             pub fn new() -> Manager {
+                Manager::with_seed(0)
+            }
+
+            /// Create a new manager with no entities and no systems, whose random number
+            /// generator is seeded with `seed`.
+            pub fn with_seed(seed: u64) -> Manager {
                 Manager {
                     next_id: 1,
+                    count:   0,
                     systems: vec![],
+                    paused:  false,
+
+                    next_system: 1,
 
-                    $($comp_name: BTreeMap::new(),)+
+                    rng:     Rng::new(seed),
+
+                    lod:    None,
+                    sleep:  None,
+                    window: None,
+                    step:   0,
+
+                    step_ids: vec![],
+
+                    $($comp_name: Rc::new(BTreeMap::new()),)+
                 }
             }
 
-            /// Add a system to the manager. Each simulation step, systems are processed in the
-            /// order that they were added to the manager. Similarly, entities are passed to the
-            /// systems in the order they were added.
-            pub fn add_system<S: System + 'static>(&mut self, system: S) {
-                self.systems.push(Box::new(system))
+            /// Add a system to the manager, returning its ID. Each simulation step, systems are
+            /// processed in the order that they were added to the manager, except for those added
+            /// with `add_system_ordered`. Similarly, entities are passed to the systems in the
+            /// order they were added.
+            pub fn add_system<S: System + 'static>(&mut self, system: S) -> SystemID {
+                let id = self.next_system;
+                self.next_system += 1;
+
+                self.systems.push(SystemSlot { id: id, system: Box::new(system) });
+
+                id
+            }
+
+            /// Add a system which must run before or after systems already in the manager,
+            /// returning its ID. It's placed as late as the constraints allow. This may be called
+            /// at any point, e.g. when loading a mod.
+            pub fn add_system_ordered<S: System + 'static>(&mut self, system: S, order: SystemOrder)
+                -> Result<SystemID, OrderError> {
+
+                for id in order.systems() {
+                    if !self.systems.iter().any(|s| s.id == id) {
+                        return Err(OrderError::NoSuchSystem(id))
+                    }
+                }
+
+                let mut lo = 0;
+                let mut hi = self.systems.len();
+
+                for (i, slot) in self.systems.iter().enumerate() {
+                    if order.is_after(slot.id) {
+                        lo = i + 1;
+                    }
+
+                    if order.is_before(slot.id) && i < hi {
+                        hi = i;
+                    }
+                }
+
+                if lo > hi {
+                    return Err(OrderError::Unsatisfiable)
+                }
+
+                let id = self.next_system;
+                self.next_system += 1;
+
+                self.systems.insert(hi, SystemSlot { id: id, system: Box::new(system) });
+
+                Ok(id)
+            }
+
+            /// Remove a system, returning it. Returns `None` if there's no system with that ID.
+            pub fn remove_system(&mut self, id: SystemID) -> Option<Box<System>> {
+                match self.systems.iter().position(|s| s.id == id) {
+                    Some(i) => Some(self.systems.remove(i).system),
+                    None    => None,
+                }
+            }
+
+            /// Swap a system for another, which takes over its position and ID (e.g. to swap in a
+            /// mod's AI), so systems can still be ordered relative to it. Returns the old system,
+            /// or gives `system` back if there's no system with that ID.
+            pub fn replace_system<S: System + 'static>(&mut self, id: SystemID, system: S)
+                -> Result<Box<System>, S> {
+
+                match self.systems.iter_mut().find(|s| s.id == id) {
+                    Some(slot) => Ok(mem::replace(&mut slot.system, Box::new(system))),
+                    None       => Err(system),
+                }
+            }
+
+            /// The ID of the first system with the given name, in running order.
+            pub fn system_id(&self, name: &str) -> Option<SystemID> {
+                self.systems.iter().find(|s| s.system.name() == name).map(|s| s.id)
+            }
+
+            /// The IDs and names of every system, in the order they run.
+            pub fn systems(&self) -> Vec<(SystemID, &'static str)> {
+                self.systems.iter().map(|s| (s.id, s.system.name())).collect()
+            }
+
+            /// Pause or resume the simulation. While paused, `update` only runs the systems whose
+            /// `runs_while_paused` returns true.
+            pub fn set_paused(&mut self, paused: bool) {
+                self.paused = paused
+            }
+
+            /// True if the simulation is paused.
+            pub fn paused(&self) -> bool {
+                self.paused
+            }
+
+            /// Simulate entities at reduced rates according to the tiers in `lod`, or every
+            /// entity every step with `None` (the default). The tiers are shared so that a system
+            /// such as `entity::lod::LodSystem` can assign them.
+            pub fn set_lod(&mut self, lod: Option<Rc<RefCell<LodTiers>>>) {
+                self.lod = lod
+            }
+
+            /// The LOD tiers in use, if any.
+            pub fn lod(&self) -> Option<Rc<RefCell<LodTiers>>> {
+                self.lod.clone()
+            }
+
+            /// Let entities which haven't changed for a while sleep, as tracked by `sleep`, or keep
+            /// every entity awake with `None` (the default). The state is shared so that gameplay
+            /// code and systems such as `entity::sleep::WakeSystem` can wake entities.
+            pub fn set_sleep(&mut self, sleep: Option<Rc<RefCell<Sleep>>>) {
+                self.sleep = sleep
+            }
+
+            /// The sleep state in use, if any.
+            pub fn sleep(&self) -> Option<Rc<RefCell<Sleep>>> {
+                self.sleep.clone()
+            }
+
+            /// Wake entity `id`, if it's asleep.
+            pub fn wake(&mut self, id: EntityID) {
+                if let Some(ref sleep) = self.sleep {
+                    sleep.borrow_mut().wake(id);
+                }
+            }
+
+            /// Deliver the window events in `window` to systems, or none with `None` (the
+            /// default). Each step's events are the ones pushed since the previous step began;
+            /// systems which react to them hold a clone of `window` (see `entity::window`).
+            pub fn set_window_events(&mut self, window: Option<Rc<RefCell<WindowEvents>>>) {
+                self.window = window
+            }
+
+            /// The window events in use, if any.
+            pub fn window_events(&self) -> Option<Rc<RefCell<WindowEvents>>> {
+                self.window.clone()
             }
 
             /// Run a single frame of processing for all entities and systems.
             pub fn update(&mut self, now: time::Duration) {
-                for system in self.systems.iter_mut() {
+                $crate::crash::set_entity_count(self.count);
+
+                // Window events are delivered even while paused, so that menus can react.
+                if let Some(ref window) = self.window {
+                    window.borrow_mut().begin_step();
+                }
+
+                let paused = self.paused;
+
+                // Nothing is throttled while paused, so it doesn't count as a step.
+                if !paused {
+                    self.step += 1;
+                }
+
+                let step = self.step;
+                let lod   = if paused { None } else { self.lod.clone() };
+                let sleep = if paused { None } else { self.sleep.clone() };
+
+                for slot in self.systems.iter_mut() {
+                    let system = &mut slot.system;
+
+                    if paused && !system.runs_while_paused() {
+                        continue
+                    }
+
+                    let _running = $crate::crash::enter_system(system.name());
+                    let _span    = $crate::trace::span($crate::trace::SYSTEM, system.name());
                     system.update(now);
                 }
 
-                $(
-                    let mut $comp_name = ComponentIter {
-                        next: None,
-                        iter: self.$comp_name.iter_mut(),
-                    };
+                // Collect the IDs first, rather than holding iterators over the component maps
+                // while systems run, so that a map is only made mutable (and copied, if a snapshot
+                // shares it) when a system actually mutates one of its components.
+                let mut ids = mem::replace(&mut self.step_ids, vec![]);
 
-                    $comp_name.next = $comp_name.iter.next();
-                )+
+                ids.clear();
 
-                let mut next_entity = None;
+                {
+                    $(let mut $comp_name = self.$comp_name.keys().cloned().peekable();)+
 
-                $(
-                    next_entity = match (next_entity, &$comp_name.next) {
-                        (Some(cur_id), &Some((new_id, _))) => if *new_id < cur_id {
-                            Some(*new_id)
-                        } else {
-                            Some(cur_id)
+                    loop {
+                        let mut next_entity: Option<EntityID> = None;
+
+                        $(
+                            if let Some(&id) = $comp_name.peek() {
+                                next_entity = match next_entity {
+                                    Some(cur_id) if cur_id < id => Some(cur_id),
+                                    _                           => Some(id),
+                                };
+                            }
+                        )+
+
+                        let cur_id = match next_entity {
+                            Some(id) => id,
+                            None     => break,
+                        };
+
+                        $(
+                            if $comp_name.peek() == Some(&cur_id) {
+                                $comp_name.next();
+                            }
+                        )+
+
+                        ids.push(cur_id);
+                    }
+                }
+
+                let _entities = $crate::trace::span($crate::trace::SYSTEM, "process_entities");
+
+                for &cur_id in ids.iter() {
+                    let (tier, steps) = match lod {
+                        Some(ref lod) => {
+                            let mut lod = lod.borrow_mut();
+                            (lod.tier(cur_id), lod.due(cur_id, step))
                         },
-                        (None, &Some((new_id, _))) => Some(*new_id),
-                        (any,  &None)              => any,
+                        None => (0, Some(1)),
                     };
-                )+
-
-                while let Some(cur_id) = next_entity {
 
+                    let awake = match sleep {
+                        Some(ref sleep) => sleep.borrow_mut().begin(cur_id),
+                        None            => true,
+                    };
 
                     let mut view = View {
-                        id: cur_id,
-                        $($comp_name: match $comp_name.next {
-                            Some((id, _)) if *id == cur_id =>
-                                match mem::replace(&mut $comp_name.next, $comp_name.iter.next()) {
-                                    Some((_, comp)) => Some(comp),
-                                    _               => unreachable!(),
-                            },
-                            _ => None,
-                        },)+
+                        id:        cur_id,
+                        rng:       Some(&mut self.rng),
+                        lod_tier:  tier,
+                        lod_steps: steps.unwrap_or(0),
+                        asleep:    !awake,
+                        changed:   false,
+                        $($comp_name: ComponentRef::managed(&mut self.$comp_name, cur_id),)+
                     };
 
-                    for system in self.systems.iter_mut() {
+                    for slot in self.systems.iter_mut() {
+                        let system = &mut slot.system;
+
+                        if paused && !system.runs_while_paused() {
+                            continue
+                        }
+
+                        if (steps.is_none() || !awake) && system.throttled() {
+                            continue
+                        }
+
+                        let _running = $crate::crash::enter_system(system.name());
                         system.process_entity(now, &mut view);
                     }
 
-                    next_entity = None;
-
-                    $(
-                        next_entity = match (next_entity, &$comp_name.next) {
-                            (Some(cur_id), &Some((new_id, _))) => if *new_id < cur_id {
-                                Some(*new_id)
-                            } else {
-                                Some(cur_id)
-                            },
-                            (None, &Some((new_id, _))) => Some(*new_id),
-                            (any,  &None)              => any,
-                        };
-                    )+
+                    // Steps skipped for LOD don't count towards falling asleep.
+                    if awake && steps.is_some() {
+                        if let Some(ref sleep) = sleep {
+                            sleep.borrow_mut().end(cur_id, view.changed);
+                        }
+                    }
                 }
+
+                self.step_ids = ids;
             }
 
             /// Create an entity from a `View`. This will clone all of the components referred to by
@@ -164,25 +499,185 @@ macro_rules! make_ecs {
                 let id = self.next_id;
                 self.next_id = id + 1;
 
+                let mut any = false;
+
                 $(
                     if let Some(comp_ref) = view.$comp_name {
-                        self.$comp_name.insert(id, comp_ref.clone());
+                        let comp: $comp_type = (*comp_ref).clone();
+                        Rc::make_mut(&mut self.$comp_name).insert(id, Rc::new(comp));
+                        any = true;
                     }
                 )+
 
+                if any {
+                    self.count += 1;
+                }
+
                 id
             }
 
             /// Remove an entity from the `Manager`. If that entity didn't exist, this is a no-op.
             pub fn remove_entity(&mut self, id: EntityID) {
-                $(self.$comp_name.remove(&id);)+
+                if let Some(ref lod) = self.lod {
+                    lod.borrow_mut().forget(id);
+                }
+
+                if let Some(ref sleep) = self.sleep {
+                    sleep.borrow_mut().forget(id);
+                }
+
+                let mut any = false;
+
+                $(
+                    if self.$comp_name.contains_key(&id) {
+                        Rc::make_mut(&mut self.$comp_name).remove(&id);
+                        any = true;
+                    }
+                )+
+
+                if any {
+                    self.count -= 1;
+                }
             }
 
-            /// Get a view of an entity.
+            /// Create an entity with the given components, returning its ID.
+            pub fn create_entity(&mut self, comps: Components) -> EntityID {
+                let id = self.next_id;
+                self.set_components(id, comps);
+                self.next_id = id + 1;
+
+                id
+            }
+
+            /// Copy all of the components of an entity.
+            pub fn components(&self, id: EntityID) -> Components {
+                Components {
+                    $($comp_name: self.$comp_name.get(&id).map(|c| (**c).clone()),)+
+                }
+            }
+
+            /// Replace all of the components of an entity with `comps`. Setting every component to
+            /// `None` removes the entity; setting components for an ID which doesn't exist creates
+            /// it with that ID.
+            pub fn set_components(&mut self, id: EntityID, comps: Components) {
+                self.wake(id);
+
+                let mut had = false;
+
+                $(
+                    if self.$comp_name.contains_key(&id) {
+                        Rc::make_mut(&mut self.$comp_name).remove(&id);
+                        had = true;
+                    }
+                )+
+
+                let has = !comps.is_empty();
+
+                $(
+                    if let Some(comp) = comps.$comp_name {
+                        Rc::make_mut(&mut self.$comp_name).insert(id, Rc::new(comp));
+                    }
+                )+
+
+                match (had, has) {
+                    (false, true) => self.count += 1,
+                    (true, false) => self.count -= 1,
+                    _             => {},
+                }
+
+                if id >= self.next_id {
+                    self.next_id = id + 1;
+                }
+            }
+
+            /// The IDs of every entity with at least one component, in ascending order.
+            pub fn entities(&self) -> Vec<EntityID> {
+                let mut ids = ::std::collections::BTreeSet::new();
+
+                $(ids.extend(self.$comp_name.keys().cloned());)+
+
+                ids.into_iter().collect()
+            }
+
+            /// The random number generator which gameplay code should draw from. Its state is part
+            /// of the world, so it should be saved and restored along with the entities.
+            pub fn rng(&mut self) -> &mut Rng {
+                &mut self.rng
+            }
+
+            /// Replace the random number generator, e.g. with one restored from a snapshot.
+            pub fn set_rng(&mut self, rng: Rng) {
+                self.rng = rng
+            }
+
+            /// The number of entities which have at least one component.
+            pub fn entity_count(&self) -> usize {
+                self.count
+            }
+
+            /// Add each component map to `report`, with the number of components in it. Heap
+            /// memory owned by the components themselves isn't counted, and components shared
+            /// with snapshots are counted as if they weren't.
+            pub fn report_memory(&self, report: &mut $crate::memory::Report) {
+                $(
+                    report.add($crate::logging::ECS, stringify!($comp_name),
+                               self.$comp_name.len(),
+                               self.$comp_name.len() *
+                                   (mem::size_of::<EntityID>() +
+                                    mem::size_of::<Rc<$comp_type>>() +
+                                    mem::size_of::<$comp_type>()));
+                )+
+
+                report.add($crate::logging::ECS, "systems", self.systems.len(),
+                           self.systems.capacity() * mem::size_of::<SystemSlot>());
+            }
+
+            /// Get a view of an entity. The entity is woken, since the caller may change it.
             pub fn view_entity<'x>(&'x mut self, id: EntityID) -> View<'x> {
+                self.wake(id);
+
+                let tier = match self.lod {
+                    Some(ref lod) => lod.borrow().tier(id),
+                    None          => 0,
+                };
+
                 View {
-                    id: id,
-                    $($comp_name: self.$comp_name.get_mut(&id),)+
+                    id:        id,
+                    rng:       Some(&mut self.rng),
+                    lod_tier:  tier,
+                    lod_steps: 1,
+                    asleep:    false,
+                    changed:   false,
+                    $($comp_name: ComponentRef::managed(&mut self.$comp_name, id),)+
+                }
+            }
+
+            /// Take a snapshot of every entity, the random number generator and the step count.
+            /// This is cheap, see `Snapshot`.
+            pub fn snapshot(&self) -> Snapshot {
+                Snapshot {
+                    next_id: self.next_id,
+                    count:   self.count,
+                    rng:     self.rng.clone(),
+                    step:    self.step,
+
+                    $($comp_name: self.$comp_name.clone(),)+
+                }
+            }
+
+            /// Put every entity, the random number generator and the step count back as they were
+            /// when `snapshot` was taken. Every entity is woken, since the sleep state describes
+            /// the world being replaced. Like taking a snapshot, this doesn't copy any components.
+            pub fn restore(&mut self, snapshot: &Snapshot) {
+                self.next_id = snapshot.next_id;
+                self.count   = snapshot.count;
+                self.rng     = snapshot.rng.clone();
+                self.step    = snapshot.step;
+
+                $(self.$comp_name = snapshot.$comp_name.clone();)+
+
+                if let Some(ref sleep) = self.sleep {
+                    sleep.borrow_mut().clear();
                 }
             }
         }
@@ -16,35 +16,311 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
 
+use std::collections::BTreeMap;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+use entity::component::{Light, Steering, WorldLocation, WorldRender};
+use time;
+
 #[macro_use]
 mod macros;
 
+/// Generate an entity system, like `make_ecs!`, whose components are the ones the engine uses
+/// (those of `entity::sim`) followed by the ones given. This lets a game add its own components
+/// without changing the engine:
+///
+/// ```ignore
+/// mod ecs {
+///     register_components! {
+///         health: ::game::Health,
+///         ai:     ::game::Brain,
+///     }
+/// }
+/// ```
+///
+/// The engine's own systems (rendering, steering...) are `EngineSystem`s, and every `View`
+/// generated here is an `EngineView`, so they can be added to the resulting `Manager` like any
+/// other `System`.
+#[macro_export]
+macro_rules! register_components {
+    { $($comp_name:ident : $comp_type:ty ,)* } => {
+        make_ecs! {
+            world_location: $crate::entity::component::WorldLocation,
+            world_render:   $crate::entity::component::WorldRender,
+            steering:       $crate::entity::component::Steering,
+            light:          $crate::entity::component::Light,
+            $($comp_name: $comp_type,)*
+        }
+
+        impl<'x> $crate::entity::EngineView for View<'x> {
+            fn id(&self) -> u64 { self.id }
+
+            fn lod_steps(&self) -> u32 { self.lod_steps }
+
+            fn changed(&self) -> bool { self.changed }
+
+            fn set_changed(&mut self) { self.changed = true }
+
+            fn world_location(&self) -> Option<&$crate::entity::component::WorldLocation> {
+                self.world_location.as_ref().map(|c| &**c)
+            }
+
+            fn world_location_mut(&mut self)
+                -> Option<&mut $crate::entity::component::WorldLocation> {
+                self.world_location.as_mut().map(|c| &mut **c)
+            }
+
+            fn world_render(&self) -> Option<&$crate::entity::component::WorldRender> {
+                self.world_render.as_ref().map(|c| &**c)
+            }
+
+            fn world_render_mut(&mut self) -> Option<&mut $crate::entity::component::WorldRender> {
+                self.world_render.as_mut().map(|c| &mut **c)
+            }
+
+            fn steering(&self) -> Option<&$crate::entity::component::Steering> {
+                self.steering.as_ref().map(|c| &**c)
+            }
+
+            fn steering_mut(&mut self) -> Option<&mut $crate::entity::component::Steering> {
+                self.steering.as_mut().map(|c| &mut **c)
+            }
+
+            fn light(&self) -> Option<&$crate::entity::component::Light> {
+                self.light.as_ref().map(|c| &**c)
+            }
+
+            fn light_mut(&mut self) -> Option<&mut $crate::entity::component::Light> {
+                self.light.as_mut().map(|c| &mut **c)
+            }
+        }
+
+        impl<S: $crate::entity::EngineSystem> System for S {
+            fn update(&mut self, now: $crate::time::Duration) {
+                $crate::entity::EngineSystem::update(self, now)
+            }
+
+            fn process_entity<'x>(&mut self, now: $crate::time::Duration, entity: &mut View<'x>) {
+                $crate::entity::EngineSystem::process_entity(self, now, entity)
+            }
+
+            fn name(&self) -> &'static str {
+                $crate::entity::EngineSystem::name(self)
+            }
+
+            fn runs_while_paused(&self) -> bool {
+                $crate::entity::EngineSystem::runs_while_paused(self)
+            }
+
+            fn throttled(&self) -> bool {
+                $crate::entity::EngineSystem::throttled(self)
+            }
+        }
+    };
+    { $($comp_name:ident : $comp_type:ty),* } => {
+        register_components! { $($comp_name: $comp_type, )* }
+    };
+}
+
 #[allow(missing_docs)]
 pub mod wire;
 
+/// Structural verification of untrusted `wire` buffers.
+pub mod verify;
+
 /// Components which can make up client- or server-side entities.
 pub mod component;
 
-/// The client-side entity system.
-#[cfg(feature = "client")] pub mod client {
-    use entity::component;
+/// Simulating distant entities less often.
+pub mod lod;
+
+/// Skipping entities which haven't changed in a while.
+pub mod sleep;
+
+/// Timed modifiers on entities, such as status effects.
+pub mod effects;
+
+/// Window events, delivered to systems through the `Manager`.
+pub mod window;
+
+/// Identifies a system which has been added to a `Manager`, so that it can later be removed or
+/// replaced. IDs are never reused by the same `Manager`.
+pub type SystemID = u64;
+
+/// Constraints on where a system runs relative to others already in the `Manager`, by their
+/// `SystemID`s (names needn't be unique, so they can't be used to tell systems apart).
+///
+/// A system can only be constrained by those added before it, so systems added without
+/// constraints never conflict with it. A system which takes over another's place with
+/// `Manager::replace_system` also takes over its ID, so constraints on it still hold.
+#[derive(Clone,Debug)]
+pub struct SystemOrder {
+    after:  Vec<SystemID>,
+    before: Vec<SystemID>,
+}
+
+impl SystemOrder {
+    /// No constraints. The system runs after every system already added.
+    pub fn new() -> SystemOrder {
+        SystemOrder { after: vec![], before: vec![] }
+    }
+
+    /// Require the system to run after the system `id`.
+    pub fn after(mut self, id: SystemID) -> SystemOrder {
+        self.after.push(id);
+        self
+    }
 
-    make_ecs! {
-        world_location: component::WorldLocation,
-        world_render:   component::WorldRender,
+    /// Require the system to run before the system `id`.
+    pub fn before(mut self, id: SystemID) -> SystemOrder {
+        self.before.push(id);
+        self
+    }
+
+    /// True if this requires running after `id`.
+    pub fn is_after(&self, id: SystemID) -> bool {
+        self.after.iter().any(|&a| a == id)
+    }
+
+    /// True if this requires running before `id`.
+    pub fn is_before(&self, id: SystemID) -> bool {
+        self.before.iter().any(|&b| b == id)
+    }
+
+    /// Every system this refers to.
+    pub fn systems(&self) -> Vec<SystemID> {
+        self.after.iter().chain(self.before.iter()).cloned().collect()
     }
 }
 
-/// The server-side entity system.
-#[cfg(feature = "server")] pub mod server {
-    use entity::component;
+/// One of the components in a `View`. It derefs to the component, and reading through it never
+/// copies anything. Components in a `Manager` may be shared with `Snapshot`s, and such a component
+/// (and its type's map of components) is only copied when it's first mutated through here.
+pub struct ComponentRef<'x, C: 'x> {
+    inner: RefInner<'x, C>,
+}
+
+enum RefInner<'x, C: 'x> {
+    // A component which isn't in a `Manager`, e.g. one about to be added with `sim_entity!`.
+    Local(&'x mut C),
+
+    // The component of entity `id` in a `Manager`'s map of components of type `C`, which has it.
+    Managed(&'x mut Rc<BTreeMap<u64, Rc<C>>>, u64),
+}
+
+impl<'x, C> ComponentRef<'x, C> {
+    /// Refer to a component which isn't in a `Manager`.
+    pub fn local(comp: &'x mut C) -> ComponentRef<'x, C> {
+        ComponentRef { inner: RefInner::Local(comp) }
+    }
+
+    /// Refer to the component of entity `id` in one of a `Manager`'s component maps, or return
+    /// `None` if the entity doesn't have one. This is used by `make_ecs!`.
+    #[doc(hidden)]
+    pub fn managed(map: &'x mut Rc<BTreeMap<u64, Rc<C>>>, id: u64) -> Option<ComponentRef<'x, C>> {
+        if map.contains_key(&id) {
+            Some(ComponentRef { inner: RefInner::Managed(map, id) })
+        } else {
+            None
+        }
+    }
+}
+
+impl<'x, C> Deref for ComponentRef<'x, C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        match self.inner {
+            RefInner::Local(ref comp)       => comp,
+            RefInner::Managed(ref map, id) => &map[&id],
+        }
+    }
+}
 
-    make_ecs! {
-        world_location: component::WorldLocation,
-        world_render:   component::WorldRender,
+impl<'x, C: Clone> DerefMut for ComponentRef<'x, C> {
+    fn deref_mut(&mut self) -> &mut C {
+        match self.inner {
+            RefInner::Local(ref mut comp)       => comp,
+            RefInner::Managed(ref mut map, id) => {
+                Rc::make_mut(Rc::make_mut(map).get_mut(&id).unwrap())
+            },
+        }
     }
 }
 
+/// The parts of an entity which the engine's systems use. The `View` of every
+/// `register_components!` module implements this. Mutating a component through it copies it if
+/// it's shared with a `Snapshot`, as with `ComponentRef`.
+pub trait EngineView {
+    /// The entity's ID.
+    fn id(&self) -> u64;
+
+    /// The number of steps since throttled systems last saw the entity (see `View::lod_steps`).
+    fn lod_steps(&self) -> u32;
+
+    /// True if a system has changed the entity this step.
+    fn changed(&self) -> bool;
+
+    /// Record that the entity changed, so that it isn't put to sleep.
+    fn set_changed(&mut self);
+
+    #[allow(missing_docs)] fn world_location(&self) -> Option<&WorldLocation>;
+    #[allow(missing_docs)] fn world_location_mut(&mut self) -> Option<&mut WorldLocation>;
+    #[allow(missing_docs)] fn world_render(&self) -> Option<&WorldRender>;
+    #[allow(missing_docs)] fn world_render_mut(&mut self) -> Option<&mut WorldRender>;
+    #[allow(missing_docs)] fn steering(&self) -> Option<&Steering>;
+    #[allow(missing_docs)] fn steering_mut(&mut self) -> Option<&mut Steering>;
+    #[allow(missing_docs)] fn light(&self) -> Option<&Light>;
+    #[allow(missing_docs)] fn light_mut(&mut self) -> Option<&mut Light>;
+}
+
+/// A system which only uses the engine's components, through an `EngineView`. Every
+/// `register_components!` module's `System` is implemented for these, so one can be added to the
+/// `Manager` of `entity::sim` or of a game's own entity system. The methods are those of `System`.
+pub trait EngineSystem {
+    #[allow(missing_docs)] fn update(&mut self, now: time::Duration);
+
+    #[allow(missing_docs)]
+    fn process_entity<V: EngineView>(&mut self, now: time::Duration, entity: &mut V);
+
+    #[allow(missing_docs)] fn name(&self) -> &'static str { "unnamed" }
+
+    #[allow(missing_docs)] fn runs_while_paused(&self) -> bool { false }
+
+    #[allow(missing_docs)] fn throttled(&self) -> bool { false }
+}
+
+/// A system couldn't be added with the `SystemOrder` it was given.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum OrderError {
+    /// It must run before or after a system which isn't in the `Manager`.
+    NoSuchSystem(SystemID),
+
+    /// It must run both before and after the same systems, directly or through the order the
+    /// `Manager` already runs them in.
+    Unsatisfiable,
+}
+
+/// The entity system shared by clients and servers.
+///
+/// This is always compiled, so a single binary built with both the `client` and `server` features
+/// (e.g. one hosting a listen server) has exactly one `Manager` type, and entities can be handed
+/// between its two halves without conversion.
+pub mod sim {
+    register_components! {}
+}
+
+/// The client-side entity system.
+#[cfg(feature = "client")] pub mod client {
+    pub use entity::sim::*;
+}
+
+/// The server-side entity system.
+#[cfg(feature = "server")] pub mod server {
+    pub use entity::sim::*;
+}
+
 #[macro_export]
 macro_rules! client_entity {
 
@@ -58,7 +334,7 @@ macro_rules! client_entity {
 
             let mut __view = $crate::entity::client::View::empty();
 
-            $( __view.$comp_name = Some(&mut $comp_name); )+
+            $( __view.$comp_name = Some($crate::entity::ComponentRef::local(&mut $comp_name)); )+
 
             $manager.entity_from_view(__view)
         }
@@ -79,7 +355,28 @@ macro_rules! server_entity {
 
             let mut __view = $crate::entity::server::View::empty();
 
-            $( __view.$comp_name = Some(&mut $comp_name); )+
+            $( __view.$comp_name = Some($crate::entity::ComponentRef::local(&mut $comp_name)); )+
+
+            $manager.entity_from_view(__view)
+        }
+    }
+
+}
+
+#[macro_export]
+macro_rules! sim_entity {
+
+    ($manager:expr, $($comp_name:ident : $comp_val:expr),+) => {
+        sim_entity!($manager, $($comp_name : $comp_val,)+)
+    };
+
+    ($manager:expr, $($comp_name:ident : $comp_val:expr,)+) => {
+        {
+            $( let mut $comp_name = $comp_val; )+
+
+            let mut __view = $crate::entity::sim::View::empty();
+
+            $( __view.$comp_name = Some($crate::entity::ComponentRef::local(&mut $comp_name)); )+
 
             $manager.entity_from_view(__view)
         }
@@ -0,0 +1,258 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Letting idle entities sleep. Most props, and units standing around, don't change from one step
+//! to the next, yet every system still processes them. Once an entity has gone a while without
+//! changing, the `Manager` stops passing it to throttled systems (see `System::throttled`) until
+//! something wakes it: gameplay code calling `Sleep::wake` (e.g. when it's damaged), or an entity
+//! moving nearby (see `WakeSystem`).
+//!
+//! Systems report changes by setting `View::changed`; an entity which no system marks as changed
+//! is considered idle.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
+use std::rc::Rc;
+
+use entity::{EngineSystem, EngineView};
+use math::{BoundingCube, BoundingSphere};
+use scene::{EntryID, LooseOctree};
+use time;
+use units::*;
+
+// The initial bounds of `WakeSystem`'s spatial index. It grows as needed.
+const INDEX_HALF_EDGE: f32 = 64.0;
+const INDEX_MIN:       f32 = 1.0;
+const INDEX_LOOSE:     f32 = 2.0;
+
+/// Which entities are asleep. This is shared between the `Manager` and whatever wakes entities.
+pub struct Sleep {
+    after: u32,
+
+    // The number of steps in a row each entity hasn't changed, for entities which didn't change
+    // last step.
+    idle: BTreeMap<u64, u32>,
+
+    // Entities to wake at their next step.
+    woken: BTreeSet<u64>,
+}
+
+impl Sleep {
+    /// Put entities to sleep once they haven't changed for `after` steps in a row.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `after` is zero.
+    pub fn new(after: u32) -> Sleep {
+        assert!(after > 0, "entities can't sleep after zero steps");
+
+        Sleep {
+            after: after,
+            idle:  BTreeMap::new(),
+            woken: BTreeSet::new(),
+        }
+    }
+
+    /// The number of idle steps after which entities go to sleep.
+    pub fn after(&self) -> u32 {
+        self.after
+    }
+
+    /// True if entity `id` is asleep.
+    pub fn is_asleep(&self, id: u64) -> bool {
+        !self.woken.contains(&id) && self.idle.get(&id).map_or(false, |&n| n >= self.after)
+    }
+
+    /// The number of entities which are asleep.
+    pub fn asleep_count(&self) -> usize {
+        self.idle.iter().filter(|&(id, _)| self.is_asleep(*id)).count()
+    }
+
+    /// Wake entity `id`, so that it's processed from its next step on. It can fall asleep again
+    /// after another `after` idle steps.
+    pub fn wake(&mut self, id: u64) {
+        if self.idle.contains_key(&id) {
+            self.woken.insert(id);
+        }
+    }
+
+    /// Forget entity `id`, e.g. because it's been removed.
+    pub fn forget(&mut self, id: u64) {
+        self.idle.remove(&id);
+        self.woken.remove(&id);
+    }
+
+    /// Wake every entity.
+    pub fn clear(&mut self) {
+        self.idle.clear();
+        self.woken.clear();
+    }
+
+    /// Called by the `Manager` before processing entity `id`. Returns false if it's asleep.
+    pub fn begin(&mut self, id: u64) -> bool {
+        if self.woken.remove(&id) {
+            self.idle.remove(&id);
+            return true
+        }
+
+        !self.is_asleep(id)
+    }
+
+    /// Called by the `Manager` after processing an awake entity, with whether any system changed
+    /// it.
+    pub fn end(&mut self, id: u64, changed: bool) {
+        if changed {
+            self.idle.remove(&id);
+        } else {
+            let after = self.after;
+            let n     = self.idle.entry(id).or_insert(0);
+
+            if *n < after {
+                *n += 1;
+            }
+        }
+    }
+}
+
+// Where an entity is, as far as `WakeSystem` knows.
+struct Tracked {
+    entry:  EntryID,
+    bounds: BoundingCube,
+
+    // The value of `WakeSystem::frame` when this entity was last processed.
+    seen: u64,
+}
+
+/// A `System` which wakes sleeping entities when a changing entity comes within `radius` of them,
+/// e.g. so that props react to a unit walking through them. It uses a spatial index of every
+/// entity with a `WorldLocation`.
+///
+/// This looks at `View::changed`, so it should run after every system which might set it.
+pub struct WakeSystem {
+    sleep:  Rc<RefCell<Sleep>>,
+    radius: Meters,
+
+    index:   LooseOctree<u64>,
+    tracked: BTreeMap<u64, Tracked>,
+    frame:   u64,
+
+    // Entities which changed last step.
+    movers: Vec<u64>,
+
+    // Scratch space for querying `index`.
+    found: Vec<EntryID>,
+}
+
+impl WakeSystem {
+    /// Create a system which wakes entities in `sleep` within `radius` of a changing entity.
+    pub fn new(sleep: Rc<RefCell<Sleep>>, radius: Meters) -> WakeSystem {
+        let initial = BoundingCube {
+            center:    vec3!(Meters ; 0.0, 0.0, 0.0),
+            half_edge: Meters(INDEX_HALF_EDGE),
+        };
+
+        WakeSystem {
+            sleep:   sleep,
+            radius:  radius,
+            index:   LooseOctree::new(initial, Meters(INDEX_MIN), INDEX_LOOSE),
+            tracked: BTreeMap::new(),
+            frame:   0,
+            movers:  vec![],
+            found:   vec![],
+        }
+    }
+
+    // Drop entities which weren't processed last step.
+    fn forget_stale(&mut self) {
+        let frame = self.frame;
+
+        let gone: Vec<_> = self.tracked.iter()
+            .filter(|&(_, t)| t.seen + 1 < frame)
+            .map(|(&id, _)| id)
+            .collect();
+
+        for id in gone {
+            if let Some(t) = self.tracked.remove(&id) {
+                self.index.remove(t.entry);
+            }
+        }
+    }
+}
+
+impl EngineSystem for WakeSystem {
+    /// Wake the entities near those which changed last step.
+    fn update(&mut self, _now: time::Duration) {
+        self.frame += 1;
+        self.forget_stale();
+
+        let movers    = mem::replace(&mut self.movers, vec![]);
+        let mut found = mem::replace(&mut self.found, vec![]);
+        let mut sleep = self.sleep.borrow_mut();
+
+        for id in movers {
+            let center = match self.tracked.get(&id) {
+                Some(t) => t.bounds.center,
+                None    => continue,
+            };
+
+            self.index.query_sphere(&BoundingSphere::new(center, self.radius), &mut found);
+
+            for &entry in found.iter() {
+                if let Some((&near, _)) = self.index.get(entry) {
+                    sleep.wake(near);
+                }
+            }
+        }
+
+        self.found = found;
+    }
+
+    /// Record where the entity is, and whether it changed.
+    fn process_entity<V: EngineView>(&mut self, _now: time::Duration, entity: &mut V) {
+        let bounds = match entity.world_location() {
+            Some(loc) => loc.bounds,
+            None      => return,
+        };
+
+        let id = entity.id();
+
+        if entity.changed() {
+            self.movers.push(id);
+        }
+
+        let frame = self.frame;
+
+        if let Some(t) = self.tracked.get_mut(&id) {
+            let (a, b) = (t.bounds.center, bounds.center);
+
+            if a.x != b.x || a.y != b.y || a.z != b.z || t.bounds.half_edge != bounds.half_edge {
+                self.index.adjust(t.entry, bounds);
+                t.bounds = bounds;
+            }
+
+            t.seen = frame;
+            return
+        }
+
+        let entry = self.index.insert(id, bounds);
+
+        self.tracked.insert(id, Tracked { entry: entry, bounds: bounds, seen: frame });
+    }
+
+    fn name(&self) -> &'static str { "wake" }
+}
@@ -0,0 +1,117 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Window events, such as resizes and requests to quit, delivered through the `Manager`.
+//!
+//! The platform layer (`client::Context::pump_events`, on the client) pushes events into a shared
+//! `WindowEvents` as they arrive. At the start of each `Manager::update` the events pushed since
+//! the last one become that step's events, so every system sees the same ones in `update` and
+//! `process_entity`, in system order, just as they see changes to the world. The events are
+//! delivered even while the `Manager` is paused, since menus need to react to them.
+//!
+//! ```ignore
+//! let window = Rc::new(RefCell::new(WindowEvents::new()));
+//! manager.set_window_events(Some(window.clone()));
+//!
+//! // In a system holding a clone of `window`:
+//! if let Some((w, h)) = self.window.borrow().resized() { ... }
+//! ```
+
+use std::mem;
+use std::path::PathBuf;
+
+/// Something that happened to the game's window.
+#[derive(Clone,Debug,PartialEq)]
+pub enum Event {
+    /// The window's drawable area changed size, to the given width and height in device pixels.
+    Resized(u32, u32),
+
+    /// The window gained keyboard focus.
+    FocusGained,
+
+    /// The window lost keyboard focus, e.g. because the player switched to another program.
+    FocusLost,
+
+    /// The player asked for the game to close, e.g. with the window's close button. Nothing closes
+    /// until the game decides to; it might ask to save first.
+    QuitRequested,
+
+    /// A file was dropped onto the window.
+    FileDropped(PathBuf),
+}
+
+/// The window events of the current step, and those waiting for the next. This is shared between
+/// the platform layer, the `Manager` and the systems which react to the events.
+pub struct WindowEvents {
+    pending: Vec<Event>,
+    current: Vec<Event>,
+}
+
+impl WindowEvents {
+    /// Create an empty queue.
+    pub fn new() -> WindowEvents {
+        WindowEvents { pending: vec![], current: vec![] }
+    }
+
+    /// Queue an event for the next step.
+    pub fn push(&mut self, ev: Event) {
+        self.pending.push(ev)
+    }
+
+    /// Make the events pushed since the last call the current ones, dropping the old current ones.
+    /// `Manager::update` calls this at the start of each step.
+    pub fn begin_step(&mut self) {
+        self.current.clear();
+        mem::swap(&mut self.current, &mut self.pending);
+    }
+
+    /// This step's events, in the order they happened.
+    pub fn events(&self) -> &[Event] {
+        &self.current
+    }
+
+    /// The size the window was last resized to this step, if it was resized.
+    pub fn resized(&self) -> Option<(u32, u32)> {
+        self.current.iter().rev().filter_map(|ev| match *ev {
+            Event::Resized(w, h) => Some((w, h)),
+            _                    => None,
+        }).next()
+    }
+
+    /// Whether the window has focus after this step's events: `Some(true)` if it gained focus,
+    /// `Some(false)` if it lost it, or `None` if neither happened.
+    pub fn focused(&self) -> Option<bool> {
+        self.current.iter().rev().filter_map(|ev| match *ev {
+            Event::FocusGained => Some(true),
+            Event::FocusLost   => Some(false),
+            _                  => None,
+        }).next()
+    }
+
+    /// True if the player asked to quit this step.
+    pub fn quit_requested(&self) -> bool {
+        self.current.iter().any(|ev| *ev == Event::QuitRequested)
+    }
+
+    /// The files dropped onto the window this step.
+    pub fn dropped_files(&self) -> Vec<&PathBuf> {
+        self.current.iter().filter_map(|ev| match *ev {
+            Event::FileDropped(ref path) => Some(path),
+            _                            => None,
+        }).collect()
+    }
+}
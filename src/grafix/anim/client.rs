@@ -15,6 +15,8 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+use std::convert::{AsRef, From};
+
 use asset;
 use grafix::anim::wire::Anim as WireAnim;
 use grafix::anim;
@@ -34,11 +36,23 @@ pub struct Anim {
 
 impl Anim {
     /// Convert from FlatBuffer representation.
-    pub fn from_wire<'x>(w: &WireAnim, h: asset::Handle<'x>) -> Anim {
-        Anim {
-            sheet_id: h.get_id(w.sheet().expect("sheet name in flatbuf")).expect("sheet id in DB"),
-            indices:  w.indices().expect("indices in flatbuf").iter().collect(),
+    pub fn from_wire<'x>(w: &WireAnim, h: asset::Handle<'x>) -> Result<Anim, anim::Error> {
+        let sheet_name = try!(w.sheet().ok_or(anim::Error::MissingField("sheet")));
+        let indices    = try!(w.indices().ok_or(anim::Error::MissingField("indices")));
+
+        let sheet_id = try!(h.get_id(sheet_name)
+            .ok_or_else(|| anim::Error::NoSuchSheet(From::from(sheet_name.as_ref()))));
+
+        let indices: Vec<u16> = indices.iter().collect();
+
+        if indices.is_empty() {
+            return Err(anim::Error::NoFrames)
         }
+
+        Ok(Anim {
+            sheet_id: sheet_id,
+            indices:  indices,
+        })
     }
 }
 
@@ -53,7 +67,9 @@ impl anim::Instance {
             return None
         };
 
-        if t < self.t_start {
+        // A zero duration is rejected by `Instance::from_wire`, but a locally constructed instance
+        // could still have one. Don't divide by it.
+        if t < self.t_start || self.duration == time::Duration::usec(0) {
             return None
         }
 
@@ -75,6 +91,13 @@ impl anim::Instance {
             sheet_id:   anim.sheet_id,
             sprite_idx: anim.indices[frame] as usize,
             game_loc:   loc,
+            tint:       sprite::WHITE,
+            flip_x:     self.flip_x,
+            scale:      1.0,
+            rotation:   0.0,
+            highlight:  None,
+            layer:      sprite::LAYER_UNITS,
+            material:   sprite::DEFAULT_MATERIAL,
         })
     }
 
@@ -83,3 +106,102 @@ impl anim::Instance {
         self.t_start + self.duration
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use flatbuffers as fb;
+
+    use asset;
+    use grafix::anim::{wire, Anim, Error};
+    use grafix::sprite;
+
+    // A database holding a single 4-frame sheet called "hero", which is never loaded.
+    fn db() -> asset::AssetDb {
+        let mut manifest = asset::ManifestBuilder::new();
+
+        manifest.add_sheet("hero", sprite::SheetDesc {
+            img_width:     64,
+            img_height:    64,
+            origin_x:      16,
+            origin_y:      32,
+            spr_width:     32,
+            spr_height:    32,
+            num_across:    2,
+            num_down:      2,
+            total:         4,
+            color_path:    "hero.png".to_string(),
+            depth_path:    "hero_depth.png".to_string(),
+            color_hash:    0,
+            depth_hash:    0,
+            normal_path:   None,
+            normal_hash:   0,
+            emissive_path: None,
+            emissive_hash: 0,
+        });
+
+        let buf = manifest.finish().unwrap();
+
+        asset::AssetDb::from_manifest(fb::get_root::<asset::wire::AssetManifest>(&buf)).ok()
+            .unwrap()
+    }
+
+    // Build an `Anim` table with only the fields given.
+    fn anim_buf(sheet: Option<&str>, indices: Option<&[u16]>) -> Vec<u8> {
+        let mut fbb = fb::FlatBufferBuilder::new();
+
+        let name    = fbb.create_string("walk");
+        let sheet   = sheet.map(|s| fbb.create_string(s));
+        let indices = indices.map(|i| fbb.create_vector(i));
+
+        let root = {
+            let mut b = wire::AnimBuilder::new(&mut fbb);
+            b.add_name(name);
+            if let Some(sheet) = sheet { b.add_sheet(sheet) }
+            if let Some(indices) = indices { b.add_indices(indices) }
+            b.finish()
+        };
+
+        fbb.finish(root);
+        fbb.into_vec()
+    }
+
+    fn from_wire(db: &asset::AssetDb, buf: &[u8]) -> Result<Anim, Error> {
+        Anim::from_wire(fb::get_root::<wire::Anim>(buf), db.get_handle())
+    }
+
+    #[test]
+    fn anim_from_wire() {
+        let db   = db();
+        let anim = from_wire(&db, &anim_buf(Some("hero"), Some(&[0, 3, 1]))).unwrap();
+
+        assert_eq!(Some(anim.sheet_id), db.get_handle().get_id("hero"));
+        assert_eq!(anim.indices, vec![0, 3, 1]);
+    }
+
+    #[test]
+    fn anim_missing_fields() {
+        let db = db();
+
+        assert_eq!(from_wire(&db, &anim_buf(None, Some(&[0]))).err(),
+                   Some(Error::MissingField("sheet")));
+
+        assert_eq!(from_wire(&db, &anim_buf(Some("hero"), None)).err(),
+                   Some(Error::MissingField("indices")));
+    }
+
+    #[test]
+    fn anim_no_such_sheet() {
+        let db = db();
+
+        assert_eq!(from_wire(&db, &anim_buf(Some("villain"), Some(&[0]))).err(),
+                   Some(Error::NoSuchSheet("villain".to_string())));
+    }
+
+    #[test]
+    fn anim_no_frames() {
+        let db = db();
+
+        assert_eq!(from_wire(&db, &anim_buf(Some("hero"), Some(&[]))).err(),
+                   Some(Error::NoFrames));
+    }
+}
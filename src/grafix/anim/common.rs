@@ -36,6 +36,10 @@ pub struct Instance {
 
     /// True if this animation should repeat indefinitely.
     pub repeat: bool,
+
+    /// True if the frames should be mirrored left to right, e.g. for a character facing the
+    /// other way.
+    pub flip_x: bool,
 }
 
 impl Instance {
@@ -46,6 +50,7 @@ impl Instance {
             t_start:  time::Duration::usec(w.t_start()),
             duration: time::Duration::usec(w.duration()),
             repeat:   w.repeat(),
+            flip_x:   w.flip_x(),
         }
     }
 
@@ -56,6 +61,7 @@ impl Instance {
             self.duration.as_usec(),
             self.anim_id as u32,
             self.repeat,
+            self.flip_x,
         )
     }
 }
@@ -18,9 +18,14 @@
 #[allow(missing_docs)]
 pub mod wire;
 
+/// Structural verification of untrusted `wire` buffers.
+pub mod verify;
+
 #[cfg(feature = "client")] mod client;
 #[cfg(feature = "client")] pub use self::client::*;
 
+use flatbuffers as fb;
+
 use time;
 
 /// An ID that refers to a particular `Anim` in a `Database`.
@@ -29,7 +34,7 @@ pub type AnimID = usize;
 /// An instance of an animation, which specifies how long it should take for the animation to
 /// complete, when the animation began, where the first frame's sprite is located, and where the
 /// animation should end up.
-#[derive(Clone)]
+#[derive(Clone,Copy,PartialEq)]
 pub struct Instance {
     /// The Anim being instantiated.
     pub anim_id: AnimID,
@@ -42,17 +47,31 @@ pub struct Instance {
 
     /// True if this animation should repeat indefinitely.
     pub repeat: bool,
+
+    /// True if the frames should be mirrored left to right, e.g. for a character facing the
+    /// other way.
+    pub flip_x: bool,
 }
 
 impl Instance {
     /// Create a struct from its FlatBuffer representation.
-    pub fn from_wire(w: &wire::AnimInstance) -> Instance {
-        Instance {
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::ZeroDuration` if the instance has a duration of zero, since there would be
+    /// no way to decide which frame to draw.
+    pub fn from_wire(w: &wire::AnimInstance) -> Result<Instance, Error> {
+        if w.duration() == 0 {
+            return Err(Error::ZeroDuration)
+        }
+
+        Ok(Instance {
             anim_id:  w.id() as AnimID,
             t_start:  time::Duration::usec(w.t_start()),
             duration: time::Duration::usec(w.duration()),
             repeat:   w.repeat(),
-        }
+            flip_x:   w.flip_x(),
+        })
     }
 
     /// Get the FlatBuffer representation of this struct.
@@ -62,6 +81,90 @@ impl Instance {
             self.duration.as_usec(),
             self.anim_id as u32,
             self.repeat,
+            self.flip_x,
         )
     }
 }
+
+/// A descriptor for an animation, as it is listed in the asset manifest. This is what tools work
+/// with; the client turns it into an `Anim` once the sheet it refers to has an ID.
+#[derive(Clone,Debug)]
+pub struct AnimDesc {
+    /// The name of the sprite sheet where the frames reside.
+    pub sheet: String,
+
+    /// The indices of the frames of this animation within the sheet, in order.
+    pub indices: Vec<u16>,
+}
+
+impl AnimDesc {
+    /// Add this descriptor to a FlatBuffer under construction, under the given name.
+    pub fn to_wire(&self, name: &str, fbb: &mut fb::FlatBufferBuilder) -> fb::Offset<wire::Anim> {
+        let name    = fbb.create_string(name);
+        let sheet   = fbb.create_string(&self.sheet);
+        let indices = fbb.create_vector(&self.indices);
+
+        let mut b = wire::AnimBuilder::new(fbb);
+        b.add_name(name);
+        b.add_sheet(sheet);
+        b.add_indices(indices);
+        b.finish()
+    }
+}
+
+/// An error encountered when converting an animation from its FlatBuffer representation.
+#[derive(Debug,PartialEq)]
+pub enum Error {
+    /// An `Instance` has a duration of zero.
+    ZeroDuration,
+
+    /// An `Anim` doesn't have any frames.
+    NoFrames,
+
+    /// A required field was missing from the FlatBuffer.
+    MissingField(&'static str),
+
+    /// An `Anim` refers to a sprite sheet which isn't in the database.
+    NoSuchSheet(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use grafix::anim::{wire, Error, Instance};
+    use time;
+
+    fn instance(repeat: bool, flip_x: bool) -> Instance {
+        Instance {
+            anim_id:  3,
+            t_start:  time::Duration::usec(1_000),
+            duration: time::Duration::usec(250_000),
+            repeat:   repeat,
+            flip_x:   flip_x,
+        }
+    }
+
+    #[test]
+    fn instance_round_trip() {
+        let cases = [(false, false), (true, false), (false, true), (true, true)];
+
+        for &(repeat, flip_x) in cases.iter() {
+            let inst = instance(repeat, flip_x);
+            let w    = inst.to_wire();
+
+            assert_eq!(w.repeat(), repeat);
+            assert_eq!(w.flip_x(), flip_x);
+            assert_eq!(w.id(), 3);
+            assert_eq!(w.t_start(), 1_000);
+            assert_eq!(w.duration(), 250_000);
+
+            assert!(Instance::from_wire(&w).unwrap() == inst);
+        }
+    }
+
+    #[test]
+    fn instance_zero_duration() {
+        let w = wire::AnimInstance::new(1_000, 0, 3, true, false);
+
+        assert_eq!(Instance::from_wire(&w).err(), Some(Error::ZeroDuration));
+    }
+}
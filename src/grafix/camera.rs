@@ -19,23 +19,191 @@ use std::f64;
 
 use num::{Float, NumCast};
 
+use grafix::wire;
 use math;
+use time;
 use units::*;
 
+pub use math::Visibility;
+
+/// The smallest zoom level a new `Zoom` allows.
+pub const DEFAULT_MIN_ZOOM: f32 = 0.25;
+
+/// The largest zoom level a new `Zoom` allows.
+pub const DEFAULT_MAX_ZOOM: f32 = 8.0;
+
+/// The factor by which `Zoom::zoom_in` and `Zoom::zoom_out` change the zoom level.
+pub const ZOOM_STEP: f32 = 2.0;
+
 fn degrees_to_radians<F: Float>(deg: F) -> F {
     deg / NumCast::from(180.0f64 / f64::consts::PI).unwrap()
 }
 
-/// How visible an object is to the camera, returned by `Camera::visible`.
-pub enum Visibility {
-    /// The object can't be seen at all.
-    Zero,
+/// The reasons a `Camera` or `Zoom` can't be restored from its FlatBuffer representation.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Error {
+    /// One of the numbers was infinite or NaN.
+    NotFinite,
+
+    /// The aspect was none of the values written by `Camera::to_wire`.
+    UnknownAspect(u8),
+
+    /// The scale, resolution or zoom limits weren't positive, or the level was outside the limits.
+    OutOfRange,
+}
+
+/// A rectangle on the ground (the plane z = 0), in game space.
+#[derive(Clone,Copy,Debug)]
+pub struct Rect {
+    /// The corner with the smallest x and y coordinates.
+    pub min: math::Vec2<Meters>,
+
+    /// The corner with the largest x and y coordinates.
+    pub max: math::Vec2<Meters>,
+}
+
+impl Rect {
+    /// Create the rectangle with corners `min` and `max`.
+    pub fn new(min: math::Vec2<Meters>, max: math::Vec2<Meters>) -> Rect {
+        Rect { min: min, max: max }
+    }
+
+    /// The rectangle's four corners, counter-clockwise.
+    pub fn corners(&self) -> [math::Vec3<Meters>; 4] {
+        let z = Meters(0.0);
+
+        [
+            vec3!(self.min.x, self.min.y, z),
+            vec3!(self.max.x, self.min.y, z),
+            vec3!(self.max.x, self.max.y, z),
+            vec3!(self.min.x, self.max.y, z),
+        ]
+    }
+}
+
+/// The orientation of a `Camera`, which decides the shape the ground takes on screen. The sprites
+/// drawn for a game are only right for one projection, so this should match the art.
+///
+/// A projection is given as XYZ Euler angles, in degrees: the camera starts out looking straight
+/// down, tilts `x` degrees away from the vertical about the x-axis, then `y` about the y-axis and
+/// `z` about the z-axis. The rotation matrix and its inverse are worked out once, up front.
+#[derive(Clone,Copy,Debug)]
+pub struct Projection {
+    angles:   (f32, f32, f32),
+    rotation: [[f32; 3]; 3],
+    inverse:  [[f32; 3]; 3],
+}
+
+impl Projection {
+    /// Orient the camera with the XYZ Euler angles `x`, `y` and `z`, in degrees.
+    pub fn from_euler(x: f32, y: f32, z: f32) -> Projection {
+        // These are the opposite of the Euler Angles used to orient the camera.
+        let x_rot: f32 = degrees_to_radians(-x);
+        let y_rot: f32 = degrees_to_radians(-y);
+        let z_rot: f32 = degrees_to_radians(-z);
+
+        let (s1, c1) = x_rot.sin_cos();
+        let (s2, c2) = y_rot.sin_cos();
+        let (s3, c3) = z_rot.sin_cos();
+
+        // This is the formula given by Wikipedia for turning XYZ Euler Angles into a 3D rotation
+        // matrix.
+        let rotation = [
+            [c2*c3,            -c2*s3,           s2],
+            [c1*s3 + c3*s1*s2, c1*c3 - s1*s2*s3, -c2*s1],
+            [s1*s3 - c1*c3*s2, c3*s1 + c1*s2*s3, c1*c2],
+        ];
+
+        // The inverse of a rotation is its transpose.
+        let mut inverse = [[0.0; 3]; 3];
+
+        for i in 0..3 {
+            for j in 0..3 {
+                inverse[i][j] = rotation[j][i];
+            }
+        }
+
+        Projection { angles: (x, y, z), rotation: rotation, inverse: inverse }
+    }
+
+    /// The classic dimetric projection of pixel art games, in which a square tile is drawn twice
+    /// as wide as it is tall. These are the angles 60° x, 0° y, 45° z.
+    pub fn dimetric() -> Projection {
+        Projection::from_euler(60.0, 0.0, 45.0)
+    }
+
+    /// True isometric projection, in which the three axes are foreshortened equally and a square
+    /// tile is drawn √3 times as wide as it is tall. These are the angles 54.74° x, 0° y, 45° z.
+    pub fn isometric() -> Projection {
+        let x = (1.0f32 / 3.0f32.sqrt()).acos().to_degrees();
+
+        Projection::from_euler(x, 0.0, 45.0)
+    }
+
+    /// Look straight down, with the x-axis to the right of the screen and the y-axis up it. Since
+    /// nothing is seen from the side, the height of things doesn't show.
+    pub fn top_down() -> Projection {
+        Projection::from_euler(0.0, 0.0, 0.0)
+    }
+
+    /// The projection, turned 45° like `dimetric`, in which a square tile is drawn `ratio` times
+    /// as wide as it is tall (2 for `dimetric`). The ratio must be at least 1, which is
+    /// `top_down` turned 45°.
+    pub fn for_tile_ratio(ratio: f32) -> Projection {
+        assert!(ratio >= 1.0, "tile ratio must be at least 1");
+
+        Projection::from_euler((1.0 / ratio).acos().to_degrees(), 0.0, 45.0)
+    }
+
+    /// The Euler angles this projection was made from, in degrees.
+    pub fn angles(&self) -> (f32, f32, f32) {
+        self.angles
+    }
+
+    /// The rotation from game space to camera space. Each row is one of the camera's axes,
+    /// expressed in game space.
+    #[inline]
+    pub fn rotation(&self) -> &[[f32; 3]; 3] {
+        &self.rotation
+    }
+
+    /// The rotation from camera space back to game space.
+    #[inline]
+    pub fn inverse(&self) -> &[[f32; 3]; 3] {
+        &self.inverse
+    }
+}
+
+/// What a `Camera` does when the shape of its `resolution` doesn't match the screen's.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Aspect {
+    /// Stretch the view to fill the screen, distorting everything in it.
+    Stretch,
+
+    /// Show exactly the view, as large as fits, with bars above and below (letterboxing) or to
+    /// either side (pillarboxing) to fill the rest of the screen.
+    Fit,
+
+    /// Show more of the world along whichever axis the screen is relatively longer in, so that the
+    /// view fills the screen without distortion.
+    Expand,
+}
+
+/// The part of the screen a `Camera` draws to, in device pixels from the bottom-left corner (the
+/// convention of `glViewport`).
+#[derive(Clone,Copy,Debug)]
+pub struct Viewport {
+    /// The left edge.
+    pub x: DevicePixels,
 
-    /// The object can be partially seen.
-    Partial,
+    /// The bottom edge.
+    pub y: DevicePixels,
 
-    /// The object is completely on camera.
-    Full
+    /// The width.
+    pub width: DevicePixels,
+
+    /// The height.
+    pub height: DevicePixels,
 }
 
 /// A camera for a world with an isometric orthogonal projection. The camera knows how to translate
@@ -54,9 +222,19 @@ pub struct Camera {
     /// The position of the camera in space.
     pub position: math::Vec3<Meters>,
 
-    // Note that the orientation of the camera is always the same, the euler angles are
-    //
-    //     60° x, 0° y, 45° z
+    /// If set, the region of the ground which the camera must not show past (see `set_bounds`).
+    pub bounds: Option<Rect>,
+
+    /// How to reconcile `resolution` with the shape of the screen.
+    pub aspect: Aspect,
+
+    /// A displacement of the view along the screen's axes, applied on top of `position` by every
+    /// conversion. This is where effects such as screen shake go (see `CameraEffects`), so that
+    /// they don't disturb whatever controls `position`.
+    pub offset: math::Vec2<Meters>,
+
+    /// The orientation of the camera (see `set_projection`).
+    pub projection: Projection,
 }
 
 impl Camera {
@@ -66,31 +244,13 @@ impl Camera {
     /// Both are measured in meters, since camera space is still "in the game world".
     #[inline]
     pub fn game_to_camera(&self, game: math::Vec3<Meters>) -> math::Vec3<Meters> {
-        // Here we count on LLVM to reduce a lot of this stuff. Hopefully computing sin_cos on
-        // constants is free, and it won't actually build the matrix below.
-
-        // These are the opposite of the Euler Angles used to orient the camera.
-        let x_rot: f32 = degrees_to_radians(-60.0f32);
-        let y_rot: f32 = degrees_to_radians( 00.0f32);
-        let z_rot: f32 = degrees_to_radians(-45.0f32);
-
-        let (s1, c1) = x_rot.sin_cos();
-        let (s2, c2) = y_rot.sin_cos();
-        let (s3, c3) = z_rot.sin_cos();
-
-        // This is the formula given by Wikipedia for turning XYZ Euler Angles into a 3D rotation
-        // matrix.
-        let m: [[f32; 3]; 3] = [
-            [c2*c3,            -c2*s3,           s2],
-            [c1*s3 + c3*s1*s2, c1*c3 - s1*s2*s3, -c2*s1],
-            [s1*s3 - c1*c3*s2, c3*s1 + c1*s2*s3, c1*c2],
-        ];
+        let m = self.projection.rotation();
 
         let tr = game - self.position;
 
         math::Vec3 {
-            x: tr.x*Meters(m[0][0]) + tr.y*Meters(m[0][1]) + tr.z*Meters(m[0][2]),
-            y: tr.x*Meters(m[1][0]) + tr.y*Meters(m[1][1]) + tr.z*Meters(m[1][2]),
+            x: tr.x*Meters(m[0][0]) + tr.y*Meters(m[0][1]) + tr.z*Meters(m[0][2]) - self.offset.x,
+            y: tr.x*Meters(m[1][0]) + tr.y*Meters(m[1][1]) + tr.z*Meters(m[1][2]) - self.offset.y,
             z: tr.x*Meters(m[2][0]) + tr.y*Meters(m[2][1]) + tr.z*Meters(m[2][2]),
         }
     }
@@ -106,48 +266,781 @@ impl Camera {
         (vec2!(x_px, y_px), -cam.z)
     }
 
+    /// Find the point in the game world at height `z` which appears at screen coordinate `scr`
+    /// (measured from the center of the screen, as returned by `camera_to_screen`). This is the
+    /// inverse of `camera_to_screen(game_to_camera(..))`, up to the rounding to whole pixels.
+    pub fn screen_to_game(&self, scr: math::Vec2<Pixels>, z: Meters) -> math::Vec3<Meters> {
+        let inv = self.projection.inverse();
+
+        let x = Meters(scr.x.0 / self.scale) + self.offset.x;
+        let y = Meters(scr.y.0 / self.scale) + self.offset.y;
+
+        // Choose the depth which lands on the plane at height `z`. (There isn't one if the camera
+        // looks along the ground.)
+        let depth = (z - self.position.z - x*Meters(inv[2][0]) - y*Meters(inv[2][1]))
+                  / Meters(inv[2][2]);
+
+        self.position + math::Vec3 {
+            x: x*Meters(inv[0][0]) + y*Meters(inv[0][1]) + depth*Meters(inv[0][2]),
+            y: x*Meters(inv[1][0]) + y*Meters(inv[1][1]) + depth*Meters(inv[1][2]),
+            z: x*Meters(inv[2][0]) + y*Meters(inv[2][1]) + depth*Meters(inv[2][2]),
+        }
+    }
+
+    /// Create a camera at `position`, with no bounds, which stretches its view to fill the screen
+    /// and has the classic dimetric projection.
+    pub fn new(scale: f32, resolution: math::Vec2<Pixels>,
+               true_resolution: math::Vec2<DevicePixels>, position: math::Vec3<Meters>) -> Camera {
+        Camera {
+            scale:           scale,
+            resolution:      resolution,
+            true_resolution: true_resolution,
+            position:        position,
+            bounds:          None,
+            aspect:          Aspect::Stretch,
+            offset:          vec2!(Meters ; 0.0, 0.0),
+            projection:      Projection::dimetric(),
+        }
+    }
+
+    /// Restore a camera from its FlatBuffer representation. The camera's `offset` is left at zero,
+    /// since effects such as shake aren't part of what's saved.
+    ///
+    /// The `true_resolution` is the one the camera was saved with; when restoring on a different
+    /// screen (a spectator joining, say), set it afterwards and `Zoom::apply` again.
+    pub fn from_wire(w: &wire::Camera) -> Result<Camera, Error> {
+        let floats = [
+            w.scale(), w.res_x(), w.res_y(), w.true_res_x(), w.true_res_y(),
+            w.pos_x(), w.pos_y(), w.pos_z(), w.angle_x(), w.angle_y(), w.angle_z(),
+            w.bounds_min_x(), w.bounds_min_y(), w.bounds_max_x(), w.bounds_max_y(),
+        ];
+
+        if floats.iter().any(|f| !f.is_finite()) {
+            return Err(Error::NotFinite);
+        }
+
+        if w.scale() <= 0.0 || w.res_x() <= 0.0 || w.res_y() <= 0.0 {
+            return Err(Error::OutOfRange);
+        }
+
+        let aspect = match w.aspect() {
+            0 => Aspect::Stretch,
+            1 => Aspect::Fit,
+            2 => Aspect::Expand,
+            n => return Err(Error::UnknownAspect(n)),
+        };
+
+        let bounds = if w.has_bounds() {
+            Some(Rect::new(vec2!(Meters ; w.bounds_min_x(), w.bounds_min_y()),
+                           vec2!(Meters ; w.bounds_max_x(), w.bounds_max_y())))
+        } else {
+            None
+        };
+
+        Ok(Camera {
+            scale:           w.scale(),
+            resolution:      vec2!(Pixels ; w.res_x(), w.res_y()),
+            true_resolution: vec2!(DevicePixels ; w.true_res_x(), w.true_res_y()),
+            position:        vec3!(Meters ; w.pos_x(), w.pos_y(), w.pos_z()),
+            bounds:          bounds,
+            aspect:          aspect,
+            offset:          vec2!(Meters ; 0.0, 0.0),
+            projection:      Projection::from_euler(w.angle_x(), w.angle_y(), w.angle_z()),
+        })
+    }
+
+    /// Convert to FlatBuffer representation, for saved games, replays and handing a view over to
+    /// a spectator. The `offset` is left out, so what's saved is the position without any shake.
+    pub fn to_wire(&self) -> wire::Camera {
+        let (ax, ay, az) = self.projection.angles();
+
+        let (bounds, has_bounds) = match self.bounds {
+            Some(r) => (r, true),
+            None    => (Rect::new(vec2!(Meters ; 0.0, 0.0), vec2!(Meters ; 0.0, 0.0)), false),
+        };
+
+        let aspect = match self.aspect {
+            Aspect::Stretch => 0,
+            Aspect::Fit     => 1,
+            Aspect::Expand  => 2,
+        };
+
+        wire::Camera::new(self.scale,
+                          self.resolution.x.0, self.resolution.y.0,
+                          self.true_resolution.x.0, self.true_resolution.y.0,
+                          self.position.x.0, self.position.y.0, self.position.z.0,
+                          ax, ay, az,
+                          bounds.min.x.0, bounds.min.y.0, bounds.max.x.0, bounds.max.y.0,
+                          has_bounds, aspect)
+    }
+
+    /// Move the camera `dx` to the right and `dy` up, as seen on screen. Things under the camera
+    /// move `dx` and `dy` the other way in camera space. The camera stays within its bounds.
+    pub fn pan(&mut self, dx: Meters, dy: Meters) {
+        self.translate(dx, dy);
+        self.clamp_to_bounds();
+    }
+
+    /// Change the camera's orientation, keeping it over the same spot on the ground and within its
+    /// bounds.
+    pub fn set_projection(&mut self, projection: Projection) {
+        let height = self.position.z;
+
+        // The line of sight of each projection, in game space.
+        let old = vec3!(Meters ; self.projection.rotation()[2][0], self.projection.rotation()[2][1],
+                        self.projection.rotation()[2][2]);
+        let new = vec3!(Meters ; projection.rotation()[2][0], projection.rotation()[2][1],
+                        projection.rotation()[2][2]);
+
+        self.projection = projection;
+
+        // Find the spot on the ground in the middle of the view, then back away from it along the
+        // new line of sight to the same height. A camera looking along the ground stays put.
+        if old.z.0 != 0.0 && new.z.0 != 0.0 {
+            let spot = self.position - old.scaled(Meters(height.0 / old.z.0));
+
+            self.position = spot + new.scaled(Meters(height.0 / new.z.0));
+        }
+
+        self.clamp_to_bounds();
+    }
+
+    /// Keep the camera from showing any of the ground outside `bounds`, or let it go anywhere with
+    /// `None`. The camera is moved inside the new bounds straight away.
+    pub fn set_bounds(&mut self, bounds: Option<Rect>) {
+        self.bounds = bounds;
+        self.clamp_to_bounds();
+    }
+
+    /// Move the camera by as little as possible so that everything it shows of the ground is
+    /// within its bounds. This needs calling after changing `position`, `resolution` or
+    /// `projection` directly; `pan`, `set_bounds`, `set_projection` and `Zoom::apply` do it
+    /// themselves.
+    ///
+    /// The bounds appear on screen as a parallelogram (a diamond, for the usual projections), so
+    /// the camera is kept to the smaller parallelogram in which the whole screen fits. If the
+    /// screen is too big to fit at all, the camera is centered on the bounds instead.
+    pub fn clamp_to_bounds(&mut self) {
+        let (dx, dy) = self.bounds_shift((0.0, 0.0));
+        self.translate(Meters(dx), Meters(dy));
+    }
+
+    // How far the view, if it were centered `center` away from `position` along the screen's
+    // axes, would have to move to stay within the bounds.
+    fn bounds_shift(&self, center: (f32, f32)) -> (f32, f32) {
+        let bounds = match self.bounds { Some(b) => b, None => return (0.0, 0.0) };
+
+        let view = self.view_resolution();
+        let hw   = view.x.0 / (2.0 * self.scale);
+        let hh   = view.y.0 / (2.0 * self.scale);
+
+        // The corners of the bounds in camera space, relative to the middle of the view.
+        let mut pts = [(0.0f32, 0.0f32); 4];
+
+        for (pt, &corner) in pts.iter_mut().zip(bounds.corners().iter()) {
+            let cam = self.game_to_camera(corner);
+            *pt = (cam.x.0 + self.offset.x.0 - center.0, cam.y.0 + self.offset.y.0 - center.1);
+        }
+
+        // The edges as half-planes `n . c >= d`, moved inward so that a screen centered on `c`
+        // fits.
+        let mut edges = [((0.0f32, 0.0f32), 0.0f32); 4];
+
+        let area = (0..4).fold(0.0, |acc, i| {
+            let (a, b) = (pts[i], pts[(i + 1) % 4]);
+            acc + a.0 * b.1 - b.0 * a.1
+        });
+
+        let wind = if area < 0.0 { -1.0 } else { 1.0 };
+
+        for i in 0..4 {
+            let (a, b) = (pts[i], pts[(i + 1) % 4]);
+
+            let n   = (-(b.1 - a.1) * wind, (b.0 - a.0) * wind);
+            let len = (n.0 * n.0 + n.1 * n.1).sqrt();
+
+            if len == 0.0 {
+                return (0.0, 0.0)
+            }
+
+            let n = (n.0 / len, n.1 / len);
+
+            edges[i] = (n, n.0 * a.0 + n.1 * a.1 + hw * n.0.abs() + hh * n.1.abs());
+        }
+
+        let inside = |c: (f32, f32)| {
+            edges.iter().all(|&(n, d)| n.0 * c.0 + n.1 * c.1 >= d - 1e-4)
+        };
+
+        // The nearest point to the middle of the screen which satisfies every edge is either the
+        // middle itself, on one edge, or at a corner where two edges meet.
+        let mut candidates = vec![(0.0, 0.0)];
+
+        for &(n, d) in edges.iter() {
+            candidates.push((n.0 * d, n.1 * d));
+        }
+
+        for i in 0..4 {
+            for j in (i + 1)..4 {
+                let ((n1, d1), (n2, d2)) = (edges[i], edges[j]);
+                let det = n1.0 * n2.1 - n1.1 * n2.0;
+
+                if det.abs() > 1e-6 {
+                    candidates.push(((d1 * n2.1 - d2 * n1.1) / det, (n1.0 * d2 - n2.0 * d1) / det));
+                }
+            }
+        }
+
+        let best = candidates.into_iter()
+            .filter(|&c| inside(c))
+            .fold(None, |best: Option<(f32, f32)>, c| match best {
+                Some(b) if b.0 * b.0 + b.1 * b.1 <= c.0 * c.0 + c.1 * c.1 => Some(b),
+                _                                                       => Some(c),
+            });
+
+        match best {
+            Some(c) => c,
+            None    => {
+                let sum = pts.iter().fold((0.0, 0.0), |acc, p| (acc.0 + p.0, acc.1 + p.1));
+                (sum.0 / 4.0, sum.1 / 4.0)
+            },
+        }
+    }
+
+    // Move the camera along the screen's axes, ignoring its bounds.
+    fn translate(&mut self, dx: Meters, dy: Meters) {
+        let inv = self.projection.inverse();
+
+        self.position = self.position + math::Vec3 {
+            x: dx*Meters(inv[0][0]) + dy*Meters(inv[0][1]),
+            y: dx*Meters(inv[1][0]) + dy*Meters(inv[1][1]),
+            z: dx*Meters(inv[2][0]) + dy*Meters(inv[2][1]),
+        };
+    }
+
     /// Convert a game-screen coordinate to NDU.
     #[inline]
     pub fn screen_to_ndu(&self, scr: math::Vec2<Pixels>) -> math::Vec2<NDU> {
-        let x_ndu = NDU(scr.x.0 / (self.resolution.x.0 / 2.0));
-        let y_ndu = NDU(scr.y.0 / (self.resolution.y.0 / 2.0));
+        let view = self.view_resolution();
+
+        let x_ndu = NDU(scr.x.0 / (view.x.0 / 2.0));
+        let y_ndu = NDU(scr.y.0 / (view.y.0 / 2.0));
 
         vec2!(x_ndu, y_ndu)
+    }
 
-        // TODO: Check to see if the aspect ratio of self.resolution differs from
-        // self.true_resolution and adjust the result accordingly.
+    /// The number of the camera's pixels which are actually shown. This is `resolution`, grown
+    /// along one axis to match the shape of the screen if the aspect policy is `Expand`.
+    pub fn view_resolution(&self) -> math::Vec2<Pixels> {
+        let (rx, ry) = (self.resolution.x.0, self.resolution.y.0);
+        let (tx, ty) = (self.true_resolution.x.0, self.true_resolution.y.0);
+
+        if self.aspect != Aspect::Expand || rx <= 0.0 || ry <= 0.0 || tx <= 0.0 || ty <= 0.0 {
+            return self.resolution
+        }
+
+        if tx / ty > rx / ry {
+            vec2!(Pixels(ry * tx / ty), Pixels(ry))
+        } else {
+            vec2!(Pixels(rx), Pixels(rx * ty / tx))
+        }
     }
 
-    fn point_visible(&self, v: math::Vec3<Meters>) -> bool {
-        let cam = self.game_to_camera(v);
-        let scr = self.camera_to_screen(cam).0;
-        let ndu = self.screen_to_ndu(scr);
+    /// The region of the screen to draw to. This is the whole screen unless the aspect policy is
+    /// `Fit`, in which case it's the largest centered rectangle with the shape of `resolution`.
+    /// NDU produced by `screen_to_ndu` are relative to this viewport.
+    pub fn viewport(&self) -> Viewport {
+        let (tx, ty) = (self.true_resolution.x.0, self.true_resolution.y.0);
+        let (rx, ry) = (self.resolution.x.0, self.resolution.y.0);
 
-        ndu.x > NDU(-1.0) && ndu.x < NDU(1.0) && ndu.y > NDU(-1.0) && ndu.y < NDU(1.0)
+        let (w, h) = if self.aspect != Aspect::Fit || rx <= 0.0 || ry <= 0.0 || ty <= 0.0 {
+            (tx, ty)
+        } else if tx / ty > rx / ry {
+            (ty * rx / ry, ty)
+        } else {
+            (tx, tx * ry / rx)
+        };
+
+        // Round to whole device pixels, so that the bars are crisp.
+        let (w, h) = (w.round(), h.round());
+
+        Viewport {
+            x:      DevicePixels(((tx - w) / 2.0).floor()),
+            y:      DevicePixels(((ty - h) / 2.0).floor()),
+            width:  DevicePixels(w),
+            height: DevicePixels(h),
+        }
+    }
+
+    /// Convert a position on the screen in device pixels from the top-left corner (as mouse
+    /// positions are reported) to a screen coordinate, as used by `screen_to_game`. Returns `None`
+    /// for positions in the bars around a letterboxed or pillarboxed view.
+    pub fn device_to_screen(&self, dev: math::Vec2<DevicePixels>) -> Option<math::Vec2<Pixels>> {
+        let vp   = self.viewport();
+        let view = self.view_resolution();
+
+        let x = dev.x.0 - vp.x.0;
+        let y = (self.true_resolution.y.0 - dev.y.0) - vp.y.0;
+
+        if x < 0.0 || y < 0.0 || x > vp.width.0 || y > vp.height.0 {
+            return None
+        }
+
+        Some(vec2!(Pixels((x / vp.width.0 - 0.5) * view.x.0),
+                   Pixels((y / vp.height.0 - 0.5) * view.y.0)))
     }
 
-    /// Return true if `bbox` can be seen by the camera.
+    /// The region of the game world which the camera can see. Since the projection is
+    /// orthographic this is a box, which is unbounded in depth.
+    pub fn frustum(&self) -> math::Frustum {
+        let m   = self.projection.rotation();
+        let row = |i: usize| vec3!(Meters ; m[i][0], m[i][1], m[i][2]);
+
+        let center = self.position + row(0).scaled(self.offset.x) + row(1).scaled(self.offset.y);
+        let view   = self.view_resolution();
+
+        math::Frustum::orthographic(center, row(0), row(1), -row(2),
+                                    Meters(view.x.0 / (2.0 * self.scale)),
+                                    Meters(view.y.0 / (2.0 * self.scale)),
+                                    None)
+    }
+
+    /// Determine how much of `bbox` can be seen by the camera.
     pub fn visible(&self, bbox: math::BoundingCube) -> Visibility {
-        // TODO: maybe there is a more efficient way to do this?
-
-        let a = bbox.center + math::SX.as_vector().scaled(bbox.half_edge * Meters(2.0));
-        let b = bbox.center + math::SXY.as_vector().scaled(bbox.half_edge * Meters(2.0));
-        let c = bbox.center + math::SYZ.as_vector().scaled(bbox.half_edge * Meters(2.0));
-        let d = bbox.center + math::SZ.as_vector().scaled(bbox.half_edge * Meters(2.0));
-
-        let mut hit    = false;
-        let mut missed = false;
-
-        if self.point_visible(a) { hit = true } else { missed = true }
-        if self.point_visible(b) { hit = true } else { missed = true }
-        if self.point_visible(c) { hit = true } else { missed = true }
-        if self.point_visible(d) { hit = true } else { missed = true }
-
-        match (hit, missed) {
-            (false, true)  => Visibility::Zero,
-            (true,  true)  => Visibility::Partial,
-            (true,  false) => Visibility::Full,
-            _              => unreachable!(),
+        self.frustum().intersects(&bbox)
+    }
+}
+
+/// Manages a `Camera`'s zoom level: the number of device pixels which make up one of the
+/// camera's pixels. A level of 2 shows everything twice as large as a level of 1.
+///
+/// Zooming changes the camera's `resolution` rather than its `scale`, so sprites are still
+/// quantized to the same pixel grid, and `screen_to_ndu` keeps mapping the viewport onto the whole
+/// screen. Changes of level can be eased in over a period of time; the level moves geometrically,
+/// so that each doubling takes as long as the last.
+pub struct Zoom {
+    min: f32,
+    max: f32,
+
+    snap: bool,
+
+    // The current transition, from `from` at `t_start` to `to` after `duration`.
+    from:     f32,
+    to:       f32,
+    t_start:  time::Duration,
+    duration: time::Duration,
+
+    // The level as of the last `update`.
+    current: f32,
+}
+
+impl Zoom {
+    /// Create a zoom which starts at `level`, clamped to the default limits.
+    pub fn new(level: f32) -> Zoom {
+        let level = level.max(DEFAULT_MIN_ZOOM).min(DEFAULT_MAX_ZOOM);
+
+        Zoom {
+            min:      DEFAULT_MIN_ZOOM,
+            max:      DEFAULT_MAX_ZOOM,
+            snap:     false,
+            from:     level,
+            to:       level,
+            t_start:  time::Duration::usec(0),
+            duration: time::Duration::usec(0),
+            current:  level,
+        }
+    }
+
+    /// Restore a zoom from its FlatBuffer representation. The zoom comes back at rest at the saved
+    /// level; a transition that was under way is not resumed.
+    pub fn from_wire(w: &wire::Zoom) -> Result<Zoom, Error> {
+        let (min, max, level) = (w.min(), w.max(), w.level());
+
+        if !min.is_finite() || !max.is_finite() || !level.is_finite() {
+            return Err(Error::NotFinite);
+        }
+
+        if !(0.0 < min && min <= max && min <= level && level <= max) {
+            return Err(Error::OutOfRange);
+        }
+
+        let mut zoom = Zoom::new(level);
+
+        zoom.set_limits(min, max);
+        zoom.set_snap(w.snap());
+        zoom.set(level);
+
+        Ok(zoom)
+    }
+
+    /// Convert to FlatBuffer representation. A zoom that's moving is saved at its current level,
+    /// not its target.
+    pub fn to_wire(&self) -> wire::Zoom {
+        wire::Zoom::new(self.min, self.max, self.current, self.snap)
+    }
+
+    /// Limit the zoom level to between `min` and `max` (which must be positive). If the level is
+    /// outside the new limits it jumps inside them.
+    pub fn set_limits(&mut self, min: f32, max: f32) {
+        assert!(0.0 < min && min <= max, "invalid zoom limits");
+
+        self.min = min;
+        self.max = max;
+
+        let level = self.clamp(self.current);
+        let to    = self.clamp(self.to);
+
+        self.from    = level;
+        self.current = level;
+        self.to      = to;
+    }
+
+    /// The smallest and largest zoom levels allowed.
+    pub fn limits(&self) -> (f32, f32) {
+        (self.min, self.max)
+    }
+
+    /// Whether to snap the level, once it comes to rest, so that a meter is a whole number of
+    /// device pixels. This keeps pixel art crisp, at the cost of not reaching every level exactly.
+    pub fn set_snap(&mut self, snap: bool) {
+        self.snap = snap;
+    }
+
+    /// The zoom level as of the last `update`.
+    pub fn level(&self) -> f32 {
+        self.current
+    }
+
+    /// The level the zoom is moving towards (or resting at).
+    pub fn target(&self) -> f32 {
+        self.to
+    }
+
+    /// True if a transition started by `zoom_to` hasn't finished yet.
+    pub fn is_moving(&self) -> bool {
+        self.current != self.to
+    }
+
+    /// Jump straight to `level`, cancelling any transition.
+    pub fn set(&mut self, level: f32) {
+        let level = self.clamp(level);
+
+        self.from     = level;
+        self.to       = level;
+        self.current  = level;
+        self.duration = time::Duration::usec(0);
+    }
+
+    /// Move from the current level to `level` over `duration`, starting at `now`.
+    pub fn zoom_to(&mut self, level: f32, now: time::Duration, duration: time::Duration) {
+        self.update(now);
+
+        self.from     = self.current;
+        self.to       = self.clamp(level);
+        self.t_start  = now;
+        self.duration = duration;
+
+        self.update(now);
+    }
+
+    /// Multiply the target level by `factor`, over `duration`. Repeated calls compound even while
+    /// the zoom is still moving.
+    pub fn zoom_by(&mut self, factor: f32, now: time::Duration, duration: time::Duration) {
+        let to = self.to * factor;
+        self.zoom_to(to, now, duration);
+    }
+
+    /// Zoom in by one `ZOOM_STEP`.
+    pub fn zoom_in(&mut self, now: time::Duration, duration: time::Duration) {
+        self.zoom_by(ZOOM_STEP, now, duration);
+    }
+
+    /// Zoom out by one `ZOOM_STEP`.
+    pub fn zoom_out(&mut self, now: time::Duration, duration: time::Duration) {
+        self.zoom_by(1.0 / ZOOM_STEP, now, duration);
+    }
+
+    /// Advance the current transition to `now`.
+    pub fn update(&mut self, now: time::Duration) {
+        let t = if now < self.t_start || self.duration.as_usec() == 0 {
+            1.0
+        } else {
+            ((now - self.t_start) / self.duration).min(1.0) as f32
+        };
+
+        self.current = if t >= 1.0 {
+            self.to
+        } else {
+            self.from * (self.to / self.from).powf(t)
+        };
+    }
+
+    /// Set `cam.resolution` from its `true_resolution` for the current level, then keep the camera
+    /// within its bounds. When snapping is on and the zoom is at rest, the level used is the
+    /// nearest one at which a meter (`cam.scale` pixels) is a whole number of device pixels.
+    pub fn apply(&self, cam: &mut Camera) {
+        let level = if self.snap && !self.is_moving() {
+            self.snapped(cam.scale)
+        } else {
+            self.current
+        };
+
+        cam.resolution = vec2!(Pixels(cam.true_resolution.x.0 / level),
+                               Pixels(cam.true_resolution.y.0 / level));
+
+        cam.clamp_to_bounds();
+    }
+
+    fn clamp(&self, level: f32) -> f32 {
+        level.max(self.min).min(self.max)
+    }
+
+    // The level nearest the current one at which `scale * level` is whole, within the limits if
+    // any such level is.
+    fn snapped(&self, scale: f32) -> f32 {
+        let px = scale * self.current;
+
+        let nearest = px.round().max(1.0);
+        let lo      = (scale * self.min).ceil().max(1.0);
+        let hi      = (scale * self.max).floor();
+
+        if lo > hi {
+            return self.current;
+        }
+
+        nearest.max(lo).min(hi) / scale
+    }
+}
+
+// A shake started by `CameraEffects::shake`.
+struct Shake {
+    amplitude: Meters,
+    frequency: f32,
+    t_start:   time::Duration,
+    duration:  time::Duration,
+
+    // Phases for each axis, so that simultaneous shakes don't move in lockstep.
+    phase: (f32, f32),
+}
+
+// An offset started by `CameraEffects::kick`.
+struct Kick {
+    offset:   math::Vec2<Meters>,
+    t_start:  time::Duration,
+    duration: time::Duration,
+}
+
+// How far through the span starting at `t_start` and lasting `duration` `now` is, from 0 to 1.
+fn progress(now: time::Duration, t_start: time::Duration, duration: time::Duration) -> f32 {
+    if now < t_start {
+        0.0
+    } else if duration.as_usec() == 0 {
+        1.0
+    } else {
+        ((now - t_start) / duration).min(1.0) as f32
+    }
+}
+
+/// Effects which displace the view without moving the camera: screen shake, kicks which spring
+/// back (e.g. recoil), and a scripted offset (e.g. to frame a cutscene). They're summed into
+/// `Camera::offset` by `apply`, after whatever moves the camera's `position` (such as
+/// `CameraFollow`) has run, so they neither fight it nor accumulate into it.
+pub struct CameraEffects {
+    shakes: Vec<Shake>,
+    kicks:  Vec<Kick>,
+
+    scripted: math::Vec2<Meters>,
+
+    // The sum of every effect as of the last `update`.
+    offset: math::Vec2<Meters>,
+
+    // The number of shakes started, which picks each one's phases.
+    started: u32,
+}
+
+impl CameraEffects {
+    /// Create a set of effects which doesn't displace the view.
+    pub fn new() -> CameraEffects {
+        CameraEffects {
+            shakes:   vec![],
+            kicks:    vec![],
+            scripted: vec2!(Meters ; 0.0, 0.0),
+            offset:   vec2!(Meters ; 0.0, 0.0),
+            started:  0,
+        }
+    }
+
+    /// Shake the view by up to `amplitude` in each direction, `frequency` times per second,
+    /// starting at `now`. The shake dies away over `duration`. Shakes add up, so a second
+    /// explosion makes the screen shake harder.
+    pub fn shake(&mut self, amplitude: Meters, frequency: f32, now: time::Duration,
+                 duration: time::Duration) {
+
+        // Spread the phases out by the golden angle.
+        let n     = self.started as f32;
+        let phase = ((n * 2.39996) % 6.28318, (n * 2.39996 + 1.5708) % 6.28318);
+
+        self.started = self.started.wrapping_add(1);
+
+        self.shakes.push(Shake {
+            amplitude: amplitude,
+            frequency: frequency,
+            t_start:   now,
+            duration:  duration,
+            phase:     phase,
+        });
+    }
+
+    /// Displace the view by `offset` at `now`, easing back to nothing over `duration`.
+    pub fn kick(&mut self, offset: math::Vec2<Meters>, now: time::Duration,
+                duration: time::Duration) {
+
+        self.kicks.push(Kick { offset: offset, t_start: now, duration: duration });
+    }
+
+    /// Displace the view by `offset` until it's changed again.
+    pub fn set_offset(&mut self, offset: math::Vec2<Meters>) {
+        self.scripted = offset;
+    }
+
+    /// Stop every shake and kick. The scripted offset stays.
+    pub fn clear(&mut self) {
+        self.shakes.clear();
+        self.kicks.clear();
+    }
+
+    /// The total displacement as of the last `update`.
+    pub fn offset(&self) -> math::Vec2<Meters> {
+        self.offset
+    }
+
+    /// Work out the displacement at `now`, and drop effects which have finished.
+    pub fn update(&mut self, now: time::Duration) {
+        self.shakes.retain(|s| progress(now, s.t_start, s.duration) < 1.0);
+        self.kicks.retain(|k| progress(now, k.t_start, k.duration) < 1.0);
+
+        let mut x = self.scripted.x.0;
+        let mut y = self.scripted.y.0;
+
+        for s in self.shakes.iter() {
+            let t     = progress(now, s.t_start, s.duration);
+            let decay = (1.0 - t) * (1.0 - t);
+            let secs  = if now > s.t_start { (now - s.t_start).as_usec() as f32 } else { 0.0 };
+            let angle = secs / 1e6 * s.frequency * 6.28318;
+
+            x += s.amplitude.0 * decay * (angle + s.phase.0).sin();
+            y += s.amplitude.0 * decay * (angle * 1.3 + s.phase.1).sin();
+        }
+
+        for k in self.kicks.iter() {
+            let left = 1.0 - progress(now, k.t_start, k.duration);
+
+            x += k.offset.x.0 * left * left;
+            y += k.offset.y.0 * left * left;
+        }
+
+        self.offset = vec2!(Meters(x), Meters(y));
+    }
+
+    /// Set `cam.offset` to the current displacement, reduced if necessary so that the view stays
+    /// within the camera's bounds. This should come after anything else which moves or zooms the
+    /// camera in the frame.
+    pub fn apply(&self, cam: &mut Camera) {
+        let (dx, dy) = cam.bounds_shift((self.offset.x.0, self.offset.y.0));
+
+        cam.offset = vec2!(self.offset.x + Meters(dx), self.offset.y + Meters(dy));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32;
+
+    use grafix::camera::{Aspect, Camera, Error, Projection, Rect, Zoom};
+    use grafix::wire;
+    use units::*;
+
+    #[test]
+    fn camera_round_trip() {
+        let mut cam = Camera::new(32.0, vec2!(Pixels ; 320.0, 240.0),
+                                  vec2!(DevicePixels ; 1280.0, 960.0),
+                                  vec3!(Meters ; 4.0, -2.0, 1.5));
+
+        cam.bounds     = Some(Rect::new(vec2!(Meters ; -10.0, -5.0), vec2!(Meters ; 10.0, 5.0)));
+        cam.aspect     = Aspect::Fit;
+        cam.offset     = vec2!(Meters ; 0.25, 0.25);
+        cam.projection = Projection::isometric();
+
+        let back = Camera::from_wire(&cam.to_wire()).unwrap();
+
+        assert_eq!(back.scale, 32.0);
+        assert_eq!((back.resolution.x.0, back.resolution.y.0), (320.0, 240.0));
+        assert_eq!((back.true_resolution.x.0, back.true_resolution.y.0), (1280.0, 960.0));
+        assert_eq!((back.position.x.0, back.position.y.0, back.position.z.0), (4.0, -2.0, 1.5));
+        assert_eq!(back.aspect, Aspect::Fit);
+        assert_eq!(back.projection.angles(), cam.projection.angles());
+
+        // Effects aren't saved.
+        assert_eq!((back.offset.x.0, back.offset.y.0), (0.0, 0.0));
+
+        let bounds = back.bounds.unwrap();
+
+        assert_eq!((bounds.min.x.0, bounds.min.y.0), (-10.0, -5.0));
+        assert_eq!((bounds.max.x.0, bounds.max.y.0), (10.0, 5.0));
+
+        cam.bounds = None;
+
+        assert!(Camera::from_wire(&cam.to_wire()).unwrap().bounds.is_none());
+    }
+
+    fn camera(scale: f32, res_x: f32, pos_x: f32, aspect: u8) -> wire::Camera {
+        wire::Camera::new(scale, res_x, 240.0, 1280.0, 960.0, pos_x, 0.0, 0.0, 60.0, 0.0, 45.0,
+                          0.0, 0.0, 0.0, 0.0, false, aspect)
+    }
+
+    #[test]
+    fn camera_invalid() {
+        let cases = [
+            (camera(f32::NAN, 320.0, 0.0, 0),       Error::NotFinite),
+            (camera(32.0, 320.0, f32::INFINITY, 0), Error::NotFinite),
+            (camera(0.0, 320.0, 0.0, 0),            Error::OutOfRange),
+            (camera(32.0, -320.0, 0.0, 0),          Error::OutOfRange),
+            (camera(32.0, 320.0, 0.0, 3),           Error::UnknownAspect(3)),
+        ];
+
+        for &(ref w, err) in cases.iter() {
+            assert_eq!(Camera::from_wire(w).err(), Some(err));
+        }
+    }
+
+    #[test]
+    fn zoom_round_trip() {
+        let mut zoom = Zoom::new(2.0);
+
+        zoom.set_limits(0.5, 4.0);
+        zoom.set_snap(true);
+
+        let back = Zoom::from_wire(&zoom.to_wire()).unwrap();
+
+        assert_eq!(back.limits(), (0.5, 4.0));
+        assert_eq!(back.level(), 2.0);
+        assert_eq!(back.target(), 2.0);
+        assert!(back.snap);
+        assert!(!back.is_moving());
+    }
+
+    #[test]
+    fn zoom_invalid() {
+        let cases = [
+            (wire::Zoom::new(f32::NAN, 4.0, 1.0, false),      Error::NotFinite),
+            (wire::Zoom::new(0.5, 4.0, f32::INFINITY, false), Error::NotFinite),
+            (wire::Zoom::new(0.0, 4.0, 1.0, false),           Error::OutOfRange),
+            (wire::Zoom::new(4.0, 0.5, 1.0, false),           Error::OutOfRange),
+            (wire::Zoom::new(0.5, 4.0, 8.0, false),           Error::OutOfRange),
+        ];
+
+        for &(ref w, err) in cases.iter() {
+            assert_eq!(Zoom::from_wire(w).err(), Some(err));
         }
     }
 }
@@ -0,0 +1,207 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Wireframe overlays for debugging. A `Lines` collects line segments in game space (such as the
+//! edges of every node in an octree), projects them through a `Camera`, and a `LineRenderer` draws
+//! them on top of everything else.
+
+/// A debug page showing which sprite sheets are resident, and commands to unload them.
+pub mod textures;
+
+use std::mem;
+
+use gl::types::*;
+use gl;
+
+use grafix::camera::Camera;
+use grafix::device::Recreate;
+use grafix::opengl;
+use grafix::shader;
+use grafix::sprite::{self, Error};
+use logging;
+use math::{self, BoundingCube};
+use scene::LooseOctree;
+use trace;
+use units::*;
+
+/// The most vertices a `LineRenderer` can draw at once. Lines beyond this are dropped.
+pub const MAX_LINE_VERTICES: usize = 1 << 16;
+
+/// An RGBA color, with each channel in `[0, 1]`.
+pub type Color = [f32; 4];
+
+/// The color of the nodes at each depth of an octree drawn by `Lines::octree`, cycling for deeper
+/// trees.
+pub const DEPTH_COLORS: [Color; 6] = [
+    [1.0, 1.0, 1.0, 1.0],
+    [1.0, 0.3, 0.3, 1.0],
+    [1.0, 0.8, 0.2, 1.0],
+    [0.3, 1.0, 0.3, 1.0],
+    [0.3, 0.8, 1.0, 1.0],
+    [0.8, 0.4, 1.0, 1.0],
+];
+
+/// One end of a line, as sent to the GPU.
+#[derive(Debug,Copy,Clone)]
+#[repr(C)]
+pub struct LineVertex {
+    /// The position on the screen.
+    pub position: math::Vec2<NDU>,
+
+    /// The color of the line at this end.
+    pub color: Color,
+}
+
+/// A set of line segments to be drawn by a `LineRenderer`. These are projected to the screen as
+/// they're added, so the camera shouldn't move between adding them and drawing them.
+pub struct Lines {
+    verts: Vec<LineVertex>,
+}
+
+impl Lines {
+    /// Create an empty set of lines.
+    pub fn new() -> Lines {
+        Lines { verts: vec![] }
+    }
+
+    /// Remove every line, e.g. at the start of a frame.
+    pub fn clear(&mut self) {
+        self.verts.clear();
+    }
+
+    /// The vertices of the lines, two per segment.
+    pub fn vertices(&self) -> &[LineVertex] {
+        &self.verts
+    }
+
+    /// Add a line segment between two points in game space.
+    pub fn line(&mut self, cam: &Camera, a: math::Vec3<Meters>, b: math::Vec3<Meters>,
+                color: Color) {
+        for &p in [a, b].iter() {
+            let (scr, _) = cam.camera_to_screen(cam.game_to_camera(p));
+
+            self.verts.push(LineVertex { position: cam.screen_to_ndu(scr), color: color });
+        }
+    }
+
+    /// Add the 12 edges of `bcube`.
+    pub fn cube(&mut self, cam: &Camera, bcube: &BoundingCube, color: Color) {
+        let h = bcube.half_edge;
+
+        let corner = |i: usize| bcube.center + vec3!(
+            if i & 1 == 0 { -h } else { h },
+            if i & 2 == 0 { -h } else { h },
+            if i & 4 == 0 { -h } else { h }
+        );
+
+        // Corners are numbered by which axes are positive, so each edge joins two corners which
+        // differ in exactly one bit.
+        for i in 0..8 {
+            for &axis in [1, 2, 4].iter() {
+                if i & axis == 0 {
+                    self.line(cam, corner(i), corner(i | axis), color);
+                }
+            }
+        }
+    }
+
+    /// Add the bounds of every node in `octree`, colored by depth from `DEPTH_COLORS`.
+    pub fn octree<T>(&mut self, cam: &Camera, octree: &LooseOctree<T>) {
+        let frustum = cam.frustum();
+
+        octree.debug_nodes(&mut |bcube: &BoundingCube, depth: u32| {
+            // Skip nodes which are off screen, so a huge world doesn't blow the vertex limit.
+            if frustum.intersects(bcube) != math::Visibility::Zero {
+                let color = DEPTH_COLORS[depth as usize % DEPTH_COLORS.len()];
+
+                self.cube(cam, bcube, color);
+            }
+        });
+    }
+}
+
+/// Draws `Lines` over the rest of the frame.
+pub struct LineRenderer {
+    prog: opengl::ShaderProgram,
+    vao:  opengl::VertexArray,
+    vbo:  opengl::VertexBuffer,
+}
+
+impl LineRenderer {
+    /// Create a new `LineRenderer`. This compiles and links a shader program, so it should only be
+    /// called after OpenGL has been initialized.
+    pub fn new() -> Result<LineRenderer, Error> {
+        let prog = try!(shader::program(&["line.vtx", "line.frg"]));
+
+        let vbo = opengl::VertexBuffer::new(mem::size_of::<LineVertex>() * MAX_LINE_VERTICES);
+
+        let vao = opengl::VertexArray::new();
+        vao.bind();
+        vbo.bind();
+
+        prog.use_program();
+        try!(sprite::set_srgb(&prog));
+
+        let stride = mem::size_of::<LineVertex>();
+
+        let position = try!(prog.get_attrib("position"));
+        position.enable();
+        position.set_pointer(2, gl::FLOAT, false, stride, 0);
+
+        let color = try!(prog.get_attrib("color"));
+        color.enable();
+        color.set_pointer(4, gl::FLOAT, false, stride, mem::size_of::<math::Vec2<NDU>>());
+
+        Ok(LineRenderer { prog: prog, vao: vao, vbo: vbo })
+    }
+
+    /// Draw `lines`, ignoring depth so that they show through whatever's in front of them.
+    pub fn draw(&self, lines: &Lines) {
+        let mut verts = lines.vertices();
+
+        if verts.len() > MAX_LINE_VERTICES {
+            warn!(target: logging::GFX, "drawing {} of {} debug line vertices",
+                  MAX_LINE_VERTICES, verts.len());
+
+            verts = &verts[..MAX_LINE_VERTICES];
+        }
+
+        if verts.is_empty() {
+            return
+        }
+
+        let mut span = trace::span(trace::DRAW, "debug_lines");
+        span.arg("vertices", &verts.len());
+
+        self.vbo.buffer_data(verts);
+
+        self.prog.use_program();
+        self.vao.bind();
+
+        unsafe {
+            gl::Disable(gl::DEPTH_TEST);
+            gl::DrawArrays(gl::LINES, 0, verts.len() as GLsizei);
+            gl::Enable(gl::DEPTH_TEST);
+        }
+    }
+}
+
+impl Recreate for LineRenderer {
+    fn recreate(&mut self) -> Result<(), String> {
+        *self = try!(LineRenderer::new().map_err(|err| format!("{:?}", err)));
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! A debug page showing which sprite sheets are resident in GPU memory, for tuning the texture
+//! budget (see `AssetDb::set_texture_budget`). `TextureView` draws a thumbnail of every loaded
+//! sheet, `listing` describes them, and `command` implements console commands to list and unload
+//! them.
+
+use std::mem;
+
+use gl::types::*;
+use gl;
+
+use asset::AssetDb;
+use grafix::device::Recreate;
+use grafix::opengl;
+use grafix::shader;
+use grafix::sprite::Error;
+use math;
+use units::*;
+
+/// The number of thumbnails in each row of a `TextureView`.
+pub const THUMB_COLUMNS: usize = 6;
+
+// The gap between thumbnails, in NDU.
+const THUMB_GAP: f32 = 0.02;
+
+/// One corner of a thumbnail, as sent to the GPU.
+#[derive(Debug,Copy,Clone)]
+#[repr(C)]
+pub struct ThumbVertex {
+    /// The position on the screen.
+    pub position: math::Vec2<NDU>,
+
+    /// The texture coordinate.
+    pub tex_coord: math::Vec2<TexCoord>,
+}
+
+/// Draws a grid of thumbnails of the resident sprite sheets, in order of name, over the rest of
+/// the frame.
+pub struct TextureView {
+    prog:  opengl::ShaderProgram,
+    vao:   opengl::VertexArray,
+    vbo:   opengl::VertexBuffer,
+    layer: opengl::Uniform,
+}
+
+impl TextureView {
+    /// Create a new `TextureView`. This compiles and links a shader program, so it should only be
+    /// called after OpenGL has been initialized.
+    pub fn new() -> Result<TextureView, Error> {
+        let prog = try!(shader::program(&["thumb.vtx", "thumb.frg"]));
+
+        let vbo = opengl::VertexBuffer::new(mem::size_of::<ThumbVertex>() * 6);
+
+        let vao = opengl::VertexArray::new();
+        vao.bind();
+        vbo.bind();
+
+        prog.use_program();
+
+        let stride = mem::size_of::<ThumbVertex>();
+
+        let position = try!(prog.get_attrib("position"));
+        position.enable();
+        position.set_pointer(2, gl::FLOAT, false, stride, 0);
+
+        let tex_coord = try!(prog.get_attrib("tex_coord"));
+        tex_coord.enable();
+        tex_coord.set_pointer(2, gl::FLOAT, false, stride, mem::size_of::<math::Vec2<NDU>>());
+
+        let tex = try!(prog.get_uniform("tex"));
+        tex.set1i(0);
+
+        let layer = try!(prog.get_uniform("layer"));
+
+        Ok(TextureView { prog: prog, vao: vao, vbo: vbo, layer: layer })
+    }
+
+    /// Draw a thumbnail of every sheet in `assets` which is resident, without counting any of them
+    /// as used. Each thumbnail keeps its sheet's proportions within a square cell, filling rows of
+    /// `THUMB_COLUMNS` from the top-left of the screen.
+    pub fn draw(&self, assets: &AssetDb) {
+        let sheets = assets.residency();
+
+        if sheets.is_empty() {
+            return
+        }
+
+        let cell = (2.0 - THUMB_GAP * (THUMB_COLUMNS + 1) as f32) / THUMB_COLUMNS as f32;
+
+        self.prog.use_program();
+        self.vao.bind();
+
+        let handle = assets.get_handle();
+
+        unsafe { gl::Disable(gl::DEPTH_TEST) }
+
+        for (i, res) in sheets.iter().enumerate() {
+            let sheet = match handle.peek_sprite_sheet(res.id) {
+                Some(sheet) => sheet,
+                None        => continue,
+            };
+
+            let (col, row) = ((i % THUMB_COLUMNS) as f32, (i / THUMB_COLUMNS) as f32);
+
+            let left = -1.0 + THUMB_GAP + col * (cell + THUMB_GAP);
+            let top  =  1.0 - THUMB_GAP - row * (cell + THUMB_GAP);
+
+            if top - cell < -1.0 {
+                break
+            }
+
+            // Fit the sheet in the cell, keeping its proportions.
+            let (w, h) = (res.width.max(1) as f32, res.height.max(1) as f32);
+            let (tw, th) = if w >= h { (cell, cell * h / w) } else { (cell * w / h, cell) };
+
+            let (x0, x1) = (left, left + tw);
+            let (y0, y1) = (top - th, top);
+
+            let vert = |x: f32, y: f32, s: f32, t: f32| ThumbVertex {
+                position:  vec2!(NDU(x), NDU(y)),
+                tex_coord: vec2!(TexCoord(s), TexCoord(t)),
+            };
+
+            // Images are stored top row first, so the top of the thumbnail is t = 0.
+            let verts = [
+                vert(x0, y0, 0.0, 1.0), vert(x1, y0, 1.0, 1.0), vert(x1, y1, 1.0, 0.0),
+                vert(x0, y0, 0.0, 1.0), vert(x1, y1, 1.0, 0.0), vert(x0, y1, 0.0, 0.0),
+            ];
+
+            self.vbo.buffer_data(&verts);
+            sheet.bind_color_texture(0);
+            self.layer.set1f(sheet.layer() as f32);
+
+            unsafe { gl::DrawArrays(gl::TRIANGLES, 0, verts.len() as GLsizei) }
+        }
+
+        unsafe { gl::Enable(gl::DEPTH_TEST) }
+    }
+}
+
+impl Recreate for TextureView {
+    fn recreate(&mut self) -> Result<(), String> {
+        *self = try!(TextureView::new().map_err(|err| format!("{:?}", err)));
+        Ok(())
+    }
+}
+
+/// Describe every resident sheet in `assets`, one per line in the same order as the thumbnails
+/// drawn by `TextureView`, followed by the totals.
+pub fn listing(assets: &AssetDb) -> String {
+    let frame  = assets.frame();
+    let usage  = assets.texture_usage();
+    let sheets = assets.residency();
+
+    let mut out = String::new();
+
+    for res in sheets.iter() {
+        out.push_str(&format!("{:<32} {:>5}x{:<5} {:>10} bytes  used {} frames ago\n",
+                              res.name, res.width, res.height, res.bytes,
+                              frame.saturating_sub(res.last_used)));
+    }
+
+    let budget = match usage.budget {
+        Some(budget) => format!("{} bytes", budget),
+        None         => From::from("none"),
+    };
+
+    out.push_str(&format!("{} sheets, {} bytes resident, budget {}, {} evicted\n",
+                          usage.sheets, usage.resident, budget, usage.evicted));
+
+    out
+}
+
+/// Run a console command against `assets`, returning the text to print. The commands are
+///
+///  * `textures`: the output of `listing`.
+///  * `unload NAME`: unload the sheet called `NAME`.
+///  * `unload-idle FRAMES`: unload every sheet which hasn't been used for at least `FRAMES`
+///    frames.
+pub fn command(assets: &AssetDb, line: &str) -> Result<String, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    match (words.get(0).cloned(), words.get(1).cloned(), words.len()) {
+        (Some("textures"), None, 1) => Ok(listing(assets)),
+
+        (Some("unload"), Some(name), 2) => {
+            let id = match assets.get_handle().get_id(name) {
+                Some(id) => id,
+                None     => return Err(format!("no asset called `{}'", name)),
+            };
+
+            if assets.unload(id) {
+                Ok(format!("unloaded `{}'", name))
+            } else {
+                Err(format!("`{}' isn't a resident sprite sheet", name))
+            }
+        },
+
+        (Some("unload-idle"), Some(frames), 2) => {
+            let frames: u64 = try!(frames.parse::<u64>()
+                .map_err(|_| format!("expected a number of frames, not `{}'", frames)));
+
+            let now   = assets.frame();
+            let idle: Vec<_> = assets.residency().into_iter()
+                .filter(|res| now.saturating_sub(res.last_used) >= frames)
+                .collect();
+
+            let bytes = idle.iter().fold(0, |total, res| total + res.bytes);
+
+            for res in idle.iter() {
+                assets.unload(res.id);
+            }
+
+            Ok(format!("unloaded {} sheets, {} bytes", idle.len(), bytes))
+        },
+
+        _ => Err(From::from("usage: textures | unload NAME | unload-idle FRAMES")),
+    }
+}
@@ -0,0 +1,224 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Recovering from the loss of the OpenGL context.
+//!
+//! On some platforms the GL context can disappear from under the game: mobile devices reclaim it
+//! in the background, drivers reset it after a hang, and some drivers replace it when toggling
+//! fullscreen. Once `opengl::Context::recreate` has made a new one, every texture, buffer and
+//! program made with the old context is gone, and drawing with them renders garbage.
+//!
+//! Each type holding GPU resources can rebuild them from the descriptors it was made from (sheets
+//! from their `SheetDesc`s, renderers from their shader sources) by implementing `Recreate`. A
+//! `Registry` remembers those in use and, once it sees that `opengl::generation` has changed,
+//! recreates each of them in the order they were registered:
+//!
+//! ```ignore
+//! let mut registry = device::Registry::new();
+//! registry.register("line renderer", &lines);
+//!
+//! // Each frame:
+//! for (name, err) in registry.update() {
+//!     error!(target: logging::GFX, "couldn't recreate {}: {}", name, err);
+//! }
+//! ```
+//!
+//! Resources built from shaders can also be rebuilt when the shaders change on disk during
+//! development (see `grafix::shader`), by marking them with `Registry::uses_shaders`.
+//!
+//! Resources inside an `entity::System`, such as `WorldRender`'s, can't be registered, since the
+//! `Manager` owns them; those systems check `opengl::generation` and `shader::generation`
+//! themselves.
+
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+use grafix::opengl;
+use grafix::shader;
+use logging;
+
+/// Something holding GPU resources which can rebuild them after the GL context is recreated.
+pub trait Recreate {
+    /// Replace every GPU resource with a new one made from the same source. After the context has
+    /// been recreated the old resources are already gone, so they must not be used (dropping them
+    /// is fine). If this fails, whatever was there before should be left in place.
+    fn recreate(&mut self) -> Result<(), String>;
+}
+
+impl<R: Recreate + ?Sized> Recreate for Box<R> {
+    fn recreate(&mut self) -> Result<(), String> {
+        (**self).recreate()
+    }
+}
+
+/// Identifies a resource added to a `Registry`, so that it can later be removed.
+pub type ResourceID = u64;
+
+struct Entry {
+    id:   ResourceID,
+    name: String,
+
+    // Whether the resource is built from shaders, and so should be rebuilt when they change.
+    shaders: bool,
+
+    // Recreates the resource, or returns `None` if it has been dropped.
+    recreate: Box<FnMut() -> Option<Result<(), String>>>,
+}
+
+/// The resources to recreate when the GL context is.
+pub struct Registry {
+    entries: Vec<Entry>,
+    next_id: ResourceID,
+
+    // The context generation which the resources were last made for.
+    generation: usize,
+
+    // The shader generation which the resources were last built from.
+    shaders: usize,
+}
+
+impl Registry {
+    /// Create a registry with no resources, for the current context.
+    pub fn new() -> Registry {
+        Registry {
+            entries:    vec![],
+            next_id:    1,
+            generation: opengl::generation(),
+            shaders:    shader::generation(),
+        }
+    }
+
+    /// Recreate `res` along with the rest of the registry. Only a weak reference is kept, so a
+    /// resource is forgotten once it's dropped.
+    pub fn register<R: Recreate + 'static>(&mut self, name: &str, res: &Rc<RefCell<R>>)
+        -> ResourceID {
+
+        let weak: Weak<RefCell<R>> = Rc::downgrade(res);
+
+        self.register_fn(name, move || {
+            weak.upgrade().map(|res| {
+                let result = res.borrow_mut().recreate();
+                result
+            })
+        })
+    }
+
+    /// Call `recreate` along with the rest of the registry, for resources which aren't kept in an
+    /// `Rc<RefCell<..>>`. It returns `None` once there's nothing left to recreate, and it's
+    /// forgotten.
+    pub fn register_fn<F>(&mut self, name: &str, recreate: F) -> ResourceID
+        where F: FnMut() -> Option<Result<(), String>> + 'static {
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.entries.push(Entry {
+            id:       id,
+            name:     From::from(name),
+            shaders:  false,
+            recreate: Box::new(recreate),
+        });
+
+        id
+    }
+
+    /// Stop recreating a resource. Returns false if there's no resource with that ID.
+    pub fn unregister(&mut self, id: ResourceID) -> bool {
+        match self.entries.iter().position(|e| e.id == id) {
+            Some(i) => { self.entries.remove(i); true },
+            None    => false,
+        }
+    }
+
+    /// Also recreate the resource `id` whenever the shaders change (see `grafix::shader`), since
+    /// it's built from them. Returns false if there's no resource with that ID.
+    pub fn uses_shaders(&mut self, id: ResourceID) -> bool {
+        match self.entries.iter_mut().find(|e| e.id == id) {
+            Some(entry) => { entry.shaders = true; true },
+            None        => false,
+        }
+    }
+
+    /// The names of the registered resources, in the order they're recreated.
+    pub fn names(&self) -> Vec<&str> {
+        self.entries.iter().map(|e| &e.name[..]).collect()
+    }
+
+    /// True if the context has been recreated since the resources were last made.
+    pub fn is_stale(&self) -> bool {
+        self.generation != opengl::generation()
+    }
+
+    /// Recreate every resource if the context has been recreated since they were made, or those
+    /// marked with `uses_shaders` if the shaders have changed. This is cheap when neither has
+    /// happened, so it can be called every frame. Returns the names of the resources which
+    /// couldn't be recreated, with the errors; those keep what they had, if they still have it.
+    pub fn update(&mut self) -> Vec<(String, String)> {
+        if self.is_stale() {
+            self.recreate_all()
+        } else if self.shaders != shader::generation() {
+            self.reload_shaders()
+        } else {
+            vec![]
+        }
+    }
+
+    /// Recreate every resource now, in the order they were registered, forgetting those which have
+    /// been dropped. Returns the names of the resources which couldn't be recreated, with the
+    /// errors; the rest are still recreated.
+    pub fn recreate_all(&mut self) -> Vec<(String, String)> {
+        let errors = self.recreate_where(|_| true);
+
+        self.generation = opengl::generation();
+        self.shaders    = shader::generation();
+
+        info!(target: logging::GFX, "recreated {} GPU resources ({} failed)",
+              self.entries.len() - errors.len(), errors.len());
+
+        errors
+    }
+
+    // Rebuild the resources which use shaders, after the shaders have changed.
+    fn reload_shaders(&mut self) -> Vec<(String, String)> {
+        let errors = self.recreate_where(|e| e.shaders);
+
+        self.shaders = shader::generation();
+
+        info!(target: logging::GFX, "rebuilt resources for the new shaders ({} failed)",
+              errors.len());
+
+        errors
+    }
+
+    // Recreate the resources for which `pred` is true, forgetting those which have been dropped.
+    fn recreate_where<F: Fn(&Entry) -> bool>(&mut self, pred: F) -> Vec<(String, String)> {
+        let mut errors = vec![];
+        let mut gone   = vec![];
+
+        for entry in self.entries.iter_mut().filter(|e| pred(e)) {
+            match (entry.recreate)() {
+                Some(Ok(()))   => {},
+                Some(Err(err)) => errors.push((entry.name.clone(), err)),
+                None           => gone.push(entry.id),
+            }
+        }
+
+        self.entries.retain(|e| !gone.contains(&e.id));
+
+        errors
+    }
+}
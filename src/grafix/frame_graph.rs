@@ -0,0 +1,390 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! A frame graph orders the render passes of a frame by the targets they draw to and read from,
+//! instead of by the order they were added. Each pass declares which targets it reads and which
+//! it writes; a pass runs after every pass which writes a target it reads, and passes which write
+//! the same target run in the order they were added.
+//!
+//! Targets are either imported (such as the window's framebuffer), or transient: created by the
+//! graph, used within a single frame, and recycled. Two transient targets with the same
+//! description share storage if they're never needed at the same time. Passes whose output is
+//! never used are skipped.
+//!
+//! The graph is generic over the type of render target, so that it doesn't need to know how
+//! targets are created; that's left to a `TargetPool`.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use trace;
+
+/// The pixel format of a render target.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Format {
+    /// 8-bit RGBA color.
+    Rgba8,
+
+    /// 16-bit floating point RGBA color, for lighting and post-processing.
+    Rgba16F,
+
+    /// 24-bit depth.
+    Depth24,
+}
+
+/// The size and format of a transient render target.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct TargetDesc {
+    /// Width in pixels.
+    pub width: u32,
+
+    /// Height in pixels.
+    pub height: u32,
+
+    /// Pixel format.
+    pub format: Format,
+}
+
+/// Creates the storage behind transient render targets.
+pub trait TargetPool<T> {
+    /// Create a render target matching `desc`.
+    fn create(&mut self, desc: &TargetDesc) -> T;
+}
+
+/// The render targets available to a pass, by name.
+pub struct Targets<'x, T: 'x> {
+    by_name: BTreeMap<&'static str, &'x T>,
+}
+
+impl<'x, T> Targets<'x, T> {
+    /// Look up a target by name. Returns `None` for targets which the graph doesn't know about.
+    pub fn get(&self, name: &str) -> Option<&'x T> {
+        self.by_name.get(name).cloned()
+    }
+}
+
+/// A stage of rendering, such as drawing the world, shadows, lighting, UI, or post-processing.
+pub trait Pass<T> {
+    /// A name for the pass, used in errors.
+    fn name(&self) -> &'static str;
+
+    /// Render the pass. Every target the pass declared is available in `targets`.
+    fn execute(&mut self, targets: &Targets<T>);
+}
+
+/// The targets a pass reads and writes.
+#[derive(Clone,Debug)]
+pub struct PassIO {
+    reads:  Vec<&'static str>,
+    writes: Vec<&'static str>,
+}
+
+impl PassIO {
+    /// A pass which neither reads nor writes any targets. Such passes are never skipped.
+    pub fn new() -> PassIO {
+        PassIO { reads: vec![], writes: vec![] }
+    }
+
+    /// Declare that the pass reads (e.g. samples from) the target `name`.
+    pub fn reads(mut self, name: &'static str) -> PassIO {
+        self.reads.push(name);
+        self
+    }
+
+    /// Declare that the pass draws to the target `name`.
+    pub fn writes(mut self, name: &'static str) -> PassIO {
+        self.writes.push(name);
+        self
+    }
+}
+
+/// A problem with the passes and targets of a `FrameGraph`.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum GraphError {
+    /// A pass uses a target which was neither imported nor declared as transient.
+    UnknownTarget {
+        /// The pass which uses the target.
+        pass: &'static str,
+
+        /// The unknown target.
+        target: &'static str,
+    },
+
+    /// A pass reads a transient target which no pass writes.
+    NeverWritten(&'static str),
+
+    /// The passes depend on each other in a loop. Holds the passes involved.
+    Cycle(Vec<&'static str>),
+}
+
+/// The render passes of a frame, and the targets they use.
+pub struct FrameGraph<T> {
+    passes: Vec<(Box<Pass<T>>, PassIO)>,
+
+    imported:  BTreeMap<&'static str, T>,
+    transient: BTreeMap<&'static str, TargetDesc>,
+    outputs:   BTreeSet<&'static str>,
+
+    // The compiled schedule, or `None` if the graph has changed since it was compiled.
+    schedule: Option<Schedule>,
+
+    // Storage for transient targets, recycled from frame to frame.
+    slots: Vec<(TargetDesc, T)>,
+}
+
+// The order to run the passes in, and where each transient target lives.
+struct Schedule {
+    order:   Vec<usize>,
+    slot_of: BTreeMap<&'static str, usize>,
+    slots:   Vec<TargetDesc>,
+}
+
+impl<T> FrameGraph<T> {
+    /// Create an empty graph.
+    pub fn new() -> FrameGraph<T> {
+        FrameGraph {
+            passes:    vec![],
+            imported:  BTreeMap::new(),
+            transient: BTreeMap::new(),
+            outputs:   BTreeSet::new(),
+            schedule:  None,
+            slots:     vec![],
+        }
+    }
+
+    /// Add a pass which uses the targets declared in `io`.
+    pub fn add_pass<P: Pass<T> + 'static>(&mut self, pass: P, io: PassIO) {
+        self.passes.push((Box::new(pass), io));
+        self.schedule = None;
+    }
+
+    /// Remove the first pass named `name`, returning it.
+    pub fn remove_pass(&mut self, name: &str) -> Option<Box<Pass<T>>> {
+        match self.passes.iter().position(|p| p.0.name() == name) {
+            Some(i) => {
+                self.schedule = None;
+                Some(self.passes.remove(i).0)
+            },
+
+            None => None,
+        }
+    }
+
+    /// Make a target which the graph doesn't manage available to passes, such as the window's
+    /// framebuffer. Imported targets are outputs of the frame: the passes which write to them are
+    /// never skipped. Importing a target under an existing name replaces it.
+    pub fn import(&mut self, name: &'static str, target: T) {
+        self.imported.insert(name, target);
+        self.outputs.insert(name);
+        self.schedule = None;
+    }
+
+    /// Declare a transient target, which the graph creates and recycles as needed.
+    pub fn transient(&mut self, name: &'static str, desc: TargetDesc) {
+        self.transient.insert(name, desc);
+        self.schedule = None;
+    }
+
+    /// Mark a transient target as an output of the frame (e.g. so that it can be inspected),
+    /// so that the passes which write it are never skipped.
+    pub fn keep(&mut self, name: &'static str) {
+        self.outputs.insert(name);
+        self.schedule = None;
+    }
+
+    /// The names of the passes which will run, in order.
+    pub fn order(&mut self) -> Result<Vec<&'static str>, GraphError> {
+        try!(self.compile());
+
+        let order = &self.schedule.as_ref().unwrap().order;
+        Ok(order.iter().map(|&i| self.passes[i].0.name()).collect())
+    }
+
+    /// The number of distinct transient targets the graph needs storage for.
+    pub fn slot_count(&mut self) -> Result<usize, GraphError> {
+        try!(self.compile());
+        Ok(self.schedule.as_ref().unwrap().slots.len())
+    }
+
+    /// Run every pass which contributes to an output, in dependency order, creating transient
+    /// targets from `pool` where there aren't suitable ones left over from the last frame.
+    pub fn run(&mut self, pool: &mut TargetPool<T>) -> Result<(), GraphError> {
+        try!(self.compile());
+
+        let sched = self.schedule.as_ref().unwrap();
+
+        self.slots.truncate(sched.slots.len());
+
+        for (i, desc) in sched.slots.iter().enumerate() {
+            if i == self.slots.len() {
+                self.slots.push((*desc, pool.create(desc)));
+            } else if self.slots[i].0 != *desc {
+                self.slots[i] = (*desc, pool.create(desc));
+            }
+        }
+
+        for &p in sched.order.iter() {
+            let (ref mut pass, ref io) = self.passes[p];
+
+            let mut by_name = BTreeMap::new();
+
+            for &name in io.reads.iter().chain(io.writes.iter()) {
+                let target = match sched.slot_of.get(name) {
+                    Some(&slot) => &self.slots[slot].1,
+                    None        => &self.imported[name],
+                };
+
+                by_name.insert(name, target);
+            }
+
+            let _span = trace::span(trace::GL, pass.name());
+            pass.execute(&Targets { by_name: by_name });
+        }
+
+        Ok(())
+    }
+
+    fn compile(&mut self) -> Result<(), GraphError> {
+        if self.schedule.is_some() {
+            return Ok(())
+        }
+
+        let passes    = &self.passes;
+        let imported  = &self.imported;
+        let transient = &self.transient;
+        let outputs   = &self.outputs;
+
+        let n = passes.len();
+
+        for &(ref pass, ref io) in passes.iter() {
+            for &t in io.reads.iter().chain(io.writes.iter()) {
+                if !imported.contains_key(t) && !transient.contains_key(t) {
+                    return Err(GraphError::UnknownTarget { pass: pass.name(), target: t })
+                }
+            }
+        }
+
+        let writers = |t: &str| -> Vec<usize> {
+            (0..n).filter(|&i| passes[i].1.writes.iter().any(|&w| w == t)).collect()
+        };
+
+        // deps[i] holds the passes which must run before pass i.
+        let mut deps: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); n];
+
+        for i in 0..n {
+            let io = &passes[i].1;
+
+            for &t in io.reads.iter() {
+                let ws = writers(t);
+
+                if ws.is_empty() && transient.contains_key(t) {
+                    return Err(GraphError::NeverWritten(t))
+                }
+
+                deps[i].extend(ws.into_iter().filter(|&w| w != i));
+            }
+
+            for &t in io.writes.iter() {
+                deps[i].extend(writers(t).into_iter().filter(|&w| w < i));
+            }
+        }
+
+        // Work backwards from the outputs to find the passes which matter.
+        let mut live = vec![false; n];
+        let mut stack: Vec<usize> = (0..n).filter(|&i| {
+            let io = &passes[i].1;
+            io.writes.is_empty() || io.writes.iter().any(|w| outputs.contains(w))
+        }).collect();
+
+        while let Some(i) = stack.pop() {
+            if !live[i] {
+                live[i] = true;
+                stack.extend(deps[i].iter().cloned());
+            }
+        }
+
+        // Topological sort, taking the earliest-added ready pass each time so that the order is
+        // stable.
+        let mut order = vec![];
+        let mut done  = vec![false; n];
+
+        while order.len() < live.iter().filter(|&&l| l).count() {
+            let next = (0..n).find(|&i| {
+                live[i] && !done[i] && deps[i].iter().all(|&d| done[d] || !live[d])
+            });
+
+            match next {
+                Some(i) => { done[i] = true; order.push(i); },
+                None    => {
+                    let stuck = (0..n).filter(|&i| live[i] && !done[i])
+                                      .map(|i| passes[i].0.name())
+                                      .collect();
+
+                    return Err(GraphError::Cycle(stuck))
+                },
+            }
+        }
+
+        // Find the span of the schedule over which each transient target is needed.
+        let mut span: BTreeMap<&'static str, (usize, usize)> = BTreeMap::new();
+
+        for (step, &p) in order.iter().enumerate() {
+            let io = &passes[p].1;
+
+            for &t in io.reads.iter().chain(io.writes.iter()) {
+                if transient.contains_key(t) {
+                    let e = span.entry(t).or_insert((step, step));
+                    e.1 = step;
+                }
+            }
+        }
+
+        // Targets kept as outputs live until the end of the frame.
+        for (&t, s) in span.iter_mut() {
+            if outputs.contains(t) {
+                s.1 = order.len();
+            }
+        }
+
+        // Give each target the first free slot of the same description, in order of first use.
+        let mut by_start: Vec<(&'static str, (usize, usize))> = span.into_iter().collect();
+        by_start.sort_by(|a, b| (a.1).0.cmp(&(b.1).0));
+
+        let mut slots:   Vec<TargetDesc> = vec![];
+        let mut free_at: Vec<usize>      = vec![];
+        let mut slot_of                  = BTreeMap::new();
+
+        for (t, (start, end)) in by_start {
+            let desc = transient[t];
+
+            let slot = match (0..slots.len()).find(|&s| slots[s] == desc && free_at[s] < start) {
+                Some(s) => s,
+                None    => {
+                    slots.push(desc);
+                    free_at.push(0);
+                    slots.len() - 1
+                },
+            };
+
+            free_at[slot] = end;
+            slot_of.insert(t, slot);
+        }
+
+        self.schedule = Some(Schedule { order: order, slot_of: slot_of, slots: slots });
+
+        Ok(())
+    }
+}
@@ -18,6 +18,12 @@
 /// Boilerplate-reducing abstractions around OpenGL, taylored to the specific use-case of bizrock.
 #[cfg(feature = "client")] pub mod opengl;
 
+/// Recreating GPU resources after the OpenGL context is lost.
+#[cfg(feature = "client")] pub mod device;
+
+/// Where shader sources come from, and reloading them from disk during development.
+#[cfg(feature = "client")] pub mod shader;
+
 /// Sprite-drawing interface built on top of the `grafix::opengl` module.
 pub mod sprite;
 
@@ -29,3 +35,25 @@ pub mod system;
 
 /// Types for working with relationships between screen-space and game-space.
 pub mod camera;
+
+/// Bitmap text, drawn through the sprite batcher.
+#[cfg(feature = "client")] pub mod text;
+
+/// Particle effects, drawn through the sprite batcher.
+#[cfg(feature = "client")] pub mod particles;
+
+/// Ordering render passes by the targets they use.
+pub mod frame_graph;
+
+/// FlatBuffer representations of camera and weather state.
+#[allow(missing_docs)]
+pub mod wire;
+
+/// Per-frame rendering statistics.
+pub mod stats;
+
+/// Debug overlays, such as the nodes of an octree and the resident sprite sheets.
+#[cfg(feature = "client")] pub mod debug;
+
+pub use self::stats::{PassStats, Stats};
+#[cfg(feature = "client")] pub use self::stats::PassTimer;
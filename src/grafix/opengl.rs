@@ -15,22 +15,40 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::From;
 use std::ffi;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
 use std::iter;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
 use std::ptr;
+use std::slice;
 use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT};
 
 use gl::types::*;
 use gl;
 use png;
 use sdl2::video;
 
+use crash;
+use grafix::camera;
+use logging;
+use memory;
+use time;
+
 // If `trace_gl` is enabled, this macro will print the expression passed to it (assumed to be a call
 // to an OpenGL function), and then call `glGetError` and print any error it finds.
 //
 // Enabling `trace_gl` will slow down code a lot, but provide a detailed view of what's going on in
-// the GL.
+// the GL. Contexts with debug output (see `ContextBuilder::debug`) report errors as they happen,
+// with more detail, so this is mostly a fallback for older ones.
 macro_rules! trace {
     ($call:expr) => (if cfg!(feature = "trace_gl") {
         let __result = $call;
@@ -44,7 +62,18 @@ macro_rules! trace {
 // This function calls glGetError and returns a suffix string describing any error found. It is
 // intended 100% for debug purposes, and should only be called from the trace!(..) macro.
 unsafe fn error_suffix() -> &'static str {
-    match gl::GetError() {
+    // Errors are already reported by `debug_callback`, and polling for them would only stall.
+    if DEBUG_OUTPUT.load(Ordering::Relaxed) {
+        return ""
+    }
+
+    let err = gl::GetError();
+
+    if err != gl::NO_ERROR {
+        crash::set_gl_error(err);
+    }
+
+    match err {
         gl::NO_ERROR                      => "",
         gl::INVALID_ENUM                  => " : GL_INVALID_ENUM",
         gl::INVALID_VALUE                 => " : GL_INVALID_VALUE",
@@ -55,481 +84,2583 @@ unsafe fn error_suffix() -> &'static str {
     }
 }
 
-/// A RAII container for a window and its OpenGL context. This object needs to be around for as long
-/// as OpenGL is being used with that window.
-///
-/// In general the user shouldn't touch this and should instead use grafix::Context, which takes
-/// care of some SDL preliminaries. Maybe I'll jump through the necessary hoops to make this
-/// not-public at some point.
-#[allow(dead_code)] // The code isn't really dead, we're relying on drop being called.
-pub struct Context {
-    window: video::Window,
-    gl_ctx: video::GLContext,
+// The number of GL contexts created so far (see `generation`).
+static GENERATION: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The number of GL contexts created so far. When a context is lost and replaced (see
+/// `Context::recreate`), every object made with the old one is gone, and this changes. Anything
+/// holding GPU resources can compare it with the value when they were made to tell that they need
+/// recreating (see `grafix::device`).
+pub fn generation() -> usize {
+    GENERATION.load(Ordering::Relaxed)
 }
 
-impl Context {
-    /// Create a new window with an associated (thread-local) OpenGL context.
-    pub fn new(title: &str, x_res: i32, y_res: i32) -> Result<Context, String> {
-        use sdl2::video::{Window, OPENGL};
-        use sdl2::video::WindowPos::*;
+// Whether the current context draws with sRGB color handling (see `srgb`).
+static SRGB: AtomicBool = ATOMIC_BOOL_INIT;
 
-        let window = try!(Window::new(title, PosCentered, PosCentered, x_res, y_res, OPENGL));
-        let gl_ctx = try!(window.gl_create_context());
+/// True if the window was created with sRGB color handling (see `ContextBuilder::srgb`). Color
+/// textures are then stored as sRGB (see `Tex2D::from_png_srgb`) and read back as linear colors,
+/// the shaders convert the colors they're given (tints and lights) from sRGB to linear, and the
+/// window converts what's drawn back to sRGB. Blending and lighting then happen on linear colors,
+/// which is what makes alpha edges and tints look right.
+pub fn srgb() -> bool {
+    SRGB.load(Ordering::Relaxed)
+}
 
-        gl::load_with(|s| unsafe { mem::transmute(video::gl_get_proc_address(s)) });
+// Whether GL objects made under context `generation` still exist. Objects from a lost context
+// mustn't be deleted, since their names may since have been reused by the new one.
+fn is_current(generation: usize) -> bool {
+    generation == self::generation()
+}
 
-        unsafe {
-            trace!(gl::Enable(gl::DEPTH_TEST));
-            trace!(gl::DepthFunc(gl::LEQUAL));
-            trace!(gl::ClearDepth(1.0));
+/// How the colors drawn are combined with what's already in the framebuffer (see `set_blend`).
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum Blend {
+    /// Replace what's there.
+    Off,
 
-            trace!(gl::Enable(gl::BLEND));
-            trace!(gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA));
-        }
+    /// Mix by the alpha of what's drawn, which is what the engine uses by default.
+    Alpha,
 
-        Ok(Context{ window: window, gl_ctx: gl_ctx })
-    }
+    /// Add what's drawn, weighted by its alpha, e.g. for glows and sparks.
+    Additive,
 
-    /// Swap OpenGL buffers, drawing the frame to the screen.
-    pub fn draw_frame(&self) {
-        self.window.gl_swap_window();
-    }
+    /// Mix colors which have already been multiplied by their alpha.
+    Premultiplied,
 }
 
-/// A 2D OpenGL Texture
-pub struct Tex2D(GLuint);
+// The GL state which the engine changes most often, as it stands in this thread's context, so that
+// binding what's already bound can be skipped. Every change to this state goes through the
+// functions below, so the cache can't go stale; it starts out as a new context does (see
+// `Context::init`).
+struct StateCache {
+    program:      GLuint,
+    vertex_array: GLuint,
+    array_buffer: GLuint,
 
-impl Tex2D {
-    /// Create a `Tex2D` from a PNG.
-    ///
-    /// # Panics
-    ///
-    /// This function will panic if `img` is not either BW (`K8`), RGB (`RGB8`), or RGBA (`RGBA8`).
-    pub fn from_png(img: &png::Image) -> Tex2D {
-        use png::PixelsByColorType::*;
+    // The active texture unit, and what's bound to each target of each unit.
+    active_unit: usize,
+    textures:    BTreeMap<(usize, GLenum), GLuint>,
 
-        let mut gl_texid = 0;
-        unsafe {
-            trace!(gl::GenTextures(1, &mut gl_texid));
-            trace!(gl::BindTexture(gl::TEXTURE_2D, gl_texid));
-            trace!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as GLint));
-            trace!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as GLint));
-            trace!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint));
-            trace!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint));
-        }
+    blend: Blend,
+}
 
-        match img.pixels {
+impl StateCache {
+    fn new() -> StateCache {
+        StateCache {
+            program:      0,
+            vertex_array: 0,
+            array_buffer: 0,
+            active_unit:  0,
+            textures:     BTreeMap::new(),
+            blend:        Blend::Off,
+        }
+    }
+}
 
-            RGBA8(ref pix) => unsafe {
-                trace!(gl::TexImage2D(
-                    gl::TEXTURE_2D,
-                    0,
-                    gl::RGBA as GLint,
-                    img.width  as GLsizei,
-                    img.height as GLsizei,
-                    0,
-                    gl::RGBA,
-                    gl::UNSIGNED_BYTE,
-                    pix.as_ptr() as *const GLvoid,
-                ));
-            },
+thread_local!(static STATE: RefCell<StateCache> = RefCell::new(StateCache::new()));
 
-            RGB8(ref pix) => unsafe {
-                trace!(gl::TexImage2D(
-                    gl::TEXTURE_2D,
-                    0,
-                    gl::RGB as GLint,
-                    img.width  as GLsizei,
-                    img.height as GLsizei,
-                    0,
-                    gl::RGB,
-                    gl::UNSIGNED_BYTE,
-                    pix.as_ptr() as *const GLvoid,
-                ));
-            },
+// Use program `name`, unless it's already in use.
+fn bind_program(name: GLuint) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
 
-            K8(ref pix) => unsafe {
-                trace!(gl::TexImage2D(
-                    gl::TEXTURE_2D,
-                    0,
-                    gl::RED as GLint,
-                    img.width  as GLsizei,
-                    img.height as GLsizei,
-                    0,
-                    gl::RED,
-                    gl::UNSIGNED_BYTE,
-                    pix.as_ptr() as *const GLvoid,
-                ));
-            },
+        if state.program != name {
+            unsafe { trace!(gl::UseProgram(name)) }
+            state.program = name;
+        }
+    })
+}
 
-            _ => panic!("PNGs must be either BW, RGB or RGBA!"),
+// Bind vertex array `name`, unless it's already bound.
+fn bind_vertex_array(name: GLuint) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
 
+        if state.vertex_array != name {
+            unsafe { trace!(gl::BindVertexArray(name)) }
+            state.vertex_array = name;
         }
+    })
+}
 
-        Tex2D(gl_texid)
-    }
+// Bind buffer `name` to `ARRAY_BUFFER`, unless it's already bound.
+fn bind_array_buffer(name: GLuint) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
 
-    /// Bind this texture to `GL_TEXTURE_2D` for the given texture unit. This function results in a
-    /// single call to `glActiveTexture` followed by a single call to `glBindTexture`.
-    pub fn bind_to_unit(&self, unit: usize) {
-        unsafe {
-            trace!(gl::ActiveTexture(gl::TEXTURE0 + (unit as GLenum)));
-            trace!(gl::BindTexture(gl::TEXTURE_2D, self.0));
+        if state.array_buffer != name {
+            unsafe { trace!(gl::BindBuffer(gl::ARRAY_BUFFER, name)) }
+            state.array_buffer = name;
         }
-    }
+    })
 }
 
-impl Drop for Tex2D {
-    /// Call `glDeleteTextures` on this texture.
-    fn drop(&mut self) {
-        unsafe { trace!(gl::DeleteTextures(1, &self.0)) }
-    }
+// Bind texture `name` to `target` of texture `unit`, or of the active unit if `unit` is `None`
+// (which is how textures are bound to be changed), unless it's already bound.
+fn bind_texture(unit: Option<usize>, target: GLenum, name: GLuint) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+
+        let unit = unit.unwrap_or(state.active_unit);
+
+        if state.textures.get(&(unit, target)).cloned().unwrap_or(0) == name {
+            return
+        }
+
+        if state.active_unit != unit {
+            unsafe { trace!(gl::ActiveTexture(gl::TEXTURE0 + (unit as GLenum))) }
+            state.active_unit = unit;
+        }
+
+        unsafe { trace!(gl::BindTexture(target, name)) }
+        state.textures.insert((unit, target), name);
+    })
 }
 
-/// An error that occurred while compiling a shader.
-#[derive(Debug)]
-pub struct CompileError {
-    /// The info log retrieved from OpenGL, which may describe the cause of the error.
-    pub info_log: String
+// Deleting an object unbinds it from the context, and its name may then be reused, so the cache
+// has to forget it too. These are called just before the objects are deleted.
+
+fn forget_program(name: GLuint) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+
+        if state.program == name { state.program = 0 }
+    })
 }
 
-/// An error that occurred while linking a shader program.
-#[derive(Debug)]
-pub struct LinkError {
-    /// The info log retrieved from OpenGL, which may describe the cause of the error.
-    pub info_log: String
+fn forget_vertex_array(name: GLuint) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+
+        if state.vertex_array == name { state.vertex_array = 0 }
+    })
 }
 
-/// A compiled OpenGL shader object. Its only purpose is to be linked with other `Shader`s into a
-/// `ShaderProgram`.
-pub struct Shader(GLuint);
+fn forget_buffer(name: GLuint) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
 
-impl Shader {
-    /// Create a new vertex shader from a source string.
-    pub fn new_vertex(src: &str) -> Result<Shader, CompileError> {
-        Shader::new(src, gl::VERTEX_SHADER)
-    }
+        if state.array_buffer == name { state.array_buffer = 0 }
+    })
+}
 
-    /// Create a new geometry shader from a source string.
-    pub fn new_geometry(src: &str) -> Result<Shader, CompileError> {
-        Shader::new(src, gl::GEOMETRY_SHADER)
-    }
+fn forget_texture(name: GLuint) {
+    STATE.with(|state| {
+        for bound in state.borrow_mut().textures.values_mut().filter(|bound| **bound == name) {
+            *bound = 0;
+        }
+    })
+}
 
-    /// Create a new fragment shader from a source string.
-    pub fn new_fragment(src: &str) -> Result<Shader, CompileError> {
-        Shader::new(src, gl::FRAGMENT_SHADER)
-    }
+/// Change how colors are blended into the framebuffer, unless it's already set that way. Whoever
+/// changes it should set it back to `Blend::Alpha` afterwards, which is what everything else
+/// expects.
+pub fn set_blend(blend: Blend) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
 
-    // Hooray for FFI and boilerplate!
-    fn new(src: &str, typ: GLenum) -> Result<Shader, CompileError> {
-        unsafe {
-            let gl_shader = trace!(gl::CreateShader(typ));
+        if state.blend == blend {
+            return
+        }
 
-            // Jump through hoops to create a `const GLchar **` for glShaderSource.
-            let src_cstr    = ffi::CString::new(src).unwrap();
-            let src_ptr_ptr = &src_cstr.as_ptr() as *const *const GLchar;
+        let func = match blend {
+            Blend::Off           => None,
+            Blend::Alpha         => Some((gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA)),
+            Blend::Additive      => Some((gl::SRC_ALPHA, gl::ONE)),
+            Blend::Premultiplied => Some((gl::ONE, gl::ONE_MINUS_SRC_ALPHA)),
+        };
 
-            trace!(gl::ShaderSource(gl_shader, 1, src_ptr_ptr, ptr::null()));
-            trace!(gl::CompileShader(gl_shader));
+        unsafe {
+            match func {
+                Some((src, dst)) => {
+                    if state.blend == Blend::Off {
+                        trace!(gl::Enable(gl::BLEND));
+                    }
+
+                    trace!(gl::BlendFunc(src, dst));
+                },
+                None => trace!(gl::Disable(gl::BLEND)),
+            }
+        }
 
-            // Check if the shader compile successfully
-            let mut status: GLint = 0;
-            trace!(gl::GetShaderiv(gl_shader, gl::COMPILE_STATUS, &mut status));
+        state.blend = blend;
+    })
+}
 
-            // If the shader failed to compile, get the info log and return it as an error.
-            if status != (gl::TRUE as GLint) {
-                let mut log_len = 0;
-                trace!(gl::GetShaderiv(gl_shader, gl::INFO_LOG_LENGTH, &mut log_len));
-                let mut log_buf: Vec<u8> = iter::repeat(0u8).take(log_len as usize).collect();
-                let log_ptr = log_buf.as_mut_ptr() as *mut GLchar;
+// Set up the GL state the engine expects of a new context.
+fn init_state() {
+    STATE.with(|state| *state.borrow_mut() = StateCache::new());
 
-                let mut real_len = 0;
-                trace!(gl::GetShaderInfoLog(gl_shader, log_len as GLsizei, &mut real_len, log_ptr));
-                // real_len doesn't include the null terminator.
-                log_buf.truncate(real_len as usize);
+    set_blend(Blend::Alpha);
 
-                let log = String::from_utf8(log_buf)
-                    .unwrap_or(From::from("Info log was not valid utf-8"));
+    unsafe {
+        trace!(gl::Enable(gl::DEPTH_TEST));
+        trace!(gl::DepthFunc(gl::LEQUAL));
+        trace!(gl::ClearDepth(1.0));
 
-                Err(CompileError{info_log: log})
-            } else {
-                Ok(Shader(gl_shader))
-            }
+        if srgb() {
+            trace!(gl::Enable(gl::FRAMEBUFFER_SRGB));
         }
     }
 }
 
-impl Drop for Shader {
-    /// Call `glDeleteShader` on this shader. Shaders should be dropped as soon as possible after
-    /// linking, since they keep unnecessary source and object code around in GL memory.
-    fn drop(&mut self) {
-        unsafe { trace!(gl::DeleteShader(self.0)) }
-    }
+/// Call `glGetError`, returning the oldest error which hasn't been reported yet, or `GL_NO_ERROR`.
+pub fn get_error() -> u32 {
+    unsafe { gl::GetError() }
 }
 
-/// A TransformFeedback buffer, at the moment this is just intended for debugging purposes. I don't
-/// see them being very useful for anything else in a 2D isometric engine.
-pub struct TransformFeedback<T: Clone>{
-    name: GLuint,
-    data: Vec<T>
+/// Call `glViewport`, so that NDU map onto the region of the window given by `vp`.
+pub fn set_viewport(vp: &camera::Viewport) {
+    unsafe {
+        trace!(gl::Viewport(vp.x.0 as GLint, vp.y.0 as GLint,
+                            vp.width.0 as GLsizei, vp.height.0 as GLsizei));
+    }
 }
 
-impl<T: Clone> TransformFeedback<T> {
+// Whether `debug_callback` is installed in the current context.
+static DEBUG_OUTPUT: AtomicBool = ATOMIC_BOOL_INIT;
 
-    /// Create a new TransformFeedback buffer with room for `count` vertices. Initialize each of
-    /// these vertices with `init` in main memory (but not on the GPU).
-    pub fn new(count: usize, init: T) -> TransformFeedback<T> {
-        let gl_xfb = unsafe {
-            let mut gl_xfb = 0;
+// What the engine is doing with the GL, innermost last (see `operation`).
+thread_local!(static OPERATIONS: RefCell<Vec<String>> = RefCell::new(vec![]));
 
-            trace!(gl::GenBuffers(1, &mut gl_xfb));
+/// Names what the engine is doing with the GL until it's dropped (see `operation`).
+pub struct Operation {
+    pushed: bool,
+}
 
-            trace!(gl::BindBuffer(gl::ARRAY_BUFFER, gl_xfb));
+impl Drop for Operation {
+    fn drop(&mut self) {
+        if self.pushed {
+            OPERATIONS.with(|ops| ops.borrow_mut().pop());
+        }
+    }
+}
 
-            trace!(gl::BufferData(
-                gl::ARRAY_BUFFER,
-                (mem::size_of::<T>() * count) as GLsizeiptr,
-                ptr::null(),
-                gl::STATIC_READ
-            ));
+/// Say that the GL calls made until the result is dropped are `what`, done to `subject`, e.g.
+/// `operation("uploading sheet", &path)`. Messages from the GL's debug output name the innermost
+/// operation, which says far more than the call which caused them. The subject is only formatted
+/// while debug output is on.
+pub fn operation(what: &'static str, subject: &fmt::Display) -> Operation {
+    if !DEBUG_OUTPUT.load(Ordering::Relaxed) {
+        return Operation { pushed: false }
+    }
 
-            gl_xfb
-        };
+    OPERATIONS.with(|ops| ops.borrow_mut().push(format!("{} '{}'", what, subject)));
 
-        TransformFeedback {
-            name: gl_xfb,
-            data: iter::repeat(init).take(count).collect(),
+    Operation { pushed: true }
+}
+
+// Log a message from the GL's debug output, along with what the engine was doing at the time.
+extern "system" fn debug_callback(source: GLenum, typ: GLenum, id: GLuint, severity: GLenum,
+                                  length: GLsizei, message: *const GLchar,
+                                  _user: *mut GLvoid) {
+
+    let message = unsafe {
+        if length < 0 {
+            ffi::CStr::from_ptr(message).to_string_lossy().into_owned()
+        } else {
+            let bytes = slice::from_raw_parts(message as *const u8, length as usize);
+            String::from_utf8_lossy(bytes).into_owned()
         }
+    };
+
+    let source = match source {
+        gl::DEBUG_SOURCE_API             => "api",
+        gl::DEBUG_SOURCE_WINDOW_SYSTEM   => "window system",
+        gl::DEBUG_SOURCE_SHADER_COMPILER => "shader compiler",
+        gl::DEBUG_SOURCE_THIRD_PARTY     => "third party",
+        gl::DEBUG_SOURCE_APPLICATION     => "application",
+        _                                => "other",
+    };
+
+    let typ = match typ {
+        gl::DEBUG_TYPE_ERROR               => "error",
+        gl::DEBUG_TYPE_DEPRECATED_BEHAVIOR => "deprecated",
+        gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR  => "undefined behavior",
+        gl::DEBUG_TYPE_PORTABILITY         => "portability",
+        gl::DEBUG_TYPE_PERFORMANCE         => "performance",
+        _                                  => "other",
+    };
+
+    let during = OPERATIONS.with(|ops| match ops.borrow().last() {
+        Some(op) => format!(" (while {})", op),
+        None     => String::new(),
+    });
+
+    match severity {
+        gl::DEBUG_SEVERITY_HIGH => error!(target: logging::GFX, "GL {} {} {}: {}{}",
+                                          source, typ, id, message, during),
+
+        gl::DEBUG_SEVERITY_MEDIUM => warn!(target: logging::GFX, "GL {} {} {}: {}{}",
+                                           source, typ, id, message, during),
+
+        gl::DEBUG_SEVERITY_LOW => info!(target: logging::GFX, "GL {} {} {}: {}{}",
+                                        source, typ, id, message, during),
+
+        _ => debug!(target: logging::GFX, "GL {} {} {}: {}{}", source, typ, id, message, during),
     }
+}
 
-    /// Read the contents of the buffer into main memory and a reference to them. This buffer must
-    /// be bound before calling read.
-    pub fn read<'x>(&'x mut self) -> &'x [T] {
-        unsafe {
-            trace!(gl::GetBufferSubData(
-                gl::TRANSFORM_FEEDBACK_BUFFER,
-                0,
-                (mem::size_of::<T>() * self.data.len()) as GLsizeiptr,
-                self.data.as_ptr() as *mut GLvoid
-            ));
-        }
+// Route the current context's debug output to `debug_callback`, if it has any. Messages are
+// delivered synchronously, so that they arrive during the operation which caused them.
+fn install_debug_output(caps: &Capabilities) {
+    if !caps.debug_output {
+        warn!(target: logging::GFX, "debug output was asked for, but the context doesn't have \
+                                     KHR_debug; build with trace_gl to check for errors instead");
 
-        &self.data
+        DEBUG_OUTPUT.store(false, Ordering::Relaxed);
+        return
     }
 
-    /// Bind this buffer to `TRANSFORM_FEEDBACK_BUFFER`.
-    pub fn bind(&self) {
-        unsafe { trace!(gl::BindBufferBase(gl::TRANSFORM_FEEDBACK_BUFFER, 0, self.name)) }
+    unsafe {
+        gl::DebugMessageCallback(debug_callback, ptr::null());
+
+        gl::Enable(gl::DEBUG_OUTPUT);
+        gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
     }
+
+    DEBUG_OUTPUT.store(true, Ordering::Relaxed);
 }
 
-/// A linked OpenGL shader program object.
-pub struct ShaderProgram(GLuint);
+/// The kind of OpenGL context to ask for (see `ContextBuilder::version`).
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum GLVersion {
+    /// OpenGL 3.3 with the core profile, which the engine's renderers are written against.
+    Core33,
 
-impl ShaderProgram {
-    /// Link several `Shader`s into a `ShaderProgram`.
-    pub fn new(shaders: &[Shader]) -> Result<ShaderProgram, LinkError> {
+    /// OpenGL 2.1, for drivers which offer nothing newer. The engine's shaders need GLSL 1.50, so
+    /// very little works on it; check `capabilities` before relying on anything.
+    Legacy21,
+}
 
-        Ok( ShaderProgram( unsafe {
-            let gl_prog = trace!(gl::CreateProgram());
+/// What the current OpenGL context supports, as found out when it was created (see
+/// `capabilities`). Renderers can use this to pick between code paths.
+#[derive(Debug,Copy,Clone)]
+pub struct Capabilities {
+    /// The version which the driver actually provided, as (major, minor).
+    pub version: (u32, u32),
 
-            for s in shaders.iter() {
-                // Attach shaders for linking.
-                trace!(gl::AttachShader(gl_prog, s.0));
-            }
+    /// Whether the context has the core profile, so that deprecated functionality is gone.
+    pub core: bool,
 
-            try!(ShaderProgram::link(gl_prog));
+    /// Whether the context is a debug context.
+    pub debug: bool,
 
-            for s in shaders.iter() {
-                // Detach shaders so they can be deleted.
-                trace!(gl::DetachShader(gl_prog, s.0))
-            }
+    /// The number of bits in the window's depth buffer.
+    pub depth_bits: u32,
+
+    /// The number of bits in the window's stencil buffer.
+    pub stencil_bits: u32,
+
+    /// The number of samples per pixel in the window, or 0 if it isn't multisampled.
+    pub samples: u32,
+
+    /// Whether geometry shaders are available (GL 3.2), as `sprite::ReleaseRenderer` needs.
+    pub geometry_shaders: bool,
+
+    /// Whether vertex attributes can advance per instance (GL 3.3 or `ARB_instanced_arrays`), as
+    /// `sprite::InstancedRenderer` needs.
+    pub instancing: bool,
+
+    /// Whether `GL_TIME_ELAPSED` queries and `glQueryCounter` timestamps are available (GL 3.3 or
+    /// `ARB_timer_query`), as `GpuTimer` and `TimestampTimer` need.
+    pub timer_queries: bool,
+
+    /// Whether the GL can report problems through a callback (GL 4.3 or `KHR_debug`), as
+    /// `ContextBuilder::debug` needs.
+    pub debug_output: bool,
+}
+
+impl Capabilities {
+    // What's known before there's a context: nothing.
+    fn none() -> Capabilities {
+        Capabilities {
+            version:          (0, 0),
+            core:             false,
+            debug:            false,
+            depth_bits:       0,
+            stencil_bits:     0,
+            samples:          0,
+            geometry_shaders: false,
+            instancing:       false,
+            timer_queries:    false,
+            debug_output:     false,
+        }
+    }
+
+    // Find out what the current context supports.
+    fn query() -> Capabilities {
+        let version = gl_string(gl::VERSION);
+
+        // The version string starts "major.minor", possibly after a prefix like "OpenGL ES ".
+        let mut nums = version.split(|c: char| !c.is_digit(10))
+                              .filter(|num| !num.is_empty())
+                              .map(|num| num.parse().unwrap_or(0));
+
+        let major = nums.next().unwrap_or(0);
+        let minor = nums.next().unwrap_or(0);
+
+        let at_least = |maj: u32, min: u32| (major, minor) >= (maj, min);
+
+        let exts = extensions(major);
+        let has  = |name: &str| exts.iter().any(|ext| ext == name);
+
+        let mut profile = 0;
+        let mut flags   = 0;
+        let mut depth   = 0;
+        let mut stencil = 0;
+        let mut samples = 0;
+
+        unsafe {
+            if at_least(3, 2) {
+                trace!(gl::GetIntegerv(gl::CONTEXT_PROFILE_MASK, &mut profile));
+            }
+
+            if at_least(3, 0) {
+                trace!(gl::GetIntegerv(gl::CONTEXT_FLAGS, &mut flags));
+
+                // The core profile has no DEPTH_BITS, so ask the window's framebuffer instead.
+                trace!(gl::GetFramebufferAttachmentParameteriv(gl::FRAMEBUFFER, gl::DEPTH,
+                    gl::FRAMEBUFFER_ATTACHMENT_DEPTH_SIZE, &mut depth));
+                trace!(gl::GetFramebufferAttachmentParameteriv(gl::FRAMEBUFFER, gl::STENCIL,
+                    gl::FRAMEBUFFER_ATTACHMENT_STENCIL_SIZE, &mut stencil));
+            } else {
+                trace!(gl::GetIntegerv(gl::DEPTH_BITS, &mut depth));
+                trace!(gl::GetIntegerv(gl::STENCIL_BITS, &mut stencil));
+            }
+
+            trace!(gl::GetIntegerv(gl::SAMPLES, &mut samples));
+        }
+
+        Capabilities {
+            version:          (major, minor),
+            core:             profile as GLenum & gl::CONTEXT_CORE_PROFILE_BIT != 0,
+            debug:            flags as GLenum & gl::CONTEXT_FLAG_DEBUG_BIT != 0,
+            depth_bits:       depth as u32,
+            stencil_bits:     stencil as u32,
+            samples:          samples as u32,
+            geometry_shaders: at_least(3, 2),
+            instancing:       at_least(3, 3) || has("GL_ARB_instanced_arrays"),
+            timer_queries:    at_least(3, 3) || has("GL_ARB_timer_query"),
+            debug_output:     at_least(4, 3) || has("GL_KHR_debug"),
+        }
+    }
+}
+
+thread_local!(static CAPABILITIES: Cell<Capabilities> = Cell::new(Capabilities::none()));
+
+/// What the current context supports. Before a `Context` has been created on this thread, this
+/// says nothing is supported.
+pub fn capabilities() -> Capabilities {
+    CAPABILITIES.with(|caps| caps.get())
+}
+
+// Call `glGetString`, returning an empty string if the GL has nothing to say.
+fn gl_string(name: GLenum) -> String {
+    unsafe {
+        let raw = trace!(gl::GetString(name));
+
+        if raw.is_null() {
+            String::new()
+        } else {
+            ffi::CStr::from_ptr(raw as *const _).to_string_lossy().into_owned()
+        }
+    }
+}
+
+// The names of the extensions which the current context supports. GL 3 lists them one at a time,
+// and the core profile has no way to get them all in one string.
+fn extensions(major: u32) -> Vec<String> {
+    if major < 3 {
+        return gl_string(gl::EXTENSIONS).split_whitespace().map(String::from).collect()
+    }
+
+    let mut count = 0;
+    unsafe { trace!(gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut count)) }
+
+    (0..count as GLuint).filter_map(|i| unsafe {
+        let raw = trace!(gl::GetStringi(gl::EXTENSIONS, i));
+
+        if raw.is_null() {
+            None
+        } else {
+            Some(ffi::CStr::from_ptr(raw as *const _).to_string_lossy().into_owned())
+        }
+    }).collect()
+}
+
+// The values of SDL_GL_CONTEXT_PROFILE_MASK and SDL_GL_CONTEXT_FLAGS which SDL understands.
+const SDL_GL_CONTEXT_PROFILE_CORE:            i32 = 0x1;
+const SDL_GL_CONTEXT_DEBUG_FLAG:              i32 = 0x1;
+const SDL_GL_CONTEXT_FORWARD_COMPATIBLE_FLAG: i32 = 0x2;
+
+// Set an attribute of the windows and contexts SDL will create, warning if it isn't accepted.
+fn set_gl_attribute(attr: video::GLAttr, value: i32, what: &str) {
+    if !video::gl_set_attribute(attr, value) {
+        warn!(target: logging::GFX, "couldn't request {} = {}", what, value);
+    }
+}
+
+/// Configures the window and OpenGL context which `build` creates. Everything has to be chosen up
+/// front, since most of it can't be changed once the window exists.
+///
+/// By default the window is 1280x720 and not fullscreen, with vsync and without sRGB color
+/// handling, and the context is GL 3.3 core (falling back to 2.1 if the driver won't make one),
+/// with a 24 bit depth buffer, an 8 bit stencil buffer, no multisampling and no debug output.
+#[derive(Clone,Debug)]
+pub struct ContextBuilder {
+    title:      String,
+    width:      i32,
+    height:     i32,
+    fullscreen: bool,
+    vsync:      bool,
+    srgb:       bool,
+
+    version:  GLVersion,
+    fallback: bool,
+
+    depth_bits:   u8,
+    stencil_bits: u8,
+    samples:      u8,
+    debug:        bool,
+}
+
+impl ContextBuilder {
+    /// Start configuring a window with the given title, with the default settings.
+    pub fn new(title: &str) -> ContextBuilder {
+        ContextBuilder {
+            title:      From::from(title),
+            width:      1280,
+            height:     720,
+            fullscreen: false,
+            vsync:      true,
+            srgb:       false,
+
+            version:  GLVersion::Core33,
+            fallback: true,
+
+            depth_bits:   24,
+            stencil_bits: 8,
+            samples:      0,
+            debug:        false,
+        }
+    }
+
+    /// The window's resolution, in pixels.
+    pub fn resolution(&mut self, width: i32, height: i32) -> &mut ContextBuilder {
+        self.width  = width;
+        self.height = height;
+        self
+    }
+
+    /// Whether the window covers the whole screen.
+    pub fn fullscreen(&mut self, fullscreen: bool) -> &mut ContextBuilder {
+        self.fullscreen = fullscreen;
+        self
+    }
+
+    /// Whether buffer swaps wait for vertical refresh.
+    pub fn vsync(&mut self, vsync: bool) -> &mut ContextBuilder {
+        self.vsync = vsync;
+        self
+    }
+
+    /// Whether the window draws with sRGB color handling (see `srgb`).
+    pub fn srgb(&mut self, srgb: bool) -> &mut ContextBuilder {
+        self.srgb = srgb;
+        self
+    }
+
+    /// The kind of context to ask for first, and whether to fall back to `GLVersion::Legacy21` if
+    /// the driver won't make one.
+    pub fn version(&mut self, version: GLVersion, fallback: bool) -> &mut ContextBuilder {
+        self.version  = version;
+        self.fallback = fallback;
+        self
+    }
+
+    /// The number of bits in the window's depth and stencil buffers.
+    pub fn depth_stencil(&mut self, depth_bits: u8, stencil_bits: u8) -> &mut ContextBuilder {
+        self.depth_bits   = depth_bits;
+        self.stencil_bits = stencil_bits;
+        self
+    }
+
+    /// The number of samples per pixel for multisample antialiasing, or 0 for none.
+    pub fn samples(&mut self, samples: u8) -> &mut ContextBuilder {
+        self.samples = samples;
+        self
+    }
+
+    /// Whether to ask for a debug context, which reports errors and warnings in detail (at some
+    /// cost in speed). If the context has `KHR_debug`, its messages are logged under
+    /// `logging::GFX` at their severity, along with the engine's current `operation`; otherwise
+    /// the `trace_gl` feature is the way to find errors.
+    pub fn debug(&mut self, debug: bool) -> &mut ContextBuilder {
+        self.debug = debug;
+        self
+    }
+
+    /// Create the window and its (thread-local) OpenGL context. What the context turned out to
+    /// support is recorded for `capabilities`.
+    pub fn build(&self) -> Result<Context, String> {
+        use sdl2::video::{Window, OPENGL, FULLSCREEN};
+        use sdl2::video::WindowPos::*;
+
+        let flags = if self.fullscreen { OPENGL | FULLSCREEN } else { OPENGL };
+
+        // These describe the window's pixel format, so they have to be set before it's created.
+        set_gl_attribute(video::GLAttr::GLDepthSize, self.depth_bits as i32, "depth bits");
+        set_gl_attribute(video::GLAttr::GLStencilSize, self.stencil_bits as i32, "stencil bits");
+
+        let buffers = if self.samples > 0 { 1 } else { 0 };
+        set_gl_attribute(video::GLAttr::GLMultiSampleBuffers, buffers, "multisample buffers");
+        set_gl_attribute(video::GLAttr::GLMultiSampleSamples, self.samples as i32, "samples");
+
+        if self.srgb {
+            set_gl_attribute(video::GLAttr::GLFramebufferSRGBCapable, 1, "an sRGB framebuffer");
+        }
+
+        SRGB.store(self.srgb, Ordering::Relaxed);
+
+        let window = try!(Window::new(&self.title, PosCentered, PosCentered, self.width,
+                                      self.height, flags));
+
+        let (gl_ctx, version) = try!(self.create_gl_context(&window, self.version));
+
+        let ctx = Context {
+            window:  window,
+            gl_ctx:  gl_ctx,
+            builder: self.clone(),
+            version: version,
+        };
+
+        ctx.init();
+
+        Ok(ctx)
+    }
+
+    // Create a GL context for `window`, asking for `first` and then, if that fails and falling
+    // back is allowed, for GL 2.1.
+    fn create_gl_context(&self, window: &video::Window, first: GLVersion)
+        -> Result<(video::GLContext, GLVersion), String> {
+
+        let mut versions = vec![first];
+
+        if self.fallback && first != GLVersion::Legacy21 {
+            versions.push(GLVersion::Legacy21);
+        }
+
+        let mut last_err = String::new();
+
+        for &version in versions.iter() {
+            self.request(version);
+
+            match window.gl_create_context() {
+                Ok(gl_ctx) => return Ok((gl_ctx, version)),
+                Err(err)   => {
+                    warn!(target: logging::GFX, "couldn't create a {:?} context: {}", version, err);
+                    last_err = err;
+                },
+            }
+        }
+
+        Err(last_err)
+    }
+
+    // Set the attributes which ask for a `version` context.
+    fn request(&self, version: GLVersion) {
+        let (major, minor, profile) = match version {
+            GLVersion::Core33   => (3, 3, SDL_GL_CONTEXT_PROFILE_CORE),
+            GLVersion::Legacy21 => (2, 1, 0),
+        };
+
+        let mut flags = 0;
+
+        if self.debug {
+            flags |= SDL_GL_CONTEXT_DEBUG_FLAG;
+        }
+
+        // OS X only offers core contexts which are forward compatible.
+        if version == GLVersion::Core33 && cfg!(target_os = "macos") {
+            flags |= SDL_GL_CONTEXT_FORWARD_COMPATIBLE_FLAG;
+        }
+
+        set_gl_attribute(video::GLAttr::GLContextMajorVersion, major, "GL major version");
+        set_gl_attribute(video::GLAttr::GLContextMinorVersion, minor, "GL minor version");
+        set_gl_attribute(video::GLAttr::GLContextProfileMask, profile, "GL profile");
+        set_gl_attribute(video::GLAttr::GLContextFlags, flags, "GL context flags");
+    }
+}
+
+/// A RAII container for a window and its OpenGL context. This object needs to be around for as long
+/// as OpenGL is being used with that window.
+///
+/// In general the user shouldn't touch this and should instead use grafix::Context, which takes
+/// care of some SDL preliminaries. Maybe I'll jump through the necessary hoops to make this
+/// not-public at some point.
+#[allow(dead_code)] // The code isn't really dead, we're relying on drop being called.
+pub struct Context {
+    window: video::Window,
+    gl_ctx: video::GLContext,
+
+    // The settings it was built with, to make a new context with if this one is lost.
+    builder: ContextBuilder,
+
+    // The kind of context which the driver agreed to make.
+    version: GLVersion,
+}
+
+impl Context {
+    /// Create a new window with an associated (thread-local) OpenGL context.
+    pub fn new(title: &str, x_res: i32, y_res: i32) -> Result<Context, String> {
+        ContextBuilder::new(title).resolution(x_res, y_res).build()
+    }
+
+    /// Create a new window with an associated (thread-local) OpenGL context, choosing whether it
+    /// covers the whole screen, whether buffer swaps wait for vertical refresh, and whether it
+    /// draws with sRGB color handling (see `srgb`). See `ContextBuilder` for everything else.
+    pub fn with_options(title: &str, x_res: i32, y_res: i32, fullscreen: bool, vsync: bool,
+                        srgb: bool) -> Result<Context, String> {
+
+        ContextBuilder::new(title).resolution(x_res, y_res)
+                                  .fullscreen(fullscreen)
+                                  .vsync(vsync)
+                                  .srgb(srgb)
+                                  .build()
+    }
+
+    // Load the GL functions for the (new) context and get it ready for use.
+    fn init(&self) {
+        gl::load_with(|s| unsafe { mem::transmute(video::gl_get_proc_address(s)) });
+
+        let vsync = self.builder.vsync;
+
+        if !video::gl_set_swap_interval(if vsync { 1 } else { 0 }) {
+            warn!(target: logging::GFX, "couldn't set the swap interval (vsync = {})", vsync);
+        }
+
+        init_state();
+
+        let caps = Capabilities::query();
+        CAPABILITIES.with(|c| c.set(caps));
+
+        if self.builder.debug {
+            install_debug_output(&caps);
+        } else {
+            DEBUG_OUTPUT.store(false, Ordering::Relaxed);
+        }
+
+        GENERATION.fetch_add(1, Ordering::Relaxed);
+
+        info!(target: logging::GFX, "created a {:?} context: {:?}", self.version, caps);
+    }
+
+    /// The kind of context which the driver agreed to make. See `capabilities` for what it
+    /// actually supports.
+    pub fn version(&self) -> GLVersion {
+        self.version
+    }
+
+    /// Replace the window's OpenGL context with a new one, after the old one has been lost (a
+    /// driver reset, or a fullscreen toggle on some drivers). Every texture, buffer and program
+    /// made with the old context is gone; this changes `generation`, so that they aren't deleted
+    /// from the new context and so that a `device::Registry` knows to recreate them.
+    pub fn recreate(&mut self) -> Result<(), String> {
+        let (gl_ctx, version) = try!(self.builder.create_gl_context(&self.window, self.version));
+
+        self.gl_ctx  = gl_ctx;
+        self.version = version;
+
+        self.init();
+
+        info!(target: logging::GFX, "recreated the GL context (generation {})", generation());
+
+        Ok(())
+    }
+
+    /// Swap OpenGL buffers, drawing the frame to the screen.
+    pub fn draw_frame(&self) {
+        self.window.gl_swap_window();
+    }
+
+    /// The window's current title.
+    pub fn title(&self) -> String {
+        self.window.title()
+    }
+
+    /// Change the window's title.
+    pub fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+
+    /// Use `img` as the window's icon. BW and RGB images are expanded to RGBA, and any other
+    /// format is an error.
+    pub fn set_icon(&mut self, img: &png::Image) -> Result<(), String> {
+        use png::PixelsByColorType::*;
+        use sdl2::pixels::PixelFormatEnum;
+        use sdl2::surface::Surface;
+
+        let texels = img.width as usize * img.height as usize;
+
+        let mut rgba = Vec::with_capacity(texels * 4);
+
+        match img.pixels {
+            RGBA8(ref pix) => rgba.extend(pix.iter().cloned()),
+
+            RGB8(ref pix) => for px in pix.chunks(3) {
+                rgba.extend(px.iter().cloned());
+                rgba.push(255);
+            },
+
+            K8(ref pix) => for &k in pix.iter() {
+                rgba.extend([k, k, k, 255].iter().cloned());
+            },
+
+            _ => return Err(From::from("window icons must be either BW, RGB or RGBA")),
+        }
+
+        // SDL names packed formats by their layout within a native-endian u32, so bytes in R, G,
+        // B, A order are "ABGR" on a little-endian machine.
+        let format = if cfg!(target_endian = "little") {
+            PixelFormatEnum::ABGR8888
+        } else {
+            PixelFormatEnum::RGBA8888
+        };
+
+        let pitch  = img.width as i32 * 4;
+        let icon   = try!(Surface::from_data(&mut rgba[..], img.width as i32, img.height as i32,
+                                             pitch, format));
+
+        self.window.set_icon(&icon);
+
+        debug!(target: logging::GFX, "set a {}x{} window icon", img.width, img.height);
+
+        Ok(())
+    }
+}
+
+/// A 2D OpenGL Texture
+pub struct Tex2D {
+    id: GLuint,
+
+    // Size of the texture's storage on the GPU.
+    bytes: usize,
+
+    width:  u32,
+    height: u32,
+
+    // The context the texture was made in.
+    generation: usize,
+}
+
+impl Tex2D {
+    /// Create a `Tex2D` from a PNG, whose pixels are taken as they are. This is for textures which
+    /// hold data rather than colors, such as depth and normal maps.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `img` is not either BW (`K8`), RGB (`RGB8`), or RGBA (`RGBA8`).
+    pub fn from_png(img: &png::Image) -> Tex2D {
+        Tex2D::upload_png(img, false)
+    }
+
+    /// Create a `Tex2D` from a PNG of colors. If the window draws with sRGB color handling (see
+    /// `srgb`), the texture is stored as sRGB, so that sampling it gives linear colors. Otherwise
+    /// this is the same as `from_png`. BW images are always taken as they are.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `img` is not either BW (`K8`), RGB (`RGB8`), or RGBA (`RGBA8`).
+    pub fn from_png_srgb(img: &png::Image) -> Tex2D {
+        Tex2D::upload_png(img, srgb())
+    }
+
+    fn upload_png(img: &png::Image, srgb: bool) -> Tex2D {
+        use png::PixelsByColorType::*;
+
+        let (rgba, rgb) = if srgb {
+            (gl::SRGB8_ALPHA8, gl::SRGB8)
+        } else {
+            (gl::RGBA, gl::RGB)
+        };
+
+        let mut gl_texid = 0;
+        unsafe {
+            trace!(gl::GenTextures(1, &mut gl_texid));
+            bind_texture(None, gl::TEXTURE_2D, gl_texid);
+            trace!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as GLint));
+            trace!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as GLint));
+            trace!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint));
+            trace!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint));
+        }
+
+        let texels = img.width as usize * img.height as usize;
+
+        let bytes = match img.pixels {
+
+            RGBA8(ref pix) => unsafe {
+                trace!(gl::TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    rgba as GLint,
+                    img.width  as GLsizei,
+                    img.height as GLsizei,
+                    0,
+                    gl::RGBA,
+                    gl::UNSIGNED_BYTE,
+                    pix.as_ptr() as *const GLvoid,
+                ));
+
+                texels * 4
+            },
+
+            RGB8(ref pix) => unsafe {
+                trace!(gl::TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    rgb as GLint,
+                    img.width  as GLsizei,
+                    img.height as GLsizei,
+                    0,
+                    gl::RGB,
+                    gl::UNSIGNED_BYTE,
+                    pix.as_ptr() as *const GLvoid,
+                ));
+
+                texels * 3
+            },
+
+            K8(ref pix) => unsafe {
+                trace!(gl::TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    gl::RED as GLint,
+                    img.width  as GLsizei,
+                    img.height as GLsizei,
+                    0,
+                    gl::RED,
+                    gl::UNSIGNED_BYTE,
+                    pix.as_ptr() as *const GLvoid,
+                ));
+
+                texels
+            },
+
+            _ => panic!("PNGs must be either BW, RGB or RGBA!"),
+
+        };
+
+        Tex2D {
+            id:         gl_texid,
+            bytes:      bytes,
+            width:      img.width,
+            height:     img.height,
+            generation: generation(),
+        }
+    }
+
+    /// Create an uninitialized 24-bit depth texture, for rendering into with a `Framebuffer`.
+    /// Sampling it gives the depth in the red channel.
+    pub fn new_depth(width: u32, height: u32) -> Tex2D {
+        let clamp = gl::CLAMP_TO_EDGE as GLint;
+
+        let mut gl_texid = 0;
+        unsafe {
+            trace!(gl::GenTextures(1, &mut gl_texid));
+            bind_texture(None, gl::TEXTURE_2D, gl_texid);
+            trace!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, clamp));
+            trace!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, clamp));
+            trace!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint));
+            trace!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint));
+
+            trace!(gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::DEPTH_COMPONENT24 as GLint,
+                width  as GLsizei,
+                height as GLsizei,
+                0,
+                gl::DEPTH_COMPONENT,
+                gl::FLOAT,
+                ptr::null(),
+            ));
+        }
+
+        Tex2D {
+            id:         gl_texid,
+            bytes:      width as usize * height as usize * 3,
+            width:      width,
+            height:     height,
+            generation: generation(),
+        }
+    }
+
+    /// Create an uninitialized RGBA texture, for rendering into with a `Framebuffer`. It's stored
+    /// as sRGB if the window is (see `srgb`), so that blending into it is done on linear colors.
+    pub fn new_color(width: u32, height: u32) -> Tex2D {
+        let clamp  = gl::CLAMP_TO_EDGE as GLint;
+        let format = if srgb() { gl::SRGB8_ALPHA8 } else { gl::RGBA8 };
+
+        let mut gl_texid = 0;
+        unsafe {
+            trace!(gl::GenTextures(1, &mut gl_texid));
+            bind_texture(None, gl::TEXTURE_2D, gl_texid);
+            trace!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, clamp));
+            trace!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, clamp));
+            trace!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint));
+            trace!(gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint));
+
+            trace!(gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                format as GLint,
+                width  as GLsizei,
+                height as GLsizei,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                ptr::null(),
+            ));
+        }
+
+        Tex2D {
+            id:         gl_texid,
+            bytes:      width as usize * height as usize * 4,
+            width:      width,
+            height:     height,
+            generation: generation(),
+        }
+    }
+
+    /// The amount of GPU memory used by this texture, in bytes. This is what the pixel data
+    /// occupies in its uploaded format; the driver may round it up.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// The width of the texture, in texels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The height of the texture, in texels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Bind this texture to `GL_TEXTURE_2D` for the given texture unit. This calls
+    /// `glActiveTexture` and `glBindTexture` only if they'd change anything.
+    pub fn bind_to_unit(&self, unit: usize) {
+        bind_texture(Some(unit), gl::TEXTURE_2D, self.id);
+    }
+}
+
+impl Drop for Tex2D {
+    /// Call `glDeleteTextures` on this texture.
+    fn drop(&mut self) {
+        if is_current(self.generation) {
+            forget_texture(self.id);
+            unsafe { trace!(gl::DeleteTextures(1, &self.id)) }
+        }
+    }
+}
+
+/// The kind of texels a `Tex2DArray` holds.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum ArrayFormat {
+    /// RGBA colors, stored as sRGB if the window draws with sRGB color handling (see `srgb`).
+    Color,
+
+    /// A single red channel of data, e.g. depth.
+    Red,
+}
+
+/// A 2D array texture: a stack of 2D textures of the same size ("layers") which are bound as one,
+/// so that a shader can choose between them for each sprite instead of them being bound in turn.
+pub struct Tex2DArray {
+    id: GLuint,
+
+    internal: GLenum,
+    format:   GLenum,
+
+    // Bytes per texel, in `format`.
+    texel_bytes: usize,
+
+    width:  u32,
+    height: u32,
+    layers: u32,
+
+    // The context the texture was made in.
+    generation: usize,
+}
+
+impl Tex2DArray {
+    /// Create an array of `layers` uninitialized `width` x `height` textures holding `format`.
+    pub fn new(width: u32, height: u32, layers: u32, format: ArrayFormat) -> Tex2DArray {
+        let (internal, format, texel_bytes) = match format {
+            ArrayFormat::Color if srgb() => (gl::SRGB8_ALPHA8, gl::RGBA, 4),
+            ArrayFormat::Color           => (gl::RGBA8, gl::RGBA, 4),
+            ArrayFormat::Red             => (gl::R8, gl::RED, 1),
+        };
+
+        let clamp = gl::CLAMP_TO_BORDER as GLint;
+
+        let mut gl_texid = 0;
+        unsafe {
+            trace!(gl::GenTextures(1, &mut gl_texid));
+            bind_texture(None, gl::TEXTURE_2D_ARRAY, gl_texid);
+            trace!(gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_S, clamp));
+            trace!(gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_T, clamp));
+            trace!(gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MIN_FILTER,
+                                     gl::NEAREST as GLint));
+            trace!(gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MAG_FILTER,
+                                     gl::NEAREST as GLint));
+        }
+
+        let mut array = Tex2DArray {
+            id:          gl_texid,
+            internal:    internal,
+            format:      format,
+            texel_bytes: texel_bytes,
+            width:       width,
+            height:      height,
+            layers:      0,
+            generation:  generation(),
+        };
+
+        array.resize(layers);
+        array
+    }
+
+    /// Change the number of layers to `layers`. The layers which are kept keep their texels; new
+    /// ones are uninitialized. This reads the texels back from the GPU, so it's best done rarely,
+    /// e.g. by doubling the number of layers each time the array fills up.
+    pub fn resize(&mut self, layers: u32) {
+        let kept  = cmp::min(self.layers, layers);
+        let layer = self.width as usize * self.height as usize * self.texel_bytes;
+
+        let mut texels: Vec<u8> = vec![0; layer * self.layers as usize];
+
+        unsafe {
+            bind_texture(None, gl::TEXTURE_2D_ARRAY, self.id);
+            trace!(gl::PixelStorei(gl::PACK_ALIGNMENT, 1));
+            trace!(gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1));
+
+            if kept > 0 {
+                trace!(gl::GetTexImage(gl::TEXTURE_2D_ARRAY, 0, self.format, gl::UNSIGNED_BYTE,
+                                       texels.as_mut_ptr() as *mut GLvoid));
+            }
+
+            trace!(gl::TexImage3D(
+                gl::TEXTURE_2D_ARRAY,
+                0,
+                self.internal as GLint,
+                self.width  as GLsizei,
+                self.height as GLsizei,
+                layers      as GLsizei,
+                0,
+                self.format,
+                gl::UNSIGNED_BYTE,
+                ptr::null(),
+            ));
+
+            if kept > 0 {
+                trace!(gl::TexSubImage3D(
+                    gl::TEXTURE_2D_ARRAY,
+                    0,
+                    0, 0, 0,
+                    self.width  as GLsizei,
+                    self.height as GLsizei,
+                    kept        as GLsizei,
+                    self.format,
+                    gl::UNSIGNED_BYTE,
+                    texels.as_ptr() as *const GLvoid,
+                ));
+            }
+        }
+
+        self.layers = layers;
+    }
+
+    /// Fill layer `layer` with the pixels of `img`, which must be the same size as the array.
+    /// Channels which `img` lacks are filled in as OpenGL does: a BW image gives red, and opaque
+    /// alpha. Channels which the array lacks are dropped.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `img` is the wrong size, if `layer` is out of range, or if
+    /// `img` is not either BW (`K8`), RGB (`RGB8`), or RGBA (`RGBA8`).
+    pub fn upload_png(&self, layer: u32, img: &png::Image) {
+        use png::PixelsByColorType::*;
+
+        assert!(img.width == self.width && img.height == self.height, "wrong size for the array");
+        assert!(layer < self.layers, "no such layer");
+
+        let (format, pix) = match img.pixels {
+            RGBA8(ref pix) => (gl::RGBA, pix),
+            RGB8(ref pix)  => (gl::RGB, pix),
+            K8(ref pix)    => (gl::RED, pix),
+            _              => panic!("PNGs must be either BW, RGB or RGBA!"),
+        };
+
+        unsafe {
+            bind_texture(None, gl::TEXTURE_2D_ARRAY, self.id);
+            trace!(gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1));
+            trace!(gl::TexSubImage3D(
+                gl::TEXTURE_2D_ARRAY,
+                0,
+                0, 0, layer as GLint,
+                img.width  as GLsizei,
+                img.height as GLsizei,
+                1,
+                format,
+                gl::UNSIGNED_BYTE,
+                pix.as_ptr() as *const GLvoid,
+            ));
+        }
+    }
+
+    /// The amount of GPU memory used by one layer of the array, in bytes.
+    pub fn layer_bytes(&self) -> usize {
+        self.width as usize * self.height as usize * self.texel_bytes
+    }
+
+    /// The amount of GPU memory used by the whole array, in bytes.
+    pub fn bytes(&self) -> usize {
+        self.layer_bytes() * self.layers as usize
+    }
+
+    /// The width of each layer, in texels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The height of each layer, in texels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The number of layers.
+    pub fn layers(&self) -> u32 {
+        self.layers
+    }
+
+    /// Bind this texture to `GL_TEXTURE_2D_ARRAY` for the given texture unit, unless it's already
+    /// bound there.
+    pub fn bind_to_unit(&self, unit: usize) {
+        bind_texture(Some(unit), gl::TEXTURE_2D_ARRAY, self.id);
+    }
+}
+
+impl Drop for Tex2DArray {
+    /// Call `glDeleteTextures` on this texture.
+    fn drop(&mut self) {
+        if is_current(self.generation) {
+            forget_texture(self.id);
+            unsafe { trace!(gl::DeleteTextures(1, &self.id)) }
+        }
+    }
+}
+
+/// An OpenGL framebuffer object, which is drawn into instead of the window, e.g. for a shadow map,
+/// a minimap, or a frame which is post-processed before it's shown.
+///
+/// A framebuffer has a color texture, a depth texture, or both, which can be sampled once drawing
+/// into the framebuffer is finished.
+pub struct Framebuffer {
+    id:    GLuint,
+    color: Option<Tex2D>,
+    depth: Option<Tex2D>,
+
+    width:  u32,
+    height: u32,
+
+    // The context the framebuffer was made in.
+    generation: usize,
+}
+
+impl Framebuffer {
+    /// Create a framebuffer which draws both color and depth, into `width` x `height` textures.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the problem if the driver reports that the framebuffer is
+    /// incomplete.
+    pub fn new(width: u32, height: u32) -> Result<Framebuffer, String> {
+        Framebuffer::with_attachments(width, height, true, true)
+    }
+
+    /// Create a framebuffer which draws nothing but color, into a `width` x `height` texture. Depth
+    /// testing has no effect while drawing into it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the problem if the driver reports that the framebuffer is
+    /// incomplete.
+    pub fn color_only(width: u32, height: u32) -> Result<Framebuffer, String> {
+        Framebuffer::with_attachments(width, height, true, false)
+    }
+
+    /// Create a framebuffer which draws nothing but depth, into a `width` x `height` texture.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the problem if the driver reports that the framebuffer is
+    /// incomplete.
+    pub fn depth_only(width: u32, height: u32) -> Result<Framebuffer, String> {
+        Framebuffer::with_attachments(width, height, false, true)
+    }
+
+    fn with_attachments(width: u32, height: u32, color: bool, depth: bool)
+        -> Result<Framebuffer, String> {
+
+        let mut gl_fbo = 0;
+        unsafe { trace!(gl::GenFramebuffers(1, &mut gl_fbo)) }
+
+        let mut fbo = Framebuffer {
+            id:         gl_fbo,
+            color:      if color { Some(Tex2D::new_color(width, height)) } else { None },
+            depth:      if depth { Some(Tex2D::new_depth(width, height)) } else { None },
+            width:      width,
+            height:     height,
+            generation: generation(),
+        };
+
+        try!(fbo.attach());
+
+        Ok(fbo)
+    }
+
+    /// Change the size of the framebuffer, e.g. to follow the window's. The textures are replaced
+    /// with new, uninitialized ones, so anything drawn is lost, and textures previously bound for
+    /// sampling must be bound again.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the problem if the driver reports that the resized framebuffer is
+    /// incomplete.
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<(), String> {
+        if width == self.width && height == self.height {
+            return Ok(())
+        }
+
+        if self.color.is_some() {
+            self.color = Some(Tex2D::new_color(width, height));
+        }
+
+        if self.depth.is_some() {
+            self.depth = Some(Tex2D::new_depth(width, height));
+        }
+
+        self.width  = width;
+        self.height = height;
+
+        self.attach()
+    }
+
+    // Attach the textures to the framebuffer, and check that the driver can draw into them.
+    fn attach(&mut self) -> Result<(), String> {
+        let color_id = self.color.as_ref().map_or(0, |tex| tex.id);
+        let depth_id = self.depth.as_ref().map_or(0, |tex| tex.id);
+
+        let status = unsafe {
+            trace!(gl::BindFramebuffer(gl::FRAMEBUFFER, self.id));
+            trace!(gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D,
+                                            color_id, 0));
+            trace!(gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_2D,
+                                            depth_id, 0));
+
+            let buffer = if self.color.is_some() { gl::COLOR_ATTACHMENT0 } else { gl::NONE };
+
+            trace!(gl::DrawBuffer(buffer));
+            trace!(gl::ReadBuffer(buffer));
+
+            let status = trace!(gl::CheckFramebufferStatus(gl::FRAMEBUFFER));
+            trace!(gl::BindFramebuffer(gl::FRAMEBUFFER, 0));
+
+            status
+        };
+
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            return Err(format!("incomplete framebuffer (status {:#x})", status))
+        }
+
+        Ok(())
+    }
+
+    /// Draw into this framebuffer, and set the viewport to cover all of it.
+    pub fn bind(&self) {
+        unsafe {
+            trace!(gl::BindFramebuffer(gl::FRAMEBUFFER, self.id));
+            trace!(gl::Viewport(0, 0, self.width as GLsizei, self.height as GLsizei));
+        }
+    }
+
+    /// Go back to drawing into the window. The viewport is left as it was, and should be reset
+    /// with `set_viewport`.
+    pub fn unbind(&self) {
+        unsafe { trace!(gl::BindFramebuffer(gl::FRAMEBUFFER, 0)) }
+    }
+
+    /// Copy the framebuffer's color onto the window, stretched to fill `dest`. Pixels are copied
+    /// without filtering, so pixel art stays crisp when it's scaled up by a whole number. Nothing
+    /// is copied from a framebuffer without color. Afterwards the window is drawn into, as after
+    /// `unbind`.
+    pub fn blit_to_default(&self, dest: &camera::Viewport) {
+        if self.color.is_none() {
+            return
+        }
+
+        unsafe {
+            trace!(gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.id));
+            trace!(gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0));
+            trace!(gl::BlitFramebuffer(
+                0, 0, self.width as GLint, self.height as GLint,
+                dest.x.0 as GLint, dest.y.0 as GLint,
+                (dest.x.0 + dest.width.0) as GLint, (dest.y.0 + dest.height.0) as GLint,
+                gl::COLOR_BUFFER_BIT,
+                gl::NEAREST,
+            ));
+            trace!(gl::BindFramebuffer(gl::FRAMEBUFFER, 0));
+        }
+    }
+
+    /// The width of the framebuffer, in texels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The height of the framebuffer, in texels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The amount of GPU memory used by the framebuffer's textures, in bytes.
+    pub fn bytes(&self) -> usize {
+        self.color.as_ref().map_or(0, |tex| tex.bytes()) +
+            self.depth.as_ref().map_or(0, |tex| tex.bytes())
+    }
+
+    /// The texture which the framebuffer's color is drawn into, unless it was made without one.
+    pub fn color_texture(&self) -> Option<&Tex2D> {
+        self.color.as_ref()
+    }
+
+    /// The texture which the framebuffer's depth is drawn into, unless it was made without one.
+    pub fn depth_texture(&self) -> Option<&Tex2D> {
+        self.depth.as_ref()
+    }
+}
+
+impl Drop for Framebuffer {
+    /// Call `glDeleteFramebuffers` on this framebuffer. The textures are deleted separately.
+    fn drop(&mut self) {
+        if is_current(self.generation) {
+            unsafe { trace!(gl::DeleteFramebuffers(1, &self.id)) }
+        }
+    }
+}
+
+/// An error that occurred while compiling a shader.
+#[derive(Debug)]
+pub struct CompileError {
+    /// The info log retrieved from OpenGL, which may describe the cause of the error.
+    pub info_log: String
+}
+
+/// An error that occurred while linking a shader program.
+#[derive(Debug)]
+pub struct LinkError {
+    /// The info log retrieved from OpenGL, which may describe the cause of the error.
+    pub info_log: String
+}
+
+/// An error that occurred while making a shader program from files (see
+/// `ShaderProgram::from_files`).
+#[derive(Debug)]
+pub enum ShaderFileError {
+    /// A file couldn't be read.
+    Read(PathBuf, io::Error),
+
+    /// A file's extension doesn't say which stage its shader is for (see `ShaderStage::from_path`).
+    UnknownStage(PathBuf),
+
+    /// An `#include` in a file, on the given line, is malformed or names a file which couldn't be
+    /// found (see `Preprocessor`).
+    Include(PathBuf, usize, String),
+
+    /// The shader in a file didn't compile.
+    Compile(PathBuf, CompileError),
+
+    /// The shaders didn't link.
+    Link(LinkError),
+}
+
+/// The stage of the pipeline which a `Shader` runs at.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum ShaderStage {
+    /// A vertex shader, in a `.vtx` file.
+    Vertex,
+
+    /// A geometry shader, in a `.geo` file.
+    Geometry,
+
+    /// A fragment shader, in a `.frg` file.
+    Fragment,
+}
+
+impl ShaderStage {
+    /// The stage of the shader in the file at `path`, going by its extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<ShaderStage> {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("vtx") => Some(ShaderStage::Vertex),
+            Some("geo") => Some(ShaderStage::Geometry),
+            Some("frg") => Some(ShaderStage::Fragment),
+            _           => None,
+        }
+    }
+
+    fn gl_enum(self) -> GLenum {
+        match self {
+            ShaderStage::Vertex   => gl::VERTEX_SHADER,
+            ShaderStage::Geometry => gl::GEOMETRY_SHADER,
+            ShaderStage::Fragment => gl::FRAGMENT_SHADER,
+        }
+    }
+}
+
+/// A minimal GLSL preprocessor, which does what GLSL's own can't before a shader is compiled:
+///
+/// * An `#include "name"` line is replaced by the file called `name`, from the search path or the
+///   built-in files. Each file is only included once per shader, however many times it's named, so
+///   shared files can include each other without guards.
+/// * The defines given to `define` are injected just after the `#version` line.
+///
+/// `#line` directives keep the line numbers in compile errors right. The main file is source string
+/// 0, and each included file is numbered from 1 in the order it's first reached.
+#[derive(Clone,Debug)]
+pub struct Preprocessor {
+    search_path: Vec<PathBuf>,
+    builtins:    Vec<(String, &'static str)>,
+    defines:     Vec<(String, String)>,
+}
+
+impl Preprocessor {
+    /// A preprocessor with an empty search path and no defines.
+    pub fn new() -> Preprocessor {
+        Preprocessor { search_path: vec![], builtins: vec![], defines: vec![] }
+    }
+
+    /// Look for included files in `dir`, after the directories added before it.
+    pub fn search<P: AsRef<Path>>(&mut self, dir: P) -> &mut Preprocessor {
+        self.search_path.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Let shaders include `src` as `name`, if there's no file called `name` in the search path.
+    /// This is for files which are compiled into the binary.
+    pub fn builtin(&mut self, name: &str, src: &'static str) -> &mut Preprocessor {
+        self.builtins.push((From::from(name), src));
+        self
+    }
+
+    /// Define `name` as `value` in every shader, replacing any earlier define of `name`.
+    pub fn define<V: fmt::Display>(&mut self, name: &str, value: V) -> &mut Preprocessor {
+        let value    = value.to_string();
+        let existing = self.defines.iter().position(|&(ref n, _)| n == name);
+
+        match existing {
+            Some(i) => self.defines[i].1 = value,
+            None    => self.defines.push((From::from(name), value)),
+        }
+
+        self
+    }
+
+    /// Expand `src`, the source of the shader at `path` (which is only used in errors).
+    pub fn process<P: AsRef<Path>>(&self, path: P, src: &str) -> Result<String, ShaderFileError> {
+        let path = path.as_ref();
+
+        // The `#version` line has to come before anything but comments, so the defines go after
+        // it, or at the very top if there isn't one.
+        let version = src.lines().position(|l| l.trim_left().starts_with("#version"));
+
+        let (head, body, first) = match version {
+            Some(i) => {
+                let head: Vec<&str> = src.lines().take(i + 1).collect();
+                let body: Vec<&str> = src.lines().skip(i + 1).collect();
+
+                (head, body, i + 2)
+            },
+            None => (vec![], src.lines().collect(), 1),
+        };
+
+        let mut out = String::new();
+
+        for line in head {
+            out.push_str(line);
+            out.push('\n');
+        }
+
+        for &(ref name, ref value) in self.defines.iter() {
+            out.push_str(&format!("#define {} {}\n", name, value));
+        }
+
+        out.push_str(&format!("#line {} 0\n", first));
+
+        let mut included = BTreeSet::new();
+        let mut sources  = 0;
+
+        try!(self.expand(path, &body, first, 0, &mut out, &mut included, &mut sources));
+
+        Ok(out)
+    }
+
+    // Append `lines`, which start from line `first` of source string `source` (the file at `path`),
+    // to `out`, expanding their includes. `included` is every file included so far, and `sources`
+    // is the number of them.
+    fn expand(&self, path: &Path, lines: &[&str], first: usize, source: usize, out: &mut String,
+              included: &mut BTreeSet<String>, sources: &mut usize)
+        -> Result<(), ShaderFileError> {
+
+        for (i, line) in lines.iter().enumerate() {
+            let directive = line.trim();
+
+            if !directive.starts_with("#include") {
+                out.push_str(line);
+                out.push('\n');
+                continue
+            }
+
+            let bad = || ShaderFileError::Include(path.to_path_buf(), first + i,
+                                                  From::from(directive));
+
+            let name = directive["#include".len()..].trim();
+
+            if name.len() < 2 || !name.starts_with('"') || !name.ends_with('"') {
+                return Err(bad())
+            }
+
+            let name = &name[1..name.len() - 1];
+
+            if included.insert(From::from(name)) {
+                let (inc_path, inc_src) = match self.find(name) {
+                    Some(found) => try!(found),
+                    None        => return Err(bad()),
+                };
+
+                *sources += 1;
+                let inc_source = *sources;
+
+                out.push_str(&format!("#line 1 {}\n", inc_source));
+
+                let inc_lines: Vec<&str> = inc_src.lines().collect();
+                try!(self.expand(&inc_path, &inc_lines, 1, inc_source, out, included, sources));
+            }
+
+            out.push_str(&format!("#line {} {}\n", first + i + 1, source));
+        }
+
+        Ok(())
+    }
+
+    // Find the file called `name`, in the search path or among the built-in files.
+    fn find(&self, name: &str) -> Option<Result<(PathBuf, Cow<'static, str>), ShaderFileError>> {
+        for dir in self.search_path.iter() {
+            let path = dir.join(name);
+
+            if !path.is_file() {
+                continue
+            }
+
+            let mut src = String::new();
+
+            return Some(match fs::File::open(&path).and_then(|mut f| f.read_to_string(&mut src)) {
+                Ok(_)    => Ok((path, Cow::Owned(src))),
+                Err(err) => Err(ShaderFileError::Read(path, err)),
+            })
+        }
+
+        self.builtins.iter().find(|&&(ref n, _)| n == name).map(|&(_, src)| {
+            Ok((PathBuf::from(name), Cow::Borrowed(src)))
+        })
+    }
+}
+
+/// A compiled OpenGL shader object. Its only purpose is to be linked with other `Shader`s into a
+/// `ShaderProgram`.
+pub struct Shader(GLuint);
+
+impl Shader {
+    /// Create a new shader for `stage` from a source string.
+    pub fn with_stage(stage: ShaderStage, src: &str) -> Result<Shader, CompileError> {
+        Shader::new(src, stage.gl_enum())
+    }
+
+    /// Create a new shader from the file at `path`, whose extension gives its stage (see
+    /// `ShaderStage::from_path`).
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Shader, ShaderFileError> {
+        let path = path.as_ref();
+
+        let stage = match ShaderStage::from_path(path) {
+            Some(stage) => stage,
+            None        => return Err(ShaderFileError::UnknownStage(path.to_path_buf())),
+        };
+
+        let mut src = String::new();
+
+        if let Err(err) = fs::File::open(path).and_then(|mut f| f.read_to_string(&mut src)) {
+            return Err(ShaderFileError::Read(path.to_path_buf(), err))
+        }
+
+        Shader::with_stage(stage, &src).map_err(|err| {
+            ShaderFileError::Compile(path.to_path_buf(), err)
+        })
+    }
+
+    /// Create a new vertex shader from a source string.
+    pub fn new_vertex(src: &str) -> Result<Shader, CompileError> {
+        Shader::new(src, gl::VERTEX_SHADER)
+    }
+
+    /// Create a new geometry shader from a source string.
+    pub fn new_geometry(src: &str) -> Result<Shader, CompileError> {
+        Shader::new(src, gl::GEOMETRY_SHADER)
+    }
+
+    /// Create a new fragment shader from a source string.
+    pub fn new_fragment(src: &str) -> Result<Shader, CompileError> {
+        Shader::new(src, gl::FRAGMENT_SHADER)
+    }
+
+    // Hooray for FFI and boilerplate!
+    fn new(src: &str, typ: GLenum) -> Result<Shader, CompileError> {
+        unsafe {
+            let gl_shader = trace!(gl::CreateShader(typ));
+
+            // Jump through hoops to create a `const GLchar **` for glShaderSource.
+            let src_cstr    = ffi::CString::new(src).unwrap();
+            let src_ptr_ptr = &src_cstr.as_ptr() as *const *const GLchar;
+
+            trace!(gl::ShaderSource(gl_shader, 1, src_ptr_ptr, ptr::null()));
+            trace!(gl::CompileShader(gl_shader));
+
+            // Check if the shader compile successfully
+            let mut status: GLint = 0;
+            trace!(gl::GetShaderiv(gl_shader, gl::COMPILE_STATUS, &mut status));
+
+            // If the shader failed to compile, get the info log and return it as an error.
+            if status != (gl::TRUE as GLint) {
+                let mut log_len = 0;
+                trace!(gl::GetShaderiv(gl_shader, gl::INFO_LOG_LENGTH, &mut log_len));
+                let mut log_buf: Vec<u8> = iter::repeat(0u8).take(log_len as usize).collect();
+                let log_ptr = log_buf.as_mut_ptr() as *mut GLchar;
+
+                let mut real_len = 0;
+                trace!(gl::GetShaderInfoLog(gl_shader, log_len as GLsizei, &mut real_len, log_ptr));
+                // real_len doesn't include the null terminator.
+                log_buf.truncate(real_len as usize);
+
+                let log = String::from_utf8(log_buf)
+                    .unwrap_or(From::from("Info log was not valid utf-8"));
+
+                Err(CompileError{info_log: log})
+            } else {
+                Ok(Shader(gl_shader))
+            }
+        }
+    }
+}
+
+impl Drop for Shader {
+    /// Call `glDeleteShader` on this shader. Shaders should be dropped as soon as possible after
+    /// linking, since they keep unnecessary source and object code around in GL memory.
+    fn drop(&mut self) {
+        unsafe { trace!(gl::DeleteShader(self.0)) }
+    }
+}
+
+/// A TransformFeedback buffer, at the moment this is just intended for debugging purposes. I don't
+/// see them being very useful for anything else in a 2D isometric engine.
+pub struct TransformFeedback<T: Clone>{
+    name: GLuint,
+    data: Vec<T>
+}
+
+impl<T: Clone> TransformFeedback<T> {
+
+    /// Create a new TransformFeedback buffer with room for `count` vertices. Initialize each of
+    /// these vertices with `init` in main memory (but not on the GPU).
+    pub fn new(count: usize, init: T) -> TransformFeedback<T> {
+        let gl_xfb = unsafe {
+            let mut gl_xfb = 0;
+
+            trace!(gl::GenBuffers(1, &mut gl_xfb));
+
+            bind_array_buffer(gl_xfb);
+
+            trace!(gl::BufferData(
+                gl::ARRAY_BUFFER,
+                (mem::size_of::<T>() * count) as GLsizeiptr,
+                ptr::null(),
+                gl::STATIC_READ
+            ));
+
+            gl_xfb
+        };
+
+        TransformFeedback {
+            name: gl_xfb,
+            data: iter::repeat(init).take(count).collect(),
+        }
+    }
+
+    /// Read the contents of the buffer into main memory and a reference to them. This buffer must
+    /// be bound before calling read.
+    pub fn read<'x>(&'x mut self) -> &'x [T] {
+        unsafe {
+            trace!(gl::GetBufferSubData(
+                gl::TRANSFORM_FEEDBACK_BUFFER,
+                0,
+                (mem::size_of::<T>() * self.data.len()) as GLsizeiptr,
+                self.data.as_ptr() as *mut GLvoid
+            ));
+        }
+
+        &self.data
+    }
+
+    /// Bind this buffer to `TRANSFORM_FEEDBACK_BUFFER`.
+    pub fn bind(&self) {
+        unsafe { trace!(gl::BindBufferBase(gl::TRANSFORM_FEEDBACK_BUFFER, 0, self.name)) }
+    }
+}
+
+/// A linked OpenGL shader program object, and the context it was made in.
+pub struct ShaderProgram(GLuint, usize);
+
+impl ShaderProgram {
+    /// Compile the shaders in the files at `paths` (see `Shader::from_file`), and link them into a
+    /// `ShaderProgram`.
+    pub fn from_files<P: AsRef<Path>>(paths: &[P]) -> Result<ShaderProgram, ShaderFileError> {
+        let mut shaders = Vec::with_capacity(paths.len());
+
+        for path in paths.iter() {
+            shaders.push(try!(Shader::from_file(path)));
+        }
+
+        ShaderProgram::new(&shaders).map_err(ShaderFileError::Link)
+    }
+
+    /// Link several `Shader`s into a `ShaderProgram`.
+    pub fn new(shaders: &[Shader]) -> Result<ShaderProgram, LinkError> {
+
+        Ok( ShaderProgram( unsafe {
+            let gl_prog = trace!(gl::CreateProgram());
+
+            for s in shaders.iter() {
+                // Attach shaders for linking.
+                trace!(gl::AttachShader(gl_prog, s.0));
+            }
+
+            try!(ShaderProgram::link(gl_prog));
+
+            for s in shaders.iter() {
+                // Detach shaders so they can be deleted.
+                trace!(gl::DetachShader(gl_prog, s.0))
+            }
+
+            gl_prog
+        }, generation()))
+    }
+
+    /// Link several `Shader`s into a `ShaderProgram`, capturing the given attributes in a transform
+    /// feedback buffer.
+    pub fn new_xfb(shaders: &[Shader], xfb_attrs: &[&str]) -> Result<ShaderProgram, LinkError> {
+
+        Ok( ShaderProgram( unsafe {
+            let gl_prog = trace!(gl::CreateProgram());
+
+            for s in shaders.iter() {
+                // Attach shaders for linking.
+                trace!(gl::AttachShader(gl_prog, s.0));
+            }
+
+            let cstr_xfb_attrs: Vec<ffi::CString> = xfb_attrs.iter()
+                .map(|&rstr| ffi::CString::new(rstr).unwrap())
+                .collect();
+
+            let cptr_xfb_attrs: Vec<*const GLchar> = cstr_xfb_attrs.iter()
+                .map(|cstr| cstr.as_ptr())
+                .collect();
+
+            trace!(gl::TransformFeedbackVaryings(gl_prog, cptr_xfb_attrs.len() as GLsizei,
+                cptr_xfb_attrs.as_ptr(), gl::INTERLEAVED_ATTRIBS));
+
+            try!(ShaderProgram::link(gl_prog));
+
+            for s in shaders.iter() {
+                // Detach shaders so they can be deleted.
+                trace!(gl::DetachShader(gl_prog, s.0))
+            }
+
+            gl_prog
+        }, generation()))
+    }
+
+    unsafe fn link(gl_prog: GLuint) -> Result<(), LinkError> {
+
+        trace!(gl::LinkProgram(gl_prog));
+
+        // Check if the program linked successfully.
+        let mut status: GLint = 0;
+        trace!(gl::GetProgramiv(gl_prog, gl::LINK_STATUS, &mut status));
+
+        // If the program failed to link, get the info log and return it as an error.
+        if status != (gl::TRUE as GLint) {
+            let mut log_len = 0;
+            trace!(gl::GetProgramiv(gl_prog, gl::INFO_LOG_LENGTH, &mut log_len));
+            let mut log_buf: Vec<u8> = iter::repeat(0u8).take(log_len as usize).collect();
+            let log_ptr = log_buf.as_mut_ptr() as *mut GLchar;
+
+            let mut real_len = 0;
+            trace!(gl::GetProgramInfoLog(gl_prog, log_len as GLsizei, &mut real_len, log_ptr));
+            // real_len doesn't include the null terminator.
+            log_buf.truncate(real_len as usize);
+
+            let log = String::from_utf8(log_buf)
+                .unwrap_or(From::from("Info log was not valid utf-8"));
+
+            return Err(LinkError{info_log: log});
+        }
+
+        Ok(())
+    }
+
+    /// Simple wrapper for `glUseProgram`, which is skipped if the program is already in use.
+    pub fn use_program(&self) {
+        bind_program(self.0);
+    }
+
+    /// Get a `VertexAttrib` corresponding to one of the active vertex attributes in this
+    /// `ShaderProgram`..
+    pub fn get_attrib(&self, name: &str) -> Result<VertexAttrib, NoSuchActiveAttrib> {
+        let attrib = unsafe {
+            let cname = ffi::CString::new(name).unwrap();
+
+            trace!(gl::GetAttribLocation(self.0, cname.as_ptr() as *const GLchar))
+        };
+
+        if attrib == -1 {
+            Err(NoSuchActiveAttrib(From::from(name)))
+        } else {
+            Ok(VertexAttrib(attrib as GLuint))
+        }
+    }
+
+    /// Get a `Uniform` corresponding to one of the active uniforms in this `ShaderProgram`.
+    pub fn get_uniform(&self, name: &str) -> Result<Uniform, NoSuchActiveUniform> {
+        self.get_uniform_as(name)
+    }
+
+    /// Get a typed `Uniform` corresponding to one of the active uniforms in this `ShaderProgram`,
+    /// which can only be set to values of type `T`. The uniform's GLSL type must be the one which
+    /// `T` stands for (see `UniformValue`); that isn't checked.
+    pub fn get_uniform_as<T: ?Sized>(&self, name: &str) -> Result<Uniform<T>, NoSuchActiveUniform> {
+        let gl_uniform = unsafe {
+            let cname = ffi::CString::new(name).unwrap();
+
+            trace!(gl::GetUniformLocation(self.0, cname.as_ptr() as *const GLchar))
+        };
+
+        if gl_uniform == -1 {
+            Err(NoSuchActiveUniform(From::from(name)))
+        } else {
+            Ok(Uniform(gl_uniform, PhantomData))
+        }
+    }
+
+    /// Have this program's uniform block called `block` read from the `UniformBlock` bound to
+    /// `binding`. Returns false, and does nothing, if the program has no such active block.
+    pub fn bind_uniform_block(&self, block: &str, binding: u32) -> bool {
+        let index = unsafe {
+            let cname = ffi::CString::new(block).unwrap();
+
+            trace!(gl::GetUniformBlockIndex(self.0, cname.as_ptr()))
+        };
+
+        if index == gl::INVALID_INDEX {
+            return false
+        }
+
+        unsafe { trace!(gl::UniformBlockBinding(self.0, index, binding as GLuint)) }
+
+        true
+    }
+}
+
+impl Drop for ShaderProgram {
+    /// Call `glDeleteProgram` on this shader program.
+    fn drop(&mut self) {
+        if is_current(self.1) {
+            forget_program(self.0);
+            unsafe { trace!(gl::DeleteProgram(self.0)) }
+        }
+    }
+}
+
+/// Simplified interface to OpenGL's Vertex Array Objects, with the context it was made in.
+pub struct VertexArray(GLuint, usize);
+
+impl VertexArray {
+    /// Generate a new `VertexArray`
+    pub fn new() -> VertexArray {
+        let mut gl_vao = 0;
+        unsafe { trace!(gl::GenVertexArrays(1, &mut gl_vao)) }
+
+        VertexArray(gl_vao, generation())
+    }
+
+    /// Call `glBindVertexArray` on this `VertexArray`, unless it's already bound.
+    pub fn bind(&self) {
+        bind_vertex_array(self.0);
+    }
+}
+
+impl Drop for VertexArray {
+    /// Call `glDeleteVertexArrays` on this Vertex Array Object.
+    fn drop(&mut self) {
+        if is_current(self.1) {
+            forget_vertex_array(self.0);
+            unsafe { trace!(gl::DeleteVertexArrays(1, &self.0)) }
+        }
+    }
+}
+
+/// Simplified, safer interface to OpenGL's Vertex Buffer Objects.
+///
+/// Buffers which are refilled every frame should be written through `map`, which treats the buffer
+/// as a ring: each frame's data goes after the last, so the GPU can keep drawing from earlier
+/// regions while it's written, and when the ring runs out the buffer is orphaned, so that the
+/// driver hands over fresh storage instead of waiting for the GPU to finish with the old.
+pub struct VertexBuffer {
+    name: GLuint,
+    size: usize,
+
+    // Where the next region handed out by `map` starts, in bytes.
+    head: usize,
+
+    // Whether a region is currently mapped.
+    mapped: bool,
+
+    // The context the buffer was made in.
+    generation: usize,
+}
+
+// The number and total size of the `VertexBuffer`s which currently exist.
+static VERTEX_BUFFERS:      AtomicUsize = ATOMIC_USIZE_INIT;
+static VERTEX_BUFFER_BYTES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Add the vertex buffers which currently exist to `report`.
+pub fn report_memory(report: &mut memory::Report) {
+    report.add(logging::GFX, "vertex buffers", VERTEX_BUFFERS.load(Ordering::Relaxed),
+               VERTEX_BUFFER_BYTES.load(Ordering::Relaxed));
+}
+
+impl VertexBuffer {
+    /// Generate a new `VertexBuffer` and allocate `size` bytes of storage on the GPU. The buffer
+    /// will be created with the `STREAM_DRAW` usage constant.
+    pub fn new(size: usize) -> VertexBuffer {
+        let mut gl_vbo = 0;
+        unsafe {
+            trace!(gl::GenBuffers(1, &mut gl_vbo));
+            bind_array_buffer(gl_vbo);
+            trace!(gl::BufferData(
+                gl::ARRAY_BUFFER,
+                size as GLsizeiptr,
+                ptr::null(),
+                gl::STREAM_DRAW,
+            ));
+        }
+
+        VERTEX_BUFFERS.fetch_add(1, Ordering::Relaxed);
+        VERTEX_BUFFER_BYTES.fetch_add(size, Ordering::Relaxed);
+
+        VertexBuffer { name: gl_vbo, size: size, head: 0, mapped: false, generation: generation() }
+    }
+
+    /// Generate a new `VertexBuffer` holding `data`, which is never changed afterwards. The buffer
+    /// will be created with the `STATIC_DRAW` usage constant.
+    pub fn new_static<T>(data: &[T]) -> VertexBuffer {
+        let size = mem::size_of::<T>() * data.len();
+
+        let mut gl_vbo = 0;
+        unsafe {
+            trace!(gl::GenBuffers(1, &mut gl_vbo));
+            bind_array_buffer(gl_vbo);
+            trace!(gl::BufferData(
+                gl::ARRAY_BUFFER,
+                size as GLsizeiptr,
+                data.as_ptr() as *const GLvoid,
+                gl::STATIC_DRAW,
+            ));
+        }
+
+        VERTEX_BUFFERS.fetch_add(1, Ordering::Relaxed);
+        VERTEX_BUFFER_BYTES.fetch_add(size, Ordering::Relaxed);
+
+        VertexBuffer { name: gl_vbo, size: size, head: 0, mapped: false, generation: generation() }
+    }
+
+    /// Make this the active Vertex Buffer. This amounts to calling `glBindBuffer` with the
+    /// `ARRAY_BUFFER` target constant, if it isn't already bound.
+    pub fn bind(&self) {
+        bind_array_buffer(self.name);
+    }
+
+    /// The size of the buffer's storage on the GPU, in bytes.
+    pub fn bytes(&self) -> usize {
+        self.size
+    }
+
+    /// Load data into the buffer, it must not be larger than the size of the buffer. The buffer is
+    /// orphaned first, so this doesn't wait for the GPU to finish drawing from the old data.
+    pub fn buffer_data<T>(&self, data: &[T]) {
+        unsafe {
+            bind_array_buffer(self.name);
+            trace!(gl::BufferData(
+                gl::ARRAY_BUFFER,
+                self.size as GLsizeiptr,
+                ptr::null(),
+                gl::STREAM_DRAW,
+            ));
+            trace!(gl::BufferSubData(
+                gl::ARRAY_BUFFER,
+                0, // Offset is always 0.
+                (mem::size_of::<T>() * data.len()) as GLsizeiptr,
+                data.as_ptr() as *const GLvoid,
+            ));
+        }
+    }
+
+    /// Map room for `count` values of type `T` for writing, after the region handed out last time.
+    /// Returns the room along with the index of its first value within the buffer (counting in
+    /// `T`s), which is where draw calls should start from. The buffer is left bound.
+    ///
+    /// The room is only for writing: what it holds beforehand is undefined, and reading it is
+    /// slow on most drivers. It must be unmapped with `unmap` before anything is drawn from the
+    /// buffer. Panics if `count` values won't fit in the buffer, or if the GL can't map it.
+    pub fn map<T>(&mut self, count: usize) -> (&mut [T], usize) {
+        let stride = mem::size_of::<T>();
+        let bytes  = stride * count;
+
+        assert!(bytes <= self.size, "{} bytes won't fit in a {} byte buffer", bytes, self.size);
+
+        self.bind();
+
+        if count == 0 {
+            return (&mut [], 0)
+        }
+
+        // Start on a whole number of values, so that the region can be drawn from by index.
+        let mut offset = (self.head + stride - 1) / stride * stride;
+
+        if offset + bytes > self.size {
+            offset = 0;
+
+            unsafe {
+                trace!(gl::BufferData(
+                    gl::ARRAY_BUFFER,
+                    self.size as GLsizeiptr,
+                    ptr::null(),
+                    gl::STREAM_DRAW,
+                ));
+            }
+        }
+
+        // Nothing before `head` is written again until the buffer has been orphaned, so there's no
+        // need for the driver to check whether the GPU is still using this region.
+        let access = gl::MAP_WRITE_BIT | gl::MAP_INVALIDATE_RANGE_BIT | gl::MAP_UNSYNCHRONIZED_BIT;
+
+        let data = unsafe {
+            trace!(gl::MapBufferRange(gl::ARRAY_BUFFER, offset as GLintptr, bytes as GLsizeiptr,
+                                      access))
+        };
+
+        if data.is_null() {
+            panic!("couldn't map vertex buffer {} (GL error {:#x})", self.name, get_error());
+        }
+
+        self.head   = offset + bytes;
+        self.mapped = true;
+
+        (unsafe { slice::from_raw_parts_mut(data as *mut T, count) }, offset / stride)
+    }
+
+    /// Finish writing the room handed out by the last call to `map`, if it's still mapped. The
+    /// buffer is left bound.
+    pub fn unmap(&mut self) {
+        self.bind();
+
+        if self.mapped {
+            unsafe { trace!(gl::UnmapBuffer(gl::ARRAY_BUFFER)); }
+            self.mapped = false;
+        }
+    }
+
+    /// Get the OpenGL name of this buffer.
+    pub fn dbg_name(&self) -> GLint { self.name as GLint }
+}
+
+impl Drop for VertexBuffer {
+    /// Call `glDeleteBuffers` on this Vertex Buffer Object.
+    fn drop(&mut self) {
+        VERTEX_BUFFERS.fetch_sub(1, Ordering::Relaxed);
+        VERTEX_BUFFER_BYTES.fetch_sub(self.size, Ordering::Relaxed);
+
+        if is_current(self.generation) {
+            forget_buffer(self.name);
+            unsafe { trace!(gl::DeleteBuffers(1, &self.name)) }
+        }
+    }
+}
+
+/// A type whose memory layout follows the std140 rules, so that it can be copied straight into a
+/// `UniformBlock` and read by a shader which declares the block with `layout(std140)`.
+///
+/// Implementors should be `#[repr(C)]`, with their fields in the same order as the block's
+/// members. Under std140, `float` and `int` are aligned to 4 bytes, `vec2` to 8, and `vec3` and
+/// `vec4` to 16; a `vec3` can be followed by a lone `float` or `int` in the same 16 bytes, and
+/// anything else must be padded out by hand. Arrays, and the columns of matrices, have every
+/// element aligned to 16 bytes.
+pub unsafe trait Std140: Copy {}
+
+/// A uniform buffer holding a single `T`, which programs read through a uniform block (see
+/// `ShaderProgram::bind_uniform_block`). Any number of programs can share one block by reading it
+/// from the same binding point, so that values which they all need are set once per frame instead
+/// of once per program.
+pub struct UniformBlock<T: Std140> {
+    name:    GLuint,
+    binding: GLuint,
+
+    // The context the buffer was made in.
+    generation: usize,
+
+    _data: PhantomData<T>,
+}
+
+impl<T: Std140> UniformBlock<T> {
+    /// Generate a new `UniformBlock` holding `data`, and bind it to `binding`.
+    pub fn new(binding: u32, data: &T) -> UniformBlock<T> {
+        let mut gl_ubo = 0;
+        unsafe {
+            trace!(gl::GenBuffers(1, &mut gl_ubo));
+            trace!(gl::BindBufferBase(gl::UNIFORM_BUFFER, binding as GLuint, gl_ubo));
+            trace!(gl::BufferData(
+                gl::UNIFORM_BUFFER,
+                mem::size_of::<T>() as GLsizeiptr,
+                data as *const T as *const GLvoid,
+                gl::DYNAMIC_DRAW,
+            ));
+        }
+
+        UniformBlock {
+            name:       gl_ubo,
+            binding:    binding as GLuint,
+            generation: generation(),
+            _data:      PhantomData,
+        }
+    }
+
+    /// The binding point which this block is bound to by `bind`.
+    pub fn binding(&self) -> u32 {
+        self.binding as u32
+    }
+
+    /// Bind this block to its binding point, for the programs which read from it. This amounts to
+    /// calling `glBindBufferBase` with the `UNIFORM_BUFFER` target constant.
+    pub fn bind(&self) {
+        unsafe { trace!(gl::BindBufferBase(gl::UNIFORM_BUFFER, self.binding, self.name)) }
+    }
+
+    /// Replace what the block holds with `data`. The block is left bound.
+    pub fn update(&mut self, data: &T) {
+        self.bind();
+
+        unsafe {
+            trace!(gl::BufferSubData(
+                gl::UNIFORM_BUFFER,
+                0,
+                mem::size_of::<T>() as GLsizeiptr,
+                data as *const T as *const GLvoid,
+            ));
+        }
+    }
+}
 
-            gl_prog
-        }))
+impl<T: Std140> Drop for UniformBlock<T> {
+    /// Call `glDeleteBuffers` on this Uniform Buffer Object.
+    fn drop(&mut self) {
+        if is_current(self.generation) {
+            unsafe { trace!(gl::DeleteBuffers(1, &self.name)) }
+        }
     }
+}
 
-    /// Link several `Shader`s into a `ShaderProgram`, capturing the given attributes in a transform
-    /// feedback buffer.
-    pub fn new_xfb(shaders: &[Shader], xfb_attrs: &[&str]) -> Result<ShaderProgram, LinkError> {
+// The number of frames' worth of timer queries that a `GpuTimer` keeps in flight. The GPU usually
+// runs a frame or two behind the CPU, so this is enough that reading back results never stalls.
+const TIMER_LATENCY: usize = 4;
 
-        Ok( ShaderProgram( unsafe {
-            let gl_prog = trace!(gl::CreateProgram());
+/// Measures how long the GPU spends executing the commands issued between `begin` and `end`, using
+/// `GL_TIME_ELAPSED` queries.
+///
+/// Results aren't available until the GPU catches up, so the timer keeps a ring of queries in
+/// flight and `poll` reports the most recent one which has finished, which will be from a few
+/// frames ago. If every query is still in flight when `begin` is called, that span goes untimed
+/// rather than waiting on the GPU.
+///
+/// OpenGL doesn't allow `GL_TIME_ELAPSED` queries to nest, so only one `GpuTimer` can be timing at
+/// any given moment.
+pub struct GpuTimer {
+    // Query objects, and whether each one has a result which hasn't been read back yet.
+    queries: Vec<(GLuint, bool)>,
 
-            for s in shaders.iter() {
-                // Attach shaders for linking.
-                trace!(gl::AttachShader(gl_prog, s.0));
-            }
+    // The query to use for the next span.
+    next: usize,
 
-            let cstr_xfb_attrs: Vec<ffi::CString> = xfb_attrs.iter()
-                .map(|&rstr| ffi::CString::new(rstr).unwrap())
-                .collect();
+    // True between a successful `begin` and its `end`.
+    active: bool,
 
-            let cptr_xfb_attrs: Vec<*const GLchar> = cstr_xfb_attrs.iter()
-                .map(|cstr| cstr.as_ptr())
-                .collect();
+    // Number of spans which were skipped because no query was free.
+    skipped: u64,
 
-            trace!(gl::TransformFeedbackVaryings(gl_prog, cptr_xfb_attrs.len() as GLsizei,
-                cptr_xfb_attrs.as_ptr(), gl::INTERLEAVED_ATTRIBS));
+    last: Option<time::Duration>,
 
-            try!(ShaderProgram::link(gl_prog));
+    // The context the queries were made in.
+    generation: usize,
+}
 
-            for s in shaders.iter() {
-                // Detach shaders so they can be deleted.
-                trace!(gl::DetachShader(gl_prog, s.0))
-            }
+impl GpuTimer {
+    /// Generate the timer's query objects.
+    pub fn new() -> GpuTimer {
+        let mut ids = [0; TIMER_LATENCY];
+        unsafe { trace!(gl::GenQueries(TIMER_LATENCY as GLsizei, ids.as_mut_ptr())) }
 
-            gl_prog
-        }))
-    }
+        GpuTimer {
+            queries: ids.iter().map(|&id| (id, false)).collect(),
+            next:    0,
+            active:  false,
+            skipped: 0,
+            last:    None,
 
-    unsafe fn link(gl_prog: GLuint) -> Result<(), LinkError> {
+            generation: generation(),
+        }
+    }
 
-        trace!(gl::LinkProgram(gl_prog));
+    /// Start timing. Commands issued between this and the next call to `end` are measured.
+    pub fn begin(&mut self) {
+        assert!(!self.active, "GpuTimer::begin called twice without a call to end");
 
-        // Check if the program linked successfully.
-        let mut status: GLint = 0;
-        trace!(gl::GetProgramiv(gl_prog, gl::LINK_STATUS, &mut status));
+        self.poll();
 
-        // If the program failed to link, get the info log and return it as an error.
-        if status != (gl::TRUE as GLint) {
-            let mut log_len = 0;
-            trace!(gl::GetProgramiv(gl_prog, gl::INFO_LOG_LENGTH, &mut log_len));
-            let mut log_buf: Vec<u8> = iter::repeat(0u8).take(log_len as usize).collect();
-            let log_ptr = log_buf.as_mut_ptr() as *mut GLchar;
+        let (id, pending) = self.queries[self.next];
 
-            let mut real_len = 0;
-            trace!(gl::GetProgramInfoLog(gl_prog, log_len as GLsizei, &mut real_len, log_ptr));
-            // real_len doesn't include the null terminator.
-            log_buf.truncate(real_len as usize);
+        if pending {
+            self.skipped += 1;
+            return
+        }
 
-            let log = String::from_utf8(log_buf)
-                .unwrap_or(From::from("Info log was not valid utf-8"));
+        unsafe { trace!(gl::BeginQuery(gl::TIME_ELAPSED, id)) }
+        self.active = true;
+    }
 
-            return Err(LinkError{info_log: log});
+    /// Stop timing the current span.
+    pub fn end(&mut self) {
+        if !self.active {
+            return
         }
 
-        Ok(())
-    }
+        unsafe { trace!(gl::EndQuery(gl::TIME_ELAPSED)) }
 
-    /// Simple wrapper for `glUseProgram`.
-    pub fn use_program(&self) {
-        unsafe { trace!(gl::UseProgram(self.0)) }
+        self.queries[self.next].1 = true;
+        self.next = (self.next + 1) % self.queries.len();
+        self.active = false;
     }
 
-    /// Get a `VertexAttrib` corresponding to one of the active vertex attributes in this
-    /// `ShaderProgram`..
-    pub fn get_attrib(&self, name: &str) -> Result<VertexAttrib, NoSuchActiveAttrib> {
-        let attrib = unsafe {
-            let cname = ffi::CString::new(name).unwrap();
+    /// Read back any results which have become available without waiting on the GPU, and return
+    /// the most recent one. Returns `None` until the first span has been measured.
+    pub fn poll(&mut self) -> Option<time::Duration> {
+        // Queries finish in the order they were issued, so start from the oldest one.
+        let n = self.queries.len();
 
-            trace!(gl::GetAttribLocation(self.0, cname.as_ptr() as *const GLchar))
-        };
+        for i in 0..n {
+            let (id, pending) = self.queries[(self.next + i) % n];
 
-        if attrib == -1 {
-            Err(NoSuchActiveAttrib(From::from(name)))
-        } else {
-            Ok(VertexAttrib(attrib as GLuint))
-        }
-    }
+            if !pending {
+                continue
+            }
 
-    /// Get a `Uniform` corresponding to one of the active uniforms in this `ShaderProgram`.
-    pub fn get_uniform(&self, name: &str) -> Result<Uniform, NoSuchActiveUniform> {
-        let gl_uniform = unsafe {
-            let cname = ffi::CString::new(name).unwrap();
+            let mut available = 0;
+            unsafe { trace!(gl::GetQueryObjectiv(id, gl::QUERY_RESULT_AVAILABLE, &mut available)) }
 
-            trace!(gl::GetUniformLocation(self.0, cname.as_ptr() as *const GLchar))
-        };
+            if available == 0 {
+                break
+            }
 
-        if gl_uniform == -1 {
-            Err(NoSuchActiveUniform(From::from(name)))
-        } else {
-            Ok(Uniform(gl_uniform))
+            let mut nsec = 0;
+            unsafe { trace!(gl::GetQueryObjectui64v(id, gl::QUERY_RESULT, &mut nsec)) }
+
+            self.queries[(self.next + i) % n].1 = false;
+            self.last = Some(time::Duration::usec(nsec / 1_000));
         }
+
+        self.last
+    }
+
+    /// The number of spans which went untimed because the GPU had fallen too far behind.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
     }
 }
 
-impl Drop for ShaderProgram {
-    /// Call `glDeleteProgram` on this shader program.
+impl Drop for GpuTimer {
+    /// Call `glDeleteQueries` on the timer's query objects.
     fn drop(&mut self) {
-        unsafe { trace!(gl::DeleteProgram(self.0)) }
+        if !is_current(self.generation) {
+            return
+        }
+
+        let ids: Vec<GLuint> = self.queries.iter().map(|&(id, _)| id).collect();
+        unsafe { trace!(gl::DeleteQueries(ids.len() as GLsizei, ids.as_ptr())) }
     }
 }
 
-/// Simplified interface to OpenGL's Vertex Array Objects.
-pub struct VertexArray(GLuint);
+/// Measures how long the GPU takes to get from `begin` to `end`, like a `GpuTimer`, but with a
+/// `glQueryCounter` timestamp at each end instead of a `GL_TIME_ELAPSED` query. Timestamps can be
+/// taken at any moment, so unlike `GpuTimer`s these can time spans which nest inside each other or
+/// inside a `GpuTimer`'s, such as the passes of a frame.
+///
+/// The time between the timestamps includes any time the GPU spends idle, waiting for commands,
+/// so a span which the CPU is slow to issue will look longer than the work in it.
+pub struct TimestampTimer {
+    // Pairs of query objects for the start and end of each span, and whether each pair has a
+    // result which hasn't been read back yet.
+    queries: Vec<(GLuint, GLuint, bool)>,
 
-impl VertexArray {
-    /// Generate a new `VertexArray`
-    pub fn new() -> VertexArray {
-        let mut gl_vao = 0;
-        unsafe { trace!(gl::GenVertexArrays(1, &mut gl_vao)) }
+    // The pair to use for the next span.
+    next: usize,
 
-        VertexArray(gl_vao)
-    }
+    // True between a successful `begin` and its `end`.
+    active: bool,
 
-    /// Call `glBindVertexArray` on this `VertexArray`.
-    pub fn bind(&self) {
-        unsafe { trace!(gl::BindVertexArray(self.0)) }
-    }
+    // Number of spans which were skipped because no pair of queries was free.
+    skipped: u64,
+
+    last: Option<time::Duration>,
+
+    // The context the queries were made in.
+    generation: usize,
 }
 
-impl Drop for VertexArray {
-    /// Call `glDeleteVertexArrays` on this Vertex Array Object.
-    fn drop(&mut self) {
-        unsafe { trace!(gl::DeleteVertexArrays(1, &self.0)) }
+impl TimestampTimer {
+    /// Generate the timer's query objects.
+    pub fn new() -> TimestampTimer {
+        let mut ids = [0; TIMER_LATENCY * 2];
+        unsafe { trace!(gl::GenQueries(ids.len() as GLsizei, ids.as_mut_ptr())) }
+
+        TimestampTimer {
+            queries: ids.chunks(2).map(|pair| (pair[0], pair[1], false)).collect(),
+            next:    0,
+            active:  false,
+            skipped: 0,
+            last:    None,
+
+            generation: generation(),
+        }
     }
-}
 
-/// Simplified, safer interface to OpenGL's Vertex Buffer Objects.
-pub struct VertexBuffer(GLuint);
+    /// Take the timestamp at the start of a span.
+    pub fn begin(&mut self) {
+        assert!(!self.active, "TimestampTimer::begin called twice without a call to end");
 
-impl VertexBuffer {
-    /// Generate a new `VertexBuffer` and allocate `size` bytes of storage on the GPU. The buffer
-    /// will be created with the `STREAM_DRAW` usage constant.
-    pub fn new(size: usize) -> VertexBuffer {
-        let mut gl_vbo = 0;
-        unsafe {
-            trace!(gl::GenBuffers(1, &mut gl_vbo));
-            trace!(gl::BindBuffer(gl::ARRAY_BUFFER, gl_vbo));
-            trace!(gl::BufferData(
-                gl::ARRAY_BUFFER,
-                size as GLsizeiptr,
-                ptr::null(),
-                gl::STREAM_DRAW,
-            ));
+        self.poll();
+
+        let (start, _, pending) = self.queries[self.next];
+
+        if pending {
+            self.skipped += 1;
+            return
         }
 
-        VertexBuffer(gl_vbo)
+        unsafe { trace!(gl::QueryCounter(start, gl::TIMESTAMP)) }
+        self.active = true;
     }
 
-    /// Make this the active Vertex Buffer. This amounts to calling `glBindBuffer` with the
-    /// `ARRAY_BUFFER` target constant.
-    pub fn bind(&self) {
-        unsafe { trace!(gl::BindBuffer(gl::ARRAY_BUFFER, self.0)) }
+    /// Take the timestamp at the end of the current span.
+    pub fn end(&mut self) {
+        if !self.active {
+            return
+        }
+
+        unsafe { trace!(gl::QueryCounter(self.queries[self.next].1, gl::TIMESTAMP)) }
+
+        self.queries[self.next].2 = true;
+        self.next = (self.next + 1) % self.queries.len();
+        self.active = false;
     }
 
-    /// Load data into the buffer, it must not be larger than the size of the buffer.
-    pub fn buffer_data<T>(&self, data: &[T]) {
-        unsafe {
-            trace!(gl::BindBuffer(gl::ARRAY_BUFFER, self.0));
-            trace!(gl::BufferSubData(
-                gl::ARRAY_BUFFER,
-                0, // Offset is always 0.
-                (mem::size_of::<T>() * data.len()) as GLsizeiptr,
-                data.as_ptr() as *const GLvoid,
-            ));
+    /// Read back any results which have become available without waiting on the GPU, and return
+    /// the most recent one. Returns `None` until the first span has been measured.
+    pub fn poll(&mut self) -> Option<time::Duration> {
+        // Spans finish in the order they were issued, so start from the oldest one. The end of
+        // each span is issued after its start, so once it's available both are.
+        let n = self.queries.len();
+
+        for i in 0..n {
+            let (start, end, pending) = self.queries[(self.next + i) % n];
+
+            if !pending {
+                continue
+            }
+
+            let mut available = 0;
+            unsafe { trace!(gl::GetQueryObjectiv(end, gl::QUERY_RESULT_AVAILABLE, &mut available)) }
+
+            if available == 0 {
+                break
+            }
+
+            let (mut t0, mut t1) = (0, 0);
+
+            unsafe {
+                trace!(gl::GetQueryObjectui64v(start, gl::QUERY_RESULT, &mut t0));
+                trace!(gl::GetQueryObjectui64v(end, gl::QUERY_RESULT, &mut t1));
+            }
+
+            self.queries[(self.next + i) % n].2 = false;
+            self.last = Some(time::Duration::usec(t1.saturating_sub(t0) / 1_000));
         }
+
+        self.last
     }
 
-    /// Get the OpenGL name of this buffer.
-    pub fn dbg_name(&self) -> GLint { self.0 as GLint }
+    /// The number of spans which went untimed because the GPU had fallen too far behind.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
 }
 
-impl Drop for VertexBuffer {
-    /// Call `glDeleteBuffers` on this Vertex Buffer Object.
+impl Drop for TimestampTimer {
+    /// Call `glDeleteQueries` on the timer's query objects.
     fn drop(&mut self) {
-        unsafe { trace!(gl::DeleteBuffers(1, &self.0)) }
+        if !is_current(self.generation) {
+            return
+        }
+
+        let ids: Vec<GLuint> = self.queries.iter().flat_map(|&(a, b, _)| vec![a, b]).collect();
+        unsafe { trace!(gl::DeleteQueries(ids.len() as GLsizei, ids.as_ptr())) }
     }
 }
 
@@ -561,6 +2692,12 @@ impl VertexAttrib {
         unsafe { trace!(gl::EnableVertexAttribArray(self.0)) }
     }
 
+    /// Advance this attribute once every `divisor` instances of an instanced draw, rather than once
+    /// per vertex (which is what a `divisor` of 0 means). This amounts to `glVertexAttribDivisor`.
+    pub fn set_divisor(&self, divisor: u32) {
+        unsafe { trace!(gl::VertexAttribDivisor(self.0, divisor as GLuint)) }
+    }
+
     /// Returns the name of the buffer object currently bound to this attributes binding point.
     pub fn dbg_buffer_binding(&self) -> GLint {
         let mut gl_name = 0;
@@ -574,8 +2711,17 @@ impl VertexAttrib {
 #[derive(Debug)]
 pub struct NoSuchActiveUniform(pub String);
 
+/// The type parameter of a `Uniform` which can be set to any kind of value, with the setter for
+/// that kind.
+pub enum Untyped {}
+
 /// A uniform variable from a shader program.
-pub struct Uniform(GLint);
+///
+/// An untyped uniform (see `ShaderProgram::get_uniform`) has a setter for each kind of GLSL value,
+/// and nothing stops the wrong one being called. A typed uniform (see
+/// `ShaderProgram::get_uniform_as`) can only be `set` to the type of value it was made for, so
+/// passing the wrong kind is a compile error.
+pub struct Uniform<T: ?Sized = Untyped>(GLint, PhantomData<T>);
 
 impl Uniform {
     /// Call glUniform1i on the underlying uniform. The corresponding program must be active in
@@ -583,4 +2729,225 @@ impl Uniform {
     pub fn set1i(&self, x: i32) {
         unsafe { trace!(gl::Uniform1i(self.0, x as GLint)) }
     }
+
+    /// Call glUniform2i on the underlying uniform. The corresponding program must be active in
+    /// order for this to work as expected.
+    pub fn set2i(&self, x: i32, y: i32) {
+        unsafe { trace!(gl::Uniform2i(self.0, x as GLint, y as GLint)) }
+    }
+
+    /// Call glUniform3i on the underlying uniform. The corresponding program must be active in
+    /// order for this to work as expected.
+    pub fn set3i(&self, x: i32, y: i32, z: i32) {
+        unsafe { trace!(gl::Uniform3i(self.0, x as GLint, y as GLint, z as GLint)) }
+    }
+
+    /// Call glUniform4i on the underlying uniform. The corresponding program must be active in
+    /// order for this to work as expected.
+    pub fn set4i(&self, x: i32, y: i32, z: i32, w: i32) {
+        unsafe { trace!(gl::Uniform4i(self.0, x as GLint, y as GLint, z as GLint, w as GLint)) }
+    }
+
+    /// Set a `bool` uniform, which GL sets as an int. The corresponding program must be active in
+    /// order for this to work as expected.
+    pub fn set_bool(&self, x: bool) {
+        self.set1i(if x { 1 } else { 0 })
+    }
+
+    /// Call glUniform1f on the underlying uniform. The corresponding program must be active in
+    /// order for this to work as expected.
+    pub fn set1f(&self, x: f32) {
+        unsafe { trace!(gl::Uniform1f(self.0, x as GLfloat)) }
+    }
+
+    /// Call glUniform2f on the underlying uniform. The corresponding program must be active in
+    /// order for this to work as expected.
+    pub fn set2f(&self, x: f32, y: f32) {
+        unsafe { trace!(gl::Uniform2f(self.0, x as GLfloat, y as GLfloat)) }
+    }
+
+    /// Call glUniform3f on the underlying uniform. The corresponding program must be active in
+    /// order for this to work as expected.
+    pub fn set3f(&self, x: f32, y: f32, z: f32) {
+        unsafe { trace!(gl::Uniform3f(self.0, x as GLfloat, y as GLfloat, z as GLfloat)) }
+    }
+
+    /// Call glUniform4f on the underlying uniform. The corresponding program must be active in
+    /// order for this to work as expected.
+    pub fn set4f(&self, x: f32, y: f32, z: f32, w: f32) {
+        unsafe {
+            trace!(gl::Uniform4f(self.0, x as GLfloat, y as GLfloat, z as GLfloat, w as GLfloat))
+        }
+    }
+
+    /// Call glUniform4fv on the underlying uniform, which must be an array of `vec4`s at least as
+    /// long as `xs`. The corresponding program must be active in order for this to work as
+    /// expected.
+    pub fn set4fv(&self, xs: &[[f32; 4]]) {
+        xs.set_uniform(self.0)
+    }
+
+    /// Call glUniformMatrix3fv on the underlying uniform, with `rows` as the rows of the matrix.
+    /// The corresponding program must be active in order for this to work as expected.
+    pub fn set_mat3(&self, rows: &[[f32; 3]; 3]) {
+        Mat3::from_rows(*rows).set_uniform(self.0)
+    }
+
+    /// Call glUniformMatrix4fv on the underlying uniform, with `rows` as the rows of the matrix.
+    /// The corresponding program must be active in order for this to work as expected.
+    pub fn set_mat4(&self, rows: &[[f32; 4]; 4]) {
+        Mat4::from_rows(*rows).set_uniform(self.0)
+    }
+}
+
+impl<T: UniformValue + ?Sized> Uniform<T> {
+    /// Set the uniform to `value`. The corresponding program must be active in order for this to
+    /// work as expected.
+    pub fn set(&self, value: &T) {
+        value.set_uniform(self.0)
+    }
+}
+
+/// A type which a typed `Uniform` can be set to, and the GLSL type that it stands for:
+///
+/// | Rust         | GLSL                         |
+/// |--------------|------------------------------|
+/// | `i32`        | `int`, or any sampler        |
+/// | `bool`       | `bool`                       |
+/// | `f32`        | `float`                      |
+/// | `[f32; N]`   | `vecN` (N from 2 to 4)       |
+/// | `[i32; N]`   | `ivecN` (N from 2 to 4)      |
+/// | `[f32]`      | an array of `float`s         |
+/// | `[[f32; 4]]` | an array of `vec4`s          |
+/// | `Mat3`       | `mat3`                       |
+/// | `Mat4`       | `mat4`                       |
+pub trait UniformValue {
+    /// Set the uniform at `location` of the active program to this value.
+    fn set_uniform(&self, location: GLint);
+}
+
+impl UniformValue for i32 {
+    fn set_uniform(&self, location: GLint) {
+        unsafe { trace!(gl::Uniform1i(location, *self as GLint)) }
+    }
+}
+
+impl UniformValue for bool {
+    fn set_uniform(&self, location: GLint) {
+        unsafe { trace!(gl::Uniform1i(location, if *self { 1 } else { 0 })) }
+    }
+}
+
+impl UniformValue for f32 {
+    fn set_uniform(&self, location: GLint) {
+        unsafe { trace!(gl::Uniform1f(location, *self as GLfloat)) }
+    }
+}
+
+impl UniformValue for [f32; 2] {
+    fn set_uniform(&self, location: GLint) {
+        unsafe { trace!(gl::Uniform2fv(location, 1, self.as_ptr() as *const GLfloat)) }
+    }
+}
+
+impl UniformValue for [f32; 3] {
+    fn set_uniform(&self, location: GLint) {
+        unsafe { trace!(gl::Uniform3fv(location, 1, self.as_ptr() as *const GLfloat)) }
+    }
+}
+
+impl UniformValue for [f32; 4] {
+    fn set_uniform(&self, location: GLint) {
+        unsafe { trace!(gl::Uniform4fv(location, 1, self.as_ptr() as *const GLfloat)) }
+    }
+}
+
+impl UniformValue for [i32; 2] {
+    fn set_uniform(&self, location: GLint) {
+        unsafe { trace!(gl::Uniform2iv(location, 1, self.as_ptr() as *const GLint)) }
+    }
+}
+
+impl UniformValue for [i32; 3] {
+    fn set_uniform(&self, location: GLint) {
+        unsafe { trace!(gl::Uniform3iv(location, 1, self.as_ptr() as *const GLint)) }
+    }
+}
+
+impl UniformValue for [i32; 4] {
+    fn set_uniform(&self, location: GLint) {
+        unsafe { trace!(gl::Uniform4iv(location, 1, self.as_ptr() as *const GLint)) }
+    }
+}
+
+impl UniformValue for [f32] {
+    fn set_uniform(&self, location: GLint) {
+        let count = self.len() as GLsizei;
+
+        unsafe { trace!(gl::Uniform1fv(location, count, self.as_ptr() as *const GLfloat)) }
+    }
+}
+
+impl UniformValue for [[f32; 4]] {
+    fn set_uniform(&self, location: GLint) {
+        let count = self.len() as GLsizei;
+
+        unsafe { trace!(gl::Uniform4fv(location, count, self.as_ptr() as *const GLfloat)) }
+    }
+}
+
+/// A 3x3 matrix for a `mat3` uniform, stored column-major as GLSL stores it: `self.0[c][r]` is
+/// row `r` of column `c`.
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub struct Mat3(pub [[f32; 3]; 3]);
+
+impl Mat3 {
+    /// The matrix whose rows are `rows`.
+    pub fn from_rows(rows: [[f32; 3]; 3]) -> Mat3 {
+        let mut cols = [[0.0; 3]; 3];
+
+        for (r, row) in rows.iter().enumerate() {
+            for (c, &x) in row.iter().enumerate() {
+                cols[c][r] = x;
+            }
+        }
+
+        Mat3(cols)
+    }
+}
+
+impl UniformValue for Mat3 {
+    fn set_uniform(&self, location: GLint) {
+        let data = self.0.as_ptr() as *const GLfloat;
+
+        unsafe { trace!(gl::UniformMatrix3fv(location, 1, gl::FALSE, data)) }
+    }
+}
+
+/// A 4x4 matrix for a `mat4` uniform, stored column-major as GLSL stores it: `self.0[c][r]` is
+/// row `r` of column `c`.
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub struct Mat4(pub [[f32; 4]; 4]);
+
+impl Mat4 {
+    /// The matrix whose rows are `rows`.
+    pub fn from_rows(rows: [[f32; 4]; 4]) -> Mat4 {
+        let mut cols = [[0.0; 4]; 4];
+
+        for (r, row) in rows.iter().enumerate() {
+            for (c, &x) in row.iter().enumerate() {
+                cols[c][r] = x;
+            }
+        }
+
+        Mat4(cols)
+    }
+}
+
+impl UniformValue for Mat4 {
+    fn set_uniform(&self, location: GLint) {
+        let data = self.0.as_ptr() as *const GLfloat;
+
+        unsafe { trace!(gl::UniformMatrix4fv(location, 1, gl::FALSE, data)) }
+    }
 }
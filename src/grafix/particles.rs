@@ -0,0 +1,366 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Particle effects, such as smoke, sparks and dust.
+//!
+//! Particles are simulated on the CPU in world `Meters`, and each live particle is drawn as an
+//! ordinary sprite through the `Batcher`. That way particles are depth tested against everything
+//! else in the frame using their sheet's depth texture, just like units: smoke drifting behind a
+//! tower is hidden by it, and sparks in front of it aren't.
+
+use std::cmp;
+use std::collections::BTreeMap;
+
+use asset::AssetID;
+use grafix::sprite::{self, Batcher, Color, DrawReq};
+use math;
+use rng::Rng;
+use time;
+use units::*;
+
+/// Describes the particles an `Emitter` gives off.
+#[derive(Clone,Debug)]
+pub struct EmitterDesc {
+    /// The sheet holding the particles' sprites.
+    pub sheet_id: AssetID,
+
+    /// The sprite which particles start with. A particle steps through `frame_count` sprites from
+    /// this one over its life.
+    pub first_frame: usize,
+
+    /// The number of frames each particle steps through (at least 1).
+    pub frame_count: usize,
+
+    /// The number of particles given off per second, while the emitter is active.
+    pub rate: f32,
+
+    /// The shortest and longest a particle can live. Each particle lives for a random time
+    /// between them.
+    pub lifetime: (time::Duration, time::Duration),
+
+    /// The smallest velocity a particle can start with along each axis, in meters per second.
+    pub velocity_min: math::Vec3<Meters>,
+
+    /// The largest velocity a particle can start with along each axis, in meters per second.
+    pub velocity_max: math::Vec3<Meters>,
+
+    /// How far from the emitter along each axis particles can appear, e.g. to fill an area with
+    /// dust rather than spray it from a point.
+    pub spread: math::Vec3<Meters>,
+
+    /// The acceleration downwards (along -z), in meters per second per second. Negative values
+    /// make particles rise, like smoke.
+    pub gravity: Meters,
+
+    /// If set, particles die as soon as they fall below this height.
+    pub floor: Option<Meters>,
+
+    /// The most particles the emitter will have alive at once.
+    pub max_particles: usize,
+
+    /// The tint each particle is drawn with.
+    pub tint: Color,
+
+    /// Fade particles out over their life, by scaling the alpha of `tint` down to 0.
+    pub fade: bool,
+
+    /// The size to draw the particles at, relative to the sheet.
+    pub scale: f32,
+
+    /// The layer to draw the particles in.
+    pub layer: u8,
+}
+
+impl EmitterDesc {
+    /// A description of particles drawn with sprite `frame` of `sheet_id`, which gives off 10 per
+    /// second and sets them still in midair for a second, to be adjusted from there.
+    pub fn new(sheet_id: AssetID, frame: usize) -> EmitterDesc {
+        EmitterDesc {
+            sheet_id:      sheet_id,
+            first_frame:   frame,
+            frame_count:   1,
+            rate:          10.0,
+            lifetime:      (time::Duration::sec(1), time::Duration::sec(1)),
+            velocity_min:  vec3!(Meters ; 0.0, 0.0, 0.0),
+            velocity_max:  vec3!(Meters ; 0.0, 0.0, 0.0),
+            spread:        vec3!(Meters ; 0.0, 0.0, 0.0),
+            gravity:       Meters(0.0),
+            floor:         None,
+            max_particles: 256,
+            tint:          sprite::WHITE,
+            fade:          false,
+            scale:         1.0,
+            layer:         sprite::LAYER_FX,
+        }
+    }
+}
+
+#[derive(Clone,Copy,Debug)]
+struct Particle {
+    pos: math::Vec3<Meters>,
+    vel: math::Vec3<Meters>,
+
+    born: time::Duration,
+    dies: time::Duration,
+}
+
+/// Gives off particles according to an `EmitterDesc`, and simulates them.
+pub struct Emitter {
+    desc: EmitterDesc,
+    pos:  math::Vec3<Meters>,
+
+    particles: Vec<Particle>,
+
+    active: bool,
+
+    // Fractions of a particle which are due but haven't been given off yet.
+    owed: f32,
+
+    // The time of the last call to `update`.
+    last: Option<time::Duration>,
+
+    rng: Rng,
+}
+
+impl Emitter {
+    /// Create an active emitter at `pos`. Its particles are randomized with `rng`, which can be
+    /// forked from the entity `Manager`'s if the effect should be the same on every client.
+    pub fn new(desc: EmitterDesc, pos: math::Vec3<Meters>, rng: Rng) -> Emitter {
+        Emitter {
+            desc:      desc,
+            pos:       pos,
+            particles: vec![],
+            active:    true,
+            owed:      0.0,
+            last:      None,
+            rng:       rng,
+        }
+    }
+
+    /// The description of the particles.
+    pub fn desc(&self) -> &EmitterDesc {
+        &self.desc
+    }
+
+    /// Change the description of the particles. Particles which are already alive keep moving as
+    /// they were, but are drawn according to `desc`.
+    pub fn set_desc(&mut self, desc: EmitterDesc) {
+        self.desc = desc;
+    }
+
+    /// Where new particles are given off, in the world.
+    pub fn position(&self) -> math::Vec3<Meters> {
+        self.pos
+    }
+
+    /// Move the emitter. Particles which are already alive stay where they are.
+    pub fn set_position(&mut self, pos: math::Vec3<Meters>) {
+        self.pos = pos;
+    }
+
+    /// Start or stop giving off particles. Particles which are already alive live out their lives
+    /// either way.
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+        self.owed   = 0.0;
+    }
+
+    /// Whether the emitter is giving off particles.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// True if the emitter is inactive and all of its particles have died.
+    pub fn is_finished(&self) -> bool {
+        !self.active && self.particles.is_empty()
+    }
+
+    /// The number of particles alive.
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+
+    /// Give off `count` particles at once (as far as `max_particles` allows), e.g. for an
+    /// explosion. This works whether or not the emitter is active.
+    pub fn burst(&mut self, count: usize, now: time::Duration) {
+        for _ in 0..count {
+            self.spawn(now);
+        }
+    }
+
+    /// Advance the simulation to `now`: move the particles, kill the ones which have died, and give
+    /// off new ones.
+    pub fn update(&mut self, now: time::Duration) {
+        let dt = match self.last {
+            Some(last) if now > last => (now - last).as_usec() as f32 / 1_000_000.0,
+            _                        => 0.0,
+        };
+
+        self.last = Some(now);
+
+        let gravity = self.desc.gravity;
+        let floor   = self.desc.floor;
+
+        for p in self.particles.iter_mut() {
+            p.vel.z = p.vel.z - gravity * Meters(dt);
+            p.pos   = p.pos + p.vel.scaled(Meters(dt));
+        }
+
+        self.particles.retain(|p| {
+            now < p.dies && floor.map_or(true, |floor| p.pos.z >= floor)
+        });
+
+        if self.active {
+            self.owed += self.desc.rate * dt;
+
+            while self.owed >= 1.0 {
+                self.owed -= 1.0;
+                self.spawn(now);
+            }
+        }
+    }
+
+    /// Register a `DrawReq` for each live particle, as of `now`.
+    pub fn draw(&self, batcher: &mut Batcher, now: time::Duration) {
+        let desc   = &self.desc;
+        let frames = if desc.frame_count > 0 { desc.frame_count } else { 1 };
+
+        for p in self.particles.iter() {
+            let life = (now - p.born) / (p.dies - p.born);
+            let life = if life.is_finite() { life.max(0.0).min(1.0) as f32 } else { 0.0 };
+
+            let frame = cmp::min((life * frames as f32) as usize, frames - 1);
+
+            let mut tint = desc.tint;
+
+            if desc.fade {
+                tint[3] *= 1.0 - life;
+            }
+
+            batcher.register(DrawReq {
+                sheet_id:   desc.sheet_id,
+                sprite_idx: desc.first_frame + frame,
+                game_loc:   p.pos,
+                tint:       tint,
+                flip_x:     false,
+                scale:      desc.scale,
+                rotation:   0.0,
+                highlight:  None,
+                layer:      desc.layer,
+                material:   sprite::DEFAULT_MATERIAL,
+            });
+        }
+    }
+
+    // Give off a particle, unless there are already `max_particles`.
+    fn spawn(&mut self, now: time::Duration) {
+        if self.particles.len() >= self.desc.max_particles {
+            return
+        }
+
+        let (lo, hi) = self.desc.lifetime;
+        let life     = if hi > lo { lo + (hi - lo) * self.rng.next_f32() as f64 } else { lo };
+
+        let spread = self.desc.spread;
+        let offset = vec3!(Meters(self.rng.range_f32(-spread.x.0, spread.x.0)),
+                           Meters(self.rng.range_f32(-spread.y.0, spread.y.0)),
+                           Meters(self.rng.range_f32(-spread.z.0, spread.z.0)));
+
+        let (vmin, vmax) = (self.desc.velocity_min, self.desc.velocity_max);
+        let vel = vec3!(Meters(self.rng.range_f32(vmin.x.0, vmax.x.0)),
+                        Meters(self.rng.range_f32(vmin.y.0, vmax.y.0)),
+                        Meters(self.rng.range_f32(vmin.z.0, vmax.z.0)));
+
+        self.particles.push(Particle {
+            pos:  self.pos + offset,
+            vel:  vel,
+            born: now,
+            dies: now + life,
+        });
+    }
+}
+
+/// Identifies an emitter in a `Particles`. IDs are never reused by the same `Particles`.
+pub type EmitterID = u64;
+
+/// A collection of emitters which are updated and drawn together.
+pub struct Particles {
+    emitters: BTreeMap<EmitterID, Emitter>,
+    next:     EmitterID,
+}
+
+impl Particles {
+    /// Create an empty collection.
+    pub fn new() -> Particles {
+        Particles { emitters: BTreeMap::new(), next: 0 }
+    }
+
+    /// Add an emitter, returning its ID.
+    pub fn add(&mut self, emitter: Emitter) -> EmitterID {
+        let id = self.next;
+        self.next += 1;
+
+        self.emitters.insert(id, emitter);
+
+        id
+    }
+
+    /// Remove an emitter immediately, along with its particles.
+    pub fn remove(&mut self, id: EmitterID) -> Option<Emitter> {
+        self.emitters.remove(&id)
+    }
+
+    /// The emitter with ID `id`, if it's still in the collection.
+    pub fn get(&self, id: EmitterID) -> Option<&Emitter> {
+        self.emitters.get(&id)
+    }
+
+    /// The emitter with ID `id`, if it's still in the collection.
+    pub fn get_mut(&mut self, id: EmitterID) -> Option<&mut Emitter> {
+        self.emitters.get_mut(&id)
+    }
+
+    /// The total number of particles alive.
+    pub fn len(&self) -> usize {
+        self.emitters.values().fold(0, |n, e| n + e.len())
+    }
+
+    /// Advance every emitter to `now`. Emitters which are finished (see `Emitter::is_finished`)
+    /// are removed, so a one-off effect can be added, given a `burst` and deactivated, and then
+    /// forgotten about.
+    pub fn update(&mut self, now: time::Duration) {
+        for e in self.emitters.values_mut() {
+            e.update(now);
+        }
+
+        let finished: Vec<_> = self.emitters.iter()
+            .filter(|&(_, e)| e.is_finished())
+            .map(|(&id, _)| id)
+            .collect();
+
+        for id in finished {
+            self.emitters.remove(&id);
+        }
+    }
+
+    /// Register a `DrawReq` for each live particle of every emitter, as of `now`.
+    pub fn draw(&self, batcher: &mut Batcher, now: time::Duration) {
+        for e in self.emitters.values() {
+            e.draw(batcher, now);
+        }
+    }
+}
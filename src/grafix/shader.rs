@@ -0,0 +1,307 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Where the engine's shaders come from.
+//!
+//! Normally the shaders are compiled into the binary. During development they can be read from a
+//! directory instead, with `load_from`, so that they can be edited while the game runs. `poll` then
+//! watches the files there, and bumps `generation` whenever one of them is saved:
+//!
+//! ```ignore
+//! shader::load_from("src/grafix/shaders");
+//!
+//! // Each iteration of the main loop:
+//! shader::poll();
+//! ```
+//!
+//! Whatever was built from the shaders rebuilds itself once it sees that `generation` has changed,
+//! in the same way as it does when the GL context is recreated (see `grafix::device`). Renderers
+//! build all of their programs before replacing the old ones, so a shader which doesn't compile
+//! leaves the old programs in place, and the error is logged with the file's name.
+//!
+//! Every shader goes through an `opengl::Preprocessor` (see `preprocessor`), so shaders can share
+//! code by including the `.glsl` files alongside them, and use the engine's constants.
+//!
+//! Games can add shaders of their own alongside the engine's with `embed`, such as the fragment
+//! shaders of their `sprite::Material`s.
+
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::convert::AsRef;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use grafix::opengl::{Preprocessor, Shader, ShaderFileError, ShaderProgram, ShaderStage};
+use grafix::sprite;
+use logging;
+use watch::Watched;
+
+// The engine's shaders, and the files they include, as compiled into the binary.
+const EMBEDDED: &'static [(&'static str, &'static str)] = &[
+    ("frame.glsl",            include_str!("shaders/frame.glsl")),
+    ("light_space.glsl",      include_str!("shaders/light_space.glsl")),
+    ("sprite.glsl",           include_str!("shaders/sprite.glsl")),
+    ("srgb.glsl",             include_str!("shaders/srgb.glsl")),
+    ("line.frg",              include_str!("shaders/line.frg")),
+    ("line.vtx",              include_str!("shaders/line.vtx")),
+    ("outline.frg",           include_str!("shaders/outline.frg")),
+    ("outline.geo",           include_str!("shaders/outline.geo")),
+    ("outline_instanced.vtx", include_str!("shaders/outline_instanced.vtx")),
+    ("shadow.frg",            include_str!("shaders/shadow.frg")),
+    ("shadow.geo",            include_str!("shaders/shadow.geo")),
+    ("shadow_instanced.vtx",  include_str!("shaders/shadow_instanced.vtx")),
+    ("sprite.frg",            include_str!("shaders/sprite.frg")),
+    ("sprite.geo",            include_str!("shaders/sprite.geo")),
+    ("sprite.vtx",            include_str!("shaders/sprite.vtx")),
+    ("sprite_instanced.vtx",  include_str!("shaders/sprite_instanced.vtx")),
+    ("thumb.frg",             include_str!("shaders/thumb.frg")),
+    ("thumb.vtx",             include_str!("shaders/thumb.vtx")),
+];
+
+// The directory which shaders are read from, if they aren't the compiled-in copies, and a watch on
+// each of the engine's shaders there.
+struct Dir {
+    path:  PathBuf,
+    files: Vec<Watched>,
+}
+
+// Shaders are only compiled on the thread which owns the GL context, like everything else in
+// `opengl`.
+thread_local!(static DIR: RefCell<Option<Dir>> = RefCell::new(None));
+thread_local!(static GENERATION: Cell<usize> = Cell::new(0));
+thread_local!(static DEFINES: RefCell<Vec<(String, String)>> = RefCell::new(vec![]));
+
+// The shaders which the game has added alongside the engine's, with `embed`.
+thread_local!(static EXTRA: RefCell<Vec<(String, &'static str)>> = RefCell::new(vec![]));
+
+/// Read the shaders from the files in `dir` from now on, rather than using the copies compiled into
+/// the binary, and watch those files for changes (see `poll`). Everything built from the shaders
+/// is rebuilt from the files, as if they had all just changed.
+pub fn load_from<P: AsRef<Path>>(dir: P) {
+    let path = dir.as_ref().to_path_buf();
+
+    let mut names: Vec<String> = EMBEDDED.iter().map(|&(name, _)| From::from(name)).collect();
+
+    EXTRA.with(|extra| names.extend(extra.borrow().iter().map(|&(ref name, _)| name.clone())));
+
+    let files = names.iter().map(|name| Watched::new(path.join(name))).collect();
+
+    info!(target: logging::GFX, "reading shaders from `{}'", path.display());
+
+    DIR.with(|dir| *dir.borrow_mut() = Some(Dir { path: path, files: files }));
+    bump();
+}
+
+/// Make the shader called `name` available alongside the engine's, as though it were compiled into
+/// the engine along with them. This is how a game provides the shaders of its `sprite::Material`s,
+/// and the files they include. Like the engine's own, it's read from the directory given to
+/// `load_from` instead while that's in use, and watched there. Everything built from the shaders
+/// is rebuilt, in case it replaces one of them.
+pub fn embed(name: &str, src: &'static str) {
+    EXTRA.with(|extra| {
+        let mut extra = extra.borrow_mut();
+
+        extra.retain(|&(ref n, _)| n != name);
+        extra.push((From::from(name), src));
+    });
+
+    DIR.with(|dir| {
+        if let Some(ref mut dir) = *dir.borrow_mut() {
+            let path = dir.path.join(name);
+
+            if !dir.files.iter().any(|f| f.path() == path.as_path()) {
+                dir.files.push(Watched::new(path));
+            }
+        }
+    });
+
+    bump();
+}
+
+/// Go back to the shaders compiled into the binary, after `load_from`. Everything built from the
+/// shaders is rebuilt.
+pub fn load_embedded() {
+    DIR.with(|dir| *dir.borrow_mut() = None);
+    bump();
+}
+
+/// The directory which shaders are being read from, or `None` if they're compiled in.
+pub fn dir() -> Option<PathBuf> {
+    DIR.with(|dir| dir.borrow().as_ref().map(|dir| dir.path.clone()))
+}
+
+/// Check the shader files for changes, if they're being read from disk (see `load_from`). If any
+/// of them has changed, `generation` goes up and this returns true. This is cheap, and should be
+/// called once per iteration of the main loop.
+pub fn poll() -> bool {
+    let changed: Vec<PathBuf> = DIR.with(|dir| {
+        match *dir.borrow_mut() {
+            // Poll every file, so that all of their timestamps are up to date.
+            Some(ref mut dir) => dir.files.iter_mut()
+                .filter_map(|f| if f.changed() { Some(f.path().to_path_buf()) } else { None })
+                .collect(),
+
+            None => vec![],
+        }
+    });
+
+    for path in changed.iter() {
+        info!(target: logging::GFX, "shader `{}' changed, rebuilding", path.display());
+    }
+
+    if changed.is_empty() {
+        return false
+    }
+
+    bump();
+    true
+}
+
+/// Define `name` as `value` in every shader, e.g. to turn on an optional effect which the shaders
+/// test for with `#ifdef`. Everything built from the shaders is rebuilt.
+pub fn define<V: fmt::Display>(name: &str, value: V) {
+    let value = value.to_string();
+
+    DEFINES.with(|defines| {
+        let mut defines = defines.borrow_mut();
+
+        defines.retain(|&(ref n, _)| n != name);
+        defines.push((From::from(name), value));
+    });
+
+    bump();
+}
+
+/// Stop defining `name`, after `define`. Everything built from the shaders is rebuilt.
+pub fn undefine(name: &str) {
+    DEFINES.with(|defines| defines.borrow_mut().retain(|&(ref n, _)| n != name));
+    bump();
+}
+
+/// The preprocessor which every shader goes through. Included files are looked for in the
+/// directory given to `load_from`, if any, and then among the files compiled into the binary.
+/// `MAX_LIGHTS` and `DEPTH_SCALE` are defined as their namesakes in `grafix::sprite` are, along
+/// with whatever was given to `define`.
+pub fn preprocessor() -> Preprocessor {
+    let mut pp = Preprocessor::new();
+
+    if let Some(dir) = dir() {
+        pp.search(dir);
+    }
+
+    for &(name, src) in EMBEDDED.iter() {
+        pp.builtin(name, src);
+    }
+
+    EXTRA.with(|extra| {
+        for &(ref name, src) in extra.borrow().iter() {
+            pp.builtin(name, src);
+        }
+    });
+
+    pp.define("MAX_LIGHTS", sprite::MAX_LIGHTS);
+    pp.define("DEPTH_SCALE", format!("float({})", sprite::DEPTH_SCALE));
+
+    DEFINES.with(|defines| {
+        for &(ref name, ref value) in defines.borrow().iter() {
+            pp.define(name, value);
+        }
+    });
+
+    pp
+}
+
+/// A number which goes up whenever the shaders change (see `poll`). Anything built from the
+/// shaders should remember the generation it was built in, and rebuild itself once this differs.
+pub fn generation() -> usize {
+    GENERATION.with(|g| g.get())
+}
+
+fn bump() {
+    GENERATION.with(|g| g.set(g.get() + 1));
+}
+
+// Where the shader called `name` comes from, for error messages.
+fn path(name: &str) -> PathBuf {
+    match dir() {
+        Some(dir) => dir.join(name),
+        None      => PathBuf::from(name),
+    }
+}
+
+/// The source of the shader called `name` (such as "sprite.frg"): either the copy compiled into
+/// the binary, or the file in the directory given to `load_from`.
+pub fn source(name: &str) -> Result<Cow<'static, str>, ShaderFileError> {
+    if let Some(dir) = dir() {
+        let path    = dir.join(name);
+        let mut src = String::new();
+
+        return match fs::File::open(&path).and_then(|mut f| f.read_to_string(&mut src)) {
+            Ok(_)    => Ok(Cow::Owned(src)),
+            Err(err) => Err(ShaderFileError::Read(path, err)),
+        }
+    }
+
+    let extra = EXTRA.with(|extra| {
+        extra.borrow().iter().find(|&&(ref n, _)| n == name).map(|&(_, src)| src)
+    });
+
+    match EMBEDDED.iter().find(|&&(n, _)| n == name).map(|&(_, src)| src).or(extra) {
+        Some(src) => Ok(Cow::Borrowed(src)),
+        None      => Err(ShaderFileError::Read(PathBuf::from(name), io::Error::new(
+            io::ErrorKind::NotFound, "no such shader is compiled into the engine"))),
+    }
+}
+
+/// Preprocess and compile the shaders called `names`, each for the stage its extension gives (see
+/// `ShaderStage::from_path`), and link them into a program.
+pub fn program(names: &[&str]) -> Result<ShaderProgram, ShaderFileError> {
+    let shaders = try!(compile(names));
+
+    ShaderProgram::new(&shaders).map_err(ShaderFileError::Link)
+}
+
+/// Like `program`, but capture the vertex attributes `xfb_attrs` with transform feedback (see
+/// `ShaderProgram::new_xfb`).
+pub fn program_xfb(names: &[&str], xfb_attrs: &[&str]) -> Result<ShaderProgram, ShaderFileError> {
+    let shaders = try!(compile(names));
+
+    ShaderProgram::new_xfb(&shaders, xfb_attrs).map_err(ShaderFileError::Link)
+}
+
+fn compile(names: &[&str]) -> Result<Vec<Shader>, ShaderFileError> {
+    let pp = preprocessor();
+
+    let mut shaders = Vec::with_capacity(names.len());
+
+    for &name in names.iter() {
+        let stage = match ShaderStage::from_path(name) {
+            Some(stage) => stage,
+            None        => return Err(ShaderFileError::UnknownStage(path(name))),
+        };
+
+        let src = try!(source(name));
+        let src = try!(pp.process(path(name), &src));
+
+        shaders.push(try!(Shader::with_stage(stage, &src).map_err(|err| {
+            ShaderFileError::Compile(path(name), err)
+        })));
+    }
+
+    Ok(shaders)
+}
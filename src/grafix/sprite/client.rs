@@ -15,79 +15,202 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-use std::convert::{AsRef, From};
+use std::cell::RefCell;
+use std::cmp::{self, Ordering};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::From;
+use std::f32;
 use std::mem;
+use std::time::Instant;
 
 use gl;
 use gl::types::*;
 use png;
+use scoped_threadpool::Pool;
 
+use arena::FrameArena;
 use asset::{self, AssetID};
 use grafix::camera::Camera;
+use grafix::device::Recreate;
 use grafix::opengl;
-use grafix;
+use grafix::shader;
+use grafix::sprite::SheetDesc;
+use grafix::{PassTimer, Stats};
+use logging;
 use math;
+use time;
+use trace;
 use units::*;
 
 // The maximum number of sprites that can be drawn on-screen at any given time.
 const MAX_SPRITES: usize = 16 * 1024;
 
-/// A descriptor which explains the properties of a sprite sheet and where to find the textures.
-pub struct SheetDesc {
-    /// Width of the texture, in texels.
-    pub img_width:  u16,
+// The number of sprites converted to vertices by each job, when the `Batcher` has a thread pool.
+// Batches smaller than this are converted on the calling thread, since handing them off would cost
+// more than it saves.
+const SPRITES_PER_JOB: usize = 1024;
 
-    /// Height of the texture, in texels.
-    pub img_height: u16,
+/// How far in meters the extremes of a depth texture are from a sprite's origin, toward or away
+/// from the camera. The shaders see this as `DEPTH_SCALE` (see `grafix::shader::preprocessor`).
+pub const DEPTH_SCALE: f32 = 5.0;
 
-    /// X-coordinate of origin pixel.
-    pub origin_x: u16,
+// The width and height of the shadow map, in texels.
+const SHADOW_MAP_SIZE: u32 = 2048;
 
-    /// Y-coordinate of origin pixel.
-    pub origin_y: u16,
+// How far in meters a fragment must be behind what the shadow map saw to be in shadow. Shadows are
+// cast by each sprite's quad at the depth of its origin, so this has to be generous enough that
+// sprites don't shadow themselves.
+const SHADOW_BIAS: f32 = 1.0;
 
-    /// Width of each sprite, in texels.
-    pub spr_width: u16,
+// The value in a `Sheet`'s depth mask for texels which the fragment shader discards.
+const MASK_EMPTY: u8 = 255;
 
-    /// Height of each sprite, in texels.
-    pub spr_height: u16,
+// Depth texels at or above this value are discarded by the fragment shader, which throws away
+// samples above 0.95 once they've been scaled to `[-1, 1]`.
+const MASK_DISCARD: u8 = 249;
 
-    /// Number of sprites in each row in the sheet.
-    pub num_across: u16,
+/// An RGBA color, with each channel in `[0, 1]`.
+pub type Color = [f32; 4];
 
-    /// Number of sprites in each column in the sheet.
-    pub num_down: u16,
+/// The tint which leaves a sprite as it was drawn.
+pub const WHITE: Color = [1.0, 1.0, 1.0, 1.0];
 
-    /// Total number of sprites in the sheet.
-    pub total: u16,
+/// The layer for the ground and anything flat on it, drawn first.
+pub const LAYER_TERRAIN: u8 = 0;
 
-    /// Path to the color PNG for this sprite sheet.
-    pub color_path: String,
+/// The layer for units, buildings and other things standing in the world.
+pub const LAYER_UNITS: u8 = 64;
 
-    /// Path to the depth PNG for this sprite sheet.
-    pub depth_path: String,
+/// The layer for effects such as explosions and spell trails, drawn over the units.
+pub const LAYER_FX: u8 = 128;
+
+/// The layer for overlays such as health bars and selection markers, drawn last.
+pub const LAYER_UI: u8 = 192;
+
+/// The most lights which can shine on the sprites in one frame. The shaders see this as
+/// `MAX_LIGHTS` (see `grafix::shader::preprocessor`).
+pub const MAX_LIGHTS: usize = 8;
+
+/// The uniform block binding point which the sprite programs read their `Frame` block from (see
+/// `FrameUniforms`).
+pub const FRAME_BINDING: u32 = 0;
+
+/// The material which every sprite is drawn with unless it asks for another, which is the engine's
+/// own look (see `Material`).
+pub const DEFAULT_MATERIAL: MaterialID = 0;
+
+// The most sheets which share one pair of array textures. OpenGL guarantees at least 256 layers.
+const MAX_ARRAY_LAYERS: usize = 256;
+
+// The textures shared by the sheets whose color and depth images have the same sizes, each sheet
+// taking one layer of each array. Sprites from sheets in the same arrays can be drawn in one call.
+struct SheetArray {
+    color: opengl::Tex2DArray,
+    depth: opengl::Tex2DArray,
+
+    // Which layers are taken by a live `Sheet`.
+    used: Vec<bool>,
 }
 
-impl SheetDesc {
-    /// Convert from FlatBuffer representation.
-    pub fn from_wire(w: &grafix::sprite::wire::SpriteSheetDesc) -> SheetDesc {
-        SheetDesc {
-            img_width:  w.img_width(),
-            img_height: w.img_height(),
-            origin_x:   w.origin_x(),
-            origin_y:   w.origin_y(),
-            spr_width:  w.spr_width(),
-            spr_height: w.spr_height(),
-            num_across: w.num_across(),
-            num_down:   w.num_down(),
-            total:      w.total(),
-            color_path: From::from(AsRef::as_ref(w.color_path().unwrap())),
-            depth_path: From::from(AsRef::as_ref(w.depth_path().unwrap())),
+impl SheetArray {
+    fn new(color: (u32, u32), depth: (u32, u32)) -> SheetArray {
+        SheetArray {
+            color: opengl::Tex2DArray::new(color.0, color.1, 1, opengl::ArrayFormat::Color),
+            depth: opengl::Tex2DArray::new(depth.0, depth.1, 1, opengl::ArrayFormat::Red),
+            used:  vec![false],
         }
     }
+
+    fn fits(&self, color: (u32, u32), depth: (u32, u32)) -> bool {
+        (self.color.width(), self.color.height()) == color &&
+            (self.depth.width(), self.depth.height()) == depth &&
+            (self.used.len() < MAX_ARRAY_LAYERS || self.used.iter().any(|&used| !used))
+    }
+
+    // Take a free layer, doubling the number of layers if they're all taken.
+    fn take_layer(&mut self) -> u32 {
+        let layer = match self.used.iter().position(|&used| !used) {
+            Some(layer) => layer,
+            None        => {
+                let layer  = self.used.len();
+                let layers = cmp::min(layer * 2, MAX_ARRAY_LAYERS);
+
+                self.color.resize(layers as u32);
+                self.depth.resize(layers as u32);
+
+                for _ in layer..layers { self.used.push(false) }
+
+                layer
+            },
+        };
+
+        self.used[layer] = true;
+        layer as u32
+    }
+}
+
+// Every `SheetArray`, by ID. Only the thread with the GL context makes sheets, so this is kept per
+// thread rather than passed to `Sheet::from_desc`.
+struct ArrayPool {
+    arrays:  BTreeMap<usize, SheetArray>,
+    next_id: usize,
+
+    // The context the arrays were made in.
+    generation: usize,
+}
+
+thread_local!(static ARRAYS: RefCell<ArrayPool> = RefCell::new(ArrayPool {
+    arrays:     BTreeMap::new(),
+    next_id:    0,
+    generation: 0,
+}));
+
+// Put a sheet's images in a free layer of the arrays for their sizes, making or growing arrays if
+// need be. Returns the ID of the arrays and the layer.
+fn store_sheet(color: &png::Image, depth: &png::Image) -> (usize, u32) {
+    let color_size = (color.width, color.height);
+    let depth_size = (depth.width, depth.height);
+
+    ARRAYS.with(|pool| {
+        let mut pool = pool.borrow_mut();
+
+        // Arrays from a lost context are gone, and their sheets are being recreated.
+        if pool.generation != opengl::generation() {
+            pool.arrays.clear();
+            pool.generation = opengl::generation();
+        }
+
+        let found = pool.arrays.iter()
+            .find(|&(_, array)| array.fits(color_size, depth_size))
+            .map(|(&id, _)| id);
+
+        let id = match found {
+            Some(id) => id,
+            None     => {
+                let id = pool.next_id;
+
+                pool.next_id += 1;
+                pool.arrays.insert(id, SheetArray::new(color_size, depth_size));
+
+                id
+            },
+        };
+
+        let array = pool.arrays.get_mut(&id).unwrap();
+        let layer = array.take_layer();
+
+        array.color.upload_png(layer, color);
+        array.depth.upload_png(layer, depth);
+
+        (id, layer)
+    })
 }
 
 /// A sprite sheet.
+///
+/// Sheets don't have textures of their own. Their color and depth images are each a layer of
+/// array textures shared with the other sheets of the same size, so that the `Batcher` can draw
+/// sprites from all of them at once.
 pub struct Sheet {
     // Position of a sprite's origin as a ratio of width and height.
     origin: math::Vec2<Pixels>,
@@ -102,20 +225,43 @@ pub struct Sheet {
     // or bottom of the texture, if the sprites don't fit the texture perfectly.
     num_across: usize,
 
-    // RGBA texture which gives the sprite its color.
-    color: opengl::Tex2D,
+    // The `SheetArray` holding the sheet's RGBA color texture and its red depth texture, which
+    // gives each pixel's distance from the camera at render time, and the layer of each it's in.
+    array: usize,
+    layer: u32,
+
+    // The size of the color texture, and the GPU memory used by the sheet's layers.
+    width:  u32,
+    height: u32,
+    bytes:  usize,
+
+    // RGB texture which gives each pixel's surface normal in camera space, for lighting.
+    normal: Option<opengl::Tex2D>,
 
-    // Red texture which gives each pixels distance from the camera, at render time.
-    depth: opengl::Tex2D,
+    // Red texture which says how much each pixel lights itself, regardless of the lighting.
+    emissive: Option<opengl::Tex2D>,
+
+    // A copy of the depth texture's red channel kept on the CPU for hit tests, with texels which
+    // aren't drawn (transparent, or discarded for their depth) set to `MASK_EMPTY`.
+    mask: Vec<u8>,
 }
 
 impl Sheet {
     /// Load a `Sheet` from a descriptor. This turns the paths in the `SheetDesc` into OpenGL
     /// textures.
-    pub fn from_desc(desc: SheetDesc) -> Result<Sheet, Error> {
+    pub fn from_desc(desc: &SheetDesc) -> Result<Sheet, Error> {
+        let _op = opengl::operation("uploading sheet", &desc.color_path);
+
         let color_png = try!(png::load_png(&desc.color_path).map_err(Error::PngError));
         let depth_png = try!(png::load_png(&desc.depth_path).map_err(Error::PngError));
 
+        let normal   = try!(optional_texture(&desc.normal_path));
+        let emissive = try!(optional_texture(&desc.emissive_path));
+
+        let (array, layer) = store_sheet(&color_png, &depth_png);
+
+        let texels = |img: &png::Image| img.width as usize * img.height as usize;
+
         Ok( Sheet {
             origin: vec2!(Pixels ; desc.origin_x as f32, desc.origin_y as f32),
 
@@ -128,12 +274,162 @@ impl Sheet {
 
             num_across: desc.num_across as usize,
 
-            color: opengl::Tex2D::from_png(&color_png),
-            depth: opengl::Tex2D::from_png(&depth_png),
+            array:  array,
+            layer:  layer,
+            width:  color_png.width,
+            height: color_png.height,
+            bytes:  texels(&color_png) * 4 + texels(&depth_png),
+
+            normal:   normal,
+            emissive: emissive,
+
+            mask: depth_mask(&color_png, &depth_png),
+        })
+    }
+
+    /// The depth of the texel at texture coordinate `(u, v)`, relative to the origin of the sprite
+    /// it belongs to: positive is further from the camera. Returns `None` for texels which aren't
+    /// drawn, and for coordinates outside the sheet.
+    ///
+    /// This reads a copy of the depth texture kept in memory, so it's cheap enough to call for
+    /// every sprite under the mouse each frame.
+    pub fn depth_offset(&self, u: TexCoord, v: TexCoord) -> Option<Meters> {
+        let (w, h) = self.dimensions();
+
+        let x = (u.0 * w as f32).floor();
+        let y = (v.0 * h as f32).floor();
+
+        if !(x >= 0.0 && y >= 0.0 && x < w as f32 && y < h as f32) {
+            return None
+        }
+
+        match self.mask.get(y as usize * w as usize + x as usize) {
+            Some(&MASK_EMPTY) | None => None,
+            Some(&r)                 => Some(Meters((2.0 * r as f32 / 255.0 - 1.0) * DEPTH_SCALE)),
+        }
+    }
+
+    /// The amount of memory used by the copy of the depth texture kept for `depth_offset`, in
+    /// bytes.
+    pub fn mask_bytes(&self) -> usize {
+        self.mask.len()
+    }
+
+    /// The amount of GPU memory used by this sheet's textures, in bytes. This counts the sheet's
+    /// layers of the arrays it shares, but not the layers which no sheet is using.
+    pub fn bytes(&self) -> usize {
+        let optional = |tex: &Option<opengl::Tex2D>| tex.as_ref().map_or(0, |t| t.bytes());
+
+        self.bytes + optional(&self.normal) + optional(&self.emissive)
+    }
+
+    /// The width and height of the sheet's image, in pixels.
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Bind the array texture which gives the sprites their color to texture unit `unit`, e.g. to
+    /// show the whole sheet in a debug view. The sheet is layer `layer()` of it.
+    pub fn bind_color_texture(&self, unit: usize) {
+        ARRAYS.with(|pool| {
+            if let Some(array) = pool.borrow().arrays.get(&self.array) {
+                array.color.bind_to_unit(unit);
+            }
+        })
+    }
+
+    /// The layer of the array textures which holds this sheet.
+    pub fn layer(&self) -> u32 {
+        self.layer
+    }
+
+    // Whether sprites from this sheet and `other` can be drawn in one call: they share array
+    // textures, and neither has lighting maps, which are bound a sheet at a time.
+    fn batches_with(&self, other: &Sheet) -> bool {
+        self.array == other.array &&
+            self.normal.is_none() && self.emissive.is_none() &&
+            other.normal.is_none() && other.emissive.is_none()
+    }
+
+    // Bind the color and depth arrays to units 0 and 1, where the sprite programs read them.
+    fn bind_textures(&self) {
+        ARRAYS.with(|pool| {
+            if let Some(array) = pool.borrow().arrays.get(&self.array) {
+                array.color.bind_to_unit(0);
+                array.depth.bind_to_unit(1);
+            }
+        })
+    }
+}
+
+impl Drop for Sheet {
+    /// Free the sheet's layers for another sheet, and free the arrays once no sheet uses them.
+    fn drop(&mut self) {
+        ARRAYS.with(|pool| {
+            let mut pool = pool.borrow_mut();
+
+            let empty = match pool.arrays.get_mut(&self.array) {
+                Some(array) => {
+                    array.used[self.layer as usize] = false;
+                    array.used.iter().all(|&used| !used)
+                },
+
+                None => false,
+            };
+
+            if empty {
+                pool.arrays.remove(&self.array);
+            }
         })
     }
 }
 
+// Load the texture at `path`, if there is one.
+fn optional_texture(path: &Option<String>) -> Result<Option<opengl::Tex2D>, Error> {
+    match *path {
+        Some(ref path) => {
+            let img = try!(png::load_png(path).map_err(Error::PngError));
+            Ok(Some(opengl::Tex2D::from_png(&img)))
+        },
+        None => Ok(None),
+    }
+}
+
+// Build a `Sheet`'s depth mask: the red channel of `depth`, except where either image says the
+// fragment shader will discard the texel. If the images are different sizes, only `depth` is used.
+fn depth_mask(color: &png::Image, depth: &png::Image) -> Vec<u8> {
+    use png::PixelsByColorType::*;
+
+    let texels = depth.width as usize * depth.height as usize;
+
+    let mut mask: Vec<u8> = match depth.pixels {
+        K8(ref pix)    => pix.clone(),
+        RGB8(ref pix)  => pix.chunks(3).map(|px| px[0]).collect(),
+        RGBA8(ref pix) => pix.chunks(4).map(|px| px[0]).collect(),
+        _              => vec![MASK_EMPTY; texels],
+    };
+
+    for r in mask.iter_mut() {
+        if *r >= MASK_DISCARD {
+            *r = MASK_EMPTY;
+        }
+    }
+
+    let same_size = color.width == depth.width && color.height == depth.height;
+
+    if let RGBA8(ref pix) = color.pixels {
+        if same_size {
+            for (r, px) in mask.iter_mut().zip(pix.chunks(4)) {
+                if px[3] < 128 {
+                    *r = MASK_EMPTY;
+                }
+            }
+        }
+    }
+
+    mask
+}
+
 /// This is the vertex type that is sent to the GPU
 #[allow(non_snake_case)]
 #[derive(Debug,Copy,Clone)]
@@ -153,6 +449,20 @@ pub struct SpriteVertex {
     /// Depth of the origin of the sprite from the camera. In `Meters`, since that's the unit used
     /// in the depth texture.
     pub depth: Meters,
+
+    /// The color which the sprite's color is multiplied by.
+    pub tint: Color,
+
+    /// The point the sprite is rotated about, in screen coordinates.
+    pub pivot: math::Vec2<NDU>,
+
+    /// The rotation about `pivot`, as a column-major 2x2 matrix on screen coordinates. This has
+    /// the shape of the screen folded in, so that sprites aren't skewed as they turn.
+    pub transform: [f32; 4],
+
+    /// The layer of the sheet's array textures which holds the sprite (see `Sheet::layer`). It's
+    /// a float, since that's what the shaders' attributes are.
+    pub layer: f32,
 }
 
 impl SpriteVertex {
@@ -166,67 +476,405 @@ impl SpriteVertex {
             tex_BR: vec2!(TexCoord ; 0.0, 0.0),
 
             depth: Meters(0.0),
+
+            tint: [0.0; 4],
+
+            pivot:     vec2!(NDU ; 0.0, 0.0),
+            transform: [0.0; 4],
+
+            layer: 0.0,
         }
     }
 }
 
 /// An abstraction around the process of rendering a sprite. The `Batcher` dispatches sprites to a
 /// `Renderer` to be drawn, and the `Renderer` is free to accomplish that however it wishes.
-pub trait Renderer {
-    /// Send `verts` to the GPU and get ready to render sprites from it (i.e. bind buffers and use
-    /// programs, etc...)
-    fn prepare(&self, verts: &[SpriteVertex]);
+///
+/// Renderers hold GPU resources, so they must be able to rebuild them if the GL context is lost.
+pub trait Renderer: Recreate {
+    /// Get room for `count` vertices, which the `Batcher` writes the frame's sprites straight into
+    /// before calling `prepare`. This is usually mapped from the renderer's vertex buffer, so it
+    /// must only be written, never read.
+    fn vertices(&mut self, count: usize) -> &mut [SpriteVertex];
+
+    /// Start bounding the frame's sprites for the shadow map, if the lighting has a light which
+    /// casts shadows. The `Batcher` adds the sprites which cast shadows as it writes them, and
+    /// passes the result to `prepare`.
+    fn shadow_bounds(&self) -> Option<ShadowBounds>;
+
+    /// Send the vertices written into the room from `vertices` to the GPU and get ready to render
+    /// sprites from them (i.e. bind buffers and use programs, etc...). The shadow map is fitted
+    /// around `shadows`.
+    fn prepare(&mut self, shadows: Option<ShadowBounds>);
 
     /// Render a `RenderGroup`.
     fn render<'x>(&mut self, grp: RenderGroup<'x>);
+
+    /// Render outlines around the sprites of a `RenderGroup`, in each vertex's `tint` (see
+    /// `DrawReq::highlight`).
+    fn render_outlines<'x>(&mut self, grp: RenderGroup<'x>);
+
+    /// Light the sprites rendered from now on with `lighting`. Until this is called, sprites are
+    /// drawn exactly as they appear in their sheets.
+    fn set_lighting(&mut self, lighting: &Lighting);
+
+    /// Get ready to draw the shadow map for the bounds passed to `prepare`. Returns false, and does
+    /// nothing, if the lighting has no light which casts shadows.
+    fn begin_shadows(&mut self) -> bool;
+
+    /// Render the sprites of a `RenderGroup` into the shadow map.
+    fn render_shadows<'x>(&mut self, grp: RenderGroup<'x>);
+
+    /// Finish the shadow map, and get back to rendering sprites as `prepare` left things. The
+    /// viewport isn't restored.
+    fn end_shadows(&mut self);
 }
 
-macro_rules! attrib_offset {
-    ($attr:ident) => ( unsafe {
-        let base: &SpriteVertex = mem::transmute(0usize);
-        let offs: usize = mem::transmute(&base.$attr);
+/// The lights shining on the sprites in a frame, in camera space. Sprites are lit by the ambient
+/// light, plus each light according to how far away it is and which way the sprite's normal map
+/// says each pixel faces. Sprites whose sheets have no normal map face the camera.
+///
+/// At most `MAX_LIGHTS` lights are used. Directional lights are chosen first, then the point lights
+/// closest to the middle of the screen.
+pub struct Lighting {
+    ambient: [f32; 3],
 
-        offs
-    })
+    // The size of half of the screen in meters, for converting NDU to camera space in the fragment
+    // shader.
+    meters_per_ndu: math::Vec2<Meters>,
+
+    // The camera's view, for `frame`.
+    resolution: math::Vec2<Pixels>,
+    scale:      f32,
+
+    lights: Vec<ShaderLight>,
 }
 
-/// A group of sprites to be rendered at the same time. This struct only exists to be passed to the
-/// `Renderer::render` method, and references a range of sprites passed to that `Renderer` in the
-/// most recent call to `Renderer::prepare`.
-pub struct RenderGroup<'x> {
-    /// The index of the first sprite to be drawn.
-    pub first: usize,
+/// What the sprite programs need to know about the frame being drawn: the `Frame` uniform block
+/// which they share, laid out as in the shaders. Renderers keep it in an `opengl::UniformBlock`
+/// bound to `FRAME_BINDING`, and update it once per frame from `Lighting::frame`.
+#[repr(C)]
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub struct FrameUniforms {
+    /// The size of the camera's view, in pixels.
+    pub resolution: [f32; 2],
 
-    /// The number of sprites to be drawn.
-    pub count: usize,
+    /// The size of half of the screen, in meters.
+    pub meters_per_ndu: [f32; 2],
 
-    /// The sprite sheet on which these sprites reside (this just provides the textures).
-    pub sheet: &'x Sheet,
+    /// The ambient light, which every pixel gets.
+    pub ambient: [f32; 3],
+
+    /// The camera's scale, in pixels per meter.
+    pub pixels_per_meter: f32,
 }
 
-/// A `Renderer` which has no instrumentation, and is designed for performance alone.
-pub struct ReleaseRenderer {
+unsafe impl opengl::Std140 for FrameUniforms {}
+
+impl FrameUniforms {
+    // What the block holds until the first frame's lighting is set, which leaves sprites unlit.
+    fn unlit() -> FrameUniforms {
+        FrameUniforms {
+            resolution:       [1.0, 1.0],
+            meters_per_ndu:   [1.0, 1.0],
+            ambient:          [1.0, 1.0, 1.0],
+            pixels_per_meter: 1.0,
+        }
+    }
+}
+
+// A light as the fragment shader sees it.
+struct ShaderLight {
+    // The camera space position of a point light (with `w` = 1), or the direction of a directional
+    // light (with `w` = 0). The `z` coordinate is depth, as returned by `camera_to_screen`.
+    position: [f32; 4],
+
+    // The color times the intensity, and the radius of a point light.
+    color: [f32; 4],
+
+    // Lights with a lower priority are chosen first.
+    priority: f32,
+
+    // Whether the light casts shadows. Only directional lights can.
+    shadows: bool,
+}
+
+impl Lighting {
+    /// Lighting for a frame drawn from `cam`, with nothing but the `ambient` light. White ambient
+    /// light leaves sprites as they appear in their sheets.
+    pub fn new(cam: &Camera, ambient: [f32; 3]) -> Lighting {
+        let view = cam.view_resolution();
+
+        Lighting {
+            ambient:        ambient,
+            meters_per_ndu: vec2!(Meters ; view.x.0 / 2.0 / cam.scale, view.y.0 / 2.0 / cam.scale),
+            resolution:     view,
+            scale:          cam.scale,
+            lights:         vec![],
+        }
+    }
+
+    /// The contents of the `Frame` uniform block for the frame which this lighting is for.
+    pub fn frame(&self) -> FrameUniforms {
+        FrameUniforms {
+            resolution:       [self.resolution.x.0, self.resolution.y.0],
+            meters_per_ndu:   [self.meters_per_ndu.x.0, self.meters_per_ndu.y.0],
+            ambient:          self.ambient,
+            pixels_per_meter: self.scale,
+        }
+    }
+
+    /// Add a point light at `center` (in game space), which fades out to nothing at `radius`.
+    /// Lights which can't reach the screen are left out.
+    pub fn add_point(&mut self, cam: &Camera, center: math::Vec3<Meters>, color: [f32; 3],
+                     radius: Meters) {
+
+        let pos = cam.game_to_camera(center);
+
+        let (dx, dy) = (pos.x.0.abs(), pos.y.0.abs());
+
+        if dx > self.meters_per_ndu.x.0 + radius.0 || dy > self.meters_per_ndu.y.0 + radius.0 {
+            return
+        }
+
+        self.lights.push(ShaderLight {
+            position: [pos.x.0, pos.y.0, -pos.z.0, 1.0],
+            color:    [color[0], color[1], color[2], radius.0],
+            priority: (dx * dx + dy * dy).sqrt(),
+            shadows:  false,
+        });
+    }
+
+    /// Add a directional light, which travels in `direction` (in game space). If `shadows` is set,
+    /// sprites cast shadows from it onto each other; only the first such light does.
+    pub fn add_directional(&mut self, cam: &Camera, direction: math::Vec3<Meters>,
+                           color: [f32; 3], shadows: bool) {
+
+        // Directions only rotate, so measure this one from the camera's own position.
+        let dir = cam.game_to_camera(cam.position + direction);
+        let off = cam.game_to_camera(cam.position);
+
+        let (x, y, z) = ((dir.x - off.x).0, (dir.y - off.y).0, -(dir.z - off.z).0);
+        let len       = (x * x + y * y + z * z).sqrt();
+
+        if !(len > 0.0) {
+            return
+        }
+
+        self.lights.push(ShaderLight {
+            position: [x / len, y / len, z / len, 0.0],
+            color:    [color[0], color[1], color[2], 0.0],
+            priority: -1.0,
+            shadows:  shadows,
+        });
+    }
+
+    // The lights which will actually be used, as the uniform arrays `light_pos` and `light_color`.
+    fn uniforms(&self) -> (Vec<[f32; 4]>, Vec<[f32; 4]>) {
+        let chosen = self.chosen();
+
+        (chosen.iter().map(|l| l.position).collect(), chosen.iter().map(|l| l.color).collect())
+    }
+
+    // The index among the chosen lights of the one which casts shadows, and its direction.
+    fn shadow(&self) -> Option<(usize, [f32; 3])> {
+        let chosen = self.chosen();
+
+        chosen.iter().position(|l| l.shadows).map(|i| {
+            let p = chosen[i].position;
+            (i, [p[0], p[1], p[2]])
+        })
+    }
+
+    fn chosen(&self) -> Vec<&ShaderLight> {
+        let mut chosen: Vec<&ShaderLight> = self.lights.iter().collect();
+
+        chosen.sort_by(|a, b| a.priority.partial_cmp(&b.priority).unwrap_or(Ordering::Equal));
+        chosen.truncate(MAX_LIGHTS);
+
+        chosen
+    }
+}
+
+// The frame of the shadow map: orthographic, looking along a directional light.
+#[derive(Debug,Copy,Clone)]
+struct LightSpace {
+    // Rows which turn camera space into light space, where `z` is the distance along the light.
+    rows: [[f32; 3]; 3],
+
+    // The corners of the box in light space which the shadow map covers.
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+/// The extent of a frame's sprites as seen from the light which casts shadows (see
+/// `Renderer::shadow_bounds`). These are gathered as the sprites' vertices are written, so that the
+/// shadow map can be fitted around them without reading the vertices back.
+#[derive(Debug,Copy,Clone)]
+pub struct ShadowBounds {
+    space:          LightSpace,
+    meters_per_ndu: math::Vec2<Meters>,
+}
+
+impl ShadowBounds {
+    // Start bounding sprites as seen looking along `dir` (in camera space).
+    fn new(dir: [f32; 3], meters_per_ndu: math::Vec2<Meters>) -> ShadowBounds {
+        let w  = normalize(dir);
+        let up = if w[1].abs() < 0.99 { [0.0, 1.0, 0.0] } else { [1.0, 0.0, 0.0] };
+        let u  = normalize(cross(up, w));
+        let v  = cross(w, u);
+
+        ShadowBounds {
+            space: LightSpace {
+                rows: [u, v, w],
+                min:  [f32::INFINITY; 3],
+                max:  [f32::NEG_INFINITY; 3],
+            },
+            meters_per_ndu: meters_per_ndu,
+        }
+    }
+
+    /// Grow the bounds around the sprite drawn by `vert`, allowing for how far its depth texture
+    /// reaches.
+    pub fn add(&mut self, vert: &SpriteVertex) {
+        let (tl, br) = (vert.screen_TL, vert.screen_BR);
+
+        let corners = [(tl.x.0, tl.y.0), (br.x.0, tl.y.0), (tl.x.0, br.y.0), (br.x.0, br.y.0)];
+
+        // Turn each corner about the pivot, as the geometry shader does.
+        let (px, py) = (vert.pivot.x.0, vert.pivot.y.0);
+        let m        = vert.transform;
+
+        let space = &mut self.space;
+        let mpn   = self.meters_per_ndu;
+
+        for &(x, y) in corners.iter() {
+            let (dx, dy) = (x - px, y - py);
+            let (x, y)   = (px + m[0] * dx + m[2] * dy, py + m[1] * dx + m[3] * dy);
+
+            for &d in [-DEPTH_SCALE, DEPTH_SCALE].iter() {
+                let cam = [x * mpn.x.0, y * mpn.y.0, vert.depth.0 + d];
+
+                for (i, &row) in space.rows.iter().enumerate() {
+                    let l = dot(row, cam);
+
+                    if l < space.min[i] { space.min[i] = l }
+                    if l > space.max[i] { space.max[i] = l }
+                }
+            }
+        }
+    }
+
+    /// Grow the bounds around everything added to `other`, which must have started from the same
+    /// call to `Renderer::shadow_bounds`.
+    pub fn merge(&mut self, other: &ShadowBounds) {
+        for i in 0..3 {
+            self.space.min[i] = self.space.min[i].min(other.space.min[i]);
+            self.space.max[i] = self.space.max[i].max(other.space.max[i]);
+        }
+    }
+
+    // The frame of a shadow map around everything added, if anything was.
+    fn finish(self) -> Option<LightSpace> {
+        let mut space = self.space;
+
+        if space.min[0] > space.max[0] {
+            return None
+        }
+
+        // Leave a little room, so that nothing lies exactly on the edge of the map.
+        for (lo, hi) in space.min.iter_mut().zip(space.max.iter_mut()) {
+            *lo -= 0.5;
+            *hi += 0.5;
+        }
+
+        Some(space)
+    }
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn normalize(a: [f32; 3]) -> [f32; 3] {
+    let len = dot(a, a).sqrt();
+
+    [a[0] / len, a[1] / len, a[2] / len]
+}
+
+// The uniforms which describe a `LightSpace`, which the sprite and shadow programs share.
+struct SpaceUniforms {
+    cam_to_light: opengl::Uniform<opengl::Mat3>,
+    light_min:    opengl::Uniform<[f32; 3]>,
+    light_max:    opengl::Uniform<[f32; 3]>,
+}
+
+impl SpaceUniforms {
+    fn new(prog: &opengl::ShaderProgram) -> Result<SpaceUniforms, Error> {
+        Ok(SpaceUniforms {
+            cam_to_light: try!(prog.get_uniform_as("cam_to_light")),
+            light_min:    try!(prog.get_uniform_as("light_min")),
+            light_max:    try!(prog.get_uniform_as("light_max")),
+        })
+    }
+
+    // Describe `space`. The program must be active.
+    fn set(&self, space: &LightSpace) {
+        self.cam_to_light.set(&opengl::Mat3::from_rows(space.rows));
+        self.light_min.set(&space.min);
+        self.light_max.set(&space.max);
+    }
+}
+
+// Draws sprites into a shadow map, as seen from a directional light. This is common to all of the
+// renderers.
+struct ShadowPass {
+    fbo:  opengl::Framebuffer,
     prog: opengl::ShaderProgram,
     vao:  opengl::VertexArray,
-    vbo:  opengl::VertexBuffer,
+
+    // The per-sprite attributes of `prog`, if it draws sprites as instances of a quad rather than
+    // as points for a geometry shader to expand.
+    instances: Option<InstanceAttribs>,
+
+    space: SpaceUniforms,
+
+    // The light which casts shadows, from the last call to `set_lighting`, as its index among the
+    // chosen lights and its direction.
+    light: Option<(usize, [f32; 3])>,
+
+    meters: math::Vec2<Meters>,
+
+    // The frame of the shadow map, from the last call to `fit`.
+    fitted: Option<LightSpace>,
 }
 
-impl ReleaseRenderer {
-    /// Create a new `sprite::Renderer`. This compiles and links a shader program, so it should only
-    /// be called after OpenGL has been initialized.
-    pub fn new() -> Result<ReleaseRenderer, Error> {
-        #![allow(non_snake_case)]
-        let vtx = try!(opengl::Shader::new_vertex(include_str!("../shaders/sprite.vtx")));
-        let geo = try!(opengl::Shader::new_geometry(include_str!("../shaders/sprite.geo")));
-        let frg = try!(opengl::Shader::new_fragment(include_str!("../shaders/sprite.frg")));
+impl ShadowPass {
+    // Build the shadow map and the program which draws it, with its attributes set up for the
+    // currently bound vertex buffer.
+    fn new() -> Result<ShadowPass, Error> {
+        let prog = try!(shader::program(&["sprite.vtx", "shadow.geo", "shadow.frg"]));
+        let vao  = try!(setup_gl_attributes(&prog));
 
-        let prog = try!(opengl::ShaderProgram::new(&[vtx, geo, frg]));
-        prog.use_program();
+        ShadowPass::with_program(prog, vao, None)
+    }
 
-        // Allow up to 16k sprites to be drawn simultaneously, this is far too many =P.
-        let vbo = opengl::VertexBuffer::new(mem::size_of::<SpriteVertex>() * MAX_SPRITES);
+    // Build the shadow map and a program which draws sprites into it as instances of `quad` (see
+    // `InstanceAttribs`).
+    fn instanced(quad: &opengl::VertexBuffer) -> Result<ShadowPass, Error> {
+        let prog = try!(shader::program(&["shadow_instanced.vtx", "shadow.frg"]));
+        let (vao, instances) = try!(InstanceAttribs::new(&prog, quad));
 
-        let vao = try!(setup_gl_attributes(&prog));
+        ShadowPass::with_program(prog, vao, Some(instances))
+    }
+
+    // Build the shadow map for `prog`, which must be active.
+    fn with_program(prog: opengl::ShaderProgram, vao: opengl::VertexArray,
+                    instances: Option<InstanceAttribs>) -> Result<ShadowPass, Error> {
 
         let color_tex = try!(prog.get_uniform("color_tex"));
         let depth_tex = try!(prog.get_uniform("depth_tex"));
@@ -234,33 +882,873 @@ impl ReleaseRenderer {
         color_tex.set1i(0);
         depth_tex.set1i(1);
 
-        Ok(ReleaseRenderer {
+        let fbo = try!(opengl::Framebuffer::depth_only(SHADOW_MAP_SIZE, SHADOW_MAP_SIZE)
+                           .map_err(Error::Framebuffer));
+
+        let space = try!(SpaceUniforms::new(&prog));
+        try!(use_frame_block(&prog));
+
+        Ok(ShadowPass {
+            fbo:  fbo,
             prog: prog,
             vao:  vao,
-            vbo:  vbo,
+
+            instances: instances,
+
+            space: space,
+
+            light:  None,
+            meters: vec2!(Meters ; 1.0, 1.0),
+            fitted: None,
         })
     }
 
-}
+    fn set_lighting(&mut self, lighting: &Lighting) {
+        self.light  = lighting.shadow();
+        self.meters = lighting.meters_per_ndu;
+    }
+
+    // Start bounding sprites for the shadow map, if there's a light which casts shadows.
+    fn bounds(&self) -> Option<ShadowBounds> {
+        self.light.map(|(_, dir)| ShadowBounds::new(dir, self.meters))
+    }
+
+    // Fit the shadow map around `bounds`, returning the index of the light which casts shadows and
+    // the frame of the map, if there is such a light.
+    fn fit(&mut self, bounds: Option<ShadowBounds>) -> Option<(usize, &LightSpace)> {
+        self.fitted = match (self.light, bounds) {
+            (Some(_), Some(bounds)) => bounds.finish(),
+            _                       => None,
+        };
+
+        match (self.light, &self.fitted) {
+            (Some((index, _)), &Some(ref space)) => Some((index, space)),
+            _                                    => None,
+        }
+    }
+
+    fn begin(&self) -> bool {
+        let space = match self.fitted {
+            Some(ref space) => space,
+            None            => return false,
+        };
+
+        self.fbo.bind();
+        unsafe { gl::Clear(gl::DEPTH_BUFFER_BIT) }
+
+        self.prog.use_program();
+        self.vao.bind();
+
+        self.space.set(space);
+
+        true
+    }
+
+    // Draw `grp`, whose vertices start from `base` in the vertex buffer.
+    fn render<'x>(&self, base: usize, grp: RenderGroup<'x>) {
+        grp.sheet.bind_textures();
+
+        let first = base + grp.first;
+
+        match self.instances {
+            Some(ref instances) => instances.draw(first, grp.count),
+            None                => unsafe {
+                gl::DrawArrays(gl::POINTS, first as GLint, grp.count as GLsizei);
+            },
+        }
+    }
+
+    // Stop drawing into the shadow map, and bind it for the sprite program to read.
+    fn end(&self) {
+        self.fbo.unbind();
+
+        if let Some(depth) = self.fbo.depth_texture() {
+            depth.bind_to_unit(4);
+        }
+    }
+}
+
+// The lighting uniforms of a sprite program.
+struct LightUniforms {
+    has_normal_tex:   opengl::Uniform<i32>,
+    has_emissive_tex: opengl::Uniform<i32>,
+    light_count: opengl::Uniform<i32>,
+    light_pos:   opengl::Uniform<[[f32; 4]]>,
+    light_color: opengl::Uniform<[[f32; 4]]>,
+
+    shadow_light: opengl::Uniform<i32>,
+    shadow_bias:  opengl::Uniform<f32>,
+    space:        SpaceUniforms,
+}
+
+impl LightUniforms {
+    // Find the uniforms in `prog`, which must be active, and set them so that sprites are unlit.
+    fn new(prog: &opengl::ShaderProgram) -> Result<LightUniforms, Error> {
+        let normal_tex   = try!(prog.get_uniform("normal_tex"));
+        let emissive_tex = try!(prog.get_uniform("emissive_tex"));
+        let shadow_tex   = try!(prog.get_uniform("shadow_tex"));
+
+        normal_tex.set1i(2);
+        emissive_tex.set1i(3);
+        shadow_tex.set1i(4);
+
+        try!(set_srgb(prog));
+        try!(use_frame_block(prog));
+
+        let uniforms = LightUniforms {
+            has_normal_tex:   try!(prog.get_uniform_as("has_normal_tex")),
+            has_emissive_tex: try!(prog.get_uniform_as("has_emissive_tex")),
+            light_count: try!(prog.get_uniform_as("light_count")),
+            light_pos:   try!(prog.get_uniform_as("light_pos")),
+            light_color: try!(prog.get_uniform_as("light_color")),
+
+            shadow_light: try!(prog.get_uniform_as("shadow_light")),
+            shadow_bias:  try!(prog.get_uniform_as("shadow_bias")),
+            space:        try!(SpaceUniforms::new(prog)),
+        };
+
+        uniforms.has_normal_tex.set(&0);
+        uniforms.has_emissive_tex.set(&0);
+        uniforms.light_count.set(&0);
+        uniforms.shadow_light.set(&-1);
+
+        Ok(uniforms)
+    }
+
+    // Set the lights. The ambient light is in the `Frame` block instead. The program must be
+    // active.
+    fn set(&self, lighting: &Lighting) {
+        let (pos, color) = lighting.uniforms();
+
+        self.light_count.set(&(pos.len() as i32));
+
+        if !pos.is_empty() {
+            self.light_pos.set(&pos);
+            self.light_color.set(&color);
+        }
+    }
+
+    // Shadow the light at `index` among those passed to `set` according to the shadow map, which
+    // covers `space`, or turn shadows off with `None`. The program must be active.
+    fn set_shadows(&self, shadows: Option<(usize, &LightSpace)>) {
+        match shadows {
+            Some((index, space)) => {
+                self.shadow_light.set(&(index as i32));
+                self.shadow_bias.set(&(SHADOW_BIAS / (space.max[2] - space.min[2])));
+                self.space.set(space);
+            },
+            None => self.shadow_light.set(&-1),
+        }
+    }
+
+    // Bind `sheet`'s normal map and emissive mask, if it has them. The program must be active.
+    fn bind_maps(&self, sheet: &Sheet) {
+        match sheet.normal {
+            Some(ref normal) => {
+                normal.bind_to_unit(2);
+                self.has_normal_tex.set(&1);
+            },
+            None => self.has_normal_tex.set(&0),
+        }
+
+        match sheet.emissive {
+            Some(ref emissive) => {
+                emissive.bind_to_unit(3);
+                self.has_emissive_tex.set(&1);
+            },
+            None => self.has_emissive_tex.set(&0),
+        }
+    }
+}
+
+macro_rules! attrib_offset {
+    ($attr:ident) => ( unsafe {
+        let base: &SpriteVertex = mem::transmute(0usize);
+        let offs: usize = mem::transmute(&base.$attr);
+
+        offs
+    })
+}
+
+/// The identifier of a `Material`, as returned by `Batcher::add_material`.
+pub type MaterialID = usize;
+
+/// A custom look for sprites: a fragment shader which draws them in place of the engine's own, and
+/// values for its uniforms (its parameters). Sprites are drawn with one by setting their
+/// `DrawReq::material` to the id which `Batcher::add_material` returned for it.
+///
+/// The shader is found in the same way as the engine's (see `grafix::shader::embed`), and goes
+/// through the same preprocessor, so it can include the engine's `.glsl` files. It receives the
+/// same `FromGeo` block as `sprite.frg`, the sprite's textures as layer `layer` of `color_tex` and
+/// `depth_tex`, and the `Frame` block (see `frame.glsl`). Only the ambient light reaches it; the
+/// rest of the `Lighting` is for the engine's shader alone.
+///
+/// Each renderer links the shader with its own vertex stages the first time it draws a sprite with
+/// the material. If that fails, the error is logged and the material's sprites are drawn as usual.
+pub struct Material {
+    shader: String,
+    params: Vec<(String, Box<opengl::UniformValue>)>,
+}
+
+impl Material {
+    /// A material which draws sprites with the fragment shader called `shader` (e.g. "water.frg"),
+    /// with none of its parameters set.
+    pub fn new(shader: &str) -> Material {
+        Material {
+            shader: From::from(shader),
+            params: vec![],
+        }
+    }
+
+    /// The name of the material's fragment shader.
+    pub fn shader(&self) -> &str {
+        &self.shader
+    }
+
+    /// Set the uniform called `name` to `value` before each group of sprites is drawn with this
+    /// material, replacing its earlier value. Parameters which the shader doesn't use are ignored.
+    pub fn set<V: opengl::UniformValue + 'static>(&mut self, name: &str, value: V) {
+        self.params.retain(|&(ref n, _)| n != name);
+        self.params.push((From::from(name), Box::new(value)));
+    }
+
+    /// Stop setting the uniform called `name`. It keeps the last value it was set to.
+    pub fn unset(&mut self, name: &str) {
+        self.params.retain(|&(ref n, _)| n != name);
+    }
+}
+
+// A material's shader linked with one of a renderer's vertex stages, and with its attributes set up
+// in the same way as the renderer's own program.
+struct MaterialProgram {
+    prog: opengl::ShaderProgram,
+    vao:  opengl::VertexArray,
+
+    // The per-sprite attributes of `prog`, if it draws sprites as instances of a quad rather than
+    // as points for a geometry shader to expand.
+    instances: Option<InstanceAttribs>,
+
+    // Each parameter's uniform, if `prog` uses it, looked up when it's first set.
+    uniforms: BTreeMap<String, Option<opengl::Uniform<opengl::UniformValue>>>,
+}
+
+impl MaterialProgram {
+    // Link `material`'s shader after the shaders called `stages`, which expand points, with its
+    // attributes set up for the currently bound vertex buffer.
+    fn new(stages: &[&str], material: &Material) -> Result<MaterialProgram, Error> {
+        let prog = try!(MaterialProgram::link(stages, material));
+        let vao  = try!(setup_gl_attributes(&prog));
+
+        MaterialProgram::with_program(prog, vao, None)
+    }
+
+    // Link `material`'s shader after the shaders called `stages`, which draw sprites as instances
+    // of `quad` (see `InstanceAttribs`).
+    fn instanced(stages: &[&str], material: &Material, quad: &opengl::VertexBuffer)
+        -> Result<MaterialProgram, Error> {
+
+        let prog = try!(MaterialProgram::link(stages, material));
+        let (vao, instances) = try!(InstanceAttribs::new(&prog, quad));
+
+        MaterialProgram::with_program(prog, vao, Some(instances))
+    }
+
+    fn link(stages: &[&str], material: &Material) -> Result<opengl::ShaderProgram, Error> {
+        let mut names = stages.to_vec();
+        names.push(material.shader());
+
+        Ok(try!(shader::program(&names)))
+    }
+
+    // Point the textures and blocks which `prog`, which must be active, uses where the engine
+    // keeps them. Materials needn't use any of them.
+    fn with_program(prog: opengl::ShaderProgram, vao: opengl::VertexArray,
+                    instances: Option<InstanceAttribs>) -> Result<MaterialProgram, Error> {
+
+        if let Ok(color_tex) = prog.get_uniform("color_tex") {
+            color_tex.set1i(0);
+        }
+
+        if let Ok(depth_tex) = prog.get_uniform("depth_tex") {
+            depth_tex.set1i(1);
+        }
+
+        let _ = set_srgb(&prog);
+        let _ = use_frame_block(&prog);
+
+        Ok(MaterialProgram {
+            prog: prog,
+            vao:  vao,
+
+            instances: instances,
+
+            uniforms: BTreeMap::new(),
+        })
+    }
+
+    // Draw `count` sprites from `first` in the vertex buffer with `material`'s parameters. This
+    // leaves `prog` active.
+    fn draw(&mut self, material: &Material, first: usize, count: usize) {
+        self.prog.use_program();
+        self.vao.bind();
+
+        for &(ref name, ref value) in material.params.iter() {
+            if !self.uniforms.contains_key(name) {
+                self.uniforms.insert(name.clone(), self.prog.get_uniform_as(name).ok());
+            }
+
+            if let Some(&Some(ref uniform)) = self.uniforms.get(name) {
+                uniform.set(&**value);
+            }
+        }
+
+        match self.instances {
+            Some(ref instances) => instances.draw(first, count),
+            None                => unsafe {
+                gl::DrawArrays(gl::POINTS, first as GLint, count as GLsizei);
+            },
+        }
+    }
+}
+
+// The programs which a renderer draws materials with, by the name of their shader, which are built
+// the first time that each is drawn. Those which fail to build are `None`, so that the error is
+// only logged once.
+struct MaterialPrograms {
+    programs: BTreeMap<String, Option<MaterialProgram>>,
+}
+
+impl MaterialPrograms {
+    fn new() -> MaterialPrograms {
+        MaterialPrograms { programs: BTreeMap::new() }
+    }
+
+    // The program for `material`, built with `build` if it hasn't been yet. Returns `None` if it
+    // couldn't be built.
+    fn get<F>(&mut self, material: &Material, build: F) -> Option<&mut MaterialProgram>
+        where F: FnOnce(&Material) -> Result<MaterialProgram, Error> {
+
+        if !self.programs.contains_key(material.shader()) {
+            let built = match build(material) {
+                Ok(prog) => Some(prog),
+                Err(err) => {
+                    error!(target: logging::GFX, "couldn't build the material `{}': {:?}",
+                           material.shader(), err);
+                    None
+                },
+            };
+
+            self.programs.insert(From::from(material.shader()), built);
+        }
+
+        self.programs.get_mut(material.shader()).and_then(|prog| prog.as_mut())
+    }
+}
+
+/// A group of sprites to be rendered at the same time. This struct only exists to be passed to the
+/// `Renderer::render` method, and references a range of the vertices written into the room from the
+/// most recent call to `Renderer::vertices`.
+///
+/// The sprites may come from several sheets, which share their textures with `sheet`; each
+/// vertex's `layer` says which of them it's from.
+pub struct RenderGroup<'x> {
+    /// The index of the first sprite to be drawn.
+    pub first: usize,
+
+    /// The number of sprites to be drawn.
+    pub count: usize,
+
+    /// The sprite sheet of the first of these sprites (this just provides the textures).
+    pub sheet: &'x Sheet,
+
+    /// The material which the sprites are drawn with, or `None` for the engine's own look. Shadows
+    /// and outlines are drawn the same whatever the material.
+    pub material: Option<&'x Material>,
+}
+
+/// A `Renderer` which has no instrumentation, and is designed for performance alone.
+pub struct ReleaseRenderer {
+    prog: opengl::ShaderProgram,
+    vao:  opengl::VertexArray,
+    vbo:  opengl::VertexBuffer,
+
+    // Where this frame's vertices start in `vbo`.
+    base: usize,
+
+    // Draws the outlines of highlighted sprites.
+    outline_prog: opengl::ShaderProgram,
+    outline_vao:  opengl::VertexArray,
+
+    lighting: LightUniforms,
+    shadows:  ShadowPass,
+
+    // The `Frame` block which all of the programs read.
+    frame: opengl::UniformBlock<FrameUniforms>,
+
+    materials: MaterialPrograms,
+}
+
+impl ReleaseRenderer {
+    /// Create a new `sprite::Renderer`. This compiles and links a shader program, so it should only
+    /// be called after OpenGL has been initialized.
+    pub fn new() -> Result<ReleaseRenderer, Error> {
+        #![allow(non_snake_case)]
+        let prog = try!(shader::program(&["sprite.vtx", "sprite.geo", "sprite.frg"]));
+        prog.use_program();
+
+        // Allow up to 16k sprites to be drawn simultaneously, this is far too many =P.
+        let vbo = opengl::VertexBuffer::new(mem::size_of::<SpriteVertex>() * MAX_SPRITES);
+
+        let vao = try!(setup_gl_attributes(&prog));
+
+        let color_tex = try!(prog.get_uniform("color_tex"));
+        let depth_tex = try!(prog.get_uniform("depth_tex"));
+
+        color_tex.set1i(0);
+        depth_tex.set1i(1);
+
+        let lighting = try!(LightUniforms::new(&prog));
+
+        vbo.bind();
+        let (outline_prog, outline_vao) = try!(outline_program());
+        let shadows = try!(ShadowPass::new());
+
+        Ok(ReleaseRenderer {
+            prog: prog,
+            vao:  vao,
+            vbo:  vbo,
+            base: 0,
+
+            outline_prog: outline_prog,
+            outline_vao:  outline_vao,
+
+            lighting: lighting,
+            shadows:  shadows,
+
+            frame: frame_block(),
+
+            materials: MaterialPrograms::new(),
+        })
+    }
+
+}
+
+impl Recreate for ReleaseRenderer {
+    fn recreate(&mut self) -> Result<(), String> {
+        *self = try!(ReleaseRenderer::new().map_err(|err| format!("{:?}", err)));
+        Ok(())
+    }
+}
+
+impl Renderer for ReleaseRenderer {
+    fn vertices(&mut self, count: usize) -> &mut [SpriteVertex] {
+        let (verts, base) = self.vbo.map(count);
+        self.base = base;
+
+        verts
+    }
+
+    fn shadow_bounds(&self) -> Option<ShadowBounds> {
+        self.shadows.bounds()
+    }
+
+    fn prepare(&mut self, shadows: Option<ShadowBounds>) {
+        self.vbo.unmap();
+
+        self.prog.use_program();
+        self.lighting.set_shadows(self.shadows.fit(shadows));
+
+        self.vao.bind();
+
+        self.vbo.bind();
+    }
+
+    fn render<'x>(&mut self, grp: RenderGroup<'x>) {
+        grp.sheet.bind_textures();
+
+        if let Some(material) = grp.material {
+            let build = |m: &Material| MaterialProgram::new(&["sprite.vtx", "sprite.geo"], m);
+
+            if let Some(prog) = self.materials.get(material, build) {
+                prog.draw(material, self.base + grp.first, grp.count);
+
+                // Leave things as `prepare` did, for the groups after this one.
+                self.prog.use_program();
+                self.vao.bind();
+                return
+            }
+        }
+
+        self.lighting.bind_maps(grp.sheet);
+
+        unsafe {
+            gl::DrawArrays(gl::POINTS, (self.base + grp.first) as GLint, grp.count as GLsizei);
+        }
+    }
+
+    fn render_outlines<'x>(&mut self, grp: RenderGroup<'x>) {
+        grp.sheet.bind_textures();
+
+        self.outline_prog.use_program();
+        self.outline_vao.bind();
+
+        unsafe {
+            gl::DrawArrays(gl::POINTS, (self.base + grp.first) as GLint, grp.count as GLsizei);
+        }
+
+        // Leave things as `prepare` did, for the groups after this one.
+        self.prog.use_program();
+        self.vao.bind();
+    }
+
+    fn set_lighting(&mut self, lighting: &Lighting) {
+        self.frame.update(&lighting.frame());
+
+        self.prog.use_program();
+        self.lighting.set(lighting);
+        self.shadows.set_lighting(lighting);
+    }
+
+    fn begin_shadows(&mut self) -> bool {
+        self.shadows.begin()
+    }
+
+    fn render_shadows<'x>(&mut self, grp: RenderGroup<'x>) {
+        self.shadows.render(self.base, grp);
+    }
+
+    fn end_shadows(&mut self) {
+        self.shadows.end();
+
+        self.prog.use_program();
+        self.vao.bind();
+    }
+}
+
+// The unit quad which `InstancedRenderer` draws an instance of for each sprite, as a triangle strip
+// from the top-left corner to the bottom-right.
+const UNIT_QUAD: [[f32; 2]; 4] = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [1.0, 1.0]];
+
+// The per-sprite attributes of a program which draws each sprite as an instance of the unit quad.
+// There's no way to start instanced drawing partway through the buffer in GL 3.2, so these are
+// pointed at the first sprite of each group just before it's drawn.
+struct InstanceAttribs {
+    // Each attribute, with its number of components and its offset within a `SpriteVertex`.
+    attribs: Vec<(opengl::VertexAttrib, usize, usize)>,
+}
+
+impl InstanceAttribs {
+    // Set up the attributes of `prog` in a new VAO, with `corner` read from `quad` and the rest
+    // advancing once per instance. Attributes which `prog` doesn't use are skipped (shadows aren't
+    // tinted, for instance). This leaves `prog` active.
+    fn new(prog: &opengl::ShaderProgram, quad: &opengl::VertexBuffer)
+        -> Result<(opengl::VertexArray, InstanceAttribs), Error> {
+
+        let vao = opengl::VertexArray::new();
+        vao.bind();
+
+        prog.use_program();
+
+        quad.bind();
+        let corner = try!(prog.get_attrib("corner"));
+        corner.enable();
+        corner.set_pointer(2, gl::FLOAT, false, mem::size_of::<[f32; 2]>(), 0);
+
+        let all: [(&str, usize, usize); 9] = [
+            ("screen_TL", 2, attrib_offset!(screen_TL)),
+            ("screen_BR", 2, attrib_offset!(screen_BR)),
+            ("tex_TL",    2, attrib_offset!(tex_TL)),
+            ("tex_BR",    2, attrib_offset!(tex_BR)),
+            ("depth",     1, attrib_offset!(depth)),
+            ("tint",      4, attrib_offset!(tint)),
+            ("pivot",     2, attrib_offset!(pivot)),
+            ("transform", 4, attrib_offset!(transform)),
+            ("layer",     1, attrib_offset!(layer)),
+        ];
+
+        let mut attribs = vec![];
+
+        for &(name, size, offset) in all.iter() {
+            let attrib = match prog.get_attrib(name) {
+                Ok(attrib) => attrib,
+                Err(_)     => continue,
+            };
+
+            attrib.enable();
+            attrib.set_divisor(1);
+
+            attribs.push((attrib, size, offset));
+        }
+
+        Ok((vao, InstanceAttribs { attribs: attribs }))
+    }
+
+    // Draw `count` sprites, starting from the `first`, out of the vertex buffer bound to
+    // `ARRAY_BUFFER`. The program and VAO which these attributes belong to must be active.
+    fn draw(&self, first: usize, count: usize) {
+        let stride = mem::size_of::<SpriteVertex>();
+
+        for &(ref attrib, size, offset) in self.attribs.iter() {
+            attrib.set_pointer(size, gl::FLOAT, false, stride, first * stride + offset);
+        }
+
+        unsafe {
+            gl::DrawArraysInstanced(gl::TRIANGLE_STRIP, 0, UNIT_QUAD.len() as GLsizei,
+                                    count as GLsizei);
+        }
+    }
+}
+
+/// A `Renderer` which draws each sprite as an instance of a unit quad, placing its corners in the
+/// vertex shader instead of expanding points with a geometry shader. The picture is the same as
+/// `ReleaseRenderer`'s; which of the two is faster depends on the driver, since some handle
+/// geometry shaders poorly.
+pub struct InstancedRenderer {
+    prog:    opengl::ShaderProgram,
+    vao:     opengl::VertexArray,
+    attribs: InstanceAttribs,
+    vbo:     opengl::VertexBuffer,
+
+    // Where this frame's vertices start in `vbo`.
+    base: usize,
+
+    // Holds `UNIT_QUAD`, which never changes. The VAOs read from it, so it has to live as long as
+    // they do, and the programs of materials are set up with it.
+    quad: opengl::VertexBuffer,
+
+    // Draws the outlines of highlighted sprites.
+    outline_prog:    opengl::ShaderProgram,
+    outline_vao:     opengl::VertexArray,
+    outline_attribs: InstanceAttribs,
+
+    lighting: LightUniforms,
+    shadows:  ShadowPass,
+
+    // The `Frame` block which all of the programs read.
+    frame: opengl::UniformBlock<FrameUniforms>,
+
+    materials: MaterialPrograms,
+}
+
+impl InstancedRenderer {
+    /// Create a new `sprite::InstancedRenderer`. This compiles and links shader programs, so it
+    /// should only be called after OpenGL has been initialized.
+    pub fn new() -> Result<InstancedRenderer, Error> {
+        let prog = try!(shader::program(&["sprite_instanced.vtx", "sprite.frg"]));
+
+        let vbo  = opengl::VertexBuffer::new(mem::size_of::<SpriteVertex>() * MAX_SPRITES);
+        let quad = opengl::VertexBuffer::new_static(&UNIT_QUAD);
+
+        let (vao, attribs) = try!(InstanceAttribs::new(&prog, &quad));
+
+        let color_tex = try!(prog.get_uniform("color_tex"));
+        let depth_tex = try!(prog.get_uniform("depth_tex"));
+
+        color_tex.set1i(0);
+        depth_tex.set1i(1);
+
+        let lighting = try!(LightUniforms::new(&prog));
+
+        let outline_prog = try!(shader::program(&["outline_instanced.vtx", "outline.frg"]));
+
+        let (outline_vao, outline_attribs) = try!(InstanceAttribs::new(&outline_prog, &quad));
+
+        let color_tex = try!(outline_prog.get_uniform("color_tex"));
+        let depth_tex = try!(outline_prog.get_uniform("depth_tex"));
+
+        color_tex.set1i(0);
+        depth_tex.set1i(1);
+
+        try!(set_srgb(&outline_prog));
+
+        let shadows = try!(ShadowPass::instanced(&quad));
+
+        Ok(InstancedRenderer {
+            prog:    prog,
+            vao:     vao,
+            attribs: attribs,
+            vbo:     vbo,
+            base:    0,
+
+            quad: quad,
+
+            outline_prog:    outline_prog,
+            outline_vao:     outline_vao,
+            outline_attribs: outline_attribs,
+
+            lighting: lighting,
+            shadows:  shadows,
+
+            frame: frame_block(),
+
+            materials: MaterialPrograms::new(),
+        })
+    }
+}
+
+impl Recreate for InstancedRenderer {
+    fn recreate(&mut self) -> Result<(), String> {
+        *self = try!(InstancedRenderer::new().map_err(|err| format!("{:?}", err)));
+        Ok(())
+    }
+}
+
+impl Renderer for InstancedRenderer {
+    fn vertices(&mut self, count: usize) -> &mut [SpriteVertex] {
+        let (verts, base) = self.vbo.map(count);
+        self.base = base;
+
+        verts
+    }
+
+    fn shadow_bounds(&self) -> Option<ShadowBounds> {
+        self.shadows.bounds()
+    }
+
+    fn prepare(&mut self, shadows: Option<ShadowBounds>) {
+        self.vbo.unmap();
+
+        self.prog.use_program();
+        self.lighting.set_shadows(self.shadows.fit(shadows));
+
+        self.vao.bind();
+
+        // The instance attributes are pointed into whatever is bound here when each group is
+        // drawn, so this has to stay bound.
+        self.vbo.bind();
+    }
+
+    fn render<'x>(&mut self, grp: RenderGroup<'x>) {
+        grp.sheet.bind_textures();
+
+        if let Some(material) = grp.material {
+            let quad  = &self.quad;
+            let build = |m: &Material| {
+                MaterialProgram::instanced(&["sprite_instanced.vtx"], m, quad)
+            };
+
+            if let Some(prog) = self.materials.get(material, build) {
+                // Building the program may have bound `quad`.
+                self.vbo.bind();
+                prog.draw(material, self.base + grp.first, grp.count);
+
+                // Leave things as `prepare` did, for the groups after this one.
+                self.prog.use_program();
+                self.vao.bind();
+                return
+            }
+        }
+
+        self.lighting.bind_maps(grp.sheet);
+
+        self.attribs.draw(self.base + grp.first, grp.count);
+    }
+
+    fn render_outlines<'x>(&mut self, grp: RenderGroup<'x>) {
+        grp.sheet.bind_textures();
+
+        self.outline_prog.use_program();
+        self.outline_vao.bind();
+
+        self.outline_attribs.draw(self.base + grp.first, grp.count);
+
+        // Leave things as `prepare` did, for the groups after this one.
+        self.prog.use_program();
+        self.vao.bind();
+    }
+
+    fn set_lighting(&mut self, lighting: &Lighting) {
+        self.frame.update(&lighting.frame());
+
+        self.prog.use_program();
+        self.lighting.set(lighting);
+        self.shadows.set_lighting(lighting);
+    }
+
+    fn begin_shadows(&mut self) -> bool {
+        self.shadows.begin()
+    }
+
+    fn render_shadows<'x>(&mut self, grp: RenderGroup<'x>) {
+        self.shadows.render(self.base, grp);
+    }
+
+    fn end_shadows(&mut self) {
+        self.shadows.end();
+
+        self.prog.use_program();
+        self.vao.bind();
+    }
+}
+
+/// The `Renderer`s which can be chosen between at runtime, e.g. from a setting. A
+/// `Box<Renderer>` is itself a `Renderer`, so the result of `create` can be handed straight to
+/// `WorldRender::new`.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum RendererKind {
+    /// A `ReleaseRenderer`, which expands sprites with a geometry shader.
+    Geometry,
+
+    /// An `InstancedRenderer`, which draws sprites as instances of a quad.
+    Instanced,
+
+    /// A `DebugRenderer`.
+    Debug,
+}
+
+impl RendererKind {
+    /// The kind of renderer which `cfg` asks for (see `Config::instanced`).
+    #[cfg(feature = "config")]
+    pub fn from_config(cfg: &::config::Config) -> RendererKind {
+        if cfg.instanced { RendererKind::Instanced } else { RendererKind::Geometry }
+    }
+
+    /// Create a renderer of this kind. This compiles and links shader programs, so it should only
+    /// be called after OpenGL has been initialized.
+    pub fn create(self) -> Result<Box<Renderer>, Error> {
+        Ok(match self {
+            RendererKind::Geometry  => Box::new(try!(ReleaseRenderer::new())) as Box<Renderer>,
+            RendererKind::Instanced => Box::new(try!(InstancedRenderer::new())) as Box<Renderer>,
+            RendererKind::Debug     => Box::new(try!(DebugRenderer::new())) as Box<Renderer>,
+        })
+    }
+}
+
+impl<R: Renderer + ?Sized> Renderer for Box<R> {
+    fn vertices(&mut self, count: usize) -> &mut [SpriteVertex] {
+        (**self).vertices(count)
+    }
+
+    fn shadow_bounds(&self) -> Option<ShadowBounds> {
+        (**self).shadow_bounds()
+    }
+
+    fn prepare(&mut self, shadows: Option<ShadowBounds>) {
+        (**self).prepare(shadows)
+    }
 
-impl Renderer for ReleaseRenderer {
-    fn prepare(&self, verts: &[SpriteVertex]) {
-        self.vbo.buffer_data(verts);
+    fn render<'x>(&mut self, grp: RenderGroup<'x>) {
+        (**self).render(grp)
+    }
 
-        self.prog.use_program();
+    fn render_outlines<'x>(&mut self, grp: RenderGroup<'x>) {
+        (**self).render_outlines(grp)
+    }
 
-        self.vao.bind();
+    fn set_lighting(&mut self, lighting: &Lighting) {
+        (**self).set_lighting(lighting)
+    }
 
-        self.vbo.bind();
+    fn begin_shadows(&mut self) -> bool {
+        (**self).begin_shadows()
     }
 
-    fn render<'x>(&mut self, grp: RenderGroup<'x>) {
-        grp.sheet.color.bind_to_unit(0);
-        grp.sheet.depth.bind_to_unit(1);
+    fn render_shadows<'x>(&mut self, grp: RenderGroup<'x>) {
+        (**self).render_shadows(grp)
+    }
 
-        unsafe {
-            gl::DrawArrays(gl::POINTS, grp.first as GLint, grp.count as GLsizei);
-        }
+    fn end_shadows(&mut self) {
+        (**self).end_shadows()
     }
 }
 
@@ -288,7 +1776,24 @@ pub struct DebugRenderer {
     full_prog: opengl::ShaderProgram,
     full_vao:  opengl::VertexArray,
 
+    full_lighting: LightUniforms,
+
+    shadows: ShadowPass,
+
+    // The `Frame` block which the full, shadow and material programs read.
+    frame: opengl::UniformBlock<FrameUniforms>,
+
+    // Used in place of the full program for sprites with a material.
+    materials: MaterialPrograms,
+
+    // Draws the outlines of highlighted sprites.
+    outline_prog: opengl::ShaderProgram,
+    outline_vao:  opengl::VertexArray,
+
     vbo: opengl::VertexBuffer,
+
+    // The vertices handed out by `vertices`, which are kept on the CPU so that they can be printed.
+    verts: Vec<SpriteVertex>,
 }
 
 impl DebugRenderer {
@@ -306,21 +1811,20 @@ impl DebugRenderer {
             "FromVert.tex_TL",
             "FromVert.tex_BR",
             "FromVert.depth",
+            "FromVert.tint",
+            "FromVert.pivot",
+            "FromVert.transform",
+            "FromVert.layer",
         ];
 
-        let vtx = try!(opengl::Shader::new_vertex(include_str!("../shaders/sprite.vtx")));
-
-        let vtx_prog = try!(opengl::ShaderProgram::new_xfb(&[vtx], vtx_names));
+        let vtx_prog = try!(shader::program_xfb(&["sprite.vtx"], vtx_names));
         
         let vtx_xfb = opengl::TransformFeedback::new(MAX_SPRITES, SpriteVertex::zero());
 
         vbo.bind();
         let vtx_vao = try!(setup_gl_attributes(&vtx_prog));
 
-        let vtx = try!(opengl::Shader::new_vertex(include_str!("../shaders/sprite.vtx")));
-        let geo = try!(opengl::Shader::new_geometry(include_str!("../shaders/sprite.geo")));
-
-        let geo_prog = try!(opengl::ShaderProgram::new(&[vtx, geo]));
+        let geo_prog = try!(shader::program(&["sprite.vtx", "sprite.geo"]));
 
         // Each input vertex gets turned into a rectangle consisting of two triangles, so there will
         // be a total of 6 vertices per-sprite output by the geometry shader.
@@ -329,21 +1833,25 @@ impl DebugRenderer {
         vbo.bind();
         let geo_vao = try!(setup_gl_attributes(&geo_prog));
 
-        let vtx = try!(opengl::Shader::new_vertex(include_str!("../shaders/sprite.vtx")));
-        let geo = try!(opengl::Shader::new_geometry(include_str!("../shaders/sprite.geo")));
-        let frg = try!(opengl::Shader::new_fragment(include_str!("../shaders/sprite.frg")));
-
-        let full_prog = try!(opengl::ShaderProgram::new(&[vtx, geo, frg]));
+        let full_prog = try!(shader::program(&["sprite.vtx", "sprite.geo", "sprite.frg"]));
 
         vbo.bind();
         let full_vao = try!(setup_gl_attributes(&full_prog));
 
+        full_prog.use_program();
+
         let color_tex = try!(full_prog.get_uniform("color_tex"));
         let depth_tex = try!(full_prog.get_uniform("depth_tex"));
 
         color_tex.set1i(0);
         depth_tex.set1i(1);
 
+        let full_lighting = try!(LightUniforms::new(&full_prog));
+
+        vbo.bind();
+        let (outline_prog, outline_vao) = try!(outline_program());
+        let shadows = try!(ShadowPass::new());
+
         Ok(DebugRenderer {
             vtx_prog: vtx_prog,
             vtx_vao:  vtx_vao,
@@ -358,12 +1866,67 @@ impl DebugRenderer {
             full_prog: full_prog,
             full_vao:  full_vao,
 
-            vbo: vbo,
+            full_lighting: full_lighting,
+
+            shadows: shadows,
+
+            frame: frame_block(),
+
+            materials: MaterialPrograms::new(),
+
+            outline_prog: outline_prog,
+            outline_vao:  outline_vao,
+
+            vbo:   vbo,
+            verts: vec![],
         })
     }
 
 }
 
+// Build the program which draws outlines, with its attributes set up for the currently bound vertex
+// buffer. This is common to the Debug and Release renderers.
+fn outline_program() -> Result<(opengl::ShaderProgram, opengl::VertexArray), Error> {
+    let prog = try!(shader::program(&["sprite.vtx", "outline.geo", "outline.frg"]));
+    let vao  = try!(setup_gl_attributes(&prog));
+
+    prog.use_program();
+
+    let color_tex = try!(prog.get_uniform("color_tex"));
+    let depth_tex = try!(prog.get_uniform("depth_tex"));
+
+    color_tex.set1i(0);
+    depth_tex.set1i(1);
+
+    try!(set_srgb(&prog));
+
+    Ok((prog, vao))
+}
+
+/// Tell a program whose fragment shader converts colors with `to_linear` whether the window draws
+/// with sRGB color handling (see `opengl::srgb`). The program must be active.
+pub fn set_srgb(prog: &opengl::ShaderProgram) -> Result<(), Error> {
+    let srgb = try!(prog.get_uniform("srgb"));
+
+    srgb.set1i(if opengl::srgb() { 1 } else { 0 });
+
+    Ok(())
+}
+
+// Have `prog` read its `Frame` block from `FRAME_BINDING`.
+fn use_frame_block(prog: &opengl::ShaderProgram) -> Result<(), Error> {
+    if prog.bind_uniform_block("Frame", FRAME_BINDING) {
+        Ok(())
+    } else {
+        Err(Error::NoSuchUniformBlock(From::from("Frame")))
+    }
+}
+
+// Make the `Frame` block which a renderer's programs share, and bind it to `FRAME_BINDING`.
+fn frame_block() -> opengl::UniformBlock<FrameUniforms> {
+    opengl::UniformBlock::new(FRAME_BINDING, &FrameUniforms::unlit())
+}
+
 // This function will set up the OpenGL Vertex Attributes for the standard sprite shader program.
 // It is here as a convenience function, since this is common to the Debug and Release renderers.
 fn setup_gl_attributes(prog: &opengl::ShaderProgram) -> Result<opengl::VertexArray, Error> {
@@ -400,21 +1963,61 @@ fn setup_gl_attributes(prog: &opengl::ShaderProgram) -> Result<opengl::VertexArr
     depth.set_pointer(1, gl::FLOAT, false, mem::size_of::<SpriteVertex>(),
         attrib_offset!(depth));
 
+    let tint = try!(prog.get_attrib("tint"));
+    tint.enable();
+    tint.set_pointer(4, gl::FLOAT, false, mem::size_of::<SpriteVertex>(),
+        attrib_offset!(tint));
+
+    let pivot = try!(prog.get_attrib("pivot"));
+    pivot.enable();
+    pivot.set_pointer(2, gl::FLOAT, false, mem::size_of::<SpriteVertex>(),
+        attrib_offset!(pivot));
+
+    let transform = try!(prog.get_attrib("transform"));
+    transform.enable();
+    transform.set_pointer(4, gl::FLOAT, false, mem::size_of::<SpriteVertex>(),
+        attrib_offset!(transform));
+
+    let layer = try!(prog.get_attrib("layer"));
+    layer.enable();
+    layer.set_pointer(1, gl::FLOAT, false, mem::size_of::<SpriteVertex>(),
+        attrib_offset!(layer));
+
     Ok(vao)
 }
 
+impl Recreate for DebugRenderer {
+    fn recreate(&mut self) -> Result<(), String> {
+        *self = try!(DebugRenderer::new().map_err(|err| format!("{:?}", err)));
+        Ok(())
+    }
+}
+
 impl Renderer for DebugRenderer {
-    fn prepare(&self, verts: &[SpriteVertex]) {
-        println!("buffering data: {:?}", verts);
-        self.vbo.buffer_data(verts);
+    fn vertices(&mut self, count: usize) -> &mut [SpriteVertex] {
+        self.verts.clear();
+        for _ in 0..count { self.verts.push(SpriteVertex::zero()) }
+
+        &mut self.verts
+    }
+
+    fn shadow_bounds(&self) -> Option<ShadowBounds> {
+        self.shadows.bounds()
+    }
+
+    fn prepare(&mut self, shadows: Option<ShadowBounds>) {
+        println!("buffering data: {:?}", self.verts);
+        self.vbo.buffer_data(&self.verts);
         self.vbo.bind();
+
+        self.full_prog.use_program();
+        self.full_lighting.set_shadows(self.shadows.fit(shadows));
     }
 
     /// Render the sprites, as well as printing the output of the vertex and geometry shaders to
     /// stdout.
     fn render<'x>(&mut self, grp: RenderGroup<'x>) {
-        grp.sheet.color.bind_to_unit(0);
-        grp.sheet.depth.bind_to_unit(1);
+        grp.sheet.bind_textures();
 
         self.vtx_prog.use_program();
         self.vtx_vao.bind();
@@ -452,14 +2055,76 @@ impl Renderer for DebugRenderer {
             println!("{:?} {:?} {:?} {:?}", prim[0], prim[1], prim[2], prim[5]);
         }
 
+        unsafe { gl::Disable(gl::RASTERIZER_DISCARD) }
+
+        if let Some(material) = grp.material {
+            println!("# material `{}'", material.shader());
+
+            let build = |m: &Material| MaterialProgram::new(&["sprite.vtx", "sprite.geo"], m);
+
+            // The new program's attributes are set up for whatever is bound.
+            self.vbo.bind();
+
+            if let Some(prog) = self.materials.get(material, build) {
+                prog.draw(material, grp.first, grp.count);
+
+                self.full_prog.use_program();
+                return
+            }
+        }
+
         self.full_prog.use_program();
         self.full_vao.bind();
+        self.full_lighting.bind_maps(grp.sheet);
+
+        unsafe {
+            gl::DrawArrays(gl::POINTS, grp.first as GLint, grp.count as GLsizei);
+        }
+    }
+
+    /// Render the outlines. Their vertices are printed by `prepare` along with everything else.
+    fn render_outlines<'x>(&mut self, grp: RenderGroup<'x>) {
+        grp.sheet.bind_textures();
+
+        println!("# outlines ({} sprites from {})", grp.count, grp.first);
+
+        self.outline_prog.use_program();
+        self.outline_vao.bind();
 
         unsafe {
-            gl::Disable(gl::RASTERIZER_DISCARD);
             gl::DrawArrays(gl::POINTS, grp.first as GLint, grp.count as GLsizei);
         }
     }
+
+    fn set_lighting(&mut self, lighting: &Lighting) {
+        println!("# lighting: ambient {:?}, {} lights", lighting.ambient, lighting.lights.len());
+
+        self.frame.update(&lighting.frame());
+
+        self.full_prog.use_program();
+        self.full_lighting.set(lighting);
+        self.shadows.set_lighting(lighting);
+    }
+
+    fn begin_shadows(&mut self) -> bool {
+        let casting = self.shadows.begin();
+
+        if casting {
+            println!("# shadow map");
+        }
+
+        casting
+    }
+
+    fn render_shadows<'x>(&mut self, grp: RenderGroup<'x>) {
+        println!("# shadows ({} sprites from {})", grp.count, grp.first);
+
+        self.shadows.render(0, grp);
+    }
+
+    fn end_shadows(&mut self) {
+        self.shadows.end();
+    }
 }
 
 /// A request for a sprite to be drawn. These are aggregated by the `Batcher` and turned into
@@ -473,11 +2138,89 @@ pub struct DrawReq {
     pub sprite_idx: usize,
 
     /// The location in the game world where that sprite's origin should be located.
-    pub game_loc: math::Vec3<Meters>
+    pub game_loc: math::Vec3<Meters>,
+
+    /// A color to multiply the sprite's color by, e.g. to flash it red or fade it out. Use `WHITE`
+    /// to draw the sprite as it is. Translucent sprites still hide whatever is drawn behind them
+    /// later in the frame, since they write to the depth buffer.
+    pub tint: Color,
+
+    /// Mirror the sprite left to right, so that one set of frames serves for facing either way.
+    /// The sprite is mirrored about its origin.
+    pub flip_x: bool,
+
+    /// The size to draw the sprite at, relative to the sheet (so 1 for its usual size). The
+    /// sprite is scaled about its origin.
+    pub scale: f32,
+
+    /// An angle in radians to turn the sprite counter-clockwise on screen, about its origin.
+    pub rotation: f32,
+
+    /// If set, an outline is drawn around the sprite in this color, e.g. to show that a unit is
+    /// selected. The outline follows the edges of the sprite's opaque pixels.
+    pub highlight: Option<Color>,
+
+    /// The layer to draw the sprite in. Layers are drawn in increasing order, so a sprite in a
+    /// higher layer is drawn after (and, if its layer doesn't test depth, over) every sprite in a
+    /// lower one. See the `LAYER_*` constants for the usual ones.
+    pub layer: u8,
+
+    /// The material to draw the sprite with (see `Batcher::add_material`). Use `DEFAULT_MATERIAL`
+    /// to draw it as the engine does. Sprites with a material which the `Batcher` doesn't have are
+    /// drawn as usual.
+    pub material: MaterialID,
+}
+
+// Where a `DrawReq` lands on screen, in pixels from the center of the screen, before it's rotated.
+#[allow(non_snake_case)]
+struct Placement {
+    // The sprite's origin, which it's rotated about, and its depth.
+    origin: math::Vec2<Pixels>,
+    depth:  Meters,
+
+    // The corners of the sprite, and the texture coordinates there.
+    screen_TL: math::Vec2<Pixels>,
+    screen_BR: math::Vec2<Pixels>,
+    tex_TL:    math::Vec2<TexCoord>,
+    tex_BR:    math::Vec2<TexCoord>,
 }
 
 impl DrawReq {
-    fn to_vertex(&self, cam: &Camera, sheet: &Sheet) -> SpriteVertex {
+    /// The depth from the camera of this sprite's pixel at screen coordinate `scr` (measured from
+    /// the center of the screen, as returned by `Camera::camera_to_screen`), taking the sheet's
+    /// depth texture into account. Returns `None` if the sprite doesn't cover `scr`, including
+    /// where it's transparent.
+    ///
+    /// Among the sprites which cover a point, the one with the least depth is the one drawn there,
+    /// so this is what mouse hover should compare: the cursor passes through the transparent
+    /// corner of a tall building to the unit standing behind it.
+    pub fn depth_at(&self, cam: &Camera, sheet: &Sheet, scr: math::Vec2<Pixels>)
+        -> Option<Meters> {
+
+        let p = self.place(cam, sheet);
+
+        // Turn `scr` back the other way about the origin, to where it was before rotation.
+        let (s, c)   = (-self.rotation).sin_cos();
+        let (dx, dy) = (scr.x.0 - p.origin.x.0, scr.y.0 - p.origin.y.0);
+
+        let x = p.origin.x.0 + c*dx - s*dy;
+        let y = p.origin.y.0 + s*dx + c*dy;
+
+        // How far across the sprite the point is. A sprite scaled to nothing covers nothing.
+        let fx = (x - p.screen_TL.x.0) / (p.screen_BR.x.0 - p.screen_TL.x.0);
+        let fy = (y - p.screen_TL.y.0) / (p.screen_BR.y.0 - p.screen_TL.y.0);
+
+        if !(fx >= 0.0 && fx < 1.0 && fy >= 0.0 && fy < 1.0) {
+            return None
+        }
+
+        let u = p.tex_TL.x.0 + fx * (p.tex_BR.x.0 - p.tex_TL.x.0);
+        let v = p.tex_TL.y.0 + fy * (p.tex_BR.y.0 - p.tex_TL.y.0);
+
+        sheet.depth_offset(TexCoord(u), TexCoord(v)).map(|offset| p.depth + offset)
+    }
+
+    fn place(&self, cam: &Camera, sheet: &Sheet) -> Placement {
         #![allow(non_snake_case)]
 
         let  cam_loc         = cam.game_to_camera(self.game_loc);
@@ -486,84 +2229,571 @@ impl DrawReq {
         let row_coef = TexCoord((self.sprite_idx / sheet.num_across) as f32);
         let col_coef = TexCoord((self.sprite_idx % sheet.num_across) as f32);
 
-        let tex_TL = vec2!(col_coef + TexCoord(1.0), row_coef) * sheet.tex_dimens;
-        let tex_BR = vec2!(col_coef, row_coef + TexCoord(1.0)) * sheet.tex_dimens;
+        let mut tex_TL = vec2!(col_coef + TexCoord(1.0), row_coef) * sheet.tex_dimens;
+        let mut tex_BR = vec2!(col_coef, row_coef + TexCoord(1.0)) * sheet.tex_dimens;
 
-        let screen_TL_px = scr_loc - sheet.origin;
-        let screen_BR_px = screen_TL_px + sheet.scr_dimens;
+        let mut origin = sheet.origin;
 
-        SpriteVertex {
-            screen_TL: cam.screen_to_ndu(screen_TL_px),
-            screen_BR: cam.screen_to_ndu(screen_BR_px),
+        // Swap the left and right edges of the sprite within the texture, and measure the origin
+        // from the other side so that it stays in the same place on screen.
+        if self.flip_x {
+            mem::swap(&mut tex_TL.x, &mut tex_BR.x);
+            origin.x = sheet.scr_dimens.x - origin.x;
+        }
+
+        let scale = Pixels(self.scale);
+
+        let screen_TL = scr_loc - vec2!(origin.x * scale, origin.y * scale);
+        let screen_BR = screen_TL + vec2!(sheet.scr_dimens.x * scale, sheet.scr_dimens.y * scale);
+
+        Placement {
+            origin: scr_loc,
+            depth:  depth,
+
+            screen_TL: screen_TL,
+            screen_BR: screen_BR,
 
             tex_TL: vec2!(TexCoord(1.0) - tex_TL.x, TexCoord(1.0) - tex_TL.y),
             tex_BR: vec2!(TexCoord(1.0) - tex_BR.x, TexCoord(1.0) - tex_BR.y),
+        }
+    }
+
+    fn to_vertex(&self, cam: &Camera, sheet: &Sheet) -> SpriteVertex {
+        let p = self.place(cam, sheet);
+
+        // Rotating in NDU would skew the sprite unless the screen is square, so the rotation is
+        // done in pixels, between converting from NDU and back.
+        let ndu_per_px = cam.screen_to_ndu(vec2!(Pixels ; 1.0, 1.0));
+        let aspect     = ndu_per_px.x.0 / ndu_per_px.y.0;
+
+        let (s, c) = self.rotation.sin_cos();
+
+        SpriteVertex {
+            screen_TL: cam.screen_to_ndu(p.screen_TL),
+            screen_BR: cam.screen_to_ndu(p.screen_BR),
 
-            depth: depth,
+            tex_TL: p.tex_TL,
+            tex_BR: p.tex_BR,
+
+            depth: p.depth,
+
+            tint: self.tint,
+
+            pivot:     cam.screen_to_ndu(p.origin),
+            transform: [c, s / aspect, -s * aspect, c],
+
+            layer: sheet.layer as f32,
         }
     }
 }
 
+// Where one sheet's sprites in one layer, with one material, went in the `Batcher`'s vertices:
+// `count` sprites from `first`, followed later by the outlines of the highlighted ones. Groups
+// which are drawn together are merged into one, with the first group's sheet.
+#[derive(Clone,Copy)]
+struct Group {
+    layer:    u8,
+    material: MaterialID,
+    sheet:    AssetID,
+    array: usize,
+    first: usize,
+    count: usize,
+
+    outlines_first: usize,
+    outlines_count: usize,
+}
+
 /// The `Batcher` gathers the set of sprites that need to be drawn each frame and aggregates them
 /// into a smaller number of GL draw calls.
 pub struct Batcher {
-    by_sheet: Vec<Vec<DrawReq>>,
+    // The requests for each sheet, with each material, in each layer (indexed by layer, then
+    // material, then sheet). The lists for sheets with no requests this frame are empty,
+    // unallocated `Vec`s; the rest are taken from `reqs`.
+    by_layer: Vec<Vec<Vec<Vec<DrawReq>>>>,
+
+    // The materials which have been added, of which the first has id 1 (after `DEFAULT_MATERIAL`).
+    materials: Vec<Material>,
+
+    // Buffers which are reused from frame to frame, so that batching doesn't allocate once it has
+    // warmed up.
+    reqs:   FrameArena<DrawReq>,
+    layout: Vec<Group>,
+    draws:  Vec<Group>,
+
+    // The shadow bounds of each job, when vertices are written on `pool`.
+    job_bounds: Vec<Option<ShadowBounds>>,
+
+    // The layers which are drawn without depth testing.
+    no_depth: BTreeSet<u8>,
+
+    // The layers which don't cast shadows.
+    no_shadows: BTreeSet<u8>,
+
+    // Used to convert `DrawReq`s to vertices in parallel, if present.
+    pool: Option<Pool>,
+
+    // Times the GPU's side of each batch. Created on the first call to `render_batch`, since it
+    // needs a GL context, if the context has timer queries.
+    timer: Option<opengl::GpuTimer>,
+
+    // Time each pass of the batch (see `Stats`).
+    shadow_timer: PassTimer,
+    world_timer:  PassTimer,
+    ui_timer:     PassTimer,
+
+    stats: Stats,
 }
 
 impl Batcher {
     /// Return a batcher which will use the given renderer.
     pub fn new() -> Batcher {
+        Batcher::with_threads(1)
+    }
+
+    /// Return a batcher which converts `DrawReq`s into vertices on a pool of `threads` worker
+    /// threads. The rendering itself still happens on the calling thread.
+    pub fn with_threads(threads: u32) -> Batcher {
         Batcher {
-            by_sheet: vec![],
+            by_layer:  vec![],
+            materials: vec![],
+            reqs:      FrameArena::new(),
+            layout:   vec![],
+            draws:    vec![],
+            no_depth: BTreeSet::new(),
+            pool:     if threads > 1 { Some(Pool::new(threads)) } else { None },
+            timer:    None,
+            stats:    Stats::new(),
+
+            shadow_timer: PassTimer::new(),
+            world_timer:  PassTimer::new(),
+            ui_timer:     PassTimer::new(),
+
+            no_shadows: (LAYER_UI as u16..256).map(|layer| layer as u8).collect(),
+            job_bounds: vec![],
+        }
+    }
+
+    /// Statistics about the most recent call to `render_batch`.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Whether sprites in `layer` are tested against (and write to) the depth buffer, which they
+    /// are unless this is called with `false`. Turning it off suits UI overlays, which should be
+    /// drawn over the world no matter how deep it is where they are.
+    pub fn set_depth_test(&mut self, layer: u8, enabled: bool) {
+        if enabled {
+            self.no_depth.remove(&layer);
+        } else {
+            self.no_depth.insert(layer);
+        }
+    }
+
+    /// Whether sprites in `layer` are drawn with depth testing (see `set_depth_test`).
+    pub fn depth_test(&self, layer: u8) -> bool {
+        !self.no_depth.contains(&layer)
+    }
+
+    /// Whether sprites in `layer` cast shadows, when the lighting has a light which casts them
+    /// (see `Lighting::add_directional`). Layers from `LAYER_UI` up don't by default; the rest do.
+    pub fn set_casts_shadows(&mut self, layer: u8, enabled: bool) {
+        if enabled {
+            self.no_shadows.remove(&layer);
+        } else {
+            self.no_shadows.insert(layer);
         }
     }
 
+    /// Whether sprites in `layer` cast shadows (see `set_casts_shadows`).
+    pub fn casts_shadows(&self, layer: u8) -> bool {
+        !self.no_shadows.contains(&layer)
+    }
+
+    /// Add `material`, so that sprites can be drawn with it by setting their `DrawReq::material`
+    /// to the id which this returns.
+    pub fn add_material(&mut self, material: Material) -> MaterialID {
+        self.materials.push(material);
+        self.materials.len()
+    }
+
+    /// The material with id `id`, or `None` for `DEFAULT_MATERIAL` and ids which weren't returned
+    /// by `add_material`.
+    pub fn material(&self, id: MaterialID) -> Option<&Material> {
+        find_material(&self.materials, id)
+    }
+
+    /// Like `material`, but mutable, so that the material's parameters can be changed (e.g. once
+    /// a frame, to animate them).
+    pub fn material_mut(&mut self, id: MaterialID) -> Option<&mut Material> {
+        if id == DEFAULT_MATERIAL { None } else { self.materials.get_mut(id - 1) }
+    }
+
     /// Register a `DrawReq` for this batch.
     pub fn register(&mut self, req: DrawReq) {
-        if req.sheet_id >= self.by_sheet.len() {
-            // Apparently `Vec::resize` is unstable, so here's a hacked version.
-            let extra = (req.sheet_id + 1) - self.by_sheet.len();
-            self.by_sheet.reserve(extra);
-            for _ in 0..extra { self.by_sheet.push(vec![]) }
+        // Sprites with a material which doesn't exist are drawn with the default one.
+        let material = if req.material <= self.materials.len() {
+            req.material
+        } else {
+            DEFAULT_MATERIAL
+        };
+
+        let by_material = grow(&mut self.by_layer, req.layer as usize);
+        let by_sheet    = grow(by_material, material);
+        let list        = grow(by_sheet, req.sheet_id);
+
+        if list.capacity() == 0 {
+            *list = self.reqs.take();
         }
 
-        self.by_sheet[req.sheet_id].push(req)
+        list.push(req)
     }
 
-    /// Render all `DrawReq`s which have been passed to this `Batcher`. In addition to causing them
-    /// to be rendered, this will also leave the `Batcher` clear for the next frame.
+    /// Render all `DrawReq`s which have been passed to this `Batcher`, ordered by layer. Within a
+    /// layer, the sprites with the same material from sheets which share array textures are drawn
+    /// together in one call, unless their sheets have lighting maps. In addition to causing them to
+    /// be rendered, this will also leave the `Batcher` clear for the next frame.
     pub fn render_batch<R: Renderer>(&mut self, r: &mut R, db: asset::Handle, cam: &Camera) {
+        let _span = trace::span(trace::GL, "render_batch");
+        let start = Instant::now();
+
+        let mut total    = 0;
+        let mut outlines = 0;
+
+        // Lay out the groups first, so that each one has a region of the vertices to itself. Sheets
+        // which aren't loaded are skipped.
+        self.layout.clear();
+
+        for (layer, by_material) in self.by_layer.iter().enumerate() {
+            for (material, by_sheet) in by_material.iter().enumerate() {
+                for (id, reqs) in by_sheet.iter().enumerate().filter(|&(_, v)| { !v.is_empty() }) {
+                    if let Some(sheet) = db.get_sprite_sheet(id) {
+                        let highlighted = reqs.iter().filter(|req| req.highlight.is_some()).count();
+
+                        self.layout.push(Group {
+                            layer:    layer as u8,
+                            material: material,
+                            sheet:    id,
+                            array:    sheet.array,
+                            first:    0,
+                            count:    reqs.len(),
+
+                            outlines_first: 0,
+                            outlines_count: highlighted,
+                        });
+                    }
+                }
+            }
+        }
 
-        let mut verts  = vec![];
-        let mut groups = vec![];
+        // Put the sheets which share arrays next to each other, so that they can be drawn together.
+        self.layout.sort_by(|a, b| {
+            (a.layer, a.material, a.array, a.sheet).cmp(&(b.layer, b.material, b.array, b.sheet))
+        });
 
-        for (id, reqs) in self.by_sheet.iter().enumerate().filter(|&(_, v)| { !v.is_empty() }) {
-            let sheet = match db.get_sprite_sheet(id) {
-                Some(sheet) => sheet,
-                None        => continue,
-            };
+        for grp in self.layout.iter_mut() {
+            grp.first          = total;
+            grp.outlines_first = outlines;
+
+            total    += grp.count;
+            outlines += grp.outlines_count;
+        }
+
+        // The outlines go after all of the sprites.
+        for grp in self.layout.iter_mut() {
+            grp.outlines_first += total;
+        }
+
+        // Merge neighbouring groups which can be drawn in one call. Their sprites and outlines are
+        // already next to each other.
+        self.draws.clear();
+
+        for grp in self.layout.iter() {
+            let sheet = db.get_sprite_sheet(grp.sheet).unwrap();
+
+            if let Some(last) = self.draws.last_mut() {
+                let last_sheet = db.get_sprite_sheet(last.sheet).unwrap();
+
+                let same = last.layer == grp.layer && last.material == grp.material;
+
+                if same && last_sheet.batches_with(sheet) {
+                    last.count          += grp.count;
+                    last.outlines_count += grp.outlines_count;
+                    continue
+                }
+            }
+
+            self.draws.push(*grp);
+        }
+
+        // The vertices are written straight into the renderer's buffer, bounding the sprites which
+        // cast shadows on the way, since the buffer can't be read back.
+        let mut bounds = r.shadow_bounds();
+
+        {
+            let by_layer   = &self.by_layer;
+            let layout     = &self.layout;
+            let no_shadows = &self.no_shadows;
+            let job_bounds = &mut self.job_bounds;
+            let verts      = r.vertices(total + outlines);
+            let db         = &db;
+
+            match self.pool {
+                Some(ref mut pool) if total > SPRITES_PER_JOB => {
+                    // Each job bounds its own sprites, and they're merged once every job is done.
+                    job_bounds.clear();
+
+                    for grp in layout.iter() {
+                        let casts = !no_shadows.contains(&grp.layer);
+
+                        for _ in 0..(grp.count + SPRITES_PER_JOB - 1) / SPRITES_PER_JOB {
+                            job_bounds.push(if casts { bounds } else { None });
+                        }
+                    }
+
+                    pool.scoped(|scope| {
+                        let mut rest  = &mut verts[..total];
+                        let mut slots = &mut job_bounds[..];
+
+                        for grp in layout.iter() {
+                            let reqs = &by_layer[grp.layer as usize][grp.material][grp.sheet];
+
+                            let (mine, others) = {rest}.split_at_mut(reqs.len());
+                            rest = others;
+
+                            let jobs = (reqs.len() + SPRITES_PER_JOB - 1) / SPRITES_PER_JOB;
+
+                            let (my_slots, other_slots) = {slots}.split_at_mut(jobs);
+                            slots = other_slots;
+
+                            let sheet = db.get_sprite_sheet(grp.sheet).unwrap();
+
+                            let work = mine.chunks_mut(SPRITES_PER_JOB)
+                                           .zip(reqs.chunks(SPRITES_PER_JOB))
+                                           .zip(my_slots.iter_mut());
+
+                            for ((out, reqs), slot) in work {
+                                scope.execute(move || {
+                                    for (vert, req) in out.iter_mut().zip(reqs.iter()) {
+                                        let v = req.to_vertex(cam, sheet);
+
+                                        if let Some(ref mut b) = *slot { b.add(&v) }
 
-            groups.push(RenderGroup {
-                first: verts.len(),
-                count: reqs.len(),
-                sheet: sheet,
+                                        *vert = v;
+                                    }
+                                });
+                            }
+                        }
+                    });
+
+                    if let Some(ref mut b) = bounds {
+                        for job in job_bounds.iter().filter_map(|job| job.as_ref()) {
+                            b.merge(job);
+                        }
+                    }
+                },
+
+                _ => for grp in layout.iter() {
+                    let reqs  = &by_layer[grp.layer as usize][grp.material][grp.sheet];
+                    let sheet = db.get_sprite_sheet(grp.sheet).unwrap();
+                    let casts = !no_shadows.contains(&grp.layer);
+
+                    let out = &mut verts[grp.first..grp.first + grp.count];
+
+                    for (vert, req) in out.iter_mut().zip(reqs.iter()) {
+                        let v = req.to_vertex(cam, sheet);
+
+                        if casts {
+                            if let Some(ref mut b) = bounds { b.add(&v) }
+                        }
+
+                        *vert = v;
+                    }
+                },
+            }
+
+            // Few sprites are highlighted at once, so their outlines aren't worth sharing out.
+            for grp in layout.iter().filter(|grp| grp.outlines_count > 0) {
+                let reqs  = &by_layer[grp.layer as usize][grp.material][grp.sheet];
+                let sheet = db.get_sprite_sheet(grp.sheet).unwrap();
+
+                let out = &mut verts[grp.outlines_first..grp.outlines_first + grp.outlines_count];
+
+                let highlighted = reqs.iter().filter_map(|req| req.highlight.map(|c| (req, c)));
+
+                for (vert, (req, color)) in out.iter_mut().zip(highlighted) {
+                    *vert = SpriteVertex { tint: color, ..req.to_vertex(cam, sheet) };
+                }
+            }
+        }
+
+        // Without timer queries, the GPU's side of the batch just goes untimed.
+        if self.timer.is_none() && opengl::capabilities().timer_queries {
+            self.timer = Some(opengl::GpuTimer::new());
+        }
+
+        let mut timer = self.timer.as_mut();
+
+        if let Some(ref mut timer) = timer {
+            timer.begin();
+        }
+
+        r.prepare(bounds);
+
+        // Draw the shadow map before any sprites, since they all read it.
+        self.shadow_timer.begin();
+
+        if r.begin_shadows() {
+            let no_shadows = &self.no_shadows;
+
+            for grp in self.draws.iter().filter(|grp| !no_shadows.contains(&grp.layer)) {
+                let _span = trace::span(trace::DRAW, "shadows");
+                let _op   = opengl::operation("drawing the shadows of layer", &grp.layer);
+
+                r.render_shadows(RenderGroup {
+                    first:    grp.first,
+                    count:    grp.count,
+                    sheet:    db.get_sprite_sheet(grp.sheet).unwrap(),
+                    material: find_material(&self.materials, grp.material),
+                });
+
+                self.shadow_timer.draw(grp.count);
+            }
+
+            r.end_shadows();
+
+            opengl::set_viewport(&cam.viewport());
+        }
+
+        let shadows = self.shadow_timer.end();
+
+        let mut depth_test = true;
+        let mut world      = None;
+
+        self.world_timer.begin();
+
+        for grp in self.draws.iter() {
+            // The groups are in order of layer, so the UI layers come last.
+            if world.is_none() && grp.layer >= LAYER_UI {
+                world = Some(self.world_timer.end());
+                self.ui_timer.begin();
+            }
+
+            let mut span = trace::span(trace::DRAW, "sprites");
+            span.arg("layer", &grp.layer);
+            span.arg("sheet", &grp.sheet);
+            span.arg("material", &grp.material);
+            span.arg("count", &grp.count);
+
+            let _op = opengl::operation("drawing sprites in layer", &grp.layer);
+
+            if self.no_depth.contains(&grp.layer) == depth_test {
+                depth_test = !depth_test;
+
+                unsafe {
+                    if depth_test {
+                        gl::Enable(gl::DEPTH_TEST)
+                    } else {
+                        gl::Disable(gl::DEPTH_TEST)
+                    }
+                }
+            }
+
+            let sheet    = db.get_sprite_sheet(grp.sheet).unwrap();
+            let material = find_material(&self.materials, grp.material);
+
+            let pass = if world.is_none() { &mut self.world_timer } else { &mut self.ui_timer };
+
+            r.render(RenderGroup {
+                first:    grp.first,
+                count:    grp.count,
+                sheet:    sheet,
+                material: material,
             });
 
-            for req in reqs.iter() {
-                let vert = req.to_vertex(cam, sheet);
-                verts.push(vert);
+            pass.draw(grp.count);
+
+            if grp.outlines_count > 0 {
+                r.render_outlines(RenderGroup {
+                    first:    grp.outlines_first,
+                    count:    grp.outlines_count,
+                    sheet:    sheet,
+                    material: material,
+                });
+
+                pass.draw(grp.outlines_count);
             }
         }
 
-        r.prepare(&verts);
+        let world = match world {
+            Some(world) => world,
+            None        => {
+                let world = self.world_timer.end();
+                self.ui_timer.begin();
+                world
+            },
+        };
+
+        let ui = self.ui_timer.end();
 
-        for g in groups {
-            r.render(g)
+        // Leave depth testing on, as the rest of the frame expects.
+        if !depth_test {
+            unsafe { gl::Enable(gl::DEPTH_TEST) }
         }
 
-        for v in self.by_sheet.iter_mut() {
-            v.clear();
+        if let Some(ref mut timer) = timer {
+            timer.end();
         }
+
+        self.stats = Stats {
+            sprites:   total,
+            groups:    self.draws.len(),
+            batch_cpu: time::Duration::from(start.elapsed()),
+            batch_gpu: timer.and_then(|timer| timer.poll()),
+
+            shadows: shadows,
+            world:   world,
+            ui:      ui,
+        };
+
+        // Hand every list back to the arena, so that next frame's sheets can reuse them.
+        for by_material in self.by_layer.iter_mut() {
+            for v in by_material.iter_mut().flat_map(|by_sheet| by_sheet.iter_mut()) {
+                if v.capacity() > 0 {
+                    self.reqs.give(mem::replace(v, vec![]));
+                }
+            }
+        }
+    }
+}
+
+// The material among `materials` (as added to a `Batcher`) with id `id`.
+fn find_material(materials: &[Material], id: MaterialID) -> Option<&Material> {
+    if id == DEFAULT_MATERIAL { None } else { materials.get(id - 1) }
+}
+
+// The element of `v` at `index`, after growing `v` with empty `Vec`s to make room for it if need
+// be.
+fn grow<T>(v: &mut Vec<Vec<T>>, index: usize) -> &mut Vec<T> {
+    // Apparently `Vec::resize` is unstable, so here's a hacked version.
+    if index >= v.len() {
+        let extra = (index + 1) - v.len();
+        v.reserve(extra);
+        for _ in 0..extra { v.push(vec![]) }
+    }
+
+    &mut v[index]
+}
+
+impl Recreate for Batcher {
+    /// Replace the GPU timers. The batcher holds nothing else on the GPU between frames.
+    fn recreate(&mut self) -> Result<(), String> {
+        self.timer = None;
+
+        self.shadow_timer.reset();
+        self.world_timer.reset();
+        self.ui_timer.reset();
+
+        Ok(())
     }
 }
 
@@ -584,6 +2814,15 @@ pub enum Error {
 
     /// The engine and the shaders disagree about the name of a uniform.
     NoSuchActiveUniform(String),
+
+    /// The engine and the shaders disagree about the name of a uniform block.
+    NoSuchUniformBlock(String),
+
+    /// A shader couldn't be loaded (see `grafix::shader`).
+    Shader(opengl::ShaderFileError),
+
+    /// A framebuffer couldn't be created.
+    Framebuffer(String),
 }
 
 impl From<opengl::CompileError> for Error {
@@ -598,6 +2837,12 @@ impl From<opengl::LinkError> for Error {
     }
 }
 
+impl From<opengl::ShaderFileError> for Error {
+    fn from(err: opengl::ShaderFileError) -> Error {
+        Error::Shader(err)
+    }
+}
+
 impl From<opengl::NoSuchActiveAttrib> for Error {
     fn from(err: opengl::NoSuchActiveAttrib) -> Error {
         match err {
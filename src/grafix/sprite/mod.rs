@@ -18,5 +18,299 @@
 #[allow(missing_docs)]
 pub mod wire;
 
+/// Structural verification of untrusted `wire` buffers.
+pub mod verify;
+
 #[cfg(feature = "client")] mod client;
 #[cfg(feature = "client")] pub use self::client::*;
+
+use std::convert::{AsRef, From};
+
+use flatbuffers as fb;
+
+/// A descriptor which explains the properties of a sprite sheet and where to find the textures.
+#[derive(Clone,Debug,PartialEq)]
+pub struct SheetDesc {
+    /// Width of the texture, in texels.
+    pub img_width:  u16,
+
+    /// Height of the texture, in texels.
+    pub img_height: u16,
+
+    /// X-coordinate of origin pixel.
+    pub origin_x: u16,
+
+    /// Y-coordinate of origin pixel.
+    pub origin_y: u16,
+
+    /// Width of each sprite, in texels.
+    pub spr_width: u16,
+
+    /// Height of each sprite, in texels.
+    pub spr_height: u16,
+
+    /// Number of sprites in each row in the sheet.
+    pub num_across: u16,
+
+    /// Number of sprites in each column in the sheet.
+    pub num_down: u16,
+
+    /// Total number of sprites in the sheet.
+    pub total: u16,
+
+    /// Path to the color PNG for this sprite sheet.
+    pub color_path: String,
+
+    /// Path to the depth PNG for this sprite sheet.
+    pub depth_path: String,
+
+    /// Content hash of the color PNG (see `asset::hash`), or 0 if unknown.
+    pub color_hash: u64,
+
+    /// Content hash of the depth PNG, or 0 if unknown.
+    pub depth_hash: u64,
+
+    /// Path to the normal map PNG for this sprite sheet, if it has one. Sheets without a normal
+    /// map are lit as if every pixel faced the camera.
+    pub normal_path: Option<String>,
+
+    /// Content hash of the normal map PNG, or 0 if unknown.
+    pub normal_hash: u64,
+
+    /// Path to the emissive mask PNG for this sprite sheet, if it has one. The mask's red channel
+    /// says how much each pixel lights itself, so that windows and torches still glow at night.
+    pub emissive_path: Option<String>,
+
+    /// Content hash of the emissive mask PNG, or 0 if unknown.
+    pub emissive_hash: u64,
+}
+
+/// The reasons a `SheetDesc` can't be converted from its FlatBuffer representation.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum DescError {
+    /// A required field was missing from the FlatBuffer.
+    MissingField(&'static str),
+
+    /// A size or count which sprites are located by is zero. Holds the field's name.
+    Zero(&'static str),
+}
+
+impl SheetDesc {
+    /// Check that none of the sizes or counts which sprites are located by are zero, since those
+    /// are divided by when drawing.
+    pub fn validate(&self) -> Result<(), DescError> {
+        let sizes = [
+            ("img_width",  self.img_width),
+            ("img_height", self.img_height),
+            ("spr_width",  self.spr_width),
+            ("spr_height", self.spr_height),
+            ("num_across", self.num_across),
+            ("num_down",   self.num_down),
+            ("total",      self.total),
+        ];
+
+        match sizes.iter().find(|&&(_, n)| n == 0) {
+            Some(&(field, _)) => Err(DescError::Zero(field)),
+            None              => Ok(()),
+        }
+    }
+
+    /// The paths of the files backing this sheet along with their content hashes, in the order
+    /// they're streamed to clients.
+    pub fn files(&self) -> Vec<(&str, u64)> {
+        let mut files = vec![(&self.color_path[..], self.color_hash),
+                             (&self.depth_path[..], self.depth_hash)];
+
+        if let Some(ref path) = self.normal_path {
+            files.push((&path[..], self.normal_hash));
+        }
+
+        if let Some(ref path) = self.emissive_path {
+            files.push((&path[..], self.emissive_hash));
+        }
+
+        files
+    }
+
+    /// Convert from FlatBuffer representation, checking the result with `validate`.
+    pub fn from_wire(w: &wire::SpriteSheetDesc) -> Result<SheetDesc, DescError> {
+        let color_path = try!(w.color_path().ok_or(DescError::MissingField("color_path")));
+        let depth_path = try!(w.depth_path().ok_or(DescError::MissingField("depth_path")));
+
+        let desc = SheetDesc {
+            img_width:  w.img_width(),
+            img_height: w.img_height(),
+            origin_x:   w.origin_x(),
+            origin_y:   w.origin_y(),
+            spr_width:  w.spr_width(),
+            spr_height: w.spr_height(),
+            num_across: w.num_across(),
+            num_down:   w.num_down(),
+            total:      w.total(),
+            color_path: From::from(AsRef::as_ref(color_path)),
+            depth_path: From::from(AsRef::as_ref(depth_path)),
+            color_hash: w.color_hash(),
+            depth_hash: w.depth_hash(),
+
+            normal_path: w.normal_path().map(|p| From::from(AsRef::as_ref(p))),
+            normal_hash: w.normal_hash(),
+
+            emissive_path: w.emissive_path().map(|p| From::from(AsRef::as_ref(p))),
+            emissive_hash: w.emissive_hash(),
+        };
+
+        try!(desc.validate());
+
+        Ok(desc)
+    }
+
+    /// Add this descriptor to a FlatBuffer under construction, under the given name.
+    pub fn to_wire(&self, name: &str, fbb: &mut fb::FlatBufferBuilder)
+        -> fb::Offset<wire::SpriteSheetDesc> {
+
+        let name       = fbb.create_string(name);
+        let color_path = fbb.create_string(&self.color_path);
+        let depth_path = fbb.create_string(&self.depth_path);
+
+        let normal_path   = self.normal_path.as_ref().map(|p| fbb.create_string(p));
+        let emissive_path = self.emissive_path.as_ref().map(|p| fbb.create_string(p));
+
+        let mut b = wire::SpriteSheetDescBuilder::new(fbb);
+        b.add_name(name);
+        b.add_img_width(self.img_width);
+        b.add_img_height(self.img_height);
+        b.add_origin_x(self.origin_x);
+        b.add_origin_y(self.origin_y);
+        b.add_spr_width(self.spr_width);
+        b.add_spr_height(self.spr_height);
+        b.add_num_across(self.num_across);
+        b.add_num_down(self.num_down);
+        b.add_total(self.total);
+        b.add_color_path(color_path);
+        b.add_depth_path(depth_path);
+        b.add_color_hash(self.color_hash);
+        b.add_depth_hash(self.depth_hash);
+
+        if let Some(normal_path) = normal_path {
+            b.add_normal_path(normal_path);
+            b.add_normal_hash(self.normal_hash);
+        }
+
+        if let Some(emissive_path) = emissive_path {
+            b.add_emissive_path(emissive_path);
+            b.add_emissive_hash(self.emissive_hash);
+        }
+
+        b.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::AsRef;
+
+    use flatbuffers as fb;
+
+    use grafix::sprite::{verify, wire, DescError, SheetDesc};
+
+    fn desc() -> SheetDesc {
+        SheetDesc {
+            img_width:     256,
+            img_height:    128,
+            origin_x:      16,
+            origin_y:      28,
+            spr_width:     32,
+            spr_height:    32,
+            num_across:    8,
+            num_down:      4,
+            total:         30,
+            color_path:    "sheets/tree.png".to_string(),
+            depth_path:    "sheets/tree.depth.png".to_string(),
+            color_hash:    0x1234,
+            depth_hash:    0x5678,
+            normal_path:   None,
+            normal_hash:   0,
+            emissive_path: None,
+            emissive_hash: 0,
+        }
+    }
+
+    // Write `desc` into a buffer as a root `SpriteSheetDesc`, verify it, and read it back.
+    fn through_buffer(desc: &SheetDesc) -> Result<SheetDesc, DescError> {
+        let mut fbb = fb::FlatBufferBuilder::new();
+
+        let root = desc.to_wire("tree", &mut fbb);
+
+        fbb.finish(root);
+
+        let buf = fbb.into_vec();
+
+        verify::verify_sprite_sheet_desc_buffer(&buf).unwrap();
+
+        let w = fb::get_root::<wire::SpriteSheetDesc>(&buf);
+
+        let name: &str = w.name().unwrap().as_ref();
+
+        assert_eq!(name, "tree");
+
+        SheetDesc::from_wire(w)
+    }
+
+    #[test]
+    fn sheet_desc_round_trip() {
+        let plain = desc();
+
+        let mut lit = desc();
+        lit.normal_path   = Some("sheets/tree.normal.png".to_string());
+        lit.normal_hash   = 0x9abc;
+        lit.emissive_path = Some("sheets/tree.emissive.png".to_string());
+        lit.emissive_hash = 0xdef0;
+
+        for desc in [plain, lit].iter() {
+            assert_eq!(through_buffer(desc), Ok(desc.clone()));
+        }
+    }
+
+    #[test]
+    fn sheet_desc_zero_sizes() {
+        let fields = ["img_width", "img_height", "spr_width", "spr_height", "num_across",
+                      "num_down", "total"];
+
+        for &field in fields.iter() {
+            let mut bad = desc();
+
+            match field {
+                "img_width"  => bad.img_width  = 0,
+                "img_height" => bad.img_height = 0,
+                "spr_width"  => bad.spr_width  = 0,
+                "spr_height" => bad.spr_height = 0,
+                "num_across" => bad.num_across = 0,
+                "num_down"   => bad.num_down   = 0,
+                _            => bad.total      = 0,
+            }
+
+            assert_eq!(bad.validate(), Err(DescError::Zero(field)));
+            assert_eq!(through_buffer(&bad), Err(DescError::Zero(field)));
+        }
+    }
+
+    #[test]
+    fn sheet_desc_missing_paths() {
+        let mut fbb = fb::FlatBufferBuilder::new();
+
+        let root = {
+            let mut b = wire::SpriteSheetDescBuilder::new(&mut fbb);
+            b.add_spr_width(32);
+            b.add_num_across(8);
+            b.add_total(8);
+            b.finish()
+        };
+
+        fbb.finish(root);
+
+        let buf = fbb.into_vec();
+        let w   = fb::get_root::<wire::SpriteSheetDesc>(&buf);
+
+        assert_eq!(SheetDesc::from_wire(w), Err(DescError::MissingField("color_path")));
+    }
+}
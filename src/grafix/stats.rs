@@ -0,0 +1,195 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+#[cfg(feature = "client")] use std::time::Instant;
+
+#[cfg(feature = "client")] use grafix::opengl;
+use time;
+
+/// Statistics about the rendering of a single frame, for performance overlays and logging.
+///
+/// GPU times come from timer queries, which finish a few frames after they are issued, so they
+/// lag slightly behind the CPU times and counts.
+#[derive(Clone,Copy,Debug)]
+pub struct Stats {
+    /// Number of sprites drawn by the `Batcher`.
+    pub sprites: usize,
+
+    /// Number of groups the sprites were batched into, each drawn with one call. Each group is the
+    /// sprites in one layer with one material, from sheets which share array textures.
+    pub groups: usize,
+
+    /// Time the CPU spent in `Batcher::render_batch`, including converting sprites to vertices and
+    /// issuing draw calls.
+    pub batch_cpu: time::Duration,
+
+    /// Time the GPU spent executing the batch's draw calls, or `None` if no measurement has
+    /// finished yet (or the context can't measure it; see `opengl::Capabilities`).
+    pub batch_gpu: Option<time::Duration>,
+
+    /// Drawing the shadow map, which is done first.
+    pub shadows: PassStats,
+
+    /// Drawing the sprites in the layers below `sprite::LAYER_UI`, and their outlines.
+    pub world: PassStats,
+
+    /// Drawing the sprites in `sprite::LAYER_UI` and above, and their outlines.
+    pub ui: PassStats,
+}
+
+impl Stats {
+    /// Statistics for a frame in which nothing was drawn.
+    pub fn new() -> Stats {
+        Stats {
+            sprites:   0,
+            groups:    0,
+            batch_cpu: time::Duration::usec(0),
+            batch_gpu: None,
+
+            shadows: PassStats::new(),
+            world:   PassStats::new(),
+            ui:      PassStats::new(),
+        }
+    }
+
+    /// True if the GPU took longer than the CPU to get through the batch, in which case speeding up
+    /// CPU-side code won't improve the frame rate. Returns `None` if there is no GPU measurement.
+    pub fn gpu_bound(&self) -> Option<bool> {
+        self.batch_gpu.map(|gpu| gpu > self.batch_cpu)
+    }
+
+    /// The number of draw calls issued by all of the passes.
+    pub fn draw_calls(&self) -> usize {
+        self.shadows.draw_calls + self.world.draw_calls + self.ui.draw_calls
+    }
+
+    /// The number of vertices drawn by all of the passes.
+    pub fn vertices(&self) -> usize {
+        self.shadows.vertices + self.world.vertices + self.ui.vertices
+    }
+}
+
+/// Statistics about one pass of a frame, as measured by a `PassTimer`.
+#[derive(Clone,Copy,Debug)]
+pub struct PassStats {
+    /// Number of draw calls issued.
+    pub draw_calls: usize,
+
+    /// Number of vertices drawn, counting each sprite as the one vertex that's written for it.
+    pub vertices: usize,
+
+    /// Time the CPU spent issuing the pass.
+    pub cpu: time::Duration,
+
+    /// Time the GPU spent on the pass, or `None` if no measurement has finished yet (or the
+    /// context can't measure it). This lags a few frames behind the rest.
+    pub gpu: Option<time::Duration>,
+}
+
+impl PassStats {
+    /// Statistics for a pass which drew nothing.
+    pub fn new() -> PassStats {
+        PassStats {
+            draw_calls: 0,
+            vertices:   0,
+            cpu:        time::Duration::usec(0),
+            gpu:        None,
+        }
+    }
+
+    /// `cpu`, in fractional milliseconds, for display.
+    pub fn cpu_ms(&self) -> f32 {
+        millis(self.cpu)
+    }
+
+    /// `gpu`, in fractional milliseconds, for display.
+    pub fn gpu_ms(&self) -> Option<f32> {
+        self.gpu.map(millis)
+    }
+}
+
+fn millis(d: time::Duration) -> f32 {
+    d.as_usec() as f32 / 1_000.0
+}
+
+/// Times a pass of each frame on the CPU, and on the GPU where the context has timer queries (see
+/// `opengl::Capabilities`), and counts the draw calls it issues. The GPU is timed with an
+/// `opengl::TimestampTimer`, so passes may be nested inside each other.
+///
+/// The `Batcher` times its own passes (see `Stats`). The engine doesn't post-process, so a game
+/// which does can time that in the same way, to show alongside them.
+#[cfg(feature = "client")]
+pub struct PassTimer {
+    // Created by the first call to `begin`, since it needs a GL context.
+    gpu: Option<opengl::TimestampTimer>,
+
+    // When the current pass began, and what it's drawn so far.
+    start:   Option<Instant>,
+    current: PassStats,
+}
+
+#[cfg(feature = "client")]
+impl PassTimer {
+    /// A timer for a pass which hasn't begun.
+    pub fn new() -> PassTimer {
+        PassTimer {
+            gpu:     None,
+            start:   None,
+            current: PassStats::new(),
+        }
+    }
+
+    /// Begin this frame's pass.
+    pub fn begin(&mut self) {
+        if self.gpu.is_none() && opengl::capabilities().timer_queries {
+            self.gpu = Some(opengl::TimestampTimer::new());
+        }
+
+        if let Some(ref mut gpu) = self.gpu {
+            gpu.begin();
+        }
+
+        self.start   = Some(Instant::now());
+        self.current = PassStats::new();
+    }
+
+    /// Count a draw call of `vertices` vertices in the current pass.
+    pub fn draw(&mut self, vertices: usize) {
+        self.current.draw_calls += 1;
+        self.current.vertices   += vertices;
+    }
+
+    /// End this frame's pass, and return its statistics.
+    pub fn end(&mut self) -> PassStats {
+        if let Some(ref mut gpu) = self.gpu {
+            gpu.end();
+        }
+
+        if let Some(start) = self.start.take() {
+            self.current.cpu = time::Duration::from(start.elapsed());
+        }
+
+        self.current.gpu = self.gpu.as_mut().and_then(|gpu| gpu.poll());
+        self.current
+    }
+
+    /// Forget the GPU timer, e.g. because the GL context which it was made in is gone. Another is
+    /// made by the next call to `begin`.
+    pub fn reset(&mut self) {
+        self.gpu = None;
+    }
+}
@@ -15,51 +15,698 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::mem;
+use std::rc::Rc;
+
 use asset;
-use entity::client as entity;
+use logging;
+use memory;
+use entity::component::LightKind;
+use entity::sim as entity;
+use entity::{EngineSystem, EngineView};
+use grafix::anim;
+use grafix::device::Recreate;
+use grafix::opengl;
+use grafix::particles::{Emitter, EmitterDesc, EmitterID, Particles};
+use grafix::shader;
 use grafix::sprite;
 use grafix::camera::Camera;
+use math::{self, BoundingCube};
+use rng::Rng;
+use scene::{EntryID, LooseOctree};
+use units::*;
 use time;
 
-/// An implementation of `entity::System` which is responsible for rendering sprites.
+use super::{Lights, Weather, WeatherKind};
+
+/// Controls how often animations choose a new frame for entities which the player can barely see.
+/// In a huge battle, most units are far from the center of the screen, and nobody will notice that
+/// they animate at a lower rate.
+///
+/// Only the choice of frame is throttled; each animation keeps its own `t_start`, so an entity
+/// which comes back into the middle of the screen picks up exactly where it would have been.
+#[derive(Clone,Copy,Debug)]
+pub struct AnimLod {
+    /// Entities within this distance of the center of the screen (in camera space) choose a new
+    /// frame every time they're drawn.
+    pub near: Meters,
+
+    /// How often entities further away than `near` choose a new frame.
+    pub far_interval: time::Duration,
+}
+
+/// The sprites `WorldRender` drew last frame, for finding the entity under the mouse. This is
+/// shared between the `WorldRender` which fills it (see `WorldRender::set_hover`) and whatever
+/// handles the mouse.
+pub struct Hover {
+    drawn: Vec<(entity::EntityID, sprite::DrawReq)>,
+}
+
+impl Hover {
+    /// Create an empty set of sprites, which picks nothing until it's filled.
+    pub fn new() -> Hover {
+        Hover { drawn: vec![] }
+    }
+
+    /// Find the entity drawn at screen coordinate `scr` (measured from the center of the screen),
+    /// as of the last frame. Each sprite's depth texture decides both whether it covers `scr` and
+    /// how far it is from the camera there, so transparent parts of a sprite don't get in the way
+    /// of what's behind them. Sprites in higher layers are drawn over lower ones, so they win
+    /// regardless of depth.
+    pub fn pick(&self, assets: &asset::AssetDb, cam: &Camera, scr: math::Vec2<Pixels>)
+        -> Option<entity::EntityID> {
+
+        let db = assets.get_handle();
+
+        let mut best: Option<(entity::EntityID, u8, Meters)> = None;
+
+        for &(id, ref req) in self.drawn.iter() {
+            let sheet = match db.get_sprite_sheet(req.sheet_id) {
+                Some(sheet) => sheet,
+                None        => continue,
+            };
+
+            let depth = match req.depth_at(cam, sheet, scr) {
+                Some(depth) => depth,
+                None        => continue,
+            };
+
+            let better = match best {
+                Some((_, layer, d)) => req.layer > layer || (req.layer == layer && depth < d),
+                None                => true,
+            };
+
+            if better {
+                best = Some((id, req.layer, depth));
+            }
+        }
+
+        best.map(|(id, _, _)| id)
+    }
+
+    // Forget last frame's sprites.
+    fn clear(&mut self) {
+        self.drawn.clear();
+    }
+}
+
+// The frame last chosen for an entity being drawn at a reduced rate.
+struct LodEntry {
+    anim: anim::Instance,
+    req:  Option<sprite::DrawReq>,
+    next: time::Duration,
+
+    // The value of `WorldRender::frame` when this entity was last drawn.
+    seen: u64,
+}
+
+// What `WorldRender` remembers about an entity between being told about it in `process_entity` and
+// drawing it in `update`.
+struct Tracked {
+    entry:  EntryID,
+    bounds: BoundingCube,
+    anim:   anim::Instance,
+
+    // The value of `WorldRender::frame` when this entity was last processed.
+    seen: u64,
+}
+
+// The initial bounds of the spatial index. The octree grows upward as needed, so this only needs to
+// be a reasonable guess at the size of a level.
+const INDEX_HALF_EDGE: f32 = 64.0;
+const INDEX_MIN:       f32 = 1.0;
+const INDEX_LOOSE:     f32 = 2.0;
+
+/// An `EngineSystem` which is responsible for rendering sprites.
+///
+/// Entities aren't drawn as they're processed. Instead their bounds and animations are kept in an
+/// octree, and each frame only the entries which the camera can see are drawn.
 pub struct WorldRender<R: sprite::Renderer> {
     assets:   asset::AssetDb,
     batcher:  sprite::Batcher,
     renderer: R,
-    camera:   Camera,
+    camera:   Rc<RefCell<Camera>>,
+
+    // The camera's visible region, recomputed each frame.
+    frustum: math::Frustum,
+
+    // Where every drawable entity is.
+    index:   LooseOctree<entity::EntityID>,
+    tracked: BTreeMap<entity::EntityID, Tracked>,
+
+    // Scratch space for the results of querying `index`.
+    visible: Vec<EntryID>,
+
+    lod:       Option<AnimLod>,
+    lod_cache: BTreeMap<entity::EntityID, LodEntry>,
+    frame:     u64,
+
+    hover:     Option<Rc<RefCell<Hover>>>,
+    lights:    Option<Rc<RefCell<Lights>>>,
+    particles: Option<Rc<RefCell<Particles>>>,
+
+    // The GL context generation which the renderer's resources were made for.
+    generation: usize,
+
+    // The shader generation which the renderer's programs were built from.
+    shaders: usize,
 }
 
-impl<R: sprite::Renderer> entity::System for WorldRender<R> {
-    /// Render last frame's entity batch.
-    fn update(&mut self, _now: time::Duration) {
-        self.batcher.render_batch(&mut self.renderer, self.assets.get_handle(), &self.camera);
-    }
+impl<R: sprite::Renderer> EngineSystem for WorldRender<R> {
+    /// Draw the entities which were processed last step, and which the camera can see.
+    fn update(&mut self, now: time::Duration) {
+        // The system owns its GPU resources, so it can't be put in a `device::Registry`. It
+        // recreates them itself instead, if the GL context has been recreated.
+        if self.generation != opengl::generation() {
+            self.recreate_gpu();
+        } else if self.shaders != shader::generation() {
+            self.reload_shaders();
+        }
 
-    /// Add this entity to the batch to be rendered.
-    fn process_entity<'x>(&mut self, now: time::Duration, entity: &mut entity::View<'x>) {
-       if let &mut entity::View{
-           world_location: Some(ref mut loc),
-           world_render:   Some(ref mut ren),
-           ..
-       } = entity {
-            if let Some(req) = ren.anim.draw_at(self.assets.get_handle(), loc.bounds.center, now) {
-                self.batcher.register(req)
+        let camera = self.camera.clone();
+        let camera = camera.borrow();
+
+        self.frustum = camera.frustum();
+
+        self.draw_visible(now);
+
+        if let Some(ref particles) = self.particles {
+            let mut particles = particles.borrow_mut();
+
+            particles.update(now);
+            particles.draw(&mut self.batcher, now);
+        }
+
+        opengl::set_viewport(&camera.viewport());
+
+        let lighting = self.lighting(&camera);
+        self.renderer.set_lighting(&lighting);
+
+        self.batcher.render_batch(&mut self.renderer, self.assets.get_handle(), &camera);
+
+        self.frame += 1;
+
+        // Forget entities which weren't processed last step, so that neither the index nor the
+        // cache grows without bound as entities come and go.
+        let frame = self.frame;
+
+        let gone: Vec<_> = self.tracked.iter()
+            .filter(|&(_, t)| t.seen + 1 < frame)
+            .map(|(&id, _)| id)
+            .collect();
+
+        for id in gone {
+            if let Some(t) = self.tracked.remove(&id) {
+                self.index.remove(t.entry);
             }
-       }
+        }
+
+        let stale: Vec<_> = self.lod_cache.iter()
+            .filter(|&(_, e)| e.seen + 1 < frame)
+            .map(|(&id, _)| id)
+            .collect();
+
+        for id in stale {
+            self.lod_cache.remove(&id);
+        }
     }
+
+    /// Record where this entity is and how it looks, to be drawn next frame if it's on camera.
+    fn process_entity<V: EngineView>(&mut self, _now: time::Duration, entity: &mut V) {
+        let id = entity.id();
+
+        if let (Some(loc), Some(ren)) = (entity.world_location(), entity.world_render()) {
+            self.track(id, loc.bounds, ren.anim);
+        }
+    }
+
+    fn name(&self) -> &'static str { "world_render" }
+
+    fn runs_while_paused(&self) -> bool { true }
 }
 
 impl <R: sprite::Renderer> WorldRender<R> {
-    /// Create a new world rendering system with the given components.
+    /// Create a new world rendering system with the given components. The camera is shared, so
+    /// that controllers such as `CameraFollow` can move it.
     ///
-    /// At the moment there is no way to update the database or camera. I'll work on that later.
-    pub fn new(assets: asset::AssetDb, renderer: R, camera: Camera) -> WorldRender<R> {
+    /// At the moment there is no way to update the database. I'll work on that later.
+    pub fn new(assets: asset::AssetDb, renderer: R, camera: Rc<RefCell<Camera>>)
+        -> WorldRender<R> {
+
+        let frustum = camera.borrow().frustum();
+
+        let initial = BoundingCube {
+            center:    vec3!(Meters ; 0.0, 0.0, 0.0),
+            half_edge: Meters(INDEX_HALF_EDGE),
+        };
+
         WorldRender {
             assets:    assets,
             batcher:   sprite::Batcher::new(),
             renderer:  renderer,
             camera:    camera,
+            frustum:   frustum,
+            index:     LooseOctree::new(initial, Meters(INDEX_MIN), INDEX_LOOSE),
+            tracked:   BTreeMap::new(),
+            visible:   vec![],
+            lod:       None,
+            lod_cache: BTreeMap::new(),
+            frame:     0,
+            hover:     None,
+            lights:    None,
+            particles: None,
+
+            generation: opengl::generation(),
+            shaders:    shader::generation(),
+        }
+    }
+
+    /// Throttle the animation of distant entities according to `lod`, or animate everything at
+    /// full rate with `None` (the default).
+    pub fn set_anim_lod(&mut self, lod: Option<AnimLod>) {
+        self.lod = lod;
+
+        if lod.is_none() {
+            self.lod_cache.clear();
+        }
+    }
+
+    /// Record the sprites drawn each frame in `hover`, so that the entity under the mouse can be
+    /// found, or stop recording them with `None` (the default).
+    pub fn set_hover(&mut self, hover: Option<Rc<RefCell<Hover>>>) {
+        self.hover = hover;
+    }
+
+    /// Light the sprites with `lights`, or draw them exactly as they appear in their sheets with
+    /// `None` (the default).
+    pub fn set_lights(&mut self, lights: Option<Rc<RefCell<Lights>>>) {
+        self.lights = lights;
+    }
+
+    /// Simulate and draw `particles` along with the world each frame, so that they're depth tested
+    /// against it, or draw no particles with `None` (the default). The particles are shared, so
+    /// that others such as the `WeatherSystem` can add emitters.
+    pub fn set_particles(&mut self, particles: Option<Rc<RefCell<Particles>>>) {
+        self.particles = particles;
+    }
+
+    /// Add the spatial index and what's remembered about each entity to `report`. The sprite
+    /// sheets are reported by the `AssetDb`.
+    pub fn report_memory(&self, report: &mut memory::Report) {
+        self.index.report_memory(report, logging::GFX, "world_render index");
+
+        report.add(logging::GFX, "world_render entities", self.tracked.len(),
+                   self.tracked.len() *
+                       (mem::size_of::<entity::EntityID>() + mem::size_of::<Tracked>()));
+
+        report.add(logging::GFX, "world_render LOD cache", self.lod_cache.len(),
+                   self.lod_cache.len() *
+                       (mem::size_of::<entity::EntityID>() + mem::size_of::<LodEntry>()));
+    }
+
+    // Rebuild the GPU resources of the renderer, batcher and sprite sheets for a new GL context.
+    fn recreate_gpu(&mut self) {
+        for (id, err) in self.assets.recreate_textures() {
+            error!(target: logging::GFX, "couldn't reload sprite sheet #{}: {:?}", id, err);
+        }
+
+        if let Err(err) = self.renderer.recreate() {
+            error!(target: logging::GFX, "couldn't recreate the sprite renderer: {}", err);
+        }
+
+        let _ = self.batcher.recreate();
+
+        self.generation = opengl::generation();
+        self.shaders    = shader::generation();
+    }
+
+    // Rebuild the renderer from the shaders, which have changed. If they don't compile, the old
+    // programs are kept and the error is logged, so that a typo doesn't end the session.
+    fn reload_shaders(&mut self) {
+        self.shaders = shader::generation();
+
+        match self.renderer.recreate() {
+            Ok(())   => info!(target: logging::GFX, "rebuilt the sprite renderer"),
+            Err(err) => error!(target: logging::GFX,
+                               "couldn't rebuild the sprite renderer, keeping the old one: {}",
+                               err),
+        }
+    }
+
+    // Convert the shared lights to camera space for the renderer.
+    fn lighting(&self, camera: &Camera) -> sprite::Lighting {
+        let lights = match self.lights {
+            Some(ref lights) => lights.borrow(),
+            None             => return sprite::Lighting::new(camera, [1.0, 1.0, 1.0]),
+        };
+
+        let ambient = [lights.ambient[0] * lights.tint[0],
+                       lights.ambient[1] * lights.tint[1],
+                       lights.ambient[2] * lights.tint[2]];
+
+        let mut lighting = sprite::Lighting::new(camera, ambient);
+
+        for placed in lights.lights() {
+            let light = &placed.light;
+
+            let color = [light.color[0] * light.intensity,
+                         light.color[1] * light.intensity,
+                         light.color[2] * light.intensity];
+
+            match light.kind {
+                LightKind::Point { radius } => {
+                    lighting.add_point(camera, placed.center, color, radius)
+                },
+
+                LightKind::Directional { direction, shadows } => {
+                    lighting.add_directional(camera, direction, color, shadows)
+                },
+            }
+        }
+
+        lighting
+    }
+
+    // Update the index with the current bounds and animation of entity `id`.
+    fn track(&mut self, id: entity::EntityID, bounds: BoundingCube, anim: anim::Instance) {
+        let frame = self.frame;
+
+        if let Some(t) = self.tracked.get_mut(&id) {
+            let (a, b) = (t.bounds.center, bounds.center);
+            let moved  = a.x != b.x || a.y != b.y || a.z != b.z ||
+                         t.bounds.half_edge != bounds.half_edge;
+
+            if moved {
+                self.index.adjust(t.entry, bounds);
+            }
+
+            t.bounds = bounds;
+            t.anim   = anim;
+            t.seen   = frame;
+
+            return
+        }
+
+        let entry = self.index.insert(id, bounds);
+
+        self.tracked.insert(id, Tracked { entry: entry, bounds: bounds, anim: anim, seen: frame });
+    }
+
+    // Add a draw request to the batch for every tracked entity which the camera can see.
+    fn draw_visible(&mut self, now: time::Duration) {
+        let mut visible = mem::replace(&mut self.visible, vec![]);
+
+        self.index.query_frustum(&self.frustum, &mut visible);
+
+        let hover = self.hover.clone();
+
+        if let Some(ref hover) = hover {
+            hover.borrow_mut().clear();
+        }
+
+        for &entry in visible.iter() {
+            let id = match self.index.get(entry) {
+                Some((&id, _)) => id,
+                None           => continue,
+            };
+
+            let (center, inst) = match self.tracked.get(&id) {
+                Some(t) => (t.bounds.center, t.anim),
+                None    => continue,
+            };
+
+            let req = match self.lod {
+                Some(lod) if !self.is_near(&lod, center) => {
+                    self.throttled_draw(id, &lod, &inst, center, now)
+                },
+
+                _ => inst.draw_at(self.assets.get_handle(), center, now),
+            };
+
+            if let Some(req) = req {
+                if let Some(ref hover) = hover {
+                    hover.borrow_mut().drawn.push((id, req));
+                }
+
+                self.batcher.register(req)
+            }
+        }
+
+        self.visible = visible;
+    }
+
+    // Return true if `loc` is close enough to the center of the screen to animate at full rate.
+    fn is_near(&self, lod: &AnimLod, loc: math::Vec3<Meters>) -> bool {
+        let cam = self.camera.borrow().game_to_camera(loc);
+
+        vec2!(cam.x, cam.y).length() <= lod.near
+    }
+
+    // Draw a distant entity, choosing a new frame only if `far_interval` has passed since the last
+    // one was chosen (or its animation has changed). The cached request is moved to `loc`, since
+    // the entity may have moved even if its frame hasn't changed.
+    fn throttled_draw(&mut self, id: entity::EntityID, lod: &AnimLod, inst: &anim::Instance,
+                      loc: math::Vec3<Meters>, now: time::Duration) -> Option<sprite::DrawReq> {
+
+        let frame = self.frame;
+
+        if let Some(e) = self.lod_cache.get_mut(&id) {
+            if e.anim == *inst && now < e.next {
+                e.seen = frame;
+
+                return e.req.map(|req| sprite::DrawReq { game_loc: loc, ..req })
+            }
+        }
+
+        let req = inst.draw_at(self.assets.get_handle(), loc, now);
+
+        self.lod_cache.insert(id, LodEntry {
+            anim: *inst,
+            req:  req,
+            next: now + lod.far_interval,
+            seen: frame,
+        });
+
+        req
+    }
+}
+
+/// How high above the ground a `WeatherSystem` gives off particles, unless changed with
+/// `WeatherSystem::set_height`.
+pub const WEATHER_HEIGHT: f32 = 10.0;
+
+/// Draws the shared `Weather` as particles falling over the ground the camera can see, and tints
+/// the world to match through `Lights::tint`: darker when it rains and paler when it snows.
+///
+/// The particles are given off from a region `WEATHER_HEIGHT` above the view, which follows the
+/// camera every frame. Once given off, particles stay where they are in the world, so panning
+/// doesn't drag the rain along with the view, and the sky over the new view is already filling up
+/// as it comes on screen.
+///
+/// It should be added with `SystemOrder::new().before(id)`, where `id` is the `WorldRender`'s
+/// `SystemID`, and drawn by giving the same `Particles` to `WorldRender::set_particles`.
+pub struct WeatherSystem {
+    weather:   Rc<RefCell<Weather>>,
+    camera:    Rc<RefCell<Camera>>,
+    particles: Rc<RefCell<Particles>>,
+    lights:    Option<Rc<RefCell<Lights>>>,
+
+    rain: EmitterDesc,
+    snow: EmitterDesc,
+
+    height:    Meters,
+    rain_tint: [f32; 3],
+    snow_tint: [f32; 3],
+
+    // The kind of weather being drawn, and its emitter (unless it's clear).
+    kind:    WeatherKind,
+    emitter: Option<EmitterID>,
+
+    rng: Rng,
+}
+
+impl WeatherSystem {
+    /// Create a system which draws `weather` into `particles` over the view of `camera`.
+    ///
+    /// Rain and snow are given off as described by `rain` and `snow`, at their `rate` when the
+    /// intensity is 1 and proportionally less below that. Their `spread` along x and y is replaced
+    /// to cover the view, and they should have a `floor` and enough lifetime to reach it. Particles
+    /// are randomized with `rng`.
+    pub fn new(weather: Rc<RefCell<Weather>>, camera: Rc<RefCell<Camera>>,
+               particles: Rc<RefCell<Particles>>, rain: EmitterDesc, snow: EmitterDesc, rng: Rng)
+        -> WeatherSystem {
+
+        WeatherSystem {
+            weather:   weather,
+            camera:    camera,
+            particles: particles,
+            lights:    None,
+            rain:      rain,
+            snow:      snow,
+            height:    Meters(WEATHER_HEIGHT),
+            rain_tint: [0.7, 0.75, 0.85],
+            snow_tint: [1.0, 1.0, 1.15],
+            kind:      WeatherKind::Clear,
+            emitter:   None,
+            rng:       rng,
         }
     }
 
+    /// A description of rain drawn with sprite `frame` of `sheet_id`: fast, slightly slanted
+    /// streaks which die as they hit the ground.
+    pub fn default_rain(sheet_id: asset::AssetID, frame: usize) -> EmitterDesc {
+        let mut desc = EmitterDesc::new(sheet_id, frame);
+
+        desc.rate          = 400.0;
+        desc.lifetime      = (time::Duration::sec(2), time::Duration::sec(2));
+        desc.velocity_min  = vec3!(Meters ; 1.0, 0.0, -14.0);
+        desc.velocity_max  = vec3!(Meters ; 1.5, 0.5, -12.0);
+        desc.floor         = Some(Meters(0.0));
+        desc.max_particles = 2048;
+
+        desc
+    }
+
+    /// A description of snow drawn with sprite `frame` of `sheet_id`: slow flakes which drift
+    /// about as they fall, and die as they reach the ground.
+    pub fn default_snow(sheet_id: asset::AssetID, frame: usize) -> EmitterDesc {
+        let mut desc = EmitterDesc::new(sheet_id, frame);
+
+        desc.rate          = 150.0;
+        desc.lifetime      = (time::Duration::sec(12), time::Duration::sec(12));
+        desc.velocity_min  = vec3!(Meters ; -0.5, -0.5, -1.2);
+        desc.velocity_max  = vec3!(Meters ; 0.5, 0.5, -0.8);
+        desc.floor         = Some(Meters(0.0));
+        desc.max_particles = 2048;
+
+        desc
+    }
+
+    /// Tint the world according to the weather by setting `Lights::tint` on `lights`, or leave
+    /// the light alone with `None` (the default).
+    pub fn set_lights(&mut self, lights: Option<Rc<RefCell<Lights>>>) {
+        if lights.is_none() {
+            if let Some(ref lights) = self.lights {
+                lights.borrow_mut().tint = [1.0, 1.0, 1.0];
+            }
+        }
+
+        self.lights = lights;
+    }
+
+    /// Give off particles `height` above the ground.
+    pub fn set_height(&mut self, height: Meters) {
+        self.height = height;
+    }
+
+    /// Tint the world with `rain` and `snow` when they fall at intensity 1. Lighter weather tints
+    /// it proportionally less.
+    pub fn set_tints(&mut self, rain: [f32; 3], snow: [f32; 3]) {
+        self.rain_tint = rain;
+        self.snow_tint = snow;
+    }
+
+    // The center and half-size of the region above the ground which covers the view, at the
+    // height particles are given off.
+    fn area(&self, camera: &Camera) -> (math::Vec3<Meters>, math::Vec2<Meters>) {
+        let view = camera.view_resolution();
+        let (hx, hy) = (view.x.0 / 2.0, view.y.0 / 2.0);
+
+        // The particles are seen as they fall, so the region has to cover what's seen at both
+        // ends of their fall.
+        let mut corners = vec![];
+
+        for &z in [Meters(0.0), self.height].iter() {
+            for &(x, y) in [(-hx, -hy), (hx, -hy), (hx, hy), (-hx, hy)].iter() {
+                corners.push(camera.screen_to_game(vec2!(Pixels(x), Pixels(y)), z));
+            }
+        }
+
+        let (mut min_x, mut min_y) = (corners[0].x.0, corners[0].y.0);
+        let (mut max_x, mut max_y) = (min_x, min_y);
+
+        for c in corners.iter() {
+            min_x = min_x.min(c.x.0);
+            min_y = min_y.min(c.y.0);
+            max_x = max_x.max(c.x.0);
+            max_y = max_y.max(c.y.0);
+        }
+
+        let center = vec3!(Meters ; (min_x + max_x) / 2.0, (min_y + max_y) / 2.0, self.height.0);
+        let half   = vec2!(Meters ; (max_x - min_x) / 2.0, (max_y - min_y) / 2.0);
+
+        (center, half)
+    }
+}
+
+impl EngineSystem for WeatherSystem {
+    /// Follow the camera, and catch up with any change to the weather.
+    fn update(&mut self, _now: time::Duration) {
+        let weather = *self.weather.borrow();
+
+        let camera = self.camera.clone();
+        let camera = camera.borrow();
+
+        let (center, half) = self.area(&camera);
+
+        let mut particles = self.particles.borrow_mut();
+
+        if weather.kind != self.kind {
+            // Let the old weather's particles finish falling, rather than vanishing mid-air.
+            if let Some(id) = self.emitter.take() {
+                if let Some(emitter) = particles.get_mut(id) {
+                    emitter.set_active(false);
+                }
+            }
+
+            let desc = match weather.kind {
+                WeatherKind::Clear => None,
+                WeatherKind::Rain  => Some(self.rain.clone()),
+                WeatherKind::Snow  => Some(self.snow.clone()),
+            };
+
+            if let Some(desc) = desc {
+                let rng = self.rng.fork();
+
+                self.emitter = Some(particles.add(Emitter::new(desc, center, rng)));
+            }
+
+            self.kind = weather.kind;
+        }
+
+        let (base, tint) = match weather.kind {
+            WeatherKind::Clear => (None, [1.0, 1.0, 1.0]),
+            WeatherKind::Rain  => (Some(&self.rain), self.rain_tint),
+            WeatherKind::Snow  => (Some(&self.snow), self.snow_tint),
+        };
+
+        if let (Some(base), Some(id)) = (base, self.emitter) {
+            if let Some(emitter) = particles.get_mut(id) {
+                let mut desc = base.clone();
+
+                desc.rate     = base.rate * weather.intensity;
+                desc.spread.x = half.x;
+                desc.spread.y = half.y;
+
+                emitter.set_desc(desc);
+                emitter.set_position(center);
+            }
+        }
+
+        if let Some(ref lights) = self.lights {
+            let f = weather.amount();
+
+            lights.borrow_mut().tint = [1.0 + (tint[0] - 1.0) * f,
+                                        1.0 + (tint[1] - 1.0) * f,
+                                        1.0 + (tint[2] - 1.0) * f];
+        }
+    }
+
+    fn process_entity<V: EngineView>(&mut self, _now: time::Duration, _entity: &mut V) {}
+
+    fn name(&self) -> &'static str { "weather" }
+
+    fn runs_while_paused(&self) -> bool { true }
 }
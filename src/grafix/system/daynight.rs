@@ -0,0 +1,208 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! The day/night cycle.
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use entity::{EngineSystem, EngineView};
+use time;
+
+use super::Lights;
+
+/// The ambient light at one time of day.
+#[derive(Clone,Copy,Debug)]
+pub struct AmbientKey {
+    /// The time of day, as a fraction of the day in `[0, 1)`, starting at midnight.
+    pub at: f32,
+
+    /// The light's red, green and blue, each in `[0, 1]`.
+    pub color: [f32; 3],
+
+    /// How bright the light is. The color is multiplied by this.
+    pub intensity: f32,
+}
+
+/// The time of day and the ambient light which goes with it, which follows a curve of `AmbientKey`s
+/// as game time passes. This is shared between the `DayNightCycle` which advances it and anything
+/// which wants to know the time, e.g. to have guards light their torches at night.
+pub struct DayNight {
+    day_length: time::Duration,
+
+    // How far into the day it was at game time zero, less than `day_length`.
+    phase: time::Duration,
+
+    // Sorted by `at`.
+    keys: Vec<AmbientKey>,
+
+    night_below: f32,
+
+    time_of_day: f32,
+    color:       [f32; 3],
+    intensity:   f32,
+}
+
+impl DayNight {
+    /// Create a cycle whose days last `day_length` of game time (which mustn't be zero), starting
+    /// at midnight at game time zero. The default curve is a dim blue night, orange dawn and dusk,
+    /// and a white noon, and it's night whenever the intensity is below 0.5.
+    pub fn new(day_length: time::Duration) -> DayNight {
+        let mut day_night = DayNight {
+            day_length:  day_length,
+            phase:       time::Duration::usec(0),
+            keys:        vec![],
+            night_below: 0.5,
+            time_of_day: 0.0,
+            color:       [1.0, 1.0, 1.0],
+            intensity:   1.0,
+        };
+
+        day_night.set_curve(vec![
+            AmbientKey { at: 0.0,  color: [0.3, 0.35, 0.6], intensity: 0.35 },
+            AmbientKey { at: 0.25, color: [1.0, 0.7,  0.5], intensity: 0.7  },
+            AmbientKey { at: 0.5,  color: [1.0, 1.0,  1.0], intensity: 1.0  },
+            AmbientKey { at: 0.75, color: [1.0, 0.6,  0.4], intensity: 0.7  },
+        ]);
+
+        day_night
+    }
+
+    /// Replace the curve which the ambient light follows. The light is interpolated linearly
+    /// between keys, wrapping around from the last key of the day to the first. An empty curve
+    /// leaves sprites as they appear in their sheets.
+    pub fn set_curve(&mut self, mut keys: Vec<AmbientKey>) {
+        keys.sort_by(|a, b| a.at.partial_cmp(&b.at).unwrap_or(Ordering::Equal));
+
+        self.keys = keys;
+        self.sample();
+    }
+
+    /// Make it `time_of_day` (a fraction of the day, starting at midnight) at game time `now`.
+    pub fn set_time_of_day(&mut self, now: time::Duration, time_of_day: f32) {
+        let day  = self.day_length;
+        let into = day * (time_of_day.max(0.0).min(1.0) as f64);
+
+        // `Duration` can't be negative, so add a whole day before taking away `now`.
+        self.phase = (into % day + day - now % day) % day;
+
+        self.update(now);
+    }
+
+    /// Consider it night whenever the ambient intensity is below `intensity`.
+    pub fn set_night_threshold(&mut self, intensity: f32) {
+        self.night_below = intensity;
+    }
+
+    /// Advance to game time `now`.
+    pub fn update(&mut self, now: time::Duration) {
+        let since = (now % self.day_length + self.phase) % self.day_length;
+
+        self.time_of_day = (since / self.day_length) as f32;
+        self.sample();
+    }
+
+    /// The time of day as a fraction of the day, starting at midnight.
+    pub fn time_of_day(&self) -> f32 {
+        self.time_of_day
+    }
+
+    /// True if the ambient light is dim enough to count as night (see `set_night_threshold`).
+    pub fn is_night(&self) -> bool {
+        self.intensity < self.night_below
+    }
+
+    /// The color of the ambient light.
+    pub fn color(&self) -> [f32; 3] {
+        self.color
+    }
+
+    /// The intensity of the ambient light.
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    /// The ambient light, i.e. its color times its intensity.
+    pub fn ambient(&self) -> [f32; 3] {
+        let (c, i) = (self.color, self.intensity);
+
+        [c[0] * i, c[1] * i, c[2] * i]
+    }
+
+    // Find the ambient light at `time_of_day` on the curve.
+    fn sample(&mut self) {
+        let t = self.time_of_day;
+
+        let (a, b) = match self.keys.len() {
+            0 => {
+                self.color     = [1.0, 1.0, 1.0];
+                self.intensity = 1.0;
+                return
+            },
+
+            n => match self.keys.iter().position(|k| k.at > t) {
+                Some(0) | None => (self.keys[n - 1], self.keys[0]),
+                Some(i)        => (self.keys[i - 1], self.keys[i]),
+            },
+        };
+
+        // Measure around the end of the day when wrapping from the last key to the first.
+        let span  = (b.at - a.at + 1.0) % 1.0;
+        let since = (t - a.at + 1.0) % 1.0;
+        let f     = if span > 0.0 { since / span } else { 0.0 };
+
+        let lerp = |x: f32, y: f32| x + (y - x) * f;
+
+        self.color     = [lerp(a.color[0], b.color[0]),
+                          lerp(a.color[1], b.color[1]),
+                          lerp(a.color[2], b.color[2])];
+        self.intensity = lerp(a.intensity, b.intensity);
+    }
+}
+
+/// A `System` which advances a shared `DayNight` with game time, and lights the world with its
+/// ambient light by setting `Lights::ambient`.
+///
+/// It doesn't run while the `Manager` is paused, so the sun stands still along with everything
+/// else.
+pub struct DayNightCycle {
+    day_night: Rc<RefCell<DayNight>>,
+    lights:    Rc<RefCell<Lights>>,
+}
+
+impl DayNightCycle {
+    /// Create a system which advances `day_night` and sets the ambient light of `lights`.
+    pub fn new(day_night: Rc<RefCell<DayNight>>, lights: Rc<RefCell<Lights>>) -> DayNightCycle {
+        DayNightCycle { day_night: day_night, lights: lights }
+    }
+}
+
+impl EngineSystem for DayNightCycle {
+    /// Advance the time of day.
+    fn update(&mut self, now: time::Duration) {
+        let mut day_night = self.day_night.borrow_mut();
+
+        day_night.update(now);
+
+        self.lights.borrow_mut().ambient = day_night.ambient();
+    }
+
+    fn process_entity<V: EngineView>(&mut self, _now: time::Duration, _entity: &mut V) {}
+
+    fn name(&self) -> &'static str { "day_night" }
+}
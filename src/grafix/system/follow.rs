@@ -0,0 +1,230 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Moving the camera to keep an entity in view.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use entity::sim as entity;
+use entity::{EngineSystem, EngineView};
+use grafix::camera::Camera;
+use math;
+use time;
+use units::*;
+
+/// How `CameraFollow` closes the distance between the camera and where it wants to be.
+#[derive(Clone,Copy,Debug)]
+pub enum Smoothing {
+    /// Jump straight to the target every step.
+    None,
+
+    /// Cover half of the remaining distance every `half_life`. The camera slows as it arrives,
+    /// but starts moving abruptly.
+    Exponential {
+        /// The time it takes to halve the distance to the target.
+        half_life: time::Duration,
+    },
+
+    /// A critically damped spring, which both starts and stops smoothly and never overshoots.
+    Spring {
+        /// The spring's angular frequency, in radians per second. Larger is stiffer.
+        frequency: f32,
+    },
+}
+
+/// A `System` which moves a shared `Camera` so that it follows an entity's `WorldLocation`.
+///
+/// The camera only moves once the entity leaves the dead zone, a rectangle around the middle of
+/// the screen, and it can lead a moving entity by looking ahead along its velocity. Movement is
+/// along the screen's axes, so the camera keeps its height.
+///
+/// Like `WorldRender`, this uses where the entity was at the previous step, so it should be added
+/// with `SystemOrder::new().before(id)`, where `id` is the `WorldRender`'s `SystemID`, to move the
+/// camera before each frame is drawn.
+pub struct CameraFollow {
+    camera: Rc<RefCell<Camera>>,
+    target: Option<entity::EntityID>,
+
+    smoothing:  Smoothing,
+    dead_zone:  math::Vec2<Meters>,
+    look_ahead: time::Duration,
+
+    // The target's last two positions, and when they were seen.
+    pos:  Option<(time::Duration, math::Vec3<Meters>)>,
+    prev: Option<(time::Duration, math::Vec3<Meters>)>,
+
+    // The spring's velocity, in camera space meters per second.
+    velocity: (f32, f32),
+
+    last: Option<time::Duration>,
+}
+
+impl CameraFollow {
+    /// Create a controller for `camera` which doesn't follow anything yet. It starts with no
+    /// smoothing, no dead zone, and no look-ahead.
+    pub fn new(camera: Rc<RefCell<Camera>>) -> CameraFollow {
+        CameraFollow {
+            camera:     camera,
+            target:     None,
+            smoothing:  Smoothing::None,
+            dead_zone:  vec2!(Meters ; 0.0, 0.0),
+            look_ahead: time::Duration::usec(0),
+            pos:        None,
+            prev:       None,
+            velocity:   (0.0, 0.0),
+            last:       None,
+        }
+    }
+
+    /// Follow `target`, or stop following with `None`. The camera stays where it is until the new
+    /// target has been seen.
+    pub fn set_target(&mut self, target: Option<entity::EntityID>) {
+        if target != self.target {
+            self.target   = target;
+            self.pos      = None;
+            self.prev     = None;
+            self.velocity = (0.0, 0.0);
+        }
+    }
+
+    /// The entity being followed.
+    pub fn target(&self) -> Option<entity::EntityID> {
+        self.target
+    }
+
+    /// Change how the camera approaches the target.
+    pub fn set_smoothing(&mut self, smoothing: Smoothing) {
+        self.smoothing = smoothing;
+        self.velocity  = (0.0, 0.0);
+    }
+
+    /// Let the target move up to `half_width` left or right and `half_height` up or down from
+    /// the middle of the screen (in camera space) before the camera moves.
+    pub fn set_dead_zone(&mut self, half_width: Meters, half_height: Meters) {
+        self.dead_zone = vec2!(half_width, half_height);
+    }
+
+    /// Aim at where the target will be after `look_ahead` if it keeps its current velocity.
+    pub fn set_look_ahead(&mut self, look_ahead: time::Duration) {
+        self.look_ahead = look_ahead;
+    }
+
+    /// Center the camera on the target immediately, ignoring smoothing and the dead zone.
+    pub fn snap(&mut self) {
+        if let Some((_, pos)) = self.pos {
+            let mut camera = self.camera.borrow_mut();
+            let goal       = camera.game_to_camera(pos);
+
+            // Effects such as screen shake aren't for following.
+            let (dx, dy) = (goal.x + camera.offset.x, goal.y + camera.offset.y);
+
+            camera.pan(dx, dy);
+        }
+
+        self.velocity = (0.0, 0.0);
+    }
+
+    // Where the camera should be looking, in game space.
+    fn goal(&self) -> Option<math::Vec3<Meters>> {
+        let (t1, p1) = match self.pos { Some(s) => s, None => return None };
+
+        let (t0, p0) = match self.prev {
+            Some((t0, p0)) if t1 > t0 => (t0, p0),
+            _                         => return Some(p1),
+        };
+
+        let ahead = (self.look_ahead / (t1 - t0)) as f32;
+
+        Some(p1 + (p1 - p0).scaled(Meters(ahead)))
+    }
+}
+
+// How far `d` sticks out of `[-half, half]`.
+fn outside(d: Meters, half: Meters) -> Meters {
+    if d > half {
+        d - half
+    } else if d < -half {
+        d + half
+    } else {
+        Meters(0.0)
+    }
+}
+
+impl EngineSystem for CameraFollow {
+    /// Move the camera towards the target.
+    fn update(&mut self, now: time::Duration) {
+        let dt = match self.last {
+            Some(last) if now > last => (now - last).as_usec() as f32 / 1_000_000.0,
+            _                        => 0.0,
+        };
+
+        self.last = Some(now);
+
+        let goal = match self.goal() { Some(g) => g, None => return };
+
+        let mut camera = self.camera.borrow_mut();
+
+        // Measure from where the camera is, not where effects have displaced the view to.
+        let rel = camera.game_to_camera(goal);
+        let ex  = outside(rel.x + camera.offset.x, self.dead_zone.x).0;
+        let ey  = outside(rel.y + camera.offset.y, self.dead_zone.y).0;
+
+        let (dx, dy) = match self.smoothing {
+            Smoothing::None => (ex, ey),
+
+            Smoothing::Exponential { half_life } => {
+                let hl = half_life.as_usec() as f32 / 1_000_000.0;
+                let k  = if hl > 0.0 { 1.0 - 0.5f32.powf(dt / hl) } else { 1.0 };
+
+                (ex * k, ey * k)
+            },
+
+            Smoothing::Spring { frequency } => {
+                // Step the spring in closed form, with the camera `-e` away from the goal.
+                let w   = frequency;
+                let d   = (-w * dt).exp();
+                let (vx, vy) = self.velocity;
+
+                let tx = (vx - w * ex) * dt;
+                let ty = (vy - w * ey) * dt;
+
+                self.velocity = ((vx - w * tx) * d, (vy - w * ty) * d);
+
+                (ex + (-ex + tx) * d, ey + (-ey + ty) * d)
+            },
+        };
+
+        camera.pan(Meters(dx), Meters(dy));
+    }
+
+    /// Remember where the target is.
+    fn process_entity<V: EngineView>(&mut self, now: time::Duration, entity: &mut V) {
+        if Some(entity.id()) != self.target {
+            return
+        }
+
+        if let Some(loc) = entity.world_location() {
+            self.prev = self.pos;
+            self.pos  = Some((now, loc.bounds.center));
+        }
+    }
+
+    fn name(&self) -> &'static str { "camera_follow" }
+
+    fn runs_while_paused(&self) -> bool { true }
+}
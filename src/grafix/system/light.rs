@@ -0,0 +1,112 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Gathering the lights in the world for the renderer.
+
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+use entity::component::{Light, LightKind};
+use entity::{EngineSystem, EngineView};
+use math;
+use time;
+use units::*;
+
+/// A light which was on an entity during the last step.
+#[derive(Clone,Copy,Debug)]
+pub struct PlacedLight {
+    /// The light.
+    pub light: Light,
+
+    /// The center of the entity's `WorldLocation`, for point lights.
+    pub center: math::Vec3<Meters>,
+}
+
+/// The lights in the world, shared between the `LightGather` which fills it and the `WorldRender`
+/// which draws with it (see `WorldRender::set_lights`).
+pub struct Lights {
+    /// The light which reaches every sprite, regardless of what other lights are nearby. White (the
+    /// default) leaves sprites as they appear in their sheets.
+    pub ambient: [f32; 3],
+
+    /// A color which the ambient light is multiplied by, set apart from `ambient` so that the
+    /// weather can tint the ground (see `WeatherSystem`) while a `DayNightCycle` sets the ambient
+    /// light. White (the default) leaves the ambient light as it is.
+    pub tint: [f32; 3],
+
+    lights: Vec<PlacedLight>,
+}
+
+impl Lights {
+    /// Create a set of lights with white ambient light and no tint, and nothing else until it's
+    /// filled.
+    pub fn new() -> Lights {
+        Lights { ambient: [1.0, 1.0, 1.0], tint: [1.0, 1.0, 1.0], lights: vec![] }
+    }
+
+    /// The lights gathered during the last step.
+    pub fn lights(&self) -> &[PlacedLight] {
+        &self.lights
+    }
+}
+
+/// A `System` which collects every entity's `Light` into a shared `Lights`.
+///
+/// Like `WorldRender`, this hands over what it saw at the previous step, so it should be added
+/// with `SystemOrder::new().before(id)`, where `id` is the `WorldRender`'s `SystemID`, to have the
+/// lights ready before each frame is drawn.
+pub struct LightGather {
+    lights:  Rc<RefCell<Lights>>,
+    pending: Vec<PlacedLight>,
+}
+
+impl LightGather {
+    /// Create a system which fills `lights`.
+    pub fn new(lights: Rc<RefCell<Lights>>) -> LightGather {
+        LightGather { lights: lights, pending: vec![] }
+    }
+}
+
+impl EngineSystem for LightGather {
+    /// Publish the lights gathered last step.
+    fn update(&mut self, _now: time::Duration) {
+        let mut lights = self.lights.borrow_mut();
+
+        mem::swap(&mut lights.lights, &mut self.pending);
+        self.pending.clear();
+    }
+
+    fn process_entity<V: EngineView>(&mut self, _now: time::Duration, entity: &mut V) {
+        let light = match entity.light() {
+            Some(light) => *light,
+            None        => return,
+        };
+
+        let center = match (light.kind, entity.world_location()) {
+            (_, Some(loc))                        => loc.bounds.center,
+            (LightKind::Directional { .. }, None) => vec3!(Meters ; 0.0, 0.0, 0.0),
+            (LightKind::Point { .. }, None)       => return,
+        };
+
+        self.pending.push(PlacedLight { light: light, center: center });
+    }
+
+    fn name(&self) -> &'static str { "light_gather" }
+
+    fn runs_while_paused(&self) -> bool { true }
+}
@@ -17,3 +17,15 @@
 
 #[cfg(feature = "client")] mod client;
 #[cfg(feature = "client")] pub use self::client::*;
+
+mod follow;
+pub use self::follow::*;
+
+mod light;
+pub use self::light::*;
+
+mod daynight;
+pub use self::daynight::*;
+
+mod weather;
+pub use self::weather::*;
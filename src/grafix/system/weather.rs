@@ -0,0 +1,148 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! The weather, as decided by the server.
+//!
+//! The server owns the weather, and sends `Weather::to_wire` to every client whenever it changes
+//! (and to each client as it joins). Each client restores it with `Weather::from_wire` into the
+//! `Weather` shared with its `WeatherSystem`, which draws it, so every client sees the same rain at
+//! the same intensity.
+
+use grafix::wire;
+
+/// The reasons a `Weather` can't be restored from its FlatBuffer representation.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum WeatherError {
+    /// The intensity was infinite or NaN.
+    NotFinite,
+
+    /// The kind was none of the values written by `Weather::to_wire`.
+    UnknownKind(u8),
+
+    /// The intensity was outside `[0, 1]`.
+    OutOfRange,
+}
+
+/// What's falling from the sky.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum WeatherKind {
+    /// Nothing.
+    Clear,
+
+    /// Rain, which darkens the ground as it gets wet.
+    Rain,
+
+    /// Snow, which whitens the ground.
+    Snow,
+}
+
+/// The weather everywhere in the world.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Weather {
+    /// What's falling.
+    pub kind: WeatherKind,
+
+    /// How heavily it's falling, from 0 (not at all) to 1 (as heavily as the `WeatherSystem` is
+    /// set up to draw).
+    pub intensity: f32,
+}
+
+impl Weather {
+    /// Clear skies.
+    pub fn clear() -> Weather {
+        Weather { kind: WeatherKind::Clear, intensity: 0.0 }
+    }
+
+    /// `kind` falling at `intensity`, clamped to `[0, 1]`.
+    pub fn new(kind: WeatherKind, intensity: f32) -> Weather {
+        Weather { kind: kind, intensity: intensity.max(0.0).min(1.0) }
+    }
+
+    /// Restore the weather from its FlatBuffer representation.
+    pub fn from_wire(w: &wire::Weather) -> Result<Weather, WeatherError> {
+        let intensity = w.intensity();
+
+        if !intensity.is_finite() {
+            return Err(WeatherError::NotFinite);
+        }
+
+        if !(0.0 <= intensity && intensity <= 1.0) {
+            return Err(WeatherError::OutOfRange);
+        }
+
+        let kind = match w.kind() {
+            0 => WeatherKind::Clear,
+            1 => WeatherKind::Rain,
+            2 => WeatherKind::Snow,
+            n => return Err(WeatherError::UnknownKind(n)),
+        };
+
+        Ok(Weather { kind: kind, intensity: intensity })
+    }
+
+    /// Convert to FlatBuffer representation, to send to clients.
+    pub fn to_wire(&self) -> wire::Weather {
+        let kind = match self.kind {
+            WeatherKind::Clear => 0,
+            WeatherKind::Rain  => 1,
+            WeatherKind::Snow  => 2,
+        };
+
+        wire::Weather::new(self.intensity, kind)
+    }
+
+    /// How much the weather shows, i.e. the intensity, or 0 for clear skies.
+    pub fn amount(&self) -> f32 {
+        match self.kind {
+            WeatherKind::Clear => 0.0,
+            _                  => self.intensity,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32;
+
+    use grafix::system::{Weather, WeatherError, WeatherKind};
+    use grafix::wire;
+
+    #[test]
+    fn weather_round_trip() {
+        let cases = [Weather::clear(),
+                     Weather::new(WeatherKind::Rain, 0.5),
+                     Weather::new(WeatherKind::Snow, 1.0)];
+
+        for weather in cases.iter() {
+            assert_eq!(Weather::from_wire(&weather.to_wire()), Ok(*weather));
+        }
+    }
+
+    #[test]
+    fn weather_invalid() {
+        let cases = [
+            (wire::Weather::new(f32::NAN, 1),      WeatherError::NotFinite),
+            (wire::Weather::new(f32::INFINITY, 1), WeatherError::NotFinite),
+            (wire::Weather::new(-0.5, 1),          WeatherError::OutOfRange),
+            (wire::Weather::new(1.5, 2),           WeatherError::OutOfRange),
+            (wire::Weather::new(0.5, 3),           WeatherError::UnknownKind(3)),
+        ];
+
+        for &(ref w, err) in cases.iter() {
+            assert_eq!(Weather::from_wire(w), Err(err));
+        }
+    }
+}
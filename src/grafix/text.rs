@@ -0,0 +1,150 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Bitmap text, drawn through the sprite `Batcher`.
+//!
+//! A font is an ordinary sprite sheet with one glyph per sprite, in character order, so a glyph is
+//! just a `DrawReq`. Text shares the sprite vertex format, vertex buffer and draw calls with
+//! everything else in the frame: a HUD of icons and labels costs one upload, and one draw call per
+//! sheet and layer, rather than a second pipeline for text.
+//!
+//! Fonts are monospaced. Each glyph is placed with its sprite's origin on the pen position, which
+//! starts at the origin of the first glyph and moves `advance` pixels to the right per character.
+
+use std::cmp;
+
+use asset::AssetID;
+use grafix::camera::Camera;
+use grafix::sprite::{self, Batcher, Color, DrawReq};
+use math;
+use units::*;
+
+/// A monospaced bitmap font, made from a sprite sheet.
+#[derive(Clone,Copy,Debug)]
+pub struct Font {
+    /// The sheet holding the glyphs.
+    pub sheet_id: AssetID,
+
+    /// The character drawn by sprite 0 of the sheet. The following sprites draw the characters
+    /// which follow it (so a sheet of printable ASCII starts at `' '`).
+    pub first: char,
+
+    /// The number of glyphs in the sheet.
+    pub count: usize,
+
+    /// How far the pen moves right after each character, in pixels.
+    pub advance: Pixels,
+
+    /// How far the pen moves down for each new line, in pixels.
+    pub line_height: Pixels,
+}
+
+/// How to draw a piece of text.
+#[derive(Clone,Copy,Debug)]
+pub struct Style {
+    /// The color of the text (a tint of the glyphs, which are usually drawn white).
+    pub color: Color,
+
+    /// The size to draw the text at, relative to the font's.
+    pub scale: f32,
+
+    /// The layer to draw the text in.
+    pub layer: u8,
+}
+
+impl Style {
+    /// White text at its usual size in `sprite::LAYER_UI`.
+    pub fn ui() -> Style {
+        Style { color: sprite::WHITE, scale: 1.0, layer: sprite::LAYER_UI }
+    }
+}
+
+impl Font {
+    /// The sprite which draws `c`, or `None` if the font has no glyph for it.
+    pub fn glyph(&self, c: char) -> Option<usize> {
+        let idx = (c as u32).wrapping_sub(self.first as u32) as usize;
+
+        if idx < self.count { Some(idx) } else { None }
+    }
+
+    /// The width of the longest line of `text` and the height of all of its lines, in pixels, at
+    /// `scale` times the font's size.
+    pub fn measure(&self, text: &str, scale: f32) -> math::Vec2<Pixels> {
+        let mut widest = 0;
+        let mut lines  = 0;
+
+        for line in text.lines() {
+            widest = cmp::max(widest, line.chars().count());
+            lines += 1;
+        }
+
+        vec2!(Pixels(self.advance.0 * scale * widest as f32),
+              Pixels(self.line_height.0 * scale * lines as f32))
+    }
+
+    /// Draw `text` with the origin of its first glyph at screen coordinate `scr` (measured from
+    /// the center of the screen, as returned by `Camera::camera_to_screen`). The glyphs are placed
+    /// in the world at the height of the ground, so this suits layers which don't test depth,
+    /// such as `sprite::LAYER_UI` (see `Batcher::set_depth_test`).
+    pub fn draw_screen(&self, batcher: &mut Batcher, cam: &Camera, text: &str,
+                       scr: math::Vec2<Pixels>, style: &Style) {
+        self.draw(batcher, cam, text, scr, Meters(0.0), style)
+    }
+
+    /// Draw `text` with the origin of its first glyph over the point `loc` in the world, e.g. for
+    /// a name above a unit. The text keeps the same size on screen however the camera moves.
+    pub fn draw_at(&self, batcher: &mut Batcher, cam: &Camera, text: &str,
+                   loc: math::Vec3<Meters>, style: &Style) {
+        let (scr, _) = cam.camera_to_screen(cam.game_to_camera(loc));
+
+        self.draw(batcher, cam, text, scr, loc.z, style)
+    }
+
+    // Register a `DrawReq` for each glyph of `text`, starting at `scr`, with each one placed in the
+    // world at height `z`.
+    fn draw(&self, batcher: &mut Batcher, cam: &Camera, text: &str, scr: math::Vec2<Pixels>,
+            z: Meters, style: &Style) {
+
+        let advance     = self.advance.0 * style.scale;
+        let line_height = self.line_height.0 * style.scale;
+
+        for (row, line) in text.lines().enumerate() {
+            for (col, c) in line.chars().enumerate() {
+                let idx = match self.glyph(c) {
+                    Some(idx) => idx,
+                    None      => continue,
+                };
+
+                let pen = vec2!(Pixels(scr.x.0 + advance * col as f32),
+                                Pixels(scr.y.0 - line_height * row as f32));
+
+                batcher.register(DrawReq {
+                    sheet_id:   self.sheet_id,
+                    sprite_idx: idx,
+                    game_loc:   cam.screen_to_game(pen, z),
+                    tint:       style.color,
+                    flip_x:     false,
+                    scale:      style.scale,
+                    rotation:   0.0,
+                    highlight:  None,
+                    layer:      style.layer,
+                    material:   sprite::DEFAULT_MATERIAL,
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,313 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! The engine's input event stream, with recording and replay.
+//!
+//! Platform events (keys, buttons, sticks, the mouse) are translated into `Event`s in terms of
+//! action names, the same ones used for the `[keybinds]` table in the engine config, and pushed
+//! into an `Input`. The game loop drains them with `Input::poll`. A `Keymap` does the translation
+//! for keys, using the bindings from the config.
+//!
+//! An `Input` can record everything pushed into it, with timestamps relative to when recording
+//! started. A `Recording` can be saved to a small text file and later replayed into an `Input`,
+//! which then ignores live events and hands out the recorded ones at the same offsets from when the
+//! replay started. This makes it possible to script smoke tests of menus and gameplay: record a
+//! session by hand once, and replay it against each new build.
+//!
+//! The text format has one event per line, as the offset in microseconds followed by the event:
+//!
+//! ```text
+//! 1500000 press ui_down
+//! 1620000 release ui_down
+//! 2000000 axis move_x -0.75
+//! 2500000 pointer 120 -48
+//! ```
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+#[cfg(feature = "config")] use config::Config;
+use lines;
+use math;
+use time::Duration;
+use units::*;
+
+/// Something the player did.
+#[derive(Clone,Debug,PartialEq)]
+pub enum Event {
+    /// The button bound to an action was pressed.
+    Pressed(String),
+
+    /// The button bound to an action was released.
+    Released(String),
+
+    /// An analog input bound to an action moved to a new value, usually in `[-1, 1]`.
+    Axis(String, f32),
+
+    /// The pointer moved to a point on the screen.
+    Pointer(math::Vec2<Pixels>),
+}
+
+/// A sequence of events, each with its offset from the start of the recording.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Recording {
+    /// The events, in the order they happened.
+    pub events: Vec<(Duration, Event)>,
+}
+
+pub use lines::Error;
+
+impl Recording {
+    /// Create an empty recording.
+    pub fn new() -> Recording {
+        Recording { events: vec![] }
+    }
+
+    /// How long the recording lasts, i.e. the offset of its last event.
+    pub fn len(&self) -> Duration {
+        self.events.last().map_or(Duration::usec(0), |&(at, _)| at)
+    }
+
+    /// Load a recording from a file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Recording, Error> {
+        lines::load(path)
+    }
+
+    /// Save the recording to a file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        lines::save(self, path)
+    }
+}
+
+impl FromStr for Recording {
+    type Err = Error;
+
+    /// Parse a recording from the text format described in the module documentation. Blank lines
+    /// and lines starting with `#` are ignored. Replay depends on the events being in order, so
+    /// they're sorted.
+    fn from_str(text: &str) -> Result<Recording, Error> {
+        lines::parse(text, parse_line).map(|events| Recording { events: events })
+    }
+}
+
+impl fmt::Display for Recording {
+    /// Render the recording in the text format described in the module documentation.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(at, ref ev) in self.events.iter() {
+            try!(match *ev {
+                Event::Pressed(ref name)  => writeln!(f, "{} press {}", at.as_usec(), name),
+                Event::Released(ref name) => writeln!(f, "{} release {}", at.as_usec(), name),
+                Event::Axis(ref name, v)  => writeln!(f, "{} axis {} {}", at.as_usec(), name, v),
+                Event::Pointer(p)         => writeln!(f, "{} pointer {} {}", at.as_usec(), p.x.0,
+                                                      p.y.0),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Option<(Duration, Event)> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    let at = match words.first().and_then(|w| u64::from_str(w).ok()) {
+        Some(us) => Duration::usec(us),
+        None     => return None,
+    };
+
+    let num = |w: &str| f32::from_str(w).ok();
+
+    let ev = match (words.get(1).cloned(), words.len()) {
+        (Some("press"), 3)   => Event::Pressed(From::from(words[2])),
+        (Some("release"), 3) => Event::Released(From::from(words[2])),
+
+        (Some("axis"), 4) => match num(words[3]) {
+            Some(v) => Event::Axis(From::from(words[2]), v),
+            None    => return None,
+        },
+
+        (Some("pointer"), 4) => match (num(words[2]), num(words[3])) {
+            (Some(x), Some(y)) => Event::Pointer(vec2!(Pixels ; x, y)),
+            _                  => return None,
+        },
+
+        _ => return None,
+    };
+
+    Some((at, ev))
+}
+
+/// Translates keys into `Event`s for the actions bound to them. Keys are named as SDL names them
+/// (e.g. "W" or "Left Shift"), and a key may be bound to several actions.
+#[derive(Clone,Debug)]
+pub struct Keymap {
+    // The actions bound to each key.
+    keys: BTreeMap<String, Vec<String>>,
+}
+
+impl Keymap {
+    /// Create a keymap with no bindings.
+    pub fn new() -> Keymap {
+        Keymap { keys: BTreeMap::new() }
+    }
+
+    /// Create a keymap with the bindings in the `[keybinds]` table of `cfg`.
+    #[cfg(feature = "config")]
+    pub fn from_config(cfg: &Config) -> Keymap {
+        let mut map = Keymap::new();
+
+        for (action, key) in cfg.keybinds.iter() {
+            map.bind(action, key);
+        }
+
+        map
+    }
+
+    /// Bind `key` to `action`, in addition to anything it's already bound to.
+    pub fn bind(&mut self, action: &str, key: &str) {
+        let actions = self.keys.entry(From::from(key)).or_insert(vec![]);
+
+        if !actions.iter().any(|a| a == action) {
+            actions.push(From::from(action));
+        }
+    }
+
+    /// Remove every binding of `action`.
+    pub fn unbind(&mut self, action: &str) {
+        for actions in self.keys.values_mut() {
+            actions.retain(|a| a != action);
+        }
+    }
+
+    /// The actions bound to `key`.
+    pub fn actions(&self, key: &str) -> &[String] {
+        match self.keys.get(key) {
+            Some(actions) => &actions[..],
+            None          => &[],
+        }
+    }
+
+    /// The events for `key` being pressed or released: one for each action bound to it.
+    pub fn key(&self, key: &str, pressed: bool) -> Vec<Event> {
+        self.actions(key).iter()
+            .map(|a| if pressed { Event::Pressed(a.clone()) } else { Event::Released(a.clone()) })
+            .collect()
+    }
+
+    /// Translate an SDL event, such as one returned by `Context::pump_events`. Key repeats, and
+    /// events other than keys, produce nothing.
+    #[cfg(feature = "client")]
+    pub fn translate(&self, ev: &::sdl2::event::Event) -> Vec<Event> {
+        use sdl2::event::Event::{KeyDown, KeyUp};
+
+        match *ev {
+            KeyDown { keycode: Some(key), repeat: false, .. } => self.key(&key.name(), true),
+            KeyUp { keycode: Some(key), .. }                  => self.key(&key.name(), false),
+            _                                                 => vec![],
+        }
+    }
+}
+
+enum Mode {
+    Live,
+    Recording(Duration, Recording),
+    Replaying(Duration, VecDeque<(Duration, Event)>),
+}
+
+/// The queue of input events waiting to be handled by the game.
+pub struct Input {
+    queue: VecDeque<Event>,
+    mode:  Mode,
+}
+
+impl Input {
+    /// Create an empty event queue.
+    pub fn new() -> Input {
+        Input { queue: VecDeque::new(), mode: Mode::Live }
+    }
+
+    /// Add a live event which happened at `now`. While a recording is being replayed, live events
+    /// are dropped so that they can't disturb it.
+    pub fn push(&mut self, now: Duration, ev: Event) {
+        match self.mode {
+            Mode::Live                           => {},
+            Mode::Replaying(..)                  => return,
+            Mode::Recording(start, ref mut rec) => rec.events.push((now - start, ev.clone())),
+        }
+
+        self.queue.push_back(ev);
+    }
+
+    /// Move any replayed events which are due by `now` into the queue. This should be called once
+    /// per frame, before draining the queue with `poll`. When the last replayed event is delivered,
+    /// live input resumes.
+    pub fn update(&mut self, now: Duration) {
+        let done = match self.mode {
+            Mode::Replaying(start, ref mut pending) => {
+                while pending.front().map_or(false, |&(at, _)| start + at <= now) {
+                    let (_, ev) = pending.pop_front().unwrap();
+                    self.queue.push_back(ev);
+                }
+
+                pending.is_empty()
+            },
+
+            _ => false,
+        };
+
+        if done {
+            self.mode = Mode::Live;
+        }
+    }
+
+    /// Take the next event off of the queue.
+    pub fn poll(&mut self) -> Option<Event> {
+        self.queue.pop_front()
+    }
+
+    /// Start recording the events pushed from `now` on. Any recording or replay in progress is
+    /// abandoned.
+    pub fn start_recording(&mut self, now: Duration) {
+        self.mode = Mode::Recording(now, Recording::new());
+    }
+
+    /// Stop recording and return what was recorded, or `None` if nothing was being recorded.
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        match ::std::mem::replace(&mut self.mode, Mode::Live) {
+            Mode::Recording(_, rec) => Some(rec),
+            other                   => { self.mode = other; None },
+        }
+    }
+
+    /// Replay `rec` as if it had started at `now`. Events already in the queue are discarded, so
+    /// that the game sees exactly the recorded stream.
+    pub fn replay(&mut self, rec: Recording, now: Duration) {
+        self.queue.clear();
+        self.mode = Mode::Replaying(now, rec.events.into_iter().collect());
+    }
+
+    /// Return true while a recording is being made.
+    pub fn is_recording(&self) -> bool {
+        match self.mode { Mode::Recording(..) => true, _ => false }
+    }
+
+    /// Return true while a recording is being replayed.
+    pub fn is_replaying(&self) -> bool {
+        match self.mode { Mode::Replaying(..) => true, _ => false }
+    }
+}
@@ -18,10 +18,20 @@
 #![warn(missing_docs)]
 
 //! A 2D Isometric Game Engine.
+//!
+//! Everything which isn't behind the `client` or `server` feature (math, time, the entity system in
+//! `entity::sim`, path finding, the network protocol...) is the simulation core shared by both.
+//! The two features only add their own layer on top and never exclude each other, so enabling both
+//! (the `listen` feature) builds a binary which runs a server and a client side by side.
 
 #[cfg(feature = "client")] extern crate gl;
 #[cfg(feature = "client")] extern crate png;
 #[cfg(feature = "client")] extern crate sdl2;
+#[cfg(feature = "client")] extern crate scoped_threadpool;
+
+#[cfg(any(feature = "tiled", feature = "ldtk"))] extern crate rustc_serialize;
+#[cfg(feature = "tiled")] extern crate xml;
+#[cfg(feature = "config")] extern crate toml;
 
 extern crate flatbuffers;
 
@@ -40,21 +50,81 @@ pub mod math;
 /// Units used throughout the engine.
 pub mod units;
 
+/// Log targets for each subsystem, and a logger which filters on them.
+pub mod logging;
+
 /// High-level graphics abstractions built on top of OpenGL.
 #[macro_use] pub mod grafix;
 
+/// Generating `wire` modules from FlatBuffer schemas.
+pub mod codegen;
+
+/// Support code shared by the generated FlatBuffer `wire` modules.
+pub mod wire;
+
 /// Code for managing assets between a server and clients.
 pub mod asset;
 
 /// Abstractions for dealing with time.
 pub mod time;
 
+/// Deterministic random numbers for gameplay code.
+pub mod rng;
+
+/// Writing a dump of engine state when the game panics.
+pub mod crash;
+
+/// Reporting how much memory each subsystem is using.
+pub mod memory;
+
+/// Capturing a frame's events as a Chrome trace.
+pub mod trace;
+
+/// Polling files for changes, so that they can be reloaded while the game runs.
+pub mod watch;
+
+/// Text formats with one timestamped entry per line.
+pub mod lines;
+
+/// Scripted sequences of camera moves, animations, spawns and cues, such as cutscenes.
+pub mod timeline;
+
+/// Engine settings, loaded from and saved to a TOML file.
+#[cfg(feature = "config")] pub mod config;
+
+/// Reusable storage for data which only lives for a single frame.
+pub mod arena;
+
 /// The Entity Component System.
 pub mod entity;
 
+/// Spatial data structures for organizing the game world.
+pub mod scene;
+
+/// Ground elevation.
+pub mod terrain;
+
+/// Grid-based maps built out of sprite sheet tiles.
+pub mod tilemap;
+
+/// Pathfinding over tile grids.
+pub mod path;
+
+/// Resolution-independent layout for HUDs and menus.
+pub mod ui;
+
+/// The input event stream, and recording it for automated tests.
+pub mod input;
+
+/// Networking primitives shared by clients and servers.
+pub mod net;
+
 // Not quite ready for this yet.
 // /// Systems which process entities, and tools for constructing them.
 // pub mod system;
 
+/// Undoable world editing, for building level editors on top of the engine.
+#[cfg(feature = "client")] pub mod editor;
+
 /// Code which is specific to game clients (as opposed to servers).
 #[cfg(feature = "client")] pub mod client;
@@ -0,0 +1,91 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Text formats with one timestamped entry per line, such as input recordings and timelines.
+//!
+//! Each line starts with the entry's offset from the beginning of the file, followed by whatever
+//! the format puts there. Blank lines and lines starting with `#` are ignored. A type in such a
+//! format implements `FromStr` with `parse` and `fmt::Display` one line per entry, and gets
+//! loading and saving from `load` and `save`.
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use time::Duration;
+
+/// An error reading or writing a line-based file.
+#[derive(Debug)]
+pub enum Error {
+    /// The file couldn't be read or written.
+    Io(io::Error),
+
+    /// A line couldn't be parsed. The line number starts at 1.
+    Malformed(usize, String),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+/// Parse each line of `text` with `parse_line`, which returns `None` for a line it doesn't
+/// understand. The entries are sorted by offset; the sort is stable, so entries at the same offset
+/// stay in the order they're written.
+pub fn parse<T, F>(text: &str, parse_line: F) -> Result<Vec<(Duration, T)>, Error>
+    where F: Fn(&str) -> Option<(Duration, T)> {
+
+    let mut entries = vec![];
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match parse_line(line) {
+            Some(entry) => entries.push(entry),
+            None        => return Err(Error::Malformed(i + 1, From::from(line))),
+        }
+    }
+
+    entries.sort_by(|a: &(Duration, T), b| a.0.cmp(&b.0));
+
+    Ok(entries)
+}
+
+/// Load a file in a line-based format.
+pub fn load<T, P>(path: P) -> Result<T, Error> where T: FromStr<Err = Error>, P: AsRef<Path> {
+    let mut text = String::new();
+
+    try!(try!(File::open(path)).read_to_string(&mut text));
+
+    text.parse()
+}
+
+/// Save `value` to a file in its line-based format.
+pub fn save<T: Display, P: AsRef<Path>>(value: &T, path: P) -> Result<(), Error> {
+    let mut file = try!(File::create(path));
+
+    try!(write!(file, "{}", value));
+
+    Ok(())
+}
@@ -0,0 +1,200 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! The engine logs through the `log` crate, using a target for each subsystem (e.g.
+//! `debug!(target: logging::ASSET, ...)`), so that each one's verbosity can be controlled
+//! separately. `init` installs a logger which writes to stderr and honors the levels set with
+//! `set_level` and `parse_filter`; applications which install their own logger can still filter on
+//! the targets.
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+use log::{self, LogLevelFilter, LogMetadata, LogRecord};
+
+/// Target for graphics and rendering.
+pub const GFX: &'static str = "gfx";
+
+/// Target for asset loading, streaming and importing.
+pub const ASSET: &'static str = "asset";
+
+/// Target for the entity component system.
+pub const ECS: &'static str = "ecs";
+
+/// Target for networking.
+pub const NET: &'static str = "net";
+
+/// Target for audio.
+pub const AUDIO: &'static str = "audio";
+
+/// A subsystem whose log messages can be filtered independently.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Category {
+    /// Graphics and rendering.
+    Gfx,
+
+    /// Asset loading, streaming and importing.
+    Asset,
+
+    /// The entity component system.
+    Ecs,
+
+    /// Networking.
+    Net,
+
+    /// Audio.
+    Audio,
+}
+
+const CATEGORIES: [Category; 5] = [
+    Category::Gfx, Category::Asset, Category::Ecs, Category::Net, Category::Audio,
+];
+
+impl Category {
+    /// Every category.
+    pub fn all() -> &'static [Category] {
+        &CATEGORIES
+    }
+
+    /// The log target used for messages in this category.
+    pub fn target(self) -> &'static str {
+        match self {
+            Category::Gfx   => GFX,
+            Category::Asset => ASSET,
+            Category::Ecs   => ECS,
+            Category::Net   => NET,
+            Category::Audio => AUDIO,
+        }
+    }
+
+    /// The category which logs to `target`, if any.
+    pub fn from_target(target: &str) -> Option<Category> {
+        CATEGORIES.iter().find(|c| c.target() == target).cloned()
+    }
+}
+
+// Levels are stored as `LogLevelFilter as usize + 1`, with 0 meaning "not set". A category which
+// isn't set uses the default level, and the default level is `Info` until it is set.
+static DEFAULT: AtomicUsize = ATOMIC_USIZE_INIT;
+static LEVELS:  [AtomicUsize; 5] = [
+    ATOMIC_USIZE_INIT, ATOMIC_USIZE_INIT, ATOMIC_USIZE_INIT, ATOMIC_USIZE_INIT, ATOMIC_USIZE_INIT,
+];
+
+const FILTERS: [LogLevelFilter; 6] = [
+    LogLevelFilter::Off,
+    LogLevelFilter::Error,
+    LogLevelFilter::Warn,
+    LogLevelFilter::Info,
+    LogLevelFilter::Debug,
+    LogLevelFilter::Trace,
+];
+
+fn encode(level: LogLevelFilter) -> usize {
+    FILTERS.iter().position(|&f| f == level).unwrap() + 1
+}
+
+fn decode(stored: usize) -> Option<LogLevelFilter> {
+    if stored == 0 { None } else { Some(FILTERS[stored - 1]) }
+}
+
+/// Set the most verbose level which will be logged for `cat`.
+pub fn set_level(cat: Category, level: LogLevelFilter) {
+    LEVELS[cat as usize].store(encode(level), Ordering::Relaxed);
+}
+
+/// Set the level for messages outside of any category, and for categories whose level hasn't been
+/// set.
+pub fn set_default_level(level: LogLevelFilter) {
+    DEFAULT.store(encode(level), Ordering::Relaxed);
+}
+
+/// The level for messages outside of any category.
+pub fn default_level() -> LogLevelFilter {
+    decode(DEFAULT.load(Ordering::Relaxed)).unwrap_or(LogLevelFilter::Info)
+}
+
+/// The most verbose level which will be logged for `cat`.
+pub fn level(cat: Category) -> LogLevelFilter {
+    decode(LEVELS[cat as usize].load(Ordering::Relaxed)).unwrap_or_else(default_level)
+}
+
+/// The most verbose level which will be logged for messages sent to `target`.
+pub fn level_for_target(target: &str) -> LogLevelFilter {
+    match Category::from_target(target) {
+        Some(cat) => level(cat),
+        None      => default_level(),
+    }
+}
+
+/// Apply a filter specification, such as `net=debug,gfx=off,warn`: a comma-separated list of
+/// `category=level` pairs, where a bare level sets the default. Nothing is changed if the
+/// specification is malformed. This is the format accepted by the console's `log` command.
+pub fn parse_filter(spec: &str) -> Result<(), String> {
+    let mut changes = vec![];
+
+    for item in spec.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        let (cat, level) = match item.find('=') {
+            Some(i) => (Some(&item[..i]), &item[i + 1..]),
+            None    => (None, item),
+        };
+
+        let level: LogLevelFilter = try!(level.trim().parse()
+            .map_err(|_| format!("unknown log level `{}'", level)));
+
+        let cat = match cat.map(|c| c.trim()) {
+            Some(c) => Some(try!(Category::from_target(c)
+                .ok_or(format!("unknown log category `{}'", c)))),
+            None    => None,
+        };
+
+        changes.push((cat, level));
+    }
+
+    for &(cat, level) in changes.iter() {
+        match cat {
+            Some(cat) => set_level(cat, level),
+            None      => set_default_level(level),
+        }
+    }
+
+    Ok(())
+}
+
+struct CategoryLogger;
+
+impl log::Log for CategoryLogger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        metadata.level() <= level_for_target(metadata.target())
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if self.enabled(record.metadata()) {
+            let _ = writeln!(io::stderr(), "[{} {}] {}", record.level(), record.target(),
+                             record.args());
+        }
+    }
+}
+
+/// Install a logger which writes to stderr, filtering messages by category. Fails if a logger has
+/// already been installed.
+pub fn init() -> Result<(), log::SetLoggerError> {
+    log::set_logger(|max_level| {
+        // Filtering happens in the logger, since the levels can change at any time.
+        max_level.set(LogLevelFilter::Trace);
+        Box::new(CategoryLogger)
+    })
+}
@@ -21,6 +21,7 @@
 use num::Float;
 
 use math;
+use math::BoundingSphere;
 use units::*;
 
 bitflags! {
@@ -45,12 +46,12 @@ impl Octant {
     pub fn as_vector(self) -> math::Vec3<Meters> {
         match self.bits {
             0b000 => vec3!(Meters ; -1.0, -1.0, -1.0),
-            0b100 => vec3!(Meters ;  1.0, -1.0, -1.0),
+            0b001 => vec3!(Meters ;  1.0, -1.0, -1.0),
             0b010 => vec3!(Meters ; -1.0,  1.0, -1.0),
-            0b001 => vec3!(Meters ; -1.0, -1.0,  1.0),
-            0b110 => vec3!(Meters ;  1.0,  1.0, -1.0),
+            0b100 => vec3!(Meters ; -1.0, -1.0,  1.0),
+            0b011 => vec3!(Meters ;  1.0,  1.0, -1.0),
             0b101 => vec3!(Meters ;  1.0, -1.0,  1.0),
-            0b011 => vec3!(Meters ; -1.0,  1.0,  1.0),
+            0b110 => vec3!(Meters ; -1.0,  1.0,  1.0),
             0b111 => vec3!(Meters ;  1.0,  1.0,  1.0),
             _     => unreachable!(),
         }
@@ -135,4 +136,17 @@ impl BoundingCube {
 
         self.contains(other.center + half_diag) && self.contains(other.center - half_diag)
     }
+
+    /// Specify whether or not `self` and `other` overlap at all.
+    #[inline] pub fn intersects(&self, other: &BoundingCube) -> bool {
+        let diff  = other.center - self.center;
+        let reach = self.half_edge + other.half_edge;
+
+        diff.x.abs() <= reach && diff.y.abs() <= reach && diff.z.abs() <= reach
+    }
+
+    /// Specify whether or not `self` and `bsphere` overlap at all.
+    #[inline] pub fn intersects_sphere(&self, bsphere: &BoundingSphere) -> bool {
+        bsphere.intersects_bcube(self)
+    }
 }
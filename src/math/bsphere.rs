@@ -0,0 +1,99 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+use num::Float;
+
+use math;
+use math::BoundingCube;
+use units::*;
+
+/// A sphere in 3D space. Round things (units, explosions, sound sources) are much better
+/// approximated by spheres than by cubes, and spheres are cheap to test against each other.
+#[derive(Copy,Clone,Debug)]
+pub struct BoundingSphere {
+    /// The location of the center of the sphere.
+    pub center: math::Vec3<Meters>,
+
+    /// The distance from the center to the surface.
+    pub radius: Meters,
+}
+
+impl BoundingSphere {
+    /// Construct a sphere.
+    pub fn new(center: math::Vec3<Meters>, radius: Meters) -> BoundingSphere {
+        BoundingSphere { center: center, radius: radius }
+    }
+
+    /// The smallest cube which contains this sphere. This is what gets stored in spatial indices,
+    /// which only deal in cubes.
+    #[inline] pub fn bcube(&self) -> BoundingCube {
+        BoundingCube { center: self.center, half_edge: self.radius }
+    }
+
+    /// Return true if `v` is within this sphere.
+    #[inline] pub fn contains(&self, v: math::Vec3<Meters>) -> bool {
+        let diff = v - self.center;
+
+        diff.dot(diff) <= self.radius * self.radius
+    }
+
+    /// Specify whether or not `self` and `other` overlap at all.
+    #[inline] pub fn intersects(&self, other: &BoundingSphere) -> bool {
+        let diff  = other.center - self.center;
+        let reach = self.radius + other.radius;
+
+        diff.dot(diff) <= reach * reach
+    }
+
+    /// Specify whether or not `other` is entirely within `self`.
+    #[inline] pub fn contains_sphere(&self, other: &BoundingSphere) -> bool {
+        let diff = other.center - self.center;
+        let room = self.radius - other.radius;
+
+        room >= Meters(0.0) && diff.dot(diff) <= room * room
+    }
+
+    /// Specify whether or not this sphere and `bcube` overlap at all. This finds the point in the
+    /// cube closest to the center of the sphere, and checks whether it's within the sphere.
+    #[inline] pub fn intersects_bcube(&self, bcube: &BoundingCube) -> bool {
+        let diff = self.center - bcube.center;
+
+        let clamp = |d: Meters| -> Meters {
+            if d > bcube.half_edge {
+                d - bcube.half_edge
+            } else if d < -bcube.half_edge {
+                d + bcube.half_edge
+            } else {
+                Meters(0.0)
+            }
+        };
+
+        let gap = vec3!(clamp(diff.x), clamp(diff.y), clamp(diff.z));
+
+        gap.dot(gap) <= self.radius * self.radius
+    }
+
+    /// Specify whether or not `bcube` is entirely within this sphere, which is the case when its
+    /// farthest corner is.
+    #[inline] pub fn contains_bcube(&self, bcube: &BoundingCube) -> bool {
+        let diff = bcube.center - self.center;
+        let far  = vec3!(diff.x.abs() + bcube.half_edge,
+                         diff.y.abs() + bcube.half_edge,
+                         diff.z.abs() + bcube.half_edge);
+
+        far.dot(far) <= self.radius * self.radius
+    }
+}
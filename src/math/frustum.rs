@@ -0,0 +1,227 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use num::Float;
+
+use math;
+use math::BoundingCube;
+use units::*;
+
+/// A plane in 3D space. The points `p` for which `normal.dot(p) + dist` is non-negative are on the
+/// "inside" of the plane.
+///
+/// A plane with a zero normal contains every point; this is how an unbounded `Frustum` leaves out
+/// a side.
+#[derive(Copy,Clone,Debug)]
+pub struct Plane {
+    /// A unit vector perpendicular to the plane, pointing toward its inside.
+    pub normal: math::Vec3<Meters>,
+
+    /// The signed distance from the plane to the origin, along `normal`.
+    pub dist: Meters,
+}
+
+impl Plane {
+    /// Construct the plane which passes through `point` and faces in the direction of `normal`.
+    pub fn from_point_normal(point: math::Vec3<Meters>, normal: math::Vec3<Meters>) -> Plane {
+        Plane { normal: normal, dist: -normal.dot(point) }
+    }
+
+    /// A plane which contains every point.
+    pub fn everything() -> Plane {
+        Plane { normal: vec3!(Meters(0.0), Meters(0.0), Meters(0.0)), dist: Meters(0.0) }
+    }
+
+    /// The signed distance from the plane to `v`, which is positive on the inside.
+    #[inline] pub fn distance(&self, v: math::Vec3<Meters>) -> Meters {
+        self.normal.dot(v) + self.dist
+    }
+
+    // How far an axis-aligned box with the given half-extents reaches along `normal`.
+    #[inline] fn reach(&self, half: math::Vec3<Meters>) -> Meters {
+        self.normal.x.abs() * half.x + self.normal.y.abs() * half.y + self.normal.z.abs() * half.z
+    }
+}
+
+/// How much of a volume lies within a `Frustum`, e.g. how visible an object is to the camera.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Visibility {
+    /// The object can't be seen at all.
+    Zero,
+
+    /// The object can be partially seen.
+    Partial,
+
+    /// The object is completely on camera.
+    Full,
+}
+
+// The axes and extents of a frustum which is really a rectangular prism (i.e. one built by
+// `Frustum::orthographic`). Keeping them around lets the cube test rule out boxes which are near an
+// edge of the prism, which the six planes alone can't.
+#[derive(Copy,Clone,Debug)]
+struct Prism {
+    center:      math::Vec3<Meters>,
+    right:       math::Vec3<Meters>,
+    up:          math::Vec3<Meters>,
+    forward:     math::Vec3<Meters>,
+    half_width:  Meters,
+    half_height: Meters,
+}
+
+/// A convex volume bounded by six planes, such as the region of space which a camera can see. For
+/// an orthographic camera this is really a rectangular prism, which `Frustum::orthographic`
+/// remembers so that it can classify boxes more precisely.
+#[derive(Copy,Clone,Debug)]
+pub struct Frustum {
+    /// The bounding planes, all facing inward. By convention these are left, right, bottom, top,
+    /// near and far.
+    pub planes: [Plane; 6],
+
+    prism: Option<Prism>,
+}
+
+impl Frustum {
+    /// Construct a frustum from its bounding planes, which must all face inward.
+    pub fn from_planes(planes: [Plane; 6]) -> Frustum {
+        Frustum { planes: planes, prism: None }
+    }
+
+    /// Construct the box-shaped frustum of an orthographic projection. `right`, `up` and `forward`
+    /// are the unit axes of the view, `center` is the center of the view, and the box extends
+    /// `half_width` and `half_height` along `right` and `up`. Pass `None` for `depth` to leave the
+    /// near and far sides unbounded, or `Some((near, far))` to bound them along `forward`.
+    pub fn orthographic(center:      math::Vec3<Meters>,
+                        right:       math::Vec3<Meters>,
+                        up:          math::Vec3<Meters>,
+                        forward:     math::Vec3<Meters>,
+                        half_width:  Meters,
+                        half_height: Meters,
+                        depth:       Option<(Meters, Meters)>) -> Frustum {
+        let left_pt   = center - right.scaled(half_width);
+        let right_pt  = center + right.scaled(half_width);
+        let bottom_pt = center - up.scaled(half_height);
+        let top_pt    = center + up.scaled(half_height);
+
+        let (near, far) = match depth {
+            Some((near, far)) => (
+                Plane::from_point_normal(center + forward.scaled(near), forward),
+                Plane::from_point_normal(center + forward.scaled(far), -forward),
+            ),
+            None => (Plane::everything(), Plane::everything()),
+        };
+
+        Frustum {
+            planes: [
+                Plane::from_point_normal(left_pt,   right),
+                Plane::from_point_normal(right_pt,  -right),
+                Plane::from_point_normal(bottom_pt, up),
+                Plane::from_point_normal(top_pt,    -up),
+                near,
+                far,
+            ],
+
+            prism: Some(Prism {
+                center:      center,
+                right:       right,
+                up:          up,
+                forward:     forward,
+                half_width:  half_width,
+                half_height: half_height,
+            }),
+        }
+    }
+
+    /// Return true if `v` is inside of the frustum.
+    pub fn contains_point(&self, v: math::Vec3<Meters>) -> bool {
+        self.planes.iter().all(|p| p.distance(v) >= Meters(0.0))
+    }
+
+    /// Determine how much of `bcube` lies within the frustum.
+    pub fn intersects(&self, bcube: &BoundingCube) -> Visibility {
+        let half = vec3!(bcube.half_edge, bcube.half_edge, bcube.half_edge);
+
+        self.classify_box(bcube.center, half)
+    }
+
+    /// Determine how much of the axis-aligned box between `min` and `max` lies within the frustum.
+    pub fn intersects_aabb(&self, min: math::Vec3<Meters>, max: math::Vec3<Meters>) -> Visibility {
+        let half = (max - min).scaled(Meters(0.5));
+
+        self.classify_box(min + half, half)
+    }
+
+    // Classify the axis-aligned box with the given center and half-extents.
+    //
+    // Testing against each plane on its own is conservative: a box which is outside of the frustum
+    // but straddles two of its planes near an edge (a big box off the corner of the screen, say)
+    // will look like it's partially inside. For a prism, the remaining separating axes are the
+    // ones perpendicular to both `forward` and a side of the box, so those are checked as well.
+    // With bounded depth there are a few more axes which could separate the two, so a box may
+    // still be reported as `Partial` when it's really outside, but never the other way around.
+    fn classify_box(&self, center: math::Vec3<Meters>, half: math::Vec3<Meters>) -> Visibility {
+        let mut result = Visibility::Full;
+
+        for plane in self.planes.iter() {
+            let dist  = plane.distance(center);
+            let reach = plane.reach(half);
+
+            if dist < -reach {
+                return Visibility::Zero
+            }
+
+            if dist < reach {
+                result = Visibility::Partial;
+            }
+        }
+
+        if result == Visibility::Partial {
+            if let Some(ref prism) = self.prism {
+                if prism.separates(center, half) {
+                    return Visibility::Zero
+                }
+            }
+        }
+
+        result
+    }
+}
+
+impl Prism {
+    // Return true if one of the axes `forward × x`, `forward × y` or `forward × z` separates the
+    // prism from the box with the given center and half-extents.
+    fn separates(&self, center: math::Vec3<Meters>, half: math::Vec3<Meters>) -> bool {
+        let f = self.forward;
+
+        let axes = [
+            vec3!(Meters(0.0), f.z, -f.y),
+            vec3!(-f.z, Meters(0.0), f.x),
+            vec3!(f.y, -f.x, Meters(0.0)),
+        ];
+
+        axes.iter().any(|&axis| {
+            // Every one of these axes is perpendicular to `forward`, so the prism's depth (bounded
+            // or not) doesn't contribute to its extent along them.
+            let prism_reach = (axis.dot(self.right) * self.half_width).abs() +
+                              (axis.dot(self.up) * self.half_height).abs();
+
+            let box_reach = axis.x.abs() * half.x + axis.y.abs() * half.y + axis.z.abs() * half.z;
+
+            (axis.dot(center) - axis.dot(self.center)).abs() > prism_reach + box_reach
+        })
+    }
+}
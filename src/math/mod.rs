@@ -19,6 +19,9 @@ mod vector2d;
 mod vector3d;
 
 pub use math::bcube::{BoundingCube, Octant, Boundedness,S0,SX,SY,SZ,SXY,SXZ,SYZ,SXYZ};
+pub use math::bsphere::BoundingSphere;
+pub use math::frustum::{Frustum, Plane, Visibility};
+pub use math::ray::Ray;
 pub use math::vector2d::Vec2;
 pub use math::vector3d::Vec3;
 
@@ -83,3 +86,6 @@ macro_rules! vec3 {
 }
 
 mod bcube;
+mod bsphere;
+mod frustum;
+mod ray;
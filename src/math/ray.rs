@@ -0,0 +1,139 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+use num::Float;
+
+use math;
+use math::{BoundingCube, BoundingSphere, Plane};
+use units::*;
+
+/// A half-line in 3D space, starting at `origin` and extending forever in the direction `dir`.
+/// Distances returned by the intersection tests are measured along `dir`, so they're only in true
+/// meters if `dir` has unit length (which `Ray::new` ensures).
+#[derive(Copy,Clone,Debug)]
+pub struct Ray {
+    /// The point where the ray starts.
+    pub origin: math::Vec3<Meters>,
+
+    /// The direction of the ray.
+    pub dir: math::Vec3<f32>,
+}
+
+impl Ray {
+    /// Construct a ray from `origin` toward `dir`, which is normalized.
+    pub fn new(origin: math::Vec3<Meters>, dir: math::Vec3<f32>) -> Ray {
+        Ray { origin: origin, dir: dir.normalized() }
+    }
+
+    /// Construct the ray which starts at `from` and passes through `to`.
+    pub fn between(from: math::Vec3<Meters>, to: math::Vec3<Meters>) -> Ray {
+        Ray::new(from, vec3!((to.x - from.x).0, (to.y - from.y).0, (to.z - from.z).0))
+    }
+
+    /// The point at distance `t` along the ray.
+    #[inline] pub fn at(&self, t: Meters) -> math::Vec3<Meters> {
+        vec3!(Meters ; self.origin.x.0 + self.dir.x * t.0,
+                       self.origin.y.0 + self.dir.y * t.0,
+                       self.origin.z.0 + self.dir.z * t.0)
+    }
+
+    /// Find where the ray enters `cube`, using the slab method. If the ray starts inside the cube
+    /// the distance is zero.
+    pub fn intersect_cube(&self, cube: &BoundingCube) -> Option<Meters> {
+        let origin = [self.origin.x.0, self.origin.y.0, self.origin.z.0];
+        let dir    = [self.dir.x, self.dir.y, self.dir.z];
+        let center = [cube.center.x.0, cube.center.y.0, cube.center.z.0];
+        let half   = cube.half_edge.0;
+
+        let mut near = 0.0f32;
+        let mut far: f32 = Float::infinity();
+
+        for i in 0..3 {
+            let lo = center[i] - half;
+            let hi = center[i] + half;
+
+            if dir[i] == 0.0 {
+                // Parallel to this slab, so we're either always between its sides or never.
+                if origin[i] < lo || origin[i] > hi {
+                    return None;
+                }
+                continue;
+            }
+
+            let inv = 1.0 / dir[i];
+            let (t0, t1) = {
+                let a = (lo - origin[i]) * inv;
+                let b = (hi - origin[i]) * inv;
+                if a < b { (a, b) } else { (b, a) }
+            };
+
+            near = near.max(t0);
+            far  = far.min(t1);
+
+            if near > far {
+                return None;
+            }
+        }
+
+        Some(Meters(near))
+    }
+
+    /// Find where the ray crosses `plane`, from either side. Returns `None` if the ray is parallel
+    /// to the plane or points away from it.
+    pub fn intersect_plane(&self, plane: &Plane) -> Option<Meters> {
+        let n     = plane.normal;
+        let along = n.x.0 * self.dir.x + n.y.0 * self.dir.y + n.z.0 * self.dir.z;
+
+        if along.abs() < 1e-6 {
+            return None;
+        }
+
+        let t = -plane.distance(self.origin).0 / along;
+
+        if t >= 0.0 { Some(Meters(t)) } else { None }
+    }
+
+    /// Find where the ray first touches the sphere with the given `center` and `radius`. If the ray
+    /// starts inside the sphere the distance is zero.
+    pub fn intersect_sphere(&self, center: math::Vec3<Meters>, radius: Meters) -> Option<Meters> {
+        let off = vec3!(self.origin.x.0 - center.x.0,
+                        self.origin.y.0 - center.y.0,
+                        self.origin.z.0 - center.z.0);
+
+        // Solve |off + t * dir|^2 = radius^2 for t.
+        let a = self.dir.dot(self.dir);
+        let b = off.dot(self.dir);
+        let c = off.dot(off) - radius.0 * radius.0;
+
+        if c <= 0.0 {
+            return Some(Meters(0.0));
+        }
+
+        let disc = b * b - a * c;
+
+        if b > 0.0 || disc < 0.0 || a == 0.0 {
+            return None;
+        }
+
+        Some(Meters((-b - disc.sqrt()) / a))
+    }
+
+    /// Find where the ray first touches `bsphere`. This is `intersect_sphere` for a
+    /// `BoundingSphere`.
+    #[inline] pub fn intersect_bsphere(&self, bsphere: &BoundingSphere) -> Option<Meters> {
+        self.intersect_sphere(bsphere.center, bsphere.radius)
+    }
+}
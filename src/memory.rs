@@ -0,0 +1,142 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Attributing memory to the subsystems which use it. Each subsystem adds what it holds to a
+//! `Report` (sprite sheets, component maps, octrees, network buffers...), which can then be
+//! inspected at runtime or printed as a histogram, so that a leak or a bloated cache shows up as
+//! one long bar.
+//!
+//! Sizes are estimates of what each structure owns, from element counts and `mem::size_of`; heap
+//! allocations made by the elements themselves and allocator overhead aren't included.
+
+use std::fmt;
+
+// The width of the longest bar printed by `Report`'s `Display` implementation.
+const BAR_WIDTH: usize = 40;
+
+/// One line of a `Report`.
+#[derive(Clone,Debug)]
+pub struct Entry {
+    /// The subsystem responsible, named like the `logging` categories (e.g. `logging::ASSET`).
+    pub subsystem: &'static str,
+
+    /// What the memory is used for, e.g. the name of a sprite sheet.
+    pub name: String,
+
+    /// How many things this entry counts (sheets, components, nodes...).
+    pub count: usize,
+
+    /// The number of bytes used.
+    pub bytes: usize,
+}
+
+/// A breakdown of memory use, filled in by each subsystem's `report_memory`.
+#[derive(Clone,Debug)]
+pub struct Report {
+    entries: Vec<Entry>,
+}
+
+impl Report {
+    /// Create an empty report.
+    pub fn new() -> Report {
+        Report { entries: vec![] }
+    }
+
+    /// Record that `subsystem` uses `bytes` bytes for `count` of the things described by `name`.
+    pub fn add<S>(&mut self, subsystem: &'static str, name: S, count: usize, bytes: usize)
+        where String: From<S> {
+
+        self.entries.push(Entry {
+            subsystem: subsystem,
+            name:      From::from(name),
+            count:     count,
+            bytes:     bytes,
+        });
+    }
+
+    /// Every entry, in the order they were added.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The total number of bytes reported.
+    pub fn total(&self) -> usize {
+        self.entries.iter().fold(0, |total, e| total + e.bytes)
+    }
+
+    /// The total for each subsystem, largest first.
+    pub fn subsystems(&self) -> Vec<(&'static str, usize)> {
+        let mut totals: Vec<(&'static str, usize)> = vec![];
+
+        for e in self.entries.iter() {
+            let found = totals.iter().position(|&(s, _)| s == e.subsystem);
+
+            match found {
+                Some(i) => totals[i].1 += e.bytes,
+                None    => totals.push((e.subsystem, e.bytes)),
+            }
+        }
+
+        totals.sort_by(|a, b| b.1.cmp(&a.1));
+        totals
+    }
+}
+
+// Format a number of bytes for people to read.
+fn human(bytes: usize) -> String {
+    if bytes >= 1 << 20 {
+        format!("{:.1} MiB", bytes as f64 / (1 << 20) as f64)
+    } else if bytes >= 1 << 10 {
+        format!("{:.1} KiB", bytes as f64 / (1 << 10) as f64)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+// A bar of `#`s as long relative to `BAR_WIDTH` as `bytes` is to `max`.
+fn bar(bytes: usize, max: usize) -> String {
+    let len = if max == 0 { 0 } else { (bytes as f64 / max as f64 * BAR_WIDTH as f64).ceil() };
+
+    (0..len as usize).map(|_| '#').collect()
+}
+
+impl fmt::Display for Report {
+    /// Print each subsystem, largest first, followed by its entries with a bar showing their size
+    /// relative to the largest entry in the report.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let max = self.entries.iter().fold(0, |max, e| if e.bytes > max { e.bytes } else { max });
+
+        try!(writeln!(f, "total: {}", human(self.total())));
+
+        for (subsystem, total) in self.subsystems() {
+            try!(writeln!(f, "{}: {}", subsystem, human(total)));
+
+            let mut entries: Vec<&Entry> = self.entries.iter()
+                .filter(|e| e.subsystem == subsystem)
+                .collect();
+
+            entries.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+
+            for e in entries {
+                try!(writeln!(f, "  {:<32} {:>8} {:>10} {}",
+                              e.name, e.count, human(e.bytes), bar(e.bytes, max)));
+            }
+        }
+
+        Ok(())
+    }
+}
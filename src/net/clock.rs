@@ -0,0 +1,347 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::VecDeque;
+
+use grafix::anim;
+use logging;
+use net::wire;
+use time::Duration;
+
+// Crystal oscillators are rarely off by more than a few hundred parts per million. Anything beyond
+// this is measurement noise, so the drift estimate is clamped to it.
+const MAX_DRIFT: f64 = 500.0e-6;
+
+// While the sample window is filling up, requests are sent this much more often than usual so that
+// a new client converges quickly.
+const STARTUP_SPEEDUP: u64 = 8;
+
+/// A signed difference between two clocks, measured at microsecond granularity. Unlike
+/// `time::Duration` this can be negative; a positive `Skew` means that the server's clock is ahead
+/// of the client's.
+#[derive(Copy,Clone,Debug,PartialEq,Eq,PartialOrd,Ord)]
+pub struct Skew {
+    us: i64,
+}
+
+impl Skew {
+    /// Create a skew from a (possibly negative) number of microseconds.
+    pub fn usec(us: i64) -> Skew {
+        Skew { us: us }
+    }
+
+    /// Return the skew as a signed number of microseconds.
+    pub fn as_usec(self) -> i64 { self.us }
+
+    /// Shift `t` by this skew. Since a `Duration` can't be negative, the result is clamped at 0.
+    pub fn apply(self, t: Duration) -> Duration {
+        let shifted = (t.as_usec() as i64) + self.us;
+
+        Duration::usec(if shifted < 0 { 0 } else { shifted as u64 })
+    }
+
+    /// Return the skew which undoes this one.
+    pub fn inverse(self) -> Skew {
+        Skew { us: -self.us }
+    }
+}
+
+// Compute `a - b` as a signed number of microseconds. `Duration`'s own subtraction is an absolute
+// difference, which is exactly what we don't want here.
+fn signed_diff(a: Duration, b: Duration) -> i64 {
+    (a.as_usec() as i64) - (b.as_usec() as i64)
+}
+
+/// Build the request which starts a synchronization round. `now` is the client's local time.
+pub fn request(now: Duration) -> wire::SyncRequest {
+    wire::SyncRequest::new(now.as_usec())
+}
+
+/// Build the server's reply to `req`. `recv` is the server time at which the request arrived, and
+/// `send` is the server time at which the reply is being sent. The closer `send` is to the moment
+/// the reply actually hits the network, the better the estimate will be.
+pub fn respond(req: &wire::SyncRequest, recv: Duration, send: Duration) -> wire::SyncResponse {
+    wire::SyncResponse::new(req.client_send(), recv.as_usec(), send.as_usec())
+}
+
+/// The four timestamps of a completed synchronization round.
+#[derive(Copy,Clone,Debug)]
+pub struct Sample {
+    /// Client time at which the request was sent.
+    pub client_send: Duration,
+
+    /// Server time at which the request was received.
+    pub server_recv: Duration,
+
+    /// Server time at which the response was sent.
+    pub server_send: Duration,
+
+    /// Client time at which the response was received.
+    pub client_recv: Duration,
+}
+
+impl Sample {
+    /// Create a sample from a server's response, and the local time at which it arrived.
+    pub fn from_wire(w: &wire::SyncResponse, client_recv: Duration) -> Sample {
+        Sample {
+            client_send: Duration::usec(w.client_send()),
+            server_recv: Duration::usec(w.server_recv()),
+            server_send: Duration::usec(w.server_send()),
+            client_recv: client_recv,
+        }
+    }
+
+    /// The offset of the server's clock relative to the client's, assuming that the network delay
+    /// was the same in both directions.
+    pub fn offset(&self) -> Skew {
+        let there = signed_diff(self.server_recv, self.client_send);
+        let back  = signed_diff(self.server_send, self.client_recv);
+
+        Skew::usec((there + back) / 2)
+    }
+
+    /// The time the round spent on the network, excluding the time the server spent holding on to
+    /// the request.
+    pub fn round_trip(&self) -> Duration {
+        let total  = signed_diff(self.client_recv, self.client_send);
+        let held   = signed_diff(self.server_send, self.server_recv);
+        let rtt    = total - held;
+
+        Duration::usec(if rtt < 0 { 0 } else { rtt as u64 })
+    }
+}
+
+/// Keeps track of the relationship between the local clock and the server's clock.
+///
+/// Each completed round of the handshake is a `Sample`. Samples with a long round trip are the
+/// least trustworthy (the delay is more likely to have been asymmetric), so only the sample with
+/// the shortest round trip in a sliding window is used. That measurement is then fed through an
+/// alpha-beta filter which smooths out jitter and tracks the rate at which the two clocks drift
+/// apart, so that the mapping stays accurate between rounds.
+pub struct ClockSync {
+    /// Weight given to each new measurement, in `(0.0, 1.0]`. Smaller values give a steadier clock
+    /// at the cost of reacting more slowly to real changes.
+    pub smoothing: f64,
+
+    /// If a measurement disagrees with the current estimate by more than this, the estimate is
+    /// stepped to the measurement rather than slewed toward it.
+    pub step_threshold: Duration,
+
+    /// How often a new synchronization round should be started, once the window is full.
+    pub interval: Duration,
+
+    // The most recent samples, oldest first.
+    samples: VecDeque<Sample>,
+
+    // Maximum number of samples kept in `samples`.
+    window: usize,
+
+    // Estimated offset (server minus client) at local time `updated`, in microseconds. `None` until
+    // the first sample arrives.
+    offset: Option<f64>,
+
+    // Estimated rate of change of `offset`, in microseconds per microsecond of local time.
+    drift: f64,
+
+    // Local time at which `offset` was last updated.
+    updated: Duration,
+
+    // Local time at which the next request should be sent.
+    next_request: Duration,
+}
+
+impl ClockSync {
+    /// Create a new, unsynchronized clock which will keep the best of the last `window` samples.
+    pub fn new(window: usize) -> ClockSync {
+        ClockSync {
+            smoothing:      0.125,
+            step_threshold: Duration::msec(250),
+            interval:       Duration::sec(4),
+
+            samples: VecDeque::with_capacity(window),
+            window:  if window == 0 { 1 } else { window },
+
+            offset: None,
+            drift:  0.0,
+
+            updated:      Duration::usec(0),
+            next_request: Duration::usec(0),
+        }
+    }
+
+    /// Return a request if it's time to start a new synchronization round. This is intended to be
+    /// called once per frame; the result (if any) should be sent to the server.
+    pub fn poll(&mut self, now: Duration) -> Option<wire::SyncRequest> {
+        if now < self.next_request {
+            return None
+        }
+
+        let wait = if self.samples.len() < self.window {
+            Duration::usec(self.interval.as_usec() / STARTUP_SPEEDUP)
+        } else {
+            self.interval
+        };
+
+        self.next_request = now + wait;
+
+        Some(request(now))
+    }
+
+    /// Record the server's response to one of our requests. `now` is the local time at which it
+    /// arrived.
+    pub fn receive(&mut self, w: &wire::SyncResponse, now: Duration) {
+        self.add_sample(Sample::from_wire(w, now))
+    }
+
+    /// Feed a completed round into the filter.
+    pub fn add_sample(&mut self, s: Sample) {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(s);
+
+        let best = self.best_sample().unwrap();
+
+        let now      = s.client_recv;
+        let measured = best.offset().as_usec() as f64;
+
+        let offset = match self.offset {
+            Some(offset) => offset,
+            None         => {
+                self.offset  = Some(measured);
+                self.updated = now;
+                return
+            },
+        };
+
+        let dt        = signed_diff(now, self.updated) as f64;
+        let predicted = offset + self.drift * dt;
+        let err       = measured - predicted;
+
+        if err.abs() > self.step_threshold.as_usec() as f64 {
+            // We're way off (the server restarted, or the client was suspended). Start over rather
+            // than spending the next minute slewing.
+            debug!(target: logging::NET,
+                   "clock error of {}us exceeds step threshold, stepping", err);
+            self.offset = Some(measured);
+            self.drift  = 0.0;
+        } else {
+            let alpha = self.smoothing;
+            let beta  = alpha * alpha / 2.0;
+
+            self.offset = Some(predicted + alpha * err);
+
+            if dt > 0.0 {
+                let drift = self.drift + beta * err / dt;
+                self.drift = drift.max(-MAX_DRIFT).min(MAX_DRIFT);
+            }
+        }
+
+        self.updated = now;
+    }
+
+    // The sample with the shortest round trip in the window.
+    fn best_sample(&self) -> Option<Sample> {
+        self.samples.iter().fold(None, |best: Option<Sample>, &s| match best {
+            Some(b) if b.round_trip() <= s.round_trip() => Some(b),
+            _                                            => Some(s),
+        })
+    }
+
+    /// True once at least one sample has been received.
+    pub fn is_synchronized(&self) -> bool {
+        self.offset.is_some()
+    }
+
+    /// The shortest round trip currently in the sample window, if any.
+    pub fn round_trip(&self) -> Option<Duration> {
+        self.best_sample().map(|s| s.round_trip())
+    }
+
+    /// The estimated skew between the two clocks at local time `local`. This is zero until the
+    /// clock is synchronized.
+    pub fn skew_at(&self, local: Duration) -> Skew {
+        match self.offset {
+            Some(offset) => {
+                let dt = signed_diff(local, self.updated) as f64;
+                Skew::usec((offset + self.drift * dt).round() as i64)
+            },
+            None => Skew::usec(0),
+        }
+    }
+
+    /// Convert a local time into server time.
+    pub fn to_server(&self, local: Duration) -> Duration {
+        self.skew_at(local).apply(local)
+    }
+
+    /// Convert a server time into local time.
+    pub fn to_local(&self, server: Duration) -> Duration {
+        // The skew depends (very weakly) on the local time we're solving for, so guess with the
+        // current skew and then refine once. The drift is small enough that one step is plenty.
+        let guess = self.skew_at(self.updated).inverse().apply(server);
+
+        self.skew_at(guess).inverse().apply(server)
+    }
+
+    /// Return a copy of a replicated animation instance with its start time moved from the server's
+    /// clock to the local clock, so that it can be drawn with `anim::Instance::draw_at`.
+    pub fn instance_to_local(&self, inst: &anim::Instance) -> anim::Instance {
+        let mut local = inst.clone();
+        local.t_start = self.to_local(inst.t_start);
+        local
+    }
+
+    /// Return a copy of a locally started animation instance with its start time moved to the
+    /// server's clock, suitable for replication.
+    pub fn instance_to_server(&self, inst: &anim::Instance) -> anim::Instance {
+        let mut server = inst.clone();
+        server.t_start = self.to_server(inst.t_start);
+        server
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use net::clock::{request, respond, Sample, Skew};
+    use time::Duration;
+
+    #[test]
+    fn sync_round_trip() {
+        let req = request(Duration::usec(1_000));
+
+        assert_eq!(req.client_send(), 1_000);
+
+        // The server's clock is 5ms ahead, the network takes 2ms each way, and the server holds
+        // on to the request for 1ms.
+        let resp = respond(&req, Duration::usec(8_000), Duration::usec(9_000));
+
+        assert_eq!(resp.client_send(), 1_000);
+        assert_eq!(resp.server_recv(), 8_000);
+        assert_eq!(resp.server_send(), 9_000);
+
+        let sample = Sample::from_wire(&resp, Duration::usec(6_000));
+
+        assert_eq!(sample.client_send, Duration::usec(1_000));
+        assert_eq!(sample.server_recv, Duration::usec(8_000));
+        assert_eq!(sample.server_send, Duration::usec(9_000));
+        assert_eq!(sample.client_recv, Duration::usec(6_000));
+
+        assert_eq!(sample.offset(), Skew::usec(5_000));
+        assert_eq!(sample.round_trip(), Duration::usec(4_000));
+    }
+}
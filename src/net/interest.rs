@@ -0,0 +1,251 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
+
+use logging;
+use math::BoundingCube;
+use memory;
+use scene::{EntryID, LooseOctree};
+use units::*;
+
+/// Identifies a connected client.
+pub type ClientID = u32;
+
+/// A change in the set of entities which are relevant to a client.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Event<E> {
+    /// The entity has become relevant, and the client should spawn it.
+    Enter(E),
+
+    /// The entity is no longer relevant, and the client should despawn it.
+    Leave(E),
+}
+
+struct Client<E> {
+    // The region of the world this client cares about (typically the area around its camera).
+    interest: BoundingCube,
+
+    // The entities which the client currently knows about.
+    relevant: BTreeSet<E>,
+}
+
+/// Decides which entities should be replicated to which clients.
+///
+/// Every entity with a location is kept in a `LooseOctree`, and each client has an interest region.
+/// Each tick, `update` queries the octree with a client's region and compares the result with what
+/// that client was told about last time, producing `Enter` and `Leave` events for the difference.
+pub struct InterestManager<E: Copy + Ord> {
+    tree: LooseOctree<E>,
+
+    // Octree entry for each tracked entity.
+    entries: BTreeMap<E, EntryID>,
+
+    clients: BTreeMap<ClientID, Client<E>>,
+
+    // Reused between queries, to avoid allocating every tick.
+    scratch: Vec<EntryID>,
+}
+
+impl<E: Copy + Ord> InterestManager<E> {
+    /// Create an `InterestManager` whose octree initially covers `initial`, never subdivides below
+    /// `min` meters, and has the given loose factor. See `LooseOctree::new`.
+    pub fn new(initial: BoundingCube, min: Meters, loose: f32) -> InterestManager<E> {
+        InterestManager {
+            tree:    LooseOctree::new(initial, min, loose),
+            entries: BTreeMap::new(),
+            clients: BTreeMap::new(),
+            scratch: vec![],
+        }
+    }
+
+    /// Set the location of an entity, starting to track it if it isn't already.
+    pub fn update_entity(&mut self, id: E, bounds: BoundingCube) {
+        if let Some(&ent_id) = self.entries.get(&id) {
+            self.tree.adjust(ent_id, bounds);
+            return
+        }
+
+        let ent_id = self.tree.insert(id, bounds);
+
+        self.entries.insert(id, ent_id);
+    }
+
+    /// Stop tracking an entity. It will produce a `Leave` event for every client which could see
+    /// it, on that client's next `update`.
+    pub fn remove_entity(&mut self, id: E) {
+        if let Some(ent_id) = self.entries.remove(&id) {
+            self.tree.remove(ent_id);
+        }
+    }
+
+    /// Start replicating to a client, with the given region of interest. The client begins with no
+    /// relevant entities, so its first `update` will produce an `Enter` for everything it can see.
+    pub fn add_client(&mut self, client: ClientID, interest: BoundingCube) {
+        self.clients.insert(client, Client {
+            interest: interest,
+            relevant: BTreeSet::new(),
+        });
+    }
+
+    /// Stop replicating to a client.
+    pub fn remove_client(&mut self, client: ClientID) {
+        self.clients.remove(&client);
+    }
+
+    /// Change a client's region of interest, e.g. because its camera moved.
+    pub fn set_interest(&mut self, client: ClientID, interest: BoundingCube) {
+        if let Some(c) = self.clients.get_mut(&client) {
+            c.interest = interest;
+        }
+    }
+
+    /// Recompute the set of entities relevant to `client`, and append an `Event` to `events` for
+    /// each entity which entered or left it.
+    pub fn update(&mut self, client: ClientID, events: &mut Vec<Event<E>>) {
+        let c = match self.clients.get_mut(&client) {
+            Some(c) => c,
+            None    => return,
+        };
+
+        self.tree.query(&c.interest, &mut self.scratch);
+
+        let tree = &self.tree;
+
+        let now: BTreeSet<E> = self.scratch.iter()
+            .filter_map(|&ent_id| tree.get(ent_id).map(|(&id, _)| id))
+            .collect();
+
+        for &id in now.difference(&c.relevant) {
+            events.push(Event::Enter(id));
+        }
+
+        for &id in c.relevant.difference(&now) {
+            events.push(Event::Leave(id));
+        }
+
+        c.relevant = now;
+    }
+
+    /// Return true if `id` was relevant to `client` as of the last `update`.
+    pub fn is_relevant(&self, client: ClientID, id: E) -> bool {
+        self.clients.get(&client).map_or(false, |c| c.relevant.contains(&id))
+    }
+
+    /// Call `f` on each entity which was relevant to `client` as of the last `update`.
+    pub fn for_each_relevant<F: FnMut(E)>(&self, client: ClientID, mut f: F) {
+        if let Some(c) = self.clients.get(&client) {
+            for &id in c.relevant.iter() {
+                f(id);
+            }
+        }
+    }
+
+    /// The region of interest of `client`.
+    pub fn interest(&self, client: ClientID) -> Option<BoundingCube> {
+        self.clients.get(&client).map(|c| c.interest)
+    }
+
+    /// The last bounds given for `id`, if it's tracked.
+    pub fn bounds(&self, id: E) -> Option<BoundingCube> {
+        self.entries.get(&id).and_then(|&ent_id| self.tree.get(ent_id)).map(|(_, b)| b)
+    }
+
+    /// Return the IDs of every connected client.
+    pub fn clients(&self) -> Vec<ClientID> {
+        self.clients.keys().cloned().collect()
+    }
+
+    /// Add the octree and each client's set of relevant entities to `report`.
+    pub fn report_memory(&self, report: &mut memory::Report) {
+        self.tree.report_memory(report, logging::NET, "interest");
+
+        let relevant = self.clients.values().fold(0, |total, c| total + c.relevant.len());
+
+        report.add(logging::NET, "relevant sets", relevant,
+                   relevant * mem::size_of::<E>() + self.entries.len() *
+                       (mem::size_of::<E>() + mem::size_of::<EntryID>()));
+    }
+}
+
+#[cfg(feature = "server")] pub use self::server::InterestSystem;
+
+#[cfg(feature = "server")]
+mod server {
+    use std::cell::RefCell;
+    use std::collections::BTreeSet;
+    use std::mem;
+    use std::rc::Rc;
+
+    use entity::sim as entity;
+    use entity::{EngineSystem, EngineView};
+    use net::interest::InterestManager;
+    use time;
+
+    /// A server-side `System` which keeps an `InterestManager` up to date with the location of
+    /// every entity that has a `WorldLocation`. Entities which are removed, or lose their
+    /// `WorldLocation`, are removed from the `InterestManager` too, so clients see them leave.
+    pub struct InterestSystem {
+        interest: Rc<RefCell<InterestManager<entity::EntityID>>>,
+
+        // Entities with a location at the last step, and so far at this one.
+        located: BTreeSet<entity::EntityID>,
+        seen:    BTreeSet<entity::EntityID>,
+    }
+
+    impl InterestSystem {
+        /// Create a system which feeds entity locations to `interest`. The `InterestManager` is
+        /// shared so that the replication code can use it after the `Manager` has updated.
+        pub fn new(interest: Rc<RefCell<InterestManager<entity::EntityID>>>) -> InterestSystem {
+            InterestSystem {
+                interest: interest,
+                located:  BTreeSet::new(),
+                seen:     BTreeSet::new(),
+            }
+        }
+    }
+
+    impl EngineSystem for InterestSystem {
+        /// Forget entities which weren't processed with a location last step.
+        fn update(&mut self, _now: time::Duration) {
+            let seen = mem::replace(&mut self.seen, BTreeSet::new());
+            let mut interest = self.interest.borrow_mut();
+
+            for &id in self.located.difference(&seen) {
+                interest.remove_entity(id);
+            }
+
+            self.located = seen;
+        }
+
+        /// Record the entity's current location, or forget it if it no longer has one.
+        fn process_entity<V: EngineView>(&mut self, _now: time::Duration, entity: &mut V) {
+            let id = entity.id();
+
+            match entity.world_location() {
+                Some(loc) => {
+                    self.interest.borrow_mut().update_entity(id, loc.bounds);
+                    self.seen.insert(id);
+                },
+                None => self.interest.borrow_mut().remove_entity(id),
+            }
+        }
+
+        fn name(&self) -> &'static str { "interest" }
+    }
+}
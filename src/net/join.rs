@@ -0,0 +1,153 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Joining a game which is already in progress.
+//!
+//! A client which connects mid-game knows nothing about the world, so the server answers its
+//! `JoinRequest` with a `Welcome` holding everything needed to catch up in one go: which map is
+//! loaded, the full current state of every entity, and the first round of clock synchronization.
+//! Once the client acknowledges the snapshot in the welcome, that snapshot becomes its baseline
+//! and it's sent ordinary deltas from then on (see the `snapshot` module).
+//!
+//! A client can also join as a spectator. Spectators receive the same updates as players but
+//! don't control anything, so there's nothing for them to predict.
+
+use logging;
+use net::clock::{self, ClockSync};
+use net::interest::ClientID;
+use net::snapshot::{Baselines, Delta, DeltaError, Received, SnapshotID};
+use net::wire;
+use time::Duration;
+
+/// How a client takes part in the game.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Role {
+    /// The client controls entities, and predicts their movement locally.
+    Player,
+
+    /// The client only watches.
+    Spectator,
+}
+
+impl Role {
+    /// Whether the client should run prediction for the entities it controls. Spectators don't
+    /// control any, so they just display the server's state as it arrives.
+    pub fn predicts(self) -> bool {
+        self == Role::Player
+    }
+
+    /// Whether the server should accept input from the client.
+    pub fn sends_input(self) -> bool {
+        self == Role::Player
+    }
+}
+
+/// Sent by a client to ask to join.
+#[derive(Copy,Clone)]
+pub struct JoinRequest {
+    /// The first clock synchronization request, so that the welcome can double as its response.
+    pub sync: wire::SyncRequest,
+
+    /// How the client wants to take part.
+    pub role: Role,
+}
+
+impl JoinRequest {
+    /// Build a request to join as `role`. `now` is the client's local time.
+    pub fn new(now: Duration, role: Role) -> JoinRequest {
+        JoinRequest { sync: clock::request(now), role: role }
+    }
+}
+
+/// The server's answer to a `JoinRequest`: everything a client needs to start following the game.
+pub struct Welcome<E: Ord> {
+    /// The ID the server has assigned to the client.
+    pub client: ClientID,
+
+    /// The map which is loaded, as the name it was loaded by.
+    pub map: String,
+
+    /// How the client takes part. The server may have overridden the role it asked for (e.g.
+    /// because the game is full).
+    pub role: Role,
+
+    /// The response to the synchronization request in the `JoinRequest`.
+    pub sync: wire::SyncResponse,
+
+    /// The full state of the world.
+    pub state: Delta<E>,
+}
+
+/// Build the welcome for a client which has just sent `req`, and start tracking its baseline in
+/// `baselines`. `recv` is the server time at which `req` arrived and `now` is the current server
+/// time. Returns `None` if no snapshot has been recorded yet, in which case the client should be
+/// welcomed after the next tick.
+pub fn welcome<E: Ord + Clone>(req: &JoinRequest, client: ClientID, role: Role, map: &str,
+                               baselines: &mut Baselines<E>, recv: Duration, now: Duration)
+    -> Option<Welcome<E>> {
+
+    // A client which is rejoining under the same ID must not be sent a delta against a baseline
+    // from its last session, so start it over.
+    baselines.remove_client(client);
+    baselines.add_client(client);
+
+    let state = match baselines.encode(client) {
+        Some(state) => state,
+        None        => return None,
+    };
+
+    debug!(target: logging::NET, "welcoming client {} as {:?} with snapshot {} ({} bytes)",
+           client, role, state.id, state.payload_bytes());
+
+    Some(Welcome {
+        client: client,
+        map:    From::from(map),
+        role:   role,
+        sync:   clock::respond(&req.sync, recv, now),
+        state:  state,
+    })
+}
+
+/// The result of accepting a `Welcome`.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Joined {
+    /// The ID the server has assigned to the client.
+    pub client: ClientID,
+
+    /// The map which should be loaded.
+    pub map: String,
+
+    /// How the client takes part.
+    pub role: Role,
+
+    /// The snapshot to acknowledge to the server, which will then switch to sending deltas.
+    pub ack: SnapshotID,
+}
+
+impl<E: Ord + Clone> Welcome<E> {
+    /// Take in the world state and clock sample carried by the welcome. `now` is the client's local
+    /// time at which it arrived. `received` should be empty, since this is the client's first
+    /// snapshot.
+    pub fn accept(self, received: &mut Received<E>, clock: &mut ClockSync, now: Duration)
+        -> Result<Joined, DeltaError> {
+
+        clock.receive(&self.sync, now);
+
+        let ack = try!(received.apply(self.state)).id;
+
+        Ok(Joined { client: self.client, map: self.map, role: self.role, ack: ack })
+    }
+}
@@ -0,0 +1,97 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+#[allow(missing_docs)]
+pub mod wire;
+
+/// Estimating the offset between a client's clock and the server's clock.
+pub mod clock;
+
+/// Deciding which entities are relevant to which clients.
+pub mod interest;
+
+/// Sending entity updates in priority order under a per-client bandwidth budget.
+pub mod priority;
+
+/// Delta-compressing world snapshots against what each client has acknowledged.
+pub mod snapshot;
+
+/// Bringing clients which connect mid-game up to date, as players or spectators.
+pub mod join;
+
+/// A loopback transport which simulates latency, jitter, loss and reordering.
+pub mod sim;
+
+use time::Duration;
+use trace;
+
+/// Something which carries packets between a client and a server.
+///
+/// Delivery is unreliable and unordered, as with UDP. Both methods take the current time so that
+/// simulated transports can be driven by a test's clock.
+pub trait Transport {
+    /// Send `packet` to the other end.
+    fn send(&mut self, now: Duration, packet: &[u8]);
+
+    /// Take the next packet which has arrived by `now`, if any.
+    fn recv(&mut self, now: Duration) -> Option<Vec<u8>>;
+}
+
+/// A `Transport` which records every packet sent and received in `trace` captures, passing them on
+/// to the transport it wraps.
+pub struct Traced<T> {
+    inner: T,
+}
+
+impl<T: Transport> Traced<T> {
+    /// Record the packets passing through `inner`.
+    pub fn new(inner: T) -> Traced<T> {
+        Traced { inner: inner }
+    }
+
+    /// The wrapped transport.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// The wrapped transport, mutably.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Stop recording, returning the wrapped transport.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Transport> Transport for Traced<T> {
+    fn send(&mut self, now: Duration, packet: &[u8]) {
+        trace::instant(trace::NET, "send", &[("bytes", &packet.len())]);
+        self.inner.send(now, packet);
+    }
+
+    fn recv(&mut self, now: Duration) -> Option<Vec<u8>> {
+        let packet = self.inner.recv(now);
+
+        if let Some(ref packet) = packet {
+            trace::instant(trace::NET, "recv", &[("bytes", &packet.len())]);
+        }
+
+        packet
+    }
+}
@@ -0,0 +1,227 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Deciding which entity updates to send to each client on each tick.
+//!
+//! A client's connection can only carry so many bytes per tick. When more entities change than fit,
+//! sending them all at once would cause a burst of latency (or loss), so instead each client gets a
+//! byte budget and a queue of pending updates. Each tick, every pending update gains priority in
+//! proportion to how close its entity is to the client's region of interest, and each change to an
+//! entity gives its update a boost on top; the updates with the most priority are sent until the
+//! budget runs out. Updates which don't make it keep their
+//! priority and go out on a later tick, so far-away entities update less often rather than never.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use net::interest::{ClientID, InterestManager};
+use units::*;
+
+/// The budget given to new clients, in bytes per tick.
+pub const DEFAULT_BUDGET: usize = 1200;
+
+/// The distance at which an entity's priority grows half as fast as one at the center of a client's
+/// region of interest.
+pub const DEFAULT_FALLOFF: f32 = 16.0;
+
+/// The priority an update gains each time its entity changes, by default. This is as much as a tick
+/// of waiting at the center of a client's region of interest.
+pub const DEFAULT_CHANGE_BOOST: f32 = 1.0;
+
+// The weight given to entities whose distance from a client isn't known.
+const UNKNOWN_WEIGHT: f32 = 1.0 / 1024.0;
+
+// An update waiting to be sent to one client.
+#[derive(Copy,Clone,Debug)]
+struct Pending {
+    // The size of the update, in bytes.
+    bytes: usize,
+
+    // Priority accumulated while waiting.
+    priority: f32,
+}
+
+struct Queue<E> {
+    budget:  usize,
+    pending: BTreeMap<E, Pending>,
+}
+
+impl<E: Copy + Ord> Queue<E> {
+    // Queue (or replace) the update for `id`, adding `boost` to its priority.
+    fn changed(&mut self, id: E, bytes: usize, boost: f32) {
+        let p = self.pending.entry(id).or_insert(Pending { bytes: bytes, priority: 0.0 });
+
+        p.bytes     = bytes;
+        p.priority += boost;
+    }
+}
+
+/// Per-client queues of entity updates, sent in priority order under a per-client byte budget.
+pub struct Prioritizer<E: Copy + Ord> {
+    clients: BTreeMap<ClientID, Queue<E>>,
+    falloff: Meters,
+    boost:   f32,
+}
+
+impl<E: Copy + Ord> Prioritizer<E> {
+    /// Create a `Prioritizer` with no clients.
+    pub fn new() -> Prioritizer<E> {
+        Prioritizer {
+            clients: BTreeMap::new(),
+            falloff: Meters(DEFAULT_FALLOFF),
+            boost:   DEFAULT_CHANGE_BOOST,
+        }
+    }
+
+    /// Set the distance at which an entity's priority grows half as fast as one at the center of a
+    /// client's region of interest.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `falloff` isn't greater than zero.
+    pub fn set_falloff(&mut self, falloff: Meters) {
+        assert!(falloff.0 > 0.0, "priority falloff must be greater than 0, got {}", falloff.0);
+
+        self.falloff = falloff;
+    }
+
+    /// Set the priority an update gains each time its entity changes (see `DEFAULT_CHANGE_BOOST`).
+    /// The larger it is, the more entities which are changing outrank ones which changed a while
+    /// ago at the same distance.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `boost` is negative or NaN.
+    pub fn set_change_boost(&mut self, boost: f32) {
+        assert!(boost >= 0.0, "change boost must not be negative, got {}", boost);
+
+        self.boost = boost;
+    }
+
+    /// Start queueing updates for `client`, who can receive `budget` bytes per tick (see
+    /// `DEFAULT_BUDGET`).
+    pub fn add_client(&mut self, client: ClientID, budget: usize) {
+        self.clients.insert(client, Queue { budget: budget, pending: BTreeMap::new() });
+    }
+
+    /// Stop queueing updates for `client`, discarding anything pending.
+    pub fn remove_client(&mut self, client: ClientID) {
+        self.clients.remove(&client);
+    }
+
+    /// Change the number of bytes per tick which `client` can receive (e.g. because its connection
+    /// is getting congested).
+    pub fn set_budget(&mut self, client: ClientID, budget: usize) {
+        if let Some(q) = self.clients.get_mut(&client) {
+            q.budget = budget;
+        }
+    }
+
+    /// Queue an update of `bytes` bytes for `id` to be sent to `client`, boosting its priority (see
+    /// `set_change_boost`). If an update for `id` is already pending, it's replaced (keeping the
+    /// priority it has built up), since only the latest state needs to be sent.
+    pub fn changed(&mut self, client: ClientID, id: E, bytes: usize) {
+        let boost = self.boost;
+
+        if let Some(q) = self.clients.get_mut(&client) {
+            q.changed(id, bytes, boost);
+        }
+    }
+
+    /// Queue an update for `id` to every client which it's relevant to, according to `interest`.
+    pub fn changed_for_relevant(&mut self, interest: &InterestManager<E>, id: E, bytes: usize) {
+        let boost = self.boost;
+
+        for (&client, q) in self.clients.iter_mut() {
+            if interest.is_relevant(client, id) {
+                q.changed(id, bytes, boost);
+            }
+        }
+    }
+
+    /// Drop any update pending for `id` to `client`, e.g. because the entity left its region of
+    /// interest.
+    pub fn forget(&mut self, client: ClientID, id: E) {
+        if let Some(q) = self.clients.get_mut(&client) {
+            q.pending.remove(&id);
+        }
+    }
+
+    /// The number of updates waiting to be sent to `client`.
+    pub fn pending(&self, client: ClientID) -> usize {
+        self.clients.get(&client).map_or(0, |q| q.pending.len())
+    }
+
+    /// Choose which pending updates to send to `client` this tick, appending their entities to
+    /// `out` in priority order and returning the number of bytes they take up. Updates with equal
+    /// priority are sent in order of entity.
+    ///
+    /// Distances are measured from the center of the client's region of interest in `interest`;
+    /// entities (or clients) which it doesn't know about get the lowest weight. If even the most
+    /// important update doesn't fit in the budget it's sent anyway, alone, so that nothing can be
+    /// starved forever by being too big.
+    pub fn schedule(&mut self, client: ClientID, interest: &InterestManager<E>, out: &mut Vec<E>)
+        -> usize {
+
+        let falloff = self.falloff;
+
+        let q = match self.clients.get_mut(&client) {
+            Some(q) => q,
+            None    => return 0,
+        };
+
+        let center = interest.interest(client).map(|region| region.center);
+
+        for (&id, p) in q.pending.iter_mut() {
+            let weight = match (center, interest.bounds(id)) {
+                (Some(c), Some(b)) => {
+                    let dist = (b.center - c).length();
+                    1.0 / (1.0 + (dist / falloff).0)
+                },
+                _ => UNKNOWN_WEIGHT,
+            };
+
+            // A NaN position would leave the update's priority NaN for good, so it's treated as
+            // unknown instead.
+            p.priority += if weight.is_nan() { UNKNOWN_WEIGHT } else { weight };
+        }
+
+        let mut order: Vec<(E, Pending)> = q.pending.iter().map(|(&id, &p)| (id, p)).collect();
+
+        order.sort_by(|a, b| match b.1.priority.partial_cmp(&a.1.priority) {
+            Some(Ordering::Equal) | None => a.0.cmp(&b.0),
+            Some(ord)                    => ord,
+        });
+
+        let mut used = 0;
+
+        for &(id, p) in order.iter() {
+            let fits = used + p.bytes <= q.budget;
+
+            if fits || used == 0 {
+                used += p.bytes;
+                out.push(id);
+                q.pending.remove(&id);
+            }
+
+            if !fits && used >= q.budget {
+                break;
+            }
+        }
+
+        used
+    }
+}
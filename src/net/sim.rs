@@ -0,0 +1,231 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! A loopback transport which simulates a bad network. Packets sent through a `Loopback` endpoint
+//! arrive at its peer after an artificial delay, and may be dropped, reordered or duplicated along
+//! the way, according to a set of `Conditions`. Everything is driven by the caller's clock and a
+//! seeded `Rng`, so a given seed and sequence of calls always produces the same deliveries.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use logging;
+use memory;
+use net::Transport;
+use rng::Rng;
+use time::Duration;
+
+/// How badly the simulated network behaves. The default is a perfect network.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct Conditions {
+    /// The one-way delay every packet experiences.
+    pub latency: Duration,
+
+    /// The most extra delay (chosen uniformly) that a packet can experience on top of `latency`.
+    /// Packets with different jitter can overtake each other.
+    pub jitter: Duration,
+
+    /// The probability that a packet is dropped, in `[0, 1]`.
+    pub loss: f32,
+
+    /// The probability that a packet is held back for an extra `latency`, so that packets sent
+    /// after it are likely to arrive first.
+    pub reorder: f32,
+
+    /// The probability that a packet is delivered twice.
+    pub duplicate: f32,
+}
+
+impl Conditions {
+    /// A network which delivers every packet immediately and in order.
+    pub fn perfect() -> Conditions {
+        Conditions {
+            latency:   Duration::usec(0),
+            jitter:    Duration::usec(0),
+            loss:      0.0,
+            reorder:   0.0,
+            duplicate: 0.0,
+        }
+    }
+}
+
+impl Default for Conditions {
+    fn default() -> Conditions { Conditions::perfect() }
+}
+
+/// Counts of what happened to the packets sent over a `Loopback` in one direction.
+#[derive(Copy,Clone,Debug,Default,PartialEq,Eq)]
+pub struct LinkStats {
+    /// Packets passed to `send`.
+    pub sent: u64,
+
+    /// Packets dropped.
+    pub lost: u64,
+
+    /// Packets held back to be reordered.
+    pub reordered: u64,
+
+    /// Extra copies of packets which were delivered.
+    pub duplicated: u64,
+
+    /// Packets (including duplicates) handed out by `recv`.
+    pub delivered: u64,
+}
+
+// One direction of a loopback connection.
+struct Link {
+    conditions: Conditions,
+    rng:        Rng,
+
+    // Packets in flight, keyed by delivery time and then by the order they were queued in so that
+    // packets due at the same moment come out in a consistent order.
+    in_flight: BTreeMap<(Duration, u64), Vec<u8>>,
+    seq:       u64,
+
+    stats: LinkStats,
+}
+
+impl Link {
+    fn new(conditions: Conditions, rng: Rng) -> Link {
+        Link {
+            conditions: conditions,
+            rng:        rng,
+            in_flight:  BTreeMap::new(),
+            seq:        0,
+            stats:      LinkStats::default(),
+        }
+    }
+
+    fn send(&mut self, now: Duration, packet: &[u8]) {
+        let c = self.conditions;
+
+        self.stats.sent += 1;
+
+        if self.rng.chance(c.loss) {
+            self.stats.lost += 1;
+            return;
+        }
+
+        let copies = if self.rng.chance(c.duplicate) { 2 } else { 1 };
+
+        for copy in 0..copies {
+            let mut delay = c.latency + self.jitter();
+
+            if self.rng.chance(c.reorder) {
+                delay = delay + c.latency + self.jitter();
+                self.stats.reordered += 1;
+            }
+
+            if copy > 0 {
+                self.stats.duplicated += 1;
+            }
+
+            self.in_flight.insert((now + delay, self.seq), packet.to_vec());
+            self.seq += 1;
+        }
+    }
+
+    fn recv(&mut self, now: Duration) -> Option<Vec<u8>> {
+        let key = match self.in_flight.keys().next() {
+            Some(&key) if key.0 <= now => key,
+            _                          => return None,
+        };
+
+        self.stats.delivered += 1;
+
+        self.in_flight.remove(&key)
+    }
+
+    fn jitter(&mut self) -> Duration {
+        let max = self.conditions.jitter.as_usec();
+
+        if max == 0 {
+            return Duration::usec(0);
+        }
+
+        Duration::usec(self.rng.next_u64() % (max + 1))
+    }
+}
+
+struct Shared {
+    // Indexed by the endpoint which sends over the link.
+    links: [Link; 2],
+}
+
+/// One end of a simulated connection, created by `Loopback::pair`.
+pub struct Loopback {
+    shared: Rc<RefCell<Shared>>,
+    side:   usize,
+}
+
+impl Loopback {
+    /// Create two connected endpoints whose traffic (in both directions) is subject to
+    /// `conditions`. The network's behavior is determined entirely by `seed`.
+    pub fn pair(conditions: Conditions, seed: u64) -> (Loopback, Loopback) {
+        debug!(target: logging::NET, "simulating network: {:?}", conditions);
+
+        let shared = Rc::new(RefCell::new(Shared {
+            links: [
+                Link::new(conditions, Rng::with_stream(seed, 0)),
+                Link::new(conditions, Rng::with_stream(seed, 1)),
+            ],
+        }));
+
+        (Loopback { shared: shared.clone(), side: 0 }, Loopback { shared: shared, side: 1 })
+    }
+
+    /// Change the conditions for packets sent from this endpoint from now on. Packets already in
+    /// flight are unaffected.
+    pub fn set_conditions(&mut self, conditions: Conditions) {
+        self.shared.borrow_mut().links[self.side].conditions = conditions;
+    }
+
+    /// The conditions for packets sent from this endpoint.
+    pub fn conditions(&self) -> Conditions {
+        self.shared.borrow().links[self.side].conditions
+    }
+
+    /// What has happened to the packets sent from this endpoint.
+    pub fn stats(&self) -> LinkStats {
+        self.shared.borrow().links[self.side].stats
+    }
+
+    /// The number of packets sent from this endpoint which haven't been delivered yet.
+    pub fn in_flight(&self) -> usize {
+        self.shared.borrow().links[self.side].in_flight.len()
+    }
+
+    /// Add the packets sent from this endpoint which are still in flight to `report`.
+    pub fn report_memory(&self, report: &mut memory::Report) {
+        let shared = self.shared.borrow();
+        let link   = &shared.links[self.side];
+
+        let bytes = link.in_flight.values().fold(0, |total, p| total + p.capacity());
+
+        report.add(logging::NET, "simulated packets in flight", link.in_flight.len(), bytes);
+    }
+}
+
+impl Transport for Loopback {
+    fn send(&mut self, now: Duration, packet: &[u8]) {
+        self.shared.borrow_mut().links[self.side].send(now, packet);
+    }
+
+    fn recv(&mut self, now: Duration) -> Option<Vec<u8>> {
+        self.shared.borrow_mut().links[1 - self.side].recv(now)
+    }
+}
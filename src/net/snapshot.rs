@@ -0,0 +1,289 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Per-client delta compression of world snapshots.
+//!
+//! Each tick the server records a `Snapshot` of the replicated state of every entity (already
+//! serialized, one blob per entity). Rather than sending everyone the difference from the previous
+//! tick, which only works if every packet arrives, the server remembers the last snapshot each
+//! client acknowledged and sends that client the difference from it. A lost packet just means the
+//! next delta is against an older baseline. If a client hasn't acknowledged anything for longer
+//! than the server keeps history, it gets the full state again.
+//!
+//! The client keeps the snapshots it has received for the same window, so that it always has the
+//! baseline which the server picked.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::rc::Rc;
+
+use logging;
+use memory;
+use net::interest::ClientID;
+
+/// Identifies a snapshot. These count up by one every time the server records a snapshot.
+pub type SnapshotID = u32;
+
+/// The number of snapshots kept by default, on both ends. At 20 ticks per second this is a little
+/// over 3 seconds.
+pub const DEFAULT_WINDOW: usize = 64;
+
+/// The replicated state of every entity at one moment, as a serialized blob per entity.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Snapshot<E: Ord> {
+    /// Which snapshot this is.
+    pub id: SnapshotID,
+
+    /// The state of each entity.
+    pub entities: BTreeMap<E, Vec<u8>>,
+}
+
+/// The difference between a baseline snapshot and a newer one.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Delta<E> {
+    /// The snapshot which applying this delta produces.
+    pub id: SnapshotID,
+
+    /// The snapshot this delta is relative to, or `None` if it holds the full state.
+    pub baseline: Option<SnapshotID>,
+
+    /// Entities which are new or whose state differs from the baseline.
+    pub changed: Vec<(E, Vec<u8>)>,
+
+    /// Entities which are in the baseline but not in the new snapshot.
+    pub removed: Vec<E>,
+}
+
+impl<E> Delta<E> {
+    /// The number of bytes of entity state carried by this delta.
+    pub fn payload_bytes(&self) -> usize {
+        self.changed.iter().fold(0, |total, &(_, ref state)| total + state.len())
+    }
+}
+
+/// Why a `Delta` couldn't be applied.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum DeltaError {
+    /// The baseline isn't one which the client has (any more). The client should keep
+    /// acknowledging what it does have, and the server will eventually fall back to a full state.
+    MissingBaseline(SnapshotID),
+
+    /// The delta is no newer than the latest snapshot already received, e.g. because it arrived
+    /// late. It can safely be dropped.
+    Stale(SnapshotID),
+}
+
+// Keep the last `window` snapshots, oldest first.
+fn push_history<E: Ord>(history: &mut VecDeque<Rc<Snapshot<E>>>, window: usize,
+                        snap: Rc<Snapshot<E>>) {
+    history.push_back(snap);
+
+    while history.len() > window {
+        history.pop_front();
+    }
+}
+
+fn find<E: Ord>(history: &VecDeque<Rc<Snapshot<E>>>, id: SnapshotID) -> Option<&Rc<Snapshot<E>>> {
+    history.iter().rev().find(|s| s.id == id)
+}
+
+/// The server's half: snapshot history and the baseline of each client.
+pub struct Baselines<E: Ord> {
+    window:  usize,
+    next_id: SnapshotID,
+    history: VecDeque<Rc<Snapshot<E>>>,
+
+    // The newest snapshot each client has acknowledged.
+    acked: BTreeMap<ClientID, Option<SnapshotID>>,
+}
+
+impl<E: Ord + Clone> Baselines<E> {
+    /// Create an empty history which remembers the last `window` snapshots (see `DEFAULT_WINDOW`).
+    pub fn new(window: usize) -> Baselines<E> {
+        assert!(window > 0, "the snapshot window must hold at least one snapshot");
+
+        Baselines {
+            window:  window,
+            next_id: 0,
+            history: VecDeque::new(),
+            acked:   BTreeMap::new(),
+        }
+    }
+
+    /// Start tracking a client. Until it acknowledges something, it will be sent full states.
+    pub fn add_client(&mut self, client: ClientID) {
+        self.acked.insert(client, None);
+    }
+
+    /// Stop tracking a client.
+    pub fn remove_client(&mut self, client: ClientID) {
+        self.acked.remove(&client);
+    }
+
+    /// Add the snapshot history to `report`. Only the encoded entity states are counted.
+    pub fn report_memory(&self, report: &mut memory::Report) {
+        let bytes = self.history.iter().fold(0, |total, snap| {
+            snap.entities.values().fold(total, |total, state| total + state.capacity())
+        });
+
+        report.add(logging::NET, "snapshot history", self.history.len(), bytes);
+    }
+
+    /// Record the current state of the world, returning the ID of the new snapshot.
+    pub fn record(&mut self, entities: BTreeMap<E, Vec<u8>>) -> SnapshotID {
+        let id = self.next_id;
+
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let snap = Rc::new(Snapshot { id: id, entities: entities });
+
+        push_history(&mut self.history, self.window, snap);
+
+        id
+    }
+
+    /// The most recently recorded snapshot.
+    pub fn latest(&self) -> Option<&Snapshot<E>> {
+        self.history.back().map(|s| &**s)
+    }
+
+    /// `client` has received snapshot `id`. Acknowledgements can arrive out of order, so older ones
+    /// than the client's current baseline are ignored.
+    pub fn ack(&mut self, client: ClientID, id: SnapshotID) {
+        if let Some(acked) = self.acked.get_mut(&client) {
+            let newer = match *acked {
+                Some(prev) => (id.wrapping_sub(prev) as i32) > 0,
+                None       => true,
+            };
+
+            if newer {
+                *acked = Some(id);
+            }
+        }
+    }
+
+    /// The snapshot which `client`'s next delta will be relative to, or `None` if it will be sent
+    /// the full state (because it hasn't acknowledged anything, or because its last
+    /// acknowledgement has fallen out of the history).
+    pub fn baseline(&self, client: ClientID) -> Option<SnapshotID> {
+        match self.acked.get(&client) {
+            Some(&Some(id)) if find(&self.history, id).is_some() => Some(id),
+            _                                                    => None,
+        }
+    }
+
+    /// Encode the latest snapshot for `client`, relative to its baseline. Returns `None` if
+    /// nothing has been recorded yet.
+    pub fn encode(&self, client: ClientID) -> Option<Delta<E>> {
+        let latest = match self.history.back() {
+            Some(latest) => latest,
+            None         => return None,
+        };
+
+        let base = self.baseline(client).and_then(|id| find(&self.history, id));
+
+        if base.is_none() {
+            if let Some(&Some(acked)) = self.acked.get(&client) {
+                debug!(target: logging::NET, "client {} baseline {} expired, resending full state",
+                       client, acked);
+            }
+        }
+
+        Some(diff(base.map(|b| &**b), latest))
+    }
+}
+
+// Compute the delta from `base` (or from nothing) to `snap`.
+fn diff<E: Ord + Clone>(base: Option<&Snapshot<E>>, snap: &Snapshot<E>) -> Delta<E> {
+    let mut changed = vec![];
+    let mut removed = vec![];
+
+    for (id, state) in snap.entities.iter() {
+        let same = base.and_then(|b| b.entities.get(id)).map_or(false, |old| old == state);
+
+        if !same {
+            changed.push((id.clone(), state.clone()));
+        }
+    }
+
+    if let Some(b) = base {
+        for id in b.entities.keys() {
+            if !snap.entities.contains_key(id) {
+                removed.push(id.clone());
+            }
+        }
+    }
+
+    Delta {
+        id:       snap.id,
+        baseline: base.map(|b| b.id),
+        changed:  changed,
+        removed:  removed,
+    }
+}
+
+/// The client's half: the snapshots received recently, any of which the server might pick as a
+/// baseline.
+pub struct Received<E: Ord> {
+    window:  usize,
+    history: VecDeque<Rc<Snapshot<E>>>,
+}
+
+impl<E: Ord + Clone> Received<E> {
+    /// Create an empty history which remembers the last `window` snapshots. This should match the
+    /// server's window.
+    pub fn new(window: usize) -> Received<E> {
+        assert!(window > 0, "the snapshot window must hold at least one snapshot");
+
+        Received { window: window, history: VecDeque::new() }
+    }
+
+    /// The newest snapshot received.
+    pub fn latest(&self) -> Option<&Snapshot<E>> {
+        self.history.back().map(|s| &**s)
+    }
+
+    /// Reconstruct a snapshot from `delta` and remember it. On success the snapshot's ID should be
+    /// acknowledged to the server.
+    pub fn apply(&mut self, delta: Delta<E>) -> Result<&Snapshot<E>, DeltaError> {
+        if let Some(latest) = self.history.back() {
+            if (delta.id.wrapping_sub(latest.id) as i32) <= 0 {
+                return Err(DeltaError::Stale(delta.id));
+            }
+        }
+
+        let mut entities = match delta.baseline {
+            Some(base) => match find(&self.history, base) {
+                Some(snap) => snap.entities.clone(),
+                None       => return Err(DeltaError::MissingBaseline(base)),
+            },
+
+            None => BTreeMap::new(),
+        };
+
+        for id in delta.removed.iter() {
+            entities.remove(id);
+        }
+
+        for (id, state) in delta.changed.into_iter() {
+            entities.insert(id, state);
+        }
+
+        push_history(&mut self.history, self.window,
+                     Rc::new(Snapshot { id: delta.id, entities: entities }));
+
+        Ok(self.latest().unwrap())
+    }
+}
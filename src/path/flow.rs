@@ -0,0 +1,324 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! A flow field records, for every cell of a `CostGrid`, which neighbor to step to next in order
+//! to reach a goal by the cheapest route. It costs about as much to build as a single A* search
+//! which explores the whole grid, but afterwards any number of entities can find their way to the
+//! goal by looking up the cell they're standing in.
+
+use std::cell::RefCell;
+use std::collections::BinaryHeap;
+use std::rc::Rc;
+
+use entity::{EngineSystem, EngineView};
+use math::Vec2;
+use path::{Cell, CostGrid, Moves, Node, DIAGONAL, STRAIGHT};
+use time;
+use units::*;
+
+/// The default number of flow fields kept by a `FlowCache`.
+pub const DEFAULT_CACHE_SIZE: usize = 16;
+
+/// A rectangle of cells which entities are sent to.
+#[derive(Clone,Copy,Debug,PartialEq,Eq,PartialOrd,Ord)]
+pub struct FlowGoal {
+    /// The first cell of the rectangle.
+    pub lo: Cell,
+
+    /// The last cell of the rectangle (inclusive).
+    pub hi: Cell,
+}
+
+impl FlowGoal {
+    /// A goal made up of a single cell.
+    pub fn cell(c: Cell) -> FlowGoal {
+        FlowGoal { lo: c, hi: c }
+    }
+
+    /// True if `c` is part of the goal.
+    pub fn contains(&self, c: Cell) -> bool {
+        c.0 >= self.lo.0 && c.1 >= self.lo.1 && c.0 <= self.hi.0 && c.1 <= self.hi.1
+    }
+}
+
+/// The next step towards a goal from every cell of a grid.
+pub struct FlowField {
+    goal:     FlowGoal,
+    width:    u32,
+    height:   u32,
+    dist:     Vec<u32>,
+    next:     Vec<Option<Cell>>,
+    revision: u64,
+}
+
+impl FlowField {
+    /// Build the field leading to `goal` over `grid`. Blocked cells of the goal are ignored.
+    pub fn new(grid: &CostGrid, goal: FlowGoal, moves: Moves) -> FlowField {
+        let size = (grid.width() * grid.height()) as usize;
+
+        let mut field = FlowField {
+            goal:     goal,
+            width:    grid.width(),
+            height:   grid.height(),
+            dist:     vec![u32::max_value(); size],
+            next:     vec![None; size],
+            revision: grid.revision(),
+        };
+
+        let mut open = BinaryHeap::new();
+
+        for y in goal.lo.1..goal.hi.1 + 1 {
+            for x in goal.lo.0..goal.hi.0 + 1 {
+                if grid.passable(x as i64, y as i64) {
+                    let i = field.index((x, y));
+                    field.dist[i] = 0;
+                    open.push(Node { est: 0, cost: 0, cell: (x, y) });
+                }
+            }
+        }
+
+        // Dijkstra outwards from the goal. Stepping from a neighbor `c` into `n` costs the same as
+        // it does in an A* search, so the fields agree with `path::find` about which routes are
+        // cheapest.
+        while let Some(Node { cost, cell: n, .. }) = open.pop() {
+            if cost > field.dist[field.index(n)] {
+                continue
+            }
+
+            for (c, _) in grid.neighbors(n, moves) {
+                let diagonal = c.0 != n.0 && c.1 != n.1;
+                let step     = (if diagonal { DIAGONAL } else { STRAIGHT }) * grid.cost(n.0, n.1);
+
+                let ci       = field.index(c);
+                let new_cost = cost + step;
+
+                if new_cost < field.dist[ci] {
+                    field.dist[ci] = new_cost;
+                    field.next[ci] = Some(n);
+
+                    open.push(Node { est: new_cost, cost: new_cost, cell: c });
+                }
+            }
+        }
+
+        field
+    }
+
+    /// The goal this field leads to.
+    pub fn goal(&self) -> FlowGoal { self.goal }
+
+    /// The cost of the cheapest route from `c` to the goal, or `None` if the goal can't be
+    /// reached from `c`.
+    pub fn distance(&self, c: Cell) -> Option<u32> {
+        if !self.in_bounds(c) {
+            return None
+        }
+
+        match self.dist[self.index(c)] {
+            d if d == u32::max_value() => None,
+            d                          => Some(d),
+        }
+    }
+
+    /// The cell to step to from `c`. Returns `None` if `c` is part of the goal, or if the goal
+    /// can't be reached from it.
+    pub fn next(&self, c: Cell) -> Option<Cell> {
+        if self.in_bounds(c) { self.next[self.index(c)] } else { None }
+    }
+
+    fn in_bounds(&self, c: Cell) -> bool {
+        c.0 < self.width && c.1 < self.height
+    }
+
+    fn index(&self, c: Cell) -> usize {
+        (c.1 * self.width + c.0) as usize
+    }
+}
+
+/// Keeps the flow fields for recently used goals, rebuilding them when the grid changes.
+pub struct FlowCache {
+    moves:    Moves,
+    capacity: usize,
+
+    // Least recently used first.
+    fields: Vec<FlowField>,
+}
+
+impl FlowCache {
+    /// Create a cache which keeps up to `DEFAULT_CACHE_SIZE` fields.
+    pub fn new(moves: Moves) -> FlowCache {
+        FlowCache::with_capacity(moves, DEFAULT_CACHE_SIZE)
+    }
+
+    /// Create a cache which keeps up to `capacity` fields (at least one).
+    pub fn with_capacity(moves: Moves, capacity: usize) -> FlowCache {
+        FlowCache {
+            moves:    moves,
+            capacity: if capacity > 0 { capacity } else { 1 },
+            fields:   vec![],
+        }
+    }
+
+    /// Get the field leading to `goal` over `grid`, building it if it isn't cached or if the grid
+    /// has changed since it was built. The least recently used field is evicted if the cache is
+    /// full.
+    pub fn get(&mut self, grid: &CostGrid, goal: FlowGoal) -> &FlowField {
+        let found = self.fields.iter().position(|f| f.goal == goal);
+
+        let field = match found {
+            Some(i) if self.fields[i].revision == grid.revision() => self.fields.remove(i),
+
+            Some(i) => {
+                self.fields.remove(i);
+                FlowField::new(grid, goal, self.moves)
+            },
+
+            None => FlowField::new(grid, goal, self.moves),
+        };
+
+        if self.fields.len() >= self.capacity {
+            self.fields.remove(0);
+        }
+
+        self.fields.push(field);
+        &self.fields[self.fields.len() - 1]
+    }
+
+    /// Forget every cached field.
+    pub fn clear(&mut self) {
+        self.fields.clear();
+    }
+}
+
+/// A `System` which moves every entity with a `Steering` component along the flow field
+/// for its goal. Entities walk from cell center to cell center, and stop once they reach a
+/// cell of the goal (or a cell from which the goal can't be reached).
+///
+/// The `CostGrid` is shared, so that the same costs can be used for A* searches and
+/// changed while the system is running.
+///
+/// The system is throttled under simulation LOD (see `entity::lod`): distant entities are moved
+/// less often, but further each time, though never past the next cell center.
+pub struct FlowSystem {
+    grid:      Rc<RefCell<CostGrid>>,
+    cache:     FlowCache,
+    origin:    Vec2<Meters>,
+    tile_size: Meters,
+
+    last: Option<time::Duration>,
+
+    // Seconds since the last update.
+    dt: f32,
+}
+
+impl FlowSystem {
+    /// Create a system which steers entities over `grid`, whose cell (0, 0) has its
+    /// corner at `origin`, and whose cells are `tile_size` on a side.
+    pub fn new(grid: Rc<RefCell<CostGrid>>, cache: FlowCache, origin: Vec2<Meters>,
+               tile_size: Meters) -> FlowSystem {
+        FlowSystem {
+            grid:      grid,
+            cache:     cache,
+            origin:    origin,
+            tile_size: tile_size,
+            last:      None,
+            dt:        0.0,
+        }
+    }
+
+    fn cell_at(&self, p: Vec2<Meters>) -> Option<(u32, u32)> {
+        let x = ((p.x - self.origin.x) / self.tile_size).0.floor();
+        let y = ((p.y - self.origin.y) / self.tile_size).0.floor();
+
+        if x < 0.0 || y < 0.0 { None } else { Some((x as u32, y as u32)) }
+    }
+
+    fn cell_center(&self, c: (u32, u32)) -> Vec2<Meters> {
+        vec2!(self.origin.x + self.tile_size * Meters(c.0 as f32 + 0.5),
+              self.origin.y + self.tile_size * Meters(c.1 as f32 + 0.5))
+    }
+}
+
+impl EngineSystem for FlowSystem {
+    fn update(&mut self, now: time::Duration) {
+        self.dt = match self.last {
+            Some(last) if now > last => (now - last).as_usec() as f32 / 1_000_000.0,
+            _                        => 0.0,
+        };
+
+        self.last = Some(now);
+    }
+
+    /// Move the entity one step along its flow field.
+    fn process_entity<V: EngineView>(&mut self, _now: time::Duration, entity: &mut V) {
+        let steps = entity.lod_steps();
+
+        let (goal, speed) = match entity.steering() {
+            Some(steer) => (steer.goal, steer.speed),
+            None        => return,
+        };
+
+        let pos = match entity.world_location() {
+            Some(loc) => vec2!(loc.bounds.center.x, loc.bounds.center.y),
+            None      => return,
+        };
+
+        let cell = match self.cell_at(pos) { Some(c) => c, None => return };
+
+        let next = {
+            let grid = self.grid.borrow();
+            self.cache.get(&grid, goal).next(cell)
+        };
+
+        let target = match next { Some(n) => self.cell_center(n), None => return };
+
+        let (dx, dy) = ((target.x - pos.x).0, (target.y - pos.y).0);
+        let dist     = (dx * dx + dy * dy).sqrt();
+        let reach    = speed.0 * self.dt * steps as f32;
+
+        if dist == 0.0 || reach <= 0.0 {
+            return
+        }
+
+        // Only now is the location written, so that it isn't copied for entities which don't move.
+        if let Some(loc) = entity.world_location_mut() {
+            if dist <= reach {
+                loc.bounds.center.x = target.x;
+                loc.bounds.center.y = target.y;
+            } else {
+                loc.bounds.center.x = pos.x + Meters(dx / dist * reach);
+                loc.bounds.center.y = pos.y + Meters(dy / dist * reach);
+            }
+        }
+
+        entity.set_changed();
+    }
+
+    fn name(&self) -> &'static str { "flow" }
+
+    fn throttled(&self) -> bool { true }
+}
+
+/// Flow field steering for the client-side entity system, e.g. to predict movement.
+#[cfg(feature = "client")] pub mod client {
+    pub use path::flow::FlowSystem;
+}
+
+/// Flow field steering for the server-side entity system.
+#[cfg(feature = "server")] pub mod server {
+    pub use path::flow::FlowSystem;
+}
@@ -0,0 +1,461 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Hierarchical pathfinding (HPA*) splits the grid into square clusters and finds, along each
+//! border between neighboring clusters, the portals where a path can cross. Within a cluster the
+//! cost between every pair of its portals is found once, ahead of time. A search then only has to
+//! explore the much smaller graph of portals, and the cell-by-cell route through each cluster is
+//! filled in as the path is followed.
+//!
+//! Paths found this way are close to optimal, but not always optimal.
+
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+
+use path::{self, Cell, CostGrid, Moves, Node, Path, STRAIGHT};
+
+// Borders longer than this get a portal at each end rather than one in the middle.
+const LONG_BORDER: u32 = 6;
+
+/// The position of a cluster, in units of clusters.
+pub type ClusterID = (u32, u32);
+
+// Which of a cluster's borders: the one shared with the cluster to the east, or to the south.
+#[derive(Clone,Copy,Debug,PartialEq,Eq,PartialOrd,Ord)]
+enum Side {
+    East,
+    South,
+}
+
+// A place where a path can cross between neighboring clusters: `a` on one side, `b` on the other,
+// along with the cost of stepping from `a` to `b` and from `b` to `a`.
+#[derive(Clone,Copy,Debug)]
+struct Portal {
+    a:      Cell,
+    b:      Cell,
+    a_to_b: u32,
+    b_to_a: u32,
+}
+
+/// The abstract graph used for hierarchical pathfinding over a `CostGrid`.
+pub struct Hierarchy {
+    cluster_size: u32,
+    moves:        Moves,
+    width:        u32,
+    height:       u32,
+
+    // Portals on the east or south border of each cluster.
+    portals: BTreeMap<(ClusterID, Side), Vec<Portal>>,
+
+    // For each cluster, the cost of travelling between each ordered pair of its portal cells.
+    edges: BTreeMap<ClusterID, BTreeMap<Cell, Vec<(Cell, u32)>>>,
+
+    // Clusters whose cells have changed since the graph was last brought up to date.
+    dirty: BTreeSet<ClusterID>,
+}
+
+impl Hierarchy {
+    /// Build the hierarchy for `grid`, using square clusters `cluster_size` cells on a side.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cluster_size` is zero.
+    pub fn new(grid: &CostGrid, cluster_size: u32, moves: Moves) -> Hierarchy {
+        assert!(cluster_size > 0, "clusters must contain at least one cell");
+
+        let mut h = Hierarchy {
+            cluster_size: cluster_size,
+            moves:        moves,
+            width:        grid.width(),
+            height:       grid.height(),
+            portals:      BTreeMap::new(),
+            edges:        BTreeMap::new(),
+            dirty:        BTreeSet::new(),
+        };
+
+        for cy in 0..h.rows() {
+            for cx in 0..h.cols() {
+                h.dirty.insert((cx, cy));
+            }
+        }
+
+        h.rebuild(grid);
+        h
+    }
+
+    /// The cluster which contains `cell`.
+    pub fn cluster_of(&self, cell: Cell) -> ClusterID {
+        (cell.0 / self.cluster_size, cell.1 / self.cluster_size)
+    }
+
+    /// The first and last cells (inclusive) of a cluster.
+    pub fn cluster_bounds(&self, c: ClusterID) -> (Cell, Cell) {
+        let lo = (c.0 * self.cluster_size, c.1 * self.cluster_size);
+        let hi = (cmp::min(lo.0 + self.cluster_size, self.width) - 1,
+                  cmp::min(lo.1 + self.cluster_size, self.height) - 1);
+
+        (lo, hi)
+    }
+
+    /// Note that the cost or blocked state of (`x`, `y`) has changed. The affected clusters are
+    /// brought up to date by the next call to `rebuild` or `find`.
+    pub fn invalidate(&mut self, x: u32, y: u32) {
+        let c        = self.cluster_of((x, y));
+        let (lo, hi) = self.cluster_bounds(c);
+
+        self.dirty.insert(c);
+
+        // A cell on the edge of a cluster can change the portals shared with its neighbor.
+        if x == lo.0 && c.0 > 0               { self.dirty.insert((c.0 - 1, c.1)); }
+        if y == lo.1 && c.1 > 0               { self.dirty.insert((c.0, c.1 - 1)); }
+        if x == hi.0 && c.0 + 1 < self.cols() { self.dirty.insert((c.0 + 1, c.1)); }
+        if y == hi.1 && c.1 + 1 < self.rows() { self.dirty.insert((c.0, c.1 + 1)); }
+    }
+
+    /// Recompute the portals and internal costs of every cluster which has been invalidated.
+    pub fn rebuild(&mut self, grid: &CostGrid) {
+        if self.dirty.is_empty() {
+            return
+        }
+
+        let dirty: Vec<ClusterID> = self.dirty.iter().cloned().collect();
+        self.dirty.clear();
+
+        // Changing the portals on a border changes the portal cells of the clusters on both sides,
+        // so their internal costs need recomputing too.
+        let mut touched = BTreeSet::new();
+
+        for &c in dirty.iter() {
+            touched.insert(c);
+
+            for (border, other) in self.borders(c) {
+                self.find_portals(grid, border);
+                touched.insert(other);
+            }
+        }
+
+        for &c in touched.iter() {
+            self.connect(grid, c);
+        }
+    }
+
+    /// Find a path from `start` to `goal`, bringing the hierarchy up to date with `grid` first.
+    /// Returns `None` if there is no path.
+    pub fn find(&mut self, grid: &CostGrid, start: Cell, goal: Cell) -> Option<HierPath> {
+        self.rebuild(grid);
+
+        let open_cell = |c: Cell| grid.passable(c.0 as i64, c.1 as i64);
+
+        if !open_cell(start) || !open_cell(goal) {
+            return None
+        }
+
+        let (sc, gc) = (self.cluster_of(start), self.cluster_of(goal));
+
+        // Nearby goals are usually reachable without leaving the cluster.
+        if sc == gc {
+            let (lo, hi) = self.cluster_bounds(sc);
+
+            if let Some(p) = path::find_within(grid, start, goal, self.moves, lo, hi) {
+                return Some(HierPath::new(self, vec![start, goal], p.cost))
+            }
+        }
+
+        // Temporary edges from the start to the portals of its cluster, and from the portals of
+        // the goal's cluster to the goal.
+        let from_start = self.links(grid, sc, |p| (start, p));
+        let to_goal: BTreeMap<Cell, u32> = self.links(grid, gc, |p| (p, goal)).into_iter()
+                                               .collect();
+
+        let mut best = BTreeMap::new();
+        let mut came = BTreeMap::new();
+        let mut open = BinaryHeap::new();
+
+        best.insert(start, 0);
+        open.push(Node { est: grid.estimate(start, goal, self.moves), cost: 0, cell: start });
+
+        while let Some(Node { cost, cell, .. }) = open.pop() {
+            if cell == goal {
+                let mut cells = vec![goal];
+                let mut cur   = goal;
+
+                while let Some(&prev) = came.get(&cur) {
+                    cells.push(prev);
+                    cur = prev;
+                }
+
+                cells.reverse();
+                return Some(HierPath::new(self, cells, cost))
+            }
+
+            if cost > *best.get(&cell).unwrap_or(&u32::max_value()) {
+                continue
+            }
+
+            // A start which is itself a portal cell can also cross its border directly.
+            let mut next = self.neighbors(cell);
+
+            if cell == start {
+                next.extend(from_start.iter().cloned());
+            }
+
+            if let Some(&c) = to_goal.get(&cell) {
+                next.push((goal, c));
+            }
+
+            for (n, step) in next {
+                let new_cost = cost + step;
+
+                if new_cost < *best.get(&n).unwrap_or(&u32::max_value()) {
+                    best.insert(n, new_cost);
+                    came.insert(n, cell);
+
+                    open.push(Node {
+                        est:  new_cost + grid.estimate(n, goal, self.moves),
+                        cost: new_cost,
+                        cell: n,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
+    fn cols(&self) -> u32 { (self.width + self.cluster_size - 1) / self.cluster_size }
+
+    fn rows(&self) -> u32 { (self.height + self.cluster_size - 1) / self.cluster_size }
+
+    // The borders of cluster `c`, each with the cluster on the other side.
+    fn borders(&self, c: ClusterID) -> Vec<((ClusterID, Side), ClusterID)> {
+        let mut out = vec![];
+
+        if c.0 + 1 < self.cols() { out.push(((c, Side::East), (c.0 + 1, c.1))); }
+        if c.1 + 1 < self.rows() { out.push(((c, Side::South), (c.0, c.1 + 1))); }
+        if c.0 > 0               { out.push((((c.0 - 1, c.1), Side::East), (c.0 - 1, c.1))); }
+        if c.1 > 0               { out.push((((c.0, c.1 - 1), Side::South), (c.0, c.1 - 1))); }
+
+        out
+    }
+
+    // Scan a border for runs of cells which can be crossed, and place portals along each run.
+    fn find_portals(&mut self, grid: &CostGrid, border: (ClusterID, Side)) {
+        let (c, side) = border;
+        let (lo, hi)  = self.cluster_bounds(c);
+
+        // The pairs of cells facing each other across the border, in order along it.
+        let pairs: Vec<(Cell, Cell)> = match side {
+            Side::East  => (lo.1..hi.1 + 1).map(|y| ((hi.0, y), (hi.0 + 1, y))).collect(),
+            Side::South => (lo.0..hi.0 + 1).map(|x| ((x, hi.1), (x, hi.1 + 1))).collect(),
+        };
+
+        let passable = |c: Cell| grid.passable(c.0 as i64, c.1 as i64);
+        let open     = |p: &(Cell, Cell)| passable(p.0) && passable(p.1);
+
+        let mut portals = vec![];
+        let mut i       = 0;
+
+        while i < pairs.len() {
+            if !open(&pairs[i]) {
+                i += 1;
+                continue
+            }
+
+            let start = i;
+
+            while i < pairs.len() && open(&pairs[i]) {
+                i += 1;
+            }
+
+            let len = (i - start) as u32;
+
+            let picks = if len > LONG_BORDER { vec![start, i - 1] }
+                        else                 { vec![start + (i - start) / 2] };
+
+            for k in picks {
+                let (a, b) = pairs[k];
+
+                portals.push(Portal {
+                    a:      a,
+                    b:      b,
+                    a_to_b: STRAIGHT * grid.cost(b.0, b.1),
+                    b_to_a: STRAIGHT * grid.cost(a.0, a.1),
+                });
+            }
+        }
+
+        self.portals.insert(border, portals);
+    }
+
+    // Recompute the costs between every pair of portal cells in cluster `c`.
+    fn connect(&mut self, grid: &CostGrid, c: ClusterID) {
+        let (lo, hi) = self.cluster_bounds(c);
+        let cells    = self.portal_cells(c);
+
+        let mut edges = BTreeMap::new();
+
+        for &from in cells.iter() {
+            let mut out = vec![];
+
+            for &to in cells.iter() {
+                if from == to {
+                    continue
+                }
+
+                if let Some(p) = path::find_within(grid, from, to, self.moves, lo, hi) {
+                    out.push((to, p.cost));
+                }
+            }
+
+            edges.insert(from, out);
+        }
+
+        self.edges.insert(c, edges);
+    }
+
+    // The cells of cluster `c` which are one side of a portal.
+    fn portal_cells(&self, c: ClusterID) -> Vec<Cell> {
+        let mut cells = BTreeSet::new();
+
+        for (border, _) in self.borders(c) {
+            for p in self.portals.get(&border).map(|p| &p[..]).unwrap_or(&[]) {
+                if self.cluster_of(p.a) == c { cells.insert(p.a); }
+                if self.cluster_of(p.b) == c { cells.insert(p.b); }
+            }
+        }
+
+        cells.into_iter().collect()
+    }
+
+    // The abstract graph's edges out of portal cell `cell`.
+    fn neighbors(&self, cell: Cell) -> Vec<(Cell, u32)> {
+        let c = self.cluster_of(cell);
+
+        let mut out = self.edges.get(&c).and_then(|e| e.get(&cell)).cloned().unwrap_or(vec![]);
+
+        for (border, _) in self.borders(c) {
+            for p in self.portals.get(&border).map(|p| &p[..]).unwrap_or(&[]) {
+                if p.a == cell { out.push((p.b, p.a_to_b)); }
+                if p.b == cell { out.push((p.a, p.b_to_a)); }
+            }
+        }
+
+        out
+    }
+
+    // The costs of the paths within cluster `c` between each of its portal cells and another cell,
+    // as given by `ends`, which maps a portal cell to the (from, to) pair to search.
+    fn links<F>(&self, grid: &CostGrid, c: ClusterID, ends: F) -> Vec<(Cell, u32)>
+        where F: Fn(Cell) -> (Cell, Cell) {
+
+        let (lo, hi) = self.cluster_bounds(c);
+
+        self.portal_cells(c).into_iter().filter_map(|p| {
+            let (from, to) = ends(p);
+            path::find_within(grid, from, to, self.moves, lo, hi).map(|path| (p, path.cost))
+        }).collect()
+    }
+}
+
+/// A path found by a `Hierarchy`. It starts out as a list of waypoints (the start, the portals it
+/// crosses, and the goal), and the cells between them are found one leg at a time as they're
+/// needed.
+#[derive(Clone,Debug)]
+pub struct HierPath {
+    /// The start, the portal cells crossed, and the goal, in order.
+    pub waypoints: Vec<Cell>,
+
+    /// The total cost of the path.
+    pub cost: u32,
+
+    cluster_size: u32,
+    moves:        Moves,
+    width:        u32,
+    height:       u32,
+
+    // Index of the waypoint at which the next leg starts.
+    next: usize,
+}
+
+impl HierPath {
+    fn new(h: &Hierarchy, waypoints: Vec<Cell>, cost: u32) -> HierPath {
+        HierPath {
+            waypoints:    waypoints,
+            cost:         cost,
+            cluster_size: h.cluster_size,
+            moves:        h.moves,
+            width:        h.width,
+            height:       h.height,
+            next:         0,
+        }
+    }
+
+    /// True if every leg has been returned by `next_leg`.
+    pub fn is_done(&self) -> bool {
+        self.next + 1 >= self.waypoints.len()
+    }
+
+    /// Find the cells of the next leg of the path, from one waypoint to the next (not including
+    /// the first). Returns `None` if the path is finished (see `is_done`), or if the grid has
+    /// changed so that the leg is no longer passable, in which case a new path should be found.
+    pub fn next_leg(&mut self, grid: &CostGrid) -> Option<Vec<Cell>> {
+        if self.is_done() {
+            return None
+        }
+
+        let (a, b) = (self.waypoints[self.next], self.waypoints[self.next + 1]);
+        self.next += 1;
+
+        self.leg(grid, a, b).map(|mut cells| { cells.remove(0); cells })
+    }
+
+    /// Find every cell of the path at once. Returns `None` if part of it is no longer passable.
+    pub fn refine(&self, grid: &CostGrid) -> Option<Path> {
+        let mut cells = vec![self.waypoints[0]];
+
+        for w in self.waypoints.windows(2) {
+            match self.leg(grid, w[0], w[1]) {
+                Some(leg) => cells.extend(leg[1..].iter().cloned()),
+                None      => return None,
+            }
+        }
+
+        let cost = path_cost(grid, &cells);
+
+        Some(Path { cells: cells, cost: cost })
+    }
+
+    // The cells from `a` to `b`, searching within the clusters which contain them.
+    fn leg(&self, grid: &CostGrid, a: Cell, b: Cell) -> Option<Vec<Cell>> {
+        let s  = self.cluster_size;
+        let lo = (cmp::min(a.0, b.0) / s * s, cmp::min(a.1, b.1) / s * s);
+        let hi = (cmp::min((cmp::max(a.0, b.0) / s + 1) * s, self.width) - 1,
+                  cmp::min((cmp::max(a.1, b.1) / s + 1) * s, self.height) - 1);
+
+        path::find_within(grid, a, b, self.moves, lo, hi).map(|p| p.cells)
+    }
+}
+
+// The cost of walking `cells`, each of which neighbors the one before.
+fn path_cost(grid: &CostGrid, cells: &[Cell]) -> u32 {
+    cells.windows(2).map(|w| {
+        let diagonal = w[0].0 != w[1].0 && w[0].1 != w[1].1;
+        let step     = if diagonal { path::DIAGONAL } else { STRAIGHT };
+
+        step * grid.cost(w[1].0, w[1].1)
+    }).fold(0, |a, b| a + b)
+}
@@ -0,0 +1,418 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Costs are integers, and ties between equally good paths are broken by cell order, so a search
+//! over the same grid gives the same path on every machine. The client can preview a move and the
+//! server will agree with it.
+
+/// Hierarchical pathfinding for large grids.
+pub mod hpa;
+
+/// Flow fields, for moving many entities to the same destination.
+pub mod flow;
+
+use std::cmp::{self, Ordering};
+use std::collections::BinaryHeap;
+
+use math::Vec3;
+use tilemap::{Layer, Tile, TileMap};
+use units::*;
+
+/// The cost of a cardinal step into a cell of cost 1.
+pub const STRAIGHT: u32 = 10;
+
+/// The cost of a diagonal step into a cell of cost 1. This is √2 rounded to the same precision as
+/// `STRAIGHT`.
+pub const DIAGONAL: u32 = 14;
+
+/// A cell of a `CostGrid`.
+pub type Cell = (u32, u32);
+
+/// The steps a path may take between neighboring cells.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Moves {
+    /// North, east, south, and west only.
+    Cardinal,
+
+    /// Cardinal steps, plus diagonal steps where neither of the cells being cut past is blocked.
+    Diagonal,
+}
+
+/// Per-cell movement costs for a grid, and which cells are impassable.
+#[derive(Clone,Debug)]
+pub struct CostGrid {
+    width:    u32,
+    height:   u32,
+    costs:    Vec<u32>,
+    blocked:  Vec<bool>,
+
+    // The lowest cost of any cell, which keeps the A* heuristic admissible.
+    min_cost: u32,
+
+    // Bumped whenever a cell changes, so that cached results can tell they're stale.
+    revision: u64,
+}
+
+impl CostGrid {
+    /// Create a grid where every cell is passable, at a cost of 1.
+    pub fn new(width: u32, height: u32) -> CostGrid {
+        CostGrid {
+            width:    width,
+            height:   height,
+            costs:    vec![1; (width * height) as usize],
+            blocked:  vec![false; (width * height) as usize],
+            min_cost: 1,
+            revision: 0,
+        }
+    }
+
+    /// Create a grid the size of `layer`, where `cost` gives the cost of each cell from its tile,
+    /// or `None` if the cell is blocked.
+    pub fn from_layer<F>(layer: &Layer, cost: F) -> CostGrid
+        where F: Fn(Option<Tile>) -> Option<u32> {
+
+        let mut grid = CostGrid::new(layer.width(), layer.height());
+
+        for y in 0..layer.height() {
+            for x in 0..layer.width() {
+                let i = grid.index(x, y);
+
+                match cost(layer.get(x, y)) {
+                    Some(c) => grid.costs[i]   = cmp::max(c, 1),
+                    None    => grid.blocked[i] = true,
+                }
+            }
+        }
+
+        grid.min_cost = grid.costs.iter().cloned().min().unwrap_or(1);
+        grid
+    }
+
+    /// Number of cells along the x-axis.
+    pub fn width(&self) -> u32 { self.width }
+
+    /// Number of cells along the y-axis.
+    pub fn height(&self) -> u32 { self.height }
+
+    /// True if (`x`, `y`) is inside the grid.
+    pub fn in_bounds(&self, x: i64, y: i64) -> bool {
+        x >= 0 && y >= 0 && x < self.width as i64 && y < self.height as i64
+    }
+
+    /// The cost of entering (`x`, `y`).
+    pub fn cost(&self, x: u32, y: u32) -> u32 {
+        self.costs[self.index(x, y)]
+    }
+
+    /// Set the cost of entering (`x`, `y`). Costs below 1 are raised to 1.
+    pub fn set_cost(&mut self, x: u32, y: u32, cost: u32) {
+        let i    = self.index(x, y);
+        let old  = self.costs[i];
+        let cost = cmp::max(cost, 1);
+
+        self.costs[i] = cost;
+        self.revision += 1;
+
+        if cost < self.min_cost {
+            self.min_cost = cost;
+        } else if old == self.min_cost && cost > old {
+            self.min_cost = self.costs.iter().cloned().min().unwrap_or(1);
+        }
+    }
+
+    /// True if (`x`, `y`) can't be entered.
+    pub fn is_blocked(&self, x: u32, y: u32) -> bool {
+        self.blocked[self.index(x, y)]
+    }
+
+    /// Mark (`x`, `y`) as blocked or passable.
+    pub fn set_blocked(&mut self, x: u32, y: u32, blocked: bool) {
+        let i = self.index(x, y);
+        self.blocked[i] = blocked;
+        self.revision  += 1;
+    }
+
+    /// A number which changes whenever the cost or blocked state of any cell does.
+    pub fn revision(&self) -> u64 { self.revision }
+
+    /// True if (`x`, `y`) is inside the grid and not blocked.
+    pub fn passable(&self, x: i64, y: i64) -> bool {
+        self.in_bounds(x, y) && !self.is_blocked(x as u32, y as u32)
+    }
+
+    /// The cells which can be reached from `c` in one step, along with the cost of each step.
+    pub fn neighbors(&self, c: Cell, moves: Moves) -> Vec<(Cell, u32)> {
+        const CARDINAL: [(i64, i64); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+        const DIAGONALS: [(i64, i64); 4] = [(1, -1), (1, 1), (-1, 1), (-1, -1)];
+
+        let (x, y) = (c.0 as i64, c.1 as i64);
+        let mut out = Vec::with_capacity(8);
+
+        for &(dx, dy) in CARDINAL.iter() {
+            if self.passable(x + dx, y + dy) {
+                let n = ((x + dx) as u32, (y + dy) as u32);
+                out.push((n, STRAIGHT * self.cost(n.0, n.1)));
+            }
+        }
+
+        if moves == Moves::Diagonal {
+            for &(dx, dy) in DIAGONALS.iter() {
+                // Don't cut corners.
+                if self.passable(x + dx, y + dy) && self.passable(x + dx, y)
+                    && self.passable(x, y + dy) {
+
+                    let n = ((x + dx) as u32, (y + dy) as u32);
+                    out.push((n, DIAGONAL * self.cost(n.0, n.1)));
+                }
+            }
+        }
+
+        out
+    }
+
+    /// A lower bound on the cost of travelling from `a` to `b`.
+    pub fn estimate(&self, a: Cell, b: Cell, moves: Moves) -> u32 {
+        let dx = (a.0 as i64 - b.0 as i64).abs() as u32;
+        let dy = (a.1 as i64 - b.1 as i64).abs() as u32;
+
+        let steps = match moves {
+            Moves::Cardinal => STRAIGHT * (dx + dy),
+            Moves::Diagonal => {
+                let (lo, hi) = (cmp::min(dx, dy), cmp::max(dx, dy));
+                DIAGONAL * lo + STRAIGHT * (hi - lo)
+            },
+        };
+
+        steps * self.min_cost
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        assert!(x < self.width && y < self.height, "cell ({}, {}) is out of bounds", x, y);
+
+        (y * self.width + x) as usize
+    }
+}
+
+/// A route through a `CostGrid`.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Path {
+    /// The cells visited, from the start to the goal inclusive.
+    pub cells: Vec<Cell>,
+
+    /// The total cost of the steps taken. For a path from `smooth`, this is the cost of the
+    /// unsmoothed route.
+    pub cost: u32,
+}
+
+impl Path {
+    /// The center of each cell of the path in the world, as laid out by `map` for `layer`.
+    pub fn waypoints(&self, map: &TileMap, layer: &Layer) -> Vec<Vec3<Meters>> {
+        self.cells.iter().map(|&(x, y)| map.tile_center(layer, x, y)).collect()
+    }
+
+    /// Remove waypoints which can be skipped by walking in a straight line, without crossing any
+    /// blocked cell or any cell more costly than those on the part of the path being skipped.
+    /// The result visits fewer cells, so its `cells` are no longer neighbors of each other.
+    ///
+    /// The straight lines between the remaining cells aren't made of grid steps, so they have no
+    /// cost of their own: the result keeps the `cost` of this, the unsmoothed route. That's still
+    /// what to compare when choosing between paths, since each was found by the same rules.
+    pub fn smooth(&self, grid: &CostGrid) -> Path {
+        if self.cells.len() <= 2 {
+            return self.clone()
+        }
+
+        let mut cells = vec![self.cells[0]];
+        let mut from  = 0;
+
+        while from < self.cells.len() - 1 {
+            let mut to       = from + 1;
+            let mut max_cost = grid.cost(self.cells[to].0, self.cells[to].1);
+
+            for next in (from + 2)..self.cells.len() {
+                let c = self.cells[next];
+                let limit = cmp::max(max_cost, grid.cost(c.0, c.1));
+
+                if !line_clear(grid, self.cells[from], c, limit) {
+                    break
+                }
+
+                to       = next;
+                max_cost = limit;
+            }
+
+            cells.push(self.cells[to]);
+            from = to;
+        }
+
+        Path { cells: cells, cost: self.cost }
+    }
+}
+
+/// Find the cheapest path from `start` to `goal` with A*. Returns `None` if either end is blocked
+/// or out of bounds, or if the goal can't be reached.
+pub fn find(grid: &CostGrid, start: Cell, goal: Cell, moves: Moves) -> Option<Path> {
+    if grid.width == 0 || grid.height == 0 {
+        return None
+    }
+
+    find_within(grid, start, goal, moves, (0, 0), (grid.width - 1, grid.height - 1))
+}
+
+/// Like `find`, but the path may only pass through the cells of the rectangle from `lo` to `hi`
+/// inclusive. The memory used is proportional to the size of the rectangle rather than the grid.
+pub fn find_within(grid: &CostGrid, start: Cell, goal: Cell, moves: Moves, lo: Cell, hi: Cell)
+    -> Option<Path> {
+
+    let inside = |c: Cell| c.0 >= lo.0 && c.1 >= lo.1 && c.0 <= hi.0 && c.1 <= hi.1;
+    let usable = |c: Cell| inside(c) && grid.passable(c.0 as i64, c.1 as i64);
+
+    if !usable(start) || !usable(goal) {
+        return None
+    }
+
+    let width = hi.0 - lo.0 + 1;
+    let index = |c: Cell| ((c.1 - lo.1) * width + (c.0 - lo.0)) as usize;
+    let size  = (width * (hi.1 - lo.1 + 1)) as usize;
+
+    let mut best = vec![u32::max_value(); size];
+    let mut came = vec![None; size];
+    let mut open = BinaryHeap::new();
+
+    best[index(start)] = 0;
+    open.push(Node { est: grid.estimate(start, goal, moves), cost: 0, cell: start });
+
+    while let Some(Node { cost, cell, .. }) = open.pop() {
+        if cell == goal {
+            return Some(Path { cells: unwind(&came, &index, goal), cost: cost })
+        }
+
+        if cost > best[index(cell)] {
+            continue
+        }
+
+        for (next, step) in grid.neighbors(cell, moves) {
+            if !inside(next) {
+                continue
+            }
+
+            let ni       = index(next);
+            let new_cost = cost + step;
+
+            if new_cost < best[ni] {
+                best[ni] = new_cost;
+                came[ni] = Some(cell);
+
+                open.push(Node {
+                    est:  new_cost + grid.estimate(next, goal, moves),
+                    cost: new_cost,
+                    cell: next,
+                });
+            }
+        }
+    }
+
+    None
+}
+
+// Follow the `came` links back from `goal`.
+fn unwind<F>(came: &[Option<Cell>], index: &F, goal: Cell) -> Vec<Cell> where F: Fn(Cell) -> usize {
+    let mut cells = vec![goal];
+    let mut cur   = goal;
+
+    while let Some(prev) = came[index(cur)] {
+        cells.push(prev);
+        cur = prev;
+    }
+
+    cells.reverse();
+    cells
+}
+
+// True if every cell touched by the segment between the centers of `a` and `b` is passable and
+// costs no more than `limit`.
+fn line_clear(grid: &CostGrid, a: Cell, b: Cell, limit: u32) -> bool {
+    let (mut x, mut y) = (a.0 as i64, a.1 as i64);
+
+    let (dx, dy) = ((b.0 as i64 - x).abs(), (b.1 as i64 - y).abs());
+    let (sx, sy) = (if b.0 as i64 > x { 1 } else { -1 }, if b.1 as i64 > y { 1 } else { -1 });
+
+    let ok = |x: i64, y: i64| grid.passable(x, y) && grid.cost(x as u32, y as u32) <= limit;
+
+    let (mut ix, mut iy) = (0, 0);
+
+    while ix < dx || iy < dy {
+        // Which cell boundary the segment crosses next: vertical (< 0), horizontal (> 0), or
+        // both at once through a corner (== 0).
+        let decision = (1 + 2 * ix) * dy - (1 + 2 * iy) * dx;
+
+        if decision == 0 {
+            // Squeezing between two cells diagonally counts as touching both.
+            if !ok(x + sx, y) || !ok(x, y + sy) {
+                return false
+            }
+
+            x  += sx;
+            y  += sy;
+            ix += 1;
+            iy += 1;
+        } else if decision < 0 {
+            x  += sx;
+            ix += 1;
+        } else {
+            y  += sy;
+            iy += 1;
+        }
+
+        if !ok(x, y) {
+            return false
+        }
+    }
+
+    true
+}
+
+// An entry in the A* open list. Ordered so that `BinaryHeap`, a max-heap, pops the lowest
+// estimate first, preferring nodes closer to the goal and then lower cells on ties.
+#[derive(PartialEq,Eq)]
+struct Node {
+    est:  u32,
+    cost: u32,
+    cell: Cell,
+}
+
+impl Ord for Node {
+    fn cmp(&self, other: &Node) -> Ordering {
+        match other.est.cmp(&self.est) {
+            Ordering::Equal => {},
+            ord             => return ord,
+        }
+
+        match self.cost.cmp(&other.cost) {
+            Ordering::Equal => {},
+            ord             => return ord,
+        }
+
+        (other.cell.1, other.cell.0).cmp(&(self.cell.1, self.cell.0))
+    }
+}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Node) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
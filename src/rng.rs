@@ -0,0 +1,188 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! The engine's random number generator is a PCG32 (permuted congruential generator). It isn't
+//! cryptographically secure, but it's fast, small enough to snapshot, and produces the same
+//! sequence on every platform given the same seed, which is what replays and lockstep need.
+
+use entity::wire;
+
+const MULTIPLIER: u64 = 6364136223846793005;
+
+// The stream used by `Rng::new`.
+const DEFAULT_STREAM: u64 = 1442695040888963407;
+
+/// A deterministic, seedable random number generator.
+///
+/// Gameplay code should draw its randomness from the `Rng` owned by the entity `Manager` rather
+/// than from the OS, so that two simulations started from the same seed (or the same snapshot)
+/// make the same decisions.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Rng {
+    state: u64,
+
+    // Always odd.
+    inc: u64,
+}
+
+impl Rng {
+    /// Create a generator from a seed.
+    pub fn new(seed: u64) -> Rng {
+        Rng::with_stream(seed, DEFAULT_STREAM)
+    }
+
+    /// Create a generator from a seed and a stream selector. Generators with the same seed but
+    /// different streams produce unrelated sequences.
+    pub fn with_stream(seed: u64, stream: u64) -> Rng {
+        let mut rng = Rng {
+            state: 0,
+            inc:   (stream << 1) | 1,
+        };
+
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+
+        rng
+    }
+
+    /// Split off a new generator, advancing this one. This is useful for handing a system its own
+    /// generator, so that the numbers it draws don't depend on how many other systems drew.
+    pub fn fork(&mut self) -> Rng {
+        let seed   = self.next_u64();
+        let stream = self.next_u64();
+
+        Rng::with_stream(seed, stream)
+    }
+
+    /// Produce a uniformly distributed `u32`.
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+
+        self.state = old.wrapping_mul(MULTIPLIER).wrapping_add(self.inc);
+
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot        = (old >> 59) as u32;
+
+        xorshifted.rotate_right(rot)
+    }
+
+    /// Produce a uniformly distributed `u64`.
+    pub fn next_u64(&mut self) -> u64 {
+        let hi = self.next_u32() as u64;
+        let lo = self.next_u32() as u64;
+
+        (hi << 32) | lo
+    }
+
+    /// Produce a uniformly distributed `f32` in the range [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        // 24 bits is all the precision an f32 mantissa has.
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    /// Produce a uniformly distributed `u32` in the range [lo, hi). Returns `lo` if the range is
+    /// empty.
+    pub fn range(&mut self, lo: u32, hi: u32) -> u32 {
+        if hi <= lo {
+            return lo
+        }
+
+        let span = hi - lo;
+
+        // Reject the values that would bias the result towards the low end of the range.
+        let threshold = span.wrapping_neg() % span;
+
+        loop {
+            let x = self.next_u32();
+
+            if x >= threshold {
+                return lo + x % span
+            }
+        }
+    }
+
+    /// Produce a uniformly distributed `f32` in the range [lo, hi).
+    pub fn range_f32(&mut self, lo: f32, hi: f32) -> f32 {
+        lo + (hi - lo) * self.next_f32()
+    }
+
+    /// Return true with probability `p`.
+    pub fn chance(&mut self, p: f32) -> bool {
+        self.next_f32() < p
+    }
+
+    /// Pick a uniformly random element of `items`, or `None` if it's empty.
+    pub fn choose<'x, T>(&mut self, items: &'x [T]) -> Option<&'x T> {
+        if items.is_empty() {
+            None
+        } else {
+            Some(&items[self.range(0, items.len() as u32) as usize])
+        }
+    }
+
+    /// Put `items` into a uniformly random order.
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.range(0, i as u32 + 1) as usize;
+            items.swap(i, j);
+        }
+    }
+
+    /// Restore a generator from its FlatBuffer representation. It will continue the sequence of
+    /// the generator it was saved from.
+    pub fn from_wire(w: &wire::RngState) -> Rng {
+        Rng {
+            state: w.state(),
+            inc:   w.inc() | 1,
+        }
+    }
+
+    /// Convert to FlatBuffer representation, for snapshots and saved games.
+    pub fn to_wire(&self) -> wire::RngState {
+        wire::RngState::new(self.state, self.inc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use entity::wire;
+    use rng::Rng;
+
+    #[test]
+    fn rng_state_round_trip() {
+        let mut rng = Rng::with_stream(42, 7);
+
+        rng.next_u64();
+
+        let mut back = Rng::from_wire(&rng.to_wire());
+
+        assert_eq!(back, rng);
+
+        for _ in 0..16 {
+            assert_eq!(back.next_u32(), rng.next_u32());
+        }
+    }
+
+    #[test]
+    fn rng_state_even_increment() {
+        // The increment must be odd for the generator to have its full period.
+        let rng = Rng::from_wire(&wire::RngState::new(1, 4));
+
+        assert_eq!(rng.to_wire().inc(), 5);
+    }
+}
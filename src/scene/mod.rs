@@ -17,3 +17,9 @@
 
 /// An octree for spatial partitioning.
 pub mod octree;
+
+/// A quadtree for spatial partitioning of flat worlds.
+pub mod quadtree;
+
+pub use self::octree::{EntryID, LooseOctree};
+pub use self::quadtree::LooseQuadtree;
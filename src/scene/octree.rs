@@ -18,22 +18,49 @@
 // Until I'm done w/ the design.
 #![allow(dead_code)]
 
-use core::nonzero::NonZero;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::mem;
+use std::slice;
+
+use num::{Float, ToPrimitive};
 
 use math;
-use math::{BoundingCube,Octant,S0,SX,SY,SZ};
+use memory;
+use math::{BoundingCube,BoundingSphere,Frustum,Octant,Visibility,S0,SX,SY,SZ};
 use units::*;
 
 /// An EntryID identifies an object which has been inserted into a `LooseOctree`.
 pub type EntryID = u32;
 
-// A NodeID is a bitwise negation of an index into a LooseOctree's `nodes` field. Hence, the "null"
-// node corresponds to the node at index u32::MAX.
+// Nodes are allocated in blocks of 8, one slot per octant, and the children of a node always share
+// a single block. This means that visiting the children of a node touches one contiguous run of
+// memory rather than 8 scattered ones, and that a node's octant is implied by its position in the
+// block. The root lives in slot 0 of a block of its own.
+const BLOCK_SIZE: u32 = 8;
+
+// A NodeID is an index into a LooseOctree's `nodes` field.
+//
+// This used to be a `NonZero` holding the bitwise negation of the index, so that `Option<NodeID>`
+// would be pointer-sized. That requires the unstable `core` crate, which isn't worth it for 4 bytes
+// per child pointer.
 #[derive(Clone,Copy,Debug,PartialEq,Eq)]
-struct NodeID(NonZero<u32>);
+struct NodeID(u32);
 
 impl NodeID {
-    fn as_index(self) -> usize { !*self.0 as usize }
+    fn as_index(self) -> usize { self.0 as usize }
+
+    // The block which this node is a part of.
+    fn block(self) -> u32 { self.0 / BLOCK_SIZE }
+
+    // Which octant of its parent this node occupies. This is meaningless for the root.
+    fn octant(self) -> Octant { Octant::from_bits_truncate((self.0 % BLOCK_SIZE) as u8) }
+
+    // The ID of the node in slot `octant` of `block`.
+    fn in_block(block: u32, octant: Octant) -> NodeID {
+        NodeID(block * BLOCK_SIZE + octant.bits() as u32)
+    }
 }
 
 /// A Loose Octree is a data structure for maintaining the locations of objects in 3D space.
@@ -41,47 +68,210 @@ pub struct LooseOctree<T> {
     // The node which is the root of the tree.
     root: NodeID,
 
-    // The nodes of the octree.
+    // The nodes of the octree, in blocks of `BLOCK_SIZE`. Slots which aren't in use are marked as
+    // not `live`.
     nodes: Vec<Node>,
 
-    // The nodes hold indices into this array.
+    // Blocks which have been released, and can be handed out again before growing `nodes`.
+    free_blocks: Vec<u32>,
+
+    // The nodes hold indices into this array. Removed entries stay behind with no value, and their
+    // slots are handed out again by `insert`.
     entries: Vec<Entry<T>>,
 
+    // Slots in `entries` which have been removed.
+    free_entries: Vec<EntryID>,
+
     // The smallest dimension that any segment of the octree may have.
     min_dist: Meters,
+
+    // How much larger a node's loose bounds are than its `bcube`. See `LooseOctree::new`.
+    loose: Meters,
+
+    // Scratch space for the traversal in `query`, kept around so that queries don't allocate once
+    // it has grown to the depth of the tree.
+    stack: RefCell<Vec<NodeID>>,
 }
 
 impl<T> LooseOctree<T> {
     /// Create a new octree with an initial root node containing the given bounding box, and which
     /// will never produce a node less than half of `min` meters to a side.
-    pub fn new(initial: BoundingCube, min: Meters) -> LooseOctree<T> {
+    ///
+    /// Everything stored in a node lies within a cube `loose` times the size of the node, so an
+    /// entry can move further without changing nodes when `loose` is larger, at the cost of queries
+    /// visiting more nodes. It must be greater than 1; 2 is the traditional choice.
+    pub fn new(initial: BoundingCube, min: Meters, loose: f32) -> LooseOctree<T> {
+        assert!(loose > 1.0, "loose factor must be greater than 1, got {}", loose);
+
         let mut octree = LooseOctree {
-            root:     NodeID(unsafe { NonZero::new(!0) }),
-            nodes:    vec![],
-            entries:  vec![],
-            min_dist: min,
+            root:        NodeID(0),
+            nodes:       vec![],
+            free_blocks: vec![],
+            entries:     vec![],
+            free_entries: vec![],
+            min_dist:    min,
+            loose:       Meters(loose),
+            stack:       RefCell::new(vec![]),
         };
 
-        octree.root = octree.new_node(Node {
-            bcube:    initial,
-            octant:   S0,
-            parent:   None,
-            children: [None; 8],
-            contents: vec![],
-        });
+        let block = octree.alloc_block();
+
+        octree.root = NodeID::in_block(block, S0);
+
+        *octree.node_by_id_mut(octree.root) = Node::live(initial, None);
 
         octree
      }
 
-    // Create a new node within the tree.
-    #[inline] fn new_node(&mut self, n: Node) -> NodeID {
-        let idx = self.nodes.len() as u32;
+    /// Build an octree holding every `(value, bounds)` pair in `entries`, which will never produce
+    /// a node less than half of `min` meters to a side and has the given loose factor (see `new`).
+    ///
+    /// This is much faster than inserting the entries one at a time into a small tree: the root is
+    /// sized to enclose all of them up front, so it never has to grow, and the entries are inserted
+    /// in Morton order, so nodes which are near each other in space are allocated near each other
+    /// in memory. Entry IDs are assigned in that order, not the order of `entries`; use `iter` to
+    /// find out which value got which ID.
+    pub fn from_entries<I>(entries: I, min: Meters, loose: f32) -> LooseOctree<T>
+        where I: IntoIterator<Item=(T, BoundingCube)> {
+
+        let entries: Vec<(T, BoundingCube)> = entries.into_iter().collect();
+
+        let initial = match entries.first() {
+            Some(&(_, first)) => {
+                let reach = |b: &BoundingCube, sign: f32| {
+                    let d = b.half_edge * Meters(sign);
+                    b.center + vec3!(d, d, d)
+                };
+
+                let mut lo = reach(&first, -1.0);
+                let mut hi = reach(&first,  1.0);
+                let mut biggest = first.half_edge;
+
+                for &(_, ref b) in entries.iter() {
+                    let (l, h) = (reach(b, -1.0), reach(b, 1.0));
+
+                    lo = vec3!(lo.x.min(l.x), lo.y.min(l.y), lo.z.min(l.z));
+                    hi = vec3!(hi.x.max(h.x), hi.y.max(h.y), hi.z.max(h.z));
+
+                    biggest = biggest.max(b.half_edge);
+                }
+
+                let extent = (hi - lo).scaled(Meters(0.5));
+
+                // The root has to contain every center, and be big enough to hold the biggest
+                // entry without growing.
+                let half_edge = extent.x.max(extent.y).max(extent.z)
+                    .max(biggest / Meters(loose - 1.0))
+                    .max(min);
+
+                BoundingCube { center: lo + extent, half_edge: half_edge }
+            },
+
+            None => BoundingCube { center: vec3!(Meters(0.0), Meters(0.0), Meters(0.0)),
+                                   half_edge: min },
+        };
+
+        let mut octree = LooseOctree::new(initial, min, loose);
 
-        self.nodes.push(n);
+        let mut entries = entries;
+
+        entries.sort_by(|a, b| {
+            morton_code(&initial, a.1.center).cmp(&morton_code(&initial, b.1.center))
+        });
+
+        for (val, bcube) in entries.into_iter() {
+            octree.insert(val, bcube);
+        }
 
-        // !idx is non-zero as long as self.nodes.len() is less than u32::MAX. Pretty safe bet, or
-        // else we're using 120GB of RAM on the tree's nodes alone =].
-        NodeID(unsafe { NonZero::new(!idx) })
+        octree
+    }
+
+    /// Compact the storage of the tree. Nodes are laid out again breadth-first from the root, so
+    /// that the blocks visited early in a query are next to each other, and blocks freed by removed
+    /// or moved entries are given back. This is worth doing after a lot of churn (e.g. once a
+    /// level has finished streaming in). Entry IDs are unaffected.
+    pub fn rebuild(&mut self) {
+        let live_blocks = self.nodes.len() / BLOCK_SIZE as usize - self.free_blocks.len();
+
+        let mut nodes = Vec::with_capacity(live_blocks * BLOCK_SIZE as usize);
+
+        for _ in 0..BLOCK_SIZE {
+            nodes.push(Node::dead());
+        }
+
+        let root = NodeID::in_block(0, S0);
+
+        // Pairs of (old ID, new ID), in the order the new blocks were handed out.
+        let mut queue = VecDeque::new();
+
+        queue.push_back((self.root, root));
+
+        while let Some((old, new)) = queue.pop_front() {
+            let mut node = mem::replace(self.node_by_id_mut(old), Node::dead());
+
+            if let Some(old_block) = node.children {
+                let new_block = (nodes.len() as u32) / BLOCK_SIZE;
+
+                for _ in 0..BLOCK_SIZE {
+                    nodes.push(Node::dead());
+                }
+
+                for i in 0..BLOCK_SIZE {
+                    let octant = Octant::from_bits_truncate(i as u8);
+                    let child  = NodeID::in_block(old_block, octant);
+
+                    if self.node_by_id(child).live {
+                        self.node_by_id_mut(child).parent = Some(new);
+                        queue.push_back((child, NodeID::in_block(new_block, octant)));
+                    }
+                }
+
+                node.children = Some(new_block);
+            }
+
+            for &ent_id in node.contents.iter() {
+                self.entries[ent_id as usize].node = new;
+            }
+
+            nodes[new.as_index()] = node;
+        }
+
+        self.nodes = nodes;
+        self.root  = root;
+        self.free_blocks.clear();
+
+        // Removed entries at the end of `entries` can go too.
+        while self.entries.last().map_or(false, |e| e.val.is_none()) {
+            self.entries.pop();
+        }
+
+        let len = self.entries.len() as EntryID;
+
+        self.free_entries.retain(|&id| id < len);
+    }
+
+    // Get an unused block of nodes, all of which are dead.
+    fn alloc_block(&mut self) -> u32 {
+        if let Some(block) = self.free_blocks.pop() {
+            return block
+        }
+
+        let block = (self.nodes.len() as u32) / BLOCK_SIZE;
+
+        for _ in 0..BLOCK_SIZE {
+            self.nodes.push(Node::dead());
+        }
+
+        block
+    }
+
+    // Return a block to the free list. Every node in it must already be dead.
+    fn free_block(&mut self, block: u32) {
+        debug_assert!((0..BLOCK_SIZE).all(|i| {
+            !self.nodes[(block * BLOCK_SIZE + i) as usize].live
+        }));
+
+        self.free_blocks.push(block);
     }
 
     #[inline] fn node_by_id(&self, id: NodeID) -> &Node {
@@ -92,73 +282,354 @@ impl<T> LooseOctree<T> {
         &mut self.nodes[id.as_index()]
     }
 
-    // Free a node from the tree. This re-arranges the backing array by swapping the last element
-    // with the element being freed. This requires some fixup work (to correct the trees internal
-    // pointers) which makes up the majority of this function.
+    // Return the child of `id` in the given octant, if it exists.
+    #[inline] fn child(&self, id: NodeID, octant: Octant) -> Option<NodeID> {
+        match self.node_by_id(id).children {
+            Some(block) => {
+                let child = NodeID::in_block(block, octant);
+
+                if self.node_by_id(child).live { Some(child) } else { None }
+            },
+            None => None,
+        }
+    }
+
+    // Free a node from the tree. Since nodes never move once they're allocated, this just marks the
+    // node as dead, and releases its block if that was the last live node in it.
     fn free_node(&mut self, id: NodeID) {
         debug_assert!(id.as_index() < self.nodes.len());
         debug_assert!(id != self.root);
-        debug_assert!(self.nodes.len() > 0);
 
-        // This is a special case, if the node is the last element then there's no fixup required.
-        if id.as_index() == self.nodes.len() - 1 {
-            self.nodes.pop();
-            return
-        }
+        let parent = self.node_by_id(id).parent;
 
-        // Remove the last element
-        let last = self.nodes.pop().unwrap();
+        *self.node_by_id_mut(id) = Node::dead();
 
-        // Fixup references to `last` so that they reference the location of the element we're about
-        // to replace.
+        let block = id.block();
 
-        if let Some(parent_id) = last.parent {
-            self.nodes[parent_id.as_index()].children[last.octant.bits() as usize] = Some(id);
+        if (0..BLOCK_SIZE).all(|i| !self.nodes[(block * BLOCK_SIZE + i) as usize].live) {
+            self.free_block(block);
+
+            if let Some(parent_id) = parent {
+                self.node_by_id_mut(parent_id).children = None;
+            }
         }
+    }
+
+    // Move the node `from` into the (dead) slot `to`, fixing up everything which refers to it.
+    fn move_node(&mut self, from: NodeID, to: NodeID) {
+        debug_assert!(!self.node_by_id(to).live);
+
+        let node = mem::replace(self.node_by_id_mut(from), Node::dead());
+
+        if let Some(block) = node.children {
+            for i in 0..BLOCK_SIZE {
+                let child = &mut self.nodes[(block * BLOCK_SIZE + i) as usize];
 
-        for &child in last.children.iter() {
-            if let Some(child_id) = child {
-                self.nodes[child_id.as_index()].parent = Some(id);
+                if child.live {
+                    child.parent = Some(to);
+                }
             }
         }
 
-        for &ent_id in last.contents.iter() {
-            self.entries[ent_id as usize].node = id;
+        for &ent_id in node.contents.iter() {
+            self.entries[ent_id as usize].node = to;
         }
 
-        // Overwrite the "freed" node with the element we removed from the end.
-        self.nodes[id.as_index()] = last;
+        if self.root == from {
+            self.root = to;
+        }
+
+        *self.node_by_id_mut(to) = node;
     }
 
-    /// Insert an object into the octree.
+    /// Insert an object into the octree. The returned ID may be one which belonged to an entry that
+    /// has since been removed.
     pub fn insert(&mut self, val: T, bcube: BoundingCube) -> EntryID {
-        let ent_id = self.entries.len() as EntryID;
-
         // It's quite frustrating that this needs to be here, rather than in the invocation of
         // self.get_node().
         let root = self.root;
 
-        let node = self.get_node(root, bcube);
+        let node  = self.get_node(root, bcube);
+        let entry = Entry { bcube: bcube, val: Some(val), node: node };
+
+        let ent_id = match self.free_entries.pop() {
+            Some(ent_id) => { self.entries[ent_id as usize] = entry; ent_id },
+            None         => { self.entries.push(entry); (self.entries.len() - 1) as EntryID },
+        };
 
-        self.entries.push(Entry { bcube: bcube, val: val, node: node });
+        self.node_by_id_mut(node).contents.push(ent_id);
 
         ent_id
     }
 
     /// Modify the location of an existing entry in the tree.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `ent_id` has been removed.
     pub fn adjust(&mut self, ent_id: EntryID, bcube: BoundingCube) {
-        let current_node = self.entries[ent_id as usize].node;
+        assert!(self.entries[ent_id as usize].val.is_some(), "adjusting removed entry {}", ent_id);
+
+        let start = self.entries[ent_id as usize].node;
 
         // Get the node which *should* contain this entry.
-        let new_node = self.get_node(current_node, bcube);
+        let new_node = self.get_node(start, bcube);
+
+        // Growing the tree upward can move the old root (and so possibly the node which holds this
+        // entry), so look up where the entry is now.
+        let current_node = self.entries[ent_id as usize].node;
+
+        self.entries[ent_id as usize].bcube = bcube;
 
         if new_node != current_node {
             self.node_by_id_mut(current_node).contents.retain(|&x| { x != ent_id });
-            self.maybe_free(current_node);
-
             self.node_by_id_mut(new_node).contents.push(ent_id);
             self.entries[ent_id as usize].node = new_node;
+
+            self.maybe_free(current_node);
+        }
+    }
+
+    /// Remove an entry from the tree, returning its value, or `None` if it was already removed.
+    /// Nodes left empty by the removal are freed.
+    pub fn remove(&mut self, ent_id: EntryID) -> Option<T> {
+        let (node, val) = match self.entries.get_mut(ent_id as usize) {
+            Some(entry) => (entry.node, entry.val.take()),
+            None        => return None,
+        };
+
+        if val.is_some() {
+            self.node_by_id_mut(node).contents.retain(|&x| { x != ent_id });
+            self.free_entries.push(ent_id);
+
+            self.maybe_free(node);
         }
+
+        val
+    }
+
+    /// The value and bounds of an entry, or `None` if it has been removed.
+    pub fn get(&self, ent_id: EntryID) -> Option<(&T, BoundingCube)> {
+        self.entries.get(ent_id as usize)
+            .and_then(|e| e.val.as_ref().map(|val| (val, e.bcube)))
+    }
+
+    /// A mutable reference to the value of an entry, or `None` if it has been removed. To change
+    /// its bounds, use `adjust`.
+    pub fn get_mut(&mut self, ent_id: EntryID) -> Option<&mut T> {
+        self.entries.get_mut(ent_id as usize).and_then(|e| e.val.as_mut())
+    }
+
+    /// Iterate over every entry in the tree, as its ID, value and bounds.
+    pub fn iter<'x>(&'x self) -> Box<Iterator<Item=(EntryID, &'x T, BoundingCube)> + 'x> {
+        Box::new(self.entries.iter().enumerate().filter_map(|(i, e)| {
+            e.val.as_ref().map(|val| (i as EntryID, val, e.bcube))
+        }))
+    }
+
+    /// The number of entries in the tree.
+    pub fn len(&self) -> usize {
+        self.entries.len() - self.free_entries.len()
+    }
+
+    /// Add the tree's nodes and entries to `report`, as `name` under `subsystem`.
+    pub fn report_memory(&self, report: &mut memory::Report, subsystem: &'static str, name: &str) {
+        let spilled = self.nodes.iter().fold(0, |total, node| match node.contents {
+            Contents::Heap(ref ids) => total + ids.capacity() * mem::size_of::<EntryID>(),
+            _                       => total,
+        });
+
+        report.add(subsystem, format!("{} nodes", name), self.nodes.len(),
+                   self.nodes.capacity() * mem::size_of::<Node>() + spilled +
+                   self.free_blocks.capacity() * mem::size_of::<u32>());
+
+        report.add(subsystem, format!("{} entries", name), self.len(),
+                   self.entries.capacity() * mem::size_of::<Entry<T>>() +
+                   self.free_entries.capacity() * mem::size_of::<EntryID>());
+    }
+
+    /// Move an existing entry so that it's bounded by `bsphere`.
+    pub fn adjust_sphere(&mut self, ent_id: EntryID, bsphere: &BoundingSphere) {
+        self.adjust(ent_id, bsphere.bcube())
+    }
+
+    /// Find every entry whose bounding cube intersects the sphere `region`, which is what an
+    /// area-of-effect wants. Like `query`, `out` is cleared first.
+    pub fn query_sphere(&self, region: &BoundingSphere, out: &mut Vec<EntryID>) {
+        self.query(&region.bcube(), out);
+
+        let entries = &self.entries;
+
+        out.retain(|&id| region.intersects_bcube(&entries[id as usize].bcube));
+    }
+
+    /// Find every entry whose bounding cube intersects `region`. The IDs of those entries are
+    /// appended to `out`, which is cleared first. Since `out` keeps its capacity, reusing the same
+    /// `Vec` from frame to frame means that a query doesn't allocate in the steady state.
+    pub fn query(&self, region: &BoundingCube, out: &mut Vec<EntryID>) {
+        out.clear();
+
+        let mut stack = self.stack.borrow_mut();
+
+        stack.clear();
+
+        // Anything stored in a node (or below it) is within the node's loose bounds. Children are
+        // tested before they're pushed, so that all 8 of them are checked while their block is in
+        // cache.
+        if self.loose_bounds(self.node_by_id(self.root)).intersects(region) {
+            stack.push(self.root);
+        }
+
+        while let Some(id) = stack.pop() {
+            let node = self.node_by_id(id);
+
+            for &ent_id in node.contents.iter() {
+                if self.entries[ent_id as usize].bcube.intersects(region) {
+                    out.push(ent_id);
+                }
+            }
+
+            if let Some(block) = node.children {
+                let first = (block * BLOCK_SIZE) as usize;
+
+                let children = &self.nodes[first..first + BLOCK_SIZE as usize];
+
+                for (i, child) in children.iter().enumerate() {
+                    if child.live && self.loose_bounds(child).intersects(region) {
+                        stack.push(NodeID((first + i) as u32));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Find every entry which is at least partially inside `frustum`, such as everything that the
+    /// camera might be able to see. Like `query`, `out` is cleared first.
+    ///
+    /// Once a node's loose bounds are found to be entirely inside the frustum, everything below it
+    /// is taken without testing any further.
+    pub fn query_frustum(&self, frustum: &Frustum, out: &mut Vec<EntryID>) {
+        out.clear();
+
+        let mut stack = self.stack.borrow_mut();
+
+        stack.clear();
+
+        match frustum.intersects(&self.loose_bounds(self.node_by_id(self.root))) {
+            Visibility::Zero    => return,
+            Visibility::Full    => return self.collect_subtree(self.root, out),
+            Visibility::Partial => stack.push(self.root),
+        }
+
+        while let Some(id) = stack.pop() {
+            let node = self.node_by_id(id);
+
+            for &ent_id in node.contents.iter() {
+                if frustum.intersects(&self.entries[ent_id as usize].bcube) != Visibility::Zero {
+                    out.push(ent_id);
+                }
+            }
+
+            if let Some(block) = node.children {
+                let first = (block * BLOCK_SIZE) as usize;
+
+                let children = &self.nodes[first..first + BLOCK_SIZE as usize];
+
+                for (i, child) in children.iter().enumerate() {
+                    if !child.live {
+                        continue
+                    }
+
+                    let child_id = NodeID((first + i) as u32);
+
+                    match frustum.intersects(&self.loose_bounds(child)) {
+                        Visibility::Zero    => {},
+                        Visibility::Full    => self.collect_subtree(child_id, out),
+                        Visibility::Partial => stack.push(child_id),
+                    }
+                }
+            }
+        }
+    }
+
+    // Append every entry stored in `id` or any of its descendants to `out`.
+    fn collect_subtree(&self, id: NodeID, out: &mut Vec<EntryID>) {
+        let node = self.node_by_id(id);
+
+        out.extend(node.contents.iter().cloned());
+
+        if let Some(block) = node.children {
+            for bits in 0..BLOCK_SIZE {
+                let child_id = NodeID::in_block(block, Octant::from_bits_truncate(bits as u8));
+
+                if self.node_by_id(child_id).live {
+                    self.collect_subtree(child_id, out);
+                }
+            }
+        }
+    }
+
+    /// Call `f` with the bounds and depth (0 for the root) of every node in the tree, parents
+    /// before their children. This is meant for debug visualization; see `grafix::debug`.
+    ///
+    /// These are the nodes' tight bounds. An entry in a node may reach outside of them by up to the
+    /// loose factor given to `new`.
+    pub fn debug_nodes(&self, f: &mut FnMut(&BoundingCube, u32)) {
+        self.debug_subtree(self.root, 0, f);
+    }
+
+    fn debug_subtree(&self, id: NodeID, depth: u32, f: &mut FnMut(&BoundingCube, u32)) {
+        let node = self.node_by_id(id);
+
+        f(&node.bcube, depth);
+
+        if let Some(block) = node.children {
+            for bits in 0..BLOCK_SIZE {
+                let child_id = NodeID::in_block(block, Octant::from_bits_truncate(bits as u8));
+
+                if self.node_by_id(child_id).live {
+                    self.debug_subtree(child_id, depth + 1, f);
+                }
+            }
+        }
+    }
+
+    /// Sort the contents of every node by the Morton (Z-order) code of each entry's center, so that
+    /// entries which are near each other in space are also visited one after the other. This is
+    /// worth doing once after loading a large number of entries which won't move (e.g. the static
+    /// props of a map); entries inserted or moved afterwards are simply appended.
+    pub fn sort_by_morton(&mut self) {
+        let loose = self.loose;
+
+        let LooseOctree { ref mut nodes, ref entries, .. } = *self;
+
+        for node in nodes.iter_mut().filter(|n| n.live) {
+            let bounds = BoundingCube {
+                center:    node.bcube.center,
+                half_edge: node.bcube.half_edge * loose,
+            };
+
+            let code = |ent_id: EntryID| {
+                morton_code(&bounds, entries[ent_id as usize].bcube.center)
+            };
+
+            node.contents.sort_by(|&a, &b| code(a).cmp(&code(b)));
+        }
+    }
+
+    // The region which is guaranteed to contain everything stored in `node`.
+    #[inline] fn loose_bounds(&self, node: &Node) -> BoundingCube {
+        BoundingCube {
+            center:    node.bcube.center,
+            half_edge: node.bcube.half_edge * self.loose,
+        }
+    }
+
+    // The largest `half_edge` of an entry which can be stored in a node with the given `half_edge`.
+    // Since the entry's center lies within the node, it stays inside the loose bounds as long as it
+    // extends no more than `loose - 1` node half-edges past its center.
+    #[inline] fn max_half_edge(&self, half_edge: Meters) -> Meters {
+        half_edge * (self.loose - Meters(1.0))
     }
 
     // Release a node if it has no contents and no children. Otherwise leave it unaffected.
@@ -167,9 +638,7 @@ impl<T> LooseOctree<T> {
         if let Some(parent_id) = self.node_by_id(id).parent {
 
             // Only free the node if the node has no children and no contents.
-            if self.node_by_id(id).contents.is_empty()
-                && !self.node_by_id(id).children.iter().any(|&x| x.is_some()) {
-
+            if self.node_by_id(id).contents.is_empty() && self.node_by_id(id).children.is_none() {
                 self.free_node(id);
 
                 self.maybe_free(parent_id);
@@ -190,13 +659,14 @@ impl<T> LooseOctree<T> {
             //  1. `bcube` is too small for the node, and needs to go in a child node.
             //  2. `bcube` is too big for this node, and needs to go in a parent node.
             //  3. `bcube` "fits" in this node, and so we just return this node's ID.
-            Some(octant) => if node_bcube.half_edge / Meters(2.0) > bcube.half_edge
-                            && node_bcube.half_edge < self.min_dist {
+            Some(octant) => if self.max_half_edge(node_bcube.half_edge * Meters(0.5))
+                                   > bcube.half_edge
+                            && node_bcube.half_edge >= self.min_dist {
                 // Case 1: recurse on a child node.
                 let child = self.get_child(id, octant);
 
                 self.get_node(child, bcube)
-            } else if node_bcube.half_edge < bcube.half_edge {
+            } else if self.max_half_edge(node_bcube.half_edge) < bcube.half_edge {
                 // Case 2: Recurse on the parent node, creating one if it doesn't exist.
                 let parent = match self.node_by_id(id).parent {
                     Some(parent) => parent,
@@ -221,64 +691,107 @@ impl<T> LooseOctree<T> {
 
     // Get a child node of `id`, creating one if it doesn't already exist.
     fn get_child(&mut self, id: NodeID, octant: Octant) -> NodeID {
-        if let Some(child) = self.node_by_id(id).children[octant.bits() as usize] {
+        if let Some(child) = self.child(id, octant) {
             // Child already exists, just return it.
             return child;
         }
 
         // Okay, we've gotta construct a child node. The math is straightforward.
 
+        let block = match self.node_by_id(id).children {
+            Some(block) => block,
+            None        => {
+                let block = self.alloc_block();
+                self.node_by_id_mut(id).children = Some(block);
+                block
+            },
+        };
+
         let old_bcube = self.node_by_id(id).bcube;
 
         let new_center = old_bcube.center
                        + octant.as_vector().scaled(old_bcube.half_edge / Meters(2.0));
 
-        let child = self.new_node(Node {
-            bcube: BoundingCube {
-                center:    new_center,
-                half_edge: old_bcube.half_edge * Meters(0.5),
-            },
-            octant:   octant,
-            parent:   Some(id),
-            children: [None; 8],
-            contents: vec![],
-        });
+        let child = NodeID::in_block(block, octant);
 
-        self.node_by_id_mut(id).children[octant.bits() as usize] = Some(child);
+        *self.node_by_id_mut(child) = Node::live(BoundingCube {
+            center:    new_center,
+            half_edge: old_bcube.half_edge * Meters(0.5),
+        }, Some(id));
 
         child
     }
 
-    // Create a parent node of `id` which comes closer to containing `v` than `id` itself (though it
-    // might not actually end up containing `v`).
+    // Create a parent node of the root `id` which comes closer to containing `v` than `id` itself
+    // (though it might not actually end up containing `v`). Since children have to live in their
+    // parent's block, this moves `id` into a new block, and returns the ID of the new root.
     fn make_parent_toward(&mut self, id: NodeID, v: math::Vec3<Meters>) -> NodeID {
+        debug_assert!(id == self.root);
+
         let old_bcube = self.node_by_id(id).bcube;
         let diff      = v - old_bcube.center;
 
         // Which octant of the parent will be `id`?
-        let octant = if diff.x < Meters(0.0) { S0 } else { SX }
-                   | if diff.y < Meters(0.0) { S0 } else { SY }
-                   | if diff.z < Meters(0.0) { S0 } else { SZ };
+        let octant = if diff.x < Meters(0.0) { SX } else { S0 }
+                   | if diff.y < Meters(0.0) { SY } else { S0 }
+                   | if diff.z < Meters(0.0) { SZ } else { S0 };
 
         let new_center = old_bcube.center + octant.as_vector().scaled(-old_bcube.half_edge);
 
-        let node = self.new_node(Node {
-            bcube: BoundingCube {
-                center:    new_center,
-                half_edge: old_bcube.half_edge * Meters(2.0),
-            },
-            octant:   S0,
-            parent:   None, // This is a top-level node.
-            children: [None; 8],
-            contents: vec![],
-        });
+        let children = self.alloc_block();
+        let moved    = NodeID::in_block(children, octant);
+
+        self.move_node(id, moved);
+        self.free_block(id.block());
 
-        self.node_by_id_mut(node).children[octant.bits() as usize] = Some(id);
+        let node = NodeID::in_block(self.alloc_block(), S0);
+
+        let mut parent = Node::live(BoundingCube {
+            center:    new_center,
+            half_edge: old_bcube.half_edge * Meters(2.0),
+        }, None); // This is a top-level node.
+
+        parent.children = Some(children);
+
+        *self.node_by_id_mut(node) = parent;
+
+        self.node_by_id_mut(moved).parent = Some(node);
+        self.root = node;
 
         node
     }
 }
 
+// Spread the low 10 bits of `x` out so that there are two zero bits between each of them.
+fn spread_bits(x: u32) -> u32 {
+    let mut x = x & 0x3ff;
+
+    x = (x | (x << 16)) & 0x030000ff;
+    x = (x | (x <<  8)) & 0x0300f00f;
+    x = (x | (x <<  4)) & 0x030c30c3;
+    x = (x | (x <<  2)) & 0x09249249;
+
+    x
+}
+
+// The 30-bit Morton code of `v`'s position within `bounds`, quantized to 10 bits per axis.
+fn morton_code(bounds: &BoundingCube, v: math::Vec3<Meters>) -> u32 {
+    let min  = bounds.center - vec3!(bounds.half_edge, bounds.half_edge, bounds.half_edge);
+    let size = bounds.half_edge * Meters(2.0);
+
+    let quantize = |x: Meters| -> u32 {
+        let t = (x / size).max(Meters(0.0)).min(Meters(1.0));
+
+        (t * Meters(1023.0)).to_u32().unwrap_or(0)
+    };
+
+    let rel = v - min;
+
+    spread_bits(quantize(rel.x))
+        | spread_bits(quantize(rel.y)) << 1
+        | spread_bits(quantize(rel.z)) << 2
+}
+
 struct Entry<T> {
     // A Cube which bounds this entry
     bcube: BoundingCube,
@@ -286,26 +799,153 @@ struct Entry<T> {
     // The node which currently contains this entry
     node: NodeID,
 
-    // The item at this location.
-    val: T,
+    // The item at this location, or `None` if the entry has been removed.
+    val: Option<T>,
 }
 
 struct Node {
     // Cube in space which this node represents. Note that, because this is a Loose Octree, geometry
-    // contained in this node is only guaranteed to inside a bounding cube with a `half_edge`
-    // *twice* that of the `bcube` field.
+    // contained in this node is only guaranteed to inside a bounding cube with a `half_edge` which
+    // is the tree's loose factor times that of the `bcube` field.
     bcube: BoundingCube,
 
-    // Which octant of the parent node contains this node. This will be S0 for the root.
-    octant: Octant,
-
     // This node's parent.
     parent: Option<NodeID>,
 
-    // The nodes which are contained inside of this one.
-    children: [Option<NodeID>; 8],
+    // The block holding the nodes which are contained inside of this one, if any of them exist.
+    children: Option<u32>,
 
     // Indices into the `entries` field of the Octree. This field has the potential to be a
     // bottleneck, since we're going to do lots of naive linear search on it.
-    contents: Vec<EntryID>,
+    contents: Contents,
+
+    // False if this slot of its block isn't in use.
+    live: bool,
+}
+
+impl Node {
+    fn live(bcube: BoundingCube, parent: Option<NodeID>) -> Node {
+        Node {
+            bcube:    bcube,
+            parent:   parent,
+            children: None,
+            contents: Contents::new(),
+            live:     true,
+        }
+    }
+
+    fn dead() -> Node {
+        Node {
+            bcube:    BoundingCube { center: vec3!(Meters(0.0), Meters(0.0), Meters(0.0)),
+                                     half_edge: Meters(0.0) },
+            parent:   None,
+            children: None,
+            contents: Contents::new(),
+            live:     false,
+        }
+    }
+}
+
+// The number of entries a node can hold without allocating.
+const INLINE_CONTENTS: usize = 6;
+
+// The entries stored in a node. Most nodes only hold a few, so up to `INLINE_CONTENTS` of them are
+// kept in the node itself and only nodes holding more spill onto the heap. This saves an allocation
+// (and a pointer chase on every query) for the vast majority of nodes.
+enum Contents {
+    Inline(u8, [EntryID; INLINE_CONTENTS]),
+    Heap(Vec<EntryID>),
+}
+
+impl Contents {
+    fn new() -> Contents {
+        Contents::Inline(0, [0; INLINE_CONTENTS])
+    }
+
+    #[inline] fn as_slice(&self) -> &[EntryID] {
+        match *self {
+            Contents::Inline(len, ref ids) => &ids[..len as usize],
+            Contents::Heap(ref ids)        => &ids[..],
+        }
+    }
+
+    #[inline] fn as_mut_slice(&mut self) -> &mut [EntryID] {
+        match *self {
+            Contents::Inline(len, ref mut ids) => &mut ids[..len as usize],
+            Contents::Heap(ref mut ids)        => &mut ids[..],
+        }
+    }
+
+    #[inline] fn iter(&self) -> slice::Iter<EntryID> {
+        self.as_slice().iter()
+    }
+
+    #[inline] fn is_empty(&self) -> bool {
+        self.as_slice().is_empty()
+    }
+
+    fn push(&mut self, id: EntryID) {
+        let spilled = match *self {
+            Contents::Inline(ref mut len, ref mut ids) => {
+                if (*len as usize) < INLINE_CONTENTS {
+                    ids[*len as usize] = id;
+                    *len += 1;
+                    return
+                }
+
+                let mut heap = Vec::with_capacity(INLINE_CONTENTS * 2);
+                heap.extend(ids.iter().cloned());
+                heap.push(id);
+                heap
+            },
+
+            Contents::Heap(ref mut ids) => {
+                ids.push(id);
+                return
+            },
+        };
+
+        *self = Contents::Heap(spilled);
+    }
+
+    fn retain<F: FnMut(&EntryID) -> bool>(&mut self, mut keep: F) {
+        let shrunk = match *self {
+            Contents::Inline(ref mut len, ref mut ids) => {
+                let mut kept = 0;
+
+                for i in 0..*len as usize {
+                    if keep(&ids[i]) {
+                        ids[kept] = ids[i];
+                        kept += 1;
+                    }
+                }
+
+                *len = kept as u8;
+                return
+            },
+
+            Contents::Heap(ref mut ids) => {
+                ids.retain(keep);
+
+                if ids.len() > INLINE_CONTENTS {
+                    return
+                }
+
+                let mut inline = [0; INLINE_CONTENTS];
+
+                for (slot, &id) in inline.iter_mut().zip(ids.iter()) {
+                    *slot = id;
+                }
+
+                Contents::Inline(ids.len() as u8, inline)
+            },
+        };
+
+        // Move back inline once there's room, so that the allocation is released.
+        *self = shrunk;
+    }
+
+    fn sort_by<F: FnMut(&EntryID, &EntryID) -> Ordering>(&mut self, compare: F) {
+        self.as_mut_slice().sort_by(compare)
+    }
 }
@@ -0,0 +1,500 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// Mirrors the octree, which is still being designed.
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::mem;
+
+use num::{Float, ToPrimitive};
+
+use math;
+use math::BoundingCube;
+use units::*;
+
+use scene::EntryID;
+
+// Nodes are allocated in blocks of 4, one slot per quadrant, just as the octree allocates them in
+// blocks of 8. See `scene::octree` for the details.
+const BLOCK_SIZE: u32 = 4;
+
+// Quadrants are numbered with the low bit set for +x and the next bit set for +y.
+const QX: u8 = 0b01;
+const QY: u8 = 0b10;
+
+// A NodeID is an index into a LooseQuadtree's `nodes` field.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+struct NodeID(u32);
+
+impl NodeID {
+    fn as_index(self) -> usize { self.0 as usize }
+
+    // The block which this node is a part of.
+    fn block(self) -> u32 { self.0 / BLOCK_SIZE }
+
+    // The ID of the node in slot `quadrant` of `block`.
+    fn in_block(block: u32, quadrant: u8) -> NodeID {
+        NodeID(block * BLOCK_SIZE + quadrant as u32)
+    }
+}
+
+// A square in the xy-plane.
+#[derive(Copy,Clone,Debug)]
+struct Square {
+    center:    math::Vec2<Meters>,
+    half_edge: Meters,
+}
+
+impl Square {
+    // The footprint of `bcube` on the xy-plane.
+    fn from_bcube(bcube: &BoundingCube) -> Square {
+        Square {
+            center:    vec2!(bcube.center.x, bcube.center.y),
+            half_edge: bcube.half_edge,
+        }
+    }
+
+    // Return the quadrant containing `v`, if any. Points on a boundary err towards quadrant 0.
+    fn quadrant(&self, v: math::Vec2<Meters>) -> Option<u8> {
+        let diff = v - self.center;
+
+        if diff.x.abs() > self.half_edge || diff.y.abs() > self.half_edge {
+            return None
+        }
+
+        Some(if diff.x > Meters(0.0) { QX } else { 0 } | if diff.y > Meters(0.0) { QY } else { 0 })
+    }
+
+    // A vector pointing from the center of a unit square at the origin to the corner of `quadrant`.
+    fn corner(quadrant: u8) -> math::Vec2<Meters> {
+        vec2!(if quadrant & QX != 0 { Meters(1.0) } else { Meters(-1.0) },
+              if quadrant & QY != 0 { Meters(1.0) } else { Meters(-1.0) })
+    }
+
+    fn intersects(&self, other: &Square) -> bool {
+        let diff  = other.center - self.center;
+        let reach = self.half_edge + other.half_edge;
+
+        diff.x.abs() <= reach && diff.y.abs() <= reach
+    }
+}
+
+/// A Loose Quadtree has the same interface as a `LooseOctree`, but partitions space along the x
+/// and y axes only. It's meant for worlds whose z-extent is trivial (as is the case for most
+/// isometric maps), where it needs half the fan-out and memory of an octree.
+///
+/// The z-components of bounding cubes are ignored entirely, so a query will return every entry
+/// whose footprint on the xy-plane intersects that of the query region.
+pub struct LooseQuadtree<T> {
+    // The node which is the root of the tree.
+    root: NodeID,
+
+    // The nodes of the quadtree, in blocks of `BLOCK_SIZE`.
+    nodes: Vec<Node>,
+
+    // Blocks which have been released, and can be handed out again before growing `nodes`.
+    free_blocks: Vec<u32>,
+
+    // The nodes hold indices into this array.
+    entries: Vec<Entry<T>>,
+
+    // The smallest dimension that any segment of the quadtree may have.
+    min_dist: Meters,
+
+    // How much larger a node's loose bounds are than its `square`.
+    loose: Meters,
+
+    // Scratch space for the traversal in `query`.
+    stack: RefCell<Vec<NodeID>>,
+}
+
+impl<T> LooseQuadtree<T> {
+    /// Create a new quadtree whose root node covers the footprint of `initial`. See
+    /// `LooseOctree::new` for the meaning of `min` and `loose`.
+    pub fn new(initial: BoundingCube, min: Meters, loose: f32) -> LooseQuadtree<T> {
+        assert!(loose > 1.0, "loose factor must be greater than 1, got {}", loose);
+
+        let mut quadtree = LooseQuadtree {
+            root:        NodeID(0),
+            nodes:       vec![],
+            free_blocks: vec![],
+            entries:     vec![],
+            min_dist:    min,
+            loose:       Meters(loose),
+            stack:       RefCell::new(vec![]),
+        };
+
+        let block = quadtree.alloc_block();
+
+        quadtree.root = NodeID::in_block(block, 0);
+
+        quadtree.nodes[quadtree.root.as_index()] = Node::live(Square::from_bcube(&initial), None);
+
+        quadtree
+    }
+
+    // Get an unused block of nodes, all of which are dead.
+    fn alloc_block(&mut self) -> u32 {
+        if let Some(block) = self.free_blocks.pop() {
+            return block
+        }
+
+        let block = (self.nodes.len() as u32) / BLOCK_SIZE;
+
+        for _ in 0..BLOCK_SIZE {
+            self.nodes.push(Node::dead());
+        }
+
+        block
+    }
+
+    // Return `block` to the free list if none of its nodes are live, and report whether it was.
+    fn maybe_free_block(&mut self, block: u32) -> bool {
+        if (0..BLOCK_SIZE).any(|i| self.nodes[(block * BLOCK_SIZE + i) as usize].live) {
+            return false
+        }
+
+        self.free_blocks.push(block);
+
+        true
+    }
+
+    /// Insert an object into the quadtree.
+    pub fn insert(&mut self, val: T, bcube: BoundingCube) -> EntryID {
+        let ent_id = self.entries.len() as EntryID;
+
+        let root = self.root;
+        let node = self.get_node(root, Square::from_bcube(&bcube));
+
+        self.nodes[node.as_index()].contents.push(ent_id);
+        self.entries.push(Entry { bcube: bcube, val: val, node: node });
+
+        ent_id
+    }
+
+    /// Modify the location of an existing entry in the tree.
+    pub fn adjust(&mut self, ent_id: EntryID, bcube: BoundingCube) {
+        let start = self.entries[ent_id as usize].node;
+
+        let new_node = self.get_node(start, Square::from_bcube(&bcube));
+
+        // Growing the tree upward can move the old root, so look up where the entry is now.
+        let current_node = self.entries[ent_id as usize].node;
+
+        self.entries[ent_id as usize].bcube = bcube;
+
+        if new_node != current_node {
+            self.nodes[current_node.as_index()].contents.retain(|&x| { x != ent_id });
+            self.nodes[new_node.as_index()].contents.push(ent_id);
+            self.entries[ent_id as usize].node = new_node;
+
+            self.maybe_free(current_node);
+        }
+    }
+
+    /// Find every entry whose footprint intersects that of `region`. The IDs of those entries are
+    /// appended to `out`, which is cleared first.
+    pub fn query(&self, region: &BoundingCube, out: &mut Vec<EntryID>) {
+        out.clear();
+
+        let region    = Square::from_bcube(region);
+        let mut stack = self.stack.borrow_mut();
+
+        stack.clear();
+
+        if self.loose_bounds(&self.nodes[self.root.as_index()]).intersects(&region) {
+            stack.push(self.root);
+        }
+
+        while let Some(id) = stack.pop() {
+            let node = &self.nodes[id.as_index()];
+
+            for &ent_id in node.contents.iter() {
+                if Square::from_bcube(&self.entries[ent_id as usize].bcube).intersects(&region) {
+                    out.push(ent_id);
+                }
+            }
+
+            if let Some(block) = node.children {
+                let first    = (block * BLOCK_SIZE) as usize;
+                let children = &self.nodes[first..first + BLOCK_SIZE as usize];
+
+                for (i, child) in children.iter().enumerate() {
+                    if child.live && self.loose_bounds(child).intersects(&region) {
+                        stack.push(NodeID((first + i) as u32));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sort the contents of every node by the Morton code of each entry's center. See
+    /// `LooseOctree::sort_by_morton`.
+    pub fn sort_by_morton(&mut self) {
+        let loose = self.loose;
+
+        let LooseQuadtree { ref mut nodes, ref entries, .. } = *self;
+
+        for node in nodes.iter_mut().filter(|n| n.live) {
+            let bounds = Square {
+                center:    node.square.center,
+                half_edge: node.square.half_edge * loose,
+            };
+
+            let code = |ent_id: EntryID| {
+                morton_code(&bounds, &entries[ent_id as usize].bcube)
+            };
+
+            node.contents.sort_by(|&a, &b| code(a).cmp(&code(b)));
+        }
+    }
+
+    // The region which is guaranteed to contain everything stored in `node`.
+    #[inline] fn loose_bounds(&self, node: &Node) -> Square {
+        Square {
+            center:    node.square.center,
+            half_edge: node.square.half_edge * self.loose,
+        }
+    }
+
+    // The largest `half_edge` of an entry which can be stored in a node with the given `half_edge`.
+    #[inline] fn max_half_edge(&self, half_edge: Meters) -> Meters {
+        half_edge * (self.loose - Meters(1.0))
+    }
+
+    // Release a node if it has no contents and no children, and then try its parent.
+    fn maybe_free(&mut self, id: NodeID) {
+        // We don't ever free the root node, even if it's empty.
+        let parent_id = match self.nodes[id.as_index()].parent {
+            Some(parent_id) => parent_id,
+            None            => return,
+        };
+
+        {
+            let node = &self.nodes[id.as_index()];
+
+            if !node.contents.is_empty() || node.children.is_some() {
+                return
+            }
+        }
+
+        self.nodes[id.as_index()] = Node::dead();
+
+        if self.maybe_free_block(id.block()) {
+            self.nodes[parent_id.as_index()].children = None;
+        }
+
+        self.maybe_free(parent_id);
+    }
+
+    // Return the node which should contain `square`, beginning the search at `id`. This allocates
+    // new nodes as necessary, and may create a new root node.
+    fn get_node(&mut self, id: NodeID, square: Square) -> NodeID {
+        let node_square = self.nodes[id.as_index()].square;
+
+        match node_square.quadrant(square.center) {
+            Some(quadrant) => if self.max_half_edge(node_square.half_edge * Meters(0.5))
+                                     > square.half_edge
+                              && node_square.half_edge >= self.min_dist {
+                let child = self.get_child(id, quadrant);
+
+                self.get_node(child, square)
+            } else if self.max_half_edge(node_square.half_edge) < square.half_edge {
+                let parent = self.get_parent(id, square.center);
+
+                self.get_node(parent, square)
+            } else {
+                id
+            },
+
+            None => {
+                let parent = self.get_parent(id, square.center);
+
+                self.get_node(parent, square)
+            },
+        }
+    }
+
+    // Get the parent of `id`, growing the tree toward `v` if `id` is the root.
+    fn get_parent(&mut self, id: NodeID, v: math::Vec2<Meters>) -> NodeID {
+        match self.nodes[id.as_index()].parent {
+            Some(parent) => parent,
+            None         => self.make_parent_toward(id, v),
+        }
+    }
+
+    // Get a child node of `id`, creating one if it doesn't already exist.
+    fn get_child(&mut self, id: NodeID, quadrant: u8) -> NodeID {
+        let block = match self.nodes[id.as_index()].children {
+            Some(block) => block,
+            None        => {
+                let block = self.alloc_block();
+                self.nodes[id.as_index()].children = Some(block);
+                block
+            },
+        };
+
+        let child = NodeID::in_block(block, quadrant);
+
+        if self.nodes[child.as_index()].live {
+            return child
+        }
+
+        let old_square = self.nodes[id.as_index()].square;
+
+        let new_center = old_square.center
+                       + Square::corner(quadrant).scaled(old_square.half_edge / Meters(2.0));
+
+        self.nodes[child.as_index()] = Node::live(Square {
+            center:    new_center,
+            half_edge: old_square.half_edge * Meters(0.5),
+        }, Some(id));
+
+        child
+    }
+
+    // Create a parent for the root `id` which comes closer to containing `v`, moving `id` into the
+    // new parent's child block. Returns the ID of the new root.
+    fn make_parent_toward(&mut self, id: NodeID, v: math::Vec2<Meters>) -> NodeID {
+        debug_assert!(id == self.root);
+
+        let old_square = self.nodes[id.as_index()].square;
+        let diff       = v - old_square.center;
+
+        // Which quadrant of the parent will be `id`?
+        let quadrant = if diff.x < Meters(0.0) { QX } else { 0 }
+                     | if diff.y < Meters(0.0) { QY } else { 0 };
+
+        let new_center = old_square.center - Square::corner(quadrant).scaled(old_square.half_edge);
+
+        let children = self.alloc_block();
+        let moved    = NodeID::in_block(children, quadrant);
+
+        let node = mem::replace(&mut self.nodes[id.as_index()], Node::dead());
+
+        if let Some(block) = node.children {
+            for i in 0..BLOCK_SIZE {
+                let child = &mut self.nodes[(block * BLOCK_SIZE + i) as usize];
+
+                if child.live {
+                    child.parent = Some(moved);
+                }
+            }
+        }
+
+        for &ent_id in node.contents.iter() {
+            self.entries[ent_id as usize].node = moved;
+        }
+
+        self.nodes[moved.as_index()] = node;
+
+        self.maybe_free_block(id.block());
+
+        let root = NodeID::in_block(self.alloc_block(), 0);
+
+        let mut parent = Node::live(Square {
+            center:    new_center,
+            half_edge: old_square.half_edge * Meters(2.0),
+        }, None);
+
+        parent.children = Some(children);
+
+        self.nodes[root.as_index()] = parent;
+        self.nodes[moved.as_index()].parent = Some(root);
+        self.root = root;
+
+        root
+    }
+}
+
+// Spread the low 16 bits of `x` out so that there is a zero bit between each of them.
+fn spread_bits(x: u32) -> u32 {
+    let mut x = x & 0xffff;
+
+    x = (x | (x << 8)) & 0x00ff00ff;
+    x = (x | (x << 4)) & 0x0f0f0f0f;
+    x = (x | (x << 2)) & 0x33333333;
+    x = (x | (x << 1)) & 0x55555555;
+
+    x
+}
+
+// The 32-bit Morton code of the center of `bcube` within `bounds`, quantized to 16 bits per axis.
+fn morton_code(bounds: &Square, bcube: &BoundingCube) -> u32 {
+    let size = bounds.half_edge * Meters(2.0);
+
+    let quantize = |x: Meters, min: Meters| -> u32 {
+        let t = ((x - min) / size).max(Meters(0.0)).min(Meters(1.0));
+
+        (t * Meters(65535.0)).to_u32().unwrap_or(0)
+    };
+
+    spread_bits(quantize(bcube.center.x, bounds.center.x - bounds.half_edge))
+        | spread_bits(quantize(bcube.center.y, bounds.center.y - bounds.half_edge)) << 1
+}
+
+struct Entry<T> {
+    // A Cube which bounds this entry. Only its footprint is used for placement.
+    bcube: BoundingCube,
+
+    // The node which currently contains this entry
+    node: NodeID,
+
+    // The item at this location.
+    val: T,
+}
+
+struct Node {
+    // Square which this node represents. Geometry contained in this node is only guaranteed to be
+    // inside of the loose bounds; see `LooseQuadtree::loose_bounds`.
+    square: Square,
+
+    // This node's parent.
+    parent: Option<NodeID>,
+
+    // The block holding this node's children, if any of them exist.
+    children: Option<u32>,
+
+    // Indices into the `entries` field of the Quadtree.
+    contents: Vec<EntryID>,
+
+    // False if this slot of its block isn't in use.
+    live: bool,
+}
+
+impl Node {
+    fn live(square: Square, parent: Option<NodeID>) -> Node {
+        Node {
+            square:   square,
+            parent:   parent,
+            children: None,
+            contents: vec![],
+            live:     true,
+        }
+    }
+
+    fn dead() -> Node {
+        Node {
+            square:   Square { center: vec2!(Meters(0.0), Meters(0.0)), half_edge: Meters(0.0) },
+            parent:   None,
+            children: None,
+            contents: vec![],
+            live:     false,
+        }
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! The world is flat unless a `Heightfield` says otherwise. A heightfield is a grid of elevation
+//! samples laid over the XY plane; heights between samples are interpolated bilinearly, and
+//! points outside the grid take the height of the nearest edge.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use math::{Vec2, Vec3};
+use units::*;
+
+/// Ground elevation over a rectangular region of the world.
+#[derive(Clone,Debug)]
+pub struct Heightfield {
+    /// World-space location of sample (0, 0).
+    pub origin: Vec2<Meters>,
+
+    /// Distance between neighboring samples.
+    pub spacing: Meters,
+
+    width:   u32,
+    height:  u32,
+    samples: Vec<Meters>,
+}
+
+impl Heightfield {
+    /// Create a flat heightfield, `width` by `height` samples, at elevation zero.
+    pub fn new(width: u32, height: u32, spacing: Meters) -> Heightfield {
+        Heightfield::from_samples(width, height, spacing,
+                                  vec![Meters(0.0); width as usize * height as usize])
+    }
+
+    /// Create a heightfield from samples in row-major order, starting from the sample at
+    /// `origin`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `samples` doesn't hold exactly `width * height` elements, or if either dimension
+    /// is zero.
+    pub fn from_samples(width: u32, height: u32, spacing: Meters, samples: Vec<Meters>)
+        -> Heightfield {
+
+        assert!(width > 0 && height > 0, "heightfield must have at least one sample");
+        assert_eq!(samples.len(), width as usize * height as usize);
+
+        Heightfield {
+            origin:  vec2!(Meters ; 0.0, 0.0),
+            spacing: spacing,
+            width:   width,
+            height:  height,
+            samples: samples,
+        }
+    }
+
+    /// Read a heightfield from a RAW file of unsigned 16-bit little-endian samples, the format
+    /// most terrain tools export. A sample of 65535 becomes a height of `max_height`.
+    pub fn load_r16<P: AsRef<Path>>(path: P, width: u32, spacing: Meters, max_height: Meters)
+        -> io::Result<Heightfield> {
+
+        let mut buf = vec![];
+        try!(try!(File::open(path)).read_to_end(&mut buf));
+
+        let count = buf.len() / 2;
+
+        if width == 0 || buf.len() % 2 != 0 || count == 0 || count % width as usize != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "file size doesn't match the heightfield width"))
+        }
+
+        let samples = buf.chunks(2).map(|b| {
+            let raw = b[0] as u16 | (b[1] as u16) << 8;
+            max_height * Meters(raw as f32 / 65535.0)
+        }).collect();
+
+        Ok(Heightfield::from_samples(width, (count / width as usize) as u32, spacing, samples))
+    }
+
+    /// The number of samples along the X axis.
+    pub fn width(&self) -> u32 { self.width }
+
+    /// The number of samples along the Y axis.
+    pub fn height(&self) -> u32 { self.height }
+
+    /// The elevation of sample (`x`, `y`), clamped to the grid.
+    pub fn get(&self, x: u32, y: u32) -> Meters {
+        let x = if x < self.width { x } else { self.width - 1 };
+        let y = if y < self.height { y } else { self.height - 1 };
+
+        self.samples[(y * self.width + x) as usize]
+    }
+
+    /// Set the elevation of sample (`x`, `y`). Samples outside the grid are ignored.
+    pub fn set(&mut self, x: u32, y: u32, z: Meters) {
+        if x < self.width && y < self.height {
+            self.samples[(y * self.width + x) as usize] = z;
+        }
+    }
+
+    /// The elevation of the ground at world-space location `p`.
+    pub fn height_at(&self, p: Vec2<Meters>) -> Meters {
+        let max_x = (self.width - 1) as f32;
+        let max_y = (self.height - 1) as f32;
+
+        let fx = clamp(((p.x - self.origin.x) / self.spacing).0, max_x);
+        let fy = clamp(((p.y - self.origin.y) / self.spacing).0, max_y);
+
+        let (x0, y0) = (fx.floor() as u32, fy.floor() as u32);
+        let (tx, ty) = (Meters(fx - x0 as f32), Meters(fy - y0 as f32));
+
+        let top    = lerp(self.get(x0, y0),     self.get(x0 + 1, y0),     tx);
+        let bottom = lerp(self.get(x0, y0 + 1), self.get(x0 + 1, y0 + 1), tx);
+
+        lerp(top, bottom, ty)
+    }
+
+    /// Raise `p` by the elevation of the ground beneath it, so that a point given relative to
+    /// the ground is placed on it.
+    pub fn place(&self, p: Vec3<Meters>) -> Vec3<Meters> {
+        vec3!(p.x, p.y, p.z + self.height_at(vec2!(p.x, p.y)))
+    }
+}
+
+fn clamp(x: f32, max: f32) -> f32 {
+    if x < 0.0 { 0.0 } else if x > max { max } else { x }
+}
+
+fn lerp(a: Meters, b: Meters, t: Meters) -> Meters {
+    a + (b - a) * t
+}
@@ -0,0 +1,279 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Maps which use auto-tiling store a `TerrainGrid`, saying which kind of terrain (grass, road,
+//! water...) covers each cell, and an `AutoTiler` derives the tile drawn in each cell from the
+//! terrain there and in the neighboring cells. Each terrain's `TerrainRules` map a bitmask of its
+//! connected neighbors to a tile, so transitions such as road junctions and shorelines only need
+//! one tile per neighbor configuration rather than being placed by hand.
+//!
+//! Bits `N`, `E`, `S` and `W` are set for edge neighbors which connect. With the `Blob`
+//! neighborhood, the corner bits are also set for connected corner neighbors, but only when both of
+//! the adjacent edges connect too; this leaves the 47 distinct configurations of a "blob" tileset.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use tilemap::{Layer, Tile};
+
+/// Identifies a kind of terrain.
+pub type TerrainID = u16;
+
+/// The neighbor to the north (negative y) connects.
+pub const N: u8 = 1 << 0;
+
+/// The neighbor to the east (positive x) connects.
+pub const E: u8 = 1 << 1;
+
+/// The neighbor to the south (positive y) connects.
+pub const S: u8 = 1 << 2;
+
+/// The neighbor to the west (negative x) connects.
+pub const W: u8 = 1 << 3;
+
+/// The north-east neighbor connects (`Blob` only).
+pub const NE: u8 = 1 << 4;
+
+/// The south-east neighbor connects (`Blob` only).
+pub const SE: u8 = 1 << 5;
+
+/// The south-west neighbor connects (`Blob` only).
+pub const SW: u8 = 1 << 6;
+
+/// The north-west neighbor connects (`Blob` only).
+pub const NW: u8 = 1 << 7;
+
+// Offsets of the neighbors, in the same order as the bits above.
+const OFFSETS: [(i32, i32); 8] = [
+    (0, -1), (1, 0), (0, 1), (-1, 0), (1, -1), (1, 1), (-1, 1), (-1, -1),
+];
+
+// For each corner bit, the edge bits which must both be set for it to count.
+const CORNERS: [(u8, u8); 4] = [(NE, N | E), (SE, S | E), (SW, S | W), (NW, N | W)];
+
+/// Which neighbors are considered when choosing a tile.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Neighborhood {
+    /// Only the four edge neighbors (16 configurations).
+    Edges,
+
+    /// Edge and corner neighbors (47 configurations).
+    Blob,
+}
+
+/// How to choose tiles for one kind of terrain.
+#[derive(Clone,Debug)]
+pub struct TerrainRules {
+    /// Which neighbors to consider.
+    pub neighborhood: Neighborhood,
+
+    /// The tile for each neighbor configuration.
+    pub tiles: BTreeMap<u8, Tile>,
+
+    /// The tile used for configurations which don't have one of their own. If a `Blob` terrain is
+    /// missing a configuration, the tile for its edge bits alone is tried before falling back to
+    /// this.
+    pub default: Tile,
+
+    /// Other terrains which this one connects to, as well as itself (e.g. roads connecting to
+    /// bridges).
+    pub connects: BTreeSet<TerrainID>,
+
+    /// Whether cells beyond the edge of the map count as connected, so that terrain which runs off
+    /// the map doesn't look like it ends there.
+    pub border_connects: bool,
+}
+
+impl TerrainRules {
+    /// Rules which draw `default` everywhere, until more tiles are added.
+    pub fn new(neighborhood: Neighborhood, default: Tile) -> TerrainRules {
+        TerrainRules {
+            neighborhood:    neighborhood,
+            tiles:           BTreeMap::new(),
+            default:         default,
+            connects:        BTreeSet::new(),
+            border_connects: true,
+        }
+    }
+
+    /// Use `tile` for the neighbor configuration `mask`.
+    pub fn add(&mut self, mask: u8, tile: Tile) {
+        self.tiles.insert(mask, tile);
+    }
+
+    /// The tile for a neighbor configuration.
+    pub fn tile(&self, mask: u8) -> Tile {
+        self.tiles.get(&mask)
+            .or(self.tiles.get(&(mask & (N | E | S | W))))
+            .cloned()
+            .unwrap_or(self.default)
+    }
+}
+
+/// The terrain covering each cell of a map.
+#[derive(Clone)]
+pub struct TerrainGrid {
+    width:  u32,
+    height: u32,
+    cells:  Vec<Option<TerrainID>>,
+}
+
+impl TerrainGrid {
+    /// Create a grid with no terrain in any cell.
+    pub fn new(width: u32, height: u32) -> TerrainGrid {
+        TerrainGrid {
+            width:  width,
+            height: height,
+            cells:  vec![None; (width * height) as usize],
+        }
+    }
+
+    /// Number of cells along the x-axis.
+    pub fn width(&self) -> u32 { self.width }
+
+    /// Number of cells along the y-axis.
+    pub fn height(&self) -> u32 { self.height }
+
+    /// The terrain at (`x`, `y`), or `None` if the cell is bare or out of bounds.
+    pub fn get(&self, x: i32, y: i32) -> Option<TerrainID> {
+        if self.in_bounds(x, y) {
+            self.cells[(y as u32 * self.width + x as u32) as usize]
+        } else {
+            None
+        }
+    }
+
+    /// True if (`x`, `y`) is a cell of the grid.
+    pub fn in_bounds(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && (x as u32) < self.width && (y as u32) < self.height
+    }
+
+    // Change a cell without updating any tiles; see `AutoTiler::set`.
+    fn set(&mut self, x: u32, y: u32, terrain: Option<TerrainID>) {
+        self.cells[(y * self.width + x) as usize] = terrain;
+    }
+}
+
+/// Chooses tiles based on the terrain in neighboring cells.
+pub struct AutoTiler {
+    rules: BTreeMap<TerrainID, TerrainRules>,
+}
+
+impl AutoTiler {
+    /// Create an `AutoTiler` which doesn't know about any terrains.
+    pub fn new() -> AutoTiler {
+        AutoTiler { rules: BTreeMap::new() }
+    }
+
+    /// Set the rules for `terrain`.
+    pub fn add_terrain(&mut self, terrain: TerrainID, rules: TerrainRules) {
+        self.rules.insert(terrain, rules);
+    }
+
+    /// The rules for `terrain`, if it has any.
+    pub fn rules(&self, terrain: TerrainID) -> Option<&TerrainRules> {
+        self.rules.get(&terrain)
+    }
+
+    /// The neighbor configuration of cell (`x`, `y`), or `None` if the cell has no terrain or its
+    /// terrain has no rules.
+    pub fn mask(&self, grid: &TerrainGrid, x: u32, y: u32) -> Option<u8> {
+        let (x, y)  = (x as i32, y as i32);
+        let terrain = match grid.get(x, y) { Some(t) => t, None => return None };
+        let rules   = match self.rules.get(&terrain) { Some(r) => r, None => return None };
+
+        let count = match rules.neighborhood {
+            Neighborhood::Edges => 4,
+            Neighborhood::Blob  => 8,
+        };
+
+        let mut mask = 0;
+
+        for (bit, &(dx, dy)) in OFFSETS.iter().enumerate().take(count) {
+            let connected = if grid.in_bounds(x + dx, y + dy) {
+                match grid.get(x + dx, y + dy) {
+                    Some(other) => other == terrain || rules.connects.contains(&other),
+                    None        => false,
+                }
+            } else {
+                rules.border_connects
+            };
+
+            if connected {
+                mask |= 1 << bit;
+            }
+        }
+
+        // A corner only matters if it's enclosed by both of the edges next to it.
+        for &(corner, edges) in CORNERS.iter() {
+            if mask & edges != edges {
+                mask &= !corner;
+            }
+        }
+
+        Some(mask)
+    }
+
+    /// The tile which should be drawn in cell (`x`, `y`).
+    pub fn tile_for(&self, grid: &TerrainGrid, x: u32, y: u32) -> Option<Tile> {
+        let terrain = grid.get(x as i32, y as i32);
+
+        match (terrain.and_then(|t| self.rules.get(&t)), self.mask(grid, x, y)) {
+            (Some(rules), Some(mask)) => Some(rules.tile(mask)),
+            _                         => None,
+        }
+    }
+
+    /// Recompute every tile of `layer` from `grid`. The two must be the same size.
+    pub fn apply(&self, grid: &TerrainGrid, layer: &mut Layer) {
+        assert!(grid.width == layer.width() && grid.height == layer.height(),
+                "terrain grid and layer sizes differ");
+
+        for y in 0..grid.height {
+            for x in 0..grid.width {
+                layer.set(x, y, self.tile_for(grid, x, y));
+            }
+        }
+    }
+
+    /// Change the terrain of cell (`x`, `y`), updating the tiles of it and its neighbors in
+    /// `layer`. Returns the cells whose tiles changed, so that renderers can refresh just those.
+    pub fn set(&self, grid: &mut TerrainGrid, layer: &mut Layer, x: u32, y: u32,
+               terrain: Option<TerrainID>) -> Vec<(u32, u32)> {
+
+        grid.set(x, y, terrain);
+
+        let mut changed = vec![];
+
+        for &(dx, dy) in [(0, 0)].iter().chain(OFFSETS.iter()) {
+            let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+
+            if !grid.in_bounds(nx, ny) {
+                continue
+            }
+
+            let (nx, ny) = (nx as u32, ny as u32);
+            let tile     = self.tile_for(grid, nx, ny);
+
+            if layer.get(nx, ny) != tile {
+                layer.set(nx, ny, tile);
+                changed.push((nx, ny));
+            }
+        }
+
+        changed
+    }
+}
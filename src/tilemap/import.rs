@@ -0,0 +1,152 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Pieces shared by the map importers.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use rustc_serialize::json::Json;
+
+use units::*;
+
+/// Controls how an imported map is placed in the world.
+#[derive(Clone,Copy)]
+pub struct ImportOptions {
+    /// Edge length of a single tile in the world.
+    pub tile_size: Meters,
+
+    /// Vertical distance between consecutive layers which don't specify an `elevation`.
+    pub layer_height: Meters,
+}
+
+impl ImportOptions {
+    /// One-meter tiles, with all layers on the ground.
+    pub fn new() -> ImportOptions {
+        ImportOptions {
+            tile_size:    Meters(1.0),
+            layer_height: Meters(0.0),
+        }
+    }
+}
+
+/// An error encountered while importing a map.
+#[derive(Debug)]
+pub enum Error {
+    /// A file couldn't be read.
+    Io(io::Error),
+
+    /// A JSON file (TMJ, TSJ, or LDtk) is malformed.
+    Json(String),
+
+    /// A TMX or TSX file isn't valid XML.
+    Xml(String),
+
+    /// A required field or attribute is missing.
+    Missing(&'static str),
+
+    /// A field or attribute has a malformed value.
+    Invalid(&'static str),
+
+    /// The map uses a feature which the importer doesn't handle.
+    Unsupported(String),
+
+    /// A tile refers to an ID which isn't covered by any tileset.
+    BadTile(u32),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+/// Read the whole of the file at `path` as text.
+pub fn read_file(path: &Path) -> Result<String, Error> {
+    let mut text = String::new();
+    try!(try!(File::open(path)).read_to_string(&mut text));
+    Ok(text)
+}
+
+/// The default location of the depth texture for the color texture at `color`.
+pub fn depth_path_for(color: &Path) -> PathBuf {
+    let stem = color.file_stem().map(|s| s.to_string_lossy().into_owned());
+    color.with_file_name(format!("{}_depth.png", stem.unwrap_or(String::new())))
+}
+
+/// Convert `v` for storage in a `SheetDesc`, failing with `Error::Invalid(field)` if it won't fit.
+pub fn to_u16(v: i64, field: &'static str) -> Result<u16, Error> {
+    if v >= 0 && v <= u16::max_value() as i64 {
+        Ok(v as u16)
+    } else {
+        Err(Error::Invalid(field))
+    }
+}
+
+pub fn parse_json(text: &str) -> Result<Json, Error> {
+    Json::from_str(text).map_err(|e| Error::Json(format!("{:?}", e)))
+}
+
+pub fn json_str<'x>(j: &'x Json, key: &'static str) -> Result<&'x str, Error> {
+    match j.find(key) {
+        Some(v) => v.as_string().ok_or(Error::Invalid(key)),
+        None    => Err(Error::Missing(key)),
+    }
+}
+
+pub fn json_u32(j: &Json, key: &'static str) -> Result<u32, Error> {
+    match j.find(key) {
+        Some(v) => v.as_u64().map(|v| v as u32).ok_or(Error::Invalid(key)),
+        None    => Err(Error::Missing(key)),
+    }
+}
+
+/// Like `json_u32`, but treats a missing field as 0.
+pub fn json_opt_u32(j: &Json, key: &'static str) -> Result<u32, Error> {
+    match j.find(key) {
+        Some(_) => json_u32(j, key),
+        None    => Ok(0),
+    }
+}
+
+pub fn json_f32(j: &Json, key: &'static str) -> Result<f32, Error> {
+    match j.find(key) {
+        Some(v) => v.as_f64().map(|v| v as f32).ok_or(Error::Invalid(key)),
+        None    => Err(Error::Missing(key)),
+    }
+}
+
+pub fn json_array<'x>(j: &'x Json, key: &'static str) -> Result<&'x [Json], Error> {
+    match j.find(key) {
+        Some(v) => v.as_array().map(|a| &a[..]).ok_or(Error::Invalid(key)),
+        None    => Err(Error::Missing(key)),
+    }
+}
+
+/// Render a JSON value as a string, without quotes if it's already a string.
+pub fn json_value(v: &Json) -> String {
+    match *v {
+        Json::String(ref s) => s.clone(),
+        ref other           => other.to_string(),
+    }
+}
+
+/// The `name` field of a JSON object, or an empty string.
+pub fn json_name(j: &Json) -> &str {
+    j.find("name").and_then(|n| n.as_string()).unwrap_or("")
+}
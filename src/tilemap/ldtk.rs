@@ -0,0 +1,462 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! LDtk projects are single JSON files, optionally with each level saved separately. Every level
+//! becomes a `TileMap` placed at the level's position in the world, made up of its tile, auto-layer
+//! and IntGrid layers. Entity layers become the map's objects, with each entity's identifier as
+//! the object's kind and its fields as the object's properties. Entity definitions become
+//! `EntityTemplate`s, which supply the size of the entities spawned from them (see
+//! `Project::prefabs`). An entity with a `facing` field faces that many degrees clockwise.
+//!
+//! Tilesets follow the same conventions as the Tiled importer: each one becomes a `SheetDesc` with
+//! its origin at the center of the tile, and the depth texture is expected next to the color image
+//! with `_depth.png` in place of its extension. Tilesets mustn't use spacing or padding, and every
+//! layer must use the project's default grid size.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use rustc_serialize::json::Json;
+
+use grafix::sprite::SheetDesc;
+use logging;
+use math;
+use tilemap::{Layer, MapObject, Prefab, PrefabSet, Tile, TileMap, Tileset};
+use tilemap::import::*;
+use units::*;
+
+#[cfg(feature = "client")] use asset;
+#[cfg(feature = "client")] use entity::sim::{EntityID, Manager};
+#[cfg(feature = "client")] use time;
+
+/// A kind of entity which level designers can place, as defined in an LDtk project.
+#[derive(Clone)]
+pub struct EntityTemplate {
+    /// The entity's identifier, which is also the `kind` of the objects placed from it.
+    pub name: String,
+
+    /// Half-edge of the bounding cube given to entities spawned from this template.
+    pub half_edge: Meters,
+
+    /// The tile used to draw the entity, if it has one.
+    pub tile: Option<Tile>,
+
+    /// Default values of the entity's fields.
+    pub fields: BTreeMap<String, String>,
+}
+
+/// A single level of an LDtk project.
+#[derive(Clone)]
+pub struct Level {
+    /// The level's identifier.
+    pub name: String,
+
+    /// The level's tiles and entities. The map's tilesets are those of the whole project, so tiles
+    /// from different levels can be compared directly.
+    pub map: TileMap,
+}
+
+/// An imported LDtk project.
+#[derive(Clone)]
+pub struct Project {
+    /// All of the project's levels.
+    pub levels: Vec<Level>,
+
+    /// Templates for each kind of entity defined by the project.
+    pub templates: Vec<EntityTemplate>,
+}
+
+impl Project {
+    /// Look up a level by its identifier.
+    pub fn level(&self, name: &str) -> Option<&Level> {
+        self.levels.iter().find(|l| l.name == name)
+    }
+
+    /// Look up an entity template by its identifier.
+    pub fn template(&self, name: &str) -> Option<&EntityTemplate> {
+        self.templates.iter().find(|t| t.name == name)
+    }
+
+    /// The project's entity templates as prefabs, so that entities can be spawned with
+    /// `TileMap::spawn_prefabs`.
+    pub fn prefabs(&self) -> PrefabSet {
+        let mut set = PrefabSet::new();
+
+        for t in self.templates.iter() {
+            set.add(Prefab {
+                name:       t.name.clone(),
+                half_edge:  t.half_edge,
+                facings:    t.tile.into_iter().collect(),
+                properties: t.fields.clone(),
+            });
+        }
+
+        set
+    }
+
+    /// Spawn the entities placed in `level`, using the assets registered by
+    /// `TileMap::register_assets` under `prefix`. Each entity's size comes from its template.
+    ///
+    /// Returns the new entities' IDs, in the same order as `level.map.objects`.
+    #[cfg(feature = "client")]
+    pub fn spawn_entities(&self, level: &Level, prefix: &str, mgr: &mut Manager,
+                          assets: &asset::Handle, now: time::Duration) -> Vec<EntityID> {
+
+        level.map.spawn_prefabs(&self.prefabs(), prefix, mgr, assets, now)
+    }
+}
+
+/// Import the LDtk project at `path`. Paths to images and separate level files are resolved
+/// relative to the project file.
+pub fn load<P: AsRef<Path>>(path: P, opts: &ImportOptions) -> Result<Project, Error> {
+    let path = path.as_ref();
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    from_str(&try!(read_file(path)), base, opts)
+}
+
+/// Import an LDtk project from the contents of its file. Relative paths are resolved against
+/// `base`.
+pub fn from_str(text: &str, base: &Path, opts: &ImportOptions) -> Result<Project, Error> {
+    let root = try!(parse_json(text));
+    let grid = try!(json_u32(&root, "defaultGridSize"));
+
+    if grid == 0 {
+        return Err(Error::Invalid("defaultGridSize"))
+    }
+
+    let defs = try!(root.find("defs").ok_or(Error::Missing("defs")));
+
+    let mut imp = Importer {
+        opts:     opts,
+        grid:     grid,
+        tilesets: vec![],
+        by_uid:   BTreeMap::new(),
+    };
+
+    for ts in try!(json_array(defs, "tilesets")).iter() {
+        try!(imp.add_tileset(ts, base));
+    }
+
+    let mut templates = vec![];
+
+    for def in try!(json_array(defs, "entities")).iter() {
+        templates.push(try!(imp.template(def)));
+    }
+
+    let mut levels = vec![];
+
+    for level in try!(json_array(&root, "levels")).iter() {
+        // Projects saved with "separate level files" leave `layerInstances` null, and keep the
+        // full level in its own file.
+        let external = match level.find("layerInstances") {
+            Some(&Json::Array(_)) => None,
+            _ => match level.find("externalRelPath").and_then(|p| p.as_string()) {
+                Some(rel) => Some(try!(parse_json(&try!(read_file(&base.join(rel)))))),
+                None      => return Err(Error::Missing("layerInstances")),
+            },
+        };
+
+        levels.push(try!(imp.level(external.as_ref().unwrap_or(level))));
+    }
+
+    Ok(Project { levels: levels, templates: templates })
+}
+
+/// Watches an LDtk project file and re-imports it whenever it changes, so that levels can be
+/// edited while the game is running.
+pub struct Reloader {
+    path:     PathBuf,
+    opts:     ImportOptions,
+    modified: Option<SystemTime>,
+}
+
+impl Reloader {
+    /// Watch the project at `path`. The first call to `poll` always imports it.
+    pub fn new<P: AsRef<Path>>(path: P, opts: ImportOptions) -> Reloader {
+        Reloader {
+            path:     path.as_ref().to_path_buf(),
+            opts:     opts,
+            modified: None,
+        }
+    }
+
+    /// Import the project if it has changed since the last call, or if this is the first call.
+    /// Returns `None` if it hasn't changed.
+    ///
+    /// Only the project file itself is watched; changes to separate level files are picked up the
+    /// next time LDtk saves the project, which it does whenever a level is saved.
+    pub fn poll(&mut self) -> Option<Result<Project, Error>> {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+
+        if self.modified.is_some() && modified == self.modified {
+            return None
+        }
+
+        self.modified = modified;
+
+        Some(load(&self.path, &self.opts))
+    }
+}
+
+/// Where to find a tileset's tiles in the project's tilesets.
+struct TilesetInfo {
+    index:   u16,
+    columns: u32,
+    total:   u32,
+}
+
+struct Importer<'x> {
+    opts:     &'x ImportOptions,
+    grid:     u32,
+    tilesets: Vec<Tileset>,
+    by_uid:   BTreeMap<u64, TilesetInfo>,
+}
+
+impl<'x> Importer<'x> {
+    fn add_tileset(&mut self, ts: &Json, base: &Path) -> Result<(), Error> {
+        let name = try!(json_str(ts, "identifier"));
+
+        // Tilesets without an image (such as LDtk's built-in icons) can't be drawn.
+        let rel_path = match ts.find("relPath").and_then(|p| p.as_string()) {
+            Some(p) => p,
+            None    => {
+                debug!(target: logging::ASSET, "skipping tileset '{}', which has no image", name);
+                return Ok(())
+            },
+        };
+
+        if try!(json_opt_u32(ts, "spacing")) != 0 || try!(json_opt_u32(ts, "padding")) != 0 {
+            return Err(Error::Unsupported(format!("spacing or padding in tileset '{}'", name)))
+        }
+
+        let size    = try!(json_u32(ts, "tileGridSize"));
+        let columns = try!(json_u32(ts, "__cWid"));
+        let rows    = try!(json_u32(ts, "__cHei"));
+
+        if size == 0 || columns == 0 || rows == 0 {
+            return Err(Error::Invalid("tileGridSize"))
+        }
+
+        let color = base.join(rel_path);
+        let depth = depth_path_for(&color);
+
+        let sheet = SheetDesc {
+            img_width:  try!(to_u16(try!(json_u32(ts, "pxWid")) as i64, "pxWid")),
+            img_height: try!(to_u16(try!(json_u32(ts, "pxHei")) as i64, "pxHei")),
+            origin_x:   try!(to_u16((size / 2) as i64, "tileGridSize")),
+            origin_y:   try!(to_u16((size / 2) as i64, "tileGridSize")),
+            spr_width:  try!(to_u16(size as i64, "tileGridSize")),
+            spr_height: try!(to_u16(size as i64, "tileGridSize")),
+            num_across: try!(to_u16(columns as i64, "__cWid")),
+            num_down:   try!(to_u16(rows as i64, "__cHei")),
+            total:      try!(to_u16((columns * rows) as i64, "__cHei")),
+            color_path: color.to_string_lossy().into_owned(),
+            depth_path: depth.to_string_lossy().into_owned(),
+            color_hash: 0,
+            depth_hash: 0,
+
+            normal_path: None,
+            normal_hash: 0,
+
+            emissive_path: None,
+            emissive_hash: 0,
+        };
+
+        let uid = try!(ts.find("uid").and_then(|u| u.as_u64()).ok_or(Error::Missing("uid")));
+
+        self.by_uid.insert(uid, TilesetInfo {
+            index:   self.tilesets.len() as u16,
+            columns: columns,
+            total:   columns * rows,
+        });
+
+        self.tilesets.push(Tileset { name: From::from(name), sheet: sheet });
+
+        Ok(())
+    }
+
+    /// Resolve tile `id` of the tileset with the given uid.
+    fn tile(&self, tileset_uid: u64, id: u32) -> Result<Tile, Error> {
+        let info = try!(self.by_uid.get(&tileset_uid).ok_or(Error::Invalid("tilesetUid")));
+
+        if id >= info.total {
+            return Err(Error::BadTile(id))
+        }
+
+        Ok(Tile::Sprite { tileset: info.index, index: id as u16 })
+    }
+
+    /// Resolve a tile given as a rectangle in pixels, as entities' tiles are.
+    fn tile_rect(&self, rect: &Json) -> Result<Option<Tile>, Error> {
+        let uid = match rect.find("tilesetUid").and_then(|u| u.as_u64()) {
+            Some(uid) => uid,
+            None      => return Ok(None),
+        };
+
+        let columns = match self.by_uid.get(&uid) {
+            Some(info) => info.columns,
+            None       => return Ok(None),
+        };
+
+        let (x, y) = (try!(json_u32(rect, "x")), try!(json_u32(rect, "y")));
+
+        self.tile(uid, (y / self.grid) * columns + x / self.grid).map(Some)
+    }
+
+    fn template(&self, def: &Json) -> Result<EntityTemplate, Error> {
+        let width  = try!(json_u32(def, "width"));
+        let height = try!(json_u32(def, "height"));
+        let edge   = if width > height { width } else { height };
+
+        let tile = match def.find("tileRect") {
+            Some(rect @ &Json::Object(_)) => try!(self.tile_rect(rect)),
+            _ => None,
+        };
+
+        let mut fields = BTreeMap::new();
+
+        for field in try!(json_array(def, "fieldDefs")).iter() {
+            let default = field.find("defaultOverride")
+                               .and_then(|d| d.find("params"))
+                               .and_then(|p| p.as_array())
+                               .and_then(|p| p.first())
+                               .map(json_value)
+                               .unwrap_or(String::new());
+
+            fields.insert(From::from(try!(json_str(field, "identifier"))), default);
+        }
+
+        Ok(EntityTemplate {
+            name:      From::from(try!(json_str(def, "identifier"))),
+            half_edge: self.opts.tile_size * Meters(edge as f32 / (2 * self.grid) as f32),
+            tile:      tile,
+            fields:    fields,
+        })
+    }
+
+    fn level(&self, level: &Json) -> Result<Level, Error> {
+        let mut map = TileMap::new(self.opts.tile_size);
+
+        map.tilesets = self.tilesets.clone();
+        map.origin   = vec3!(self.cells(try!(json_f32(level, "worldX"))),
+                             self.cells(try!(json_f32(level, "worldY"))),
+                             Meters(0.0));
+
+        // LDtk lists layers from the top down.
+        let layers = try!(json_array(level, "layerInstances"));
+
+        for (i, layer) in layers.iter().rev().enumerate() {
+            let name = try!(json_str(layer, "__identifier"));
+            let z    = self.opts.layer_height * Meters(i as f32);
+
+            if try!(json_u32(layer, "__gridSize")) != self.grid {
+                return Err(Error::Unsupported(format!("grid size of layer '{}'", name)))
+            }
+
+            let tiles = match try!(json_str(layer, "__type")) {
+                "Tiles"                 => try!(json_array(layer, "gridTiles")),
+                "IntGrid" | "AutoLayer" => try!(json_array(layer, "autoLayerTiles")),
+
+                "Entities" => {
+                    for ent in try!(json_array(layer, "entityInstances")).iter() {
+                        map.objects.push(try!(self.entity(ent, map.origin, z)));
+                    }
+                    continue
+                },
+
+                other => {
+                    debug!(target: logging::ASSET, "skipping {} layer '{}'", other, name);
+                    continue
+                },
+            };
+
+            // IntGrid layers without auto-tiling rules have no tiles, and nothing to draw.
+            if tiles.is_empty() {
+                continue
+            }
+
+            let uid = try!(layer.find("__tilesetDefUid").and_then(|u| u.as_u64())
+                                .ok_or(Error::Missing("__tilesetDefUid")));
+
+            let mut out = Layer::new(name, z, try!(json_u32(layer, "__cWid")),
+                                     try!(json_u32(layer, "__cHei")));
+
+            for t in tiles.iter() {
+                let (x, y) = try!(json_point(t, "px"));
+                let tile   = try!(self.tile(uid, try!(json_u32(t, "t"))));
+
+                // Cells can hold stacked tiles in LDtk; only the topmost one is kept.
+                out.set(x / self.grid, y / self.grid, Some(tile));
+            }
+
+            map.layers.push(out);
+        }
+
+        Ok(Level { name: From::from(try!(json_str(level, "identifier"))), map: map })
+    }
+
+    fn entity(&self, ent: &Json, origin: math::Vec3<Meters>, z: Meters)
+        -> Result<MapObject, Error> {
+
+        let (x, y) = try!(json_point(ent, "px"));
+
+        let tile = match ent.find("__tile") {
+            Some(rect @ &Json::Object(_)) => try!(self.tile_rect(rect)),
+            _ => None,
+        };
+
+        let mut properties = BTreeMap::new();
+
+        for field in try!(json_array(ent, "fieldInstances")).iter() {
+            let value = field.find("__value").map(json_value).unwrap_or(String::new());
+            properties.insert(From::from(try!(json_str(field, "__identifier"))), value);
+        }
+
+        let facing = match properties.get("facing") {
+            Some(f) => try!(f.parse::<f32>().map_err(|_| Error::Invalid("facing"))),
+            None    => 0.0,
+        };
+
+        Ok(MapObject {
+            name:       From::from(json_str(ent, "iid").unwrap_or("")),
+            kind:       From::from(try!(json_str(ent, "__identifier"))),
+            pos:        origin + vec3!(self.cells(x as f32), self.cells(y as f32), z),
+            tile:       tile,
+            properties: properties,
+            prefab:     None,
+            facing:     facing,
+        })
+    }
+
+    /// Convert a distance in pixels to world space.
+    fn cells(&self, px: f32) -> Meters {
+        self.opts.tile_size * Meters(px / self.grid as f32)
+    }
+}
+
+/// Read a `[x, y]` pair of pixel coordinates.
+fn json_point(j: &Json, key: &'static str) -> Result<(u32, u32), Error> {
+    let xy = try!(json_array(j, key));
+
+    match (xy.get(0).and_then(|x| x.as_u64()), xy.get(1).and_then(|y| y.as_u64())) {
+        (Some(x), Some(y)) => Ok((x as u32, y as u32)),
+        _                  => Err(Error::Invalid(key)),
+    }
+}
@@ -0,0 +1,475 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+/// Importer for maps authored in the Tiled editor.
+#[cfg(feature = "tiled")] pub mod tiled;
+
+/// Importer for projects authored in the LDtk level editor.
+#[cfg(feature = "ldtk")] pub mod ldtk;
+
+/// Choosing tiles automatically from the terrain around them.
+pub mod autotile;
+
+/// Drawing tile layers through the sprite `Batcher`.
+#[cfg(feature = "client")] pub mod render;
+
+/// Streaming the chunks of a large map in and out around the camera.
+#[cfg(feature = "client")] pub mod stream;
+
+#[cfg(any(feature = "tiled", feature = "ldtk"))] mod import;
+
+#[cfg(any(feature = "tiled", feature = "ldtk"))]
+pub use self::import::{Error as ImportError, ImportOptions};
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use asset::ManifestBuilder;
+use entity::ComponentRef;
+use grafix::anim::{AnimDesc, AnimID};
+use grafix::sprite::SheetDesc;
+use logging;
+use math;
+use terrain::Heightfield;
+use time;
+use units::*;
+
+#[cfg(feature = "client")] use asset;
+#[cfg(feature = "client")] use entity::sim::{EntityID, Manager};
+#[cfg(feature = "client")] use entity::component::{WorldLocation, WorldRender};
+#[cfg(feature = "client")] use grafix::anim;
+
+/// What is drawn in a single cell of a layer.
+#[derive(Clone,Copy,Debug,PartialEq,Eq,PartialOrd,Ord)]
+pub enum Tile {
+    /// A sprite from one of the map's tilesets.
+    Sprite {
+        /// Index of the tileset in `TileMap::tilesets`.
+        tileset: u16,
+
+        /// Index of the sprite within the tileset's sheet.
+        index: u16,
+    },
+
+    /// An animation which loops forever, such as water or a torch. Every tile showing the same
+    /// animation with the same period is on the same frame at any given time.
+    Anim {
+        /// The animation to play.
+        anim: AnimID,
+
+        /// How long it takes to play through every frame once.
+        period: time::Duration,
+    },
+}
+
+/// A sprite sheet whose sprites are used as tiles.
+#[derive(Clone,Debug)]
+pub struct Tileset {
+    /// Name of the tileset, unique within its map.
+    pub name: String,
+
+    /// The sheet backing this tileset.
+    pub sheet: SheetDesc,
+}
+
+/// A rectangular grid of tiles, all at the same elevation.
+#[derive(Clone)]
+pub struct Layer {
+    /// Name of the layer, as given by the map's author.
+    pub name: String,
+
+    /// The elevation at which this layer's tiles are placed.
+    pub z: Meters,
+
+    width:  u32,
+    height: u32,
+    tiles:  Vec<Option<Tile>>,
+}
+
+impl Layer {
+    /// Create an empty layer which is `width` tiles along the x-axis and `height` tiles along the
+    /// y-axis.
+    pub fn new<S>(name: S, z: Meters, width: u32, height: u32) -> Layer where String: From<S> {
+        Layer {
+            name:   From::from(name),
+            z:      z,
+            width:  width,
+            height: height,
+            tiles:  vec![None; (width * height) as usize],
+        }
+    }
+
+    /// Number of tiles along the x-axis.
+    pub fn width(&self) -> u32 { self.width }
+
+    /// Number of tiles along the y-axis.
+    pub fn height(&self) -> u32 { self.height }
+
+    /// The tile at (`x`, `y`), or `None` if the cell is empty or out of bounds.
+    pub fn get(&self, x: u32, y: u32) -> Option<Tile> {
+        if x < self.width && y < self.height {
+            self.tiles[(y * self.width + x) as usize]
+        } else {
+            None
+        }
+    }
+
+    /// Replace the tile at (`x`, `y`).
+    ///
+    /// # Panics
+    ///
+    /// Panics if (`x`, `y`) is out of bounds.
+    pub fn set(&mut self, x: u32, y: u32, tile: Option<Tile>) {
+        assert!(x < self.width && y < self.height, "tile ({}, {}) is out of bounds", x, y);
+
+        self.tiles[(y * self.width + x) as usize] = tile;
+    }
+
+    /// Iterate over the non-empty cells of this layer, as `(x, y, tile)`.
+    pub fn iter<'x>(&'x self) -> Box<Iterator<Item=(u32, u32, Tile)> + 'x> {
+        let width = self.width;
+
+        Box::new(self.tiles.iter().enumerate().filter_map(move |(i, t)| {
+            t.map(|t| (i as u32 % width, i as u32 / width, t))
+        }))
+    }
+}
+
+/// A point of interest placed on a map, from which an entity may be spawned.
+#[derive(Clone)]
+pub struct MapObject {
+    /// Name of the object, as given by the map's author. May be empty.
+    pub name: String,
+
+    /// A free-form type or class name, used by games to decide what to spawn.
+    pub kind: String,
+
+    /// Location of the object's anchor in the world, relative to the map's terrain (if any).
+    pub pos: math::Vec3<Meters>,
+
+    /// The sprite which represents the object, if any. This overrides the prefab's tile.
+    pub tile: Option<Tile>,
+
+    /// Custom properties attached to the object, with their values as strings. These override
+    /// the prefab's properties of the same name.
+    pub properties: BTreeMap<String, String>,
+
+    /// The name of the `Prefab` this object is an instance of. If this is `None`, the object's
+    /// `kind` is used instead.
+    pub prefab: Option<String>,
+
+    /// The direction the object faces, in degrees clockwise.
+    pub facing: f32,
+}
+
+impl MapObject {
+    /// Create an object of the given kind at `pos`, with no tile or properties, which faces 0°.
+    pub fn new<S>(kind: S, pos: math::Vec3<Meters>) -> MapObject where String: From<S> {
+        MapObject {
+            name:       String::new(),
+            kind:       From::from(kind),
+            pos:        pos,
+            tile:       None,
+            properties: BTreeMap::new(),
+            prefab:     None,
+            facing:     0.0,
+        }
+    }
+
+    /// The name of the prefab this object is placed from.
+    pub fn prefab_name(&self) -> &str {
+        self.prefab.as_ref().unwrap_or(&self.kind)
+    }
+}
+
+/// A reusable kind of object, which level designers place on maps by name. The objects placed
+/// from a prefab share its size, sprites and default properties, and may override them.
+#[derive(Clone)]
+pub struct Prefab {
+    /// The name by which objects refer to the prefab.
+    pub name: String,
+
+    /// Half-edge of the bounding cube given to entities spawned from this prefab.
+    pub half_edge: Meters,
+
+    /// The tile drawn for each direction the prefab can face, evenly spaced clockwise starting
+    /// from 0°. A prefab which looks the same from every direction has a single tile; one with no
+    /// tiles is invisible.
+    pub facings: Vec<Tile>,
+
+    /// Default values of the prefab's properties.
+    pub properties: BTreeMap<String, String>,
+}
+
+impl Prefab {
+    /// The tile to draw for an object facing `degrees` clockwise, or `None` if the prefab has no
+    /// tiles.
+    pub fn tile_for(&self, degrees: f32) -> Option<Tile> {
+        if self.facings.is_empty() {
+            return None
+        }
+
+        let n    = self.facings.len() as f32;
+        let turn = (degrees / 360.0).fract();
+        let turn = if turn < 0.0 { turn + 1.0 } else { turn };
+
+        // Round to the nearest facing, wrapping back to the first one.
+        let i = (turn * n + 0.5).floor() as usize % self.facings.len();
+
+        Some(self.facings[i])
+    }
+
+    /// Combine the prefab with the overrides in `obj`, returning the object as it should be
+    /// spawned: its tile is chosen for its facing unless it has its own, and its properties
+    /// include the prefab's defaults.
+    pub fn instantiate(&self, obj: &MapObject) -> MapObject {
+        let mut out = obj.clone();
+
+        out.tile       = obj.tile.or(self.tile_for(obj.facing));
+        out.properties = self.properties.clone();
+        out.properties.extend(obj.properties.iter().map(|(k, v)| (k.clone(), v.clone())));
+
+        out
+    }
+}
+
+/// A collection of prefabs, looked up by name.
+#[derive(Clone)]
+pub struct PrefabSet {
+    by_name: BTreeMap<String, Prefab>,
+}
+
+impl PrefabSet {
+    /// Create an empty set.
+    pub fn new() -> PrefabSet {
+        PrefabSet { by_name: BTreeMap::new() }
+    }
+
+    /// Add a prefab, replacing any other prefab with the same name.
+    pub fn add(&mut self, prefab: Prefab) {
+        self.by_name.insert(prefab.name.clone(), prefab);
+    }
+
+    /// Look up a prefab by name.
+    pub fn get(&self, name: &str) -> Option<&Prefab> {
+        self.by_name.get(name)
+    }
+
+    /// Iterate over the prefabs, in order of name.
+    pub fn iter<'x>(&'x self) -> Box<Iterator<Item=&'x Prefab> + 'x> {
+        Box::new(self.by_name.values())
+    }
+}
+
+/// A grid-based map, made up of layers of tiles and a set of objects.
+///
+/// Tile (x, y) of every layer covers the square from `origin + (x, y) * tile_size` to
+/// `origin + (x + 1, y + 1) * tile_size` in world space, raised by the layer's `z`. If the map has
+/// `terrain`, tiles and objects are also raised by the elevation of the ground beneath them.
+#[derive(Clone)]
+pub struct TileMap {
+    /// World-space location of the corner of tile (0, 0).
+    pub origin: math::Vec3<Meters>,
+
+    /// Edge length of a single tile in the world.
+    pub tile_size: Meters,
+
+    /// The tilesets referred to by the map's tiles.
+    pub tilesets: Vec<Tileset>,
+
+    /// Tile layers, from bottom to top.
+    pub layers: Vec<Layer>,
+
+    /// Objects placed on the map.
+    pub objects: Vec<MapObject>,
+
+    /// The shape of the ground under the map, in world space.
+    pub terrain: Option<Heightfield>,
+}
+
+impl TileMap {
+    /// Create an empty map.
+    pub fn new(tile_size: Meters) -> TileMap {
+        TileMap {
+            origin:    vec3!(Meters ; 0.0, 0.0, 0.0),
+            tile_size: tile_size,
+            tilesets:  vec![],
+            layers:    vec![],
+            objects:   vec![],
+            terrain:   None,
+        }
+    }
+
+    /// World-space location of the center of tile (`x`, `y`) in `layer`.
+    pub fn tile_center(&self, layer: &Layer, x: u32, y: u32) -> math::Vec3<Meters> {
+        self.ground(self.origin + vec3!((Meters(x as f32) + Meters(0.5)) * self.tile_size,
+                                        (Meters(y as f32) + Meters(0.5)) * self.tile_size,
+                                        layer.z))
+    }
+
+    /// Raise `p` by the elevation of the map's terrain beneath it. Without terrain, `p` is
+    /// returned unchanged.
+    pub fn ground(&self, p: math::Vec3<Meters>) -> math::Vec3<Meters> {
+        match self.terrain {
+            Some(ref terrain) => terrain.place(p),
+            None              => p,
+        }
+    }
+
+    /// The asset name under which `register_assets` stores the sheet for `tileset`.
+    pub fn sheet_name(&self, prefix: &str, tileset: u16) -> String {
+        format!("{}/{}", prefix, self.tilesets[tileset as usize].name)
+    }
+
+    /// The asset name under which `register_assets` stores the single-frame animation for sprite
+    /// `index` of `tileset`.
+    pub fn tile_anim_name(&self, prefix: &str, tileset: u16, index: u16) -> String {
+        format!("{}/{}", self.sheet_name(prefix, tileset), index)
+    }
+
+    /// Add a sheet to `builder` for each of this map's tilesets, along with a single-frame
+    /// animation for each distinct sprite tile used by the map. Asset names start with `prefix`
+    /// (see `sheet_name` and `tile_anim_name`). Animated tiles refer to animations which are
+    /// already registered, so nothing is added for them.
+    pub fn register_assets(&self, prefix: &str, builder: &mut ManifestBuilder) {
+        self.register_assets_with(&PrefabSet::new(), prefix, builder)
+    }
+
+    /// Like `register_assets`, but also adds animations for the tiles of every prefab in
+    /// `prefabs`, which must use this map's tilesets.
+    pub fn register_assets_with(&self, prefabs: &PrefabSet, prefix: &str,
+                                builder: &mut ManifestBuilder) {
+        let mut used = BTreeSet::new();
+
+        for layer in self.layers.iter() {
+            used.extend(layer.iter().map(|(_, _, t)| t));
+        }
+
+        used.extend(self.objects.iter().filter_map(|o| o.tile));
+
+        for prefab in prefabs.iter() {
+            used.extend(prefab.facings.iter().cloned());
+        }
+
+        for (i, ts) in self.tilesets.iter().enumerate() {
+            builder.add_sheet(self.sheet_name(prefix, i as u16), ts.sheet.clone());
+        }
+
+        for &tile in used.iter() {
+            if let Tile::Sprite { tileset, index } = tile {
+                builder.add_anim(self.tile_anim_name(prefix, tileset, index), AnimDesc {
+                    sheet:   self.sheet_name(prefix, tileset),
+                    indices: vec![index],
+                });
+            }
+        }
+    }
+
+    /// Create an entity for each of the map's objects, using the assets registered by
+    /// `register_assets` under `prefix`. Each entity's bounding cube is the size of one tile.
+    ///
+    /// Returns the new entities' IDs, in the same order as `self.objects`.
+    #[cfg(feature = "client")]
+    pub fn spawn_objects(&self, prefix: &str, mgr: &mut Manager, assets: &asset::Handle,
+                         now: time::Duration) -> Vec<EntityID> {
+
+        let half_edge = self.tile_size / Meters(2.0);
+
+        self.objects.iter()
+            .map(|obj| self.spawn_object(obj, half_edge, prefix, mgr, assets, now))
+            .collect()
+    }
+
+    /// Create an entity for each of the map's objects from the prefab it refers to (see
+    /// `Prefab::instantiate`), using the assets registered by `register_assets` under `prefix`.
+    /// Objects whose prefab isn't in `prefabs` are spawned as they are, one tile in size, and a
+    /// warning is logged.
+    ///
+    /// Returns the new entities' IDs, in the same order as `self.objects`.
+    #[cfg(feature = "client")]
+    pub fn spawn_prefabs(&self, prefabs: &PrefabSet, prefix: &str, mgr: &mut Manager,
+                         assets: &asset::Handle, now: time::Duration) -> Vec<EntityID> {
+
+        let default_half_edge = self.tile_size / Meters(2.0);
+
+        self.objects.iter().map(|obj| match prefabs.get(obj.prefab_name()) {
+            Some(prefab) => {
+                let obj = prefab.instantiate(obj);
+                self.spawn_object(&obj, prefab.half_edge, prefix, mgr, assets, now)
+            },
+
+            None => {
+                warn!(target: logging::ASSET, "no prefab named '{}' for object '{}'",
+                      obj.prefab_name(), obj.name);
+
+                self.spawn_object(obj, default_half_edge, prefix, mgr, assets, now)
+            },
+        }).collect()
+    }
+
+    /// Create an entity for `obj`, with a bounding cube of the given half-edge. If the object has a
+    /// tile, the entity is given a `WorldRender` showing it, using the assets registered by
+    /// `register_assets` under `prefix`; otherwise it only gets a `WorldLocation`.
+    #[cfg(feature = "client")]
+    pub fn spawn_object(&self, obj: &MapObject, half_edge: Meters, prefix: &str, mgr: &mut Manager,
+                        assets: &asset::Handle, now: time::Duration) -> EntityID {
+
+        let mut loc = WorldLocation {
+            bounds: math::BoundingCube { center: self.ground(obj.pos), half_edge: half_edge },
+        };
+
+        let anim = match obj.tile {
+            Some(Tile::Sprite { tileset, index }) => {
+                assets.get_id(self.tile_anim_name(prefix, tileset, index))
+                      .map(|id| (id, time::Duration::sec(1)))
+            },
+
+            Some(Tile::Anim { anim, period }) => Some((anim, period)),
+
+            None => None,
+        };
+
+        match anim {
+            Some((anim_id, duration)) => {
+                let mut ren = WorldRender {
+                    anim: anim::Instance {
+                        anim_id:  anim_id,
+                        t_start:  now,
+                        duration: duration,
+                        repeat:   true,
+                        flip_x:   false,
+                    },
+                };
+
+                let mut view = ::entity::sim::View::empty();
+                view.world_location = Some(ComponentRef::local(&mut loc));
+                view.world_render   = Some(ComponentRef::local(&mut ren));
+                mgr.entity_from_view(view)
+            },
+
+            None => {
+                if let Some(t) = obj.tile {
+                    error!(target: logging::ASSET,
+                           "no animation registered for tile {:?} of object '{}'", t, obj.name);
+                }
+
+                let mut view = ::entity::sim::View::empty();
+                view.world_location = Some(ComponentRef::local(&mut loc));
+                mgr.entity_from_view(view)
+            },
+        }
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use asset::{self, AssetID};
+use grafix::anim;
+use grafix::sprite::{self, Batcher, DrawReq};
+use logging;
+use math::{self, BoundingCube, Visibility};
+use tilemap::{Tile, TileMap};
+use time;
+use units::*;
+
+/// Draws the tile layers of `TileMap`s by handing `DrawReq`s to a `Batcher`.
+///
+/// Sprite tiles are drawn from the sheets registered by `TileMap::register_assets`, so every map
+/// drawn by the same renderer must use the same tilesets. Animated tiles choose their frame each
+/// time they're drawn.
+pub struct TileRenderer {
+    prefix: String,
+
+    // The sheet for each tileset, looked up by name the first time it's needed.
+    sheets: Vec<Option<AssetID>>,
+}
+
+impl TileRenderer {
+    /// Create a renderer for maps whose assets were registered under `prefix`.
+    pub fn new(prefix: &str) -> TileRenderer {
+        TileRenderer {
+            prefix: From::from(prefix),
+            sheets: vec![],
+        }
+    }
+
+    /// Forget which sheets the tilesets were found in, e.g. after the asset manifest is reloaded.
+    pub fn clear_cache(&mut self) {
+        self.sheets.clear();
+    }
+
+    /// Register a `DrawReq` with `batcher` for every tile of `map` which is inside `frustum`, as
+    /// it should appear at time `now`.
+    pub fn draw(&mut self, map: &TileMap, frustum: &math::Frustum, assets: &asset::AssetDb,
+                now: time::Duration, batcher: &mut Batcher) {
+
+        self.resolve_sheets(map, assets);
+
+        let half_edge = map.tile_size / Meters(2.0);
+
+        for layer in map.layers.iter() {
+            for (x, y, tile) in layer.iter() {
+                let center = map.tile_center(layer, x, y);
+
+                let bounds = BoundingCube { center: center, half_edge: half_edge };
+
+                if frustum.intersects(&bounds) == Visibility::Zero {
+                    continue
+                }
+
+                let req = match tile {
+                    Tile::Sprite { tileset, index } => {
+                        self.sheets[tileset as usize].map(|sheet_id| DrawReq {
+                            sheet_id:   sheet_id,
+                            sprite_idx: index as usize,
+                            game_loc:   center,
+                            tint:       sprite::WHITE,
+                            flip_x:     false,
+                            scale:      1.0,
+                            rotation:   0.0,
+                            highlight:  None,
+                            layer:      sprite::LAYER_TERRAIN,
+                            material:   sprite::DEFAULT_MATERIAL,
+                        })
+                    },
+
+                    // All instances start at time zero, so that neighboring tiles stay in step.
+                    Tile::Anim { anim, period } => anim::Instance {
+                        anim_id:  anim,
+                        t_start:  time::Duration::usec(0),
+                        duration: period,
+                        repeat:   true,
+                        flip_x:   false,
+                    }.draw_at(assets.get_handle(), center, now),
+                };
+
+                // Animated tiles come back in the units' layer, like any other animation.
+                if let Some(req) = req {
+                    batcher.register(DrawReq { layer: sprite::LAYER_TERRAIN, ..req });
+                }
+            }
+        }
+    }
+
+    fn resolve_sheets(&mut self, map: &TileMap, assets: &asset::AssetDb) {
+        if self.sheets.len() >= map.tilesets.len() {
+            return
+        }
+
+        let handle = assets.get_handle();
+
+        for i in self.sheets.len()..map.tilesets.len() {
+            let id = handle.get_id(map.sheet_name(&self.prefix, i as u16));
+
+            if id.is_none() {
+                error!(target: logging::ASSET, "no sheet registered for tileset '{}'",
+                       map.tilesets[i].name);
+            }
+
+            self.sheets.push(id);
+        }
+    }
+}
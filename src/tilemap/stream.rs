@@ -0,0 +1,388 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use num::Float;
+
+use asset;
+use entity::sim::{EntityID, Manager};
+use grafix::camera::{Camera, Visibility};
+use logging;
+use math::{self, BoundingCube};
+use scene::{EntryID, LooseOctree};
+use tilemap::TileMap;
+use time;
+use units::*;
+
+/// Identifies a chunk of a streamed map. Chunk (x, y) covers tiles `(x, y) * chunk_size` up to
+/// (but not including) `(x + 1, y + 1) * chunk_size`.
+#[derive(Copy,Clone,Debug,PartialEq,Eq,PartialOrd,Ord)]
+pub struct ChunkCoord {
+    /// Position of the chunk along the x-axis, in chunks.
+    pub x: i32,
+
+    /// Position of the chunk along the y-axis, in chunks.
+    pub y: i32,
+}
+
+/// Somewhere chunks can be loaded from. Sources are called on a background thread, so they can
+/// take their time reading and deserializing chunk data.
+pub trait ChunkSource: Send + 'static {
+    /// Load the chunk at `coord`. Returns `Ok(None)` if there's no such chunk, i.e. the chunk is
+    /// empty. The returned map's origin is ignored, since the streamer places each chunk itself.
+    fn load(&self, coord: ChunkCoord) -> Result<Option<TileMap>, String>;
+}
+
+impl<F> ChunkSource for F
+    where F: Fn(ChunkCoord) -> Result<Option<TileMap>, String> + Send + 'static {
+
+    fn load(&self, coord: ChunkCoord) -> Result<Option<TileMap>, String> {
+        self(coord)
+    }
+}
+
+/// Loads chunks from a directory of Tiled maps, where chunk (x, y) is stored as `x_y.tmj` or
+/// `x_y.tmx`.
+#[cfg(feature = "tiled")]
+pub struct TiledChunks {
+    /// Directory containing the chunk files.
+    pub dir: ::std::path::PathBuf,
+
+    /// Options used to import each chunk.
+    pub opts: ::tilemap::ImportOptions,
+}
+
+#[cfg(feature = "tiled")]
+impl ChunkSource for TiledChunks {
+    fn load(&self, coord: ChunkCoord) -> Result<Option<TileMap>, String> {
+        for ext in ["tmj", "tmx"].iter() {
+            let path = self.dir.join(format!("{}_{}.{}", coord.x, coord.y, ext));
+
+            if path.exists() {
+                return ::tilemap::tiled::load(&path, &self.opts)
+                    .map(Some)
+                    .map_err(|e| format!("{}: {:?}", path.display(), e))
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// A change in the set of chunks which are resident, reported by `ChunkStreamer::update`.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum ChunkEvent {
+    /// The chunk finished loading, and its entities have been spawned.
+    Loaded(ChunkCoord),
+
+    /// The chunk was unloaded, and its entities have been removed.
+    Unloaded(ChunkCoord),
+}
+
+enum State {
+    // A request has been sent to the loader thread.
+    Loading,
+
+    // The source had nothing for this chunk, or failed to load it.
+    Missing,
+
+    Loaded(TileMap, Vec<EntityID>),
+}
+
+struct Chunk {
+    state: State,
+
+    // The last frame on which the chunk was on camera.
+    seen: u64,
+}
+
+/// Keeps the chunks of a large map which are near the camera resident, loading them on a
+/// background thread as they come into view and unloading them once they have been out of view for
+/// a while.
+///
+/// When a chunk loads, an entity is spawned for each of its objects (see `TileMap::spawn_objects`)
+/// and registered in the streamer's octree, which can be searched with `query`. The assets for
+/// every chunk's tiles must already be registered with the `AssetDb` under the streamer's prefix,
+/// e.g. by calling `TileMap::register_assets` on a map which uses all of the same tilesets.
+pub struct ChunkStreamer {
+    chunk_size:   u32,
+    tile_size:    Meters,
+    prefix:       String,
+
+    // Number of chunks around the camera's chunk which are checked for visibility.
+    radius:       i32,
+
+    // Number of frames a chunk stays resident after leaving the camera.
+    unload_after: u64,
+
+    requests: Sender<ChunkCoord>,
+    results:  Receiver<(ChunkCoord, Result<Option<TileMap>, String>)>,
+
+    chunks: BTreeMap<ChunkCoord, Chunk>,
+    frame:  u64,
+
+    tree:    LooseOctree<EntityID>,
+    entries: BTreeMap<EntityID, EntryID>,
+}
+
+impl ChunkStreamer {
+    /// Stream chunks of `chunk_size` by `chunk_size` tiles, each `tile_size` across, from `source`.
+    /// Entities are spawned using the assets registered under `prefix`.
+    pub fn new<S: ChunkSource>(source: S, chunk_size: u32, tile_size: Meters, prefix: &str)
+        -> ChunkStreamer {
+
+        let (req_tx, req_rx) = mpsc::channel::<ChunkCoord>();
+        let (res_tx, res_rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            // The thread exits once the streamer (and with it, `req_tx`) is dropped.
+            for coord in req_rx.iter() {
+                if res_tx.send((coord, source.load(coord))).is_err() {
+                    break
+                }
+            }
+        });
+
+        let chunk_edge = tile_size * Meters(chunk_size as f32);
+
+        ChunkStreamer {
+            chunk_size:   chunk_size,
+            tile_size:    tile_size,
+            prefix:       From::from(prefix),
+            radius:       4,
+            unload_after: 60,
+            requests:     req_tx,
+            results:      res_rx,
+            chunks:       BTreeMap::new(),
+            frame:        0,
+            tree:         LooseOctree::new(BoundingCube {
+                center:    vec3!(Meters ; 0.0, 0.0, 0.0),
+                half_edge: chunk_edge * Meters(8.0),
+            }, tile_size, 2.0),
+            entries:      BTreeMap::new(),
+        }
+    }
+
+    /// Check `radius` chunks in each direction around the camera for visibility. This should be
+    /// large enough to cover the screen at the camera's zoom level; the default is 4.
+    pub fn set_radius(&mut self, radius: u32) {
+        self.radius = radius as i32;
+    }
+
+    /// Keep chunks resident for `frames` frames after they leave the camera, so that panning back
+    /// and forth doesn't reload them. The default is 60.
+    pub fn set_unload_after(&mut self, frames: u64) {
+        self.unload_after = frames;
+    }
+
+    /// Edge length of a chunk in the world.
+    pub fn chunk_edge(&self) -> Meters {
+        self.tile_size * Meters(self.chunk_size as f32)
+    }
+
+    /// The chunk containing the point `pos`.
+    pub fn chunk_at(&self, pos: math::Vec3<Meters>) -> ChunkCoord {
+        let edge = self.chunk_edge();
+
+        ChunkCoord {
+            x: (pos.x / edge).floor().0 as i32,
+            y: (pos.y / edge).floor().0 as i32,
+        }
+    }
+
+    /// World-space location of the corner of tile (0, 0) of the chunk at `coord`.
+    pub fn chunk_origin(&self, coord: ChunkCoord) -> math::Vec3<Meters> {
+        let edge = self.chunk_edge();
+        vec3!(edge * Meters(coord.x as f32), edge * Meters(coord.y as f32), Meters(0.0))
+    }
+
+    /// A bounding cube around the chunk at `coord`, centered on the ground.
+    pub fn chunk_bounds(&self, coord: ChunkCoord) -> BoundingCube {
+        let half = self.chunk_edge() / Meters(2.0);
+
+        BoundingCube {
+            center:    self.chunk_origin(coord) + vec3!(half, half, Meters(0.0)),
+            half_edge: half,
+        }
+    }
+
+    /// The map for the chunk at `coord`, if it's resident.
+    pub fn chunk(&self, coord: ChunkCoord) -> Option<&TileMap> {
+        match self.chunks.get(&coord) {
+            Some(&Chunk { state: State::Loaded(ref map, _), .. }) => Some(map),
+            _ => None,
+        }
+    }
+
+    /// Iterate over the resident chunks.
+    pub fn loaded<'x>(&'x self) -> Box<Iterator<Item=(ChunkCoord, &'x TileMap)> + 'x> {
+        Box::new(self.chunks.iter().filter_map(|(&coord, chunk)| match chunk.state {
+            State::Loaded(ref map, _) => Some((coord, map)),
+            _                         => None,
+        }))
+    }
+
+    /// Append the entities from resident chunks whose bounds may intersect `region` to `out`.
+    pub fn query(&self, region: &BoundingCube, out: &mut Vec<EntityID>) {
+        let mut found = vec![];
+        self.tree.query(region, &mut found);
+
+        out.extend(found.iter().filter_map(|&e| self.tree.get(e).map(|(&id, _)| id)));
+    }
+
+    /// Request chunks which have come into view, spawn the entities of chunks which have finished
+    /// loading, and unload chunks which have been out of view for long enough. Call this once per
+    /// frame.
+    pub fn update(&mut self, cam: &Camera, mgr: &mut Manager, assets: &asset::Handle,
+                  now: time::Duration) -> Vec<ChunkEvent> {
+
+        let mut events = vec![];
+
+        self.frame += 1;
+
+        let center = self.chunk_at(cam.position);
+
+        for dy in -self.radius..self.radius + 1 {
+            for dx in -self.radius..self.radius + 1 {
+                let coord = ChunkCoord { x: center.x + dx, y: center.y + dy };
+
+                if let Visibility::Zero = cam.visible(self.chunk_bounds(coord)) {
+                    continue
+                }
+
+                self.want(coord);
+            }
+        }
+
+        loop {
+            match self.results.try_recv() {
+                Ok((coord, result)) => if self.finish(coord, result, mgr, assets, now) {
+                    events.push(ChunkEvent::Loaded(coord));
+                },
+
+                Err(TryRecvError::Empty)        => break,
+                Err(TryRecvError::Disconnected) => {
+                    error!(target: logging::ASSET, "chunk loader thread has exited");
+                    break
+                },
+            }
+        }
+
+        let stale: Vec<ChunkCoord> = self.chunks.iter()
+            .filter(|&(_, c)| self.frame - c.seen > self.unload_after)
+            .map(|(&coord, _)| coord)
+            .collect();
+
+        for coord in stale.into_iter() {
+            if self.unload(coord, mgr) {
+                events.push(ChunkEvent::Unloaded(coord));
+            }
+        }
+
+        events
+    }
+
+    // Mark a chunk as on camera this frame, requesting it if it isn't resident.
+    fn want(&mut self, coord: ChunkCoord) {
+        if let Some(chunk) = self.chunks.get_mut(&coord) {
+            chunk.seen = self.frame;
+            return
+        }
+
+        if self.requests.send(coord).is_err() {
+            return
+        }
+
+        self.chunks.insert(coord, Chunk { state: State::Loading, seen: self.frame });
+    }
+
+    // Handle a chunk from the loader thread. Returns true if the chunk became resident.
+    fn finish(&mut self, coord: ChunkCoord, result: Result<Option<TileMap>, String>,
+              mgr: &mut Manager, assets: &asset::Handle, now: time::Duration) -> bool {
+
+        // The chunk may have been unloaded while it was loading.
+        match self.chunks.get(&coord) {
+            Some(&Chunk { state: State::Loading, .. }) => {},
+            _ => return false,
+        }
+
+        let state = match result {
+            Ok(Some(mut map)) => {
+                map.origin    = self.chunk_origin(coord);
+                map.tile_size = self.tile_size;
+
+                let ids = map.spawn_objects(&self.prefix, mgr, assets, now);
+
+                for (obj, &id) in map.objects.iter().zip(ids.iter()) {
+                    self.track(id, BoundingCube {
+                        center:    map.ground(obj.pos),
+                        half_edge: self.tile_size / Meters(2.0),
+                    });
+                }
+
+                State::Loaded(map, ids)
+            },
+
+            Ok(None) => State::Missing,
+
+            Err(err) => {
+                error!(target: logging::ASSET,
+                       "failed to load chunk ({}, {}): {}", coord.x, coord.y, err);
+                State::Missing
+            },
+        };
+
+        let loaded = if let State::Loaded(..) = state { true } else { false };
+
+        if let Some(chunk) = self.chunks.get_mut(&coord) {
+            chunk.state = state;
+        }
+
+        loaded
+    }
+
+    // Forget a chunk, removing its entities. Returns true if it was resident.
+    fn unload(&mut self, coord: ChunkCoord, mgr: &mut Manager) -> bool {
+        match self.chunks.remove(&coord) {
+            Some(Chunk { state: State::Loaded(_, ids), .. }) => {
+                for &id in ids.iter() {
+                    mgr.remove_entity(id);
+                    self.untrack(id);
+                }
+
+                true
+            },
+
+            _ => false,
+        }
+    }
+
+    fn track(&mut self, id: EntityID, bounds: BoundingCube) {
+        let ent_id = self.tree.insert(id, bounds);
+
+        self.entries.insert(id, ent_id);
+    }
+
+    fn untrack(&mut self, id: EntityID) {
+        if let Some(ent_id) = self.entries.remove(&id) {
+            self.tree.remove(ent_id);
+        }
+    }
+}
@@ -0,0 +1,647 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Maps are read from either of Tiled's formats: TMX (XML) or TMJ (JSON), along with any external
+//! tilesets (TSX or TSJ) that they reference. Both orthogonal and isometric maps are supported;
+//! since the engine draws the world isometrically either way, tile (x, y) of a Tiled layer always
+//! becomes tile (x, y) of the resulting `TileMap`.
+//!
+//! Each tileset becomes a `SheetDesc` whose origin is the center of the ground tile at the bottom
+//! of each sprite. Tilesets must be backed by a single image without margins or spacing. The depth
+//! texture is taken from the tileset's `depth_image` property if it has one, and otherwise is
+//! expected next to the color image, with `_depth.png` in place of its extension.
+//!
+//! Layers are stacked `ImportOptions::layer_height` apart, unless they have an `elevation`
+//! property (in meters). Groups are flattened, and image layers are skipped.
+//!
+//! Objects become the map's objects. An object refers to the prefab named by its `prefab`
+//! property, or failing that to the prefab named after the file stem of the Tiled template it was
+//! created from (so `chest.tx` places a "chest"). Its rotation becomes its facing.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use rustc_serialize::json::Json;
+use xml::reader::EventReader;
+use xml::reader::events::XmlEvent;
+
+use logging;
+use grafix::sprite::SheetDesc;
+use tilemap::{Layer, MapObject, Tile, TileMap, Tileset};
+use tilemap::import::*;
+use units::*;
+
+/// Tiled stores flip flags in the high bits of each global tile ID.
+const GID_MASK: u32 = 0x1fffffff;
+
+/// Import the map at `path`, choosing the format by its extension (`.tmx` or `.tmj`). Paths to
+/// images are resolved relative to the file which mentions them.
+pub fn load<P: AsRef<Path>>(path: P, opts: &ImportOptions) -> Result<TileMap, Error> {
+    let path = path.as_ref();
+    let base = path.parent().unwrap_or(Path::new(""));
+    let text = try!(read_file(path));
+
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("tmx") => from_tmx(&text, base, opts),
+        Some("tmj") | Some("json") => from_tmj(&text, base, opts),
+        _ => Err(Error::Unsupported(format!("unrecognized map file {}", path.display()))),
+    }
+}
+
+/// Import a map from the contents of a TMX file. Relative paths are resolved against `base`.
+pub fn from_tmx(text: &str, base: &Path, opts: &ImportOptions) -> Result<TileMap, Error> {
+    let root = try!(parse_xml(text));
+
+    if root.name != "map" {
+        return Err(Error::Missing("map"))
+    }
+
+    let mut imp = try!(Importer::new(
+        opts,
+        try!(root.req_str("orientation")),
+        try!(root.req("tilewidth")),
+        try!(root.req("tileheight")),
+        try!(root.opt("infinite", 0u32)) != 0,
+    ));
+
+    for ts in root.children_named("tileset") {
+        let first_gid = try!(ts.req("firstgid"));
+
+        match ts.attr("source") {
+            Some(source) => {
+                let (raw, ts_base) = try!(load_external(source, base));
+                try!(imp.add_tileset(first_gid, raw, &ts_base));
+            },
+
+            None => try!(imp.add_tileset(first_gid, try!(tileset_from_xml(ts)), base)),
+        }
+    }
+
+    try!(xml_layers(&mut imp, &root));
+
+    Ok(imp.map)
+}
+
+/// Import a map from the contents of a TMJ file. Relative paths are resolved against `base`.
+pub fn from_tmj(text: &str, base: &Path, opts: &ImportOptions) -> Result<TileMap, Error> {
+    let root = try!(parse_json(text));
+
+    let mut imp = try!(Importer::new(
+        opts,
+        try!(json_str(&root, "orientation")),
+        try!(json_u32(&root, "tilewidth")),
+        try!(json_u32(&root, "tileheight")),
+        root.find("infinite").and_then(|i| i.as_boolean()).unwrap_or(false),
+    ));
+
+    for ts in try!(json_array(&root, "tilesets")).iter() {
+        let first_gid = try!(json_u32(ts, "firstgid"));
+
+        match ts.find("source").and_then(|s| s.as_string()) {
+            Some(source) => {
+                let (raw, ts_base) = try!(load_external(source, base));
+                try!(imp.add_tileset(first_gid, raw, &ts_base));
+            },
+
+            None => try!(imp.add_tileset(first_gid, try!(tileset_from_json(ts)), base)),
+        }
+    }
+
+    try!(json_layers(&mut imp, try!(json_array(&root, "layers"))));
+
+    Ok(imp.map)
+}
+
+/// A tileset as described by Tiled, before it has been turned into a `SheetDesc`.
+struct RawTileset {
+    name:         String,
+    tile_width:   u32,
+    tile_height:  u32,
+    tile_count:   u32,
+    columns:      u32,
+    margin:       u32,
+    spacing:      u32,
+    offset:       (i32, i32),
+    image:        String,
+    image_width:  u32,
+    image_height: u32,
+    properties:   BTreeMap<String, String>,
+}
+
+/// An object as described by Tiled, in pixels.
+struct RawObject {
+    name:       String,
+    kind:       String,
+    x:          f32,
+    y:          f32,
+    gid:        u32,
+    rotation:   f32,
+    template:   Option<String>,
+    properties: BTreeMap<String, String>,
+}
+
+/// State shared by the TMX and TMJ importers.
+struct Importer<'x> {
+    opts:        &'x ImportOptions,
+    orthogonal:  bool,
+    tile_width:  u32,
+    tile_height: u32,
+    first_gids:  Vec<u32>,
+    num_layers:  usize,
+    map:         TileMap,
+}
+
+impl<'x> Importer<'x> {
+    fn new(opts: &'x ImportOptions, orientation: &str, tile_width: u32, tile_height: u32,
+           infinite: bool) -> Result<Importer<'x>, Error> {
+
+        let orthogonal = match orientation {
+            "orthogonal" => true,
+            "isometric"  => false,
+            other        => return Err(Error::Unsupported(format!("{} maps", other))),
+        };
+
+        if infinite {
+            return Err(Error::Unsupported(From::from("infinite maps")))
+        }
+
+        if tile_width == 0 || tile_height == 0 {
+            return Err(Error::Invalid("tilewidth"))
+        }
+
+        Ok(Importer {
+            opts:        opts,
+            orthogonal:  orthogonal,
+            tile_width:  tile_width,
+            tile_height: tile_height,
+            first_gids:  vec![],
+            num_layers:  0,
+            map:         TileMap::new(opts.tile_size),
+        })
+    }
+
+    fn add_tileset(&mut self, first_gid: u32, raw: RawTileset, base: &Path) -> Result<(), Error> {
+        if raw.image.is_empty() {
+            return Err(Error::Unsupported(format!("image collection tileset '{}'", raw.name)))
+        }
+
+        if raw.margin != 0 || raw.spacing != 0 {
+            return Err(Error::Unsupported(format!("margins or spacing in tileset '{}'", raw.name)))
+        }
+
+        if raw.tile_width == 0 || raw.tile_height == 0 {
+            return Err(Error::Invalid("tilewidth"))
+        }
+
+        let columns = if raw.columns > 0 { raw.columns } else { raw.image_width / raw.tile_width };
+
+        if columns == 0 || raw.tile_count == 0 {
+            return Err(Error::Invalid("columns"))
+        }
+
+        let color = base.join(&raw.image);
+        let depth = match raw.properties.get("depth_image") {
+            Some(path) => base.join(path),
+            None       => depth_path_for(&color),
+        };
+
+        // Put the origin at the center of the ground tile, which sits at the bottom of taller
+        // sprites. Tiled's tile offset moves the image, so the origin moves the other way. A tile
+        // shorter than half a map tile, or a large offset, puts the origin outside the sprite,
+        // which `to_u16` rejects.
+        let origin_x = (raw.tile_width / 2) as i64 - raw.offset.0 as i64;
+        let origin_y = raw.tile_height as i64 - (self.tile_height / 2) as i64 - raw.offset.1 as i64;
+
+        let rows = (raw.tile_count + columns - 1) / columns;
+
+        let sheet = SheetDesc {
+            img_width:  try!(to_u16(raw.image_width as i64, "imagewidth")),
+            img_height: try!(to_u16(raw.image_height as i64, "imageheight")),
+            origin_x:   try!(to_u16(origin_x, "tileoffset")),
+            origin_y:   try!(to_u16(origin_y, "tileoffset")),
+            spr_width:  try!(to_u16(raw.tile_width as i64, "tilewidth")),
+            spr_height: try!(to_u16(raw.tile_height as i64, "tileheight")),
+            num_across: try!(to_u16(columns as i64, "columns")),
+            num_down:   try!(to_u16(rows as i64, "tilecount")),
+            total:      try!(to_u16(raw.tile_count as i64, "tilecount")),
+            color_path: color.to_string_lossy().into_owned(),
+            depth_path: depth.to_string_lossy().into_owned(),
+            color_hash: 0,
+            depth_hash: 0,
+
+            normal_path: None,
+            normal_hash: 0,
+
+            emissive_path: None,
+            emissive_hash: 0,
+        };
+
+        self.first_gids.push(first_gid);
+        self.map.tilesets.push(Tileset { name: raw.name, sheet: sheet });
+
+        Ok(())
+    }
+
+    /// Resolve a global tile ID. Flip flags are discarded, since sprites can't be flipped.
+    fn tile(&self, gid: u32) -> Result<Option<Tile>, Error> {
+        if gid & !GID_MASK != 0 {
+            debug!(target: logging::ASSET, "ignoring flip flags on tile {:#x}", gid);
+        }
+
+        let gid = gid & GID_MASK;
+
+        if gid == 0 {
+            return Ok(None)
+        }
+
+        match self.first_gids.iter().rposition(|&first| first <= gid) {
+            Some(i) => {
+                let index = gid - self.first_gids[i];
+
+                if index >= self.map.tilesets[i].sheet.total as u32 {
+                    return Err(Error::BadTile(gid))
+                }
+
+                Ok(Some(Tile::Sprite { tileset: i as u16, index: index as u16 }))
+            },
+
+            None => Err(Error::BadTile(gid)),
+        }
+    }
+
+    /// The elevation of the next layer, given its properties.
+    fn next_z(&mut self, props: &BTreeMap<String, String>) -> Result<Meters, Error> {
+        let index = self.num_layers;
+        self.num_layers += 1;
+
+        match props.get("elevation") {
+            Some(e) => e.parse::<f32>().map(Meters).map_err(|_| Error::Invalid("elevation")),
+            None    => Ok(self.opts.layer_height * Meters(index as f32)),
+        }
+    }
+
+    fn add_layer(&mut self, name: String, width: u32, height: u32, gids: &[u32],
+                 props: &BTreeMap<String, String>) -> Result<(), Error> {
+
+        if width == 0 || gids.len() != (width * height) as usize {
+            return Err(Error::Invalid("data"))
+        }
+
+        let z = try!(self.next_z(props));
+        let mut layer = Layer::new(name, z, width, height);
+
+        for (i, &gid) in gids.iter().enumerate() {
+            let tile = try!(self.tile(gid));
+            layer.set(i as u32 % width, i as u32 / width, tile);
+        }
+
+        self.map.layers.push(layer);
+
+        Ok(())
+    }
+
+    fn add_object(&mut self, raw: RawObject, z: Meters) -> Result<(), Error> {
+        // Tiled measures object positions in pixels. On isometric maps, both axes are measured in
+        // units of the tile height.
+        let (unit_x, unit_y) = if self.orthogonal {
+            (self.tile_width as f32, self.tile_height as f32)
+        } else {
+            (self.tile_height as f32, self.tile_height as f32)
+        };
+
+        let tile = try!(self.tile(raw.gid));
+
+        // A "prefab" property names the prefab explicitly. Otherwise an object created from a
+        // Tiled template is an instance of the prefab named after the template's file.
+        let mut properties = raw.properties;
+        let template       = raw.template;
+
+        let prefab = properties.remove("prefab").or_else(|| template.as_ref().and_then(|t| {
+            Path::new(t).file_stem().and_then(|s| s.to_str()).map(From::from)
+        }));
+
+        self.map.objects.push(MapObject {
+            name:       raw.name,
+            kind:       raw.kind,
+            pos:        self.map.origin + vec3!(self.map.tile_size * Meters(raw.x / unit_x),
+                                                self.map.tile_size * Meters(raw.y / unit_y),
+                                                z),
+            tile:       tile,
+            properties: properties,
+            prefab:     prefab,
+            facing:     raw.rotation,
+        });
+
+        Ok(())
+    }
+}
+
+/// Load an external tileset, returning it along with the directory that its paths are relative to.
+fn load_external(source: &str, base: &Path) -> Result<(RawTileset, PathBuf), Error> {
+    let path    = base.join(source);
+    let ts_base = path.parent().map(|p| p.to_path_buf()).unwrap_or(PathBuf::new());
+    let text    = try!(read_file(&path));
+
+    let raw = match path.extension().and_then(|e| e.to_str()) {
+        Some("tsx") => try!(tileset_from_xml(&try!(parse_xml(&text)))),
+        Some("tsj") | Some("json") => try!(tileset_from_json(&try!(parse_json(&text)))),
+        _ => return Err(Error::Unsupported(format!("unrecognized tileset {}", path.display()))),
+    };
+
+    Ok((raw, ts_base))
+}
+
+// ------------------------------------------------------------------------------------------------
+// TMX
+
+/// A minimal DOM, which is all we need for files the size of a map.
+struct Element {
+    name:     String,
+    attrs:    Vec<(String, String)>,
+    children: Vec<Element>,
+    text:     String,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref v)| &v[..])
+    }
+
+    fn req_str(&self, name: &'static str) -> Result<&str, Error> {
+        self.attr(name).ok_or(Error::Missing(name))
+    }
+
+    fn req<T: FromStr>(&self, name: &'static str) -> Result<T, Error> {
+        try!(self.req_str(name)).parse().map_err(|_| Error::Invalid(name))
+    }
+
+    fn opt<T: FromStr>(&self, name: &'static str, default: T) -> Result<T, Error> {
+        match self.attr(name) {
+            Some(v) => v.parse().map_err(|_| Error::Invalid(name)),
+            None    => Ok(default),
+        }
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    fn children_named<'x>(&'x self, name: &'x str) -> Box<Iterator<Item=&'x Element> + 'x> {
+        Box::new(self.children.iter().filter(move |c| c.name == name))
+    }
+}
+
+fn parse_xml(text: &str) -> Result<Element, Error> {
+    let mut stack: Vec<Element> = vec![];
+    let mut root = None;
+
+    for ev in EventReader::new(text.as_bytes()).events() {
+        match ev {
+            XmlEvent::StartElement { name, attributes, .. } => stack.push(Element {
+                name:     name.local_name,
+                attrs:    attributes.into_iter().map(|a| (a.name.local_name, a.value)).collect(),
+                children: vec![],
+                text:     String::new(),
+            }),
+
+            XmlEvent::EndElement { .. } => {
+                let el = try!(stack.pop().ok_or(Error::Xml(From::from("unbalanced element"))));
+
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(el),
+                    None         => root = Some(el),
+                }
+            },
+
+            XmlEvent::Characters(s) | XmlEvent::CData(s) => {
+                if let Some(el) = stack.last_mut() {
+                    el.text.push_str(&s);
+                }
+            },
+
+            XmlEvent::Error(e) => return Err(Error::Xml(format!("{}", e))),
+
+            _ => {},
+        }
+    }
+
+    root.ok_or(Error::Xml(From::from("no root element")))
+}
+
+fn xml_properties(el: &Element) -> Result<BTreeMap<String, String>, Error> {
+    let mut props = BTreeMap::new();
+
+    if let Some(list) = el.child("properties") {
+        for p in list.children_named("property") {
+            let value = match p.attr("value") {
+                Some(v) => From::from(v),
+                None    => p.text.clone(),
+            };
+
+            props.insert(From::from(try!(p.req_str("name"))), value);
+        }
+    }
+
+    Ok(props)
+}
+
+fn tileset_from_xml(el: &Element) -> Result<RawTileset, Error> {
+    let offset = match el.child("tileoffset") {
+        Some(o) => (try!(o.opt("x", 0)), try!(o.opt("y", 0))),
+        None    => (0, 0),
+    };
+
+    let (image, image_width, image_height) = match el.child("image") {
+        Some(i) => (From::from(try!(i.req_str("source"))), try!(i.req("width")),
+                    try!(i.req("height"))),
+        None    => (String::new(), 0, 0),
+    };
+
+    Ok(RawTileset {
+        name:         From::from(try!(el.req_str("name"))),
+        tile_width:   try!(el.req("tilewidth")),
+        tile_height:  try!(el.req("tileheight")),
+        tile_count:   try!(el.req("tilecount")),
+        columns:      try!(el.opt("columns", 0)),
+        margin:       try!(el.opt("margin", 0)),
+        spacing:      try!(el.opt("spacing", 0)),
+        offset:       offset,
+        image:        image,
+        image_width:  image_width,
+        image_height: image_height,
+        properties:   try!(xml_properties(el)),
+    })
+}
+
+fn xml_layers(imp: &mut Importer, parent: &Element) -> Result<(), Error> {
+    for el in parent.children.iter() {
+        let name  = el.attr("name").unwrap_or("");
+        let props = try!(xml_properties(el));
+
+        match &el.name[..] {
+            "layer" => {
+                let data = try!(el.child("data").ok_or(Error::Missing("data")));
+
+                let gids: Vec<u32> = match data.attr("encoding") {
+                    Some("csv") => try!(data.text.trim().split(',')
+                        .map(|g| g.trim().parse().map_err(|_| Error::Invalid("data")))
+                        .collect::<Result<_, Error>>()),
+
+                    None => try!(data.children_named("tile").map(|t| t.opt("gid", 0))
+                        .collect::<Result<_, Error>>()),
+
+                    Some(enc) => return Err(Error::Unsupported(format!("{} layer data", enc))),
+                };
+
+                let (width, height) = (try!(el.req("width")), try!(el.req("height")));
+                try!(imp.add_layer(From::from(name), width, height, &gids, &props));
+            },
+
+            "objectgroup" => {
+                let z = try!(imp.next_z(&props));
+
+                for obj in el.children_named("object") {
+                    let kind = obj.attr("class").or(obj.attr("type")).unwrap_or("");
+
+                    try!(imp.add_object(RawObject {
+                        name:       From::from(obj.attr("name").unwrap_or("")),
+                        kind:       From::from(kind),
+                        x:          try!(obj.req("x")),
+                        y:          try!(obj.req("y")),
+                        gid:        try!(obj.opt("gid", 0)),
+                        rotation:   try!(obj.opt("rotation", 0.0)),
+                        template:   obj.attr("template").map(From::from),
+                        properties: try!(xml_properties(obj)),
+                    }, z));
+                }
+            },
+
+            "group" => try!(xml_layers(imp, el)),
+
+            "imagelayer" => debug!(target: logging::ASSET, "skipping image layer '{}'", name),
+
+            _ => {},
+        }
+    }
+
+    Ok(())
+}
+
+// ------------------------------------------------------------------------------------------------
+// TMJ
+
+/// Read custom properties, which are a list of `{name, type, value}` objects in current versions of
+/// Tiled but were a plain object in older ones.
+fn json_properties(j: &Json) -> Result<BTreeMap<String, String>, Error> {
+    let mut props = BTreeMap::new();
+
+    match j.find("properties") {
+        Some(&Json::Array(ref list)) => {
+            for p in list.iter() {
+                let value = p.find("value").map(json_value).unwrap_or(String::new());
+                props.insert(From::from(try!(json_str(p, "name"))), value);
+            }
+        },
+
+        Some(&Json::Object(ref obj)) => {
+            for (k, v) in obj.iter() {
+                props.insert(k.clone(), json_value(v));
+            }
+        },
+
+        Some(_) => return Err(Error::Invalid("properties")),
+
+        None => {},
+    }
+
+    Ok(props)
+}
+
+fn tileset_from_json(j: &Json) -> Result<RawTileset, Error> {
+    let offset = match j.find("tileoffset") {
+        Some(o) => (try!(json_f32(o, "x")) as i32, try!(json_f32(o, "y")) as i32),
+        None    => (0, 0),
+    };
+
+    Ok(RawTileset {
+        name:         From::from(try!(json_str(j, "name"))),
+        tile_width:   try!(json_u32(j, "tilewidth")),
+        tile_height:  try!(json_u32(j, "tileheight")),
+        tile_count:   try!(json_u32(j, "tilecount")),
+        columns:      try!(json_opt_u32(j, "columns")),
+        margin:       try!(json_opt_u32(j, "margin")),
+        spacing:      try!(json_opt_u32(j, "spacing")),
+        offset:       offset,
+        image:        From::from(j.find("image").and_then(|i| i.as_string()).unwrap_or("")),
+        image_width:  try!(json_opt_u32(j, "imagewidth")),
+        image_height: try!(json_opt_u32(j, "imageheight")),
+        properties:   try!(json_properties(j)),
+    })
+}
+
+fn json_layers(imp: &mut Importer, layers: &[Json]) -> Result<(), Error> {
+    for layer in layers.iter() {
+        let name  = json_name(layer);
+        let props = try!(json_properties(layer));
+
+        match try!(json_str(layer, "type")) {
+            "tilelayer" => {
+                match layer.find("encoding").and_then(|e| e.as_string()) {
+                    None | Some("csv") => {},
+                    Some(enc) => return Err(Error::Unsupported(format!("{} layer data", enc))),
+                }
+
+                let gids: Vec<u32> = try!(try!(json_array(layer, "data")).iter()
+                    .map(|g| g.as_u64().map(|g| g as u32).ok_or(Error::Invalid("data")))
+                    .collect::<Result<_, Error>>());
+
+                try!(imp.add_layer(From::from(name), try!(json_u32(layer, "width")),
+                                   try!(json_u32(layer, "height")), &gids, &props));
+            },
+
+            "objectgroup" => {
+                let z = try!(imp.next_z(&props));
+
+                for obj in try!(json_array(layer, "objects")).iter() {
+                    let kind = obj.find("class").or(obj.find("type"))
+                        .and_then(|k| k.as_string()).unwrap_or("");
+
+                    try!(imp.add_object(RawObject {
+                        name:       From::from(json_name(obj)),
+                        kind:       From::from(kind),
+                        x:          try!(json_f32(obj, "x")),
+                        y:          try!(json_f32(obj, "y")),
+                        gid:        try!(json_opt_u32(obj, "gid")),
+                        rotation:   obj.find("rotation").and_then(|r| r.as_f64())
+                                       .unwrap_or(0.0) as f32,
+                        template:   obj.find("template").and_then(|t| t.as_string())
+                                       .map(From::from),
+                        properties: try!(json_properties(obj)),
+                    }, z));
+                }
+            },
+
+            "group" => try!(json_layers(imp, try!(json_array(layer, "layers")))),
+
+            other => debug!(target: logging::ASSET, "skipping {} layer '{}'", other, name),
+        }
+    }
+
+    Ok(())
+}
+
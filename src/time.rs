@@ -16,6 +16,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
 use std::ops::{Add, Sub, Mul, Div, Rem};
+use std::time as std_time;
 
 /// A period of time, measured at microsecond granularity. Duratons are unsigned, there is no such
 /// thing as a negative duration. It is implemented as a 64-bit number of microseconds, the struct
@@ -59,6 +60,13 @@ impl Duration {
     pub fn as_usec(self) -> u64 { self.us }
 }
 
+impl From<std_time::Duration> for Duration {
+    /// Convert from the standard library's representation, truncating to whole microseconds.
+    fn from(d: std_time::Duration) -> Duration {
+        Duration { us: d.as_secs() * 1_000_000 + (d.subsec_nanos() / 1_000) as u64 }
+    }
+}
+
 impl Add for Duration {
     type Output = Duration;
 
@@ -0,0 +1,486 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Scripted sequences of events, such as cutscenes.
+//!
+//! A `Timeline` lists actions at offsets from its start: moving the camera, spawning and removing
+//! entities (called actors, and referred to by name), starting animations on them, and cues which
+//! are passed on to the game, such as sounds to play. A `Playback` runs a timeline against the
+//! engine clock, so that a cutscene is a data file rather than a state machine in game code.
+//!
+//! Timelines are text files kept with the game's other assets, so that mods can replace them like
+//! any other file (see `asset::mods::ModSet::resolve`). Each line is an offset in milliseconds
+//! followed by an action:
+//!
+//! ```text
+//! # Pan over to the gate while a guard walks up to it and waves.
+//! 0     camera 12 4 0 2000
+//! 500   spawn guard 16 8 0 guard_walk 800 repeat
+//! 2000  animate guard guard_wave 600
+//! 2000  audio gate_creak
+//! 2600  despawn guard
+//! 3000  cue fade_out 500
+//! ```
+//!
+//! The actions are
+//!
+//!  * `camera X Y Z MS`: move the camera's position to `(X, Y, Z)` over `MS` milliseconds.
+//!  * `spawn ACTOR X Y Z ANIM MS [repeat]`: create an entity at `(X, Y, Z)` showing the animation
+//!    called `ANIM`, which takes `MS` milliseconds and optionally repeats.
+//!  * `animate ACTOR ANIM MS [repeat]`: start an animation on an actor.
+//!  * `despawn ACTOR`: remove an actor.
+//!  * `audio NAME`: play a sound, by passing `Cue::Audio` to the game.
+//!  * `cue NAME [ARGS...]`: anything else, passed to the game as `Cue::Custom`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use entity::component::{WorldLocation, WorldRender};
+use entity::sim::{Components, EntityID, Manager};
+use grafix::anim::{self, AnimID};
+use grafix::camera::Camera;
+use lines;
+use logging;
+use math;
+use time::Duration;
+use units::*;
+
+/// The half-edge of the bounding cube given to actors created by `spawn`.
+pub const ACTOR_HALF_EDGE: Meters = Meters(0.5);
+
+/// Something a timeline does.
+#[derive(Clone,Debug,PartialEq)]
+pub enum Action {
+    /// Move the camera's position to `to`, easing in and out over `over`.
+    Camera {
+        /// Where the camera ends up.
+        to: math::Vec3<Meters>,
+
+        /// How long the move takes.
+        over: Duration,
+    },
+
+    /// Create an entity called `actor` at `at`, showing an animation.
+    Spawn {
+        /// The name the timeline uses for the entity.
+        actor: String,
+
+        /// Where the entity is created.
+        at: math::Vec3<Meters>,
+
+        /// The animation it shows.
+        anim: AnimSpec,
+    },
+
+    /// Start an animation on an actor.
+    Animate {
+        /// The actor's name.
+        actor: String,
+
+        /// The animation to start.
+        anim: AnimSpec,
+    },
+
+    /// Remove an actor.
+    Despawn(String),
+
+    /// Pass a cue on to the game.
+    Cue(Cue),
+}
+
+/// An animation to start, by the name of its asset.
+#[derive(Clone,Debug,PartialEq)]
+pub struct AnimSpec {
+    /// The name of the animation asset.
+    pub name: String,
+
+    /// How long the animation takes.
+    pub duration: Duration,
+
+    /// True if the animation repeats until replaced.
+    pub repeat: bool,
+}
+
+/// An event which the engine leaves to the game to carry out.
+#[derive(Clone,Debug,PartialEq)]
+pub enum Cue {
+    /// Play the sound called by the given name.
+    Audio(String),
+
+    /// A cue with a name and arguments of the game's choosing.
+    Custom(String, Vec<String>),
+}
+
+/// A sequence of actions, each with its offset from the start of the timeline.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Timeline {
+    /// The actions, in the order they happen.
+    pub actions: Vec<(Duration, Action)>,
+}
+
+pub use lines::Error;
+
+impl Timeline {
+    /// Create an empty timeline.
+    pub fn new() -> Timeline {
+        Timeline { actions: vec![] }
+    }
+
+    /// How long the timeline lasts: until its last action, including the time taken by any
+    /// camera move.
+    pub fn len(&self) -> Duration {
+        self.actions.iter().fold(Duration::usec(0), |len, &(at, ref action)| {
+            let end = match *action {
+                Action::Camera { over, .. } => at + over,
+                _                           => at,
+            };
+
+            if end > len { end } else { len }
+        })
+    }
+
+    /// Load a timeline from a file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Timeline, Error> {
+        lines::load(path)
+    }
+
+    /// Save the timeline to a file, e.g. from an editor.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        lines::save(self, path)
+    }
+}
+
+impl FromStr for Timeline {
+    type Err = Error;
+
+    /// Parse a timeline from the text format described in the module documentation. Blank lines
+    /// and lines starting with `#` are ignored. Playback depends on the actions being in order, so
+    /// they're sorted; actions at the same offset happen in the order they're written.
+    fn from_str(text: &str) -> Result<Timeline, Error> {
+        lines::parse(text, parse_line).map(|actions| Timeline { actions: actions })
+    }
+}
+
+impl fmt::Display for Timeline {
+    /// Render the timeline in the text format described in the module documentation. Offsets and
+    /// durations are written in whole milliseconds.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(at, ref action) in self.actions.iter() {
+            try!(write!(f, "{}", at.as_msec()));
+
+            try!(match *action {
+                Action::Camera { ref to, over } =>
+                    write!(f, " camera {} {} {} {}", to.x.0, to.y.0, to.z.0, over.as_msec()),
+
+                Action::Spawn { ref actor, at: ref pos, ref anim } =>
+                    write!(f, " spawn {} {} {} {} {}", actor, pos.x.0, pos.y.0, pos.z.0, anim),
+
+                Action::Animate { ref actor, ref anim } => write!(f, " animate {} {}", actor, anim),
+
+                Action::Despawn(ref actor) => write!(f, " despawn {}", actor),
+
+                Action::Cue(Cue::Audio(ref name)) => write!(f, " audio {}", name),
+
+                Action::Cue(Cue::Custom(ref name, ref args)) => {
+                    try!(write!(f, " cue {}", name));
+
+                    for arg in args.iter() {
+                        try!(write!(f, " {}", arg));
+                    }
+
+                    Ok(())
+                },
+            });
+
+            try!(writeln!(f, ""));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for AnimSpec {
+    /// Render the animation as it's written in a timeline: its name, its duration in milliseconds,
+    /// and `repeat` if it repeats.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{} {}", self.name, self.duration.as_msec()));
+
+        if self.repeat {
+            try!(write!(f, " repeat"));
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Option<(Duration, Action)> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    let ms  = |w: &str| u64::from_str(w).ok().map(Duration::msec);
+    let num = |w: &str| f32::from_str(w).ok();
+
+    let at = match words.first().and_then(|&w| ms(w)) {
+        Some(at) => at,
+        None     => return None,
+    };
+
+    let point = |i: usize| match (num(words[i]), num(words[i + 1]), num(words[i + 2])) {
+        (Some(x), Some(y), Some(z)) => Some(vec3!(Meters ; x, y, z)),
+        _                           => None,
+    };
+
+    // An animation starting at word `i`, which must be the last thing on the line.
+    let anim = |i: usize| {
+        let repeat = match words.len() - i {
+            2 => false,
+            3 if words[i + 2] == "repeat" => true,
+            _ => return None,
+        };
+
+        ms(words[i + 1]).map(|duration| AnimSpec {
+            name:     From::from(words[i]),
+            duration: duration,
+            repeat:   repeat,
+        })
+    };
+
+    let action = match (words.get(1).cloned(), words.len()) {
+        (Some("camera"), 6) => match (point(2), ms(words[5])) {
+            (Some(to), Some(over)) => Action::Camera { to: to, over: over },
+            _                      => return None,
+        },
+
+        (Some("spawn"), 8) | (Some("spawn"), 9) => match (point(3), anim(6)) {
+            (Some(at), Some(anim)) => Action::Spawn {
+                actor: From::from(words[2]),
+                at:    at,
+                anim:  anim,
+            },
+            _ => return None,
+        },
+
+        (Some("animate"), 5) | (Some("animate"), 6) => match anim(3) {
+            Some(anim) => Action::Animate { actor: From::from(words[2]), anim: anim },
+            None       => return None,
+        },
+
+        (Some("despawn"), 3) => Action::Despawn(From::from(words[2])),
+
+        (Some("audio"), 3) => Action::Cue(Cue::Audio(From::from(words[2]))),
+
+        (Some("cue"), n) if n >= 3 => Action::Cue(Cue::Custom(
+            From::from(words[2]),
+            words[3..].iter().map(|&w| From::from(w)).collect(),
+        )),
+
+        _ => return None,
+    };
+
+    Some((at, action))
+}
+
+// A camera move in progress.
+struct CameraMove {
+    from:  math::Vec3<Meters>,
+    to:    math::Vec3<Meters>,
+    start: Duration,
+    over:  Duration,
+}
+
+/// Plays a `Timeline`, carrying out each of its actions once the engine clock reaches it.
+pub struct Playback {
+    timeline: Timeline,
+    start:    Duration,
+
+    // The index of the next action to carry out.
+    next: usize,
+
+    actors: BTreeMap<String, EntityID>,
+    camera: Option<CameraMove>,
+}
+
+impl Playback {
+    /// Start playing `timeline` at `now`.
+    pub fn new(timeline: Timeline, now: Duration) -> Playback {
+        Playback {
+            timeline: timeline,
+            start:    now,
+            next:     0,
+            actors:   BTreeMap::new(),
+            camera:   None,
+        }
+    }
+
+    /// The timeline being played.
+    pub fn timeline(&self) -> &Timeline {
+        &self.timeline
+    }
+
+    /// True once every action has been carried out and the camera has stopped moving.
+    pub fn is_finished(&self) -> bool {
+        self.next == self.timeline.actions.len() && self.camera.is_none()
+    }
+
+    /// The entity an actor refers to, if it has been spawned and not despawned.
+    pub fn actor(&self, name: &str) -> Option<EntityID> {
+        self.actors.get(name).cloned()
+    }
+
+    /// Carry out every action which is due by `now`, and move the camera. Actors are created in
+    /// and removed from `world`, and animations are found by name with `anims` (e.g.
+    /// `asset::Handle::get_id` on a client). Returns the cues which the game should carry out, in
+    /// order.
+    ///
+    /// Actions which refer to actors or animations which don't exist are skipped, with a warning.
+    pub fn update<F>(&mut self, now: Duration, world: &mut Manager, camera: &mut Camera, anims: F)
+        -> Vec<Cue> where F: Fn(&str) -> Option<AnimID> {
+
+        let mut cues = vec![];
+
+        while self.next < self.timeline.actions.len() {
+            let (at, action) = self.timeline.actions[self.next].clone();
+
+            if self.start + at > now {
+                break
+            }
+
+            self.next += 1;
+
+            // Actions happen at the time the timeline says, even if `update` is late.
+            let when = self.start + at;
+
+            match action {
+                Action::Camera { to, over } => {
+                    self.camera = Some(CameraMove {
+                        from:  camera.position,
+                        to:    to,
+                        start: when,
+                        over:  over,
+                    });
+                },
+
+                Action::Spawn { actor, at: pos, anim } => {
+                    let inst = match instance(&anim, when, &anims) {
+                        Some(inst) => inst,
+                        None       => continue,
+                    };
+
+                    let comps = Components {
+                        world_location: Some(WorldLocation {
+                            bounds: math::BoundingCube { center: pos, half_edge: ACTOR_HALF_EDGE },
+                        }),
+                        world_render: Some(WorldRender { anim: inst }),
+                        ..Components::none()
+                    };
+
+                    if let Some(old) = self.actors.insert(actor, world.create_entity(comps)) {
+                        world.remove_entity(old);
+                    }
+                },
+
+                Action::Animate { actor, anim } => {
+                    let id = match self.actors.get(&actor) {
+                        Some(&id) => id,
+                        None      => {
+                            warn!(target: logging::ECS, "timeline animates unknown actor `{}'",
+                                  actor);
+                            continue
+                        },
+                    };
+
+                    if let Some(inst) = instance(&anim, when, &anims) {
+                        if let Some(mut render) = world.view_entity(id).world_render {
+                            render.anim = inst;
+                        }
+                    }
+                },
+
+                Action::Despawn(actor) => match self.actors.remove(&actor) {
+                    Some(id) => world.remove_entity(id),
+                    None     => warn!(target: logging::ECS,
+                                      "timeline despawns unknown actor `{}'", actor),
+                },
+
+                Action::Cue(cue) => cues.push(cue),
+            }
+        }
+
+        self.move_camera(now, camera);
+
+        cues
+    }
+
+    /// Remove every actor from `world`, e.g. when a cutscene is skipped or over.
+    pub fn despawn_all(&mut self, world: &mut Manager) {
+        for (_, id) in self.actors.iter() {
+            world.remove_entity(*id);
+        }
+
+        self.actors.clear();
+    }
+
+    fn move_camera(&mut self, now: Duration, camera: &mut Camera) {
+        let done = match self.camera {
+            Some(ref mv) => {
+                let t = if mv.over.as_usec() == 0 || now >= mv.start + mv.over {
+                    1.0
+                } else if now <= mv.start {
+                    0.0
+                } else {
+                    ((now - mv.start) / mv.over) as f32
+                };
+
+                // Ease in and out, so that the camera doesn't lurch into motion.
+                let s = t * t * (3.0 - 2.0 * t);
+
+                camera.position = mv.from + (mv.to - mv.from).scaled(Meters(s));
+                camera.clamp_to_bounds();
+
+                t >= 1.0
+            },
+
+            None => false,
+        };
+
+        if done {
+            self.camera = None;
+        }
+    }
+}
+
+// An instance of `anim` starting at `when`, or `None` (with a warning) if there's no such
+// animation.
+fn instance<F>(anim: &AnimSpec, when: Duration, anims: &F) -> Option<anim::Instance>
+    where F: Fn(&str) -> Option<AnimID> {
+
+    match anims(&anim.name) {
+        Some(id) => Some(anim::Instance {
+            anim_id:  id,
+            t_start:  when,
+            duration: anim.duration,
+            repeat:   anim.repeat,
+            flip_x:   false,
+        }),
+
+        None => {
+            warn!(target: logging::ECS, "timeline refers to unknown animation `{}'", anim.name);
+            None
+        },
+    }
+}
@@ -0,0 +1,280 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Capturing everything the engine does during one frame as a Chrome trace, for investigating
+//! slow frames in more detail than the stats overlays give.
+//!
+//! `request_capture` (or the `trace` console command, or the `CAPTURE_ACTION` key binding) arms a
+//! capture, which starts at the next frame boundary and ends at the one after, when the events are
+//! written out as JSON. The file can be opened in `chrome://tracing` or Perfetto.
+//!
+//! The engine records the systems it runs, its draw calls and render passes, and packets sent
+//! through a `net::Traced` transport. Games can add their own events with `span` and `instant`.
+//! Recording costs next to nothing while no capture is running. Events are only recorded on the
+//! thread which calls `next_frame`; others are ignored.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use input;
+
+/// Category for the systems run by an entity manager.
+pub const SYSTEM: &'static str = "system";
+
+/// Category for draw calls.
+pub const DRAW: &'static str = "draw";
+
+/// Category for render passes and other work on the GL context.
+pub const GL: &'static str = "gl";
+
+/// Category for network packets.
+pub const NET: &'static str = "net";
+
+/// Where the `trace` console command and `CAPTURE_ACTION` write captures by default.
+pub const DEFAULT_PATH: &'static str = "frame-trace.json";
+
+/// The input action which captures the next frame to `DEFAULT_PATH` (see `handle_event`).
+pub const CAPTURE_ACTION: &'static str = "capture_trace";
+
+// One event in the Chrome trace format. `dur` is only used by complete events (phase 'X').
+struct Event {
+    cat:   &'static str,
+    name:  &'static str,
+    phase: char,
+    ts:    u64,
+    dur:   u64,
+    args:  Vec<(&'static str, String)>,
+}
+
+struct Capture {
+    path:   PathBuf,
+    frame:  u64,
+    origin: Instant,
+    events: Vec<Event>,
+}
+
+thread_local!(static PENDING: RefCell<Option<PathBuf>> = RefCell::new(None));
+thread_local!(static CAPTURE: RefCell<Option<Capture>> = RefCell::new(None));
+
+/// Capture the next frame, writing it to `path` once it's over. This replaces any capture which
+/// was requested but hasn't started yet.
+pub fn request_capture<P: AsRef<Path>>(path: P) {
+    let path = path.as_ref().to_path_buf();
+    PENDING.with(|p| *p.borrow_mut() = Some(path));
+}
+
+/// True while a frame is being captured.
+pub fn is_recording() -> bool {
+    CAPTURE.with(|c| c.borrow().is_some())
+}
+
+/// Mark the boundary between frames, with `frame` the number of the frame which is starting. This
+/// finishes the capture in progress, if any, writing it out, and starts a capture if one has been
+/// requested. Returns where a finished capture was written.
+///
+/// `client::Context::draw_frame` calls this after every frame; servers should call it once per
+/// tick.
+pub fn next_frame(frame: u64) -> Option<io::Result<PathBuf>> {
+    let finished = CAPTURE.with(|c| c.borrow_mut().take());
+
+    let result = finished.map(|capture| {
+        let path = capture.path.clone();
+        write_capture(&capture).map(|_| path)
+    });
+
+    if let Some(path) = PENDING.with(|p| p.borrow_mut().take()) {
+        let capture = Capture {
+            path:   path,
+            frame:  frame,
+            origin: Instant::now(),
+            events: vec![],
+        };
+
+        CAPTURE.with(|c| *c.borrow_mut() = Some(capture));
+    }
+
+    result
+}
+
+/// Records the time between its creation and being dropped as one event. See `span`.
+pub struct Span {
+    cat:   &'static str,
+    name:  &'static str,
+    start: Option<Instant>,
+    args:  Vec<(&'static str, String)>,
+}
+
+impl Span {
+    /// Attach `value` to the event under `key`. The value is only formatted while recording.
+    pub fn arg(&mut self, key: &'static str, value: &fmt::Display) {
+        if self.start.is_some() {
+            self.args.push((key, value.to_string()));
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let start = match self.start { Some(start) => start, None => return };
+
+        let (cat, name) = (self.cat, self.name);
+        let args = mem::replace(&mut self.args, vec![]);
+
+        CAPTURE.with(|c| {
+            if let Some(ref mut capture) = *c.borrow_mut() {
+                let ts  = micros_between(capture.origin, start);
+                let dur = micros_between(start, Instant::now());
+
+                capture.events.push(Event {
+                    cat: cat, name: name, phase: 'X', ts: ts, dur: dur, args: args,
+                });
+            }
+        });
+    }
+}
+
+/// Record the time from now until the returned `Span` is dropped as an event called `name` in the
+/// category `cat`.
+pub fn span(cat: &'static str, name: &'static str) -> Span {
+    let start = if is_recording() { Some(Instant::now()) } else { None };
+
+    Span { cat: cat, name: name, start: start, args: vec![] }
+}
+
+/// Record that something called `name` in the category `cat` happened just now, with `args`
+/// attached. The arguments are only formatted while recording.
+pub fn instant(cat: &'static str, name: &'static str, args: &[(&'static str, &fmt::Display)]) {
+    CAPTURE.with(|c| {
+        if let Some(ref mut capture) = *c.borrow_mut() {
+            let ts = micros_between(capture.origin, Instant::now());
+
+            capture.events.push(Event {
+                cat:   cat,
+                name:  name,
+                phase: 'i',
+                ts:    ts,
+                dur:   0,
+                args:  args.iter().map(|&(k, v)| (k, v.to_string())).collect(),
+            });
+        }
+    });
+}
+
+/// Request a capture to `DEFAULT_PATH` if `event` is a press of `CAPTURE_ACTION`. Returns true if
+/// it was.
+pub fn handle_event(event: &input::Event) -> bool {
+    match *event {
+        input::Event::Pressed(ref action) if action == CAPTURE_ACTION => {
+            request_capture(DEFAULT_PATH);
+            true
+        },
+        _ => false,
+    }
+}
+
+/// Run a console command, returning the text to print. The only command is `trace [PATH]`, which
+/// captures the next frame to `PATH` (by default `DEFAULT_PATH`).
+pub fn command(line: &str) -> Result<String, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    match (words.get(0).cloned(), words.len()) {
+        (Some("trace"), 1) => {
+            request_capture(DEFAULT_PATH);
+            Ok(format!("capturing the next frame to `{}'", DEFAULT_PATH))
+        },
+
+        (Some("trace"), 2) => {
+            request_capture(words[1]);
+            Ok(format!("capturing the next frame to `{}'", words[1]))
+        },
+
+        _ => Err(From::from("usage: trace [PATH]")),
+    }
+}
+
+fn micros_between(earlier: Instant, later: Instant) -> u64 {
+    if later < earlier {
+        return 0
+    }
+
+    let d = later.duration_since(earlier);
+    d.as_secs() * 1_000_000 + (d.subsec_nanos() / 1_000) as u64
+}
+
+// Write `s` as a JSON string, with quotes.
+fn write_json_str<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    try!(write!(out, "\""));
+
+    for c in s.chars() {
+        try!(match c {
+            '"'                     => write!(out, "\\\""),
+            '\\'                    => write!(out, "\\\\"),
+            '\n'                    => write!(out, "\\n"),
+            c if (c as u32) < 0x20  => write!(out, "\\u{:04x}", c as u32),
+            c                       => write!(out, "{}", c),
+        });
+    }
+
+    write!(out, "\"")
+}
+
+fn write_capture(capture: &Capture) -> io::Result<()> {
+    let mut out = io::BufWriter::new(try!(File::create(&capture.path)));
+
+    try!(write!(out, "{{\"displayTimeUnit\":\"ms\",\"otherData\":{{\"frame\":\"{}\"}},",
+                capture.frame));
+    try!(write!(out, "\"traceEvents\":[\n"));
+    try!(write!(out, "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":1,\
+                      \"args\":{{\"name\":\"main\"}}}}"));
+
+    for ev in capture.events.iter() {
+        try!(write!(out, ",\n{{\"name\":"));
+        try!(write_json_str(&mut out, ev.name));
+        try!(write!(out, ",\"cat\":"));
+        try!(write_json_str(&mut out, ev.cat));
+        try!(write!(out, ",\"ph\":\"{}\",\"ts\":{},\"pid\":1,\"tid\":1", ev.phase, ev.ts));
+
+        match ev.phase {
+            'X' => try!(write!(out, ",\"dur\":{}", ev.dur)),
+            'i' => try!(write!(out, ",\"s\":\"t\"")),
+            _   => {},
+        }
+
+        try!(write!(out, ",\"args\":{{"));
+
+        for (i, &(key, ref value)) in ev.args.iter().enumerate() {
+            if i > 0 {
+                try!(write!(out, ","));
+            }
+
+            try!(write_json_str(&mut out, key));
+            try!(write!(out, ":"));
+            try!(write_json_str(&mut out, value));
+        }
+
+        try!(write!(out, "}}}}"));
+    }
+
+    try!(write!(out, "\n]}}\n"));
+
+    out.flush()
+}
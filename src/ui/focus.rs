@@ -0,0 +1,350 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Keyboard and gamepad navigation of UI elements. A `Focus` keeps track of which element of a
+//! `Layout` is selected, and moves the selection in response to `NavAction`s: stepping to the next
+//! or previous element, or to the nearest element in some direction. It also has a virtual cursor
+//! mode, where an analog stick moves a pointer around the screen like a mouse would.
+//!
+//! Actions are looked up by the same names used for the `[keybinds]` table in the engine config,
+//! so whatever maps physical buttons to action names can drive menus with `NavAction::from_name`.
+
+use num::Float;
+
+use math;
+use time;
+use ui::layout::{ElementID, Layout};
+use units::*;
+
+/// A direction to move the focus in.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Direction {
+    /// Toward the top of the screen.
+    Up,
+
+    /// Toward the bottom of the screen.
+    Down,
+
+    /// Toward the left of the screen.
+    Left,
+
+    /// Toward the right of the screen.
+    Right,
+}
+
+impl Direction {
+    /// A unit vector pointing in this direction, in screen space (where `y` is up).
+    pub fn as_vector(self) -> math::Vec2<f32> {
+        match self {
+            Direction::Up    => vec2!( 0.0,  1.0),
+            Direction::Down  => vec2!( 0.0, -1.0),
+            Direction::Left  => vec2!(-1.0,  0.0),
+            Direction::Right => vec2!( 1.0,  0.0),
+        }
+    }
+}
+
+/// Something the player asked the UI to do.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum NavAction {
+    /// Move the focus to the nearest element in a direction.
+    Move(Direction),
+
+    /// Move the focus to the next element in tab order.
+    Next,
+
+    /// Move the focus to the previous element in tab order.
+    Previous,
+
+    /// Press the focused element.
+    Activate,
+
+    /// Leave the current menu.
+    Back,
+
+    /// Switch between directional focus and the virtual cursor.
+    ToggleCursor,
+}
+
+impl NavAction {
+    /// Look up an action by its name in the action map (e.g. `"ui_up"` or `"ui_activate"`).
+    pub fn from_name(name: &str) -> Option<NavAction> {
+        Some(match name {
+            "ui_up"            => NavAction::Move(Direction::Up),
+            "ui_down"          => NavAction::Move(Direction::Down),
+            "ui_left"          => NavAction::Move(Direction::Left),
+            "ui_right"         => NavAction::Move(Direction::Right),
+            "ui_next"          => NavAction::Next,
+            "ui_previous"      => NavAction::Previous,
+            "ui_activate"      => NavAction::Activate,
+            "ui_back"          => NavAction::Back,
+            "ui_toggle_cursor" => NavAction::ToggleCursor,
+            _                  => return None,
+        })
+    }
+
+    /// The name of this action in the action map. This is the inverse of `from_name`.
+    pub fn name(self) -> &'static str {
+        match self {
+            NavAction::Move(Direction::Up)    => "ui_up",
+            NavAction::Move(Direction::Down)  => "ui_down",
+            NavAction::Move(Direction::Left)  => "ui_left",
+            NavAction::Move(Direction::Right) => "ui_right",
+            NavAction::Next                   => "ui_next",
+            NavAction::Previous               => "ui_previous",
+            NavAction::Activate               => "ui_activate",
+            NavAction::Back                   => "ui_back",
+            NavAction::ToggleCursor           => "ui_toggle_cursor",
+        }
+    }
+}
+
+/// What happened as the result of a `NavAction`.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum NavEvent {
+    /// The focus moved to an element.
+    Focused(ElementID),
+
+    /// An element was pressed.
+    Activated(ElementID),
+
+    /// The player wants to leave the current menu.
+    Back,
+}
+
+/// How fast the virtual cursor moves with the stick pushed all the way, in pixels per second.
+pub const DEFAULT_CURSOR_SPEED: f32 = 800.0;
+
+/// The focused element of a UI, and the virtual cursor.
+pub struct Focus {
+    // Focusable elements, in tab order.
+    order: Vec<ElementID>,
+
+    // Index into `order` of the focused element.
+    current: Option<usize>,
+
+    cursor:       Option<math::Vec2<Pixels>>,
+    cursor_speed: Pixels,
+}
+
+impl Focus {
+    /// Create a `Focus` with no focusable elements.
+    pub fn new() -> Focus {
+        Focus {
+            order:        vec![],
+            current:      None,
+            cursor:       None,
+            cursor_speed: Pixels(DEFAULT_CURSOR_SPEED),
+        }
+    }
+
+    /// Make `id` focusable, after all of the existing elements in tab order. The first element
+    /// added gets the focus.
+    pub fn add(&mut self, id: ElementID) {
+        if self.order.contains(&id) {
+            return;
+        }
+
+        self.order.push(id);
+
+        if self.current.is_none() {
+            self.current = Some(0);
+        }
+    }
+
+    /// Make `id` unfocusable. If it had the focus, the focus moves to the next element.
+    pub fn remove(&mut self, id: ElementID) {
+        let idx = match self.order.iter().position(|&x| x == id) {
+            Some(idx) => idx,
+            None      => return,
+        };
+
+        self.order.remove(idx);
+
+        self.current = match self.current {
+            _ if self.order.is_empty()  => None,
+            Some(cur) if cur > idx      => Some(cur - 1),
+            Some(cur) if cur == idx     => Some(cur % self.order.len()),
+            other                       => other,
+        };
+    }
+
+    /// The focused element, if any.
+    pub fn focused(&self) -> Option<ElementID> {
+        self.current.map(|i| self.order[i])
+    }
+
+    /// Focus `id`, returning false if it isn't focusable.
+    pub fn set_focused(&mut self, id: ElementID) -> bool {
+        match self.order.iter().position(|&x| x == id) {
+            Some(idx) => { self.current = Some(idx); true },
+            None      => false,
+        }
+    }
+
+    /// Move the focus to the next element in tab order, wrapping around at the end.
+    pub fn next(&mut self) -> Option<ElementID> {
+        let len = self.order.len();
+
+        self.current = self.current.map(|i| (i + 1) % len);
+        self.focused()
+    }
+
+    /// Move the focus to the previous element in tab order, wrapping around at the start.
+    pub fn previous(&mut self) -> Option<ElementID> {
+        let len = self.order.len();
+
+        self.current = self.current.map(|i| (i + len - 1) % len);
+        self.focused()
+    }
+
+    /// Move the focus to the nearest element in direction `dir`, judging by the centers of their
+    /// rectangles in `layout`. If there's nothing that way the focus stays put.
+    pub fn move_focus(&mut self, layout: &Layout, dir: Direction) -> Option<ElementID> {
+        let cur = match self.current {
+            Some(cur) => cur,
+            None      => return None,
+        };
+
+        let from = layout.rect(self.order[cur]).center();
+        let axis = dir.as_vector();
+
+        let mut best: Option<(usize, f32)> = None;
+
+        for (i, &id) in self.order.iter().enumerate() {
+            if i == cur {
+                continue;
+            }
+
+            let d     = layout.rect(id).center() - from;
+            let along = d.x.0 * axis.x + d.y.0 * axis.y;
+            let aside = (d.x.0 * axis.y - d.y.0 * axis.x).abs();
+
+            // Only consider elements within 45° of `dir`. Among those, prefer ones which are
+            // lined up with the current element over ones which are merely close.
+            if along <= 0.0 || aside > along {
+                continue;
+            }
+
+            let score = along + 2.0 * aside;
+
+            if best.map_or(true, |(_, s)| score < s) {
+                best = Some((i, score));
+            }
+        }
+
+        if let Some((i, _)) = best {
+            self.current = Some(i);
+        }
+
+        self.focused()
+    }
+
+    /// The position of the virtual cursor, if it's active.
+    pub fn cursor(&self) -> Option<math::Vec2<Pixels>> {
+        self.cursor
+    }
+
+    /// Set how fast the virtual cursor moves with the stick pushed all the way.
+    pub fn set_cursor_speed(&mut self, px_per_sec: Pixels) {
+        self.cursor_speed = px_per_sec;
+    }
+
+    /// Turn on the virtual cursor, starting at the center of the focused element (or of the screen
+    /// if nothing is focused).
+    pub fn enable_cursor(&mut self, layout: &Layout) {
+        let start = match self.focused() {
+            Some(id) => layout.rect(id).center(),
+            None     => layout.screen().center(),
+        };
+
+        self.cursor = Some(start);
+    }
+
+    /// Turn off the virtual cursor. The focus stays on whatever it was last over.
+    pub fn disable_cursor(&mut self) {
+        self.cursor = None;
+    }
+
+    /// Move the virtual cursor by an analog `stick` reading (each axis in `[-1, 1]`) held for `dt`.
+    /// The cursor stays on the screen, and focuses whichever focusable element it's over.
+    pub fn move_cursor(&mut self, layout: &Layout, stick: math::Vec2<f32>, dt: time::Duration)
+        -> Option<ElementID> {
+
+        let pos = match self.cursor {
+            Some(pos) => pos,
+            None      => return self.focused(),
+        };
+
+        let dist   = self.cursor_speed.0 * (dt.as_usec() as f32 / 1_000_000.0);
+        let screen = layout.screen();
+
+        let x = (pos.x.0 + stick.x * dist).max(screen.min.x.0).min(screen.max.x.0);
+        let y = (pos.y.0 + stick.y * dist).max(screen.min.y.0).min(screen.max.y.0);
+
+        self.point_at(layout, vec2!(Pixels ; x, y))
+    }
+
+    /// Put the virtual cursor at `pos` (e.g. where the mouse is), focusing whichever focusable
+    /// element is under it.
+    pub fn point_at(&mut self, layout: &Layout, pos: math::Vec2<Pixels>) -> Option<ElementID> {
+        self.cursor = Some(pos);
+
+        // The topmost element under the cursor might not be focusable (e.g. a panel behind the
+        // buttons), so look for the topmost one which is.
+        let mut hit = None;
+
+        for (i, &id) in self.order.iter().enumerate() {
+            if layout.rect(id).contains(pos) && hit.map_or(true, |(_, j)| id > j) {
+                hit = Some((i, id));
+            }
+        }
+
+        if let Some((i, _)) = hit {
+            self.current = Some(i);
+        }
+
+        self.focused()
+    }
+
+    /// Respond to `action`, returning what happened (if anything).
+    pub fn handle(&mut self, layout: &Layout, action: NavAction) -> Option<NavEvent> {
+        let before = self.focused();
+
+        let after = match action {
+            NavAction::Move(dir)    => self.move_focus(layout, dir),
+            NavAction::Next         => self.next(),
+            NavAction::Previous     => self.previous(),
+            NavAction::Back         => return Some(NavEvent::Back),
+            NavAction::Activate     => return self.focused().map(NavEvent::Activated),
+
+            NavAction::ToggleCursor => {
+                if self.cursor.is_some() {
+                    self.disable_cursor();
+                } else {
+                    self.enable_cursor(layout);
+                }
+
+                return None;
+            },
+        };
+
+        match after {
+            Some(id) if after != before => Some(NavEvent::Focused(id)),
+            _                           => None,
+        }
+    }
+}
@@ -0,0 +1,330 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! UI elements are positioned with a `Placement`, which is made of anchors, percentages and pixel
+//! offsets rather than absolute coordinates. A `Layout` resolves every placement to a `Rect` in
+//! `Pixels` for the current resolution, and does it again whenever the resolution changes, so HUD
+//! code never needs to know the size of the screen.
+//!
+//! Resolved rectangles are in the same screen space as `Camera::camera_to_screen`: the origin is at
+//! the center of the screen and `y` increases upward.
+
+use std::ops::Add;
+
+use math;
+use units::*;
+
+/// A point within a rectangle, as a fraction of its width and height. `(0, 0)` is the bottom-left
+/// corner and `(1, 1)` is the top-right corner.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct Anchor {
+    /// Fraction of the width, from the left edge.
+    pub x: f32,
+
+    /// Fraction of the height, from the bottom edge.
+    pub y: f32,
+}
+
+impl Anchor {
+    /// Construct an anchor from fractions of the width and height.
+    pub fn new(x: f32, y: f32) -> Anchor {
+        Anchor { x: x, y: y }
+    }
+}
+
+/// The top-left corner.
+pub const TOP_LEFT:     Anchor = Anchor { x: 0.0, y: 1.0 };
+
+/// The middle of the top edge.
+pub const TOP:          Anchor = Anchor { x: 0.5, y: 1.0 };
+
+/// The top-right corner.
+pub const TOP_RIGHT:    Anchor = Anchor { x: 1.0, y: 1.0 };
+
+/// The middle of the left edge.
+pub const LEFT:         Anchor = Anchor { x: 0.0, y: 0.5 };
+
+/// The center.
+pub const CENTER:       Anchor = Anchor { x: 0.5, y: 0.5 };
+
+/// The middle of the right edge.
+pub const RIGHT:        Anchor = Anchor { x: 1.0, y: 0.5 };
+
+/// The bottom-left corner.
+pub const BOTTOM_LEFT:  Anchor = Anchor { x: 0.0, y: 0.0 };
+
+/// The middle of the bottom edge.
+pub const BOTTOM:       Anchor = Anchor { x: 0.5, y: 0.0 };
+
+/// The bottom-right corner.
+pub const BOTTOM_RIGHT: Anchor = Anchor { x: 1.0, y: 0.0 };
+
+/// A distance which is part percentage of some extent (the width or height of the parent) and part
+/// fixed number of pixels. Lengths can be added, so `Length::percent(50.0) + Length::px(-8.0)` is
+/// "8 pixels short of half".
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct Length {
+    /// Percentage of the extent, where `100.0` is all of it.
+    pub percent: f32,
+
+    /// Fixed offset, in pixels.
+    pub px: Pixels,
+}
+
+impl Length {
+    /// A length of zero.
+    pub fn zero() -> Length {
+        Length { percent: 0.0, px: Pixels(0.0) }
+    }
+
+    /// A fixed number of pixels.
+    pub fn px(px: f32) -> Length {
+        Length { percent: 0.0, px: Pixels(px) }
+    }
+
+    /// A percentage of the extent.
+    pub fn percent(percent: f32) -> Length {
+        Length { percent: percent, px: Pixels(0.0) }
+    }
+
+    /// Resolve this length against an extent.
+    #[inline] pub fn resolve(&self, extent: Pixels) -> Pixels {
+        Pixels(extent.0 * self.percent / 100.0) + self.px
+    }
+}
+
+impl Add for Length {
+    type Output = Length;
+
+    fn add(self, rhs: Length) -> Length {
+        Length { percent: self.percent + rhs.percent, px: self.px + rhs.px }
+    }
+}
+
+/// An axis-aligned rectangle in screen space.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct Rect {
+    /// The bottom-left corner.
+    pub min: math::Vec2<Pixels>,
+
+    /// The top-right corner.
+    pub max: math::Vec2<Pixels>,
+}
+
+impl Rect {
+    /// The rectangle covering a screen of the given resolution.
+    pub fn screen(resolution: math::Vec2<Pixels>) -> Rect {
+        let half = vec2!(resolution.x / Pixels(2.0), resolution.y / Pixels(2.0));
+
+        Rect { min: vec2!(-half.x, -half.y), max: half }
+    }
+
+    /// The width and height of the rectangle.
+    #[inline] pub fn size(&self) -> math::Vec2<Pixels> {
+        self.max - self.min
+    }
+
+    /// The center of the rectangle.
+    #[inline] pub fn center(&self) -> math::Vec2<Pixels> {
+        self.point(CENTER)
+    }
+
+    /// The point at `anchor` within the rectangle.
+    #[inline] pub fn point(&self, anchor: Anchor) -> math::Vec2<Pixels> {
+        let size = self.size();
+
+        vec2!(self.min.x + Pixels(size.x.0 * anchor.x), self.min.y + Pixels(size.y.0 * anchor.y))
+    }
+
+    /// Return true if `p` is within the rectangle, e.g. to see whether the cursor is over a button.
+    #[inline] pub fn contains(&self, p: math::Vec2<Pixels>) -> bool {
+        p.x >= self.min.x && p.x <= self.max.x && p.y >= self.min.y && p.y <= self.max.y
+    }
+}
+
+/// Where an element goes, relative to its parent (or the screen, for top-level elements).
+///
+/// The point at `anchor` in the parent, moved by `(x, y)`, is where the element's own `pivot` ends
+/// up. Percentages in `x` and `width` are of the parent's width, and those in `y` and `height` are
+/// of its height. For example, a 200×40 health bar 16 pixels in from the top-left corner is
+///
+/// ```rust,ignore
+/// Placement::new(TOP_LEFT, Length::px(200.0), Length::px(40.0)).offset(Length::px(16.0),
+///                                                                       Length::px(-16.0))
+/// ```
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct Placement {
+    /// The point in the parent which the element is attached to.
+    pub anchor: Anchor,
+
+    /// The point in the element which is attached to `anchor`.
+    pub pivot: Anchor,
+
+    /// Horizontal offset from the anchor.
+    pub x: Length,
+
+    /// Vertical offset from the anchor.
+    pub y: Length,
+
+    /// The width of the element.
+    pub width: Length,
+
+    /// The height of the element.
+    pub height: Length,
+}
+
+impl Placement {
+    /// Place an element of the given size at `anchor`, pivoting about the same point of itself (so
+    /// that an element at `TOP_RIGHT` sits inside the top-right corner).
+    pub fn new(anchor: Anchor, width: Length, height: Length) -> Placement {
+        Placement {
+            anchor: anchor,
+            pivot:  anchor,
+            x:      Length::zero(),
+            y:      Length::zero(),
+            width:  width,
+            height: height,
+        }
+    }
+
+    /// An element which covers all of its parent.
+    pub fn fill() -> Placement {
+        Placement::new(CENTER, Length::percent(100.0), Length::percent(100.0))
+    }
+
+    /// Use a different pivot.
+    pub fn pivot(mut self, pivot: Anchor) -> Placement {
+        self.pivot = pivot;
+        self
+    }
+
+    /// Move the element by the given offset.
+    pub fn offset(mut self, x: Length, y: Length) -> Placement {
+        self.x = x;
+        self.y = y;
+        self
+    }
+
+    /// Resolve this placement within `parent`.
+    pub fn resolve(&self, parent: &Rect) -> Rect {
+        let extent = parent.size();
+
+        let size = vec2!(self.width.resolve(extent.x), self.height.resolve(extent.y));
+        let at   = parent.point(self.anchor) + vec2!(self.x.resolve(extent.x),
+                                                      self.y.resolve(extent.y));
+
+        let min = vec2!(at.x - Pixels(size.x.0 * self.pivot.x),
+                        at.y - Pixels(size.y.0 * self.pivot.y));
+
+        Rect { min: min, max: min + size }
+    }
+}
+
+/// Identifies an element in a `Layout`.
+pub type ElementID = u32;
+
+struct Element {
+    placement: Placement,
+    parent:    Option<ElementID>,
+    rect:      Rect,
+}
+
+/// A set of UI elements whose rectangles are kept up to date with the resolution of the screen.
+///
+/// Parents are always added before their children, so resolving the elements in order is enough to
+/// resolve the whole tree.
+pub struct Layout {
+    screen:   Rect,
+    elements: Vec<Element>,
+}
+
+impl Layout {
+    /// Create an empty layout for a screen of the given resolution.
+    pub fn new(resolution: math::Vec2<Pixels>) -> Layout {
+        Layout { screen: Rect::screen(resolution), elements: vec![] }
+    }
+
+    /// The rectangle covering the whole screen.
+    pub fn screen(&self) -> Rect {
+        self.screen
+    }
+
+    /// Add an element, positioned relative to `parent` (or the screen, if it's `None`).
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `parent` isn't an element of this layout.
+    pub fn add(&mut self, placement: Placement, parent: Option<ElementID>) -> ElementID {
+        let id   = self.elements.len() as ElementID;
+        let rect = placement.resolve(&self.parent_rect(parent));
+
+        self.elements.push(Element { placement: placement, parent: parent, rect: rect });
+
+        id
+    }
+
+    /// The resolved rectangle of the element `id`.
+    pub fn rect(&self, id: ElementID) -> Rect {
+        self.elements[id as usize].rect
+    }
+
+    /// The placement of the element `id`.
+    pub fn placement(&self, id: ElementID) -> &Placement {
+        &self.elements[id as usize].placement
+    }
+
+    /// Change the placement of the element `id`. It and all of its descendants are re-resolved.
+    pub fn set_placement(&mut self, id: ElementID, placement: Placement) {
+        self.elements[id as usize].placement = placement;
+
+        self.resolve_from(id as usize);
+    }
+
+    /// The screen has changed size, so re-resolve every element.
+    pub fn resize(&mut self, resolution: math::Vec2<Pixels>) {
+        self.screen = Rect::screen(resolution);
+
+        self.resolve_from(0);
+    }
+
+    /// The topmost element containing `p`, if any. Later elements are drawn over earlier ones, so
+    /// they're checked first.
+    pub fn hit(&self, p: math::Vec2<Pixels>) -> Option<ElementID> {
+        self.elements.iter().rposition(|e| e.rect.contains(p)).map(|i| i as ElementID)
+    }
+
+    /// The number of elements in the layout.
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    fn parent_rect(&self, parent: Option<ElementID>) -> Rect {
+        match parent {
+            Some(p) => self.elements[p as usize].rect,
+            None    => self.screen,
+        }
+    }
+
+    // Re-resolve every element from `first` on. Anything which might depend on `first` comes after
+    // it, so this is conservative but correct.
+    fn resolve_from(&mut self, first: usize) {
+        for i in first..self.elements.len() {
+            let parent = self.parent_rect(self.elements[i].parent);
+
+            self.elements[i].rect = self.elements[i].placement.resolve(&parent);
+        }
+    }
+}
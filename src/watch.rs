@@ -0,0 +1,58 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Noticing when files change on disk, so that whatever was loaded from them can be reloaded while
+//! the game runs. Files are polled by modification time, which is cheap enough to do every frame
+//! for the handful of files that an editor might touch.
+
+use std::convert::AsRef;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A file whose modification time is being watched.
+pub struct Watched {
+    path:     PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl Watched {
+    /// Start watching the file at `path`. It needn't exist yet.
+    pub fn new<P: AsRef<Path>>(path: P) -> Watched {
+        let mut w = Watched { path: path.as_ref().to_path_buf(), modified: None };
+        w.changed();
+        w
+    }
+
+    /// The path of the watched file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return true if the file has been modified since the last call. A file which can't be read
+    /// (e.g. because an editor is in the middle of replacing it) is treated as unchanged, so that
+    /// it gets picked up on a later poll once it's back.
+    pub fn changed(&mut self) -> bool {
+        let modified = match fs::metadata(&self.path).and_then(|m| m.modified()) {
+            Ok(modified) => Some(modified),
+            Err(_)       => return false,
+        };
+
+        let changed = self.modified.is_some() && modified != self.modified;
+        self.modified = modified;
+        changed
+    }
+}
@@ -0,0 +1,331 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// The generated `wire` modules read FlatBuffers without any bounds checking, which is fine for
+// buffers we wrote ourselves but not for anything that came over the network. A `Verifier` walks
+// a buffer according to its schema and checks that every offset, string and vector it would touch
+// is in bounds, so that a malformed or hostile buffer is rejected up front instead of causing an
+// out-of-bounds read later.
+//
+// Each schema has a matching `verify` module (e.g. `asset::verify`), generated alongside its `wire`
+// module by `codegen`, which drives the `Verifier` for the tables in that schema and exposes a
+// `verify_buffer` entry point for its root type.
+//
+// The accessors read fields in place, handing out references to structs, so alignment is checked
+// against actual addresses rather than offsets within the buffer.
+
+use std::str;
+
+// Nesting deeper than this is certainly malicious; none of our schemas come close.
+const MAX_DEPTH: usize = 64;
+
+// Upper bound on the number of tables in a single buffer, so that a buffer full of tables which all
+// point at each other can't keep the verifier busy forever.
+const MAX_TABLES: usize = 1_000_000;
+
+/// The reason a buffer failed verification. Each variant carries the buffer offset at which the
+/// problem was found.
+#[derive(Debug,PartialEq,Eq)]
+pub enum Error {
+    /// Something points outside of the buffer.
+    OutOfBounds(usize),
+
+    /// A table or offset isn't aligned the way FlatBuffers requires.
+    Misaligned(usize),
+
+    /// A vtable is too small or has an odd size.
+    BadVTable(usize),
+
+    /// A string isn't valid UTF-8, or is missing its null terminator.
+    BadString(usize),
+
+    /// A field marked `(required)` in the schema is absent. Carries the table's offset.
+    MissingRequired(usize),
+
+    /// Tables are nested more deeply than any of our schemas allow.
+    TooDeep(usize),
+
+    /// The buffer contains an absurd number of tables.
+    TooManyTables(usize),
+}
+
+/// Checks a FlatBuffer for structural validity. See the module documentation.
+pub struct Verifier<'x> {
+    buf:    &'x [u8],
+    depth:  usize,
+    tables: usize,
+}
+
+impl<'x> Verifier<'x> {
+    /// Create a verifier for `buf`.
+    pub fn new(buf: &'x [u8]) -> Verifier<'x> {
+        Verifier {
+            buf:    buf,
+            depth:  0,
+            tables: 0,
+        }
+    }
+
+    // Make sure that `len` bytes starting at `pos` are within the buffer.
+    fn check(&self, pos: usize, len: usize) -> Result<(), Error> {
+        match pos.checked_add(len) {
+            Some(end) if end <= self.buf.len() => Ok(()),
+            _                                   => Err(Error::OutOfBounds(pos)),
+        }
+    }
+
+    fn check_aligned(&self, pos: usize, align: usize) -> Result<(), Error> {
+        let addr = (self.buf.as_ptr() as usize).wrapping_add(pos);
+
+        if addr % align == 0 { Ok(()) } else { Err(Error::Misaligned(pos)) }
+    }
+
+    fn read_u16(&self, pos: usize) -> Result<u16, Error> {
+        try!(self.check(pos, 2));
+
+        Ok((self.buf[pos] as u16) | ((self.buf[pos + 1] as u16) << 8))
+    }
+
+    fn read_u32(&self, pos: usize) -> Result<u32, Error> {
+        try!(self.check(pos, 4));
+
+        Ok((self.buf[pos] as u32)
+            | ((self.buf[pos + 1] as u32) << 8)
+            | ((self.buf[pos + 2] as u32) << 16)
+            | ((self.buf[pos + 3] as u32) << 24))
+    }
+
+    /// Follow the `uoffset` stored at `pos`, returning the position it points to.
+    pub fn deref(&self, pos: usize) -> Result<usize, Error> {
+        try!(self.check_aligned(pos, 4));
+
+        let off = try!(self.read_u32(pos)) as usize;
+
+        match pos.checked_add(off) {
+            Some(target) if target < self.buf.len() => Ok(target),
+            _                                        => Err(Error::OutOfBounds(pos)),
+        }
+    }
+
+    /// Return the position of the buffer's root table.
+    pub fn root(&self) -> Result<usize, Error> {
+        self.deref(0)
+    }
+
+    /// Check the table header and vtable of the table at `pos`.
+    pub fn table(&mut self, pos: usize) -> Result<Table, Error> {
+        self.tables += 1;
+
+        if self.tables > MAX_TABLES {
+            return Err(Error::TooManyTables(pos))
+        }
+
+        try!(self.check_aligned(pos, 4));
+
+        // The table begins with a signed offset *back* to its vtable.
+        let soff   = try!(self.read_u32(pos)) as i32 as i64;
+        let vtable = (pos as i64) - soff;
+
+        if vtable < 0 || vtable >= self.buf.len() as i64 {
+            return Err(Error::OutOfBounds(pos))
+        }
+
+        let vtable = vtable as usize;
+
+        try!(self.check_aligned(vtable, 2));
+
+        let vsize = try!(self.read_u16(vtable)) as usize;
+        let tsize = try!(self.read_u16(vtable + 2)) as usize;
+
+        if vsize < 4 || vsize % 2 != 0 {
+            return Err(Error::BadVTable(vtable))
+        }
+
+        try!(self.check(vtable, vsize));
+        try!(self.check(pos, tsize));
+
+        Ok(Table { pos: pos, vtable: vtable, vsize: vsize, tsize: tsize })
+    }
+
+    /// Note that we're descending into a nested table. Must be paired with `leave`.
+    pub fn enter(&mut self, pos: usize) -> Result<(), Error> {
+        self.depth += 1;
+
+        if self.depth > MAX_DEPTH { Err(Error::TooDeep(pos)) } else { Ok(()) }
+    }
+
+    /// Note that we're done with a nested table.
+    pub fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Check the string at `pos`: its length must be in bounds, it must be null terminated, and it
+    /// must be valid UTF-8.
+    pub fn string(&self, pos: usize) -> Result<(), Error> {
+        let len = try!(self.read_u32(pos)) as usize;
+
+        let start = pos + 4;
+
+        try!(self.check(start, len));
+        try!(self.check(start + len, 1));
+
+        if self.buf[start + len] != 0 {
+            return Err(Error::BadString(pos))
+        }
+
+        match str::from_utf8(&self.buf[start..start + len]) {
+            Ok(_)  => Ok(()),
+            Err(_) => Err(Error::BadString(pos)),
+        }
+    }
+
+    /// Check the vector at `pos`, whose elements are `elem_size` bytes each and aligned to
+    /// `elem_align`. Returns the position of the first element and the number of elements.
+    pub fn vector(&self, pos: usize, elem_size: usize, elem_align: usize)
+        -> Result<(usize, usize), Error> {
+
+        try!(self.check_aligned(pos, 4));
+        try!(self.check_aligned(pos + 4, elem_align));
+
+        let len = try!(self.read_u32(pos)) as usize;
+
+        let bytes = try!(len.checked_mul(elem_size).ok_or(Error::OutOfBounds(pos)));
+
+        try!(self.check(pos + 4, bytes));
+
+        Ok((pos + 4, len))
+    }
+}
+
+/// A table whose header and vtable have been checked. Its fields are checked individually, since
+/// only the schema knows what they are.
+#[derive(Copy,Clone,Debug)]
+pub struct Table {
+    pos:    usize,
+    vtable: usize,
+    vsize:  usize,
+    tsize:  usize,
+}
+
+impl Table {
+    /// The position of the table within the buffer.
+    pub fn pos(&self) -> usize { self.pos }
+
+    // Return the position of the field in vtable slot `slot` (the same number the generated code
+    // passes to `get_field`), if it is present. The field must fit within the table.
+    fn field(&self, v: &Verifier, slot: usize, size: usize) -> Result<Option<usize>, Error> {
+        if slot + 2 > self.vsize {
+            // Fields beyond the end of the vtable were added to the schema after this buffer was
+            // written. They're simply absent.
+            return Ok(None)
+        }
+
+        let off = try!(v.read_u16(self.vtable + slot)) as usize;
+
+        if off == 0 {
+            return Ok(None)
+        }
+
+        if off + size > self.tsize {
+            return Err(Error::OutOfBounds(self.pos + off))
+        }
+
+        Ok(Some(self.pos + off))
+    }
+
+    fn require<T>(&self, x: Option<T>, required: bool) -> Result<Option<T>, Error> {
+        match x {
+            None if required => Err(Error::MissingRequired(self.pos)),
+            _                => Ok(x),
+        }
+    }
+
+    /// Check a scalar or struct field of `size` bytes, which must be aligned to `align`.
+    pub fn inline(&self, v: &Verifier, slot: usize, size: usize, align: usize)
+        -> Result<(), Error> {
+
+        if let Some(field) = try!(self.field(v, slot, size)) {
+            try!(v.check_aligned(field, align));
+        }
+
+        Ok(())
+    }
+
+    /// Check a string field.
+    pub fn string(&self, v: &Verifier, slot: usize, required: bool) -> Result<(), Error> {
+        let field = try!(self.require(try!(self.field(v, slot, 4)), required));
+
+        if let Some(field) = field {
+            try!(v.string(try!(v.deref(field))));
+        }
+
+        Ok(())
+    }
+
+    /// Check a vector of scalars or structs, each `elem_size` bytes and aligned to `elem_align`.
+    pub fn vector(&self, v: &Verifier, slot: usize, elem_size: usize, elem_align: usize,
+                  required: bool) -> Result<(), Error> {
+
+        let field = try!(self.require(try!(self.field(v, slot, 4)), required));
+
+        if let Some(field) = field {
+            try!(v.vector(try!(v.deref(field)), elem_size, elem_align));
+        }
+
+        Ok(())
+    }
+
+    /// Check a table field, calling `f` with the position of the table.
+    pub fn table<F>(&self, v: &mut Verifier, slot: usize, required: bool, f: F)
+        -> Result<(), Error> where F: FnOnce(&mut Verifier, usize) -> Result<(), Error> {
+
+        let field = try!(self.require(try!(self.field(v, slot, 4)), required));
+
+        if let Some(field) = field {
+            let pos = try!(v.deref(field));
+
+            try!(v.enter(field));
+            try!(f(v, pos));
+
+            v.leave();
+        }
+
+        Ok(())
+    }
+
+    /// Check a vector of tables, calling `f` with the position of each element table.
+    pub fn tables<F>(&self, v: &mut Verifier, slot: usize, required: bool, mut f: F)
+        -> Result<(), Error> where F: FnMut(&mut Verifier, usize) -> Result<(), Error> {
+
+        let field = try!(self.require(try!(self.field(v, slot, 4)), required));
+
+        if let Some(field) = field {
+            let (first, len) = try!(v.vector(try!(v.deref(field)), 4, 4));
+
+            try!(v.enter(field));
+
+            for i in 0..len {
+                let elem = try!(v.deref(first + 4 * i));
+                try!(f(v, elem));
+            }
+
+            v.leave();
+        }
+
+        Ok(())
+    }
+}